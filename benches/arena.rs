@@ -0,0 +1,84 @@
+//! How the guest memory arena is mapped against a guest that strides over
+//! 64 MiB of it, touching a word every 4 KiB or so, which is about as bad as it
+//! gets for the host's TLB. Run with `cargo bench --bench arena`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use risc_y::{
+    core::{AdaptiveMemReader, Core32},
+    elfnote::ElfConfig,
+    load::{ElfTarget, LoadedElf, Segment},
+    mmap::Hugepages,
+};
+
+const MEMORY_SIZE: usize = 128 << 20;
+const TEXT: u64 = 0x10000;
+
+// s0 passes over [0x1000000, 0x5000000), incrementing a word every 4160 bytes
+const PROGRAM: [u32; 15] = [
+    0x00800413, // li s0, 8
+    0x010002b7, // pass: lui t0, 0x1000
+    0x05000337, // lui t1, 0x5000
+    0x000013b7, // lui t2, 1
+    0x04038393, // addi t2, t2, 64
+    0x0002ae03, // loop: lw t3, 0(t0)
+    0x001e0e13, // addi t3, t3, 1
+    0x01c2a023, // sw t3, 0(t0)
+    0x007282b3, // add t0, t0, t2
+    0xfe62e8e3, // bltu t0, t1, loop
+    0xfff40413, // addi s0, s0, -1
+    0xfc041ce3, // bnez s0, pass
+    0x00000513, // li a0, 0
+    0x05d00893, // li a7, 93
+    0x00000073, // ecall
+];
+
+fn elf() -> LoadedElf {
+    let data: Vec<u8> = PROGRAM.iter().flat_map(|word| word.to_le_bytes()).collect();
+    LoadedElf {
+        base: TEXT,
+        entrypoint: TEXT,
+        segments: vec![Segment {
+            offset: 0,
+            vaddr: TEXT,
+            size: data.len() as u64,
+            data,
+            executable: true,
+            file: None,
+        }],
+        symbols: Vec::new(),
+        wk_memmove: 0,
+        wk_memcpy: 0,
+        wk_memset: 0,
+        wk_cos: 0,
+        wk_sin: 0,
+        fatal_fns: Vec::new(),
+        build_id: None,
+        target: ElfTarget::default(),
+        config: ElfConfig::default(),
+    }
+}
+
+fn arena(c: &mut Criterion) {
+    let mut group = c.benchmark_group("arena");
+    group.sample_size(10);
+
+    for hugepages in [Hugepages::Off, Hugepages::Align, Hugepages::Madvise] {
+        group.bench_function(hugepages.to_string(), |b| {
+            b.iter(|| {
+                let mut core = Core32::<AdaptiveMemReader<u32>>::with_hugepages(
+                    elf(),
+                    None,
+                    MEMORY_SIZE,
+                    false,
+                    hugepages,
+                );
+                core.run()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, arena);
+criterion_main!(benches);
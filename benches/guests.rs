@@ -0,0 +1,62 @@
+//! Whole guest programs from `benches/guests/`, run through the library as
+//! `riscy` runs them. Throughput is in guest instructions, so criterion's
+//! Melem/s is MIPS, comparable from one change to the next. Run with
+//! `cargo bench --bench guests`, and see `benches/guests/README.md` for what
+//! each guest does and how to add CoreMark and Dhrystone.
+
+use std::path::Path;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use risc_y::{
+    core::{AdaptiveMemReader, Core32},
+    load::LoadedElf,
+};
+
+const MEMORY_SIZE: usize = 16 << 20;
+
+// with the exit code each should finish with, or `None` if it isn't checked
+// in and can be left out
+const GUESTS: [(&str, Option<i32>); 6] = [
+    ("coremark", None),
+    ("dhrystone", None),
+    ("integer", Some(48)),
+    ("fp", Some(30)),
+    ("memcpy", Some(0)),
+    ("dispatch", Some(100)),
+];
+
+fn run(elf: &LoadedElf) -> Core32<AdaptiveMemReader<u32>> {
+    let mut core = Core32::new(elf.clone(), None, MEMORY_SIZE, false);
+    core.run();
+    core
+}
+
+fn guests(c: &mut Criterion) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/guests");
+    let mut group = c.benchmark_group("guests");
+    group.sample_size(10);
+
+    for (name, exit_code) in GUESTS {
+        let path = dir.join(format!("{name}.elf"));
+        if exit_code.is_none() && !path.exists() {
+            eprintln!("skipping {name}: no {}", path.display());
+            continue;
+        }
+        let elf = LoadedElf::load(path.to_str().unwrap())
+            .unwrap_or_else(|err| panic!("{}: {err}", path.display()));
+
+        let mut core = Core32::<AdaptiveMemReader<u32>>::new(elf.clone(), None, MEMORY_SIZE, false);
+        let info = core.run();
+        if let Some(exit_code) = exit_code {
+            assert_eq!(info.return_code, exit_code, "{name} exited wrongly");
+        }
+
+        group.throughput(Throughput::Elements(core.instret()));
+        group.bench_function(name, |b| b.iter(|| run(&elf)));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, guests);
+criterion_main!(benches);
@@ -0,0 +1,964 @@
+//! compiler-rt's soft-float helpers, transcribed from its C, each twice: under
+//! its own name, which `native`'s test has riscy service natively, and as
+//! `guest_*`, which runs as it is. `_start` calls both on zeros, subnormals,
+//! infinities, NaNs and integers out of range, and exits with the number of
+//! the first case they disagree on, or 0.
+//!
+//! Division's special cases are compiler-rt's, but the quotient is worked out a
+//! bit at a time rather than by Newton-Raphson. Both round it correctly, so
+//! they agree everywhere else.
+//!
+//! It's `no_core`, as there's no `core` for riscv32 to build against here, so
+//! there's just enough of one for the operators it uses, and no indexing or
+//! dividing, which could panic.
+
+#![feature(no_core, lang_items, rustc_attrs, decl_macro)]
+#![no_core]
+#![no_std]
+#![allow(internal_features, clippy::all)]
+
+#[lang = "pointee_sized"]
+pub trait PointeeSized {}
+#[lang = "meta_sized"]
+pub trait MetaSized: PointeeSized {}
+#[lang = "sized"]
+pub trait Sized: MetaSized {}
+#[lang = "copy"]
+pub trait Copy {}
+
+#[lang = "legacy_receiver"]
+pub trait LegacyReceiver {}
+impl<T: PointeeSized> LegacyReceiver for &T {}
+
+macro_rules! ops {
+    ($($trait:ident $lang:literal $method:ident;)*) => {
+        $(
+            #[lang = $lang]
+            pub trait $trait<Rhs = Self> {
+                type Output;
+                fn $method(self, rhs: Rhs) -> Self::Output;
+            }
+        )*
+    };
+}
+
+ops! {
+    Add "add" add;
+    Sub "sub" sub;
+    Mul "mul" mul;
+    BitAnd "bitand" bitand;
+    BitOr "bitor" bitor;
+    BitXor "bitxor" bitxor;
+    Shl "shl" shl;
+    Shr "shr" shr;
+}
+
+macro_rules! binops {
+    ($ty:ident: $($trait:ident $method:ident $op:tt $rhs:ident),*) => {
+        $(impl $trait<$rhs> for $ty {
+            type Output = $ty;
+            fn $method(self, rhs: $rhs) -> $ty {
+                self $op rhs
+            }
+        })*
+    };
+}
+
+macro_rules! prims {
+    ($($ty:ident)*) => {
+        $(
+            impl Copy for $ty {}
+            binops!($ty: Add add + $ty, Sub sub - $ty, Mul mul * $ty, BitAnd bitand & $ty,
+                BitOr bitor | $ty, BitXor bitxor ^ $ty, Shl shl << u32, Shr shr >> u32);
+            impl Not for $ty {
+                type Output = $ty;
+                fn not(self) -> $ty {
+                    !self
+                }
+            }
+            impl PartialEq for $ty {
+                fn eq(&self, other: &$ty) -> bool {
+                    *self == *other
+                }
+                fn ne(&self, other: &$ty) -> bool {
+                    *self != *other
+                }
+            }
+            impl PartialOrd for $ty {
+                fn lt(&self, other: &$ty) -> bool {
+                    *self < *other
+                }
+                fn le(&self, other: &$ty) -> bool {
+                    *self <= *other
+                }
+                fn gt(&self, other: &$ty) -> bool {
+                    *self > *other
+                }
+                fn ge(&self, other: &$ty) -> bool {
+                    *self >= *other
+                }
+            }
+        )*
+    };
+}
+
+prims!(u32 u64 i32 i64);
+
+#[lang = "not"]
+pub trait Not {
+    type Output;
+    fn not(self) -> Self::Output;
+}
+
+#[lang = "neg"]
+pub trait Neg {
+    type Output;
+    fn neg(self) -> Self::Output;
+}
+
+impl Neg for i32 {
+    type Output = i32;
+    fn neg(self) -> i32 {
+        -self
+    }
+}
+
+#[lang = "eq"]
+pub trait PartialEq<Rhs = Self> {
+    fn eq(&self, other: &Rhs) -> bool;
+    fn ne(&self, other: &Rhs) -> bool;
+}
+
+#[lang = "partial_ord"]
+pub trait PartialOrd<Rhs = Self> {
+    fn lt(&self, other: &Rhs) -> bool;
+    fn le(&self, other: &Rhs) -> bool;
+    fn gt(&self, other: &Rhs) -> bool;
+    fn ge(&self, other: &Rhs) -> bool;
+}
+
+impl Copy for bool {}
+impl Not for bool {
+    type Output = bool;
+    fn not(self) -> bool {
+        !self
+    }
+}
+impl PartialEq for bool {
+    fn eq(&self, other: &bool) -> bool {
+        *self == *other
+    }
+    fn ne(&self, other: &bool) -> bool {
+        *self != *other
+    }
+}
+
+#[rustc_builtin_macro]
+macro global_asm("assembly template", $(operands,)* $(options($(option),*))?) {}
+
+global_asm!(
+    "
+    .globl _start
+_start:
+    call run
+    li a7, 93
+    ecall
+"
+);
+
+// fp_lib.h, fp_add_impl.inc, fp_mul_impl.inc, fp_div_impl.inc, fp_compare_impl.inc,
+// fp_fixint_impl.inc and fp_fixuint_impl.inc, for a format `$rep` wide with
+// `$sig` bits of significand after the point
+macro_rules! soft_float {
+    ($rep:ident, $srep:ident, $width:literal, $sig:literal) => {
+        type Rep = $rep;
+        const WIDTH: u32 = $width;
+        const SIG: u32 = $sig;
+        const EXP_BITS: u32 = WIDTH - SIG - 1;
+        const MAX_EXP: u32 = (1 << EXP_BITS) - 1;
+        const BIAS: u32 = MAX_EXP >> 1;
+        const IMPLICIT: Rep = 1 << SIG;
+        const SIG_MASK: Rep = IMPLICIT - 1;
+        const SIGN: Rep = 1 << (WIDTH - 1);
+        const ABS: Rep = SIGN - 1;
+        const EXP_MASK: Rep = ABS ^ SIG_MASK;
+        const INF: Rep = EXP_MASK;
+        const QUIET: Rep = IMPLICIT >> 1;
+        const QNAN: Rep = EXP_MASK | QUIET;
+
+        fn clz(x: Rep) -> u32 {
+            let mut x = x;
+            let mut n = 0;
+            while n < WIDTH && x & SIGN == 0 {
+                x = x << 1;
+                n = n + 1;
+            }
+            n
+        }
+
+        // the significand shifted up to the implicit bit, and the exponent that
+        // makes up for it
+        fn normalize(significand: Rep) -> (Rep, i32) {
+            let shift = clz(significand) - clz(IMPLICIT);
+            (significand << shift, 1 - shift as i32)
+        }
+
+        fn wide_multiply(a: Rep, b: Rep) -> (Rep, Rep) {
+            const HALF: u32 = WIDTH >> 1;
+            const LO: Rep = (1 << HALF) - 1;
+            let plolo = (a & LO) * (b & LO);
+            let plohi = (a & LO) * (b >> HALF);
+            let philo = (a >> HALF) * (b & LO);
+            let phihi = (a >> HALF) * (b >> HALF);
+            let r0 = plolo & LO;
+            let r1 = (plolo >> HALF) + (plohi & LO) + (philo & LO);
+            (
+                (plohi >> HALF) + (philo >> HALF) + (r1 >> HALF) + phihi,
+                r0 + (r1 << HALF),
+            )
+        }
+
+        fn wide_right_shift_with_sticky(hi: Rep, lo: Rep, count: u32) -> (Rep, Rep) {
+            if count < WIDTH {
+                let sticky = (lo << (WIDTH - count) != 0) as Rep;
+                (hi >> count, hi << (WIDTH - count) | lo >> count | sticky)
+            } else if count < 2 * WIDTH {
+                let sticky = (hi << (2 * WIDTH - count) | lo != 0) as Rep;
+                (0, hi >> (count - WIDTH) | sticky)
+            } else {
+                (0, (hi | lo != 0) as Rep)
+            }
+        }
+
+        // the end of `add`: the significand has the implicit bit 3 up, with round,
+        // guard and sticky bits below it
+        fn round(sign: Rep, exponent: i32, significand: Rep) -> Rep {
+            let mut exponent = exponent;
+            let mut significand = significand;
+            if exponent >= MAX_EXP as i32 {
+                return INF | sign;
+            }
+            if exponent <= 0 {
+                let shift = (1 - exponent) as u32;
+                if shift >= WIDTH {
+                    significand = (significand != 0) as Rep;
+                } else {
+                    let sticky = (significand << (WIDTH - shift) != 0) as Rep;
+                    significand = significand >> shift | sticky;
+                }
+                exponent = 0;
+            }
+            let round_guard_sticky = significand & 7;
+            let mut result = significand >> 3 & SIG_MASK;
+            result = result | (exponent as Rep) << SIG;
+            result = result | sign;
+            if round_guard_sticky > 4 {
+                result = result + 1;
+            }
+            if round_guard_sticky == 4 {
+                result = result + (result & 1);
+            }
+            result
+        }
+
+        pub fn add(a: Rep, b: Rep) -> Rep {
+            let mut a_rep = a;
+            let mut b_rep = b;
+            let a_abs = a_rep & ABS;
+            let b_abs = b_rep & ABS;
+
+            if a_abs - 1 >= INF - 1 || b_abs - 1 >= INF - 1 {
+                if a_abs > INF {
+                    return a | QUIET;
+                }
+                if b_abs > INF {
+                    return b | QUIET;
+                }
+                if a_abs == INF {
+                    if a ^ b == SIGN {
+                        return QNAN;
+                    }
+                    return a;
+                }
+                if b_abs == INF {
+                    return b;
+                }
+                if a_abs == 0 {
+                    if b_abs == 0 {
+                        return a & b;
+                    }
+                    return b;
+                }
+                if b_abs == 0 {
+                    return a;
+                }
+            }
+
+            if b_abs > a_abs {
+                let temp = a_rep;
+                a_rep = b_rep;
+                b_rep = temp;
+            }
+
+            let mut a_exponent = (a_rep >> SIG & MAX_EXP as Rep) as i32;
+            let mut b_exponent = (b_rep >> SIG & MAX_EXP as Rep) as i32;
+            let mut a_significand = a_rep & SIG_MASK;
+            let mut b_significand = b_rep & SIG_MASK;
+            if a_exponent == 0 {
+                (a_significand, a_exponent) = normalize(a_significand);
+            }
+            if b_exponent == 0 {
+                (b_significand, b_exponent) = normalize(b_significand);
+            }
+
+            let result_sign = a_rep & SIGN;
+            let subtraction = (a_rep ^ b_rep) & SIGN != 0;
+
+            a_significand = (a_significand | IMPLICIT) << 3;
+            b_significand = (b_significand | IMPLICIT) << 3;
+
+            let align = (a_exponent - b_exponent) as u32;
+            if align != 0 {
+                if align < WIDTH {
+                    let sticky = (b_significand << (WIDTH - align) != 0) as Rep;
+                    b_significand = b_significand >> align | sticky;
+                } else {
+                    b_significand = 1;
+                }
+            }
+            if subtraction {
+                a_significand = a_significand - b_significand;
+                if a_significand == 0 {
+                    return 0;
+                }
+                if a_significand < IMPLICIT << 3 {
+                    let shift = clz(a_significand) - clz(IMPLICIT << 3);
+                    a_significand = a_significand << shift;
+                    a_exponent = a_exponent - shift as i32;
+                }
+            } else {
+                a_significand = a_significand + b_significand;
+                if a_significand & IMPLICIT << 4 != 0 {
+                    let sticky = a_significand & 1;
+                    a_significand = a_significand >> 1 | sticky;
+                    a_exponent = a_exponent + 1;
+                }
+            }
+            round(result_sign, a_exponent, a_significand)
+        }
+
+        pub fn sub(a: Rep, b: Rep) -> Rep {
+            add(a, b ^ SIGN)
+        }
+
+        pub fn mul(a: Rep, b: Rep) -> Rep {
+            let a_exponent = (a >> SIG & MAX_EXP as Rep) as u32;
+            let b_exponent = (b >> SIG & MAX_EXP as Rep) as u32;
+            let product_sign = (a ^ b) & SIGN;
+            let mut a_significand = a & SIG_MASK;
+            let mut b_significand = b & SIG_MASK;
+            let mut scale = 0;
+
+            if a_exponent - 1 >= MAX_EXP - 1 || b_exponent - 1 >= MAX_EXP - 1 {
+                let a_abs = a & ABS;
+                let b_abs = b & ABS;
+                if a_abs > INF {
+                    return a | QUIET;
+                }
+                if b_abs > INF {
+                    return b | QUIET;
+                }
+                if a_abs == INF {
+                    if b_abs != 0 {
+                        return a_abs | product_sign;
+                    }
+                    return QNAN;
+                }
+                if b_abs == INF {
+                    if a_abs != 0 {
+                        return b_abs | product_sign;
+                    }
+                    return QNAN;
+                }
+                if a_abs == 0 || b_abs == 0 {
+                    return product_sign;
+                }
+                if a_abs < IMPLICIT {
+                    let (significand, adjust) = normalize(a_significand);
+                    a_significand = significand;
+                    scale = scale + adjust;
+                }
+                if b_abs < IMPLICIT {
+                    let (significand, adjust) = normalize(b_significand);
+                    b_significand = significand;
+                    scale = scale + adjust;
+                }
+            }
+
+            a_significand = a_significand | IMPLICIT;
+            b_significand = b_significand | IMPLICIT;
+
+            let (mut product_hi, mut product_lo) =
+                wide_multiply(a_significand, b_significand << EXP_BITS);
+            let mut product_exponent = a_exponent as i32 + b_exponent as i32 - BIAS as i32 + scale;
+
+            if product_hi & IMPLICIT != 0 {
+                product_exponent = product_exponent + 1;
+            } else {
+                product_hi = product_hi << 1 | product_lo >> (WIDTH - 1);
+                product_lo = product_lo << 1;
+            }
+
+            if product_exponent >= MAX_EXP as i32 {
+                return INF | product_sign;
+            }
+            if product_exponent <= 0 {
+                let shift = (1 - product_exponent) as u32;
+                if shift >= WIDTH {
+                    return product_sign;
+                }
+                (product_hi, product_lo) =
+                    wide_right_shift_with_sticky(product_hi, product_lo, shift);
+            } else {
+                product_hi = product_hi & SIG_MASK;
+                product_hi = product_hi | (product_exponent as Rep) << SIG;
+            }
+
+            product_hi = product_hi | product_sign;
+            if product_lo > SIGN {
+                product_hi = product_hi + 1;
+            }
+            if product_lo == SIGN {
+                product_hi = product_hi + (product_hi & 1);
+            }
+            product_hi
+        }
+
+        pub fn div(a: Rep, b: Rep) -> Rep {
+            let a_exponent = (a >> SIG & MAX_EXP as Rep) as u32;
+            let b_exponent = (b >> SIG & MAX_EXP as Rep) as u32;
+            let quotient_sign = (a ^ b) & SIGN;
+            let mut a_significand = a & SIG_MASK;
+            let mut b_significand = b & SIG_MASK;
+            let mut scale = 0;
+
+            if a_exponent - 1 >= MAX_EXP - 1 || b_exponent - 1 >= MAX_EXP - 1 {
+                let a_abs = a & ABS;
+                let b_abs = b & ABS;
+                if a_abs > INF {
+                    return a | QUIET;
+                }
+                if b_abs > INF {
+                    return b | QUIET;
+                }
+                if a_abs == INF {
+                    if b_abs == INF {
+                        return QNAN;
+                    }
+                    return a_abs | quotient_sign;
+                }
+                if b_abs == INF {
+                    return quotient_sign;
+                }
+                if a_abs == 0 {
+                    if b_abs == 0 {
+                        return QNAN;
+                    }
+                    return quotient_sign;
+                }
+                if b_abs == 0 {
+                    return INF | quotient_sign;
+                }
+                if a_abs < IMPLICIT {
+                    let (significand, adjust) = normalize(a_significand);
+                    a_significand = significand;
+                    scale = scale + adjust;
+                }
+                if b_abs < IMPLICIT {
+                    let (significand, adjust) = normalize(b_significand);
+                    b_significand = significand;
+                    scale = scale - adjust;
+                }
+            }
+
+            a_significand = a_significand | IMPLICIT;
+            b_significand = b_significand | IMPLICIT;
+            let mut quotient_exponent = a_exponent as i32 - b_exponent as i32 + scale + BIAS as i32;
+
+            // the quotient in [1, 2), with round, guard and sticky bits, as `add`
+            // rounds it
+            if a_significand < b_significand {
+                a_significand = a_significand << 1;
+                quotient_exponent = quotient_exponent - 1;
+            }
+            let mut remainder = a_significand;
+            let mut quotient: Rep = 0;
+            let mut bit = 0;
+            while bit < SIG + 4 {
+                quotient = quotient << 1;
+                if remainder >= b_significand {
+                    remainder = remainder - b_significand;
+                    quotient = quotient | 1;
+                }
+                remainder = remainder << 1;
+                bit = bit + 1;
+            }
+            quotient = quotient | (remainder != 0) as Rep;
+            round(quotient_sign, quotient_exponent, quotient)
+        }
+
+        // `__leXf2`, also `__ltXf2`, `__eqXf2` and `__neXf2`
+        pub fn le(a: Rep, b: Rep) -> i32 {
+            let a_int = a as $srep;
+            let b_int = b as $srep;
+            let a_abs = a & ABS;
+            let b_abs = b & ABS;
+            if a_abs > INF || b_abs > INF {
+                return 1;
+            }
+            if a_abs | b_abs == 0 {
+                return 0;
+            }
+            if a_int & b_int >= 0 {
+                if a_int < b_int {
+                    -1
+                } else if a_int == b_int {
+                    0
+                } else {
+                    1
+                }
+            } else if a_int > b_int {
+                -1
+            } else if a_int == b_int {
+                0
+            } else {
+                1
+            }
+        }
+
+        // `__geXf2`, also `__gtXf2`
+        pub fn ge(a: Rep, b: Rep) -> i32 {
+            if a & ABS > INF || b & ABS > INF {
+                return -1;
+            }
+            le(a, b)
+        }
+
+        pub fn unord(a: Rep, b: Rep) -> i32 {
+            (a & ABS > INF || b & ABS > INF) as i32
+        }
+
+        pub fn neg(a: Rep) -> Rep {
+            a ^ SIGN
+        }
+
+        // `__fixXfYi` to an integer `width` bits wide, returned in the low bits
+        pub fn fixint(a: Rep, width: u32) -> u64 {
+            let max = !0u64 >> (64 - width) >> 1;
+            let a_abs = a & ABS;
+            let negative = a & SIGN != 0;
+            let exponent = (a_abs >> SIG) as i32 - BIAS as i32;
+            let significand = (a_abs & SIG_MASK | IMPLICIT) as u64;
+
+            if exponent < 0 {
+                return 0;
+            }
+            if exponent as u32 >= width {
+                return if negative { !max } else { max };
+            }
+            let magnitude = if (exponent as u32) < SIG {
+                significand >> (SIG - exponent as u32)
+            } else {
+                significand << (exponent as u32 - SIG)
+            };
+            if negative {
+                0 - magnitude
+            } else {
+                magnitude
+            }
+        }
+
+        // `__fixunsXfYi` to an integer `width` bits wide
+        pub fn fixuint(a: Rep, width: u32) -> u64 {
+            let a_abs = a & ABS;
+            let exponent = (a_abs >> SIG) as i32 - BIAS as i32;
+            let significand = (a_abs & SIG_MASK | IMPLICIT) as u64;
+
+            if a & SIGN != 0 || exponent < 0 {
+                return 0;
+            }
+            if exponent as u32 >= width {
+                return !0u64 >> (64 - width);
+            }
+            if (exponent as u32) < SIG {
+                significand >> (SIG - exponent as u32)
+            } else {
+                significand << (exponent as u32 - SIG)
+            }
+        }
+
+        // int_to_fp_impl.inc: `magnitude`, negated if `negative`, rounded to
+        // nearest
+        pub fn from_int(negative: bool, magnitude: u64) -> Rep {
+            if magnitude == 0 {
+                return 0;
+            }
+            let sign = if negative { SIGN } else { 0 };
+            let mut m = magnitude;
+            let mut lz = 0;
+            while m & (1 << 63) == 0 {
+                m = m << 1;
+                lz = lz + 1;
+            }
+            let exponent = 63 - lz;
+            let result = if exponent <= SIG {
+                (magnitude << (SIG - exponent)) as Rep
+            } else {
+                let shift = exponent - SIG;
+                let dropped = magnitude << (64 - shift);
+                let kept = (magnitude >> shift) as Rep;
+                let half = 1 << 63;
+                if dropped > half || (dropped == half && kept & 1 != 0) {
+                    // a carry out of the significand bumps the exponent, as it should
+                    return sign | (((exponent + BIAS) as Rep) << SIG) + (kept & SIG_MASK) + 1;
+                }
+                kept
+            };
+            sign | ((exponent + BIAS) as Rep) << SIG | result & SIG_MASK
+        }
+    };
+}
+
+mod double {
+    soft_float!(u64, i64, 64, 52);
+
+    // fp_trunc_impl.inc, to a single
+    pub fn trunc(a: u64) -> u32 {
+        const DST_SIG: u32 = 23;
+        const DST_INF_EXP: u32 = 255;
+        const DST_BIAS: u32 = 127;
+        const ROUND_MASK: u64 = (1 << (SIG - DST_SIG)) - 1;
+        const HALFWAY: u64 = 1 << (SIG - DST_SIG - 1);
+        const NAN_CODE: u64 = QUIET - 1;
+        const UNDERFLOW: u64 = ((BIAS + 1 - DST_BIAS) as u64) << SIG;
+        const OVERFLOW: u64 = ((BIAS + DST_INF_EXP - DST_BIAS) as u64) << SIG;
+        const DST_QUIET: u32 = 1 << (DST_SIG - 1);
+
+        let a_abs = a & ABS;
+        let sign = a & SIGN;
+        let mut abs_result;
+        if a_abs - UNDERFLOW < a_abs - OVERFLOW {
+            abs_result = (a_abs >> (SIG - DST_SIG)) as u32;
+            abs_result = abs_result - ((BIAS - DST_BIAS) << DST_SIG);
+            let round_bits = a_abs & ROUND_MASK;
+            if round_bits > HALFWAY {
+                abs_result = abs_result + 1;
+            } else if round_bits == HALFWAY {
+                abs_result = abs_result + (abs_result & 1);
+            }
+        } else if a_abs > INF {
+            abs_result = DST_INF_EXP << DST_SIG;
+            abs_result = abs_result | DST_QUIET;
+            abs_result =
+                abs_result | ((a_abs & NAN_CODE) >> (SIG - DST_SIG)) as u32 & (DST_QUIET - 1);
+        } else if a_abs >= OVERFLOW {
+            abs_result = DST_INF_EXP << DST_SIG;
+        } else {
+            let a_exp = (a_abs >> SIG) as u32;
+            let shift = BIAS - DST_BIAS - a_exp + 1;
+            let significand = a & SIG_MASK | IMPLICIT;
+            if shift > SIG {
+                abs_result = 0;
+            } else {
+                let sticky = (significand << (64 - shift) != 0) as u64;
+                let denormalized = significand >> shift | sticky;
+                abs_result = (denormalized >> (SIG - DST_SIG)) as u32;
+                let round_bits = denormalized & ROUND_MASK;
+                if round_bits > HALFWAY {
+                    abs_result = abs_result + 1;
+                } else if round_bits == HALFWAY {
+                    abs_result = abs_result + (abs_result & 1);
+                }
+            }
+        }
+        abs_result | (sign >> 32) as u32
+    }
+}
+
+mod single {
+    soft_float!(u32, i32, 32, 23);
+
+    // fp_extend_impl.inc, to a double
+    pub fn extend(a: u32) -> u64 {
+        const DST_SIG: u32 = 52;
+        const DST_INF_EXP: u64 = 0x7ff;
+        const DST_BIAS: u32 = 1023;
+        const MIN_NORMAL: u32 = 1 << SIG;
+        const NAN_CODE: u32 = QUIET - 1;
+
+        let a_abs = a & ABS;
+        let sign = a & SIGN;
+        let mut abs_result: u64;
+        if a_abs - MIN_NORMAL < INF - MIN_NORMAL {
+            abs_result = (a_abs as u64) << (DST_SIG - SIG);
+            abs_result = abs_result + (((DST_BIAS - BIAS) as u64) << DST_SIG);
+        } else if a_abs >= INF {
+            abs_result = DST_INF_EXP << DST_SIG;
+            abs_result = abs_result | ((a_abs & QUIET) as u64) << (DST_SIG - SIG);
+            abs_result = abs_result | ((a_abs & NAN_CODE) as u64) << (DST_SIG - SIG);
+        } else if a_abs != 0 {
+            let scale = clz(a_abs) - clz(MIN_NORMAL);
+            abs_result = (a_abs as u64) << (DST_SIG - SIG + scale);
+            abs_result = abs_result ^ (1 << DST_SIG);
+            let result_exponent = DST_BIAS - BIAS - scale + 1;
+            abs_result = abs_result | (result_exponent as u64) << DST_SIG;
+        } else {
+            abs_result = 0;
+        }
+        abs_result | (sign as u64) << 32
+    }
+}
+
+// each helper twice, under its own name and as `guest_*`, which riscy doesn't
+// service
+macro_rules! helpers {
+    ($($name:ident $guest:ident |$($arg:ident: $ty:ty),*| -> $ret:ty $body:block)*) => {
+        $(
+            #[no_mangle]
+            #[inline(never)]
+            pub extern "C" fn $name($($arg: $ty),*) -> $ret $body
+
+            #[no_mangle]
+            #[inline(never)]
+            pub extern "C" fn $guest($($arg: $ty),*) -> $ret $body
+        )*
+    };
+}
+
+helpers! {
+    __adddf3 guest_adddf3 |a: u64, b: u64| -> u64 { double::add(a, b) }
+    __subdf3 guest_subdf3 |a: u64, b: u64| -> u64 { double::sub(a, b) }
+    __muldf3 guest_muldf3 |a: u64, b: u64| -> u64 { double::mul(a, b) }
+    __divdf3 guest_divdf3 |a: u64, b: u64| -> u64 { double::div(a, b) }
+    __negdf2 guest_negdf2 |a: u64| -> u64 { double::neg(a) }
+    __eqdf2 guest_eqdf2 |a: u64, b: u64| -> i32 { double::le(a, b) }
+    __nedf2 guest_nedf2 |a: u64, b: u64| -> i32 { double::le(a, b) }
+    __ltdf2 guest_ltdf2 |a: u64, b: u64| -> i32 { double::le(a, b) }
+    __ledf2 guest_ledf2 |a: u64, b: u64| -> i32 { double::le(a, b) }
+    __gtdf2 guest_gtdf2 |a: u64, b: u64| -> i32 { double::ge(a, b) }
+    __gedf2 guest_gedf2 |a: u64, b: u64| -> i32 { double::ge(a, b) }
+    __unorddf2 guest_unorddf2 |a: u64, b: u64| -> i32 { double::unord(a, b) }
+    __floatsidf guest_floatsidf |a: i32| -> u64 { double::from_int(a < 0, if a < 0 { 0 - a as i64 } else { a as i64 } as u64) }
+    __floatunsidf guest_floatunsidf |a: u32| -> u64 { double::from_int(false, a as u64) }
+    __floatdidf guest_floatdidf |a: i64| -> u64 { double::from_int(a < 0, if a < 0 { 0 - a as u64 } else { a as u64 }) }
+    __floatundidf guest_floatundidf |a: u64| -> u64 { double::from_int(false, a) }
+    __fixdfsi guest_fixdfsi |a: u64| -> i32 { double::fixint(a, 32) as i32 }
+    __fixunsdfsi guest_fixunsdfsi |a: u64| -> u32 { double::fixuint(a, 32) as u32 }
+    __fixdfdi guest_fixdfdi |a: u64| -> i64 { double::fixint(a, 64) as i64 }
+    __fixunsdfdi guest_fixunsdfdi |a: u64| -> u64 { double::fixuint(a, 64) }
+    __extendsfdf2 guest_extendsfdf2 |a: u32| -> u64 { single::extend(a) }
+    __truncdfsf2 guest_truncdfsf2 |a: u64| -> u32 { double::trunc(a) }
+    __addsf3 guest_addsf3 |a: u32, b: u32| -> u32 { single::add(a, b) }
+    __subsf3 guest_subsf3 |a: u32, b: u32| -> u32 { single::sub(a, b) }
+    __mulsf3 guest_mulsf3 |a: u32, b: u32| -> u32 { single::mul(a, b) }
+    __divsf3 guest_divsf3 |a: u32, b: u32| -> u32 { single::div(a, b) }
+    __negsf2 guest_negsf2 |a: u32| -> u32 { single::neg(a) }
+    __eqsf2 guest_eqsf2 |a: u32, b: u32| -> i32 { single::le(a, b) }
+    __nesf2 guest_nesf2 |a: u32, b: u32| -> i32 { single::le(a, b) }
+    __ltsf2 guest_ltsf2 |a: u32, b: u32| -> i32 { single::le(a, b) }
+    __lesf2 guest_lesf2 |a: u32, b: u32| -> i32 { single::le(a, b) }
+    __gtsf2 guest_gtsf2 |a: u32, b: u32| -> i32 { single::ge(a, b) }
+    __gesf2 guest_gesf2 |a: u32, b: u32| -> i32 { single::ge(a, b) }
+    __unordsf2 guest_unordsf2 |a: u32, b: u32| -> i32 { single::unord(a, b) }
+    __floatsisf guest_floatsisf |a: i32| -> u32 { single::from_int(a < 0, if a < 0 { 0 - a as i64 } else { a as i64 } as u64) }
+    __floatunsisf guest_floatunsisf |a: u32| -> u32 { single::from_int(false, a as u64) }
+    __floatdisf guest_floatdisf |a: i64| -> u32 { single::from_int(a < 0, if a < 0 { 0 - a as u64 } else { a as u64 }) }
+    __floatundisf guest_floatundisf |a: u64| -> u32 { single::from_int(false, a) }
+    __fixsfsi guest_fixsfsi |a: u32| -> i32 { single::fixint(a, 32) as i32 }
+    __fixunssfsi guest_fixunssfsi |a: u32| -> u32 { single::fixuint(a, 32) as u32 }
+    __fixsfdi guest_fixsfdi |a: u32| -> i64 { single::fixint(a, 64) as i64 }
+    __fixunssfdi guest_fixunssfdi |a: u32| -> u64 { single::fixuint(a, 64) }
+}
+
+// the `i`th of the doubles each helper is checked on, and every pair of them for
+// those taking two
+fn double(i: u32) -> u64 {
+    match i {
+        0 => 0,
+        1 => 0x8000_0000_0000_0000,
+        2 => 0x3ff0_0000_0000_0000,  // 1
+        3 => 0xbff0_0000_0000_0000,  // -1
+        4 => 0x3ff8_0000_0000_0000,  // 1.5
+        5 => 0xc004_0000_0000_0000,  // -2.5
+        6 => 0xbfe0_0000_0000_0000,  // -0.5
+        7 => 0x3fef_ffff_ffff_ffff,  // just under 1
+        8 => 0x3ff0_0000_0000_0001,  // just over 1
+        9 => 0x41e0_0000_0000_0000,  // 2^31
+        10 => 0xc1e0_0000_0000_0000, // -2^31
+        11 => 0xc1e0_0000_0020_0000, // -2^31 - 1
+        12 => 0x41f0_0000_0000_0000, // 2^32
+        13 => 0x41e6_5a0b_c000_0000, // 3e9
+        14 => 0x43e0_0000_0000_0000, // 2^63
+        15 => 0xc3e0_0000_0000_0000, // -2^63
+        16 => 0x43f0_0000_0000_0000, // 2^64
+        17 => 0x7fef_ffff_ffff_ffff, // the largest
+        18 => 0xffef_ffff_ffff_ffff,
+        19 => 0x0010_0000_0000_0000, // the smallest normal
+        20 => 0x0000_0000_0000_0001, // the smallest subnormal
+        21 => 0x800f_ffff_ffff_ffff, // the largest subnormal, negative
+        22 => 0x3810_0000_0000_0000, // the smallest normal single
+        23 => 0x36a0_0000_0000_0000, // the smallest subnormal single
+        24 => 0x47ef_ffff_f000_0000, // halfway past the largest single
+        25 => 0x7ff0_0000_0000_0000, // inf
+        26 => 0xfff0_0000_0000_0000, // -inf
+        27 => 0x7ff8_0000_0000_0000, // the canonical NaN
+        28 => 0xfff8_0000_0000_0123, // a negative NaN with a payload
+        29 => 0x7ff0_0000_0000_0001, // a signalling NaN
+        _ => 0xfff4_0000_2000_0000,  // a negative one, payload in a single's bits
+    }
+}
+
+const DOUBLES: u32 = 31;
+
+fn single(i: u32) -> u32 {
+    match i {
+        0 => 0,
+        1 => 0x8000_0000,
+        2 => 0x3f80_0000,  // 1
+        3 => 0xbf80_0000,  // -1
+        4 => 0x3fc0_0000,  // 1.5
+        5 => 0xc020_0000,  // -2.5
+        6 => 0xbf00_0000,  // -0.5
+        7 => 0x3f7f_ffff,  // just under 1
+        8 => 0x3f80_0001,  // just over 1
+        9 => 0x4f00_0000,  // 2^31
+        10 => 0xcf00_0000, // -2^31
+        11 => 0xcf00_0001, // just under -2^31
+        12 => 0x4f80_0000, // 2^32
+        13 => 0x4f32_d05e, // 3e9
+        14 => 0x5f00_0000, // 2^63
+        15 => 0xdf00_0000, // -2^63
+        16 => 0x5f80_0000, // 2^64
+        17 => 0x7f7f_ffff, // the largest
+        18 => 0xff7f_ffff,
+        19 => 0x0080_0000, // the smallest normal
+        20 => 0x0000_0001, // the smallest subnormal
+        21 => 0x807f_ffff, // the largest subnormal, negative
+        22 => 0x7f80_0000, // inf
+        23 => 0xff80_0000, // -inf
+        24 => 0x7fc0_0000, // the canonical NaN
+        25 => 0xffc0_0123, // a negative NaN with a payload
+        26 => 0x7f80_0001, // a signalling NaN
+        _ => 0xffa0_0000,  // a negative one
+    }
+}
+
+const SINGLES: u32 = 28;
+
+// the integers converted to floats
+fn int(i: u32) -> u64 {
+    match i {
+        0 => 0,
+        1 => 1,
+        2 => !0,                    // -1
+        3 => 0xffff_ffff_8000_0000, // i32::MIN
+        4 => 0x7fff_ffff,           // i32::MAX
+        5 => 0xffff_ffff,           // u32::MAX
+        6 => (1 << 24) + 1,         // a tie for a single
+        7 => (1 << 24) + 3,
+        8 => (1 << 53) + 1, // a tie for a double
+        9 => (1 << 53) + 3,
+        10 => 0x8000_0000_0000_0000, // i64::MIN
+        11 => 0x7fff_ffff_ffff_ffff, // i64::MAX
+        12 => 0x8000_0080_0000_0001, // just over a tie for a single
+        _ => 0xffff_ffff_ffff_fc00,  // rounding up to 2^64 as a double
+    }
+}
+
+const INTS: u32 = 14;
+
+macro_rules! check {
+    ($case:ident, $name:ident, $guest:ident ($($arg:expr),*)) => {
+        $case = $case + 1;
+        if $name($($arg),*) != $guest($($arg),*) {
+            return $case;
+        }
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn run() -> u32 {
+    let mut case = 0;
+
+    let mut i = 0;
+    while i < DOUBLES {
+        let a = double(i);
+        check!(case, __negdf2, guest_negdf2(a));
+        check!(case, __fixdfsi, guest_fixdfsi(a));
+        check!(case, __fixunsdfsi, guest_fixunsdfsi(a));
+        check!(case, __fixdfdi, guest_fixdfdi(a));
+        check!(case, __fixunsdfdi, guest_fixunsdfdi(a));
+        check!(case, __truncdfsf2, guest_truncdfsf2(a));
+        let mut j = 0;
+        while j < DOUBLES {
+            let b = double(j);
+            check!(case, __adddf3, guest_adddf3(a, b));
+            check!(case, __subdf3, guest_subdf3(a, b));
+            check!(case, __muldf3, guest_muldf3(a, b));
+            check!(case, __divdf3, guest_divdf3(a, b));
+            check!(case, __eqdf2, guest_eqdf2(a, b));
+            check!(case, __nedf2, guest_nedf2(a, b));
+            check!(case, __ltdf2, guest_ltdf2(a, b));
+            check!(case, __ledf2, guest_ledf2(a, b));
+            check!(case, __gtdf2, guest_gtdf2(a, b));
+            check!(case, __gedf2, guest_gedf2(a, b));
+            check!(case, __unorddf2, guest_unorddf2(a, b));
+            j = j + 1;
+        }
+        i = i + 1;
+    }
+
+    let mut i = 0;
+    while i < SINGLES {
+        let a = single(i);
+        check!(case, __negsf2, guest_negsf2(a));
+        check!(case, __fixsfsi, guest_fixsfsi(a));
+        check!(case, __fixunssfsi, guest_fixunssfsi(a));
+        check!(case, __fixsfdi, guest_fixsfdi(a));
+        check!(case, __fixunssfdi, guest_fixunssfdi(a));
+        check!(case, __extendsfdf2, guest_extendsfdf2(a));
+        let mut j = 0;
+        while j < SINGLES {
+            let b = single(j);
+            check!(case, __addsf3, guest_addsf3(a, b));
+            check!(case, __subsf3, guest_subsf3(a, b));
+            check!(case, __mulsf3, guest_mulsf3(a, b));
+            check!(case, __divsf3, guest_divsf3(a, b));
+            check!(case, __eqsf2, guest_eqsf2(a, b));
+            check!(case, __nesf2, guest_nesf2(a, b));
+            check!(case, __ltsf2, guest_ltsf2(a, b));
+            check!(case, __lesf2, guest_lesf2(a, b));
+            check!(case, __gtsf2, guest_gtsf2(a, b));
+            check!(case, __gesf2, guest_gesf2(a, b));
+            check!(case, __unordsf2, guest_unordsf2(a, b));
+            j = j + 1;
+        }
+        i = i + 1;
+    }
+
+    let mut i = 0;
+    while i < INTS {
+        let a = int(i);
+        check!(case, __floatsidf, guest_floatsidf(a as i32));
+        check!(case, __floatunsidf, guest_floatunsidf(a as u32));
+        check!(case, __floatdidf, guest_floatdidf(a as i64));
+        check!(case, __floatundidf, guest_floatundidf(a));
+        check!(case, __floatsisf, guest_floatsisf(a as i32));
+        check!(case, __floatunsisf, guest_floatunsisf(a as u32));
+        check!(case, __floatdisf, guest_floatdisf(a as i64));
+        check!(case, __floatundisf, guest_floatundisf(a));
+        i = i + 1;
+    }
+
+    0
+}
@@ -0,0 +1,40 @@
+# Virtual calls through a two-entry vtable, one every pass and the other
+# every eighth, 2M passes
+    .globl _start
+    .text
+_start:
+    li s0, 2000000
+    li s1, 0
+    la s2, vtable
+loop:
+    lw t0, 0(s2)
+    mv a0, s1
+    jalr t0
+    mv s1, a0
+    andi t1, s0, 7
+    bnez t1, mono
+    lw t0, 4(s2)
+    mv a0, s1
+    jalr t0
+    mv s1, a0
+mono:
+    addi s0, s0, -1
+    bnez s0, loop
+    andi a0, s1, 0x7f
+    li a7, 93
+    ecall
+inc:
+    addi a0, a0, 3
+    xori a0, a0, 5
+    slli t2, a0, 1
+    add a0, a0, t2
+    ret
+dec:
+    addi a0, a0, -1
+    srli t2, a0, 2
+    sub a0, a0, t2
+    addi a0, a0, 1
+    ret
+    .data
+vtable:
+    .word inc, dec
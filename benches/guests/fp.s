@@ -0,0 +1,65 @@
+# y = a*x + y over 1024 doubles, then a Horner polynomial of each y, 2000 passes
+    .globl _start
+    .text
+_start:
+    la s1, x
+    la s2, y
+    li t0, 1024
+    fcvt.d.w ft0, zero
+    li t1, 1
+    fcvt.d.w ft1, t1
+    li t1, 1000
+    fcvt.d.w ft2, t1
+    fdiv.d ft1, ft1, ft2        # 0.001
+init:
+    fadd.d ft0, ft0, ft1
+    fsd ft0, 0(s1)
+    fsd ft1, 0(s2)
+    addi s1, s1, 8
+    addi s2, s2, 8
+    addi t0, t0, -1
+    bnez t0, init
+
+    li s0, 2000
+    li t1, 3
+    fcvt.d.w fa0, t1
+    fdiv.d fa0, ft1, fa0        # a
+    fcvt.d.w fs0, zero          # sum
+pass:
+    la s1, x
+    la s2, y
+    li t0, 1024
+axpy:
+    fld ft3, 0(s1)
+    fld ft4, 0(s2)
+    fmadd.d ft4, fa0, ft3, ft4
+    # ((y/2 + 1/3) y + 1/5) y, stays bounded for small y
+    fmul.d ft5, ft4, ft1
+    fadd.d ft5, ft5, fa0
+    fmadd.d ft5, ft5, ft4, ft1
+    fmul.d ft5, ft5, ft4
+    fadd.d fs0, fs0, ft5
+    fsd ft4, 0(s2)
+    addi s1, s1, 8
+    addi s2, s2, 8
+    addi t0, t0, -1
+    bnez t0, axpy
+    addi s0, s0, -1
+    bnez s0, pass
+
+    # the sum's low bits too, so an ulp off anywhere changes the code
+    fcvt.w.d a0, fs0, rtz
+    la t0, y
+    fsd fs0, 0(t0)
+    lw t1, 0(t0)
+    xor a0, a0, t1
+    andi a0, a0, 0x7f
+    li a7, 93
+    ecall
+
+    .bss
+    .balign 8
+x:
+    .zero 8192
+y:
+    .zero 8192
@@ -0,0 +1,1072 @@
+# Known answers for what riscy's fp results depend on besides the values: the
+# rounding mode, NaN-boxing, canonical NaNs and saturation. Each case is
+# checked in turn, and the exit code is the number of the first that's wrong,
+# or 0. Run under --self-check too, so the core and the oracle have to agree
+# on every step as well as on the answers. The answers were worked out with
+# exact rational arithmetic, not by either of them
+
+# fail unless `a` and `b` are equal, from further away than a branch reaches
+    .macro expect a, b
+    beq \a, \b, 1f
+    j fail
+1:
+    .endm
+
+# the next case, failing unless `reg` is `expected`
+    .macro check_x reg, expected
+    addi s0, s0, 1
+    li t6, \expected
+    expect \reg, t6
+    .endm
+
+# the next case, failing unless `freg` is the single `bits`, NaN-boxed
+    .macro check_s freg, bits
+    addi s0, s0, 1
+    fsd \freg, 0(s1)
+    lw t5, 0(s1)
+    li t6, \bits
+    expect t5, t6
+    lw t5, 4(s1)
+    li t6, -1
+    expect t5, t6
+    .endm
+
+# the next case, failing unless `freg` is the double `hi:lo`
+    .macro check_d freg, hi, lo
+    addi s0, s0, 1
+    fsd \freg, 0(s1)
+    lw t5, 0(s1)
+    li t6, \lo
+    expect t5, t6
+    lw t5, 4(s1)
+    li t6, \hi
+    expect t5, t6
+    .endm
+
+# the next case, failing unless the flags raised since the last are `expected`
+    .macro check_flags expected
+    csrrw t5, fflags, zero
+    check_x t5, \expected
+    .endm
+
+    .macro li_s freg, bits
+    li t5, \bits
+    fmv.w.x \freg, t5
+    .endm
+
+    .macro li_d freg, hi, lo
+    li t5, \lo
+    sw t5, 0(s1)
+    li t5, \hi
+    sw t5, 4(s1)
+    fld \freg, 0(s1)
+    .endm
+
+    .globl _start
+    .text
+_start:
+    la s1, scratch
+    li s0, 0
+    csrw fflags, zero
+
+# fcvt.w and fcvt.wu, rounding and saturating
+    li_s fa0, 1075838976
+    fcvt.w.s a0, fa0, rne    # 2.5
+    check_x a0, 2
+    fcvt.w.s a0, fa0, rtz    # 2.5
+    check_x a0, 2
+    fcvt.w.s a0, fa0, rdn    # 2.5
+    check_x a0, 2
+    fcvt.w.s a0, fa0, rup    # 2.5
+    check_x a0, 3
+    fcvt.w.s a0, fa0, rmm    # 2.5
+    check_x a0, 3
+    check_flags 1
+    fcvt.wu.s a0, fa0, rne    # 2.5
+    check_x a0, 2
+    fcvt.wu.s a0, fa0, rtz    # 2.5
+    check_x a0, 2
+    fcvt.wu.s a0, fa0, rdn    # 2.5
+    check_x a0, 2
+    fcvt.wu.s a0, fa0, rup    # 2.5
+    check_x a0, 3
+    fcvt.wu.s a0, fa0, rmm    # 2.5
+    check_x a0, 3
+    check_flags 1
+    li_s fa0, -1071644672
+    fcvt.w.s a0, fa0, rne    # -2.5
+    check_x a0, -2
+    fcvt.w.s a0, fa0, rtz    # -2.5
+    check_x a0, -2
+    fcvt.w.s a0, fa0, rdn    # -2.5
+    check_x a0, -3
+    fcvt.w.s a0, fa0, rup    # -2.5
+    check_x a0, -2
+    fcvt.w.s a0, fa0, rmm    # -2.5
+    check_x a0, -3
+    check_flags 1
+    fcvt.wu.s a0, fa0, rne    # -2.5
+    check_x a0, 0
+    fcvt.wu.s a0, fa0, rtz    # -2.5
+    check_x a0, 0
+    fcvt.wu.s a0, fa0, rdn    # -2.5
+    check_x a0, 0
+    fcvt.wu.s a0, fa0, rup    # -2.5
+    check_x a0, 0
+    fcvt.wu.s a0, fa0, rmm    # -2.5
+    check_x a0, 0
+    check_flags 16
+    li_s fa0, -1090519040
+    fcvt.w.s a0, fa0, rne    # -0.5
+    check_x a0, 0
+    fcvt.w.s a0, fa0, rtz    # -0.5
+    check_x a0, 0
+    fcvt.w.s a0, fa0, rdn    # -0.5
+    check_x a0, -1
+    fcvt.w.s a0, fa0, rup    # -0.5
+    check_x a0, 0
+    fcvt.w.s a0, fa0, rmm    # -0.5
+    check_x a0, -1
+    check_flags 1
+    fcvt.wu.s a0, fa0, rne    # -0.5
+    check_x a0, 0
+    fcvt.wu.s a0, fa0, rtz    # -0.5
+    check_x a0, 0
+    fcvt.wu.s a0, fa0, rdn    # -0.5
+    check_x a0, 0
+    fcvt.wu.s a0, fa0, rup    # -0.5
+    check_x a0, 0
+    fcvt.wu.s a0, fa0, rmm    # -0.5
+    check_x a0, 0
+    check_flags 17
+    li_s fa0, 2143289344
+    fcvt.w.s a0, fa0, rne    # nan
+    check_x a0, 2147483647
+    fcvt.w.s a0, fa0, rtz    # nan
+    check_x a0, 2147483647
+    fcvt.w.s a0, fa0, rdn    # nan
+    check_x a0, 2147483647
+    fcvt.w.s a0, fa0, rup    # nan
+    check_x a0, 2147483647
+    fcvt.w.s a0, fa0, rmm    # nan
+    check_x a0, 2147483647
+    check_flags 16
+    fcvt.wu.s a0, fa0, rne    # nan
+    check_x a0, -1
+    fcvt.wu.s a0, fa0, rtz    # nan
+    check_x a0, -1
+    fcvt.wu.s a0, fa0, rdn    # nan
+    check_x a0, -1
+    fcvt.wu.s a0, fa0, rup    # nan
+    check_x a0, -1
+    fcvt.wu.s a0, fa0, rmm    # nan
+    check_x a0, -1
+    check_flags 16
+    li_s fa0, 1328730206
+    fcvt.w.s a0, fa0, rne    # 3000000000.0
+    check_x a0, 2147483647
+    fcvt.w.s a0, fa0, rtz    # 3000000000.0
+    check_x a0, 2147483647
+    fcvt.w.s a0, fa0, rdn    # 3000000000.0
+    check_x a0, 2147483647
+    fcvt.w.s a0, fa0, rup    # 3000000000.0
+    check_x a0, 2147483647
+    fcvt.w.s a0, fa0, rmm    # 3000000000.0
+    check_x a0, 2147483647
+    check_flags 16
+    fcvt.wu.s a0, fa0, rne    # 3000000000.0
+    check_x a0, -1294967296
+    fcvt.wu.s a0, fa0, rtz    # 3000000000.0
+    check_x a0, -1294967296
+    fcvt.wu.s a0, fa0, rdn    # 3000000000.0
+    check_x a0, -1294967296
+    fcvt.wu.s a0, fa0, rup    # 3000000000.0
+    check_x a0, -1294967296
+    fcvt.wu.s a0, fa0, rmm    # 3000000000.0
+    check_x a0, -1294967296
+    check_flags 0
+    li_s fa0, 1332636456
+    fcvt.w.s a0, fa0, rne    # 4000000000.0
+    check_x a0, 2147483647
+    fcvt.w.s a0, fa0, rtz    # 4000000000.0
+    check_x a0, 2147483647
+    fcvt.w.s a0, fa0, rdn    # 4000000000.0
+    check_x a0, 2147483647
+    fcvt.w.s a0, fa0, rup    # 4000000000.0
+    check_x a0, 2147483647
+    fcvt.w.s a0, fa0, rmm    # 4000000000.0
+    check_x a0, 2147483647
+    check_flags 16
+    fcvt.wu.s a0, fa0, rne    # 4000000000.0
+    check_x a0, -294967296
+    fcvt.wu.s a0, fa0, rtz    # 4000000000.0
+    check_x a0, -294967296
+    fcvt.wu.s a0, fa0, rdn    # 4000000000.0
+    check_x a0, -294967296
+    fcvt.wu.s a0, fa0, rup    # 4000000000.0
+    check_x a0, -294967296
+    fcvt.wu.s a0, fa0, rmm    # 4000000000.0
+    check_x a0, -294967296
+    check_flags 0
+    li_s fa0, 1069547520
+    fcvt.w.s a0, fa0, rne    # 1.5
+    check_x a0, 2
+    fcvt.w.s a0, fa0, rtz    # 1.5
+    check_x a0, 1
+    fcvt.w.s a0, fa0, rdn    # 1.5
+    check_x a0, 1
+    fcvt.w.s a0, fa0, rup    # 1.5
+    check_x a0, 2
+    fcvt.w.s a0, fa0, rmm    # 1.5
+    check_x a0, 2
+    check_flags 1
+    fcvt.wu.s a0, fa0, rne    # 1.5
+    check_x a0, 2
+    fcvt.wu.s a0, fa0, rtz    # 1.5
+    check_x a0, 1
+    fcvt.wu.s a0, fa0, rdn    # 1.5
+    check_x a0, 1
+    fcvt.wu.s a0, fa0, rup    # 1.5
+    check_x a0, 2
+    fcvt.wu.s a0, fa0, rmm    # 1.5
+    check_x a0, 2
+    check_flags 1
+    li_d fa0, 1074003968, 0
+    fcvt.w.d a0, fa0, rne    # 2.5
+    check_x a0, 2
+    fcvt.w.d a0, fa0, rtz    # 2.5
+    check_x a0, 2
+    fcvt.w.d a0, fa0, rdn    # 2.5
+    check_x a0, 2
+    fcvt.w.d a0, fa0, rup    # 2.5
+    check_x a0, 3
+    fcvt.w.d a0, fa0, rmm    # 2.5
+    check_x a0, 3
+    check_flags 1
+    fcvt.wu.d a0, fa0, rne    # 2.5
+    check_x a0, 2
+    fcvt.wu.d a0, fa0, rtz    # 2.5
+    check_x a0, 2
+    fcvt.wu.d a0, fa0, rdn    # 2.5
+    check_x a0, 2
+    fcvt.wu.d a0, fa0, rup    # 2.5
+    check_x a0, 3
+    fcvt.wu.d a0, fa0, rmm    # 2.5
+    check_x a0, 3
+    check_flags 1
+    li_d fa0, -1073479680, 0
+    fcvt.w.d a0, fa0, rne    # -2.5
+    check_x a0, -2
+    fcvt.w.d a0, fa0, rtz    # -2.5
+    check_x a0, -2
+    fcvt.w.d a0, fa0, rdn    # -2.5
+    check_x a0, -3
+    fcvt.w.d a0, fa0, rup    # -2.5
+    check_x a0, -2
+    fcvt.w.d a0, fa0, rmm    # -2.5
+    check_x a0, -3
+    check_flags 1
+    fcvt.wu.d a0, fa0, rne    # -2.5
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rtz    # -2.5
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rdn    # -2.5
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rup    # -2.5
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rmm    # -2.5
+    check_x a0, 0
+    check_flags 16
+    li_d fa0, 1074135040, 0
+    fcvt.w.d a0, fa0, rne    # 2.75
+    check_x a0, 3
+    fcvt.w.d a0, fa0, rtz    # 2.75
+    check_x a0, 2
+    fcvt.w.d a0, fa0, rdn    # 2.75
+    check_x a0, 2
+    fcvt.w.d a0, fa0, rup    # 2.75
+    check_x a0, 3
+    fcvt.w.d a0, fa0, rmm    # 2.75
+    check_x a0, 3
+    check_flags 1
+    fcvt.wu.d a0, fa0, rne    # 2.75
+    check_x a0, 3
+    fcvt.wu.d a0, fa0, rtz    # 2.75
+    check_x a0, 2
+    fcvt.wu.d a0, fa0, rdn    # 2.75
+    check_x a0, 2
+    fcvt.wu.d a0, fa0, rup    # 2.75
+    check_x a0, 3
+    fcvt.wu.d a0, fa0, rmm    # 2.75
+    check_x a0, 3
+    check_flags 1
+    li_d fa0, -1075838976, 0
+    fcvt.w.d a0, fa0, rne    # -0.5
+    check_x a0, 0
+    fcvt.w.d a0, fa0, rtz    # -0.5
+    check_x a0, 0
+    fcvt.w.d a0, fa0, rdn    # -0.5
+    check_x a0, -1
+    fcvt.w.d a0, fa0, rup    # -0.5
+    check_x a0, 0
+    fcvt.w.d a0, fa0, rmm    # -0.5
+    check_x a0, -1
+    check_flags 1
+    fcvt.wu.d a0, fa0, rne    # -0.5
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rtz    # -0.5
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rdn    # -0.5
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rup    # -0.5
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rmm    # -0.5
+    check_x a0, 0
+    check_flags 17
+    li_d fa0, 2146959360, 0
+    fcvt.w.d a0, fa0, rne    # nan
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rtz    # nan
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rdn    # nan
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rup    # nan
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rmm    # nan
+    check_x a0, 2147483647
+    check_flags 16
+    fcvt.wu.d a0, fa0, rne    # nan
+    check_x a0, -1
+    fcvt.wu.d a0, fa0, rtz    # nan
+    check_x a0, -1
+    fcvt.wu.d a0, fa0, rdn    # nan
+    check_x a0, -1
+    fcvt.wu.d a0, fa0, rup    # nan
+    check_x a0, -1
+    fcvt.wu.d a0, fa0, rmm    # nan
+    check_x a0, -1
+    check_flags 16
+    li_d fa0, 2146435072, 0
+    fcvt.w.d a0, fa0, rne    # inf
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rtz    # inf
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rdn    # inf
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rup    # inf
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rmm    # inf
+    check_x a0, 2147483647
+    check_flags 16
+    fcvt.wu.d a0, fa0, rne    # inf
+    check_x a0, -1
+    fcvt.wu.d a0, fa0, rtz    # inf
+    check_x a0, -1
+    fcvt.wu.d a0, fa0, rdn    # inf
+    check_x a0, -1
+    fcvt.wu.d a0, fa0, rup    # inf
+    check_x a0, -1
+    fcvt.wu.d a0, fa0, rmm    # inf
+    check_x a0, -1
+    check_flags 16
+    li_d fa0, -1048576, 0
+    fcvt.w.d a0, fa0, rne    # -inf
+    check_x a0, -2147483648
+    fcvt.w.d a0, fa0, rtz    # -inf
+    check_x a0, -2147483648
+    fcvt.w.d a0, fa0, rdn    # -inf
+    check_x a0, -2147483648
+    fcvt.w.d a0, fa0, rup    # -inf
+    check_x a0, -2147483648
+    fcvt.w.d a0, fa0, rmm    # -inf
+    check_x a0, -2147483648
+    check_flags 16
+    fcvt.wu.d a0, fa0, rne    # -inf
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rtz    # -inf
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rdn    # -inf
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rup    # -inf
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rmm    # -inf
+    check_x a0, 0
+    check_flags 16
+    li_d fa0, 1105615371, -1073741824
+    fcvt.w.d a0, fa0, rne    # 3000000000.0
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rtz    # 3000000000.0
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rdn    # 3000000000.0
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rup    # 3000000000.0
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rmm    # 3000000000.0
+    check_x a0, 2147483647
+    check_flags 16
+    fcvt.wu.d a0, fa0, rne    # 3000000000.0
+    check_x a0, -1294967296
+    fcvt.wu.d a0, fa0, rtz    # 3000000000.0
+    check_x a0, -1294967296
+    fcvt.wu.d a0, fa0, rdn    # 3000000000.0
+    check_x a0, -1294967296
+    fcvt.wu.d a0, fa0, rup    # 3000000000.0
+    check_x a0, -1294967296
+    fcvt.wu.d a0, fa0, rmm    # 3000000000.0
+    check_x a0, -1294967296
+    check_flags 0
+    li_d fa0, -1041868277, -1073741824
+    fcvt.w.d a0, fa0, rne    # -3000000000.0
+    check_x a0, -2147483648
+    fcvt.w.d a0, fa0, rtz    # -3000000000.0
+    check_x a0, -2147483648
+    fcvt.w.d a0, fa0, rdn    # -3000000000.0
+    check_x a0, -2147483648
+    fcvt.w.d a0, fa0, rup    # -3000000000.0
+    check_x a0, -2147483648
+    fcvt.w.d a0, fa0, rmm    # -3000000000.0
+    check_x a0, -2147483648
+    check_flags 16
+    fcvt.wu.d a0, fa0, rne    # -3000000000.0
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rtz    # -3000000000.0
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rdn    # -3000000000.0
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rup    # -3000000000.0
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rmm    # -3000000000.0
+    check_x a0, 0
+    check_flags 16
+    li_d fa0, 1106247679, -1048576
+    fcvt.w.d a0, fa0, rne    # 4294967295.5
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rtz    # 4294967295.5
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rdn    # 4294967295.5
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rup    # 4294967295.5
+    check_x a0, 2147483647
+    fcvt.w.d a0, fa0, rmm    # 4294967295.5
+    check_x a0, 2147483647
+    check_flags 16
+    fcvt.wu.d a0, fa0, rne    # 4294967295.5
+    check_x a0, -1
+    fcvt.wu.d a0, fa0, rtz    # 4294967295.5
+    check_x a0, -1
+    fcvt.wu.d a0, fa0, rdn    # 4294967295.5
+    check_x a0, -1
+    fcvt.wu.d a0, fa0, rup    # 4294967295.5
+    check_x a0, -1
+    fcvt.wu.d a0, fa0, rmm    # 4294967295.5
+    check_x a0, -1
+    check_flags 17
+    li_d fa0, -1042284544, 1048576
+    fcvt.w.d a0, fa0, rne    # -2147483648.5
+    check_x a0, -2147483648
+    fcvt.w.d a0, fa0, rtz    # -2147483648.5
+    check_x a0, -2147483648
+    fcvt.w.d a0, fa0, rdn    # -2147483648.5
+    check_x a0, -2147483648
+    fcvt.w.d a0, fa0, rup    # -2147483648.5
+    check_x a0, -2147483648
+    fcvt.w.d a0, fa0, rmm    # -2147483648.5
+    check_x a0, -2147483648
+    check_flags 17
+    fcvt.wu.d a0, fa0, rne    # -2147483648.5
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rtz    # -2147483648.5
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rdn    # -2147483648.5
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rup    # -2147483648.5
+    check_x a0, 0
+    fcvt.wu.d a0, fa0, rmm    # -2147483648.5
+    check_x a0, 0
+    check_flags 16
+    li_d fa0, 1075576832, 0
+    fcvt.w.d a0, fa0, rne    # 7.0
+    check_x a0, 7
+    fcvt.w.d a0, fa0, rtz    # 7.0
+    check_x a0, 7
+    fcvt.w.d a0, fa0, rdn    # 7.0
+    check_x a0, 7
+    fcvt.w.d a0, fa0, rup    # 7.0
+    check_x a0, 7
+    fcvt.w.d a0, fa0, rmm    # 7.0
+    check_x a0, 7
+    check_flags 0
+    fcvt.wu.d a0, fa0, rne    # 7.0
+    check_x a0, 7
+    fcvt.wu.d a0, fa0, rtz    # 7.0
+    check_x a0, 7
+    fcvt.wu.d a0, fa0, rdn    # 7.0
+    check_x a0, 7
+    fcvt.wu.d a0, fa0, rup    # 7.0
+    check_x a0, 7
+    fcvt.wu.d a0, fa0, rmm    # 7.0
+    check_x a0, 7
+    check_flags 0
+
+# fcvt.s.w, fcvt.s.wu and fcvt.s.d in each rounding mode
+    li a0, 16777217
+    fcvt.s.w fa0, a0, rne    # 16777217
+    check_s fa0, 1266679808
+    fcvt.s.w fa0, a0, rtz    # 16777217
+    check_s fa0, 1266679808
+    fcvt.s.w fa0, a0, rdn    # 16777217
+    check_s fa0, 1266679808
+    fcvt.s.w fa0, a0, rup    # 16777217
+    check_s fa0, 1266679809
+    fcvt.s.w fa0, a0, rmm    # 16777217
+    check_s fa0, 1266679809
+    li a0, -16777217
+    fcvt.s.w fa0, a0, rne    # -16777217
+    check_s fa0, -880803840
+    fcvt.s.w fa0, a0, rtz    # -16777217
+    check_s fa0, -880803840
+    fcvt.s.w fa0, a0, rdn    # -16777217
+    check_s fa0, -880803839
+    fcvt.s.w fa0, a0, rup    # -16777217
+    check_s fa0, -880803840
+    fcvt.s.w fa0, a0, rmm    # -16777217
+    check_s fa0, -880803839
+    li a0, 2147483647
+    fcvt.s.w fa0, a0, rne    # 2147483647
+    check_s fa0, 1325400064
+    fcvt.s.w fa0, a0, rtz    # 2147483647
+    check_s fa0, 1325400063
+    fcvt.s.w fa0, a0, rdn    # 2147483647
+    check_s fa0, 1325400063
+    fcvt.s.w fa0, a0, rup    # 2147483647
+    check_s fa0, 1325400064
+    fcvt.s.w fa0, a0, rmm    # 2147483647
+    check_s fa0, 1325400064
+    li a0, -1
+    fcvt.s.wu fa0, a0, rne    # 4294967295
+    check_s fa0, 1333788672
+    fcvt.s.wu fa0, a0, rtz    # 4294967295
+    check_s fa0, 1333788671
+    fcvt.s.wu fa0, a0, rdn    # 4294967295
+    check_s fa0, 1333788671
+    fcvt.s.wu fa0, a0, rup    # 4294967295
+    check_s fa0, 1333788672
+    fcvt.s.wu fa0, a0, rmm    # 4294967295
+    check_s fa0, 1333788672
+    li a0, 16777219
+    fcvt.s.wu fa0, a0, rne    # 16777219
+    check_s fa0, 1266679810
+    fcvt.s.wu fa0, a0, rtz    # 16777219
+    check_s fa0, 1266679809
+    fcvt.s.wu fa0, a0, rdn    # 16777219
+    check_s fa0, 1266679809
+    fcvt.s.wu fa0, a0, rup    # 16777219
+    check_s fa0, 1266679810
+    fcvt.s.wu fa0, a0, rmm    # 16777219
+    check_s fa0, 1266679810
+    li_d fa1, 1070945621, 1431655765
+    fcvt.s.d fa0, fa1, rne    # 1/3
+    check_s fa0, 1051372203
+    fcvt.s.d fa0, fa1, rtz    # 1/3
+    check_s fa0, 1051372202
+    fcvt.s.d fa0, fa1, rdn    # 1/3
+    check_s fa0, 1051372202
+    fcvt.s.d fa0, fa1, rup    # 1/3
+    check_s fa0, 1051372203
+    fcvt.s.d fa0, fa1, rmm    # 1/3
+    check_s fa0, 1051372203
+    li_d fa1, -1076538027, 1431655765
+    fcvt.s.d fa0, fa1, rne    # -1/3
+    check_s fa0, -1096111445
+    fcvt.s.d fa0, fa1, rtz    # -1/3
+    check_s fa0, -1096111446
+    fcvt.s.d fa0, fa1, rdn    # -1/3
+    check_s fa0, -1096111445
+    fcvt.s.d fa0, fa1, rup    # -1/3
+    check_s fa0, -1096111446
+    fcvt.s.d fa0, fa1, rmm    # -1/3
+    check_s fa0, -1096111445
+    li_d fa1, 2146435071, -1
+    fcvt.s.d fa0, fa1, rne    # the largest double
+    check_s fa0, 2139095040
+    fcvt.s.d fa0, fa1, rtz    # the largest double
+    check_s fa0, 2139095039
+    fcvt.s.d fa0, fa1, rdn    # the largest double
+    check_s fa0, 2139095039
+    fcvt.s.d fa0, fa1, rup    # the largest double
+    check_s fa0, 2139095040
+    fcvt.s.d fa0, fa1, rmm    # the largest double
+    check_s fa0, 2139095040
+    li_d fa1, 915406848, 0
+    fcvt.s.d fa0, fa1, rne    # halfway to the smallest single
+    check_s fa0, 0
+    fcvt.s.d fa0, fa1, rtz    # halfway to the smallest single
+    check_s fa0, 0
+    fcvt.s.d fa0, fa1, rdn    # halfway to the smallest single
+    check_s fa0, 0
+    fcvt.s.d fa0, fa1, rup    # halfway to the smallest single
+    check_s fa0, 1
+    fcvt.s.d fa0, fa1, rmm    # halfway to the smallest single
+    check_s fa0, 1
+    li_d fa1, -1231552512, 0
+    fcvt.s.d fa0, fa1, rne    # -3/4 of the smallest single
+    check_s fa0, -2147483647
+    fcvt.s.d fa0, fa1, rtz    # -3/4 of the smallest single
+    check_s fa0, -2147483648
+    fcvt.s.d fa0, fa1, rdn    # -3/4 of the smallest single
+    check_s fa0, -2147483647
+    fcvt.s.d fa0, fa1, rup    # -3/4 of the smallest single
+    check_s fa0, -2147483648
+    fcvt.s.d fa0, fa1, rmm    # -3/4 of the smallest single
+    check_s fa0, -2147483647
+
+# arithmetic in each rounding mode
+    li_s fa1, 1065353216
+    li_s fa2, 1077936128
+    fdiv.s fa0, fa1, fa2, rne    # 1/3
+    check_s fa0, 1051372203
+    fdiv.s fa0, fa1, fa2, rtz    # 1/3
+    check_s fa0, 1051372202
+    fdiv.s fa0, fa1, fa2, rdn    # 1/3
+    check_s fa0, 1051372202
+    fdiv.s fa0, fa1, fa2, rup    # 1/3
+    check_s fa0, 1051372203
+    fdiv.s fa0, fa1, fa2, rmm    # 1/3
+    check_s fa0, 1051372203
+    li_s fa1, -1082130432
+    li_s fa2, 1077936128
+    fdiv.s fa0, fa1, fa2, rne    # -1/3
+    check_s fa0, -1096111445
+    fdiv.s fa0, fa1, fa2, rtz    # -1/3
+    check_s fa0, -1096111446
+    fdiv.s fa0, fa1, fa2, rdn    # -1/3
+    check_s fa0, -1096111445
+    fdiv.s fa0, fa1, fa2, rup    # -1/3
+    check_s fa0, -1096111446
+    fdiv.s fa0, fa1, fa2, rmm    # -1/3
+    check_s fa0, -1096111445
+    li_d fa1, 1072693248, 0
+    li_d fa2, 1074266112, 0
+    fdiv.d fa0, fa1, fa2, rne    # 1/3
+    check_d fa0, 1070945621, 1431655765
+    fdiv.d fa0, fa1, fa2, rtz    # 1/3
+    check_d fa0, 1070945621, 1431655765
+    fdiv.d fa0, fa1, fa2, rdn    # 1/3
+    check_d fa0, 1070945621, 1431655765
+    fdiv.d fa0, fa1, fa2, rup    # 1/3
+    check_d fa0, 1070945621, 1431655766
+    fdiv.d fa0, fa1, fa2, rmm    # 1/3
+    check_d fa0, 1070945621, 1431655765
+    li_d fa1, 0, 1
+    li_d fa2, 1073741824, 0
+    fdiv.d fa0, fa1, fa2, rne    # halfway to the smallest double
+    check_d fa0, 0, 0
+    fdiv.d fa0, fa1, fa2, rtz    # halfway to the smallest double
+    check_d fa0, 0, 0
+    fdiv.d fa0, fa1, fa2, rdn    # halfway to the smallest double
+    check_d fa0, 0, 0
+    fdiv.d fa0, fa1, fa2, rup    # halfway to the smallest double
+    check_d fa0, 0, 1
+    fdiv.d fa0, fa1, fa2, rmm    # halfway to the smallest double
+    check_d fa0, 0, 1
+    li_d fa1, 2117592124, -2013235812
+    li_d fa2, 1037794527, -640172613
+    fdiv.d fa0, fa1, fa2, rne    # overflowing
+    check_d fa0, 2146435072, 0
+    fdiv.d fa0, fa1, fa2, rtz    # overflowing
+    check_d fa0, 2146435071, -1
+    fdiv.d fa0, fa1, fa2, rdn    # overflowing
+    check_d fa0, 2146435071, -1
+    fdiv.d fa0, fa1, fa2, rup    # overflowing
+    check_d fa0, 2146435072, 0
+    fdiv.d fa0, fa1, fa2, rmm    # overflowing
+    check_d fa0, 2146435072, 0
+    li_d fa1, 1072693248, 0
+    li_d fa2, 1017118720, 0
+    fadd.d fa0, fa1, fa2, rne    # 1 + 2^-53, a tie
+    check_d fa0, 1072693248, 0
+    fadd.d fa0, fa1, fa2, rtz    # 1 + 2^-53, a tie
+    check_d fa0, 1072693248, 0
+    fadd.d fa0, fa1, fa2, rdn    # 1 + 2^-53, a tie
+    check_d fa0, 1072693248, 0
+    fadd.d fa0, fa1, fa2, rup    # 1 + 2^-53, a tie
+    check_d fa0, 1072693248, 1
+    fadd.d fa0, fa1, fa2, rmm    # 1 + 2^-53, a tie
+    check_d fa0, 1072693248, 1
+    li_d fa1, -1074790400, 0
+    li_d fa2, -1130364928, 0
+    fadd.d fa0, fa1, fa2, rne    # -1 - 2^-53, a tie
+    check_d fa0, -1074790400, 0
+    fadd.d fa0, fa1, fa2, rtz    # -1 - 2^-53, a tie
+    check_d fa0, -1074790400, 0
+    fadd.d fa0, fa1, fa2, rdn    # -1 - 2^-53, a tie
+    check_d fa0, -1074790400, 1
+    fadd.d fa0, fa1, fa2, rup    # -1 - 2^-53, a tie
+    check_d fa0, -1074790400, 0
+    fadd.d fa0, fa1, fa2, rmm    # -1 - 2^-53, a tie
+    check_d fa0, -1074790400, 1
+    li_s fa1, 1065353216
+    li_s fa2, 864026624
+    fadd.s fa0, fa1, fa2, rne    # 1 + 2^-24, a tie
+    check_s fa0, 1065353216
+    fadd.s fa0, fa1, fa2, rtz    # 1 + 2^-24, a tie
+    check_s fa0, 1065353216
+    fadd.s fa0, fa1, fa2, rdn    # 1 + 2^-24, a tie
+    check_s fa0, 1065353216
+    fadd.s fa0, fa1, fa2, rup    # 1 + 2^-24, a tie
+    check_s fa0, 1065353217
+    fadd.s fa0, fa1, fa2, rmm    # 1 + 2^-24, a tie
+    check_s fa0, 1065353217
+    li_s fa1, 1065353216
+    li_s fa2, 859832320
+    fadd.s fa0, fa1, fa2, rne    # 1 + 3 * 2^-26
+    check_s fa0, 1065353216
+    fadd.s fa0, fa1, fa2, rtz    # 1 + 3 * 2^-26
+    check_s fa0, 1065353216
+    fadd.s fa0, fa1, fa2, rdn    # 1 + 3 * 2^-26
+    check_s fa0, 1065353216
+    fadd.s fa0, fa1, fa2, rup    # 1 + 3 * 2^-26
+    check_s fa0, 1065353217
+    fadd.s fa0, fa1, fa2, rmm    # 1 + 3 * 2^-26
+    check_s fa0, 1065353216
+    li_d fa1, 1072693248, 0
+    li_d fa2, 1072693248, 0
+    fsub.d fa0, fa1, fa2, rne    # 1 - 1, -0 rounding down
+    check_d fa0, 0, 0
+    fsub.d fa0, fa1, fa2, rtz    # 1 - 1, -0 rounding down
+    check_d fa0, 0, 0
+    fsub.d fa0, fa1, fa2, rdn    # 1 - 1, -0 rounding down
+    check_d fa0, -2147483648, 0
+    fsub.d fa0, fa1, fa2, rup    # 1 - 1, -0 rounding down
+    check_d fa0, 0, 0
+    fsub.d fa0, fa1, fa2, rmm    # 1 - 1, -0 rounding down
+    check_d fa0, 0, 0
+    li_s fa1, 1065353216
+    li_s fa2, 1065353216
+    fsub.s fa0, fa1, fa2, rne    # 1 - 1, -0 rounding down
+    check_s fa0, 0
+    fsub.s fa0, fa1, fa2, rtz    # 1 - 1, -0 rounding down
+    check_s fa0, 0
+    fsub.s fa0, fa1, fa2, rdn    # 1 - 1, -0 rounding down
+    check_s fa0, -2147483648
+    fsub.s fa0, fa1, fa2, rup    # 1 - 1, -0 rounding down
+    check_s fa0, 0
+    fsub.s fa0, fa1, fa2, rmm    # 1 - 1, -0 rounding down
+    check_s fa0, 0
+    li_s fa1, 1065353217
+    li_s fa2, 1065353217
+    fmul.s fa0, fa1, fa2, rne    # (1 + 2^-23)^2
+    check_s fa0, 1065353218
+    fmul.s fa0, fa1, fa2, rtz    # (1 + 2^-23)^2
+    check_s fa0, 1065353218
+    fmul.s fa0, fa1, fa2, rdn    # (1 + 2^-23)^2
+    check_s fa0, 1065353218
+    fmul.s fa0, fa1, fa2, rup    # (1 + 2^-23)^2
+    check_s fa0, 1065353219
+    fmul.s fa0, fa1, fa2, rmm    # (1 + 2^-23)^2
+    check_s fa0, 1065353218
+    li_d fa1, 1072693248, 1
+    li_d fa2, 1072693247, -1
+    fmul.d fa0, fa1, fa2, rne    # (1 + 2^-52)(1 - 2^-53)
+    check_d fa0, 1072693248, 0
+    fmul.d fa0, fa1, fa2, rtz    # (1 + 2^-52)(1 - 2^-53)
+    check_d fa0, 1072693248, 0
+    fmul.d fa0, fa1, fa2, rdn    # (1 + 2^-52)(1 - 2^-53)
+    check_d fa0, 1072693248, 0
+    fmul.d fa0, fa1, fa2, rup    # (1 + 2^-52)(1 - 2^-53)
+    check_d fa0, 1072693248, 1
+    fmul.d fa0, fa1, fa2, rmm    # (1 + 2^-52)(1 - 2^-53)
+    check_d fa0, 1072693248, 0
+    li_d fa1, 2146435071, -1
+    li_d fa2, 1073741824, 0
+    fmul.d fa0, fa1, fa2, rne    # overflowing
+    check_d fa0, 2146435072, 0
+    fmul.d fa0, fa1, fa2, rtz    # overflowing
+    check_d fa0, 2146435071, -1
+    fmul.d fa0, fa1, fa2, rdn    # overflowing
+    check_d fa0, 2146435071, -1
+    fmul.d fa0, fa1, fa2, rup    # overflowing
+    check_d fa0, 2146435072, 0
+    fmul.d fa0, fa1, fa2, rmm    # overflowing
+    check_d fa0, 2146435072, 0
+    li_d fa1, -1048577, -1
+    li_d fa2, 1073741824, 0
+    fmul.d fa0, fa1, fa2, rne    # overflowing negative
+    check_d fa0, -1048576, 0
+    fmul.d fa0, fa1, fa2, rtz    # overflowing negative
+    check_d fa0, -1048577, -1
+    fmul.d fa0, fa1, fa2, rdn    # overflowing negative
+    check_d fa0, -1048576, 0
+    fmul.d fa0, fa1, fa2, rup    # overflowing negative
+    check_d fa0, -1048577, -1
+    fmul.d fa0, fa1, fa2, rmm    # overflowing negative
+    check_d fa0, -1048576, 0
+    li_s fa1, 226492416
+    li_s fa2, -1333788672
+    fmul.s fa0, fa1, fa2, rne    # underflowing negative
+    check_s fa0, -2146959360
+    fmul.s fa0, fa1, fa2, rtz    # underflowing negative
+    check_s fa0, -2146959360
+    fmul.s fa0, fa1, fa2, rdn    # underflowing negative
+    check_s fa0, -2146959360
+    fmul.s fa0, fa1, fa2, rup    # underflowing negative
+    check_s fa0, -2146959360
+    fmul.s fa0, fa1, fa2, rmm    # underflowing negative
+    check_s fa0, -2146959360
+    li_s fa1, 1073741824
+    fsqrt.s fa0, fa1, rne    # sqrt 2.0
+    check_s fa0, 1068827891
+    fsqrt.s fa0, fa1, rtz    # sqrt 2.0
+    check_s fa0, 1068827891
+    fsqrt.s fa0, fa1, rdn    # sqrt 2.0
+    check_s fa0, 1068827891
+    fsqrt.s fa0, fa1, rup    # sqrt 2.0
+    check_s fa0, 1068827892
+    fsqrt.s fa0, fa1, rmm    # sqrt 2.0
+    check_s fa0, 1068827891
+    li_d fa1, 1073741824, 0
+    fsqrt.d fa0, fa1, rne    # sqrt 2.0
+    check_d fa0, 1073127582, 1719614413
+    fsqrt.d fa0, fa1, rtz    # sqrt 2.0
+    check_d fa0, 1073127582, 1719614412
+    fsqrt.d fa0, fa1, rdn    # sqrt 2.0
+    check_d fa0, 1073127582, 1719614412
+    fsqrt.d fa0, fa1, rup    # sqrt 2.0
+    check_d fa0, 1073127582, 1719614413
+    fsqrt.d fa0, fa1, rmm    # sqrt 2.0
+    check_d fa0, 1073127582, 1719614413
+    li_d fa1, 0, 2
+    fsqrt.d fa0, fa1, rne    # sqrt 1e-323
+    check_d fa0, 510042270, 1719614413
+    fsqrt.d fa0, fa1, rtz    # sqrt 1e-323
+    check_d fa0, 510042270, 1719614412
+    fsqrt.d fa0, fa1, rdn    # sqrt 1e-323
+    check_d fa0, 510042270, 1719614412
+    fsqrt.d fa0, fa1, rup    # sqrt 1e-323
+    check_d fa0, 510042270, 1719614413
+    fsqrt.d fa0, fa1, rmm    # sqrt 1e-323
+    check_d fa0, 510042270, 1719614413
+    li_s fa1, 2137108966
+    fsqrt.s fa0, fa1, rne    # sqrt 3e+38
+    check_s fa0, 1601199822
+    fsqrt.s fa0, fa1, rtz    # sqrt 3e+38
+    check_s fa0, 1601199822
+    fsqrt.s fa0, fa1, rdn    # sqrt 3e+38
+    check_s fa0, 1601199822
+    fsqrt.s fa0, fa1, rup    # sqrt 3e+38
+    check_s fa0, 1601199823
+    fsqrt.s fa0, fa1, rmm    # sqrt 3e+38
+    check_s fa0, 1601199822
+    li_d fa1, 1072693248, 1
+    li_d fa2, 1072693248, 1
+    li_d fa3, -1074790400, 0
+    fmadd.d fa0, fa1, fa2, fa3, rne    # (1 + 2^-52)^2 - 1
+    check_d fa0, 1019215872, 0
+    fmadd.d fa0, fa1, fa2, fa3, rtz    # (1 + 2^-52)^2 - 1
+    check_d fa0, 1019215872, 0
+    fmadd.d fa0, fa1, fa2, fa3, rdn    # (1 + 2^-52)^2 - 1
+    check_d fa0, 1019215872, 0
+    fmadd.d fa0, fa1, fa2, fa3, rup    # (1 + 2^-52)^2 - 1
+    check_d fa0, 1019215872, 1
+    fmadd.d fa0, fa1, fa2, fa3, rmm    # (1 + 2^-52)^2 - 1
+    check_d fa0, 1019215872, 1
+    li_s fa1, 1065353217
+    li_s fa2, 1065353215
+    li_s fa3, 1065353216
+    fmsub.s fa0, fa1, fa2, fa3, rne    # (1 + 2^-23)(1 - 2^-24) - 1
+    check_s fa0, 864026622
+    fmsub.s fa0, fa1, fa2, fa3, rtz    # (1 + 2^-23)(1 - 2^-24) - 1
+    check_s fa0, 864026622
+    fmsub.s fa0, fa1, fa2, fa3, rdn    # (1 + 2^-23)(1 - 2^-24) - 1
+    check_s fa0, 864026622
+    fmsub.s fa0, fa1, fa2, fa3, rup    # (1 + 2^-23)(1 - 2^-24) - 1
+    check_s fa0, 864026622
+    fmsub.s fa0, fa1, fa2, fa3, rmm    # (1 + 2^-23)(1 - 2^-24) - 1
+    check_s fa0, 864026622
+    li_d fa1, 1070945621, 1431655765
+    li_d fa2, 1074266112, 0
+    li_d fa3, 1072693248, 0
+    fnmsub.d fa0, fa1, fa2, fa3, rne    # 1 - 3 * (1/3)
+    check_d fa0, 1016070144, 0
+    fnmsub.d fa0, fa1, fa2, fa3, rtz    # 1 - 3 * (1/3)
+    check_d fa0, 1016070144, 0
+    fnmsub.d fa0, fa1, fa2, fa3, rdn    # 1 - 3 * (1/3)
+    check_d fa0, 1016070144, 0
+    fnmsub.d fa0, fa1, fa2, fa3, rup    # 1 - 3 * (1/3)
+    check_d fa0, 1016070144, 0
+    fnmsub.d fa0, fa1, fa2, fa3, rmm    # 1 - 3 * (1/3)
+    check_d fa0, 1016070144, 0
+    li_d fa1, 1072693248, 0
+    li_d fa2, 1072693248, 0
+    li_d fa3, -1074790400, 0
+    fnmadd.d fa0, fa1, fa2, fa3, rne    # -(1 * 1) + 1, -0 rounding down
+    check_d fa0, 0, 0
+    fnmadd.d fa0, fa1, fa2, fa3, rtz    # -(1 * 1) + 1, -0 rounding down
+    check_d fa0, 0, 0
+    fnmadd.d fa0, fa1, fa2, fa3, rdn    # -(1 * 1) + 1, -0 rounding down
+    check_d fa0, -2147483648, 0
+    fnmadd.d fa0, fa1, fa2, fa3, rup    # -(1 * 1) + 1, -0 rounding down
+    check_d fa0, 0, 0
+    fnmadd.d fa0, fa1, fa2, fa3, rmm    # -(1 * 1) + 1, -0 rounding down
+    check_d fa0, 0, 0
+    li_s fa1, 1051372203
+    li_s fa2, 1077936128
+    li_s fa3, 813694976
+    fnmadd.s fa0, fa1, fa2, fa3, rne    # -(1/3 * 3) - 2^-30
+    check_s fa0, -1082130432
+    fnmadd.s fa0, fa1, fa2, fa3, rtz    # -(1/3 * 3) - 2^-30
+    check_s fa0, -1082130432
+    fnmadd.s fa0, fa1, fa2, fa3, rdn    # -(1/3 * 3) - 2^-30
+    check_s fa0, -1082130431
+    fnmadd.s fa0, fa1, fa2, fa3, rup    # -(1/3 * 3) - 2^-30
+    check_s fa0, -1082130432
+    fnmadd.s fa0, fa1, fa2, fa3, rmm    # -(1/3 * 3) - 2^-30
+    check_s fa0, -1082130432
+
+# the dynamic rounding mode follows frm
+    li_s fa1, 1065353216
+    li_s fa2, 1077936128
+    fsrmi 0
+    fdiv.s fa0, fa1, fa2    # 1/3, rne
+    check_s fa0, 1051372203
+    fcvt.w.s a0, fa2
+    check_x a0, 3
+    fsrmi 1
+    fdiv.s fa0, fa1, fa2    # 1/3, rtz
+    check_s fa0, 1051372202
+    fcvt.w.s a0, fa2
+    check_x a0, 3
+    fsrmi 2
+    fdiv.s fa0, fa1, fa2    # 1/3, rdn
+    check_s fa0, 1051372202
+    fcvt.w.s a0, fa2
+    check_x a0, 3
+    fsrmi 3
+    fdiv.s fa0, fa1, fa2    # 1/3, rup
+    check_s fa0, 1051372203
+    fcvt.w.s a0, fa2
+    check_x a0, 3
+    fsrmi 4
+    fdiv.s fa0, fa1, fa2    # 1/3, rmm
+    check_s fa0, 1051372203
+    fcvt.w.s a0, fa2
+    check_x a0, 3
+    fsrmi 0
+    csrw fflags, zero
+
+# a single that isn't NaN-boxed reads as the canonical NaN, though moves and
+# stores take its bits as they are
+    li_d ft0, 1072693248, 305419896
+    fadd.s fa0, ft0, ft0
+    check_s fa0, 2143289344
+    fsgnjn.s fa0, ft0, ft0
+    check_s fa0, -4194304
+    fcvt.d.s fa0, ft0
+    check_d fa0, 2146959360, 0
+    fclass.s a0, ft0
+    check_x a0, 512
+    feq.s a0, ft0, ft0
+    check_x a0, 0
+    fmv.x.w a0, ft0
+    check_x a0, 305419896
+    fsw ft0, 8(s1)
+    lw a0, 8(s1)
+    check_x a0, 305419896
+    flw fa0, 8(s1)
+    check_s fa0, 305419896
+    fmv.w.x fa0, a0
+    check_s fa0, 305419896
+    fcvt.s.w fa0, a0
+    check_s fa0, 1301390004
+    csrw fflags, zero
+
+# NaN results are canonical, whatever the NaNs that went in
+    li_d fa1, 2146435072, 0
+    li_d fa2, -1048576, 0
+    fadd.d fa0, fa1, fa2    # inf - inf
+    check_d fa0, 2146959360, 0
+    li_s fa1, 2139095040
+    li_s fa2, 2139095040
+    fsub.s fa0, fa1, fa2    # inf - inf
+    check_s fa0, 2143289344
+    li_d fa1, 0, 0
+    li_d fa2, 0, 0
+    fdiv.d fa0, fa1, fa2    # 0/0
+    check_d fa0, 2146959360, 0
+    li_d fa1, -1074790400, 0
+    fsqrt.d fa0, fa1    # sqrt -1
+    check_d fa0, 2146959360, 0
+    li_s fa1, -1082130432
+    fsqrt.s fa0, fa1    # sqrt -1
+    check_s fa0, 2143289344
+    li_s fa1, 0
+    li_s fa2, 2139095040
+    fmul.s fa0, fa1, fa2    # 0 * inf
+    check_s fa0, 2143289344
+    li_d fa1, 0, 0
+    li_d fa2, -1048576, 0
+    li_d fa3, 1072693248, 0
+    fmadd.d fa0, fa1, fa2, fa3    # 0 * -inf + 1
+    check_d fa0, 2146959360, 0
+    li_s fa1, 2139095041
+    li_s fa2, 1065353216
+    fadd.s fa0, fa1, fa2    # a signalling NaN + 1
+    check_s fa0, 2143289344
+    li_d fa1, -1048576, 291
+    li_d fa2, 1072693248, 0
+    fmul.d fa0, fa1, fa2    # a negative NaN with a payload
+    check_d fa0, 2146959360, 0
+    li_s fa1, -4194303
+    li_s fa2, 2139095041
+    fmin.s fa0, fa1, fa2    # two NaNs
+    check_s fa0, 2143289344
+    li_d fa1, -524288, 1
+    li_d fa2, 2146435072, 1
+    fmax.d fa0, fa1, fa2    # two NaNs
+    check_d fa0, 2146959360, 0
+    li_d fa1, -1048576, 1
+    fcvt.s.d fa0, fa1    # a double NaN with a payload
+    check_s fa0, 2143289344
+    li_s fa1, -8388607
+    fcvt.d.s fa0, fa1    # a single one
+    check_d fa0, 2146959360, 0
+    csrw fflags, zero
+
+# fmin and fmax order -0 below +0, and ignore a single NaN
+    li_s fa1, 0
+    li_s fa2, -2147483648
+    fmin.s fa0, fa1, fa2
+    check_s fa0, -2147483648
+    li_s fa1, -2147483648
+    li_s fa2, 0
+    fmax.s fa0, fa1, fa2
+    check_s fa0, 0
+    li_d fa1, 0, 0
+    li_d fa2, -2147483648, 0
+    fmin.d fa0, fa1, fa2
+    check_d fa0, -2147483648, 0
+    li_d fa1, -2147483648, 0
+    li_d fa2, 0, 0
+    fmax.d fa0, fa1, fa2
+    check_d fa0, 0, 0
+    li_s fa1, 2143289344
+    li_s fa2, 1065353216
+    fmin.s fa0, fa1, fa2
+    check_s fa0, 1065353216
+    li_d fa1, 1072693248, 0
+    li_d fa2, 2146697216, 0
+    fmax.d fa0, fa1, fa2
+    check_d fa0, 1072693248, 0
+
+    li a0, 0
+    j exit
+fail:
+    mv a0, s0
+exit:
+    li a7, 93
+    ecall
+
+    .bss
+    .balign 8
+scratch:
+    .zero 16
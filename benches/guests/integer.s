@@ -0,0 +1,32 @@
+# Integer ALU work with a data-dependent branch, 2M passes
+    .globl _start
+    .text
+_start:
+    li s0, 2000000
+    li a0, 0
+    li t1, 12345
+    la s1, scratch
+loop:
+    add a0, a0, t1
+    xor t1, t1, a0
+    slli t2, t1, 3
+    srli t3, a0, 5
+    sub a0, a0, t3
+    mul t2, t2, t3
+    add t1, t1, t2
+    sw a0, 0(s1)
+    lw t4, 0(s1)
+    add t1, t1, t4
+    andi t5, a0, 1
+    beqz t5, skip
+    addi a0, a0, 7
+skip:
+    addi s0, s0, -1
+    bnez s0, loop
+    andi a0, a0, 0x7f
+    li a7, 93
+    ecall
+
+    .bss
+scratch:
+    .word 0
@@ -0,0 +1,65 @@
+# Copies 64 KiB a word at a time, unrolled four times, then the same 64 KiB a
+# byte at a time, 100 passes. `copy` isn't called memcpy, so riscy runs it
+# rather than copying for the guest
+    .globl _start
+    .text
+_start:
+    la t0, src
+    li t1, 16384
+fill:
+    sw t1, 0(t0)
+    addi t0, t0, 4
+    addi t1, t1, -1
+    bnez t1, fill
+
+    li s0, 100
+pass:
+    la a0, dst
+    la a1, src
+    li a2, 65536
+    call copy_words
+    la a0, dst
+    la a1, src
+    li a2, 65536
+    call copy_bytes
+    addi s0, s0, -1
+    bnez s0, pass
+
+    la t0, dst
+    lw a0, 0(t0)
+    andi a0, a0, 0x7f
+    li a7, 93
+    ecall
+
+copy_words:
+    add a2, a0, a2
+1:
+    lw t0, 0(a1)
+    lw t1, 4(a1)
+    lw t2, 8(a1)
+    lw t3, 12(a1)
+    sw t0, 0(a0)
+    sw t1, 4(a0)
+    sw t2, 8(a0)
+    sw t3, 12(a0)
+    addi a0, a0, 16
+    addi a1, a1, 16
+    bltu a0, a2, 1b
+    ret
+
+copy_bytes:
+    add a2, a0, a2
+1:
+    lbu t0, 0(a1)
+    sb t0, 0(a0)
+    addi a0, a0, 1
+    addi a1, a1, 1
+    bltu a0, a2, 1b
+    ret
+
+    .bss
+    .balign 4
+src:
+    .zero 65536
+dst:
+    .zero 65536
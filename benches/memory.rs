@@ -0,0 +1,79 @@
+//! The guest load and store hot path, for each `MemReader`, against a guest
+//! copying 64 KiB a word, a halfword and a byte at a time. Run with
+//! `cargo bench --bench memory`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use risc_y::{
+    core::{AdaptiveMemReader, AlignedMemReader, Core32, MemReader, UnalignedMemReader},
+    elfnote::ElfConfig,
+    load::{ElfTarget, LoadedElf, Segment},
+};
+
+const MEMORY_SIZE: usize = 16 << 20;
+const TEXT: u64 = 0x10000;
+
+// 64 passes reading [0x100000, 0x110000) and writing [0x200000, 0x210000)
+const PROGRAM: [u32; 17] = [
+    0x04000413, // li s0, 64
+    0x001002b7, // pass: lui t0, 0x100
+    0x00110337, // lui t1, 0x110
+    0x002003b7, // lui t2, 0x200
+    0x0002ae03, // loop: lw t3, 0(t0)
+    0x0012ce83, // lbu t4, 1(t0)
+    0x01de0e33, // add t3, t3, t4
+    0x01c3a023, // sw t3, 0(t2)
+    0x01c39323, // sh t3, 6(t2)
+    0x00828293, // addi t0, t0, 8
+    0x00838393, // addi t2, t2, 8
+    0xfe62e2e3, // bltu t0, t1, loop
+    0xfff40413, // addi s0, s0, -1
+    0xfc0418e3, // bnez s0, pass
+    0x00000513, // li a0, 0
+    0x05d00893, // li a7, 93
+    0x00000073, // ecall
+];
+
+fn elf() -> LoadedElf {
+    let data: Vec<u8> = PROGRAM.iter().flat_map(|word| word.to_le_bytes()).collect();
+    LoadedElf {
+        base: TEXT,
+        entrypoint: TEXT,
+        segments: vec![Segment {
+            offset: 0,
+            vaddr: TEXT,
+            size: data.len() as u64,
+            data,
+            executable: true,
+            file: None,
+        }],
+        symbols: Vec::new(),
+        wk_memmove: 0,
+        wk_memcpy: 0,
+        wk_memset: 0,
+        wk_cos: 0,
+        wk_sin: 0,
+        fatal_fns: Vec::new(),
+        build_id: None,
+        target: ElfTarget::default(),
+        config: ElfConfig::default(),
+    }
+}
+
+fn run<Reader: MemReader<Idx = u32>>() {
+    let mut core = Core32::<Reader>::new(elf(), None, MEMORY_SIZE, false);
+    core.run();
+}
+
+fn memory(c: &mut Criterion) {
+    let mut group = c.benchmark_group("memory");
+    group.sample_size(10);
+
+    group.bench_function("aligned", |b| b.iter(run::<AlignedMemReader<u32>>));
+    group.bench_function("unaligned", |b| b.iter(run::<UnalignedMemReader<u32>>));
+    group.bench_function("adaptive", |b| b.iter(run::<AdaptiveMemReader<u32>>));
+
+    group.finish();
+}
+
+criterion_group!(benches, memory);
+criterion_main!(benches);
@@ -0,0 +1,248 @@
+//! Generates the instruction decode tables from the riscv-opcodes style data in
+//! `opcodes/`, see `src/opcodes.rs`.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Operand fields, and the bits they occupy
+const FIELDS: &[(&str, &str, u32, u32)] = &[
+    ("rd", "Rd", 11, 7),
+    ("rs1", "Rs1", 19, 15),
+    ("rs2", "Rs2", 24, 20),
+    ("rs3", "Rs3", 31, 27),
+    ("rm", "Rm", 14, 12),
+    ("imm12", "Imm12", 31, 20),
+    ("imm20", "Imm20", 31, 12),
+    ("jimm20", "Jimm20", 31, 12),
+    ("imm12hi", "Imm12Hi", 31, 25),
+    ("imm12lo", "Imm12Lo", 11, 7),
+    ("bimm12hi", "Bimm12Hi", 31, 25),
+    ("bimm12lo", "Bimm12Lo", 11, 7),
+    ("shamtw", "Shamtw", 24, 20),
+    ("shamtd", "Shamtd", 25, 20),
+    ("fm", "Fm", 31, 28),
+    ("pred", "Pred", 27, 24),
+    ("succ", "Succ", 23, 20),
+    ("csr", "Csr", 31, 20),
+    ("zimm", "Zimm", 19, 15),
+    ("aq", "Aq", 26, 26),
+    ("rl", "Rl", 25, 25),
+];
+
+struct Entry {
+    name: String,
+    extension: String,
+    // `rv32_*`/`rv64_*` files only apply to that xlen
+    xlen: Option<u32>,
+    mask: u32,
+    match_: u32,
+    fields: Vec<&'static str>,
+}
+
+fn bits(hi: u32, lo: u32) -> u32 {
+    (((1u64 << (hi + 1)) - 1) as u32) & !((1u32 << lo) - 1)
+}
+
+fn parse_num(s: &str) -> u32 {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .unwrap_or_else(|_| panic!("bad number '{s}'"))
+}
+
+fn parse_line(extension: &str, line: &str) -> Entry {
+    let mut tokens = line.split_whitespace();
+    let mut name = tokens.next().unwrap();
+    if name == "$pseudo_op" {
+        // `$pseudo_op ext::base name ...`, a more specific encoding of `base`
+        tokens.next().unwrap();
+        name = tokens.next().unwrap();
+    }
+
+    let mut mask = 0;
+    let mut match_ = 0;
+    let mut fields = Vec::new();
+
+    for token in tokens {
+        if let Some((range, val)) = token.split_once('=') {
+            let (hi, lo) = match range.split_once("..") {
+                Some((hi, lo)) => (parse_num(hi), parse_num(lo)),
+                None => (parse_num(range), parse_num(range)),
+            };
+            let val = parse_num(val);
+            let field = bits(hi, lo);
+
+            assert!(mask & field == 0, "{name}: overlapping bits {range}");
+            assert!(
+                val <= field >> lo,
+                "{name}: {val:#x} doesn't fit in {range}"
+            );
+
+            mask |= field;
+            match_ |= val << lo;
+        } else {
+            let &(field, ..) = FIELDS
+                .iter()
+                .find(|(field, ..)| *field == token)
+                .unwrap_or_else(|| panic!("{name}: unknown field '{token}'"));
+            fields.push(field);
+        }
+    }
+
+    // every bit is either fixed or part of an operand, exactly once
+    let mut covered = mask;
+    for field in &fields {
+        let &(_, _, hi, lo) = FIELDS.iter().find(|(f, ..)| f == field).unwrap();
+        assert!(covered & bits(hi, lo) == 0, "{name}: {field} overlaps");
+        covered |= bits(hi, lo);
+    }
+    assert_eq!(covered, u32::MAX, "{name}: not every bit is specified");
+
+    let xlen = if extension.starts_with("rv32_") {
+        Some(32)
+    } else if extension.starts_with("rv64_") {
+        Some(64)
+    } else {
+        None
+    };
+
+    Entry {
+        name: name.to_string(),
+        extension: extension.to_string(),
+        xlen,
+        mask,
+        match_,
+        fields,
+    }
+}
+
+/// `fcvt.wu.s` => `FcvtWuS`
+fn variant_name(name: &str) -> String {
+    name.split('.')
+        .map(|part| {
+            let mut chars = part.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            first.to_string() + chars.as_str()
+        })
+        .collect()
+}
+
+fn main() {
+    let dir = Path::new("opcodes");
+    println!("cargo:rerun-if-changed=opcodes");
+
+    let mut files = fs::read_dir(dir)
+        .expect("opcodes directory missing")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            let name = path.file_name().unwrap().to_str().unwrap();
+            name.starts_with("rv")
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+
+    let mut entries = Vec::new();
+    for path in &files {
+        let extension = path.file_name().unwrap().to_str().unwrap();
+        for line in fs::read_to_string(path).unwrap().lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if !line.is_empty() {
+                entries.push(parse_line(extension, line));
+            }
+        }
+    }
+
+    // the same instruction can have different encodings for different xlens
+    // (e.g. the shamt width), but only one for each
+    for (idx, a) in entries.iter().enumerate() {
+        for b in &entries[idx + 1..] {
+            let disjoint = matches!((a.xlen, b.xlen), (Some(x), Some(y)) if x != y);
+            assert!(a.name != b.name || disjoint, "{} defined twice", a.name);
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(out, "// @generated by build.rs from opcodes/").unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(out, "#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]").unwrap();
+    writeln!(out, "pub enum Opcode {{").unwrap();
+    let mut variants = Vec::new();
+    for entry in &entries {
+        let variant = variant_name(&entry.name);
+        if !variants.contains(&variant) {
+            writeln!(out, "    {variant},").unwrap();
+            variants.push(variant);
+        }
+    }
+    writeln!(out, "}}").unwrap();
+    writeln!(out).unwrap();
+
+    writeln!(
+        out,
+        "pub static OPCODES: [OpcodeInfo; {}] = [",
+        entries.len()
+    )
+    .unwrap();
+    for entry in &entries {
+        let fields = entry
+            .fields
+            .iter()
+            .map(|field| {
+                let &(_, variant, ..) = FIELDS.iter().find(|(f, ..)| f == field).unwrap();
+                format!("Field::{variant}")
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(
+            out,
+            "    OpcodeInfo {{ opcode: Opcode::{}, name: {:?}, extension: {:?}, xlen: {:?}, mask: {:#010x}, match_: {:#010x}, fields: &[{}] }},",
+            variant_name(&entry.name),
+            entry.name,
+            entry.extension,
+            entry.xlen,
+            entry.mask,
+            entry.match_,
+            fields
+        )
+        .unwrap();
+    }
+    writeln!(out, "];").unwrap();
+    writeln!(out).unwrap();
+
+    // candidates for each major opcode (the low 7 bits, always fixed), with the
+    // most specific encodings first so pseudo-ops win over their base
+    let mut by_major = BTreeMap::<u32, Vec<usize>>::new();
+    for (idx, entry) in entries.iter().enumerate() {
+        assert!(
+            entry.mask & 0x7f == 0x7f,
+            "{}: major opcode not fixed",
+            entry.name
+        );
+        by_major.entry(entry.match_ & 0x7f).or_default().push(idx);
+    }
+
+    writeln!(out, "fn candidates(major: u32) -> &'static [u16] {{").unwrap();
+    writeln!(out, "    match major {{").unwrap();
+    for (major, mut idxs) in by_major {
+        idxs.sort_by_key(|&idx| std::cmp::Reverse(entries[idx].mask.count_ones()));
+        let idxs = idxs
+            .iter()
+            .map(|idx| idx.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(out, "        {major:#04x} => &[{idxs}],").unwrap();
+    }
+    writeln!(out, "        _ => &[],").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}").unwrap();
+
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("opcodes.rs");
+    fs::write(out_path, out).unwrap();
+}
@@ -0,0 +1,33 @@
+# Startup code for riscy's magic console (`riscy --console`): calls `main` and
+# exits with what it returns. Memory starts zeroed, so .bss needs no clearing.
+# The console is asked for in a note, as `RISCY_CONFIG` in
+# include/riscy_config.h writes it, so `--console` can be left off.
+
+  .equ CONSOLE, 0x10000000
+
+  .section .text.init
+  .globl _start
+_start:
+  call main
+  li t0, CONSOLE
+  sw a0, 0(t0)
+1:
+  j 1b
+
+  .section .note.riscy, "a", @note
+  .balign 4
+  .4byte 6, 2f - 1f, 0x5259
+  .asciz "riscy"
+  .balign 4
+1:
+  .asciz "device=console@0x10000000"
+2:
+  .balign 4
+
+# void putchar(char c)
+  .text
+  .globl putchar
+putchar:
+  li t0, CONSOLE
+  sb a0, 0(t0)
+  ret
@@ -0,0 +1,25 @@
+# Prints a greeting through `putchar` from crt0.s and returns 0
+
+  .text
+  .globl main
+main:
+  addi sp, sp, -16
+  sw ra, 12(sp)
+  sw s0, 8(sp)
+  la s0, message
+1:
+  lbu a0, 0(s0)
+  beqz a0, 2f
+  call putchar
+  addi s0, s0, 1
+  j 1b
+2:
+  li a0, 0
+  lw s0, 8(sp)
+  lw ra, 12(sp)
+  addi sp, sp, 16
+  ret
+
+  .section .rodata
+message:
+  .asciz "hello from bare metal\n"
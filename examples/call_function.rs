@@ -0,0 +1,50 @@
+//! Calls a single function in a guest program, rather than running it from
+//! its entry point.
+//!
+//! ```text
+//! cargo run --example call_function -- program.elf add 2 3
+//! ```
+
+use std::{env, error::Error};
+
+use risc_y::{
+    call::ArgValue,
+    core::{Core32, UnalignedMemReader},
+    load::LoadedElf,
+};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let usage = "usage: call_function PROGRAM FUNCTION [ARGS...]";
+    let path = args.next().ok_or(usage)?;
+    let function = args.next().ok_or(usage)?;
+    // integers, or floats like `1.5` (a double) and `1.5f` (a single)
+    let call_args = args
+        .map(|arg| arg.parse::<ArgValue>())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let ret = call(&path, &function, &call_args)?;
+    println!("{function} returned {ret}");
+    Ok(())
+}
+
+fn call(path: &str, function: &str, args: &[ArgValue]) -> Result<i32, Box<dyn Error>> {
+    let elf = LoadedElf::load(path)?;
+    let mut core = Core32::<UnalignedMemReader<u32>>::new(elf, None, 16 << 20, false);
+    let ret = core.call_function(function, args)?;
+
+    // an fp result would be in `ret.double()` or `ret.single()`
+    Ok(ret.int())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_add() {
+        let guest = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/guest/guest.elf");
+        let args = [ArgValue::Int(2), ArgValue::Int(3)];
+        assert_eq!(call(guest, "add", &args).unwrap(), 5);
+    }
+}
@@ -0,0 +1,73 @@
+//! Collects which instructions the guest ran with a hook, and reports the
+//! coverage of each function.
+//!
+//! ```text
+//! cargo run --example coverage -- program.elf
+//! ```
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashSet},
+    env,
+    error::Error,
+    rc::Rc,
+};
+
+use risc_y::{
+    core::{Core32, UnalignedMemReader},
+    hooks::{Hook, HookAction},
+    instruction::Instruction,
+    load::LoadedElf,
+};
+
+/// Every pc run. The core owns the hook, so the set is shared with `main`
+#[derive(Clone, Default)]
+struct Coverage {
+    pcs: Rc<RefCell<HashSet<u32>>>,
+}
+
+impl Hook for Coverage {
+    fn before_instruction(&mut self, pc: u32, _instr: &Instruction) -> HookAction {
+        self.pcs.borrow_mut().insert(pc);
+        HookAction::Continue
+    }
+
+    fn wants_reg_writes(&self) -> bool {
+        false
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let path = env::args().nth(1).ok_or("usage: coverage PROGRAM")?;
+    let elf = LoadedElf::load(&path)?;
+    let code: Vec<_> = elf
+        .segments
+        .iter()
+        .filter(|seg| seg.executable)
+        .map(|seg| seg.vaddr..seg.vaddr + seg.data.len() as u64)
+        .collect();
+    let symbols = elf.clone();
+
+    let mut core = Core32::<UnalignedMemReader<u32>>::new(elf, None, 16 << 20, false);
+    let coverage = Coverage::default();
+    core.add_hook(Box::new(coverage.clone()));
+    core.run();
+
+    // instructions run and in all, by function
+    let mut functions = BTreeMap::<&str, (usize, usize)>::new();
+    let pcs = coverage.pcs.borrow();
+    for pc in code.into_iter().flat_map(|range| range.step_by(4)) {
+        let Some((name, _)) = symbols.symbolize(pc) else {
+            continue;
+        };
+        let (run, total) = functions.entry(name).or_default();
+        *run += pcs.contains(&(pc as u32)) as usize;
+        *total += 1;
+    }
+
+    for (name, (run, total)) in functions {
+        let percent = run as f64 / total as f64 * 100.0;
+        println!("{percent:6.1}% {run:>6}/{total:<6} {name}");
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+//! Runs a guest program with its output captured, as a test harness would.
+//!
+//! ```text
+//! cargo run --example embed -- program.elf
+//! ```
+
+use std::{env, error::Error, process::ExitCode};
+
+use risc_y::{
+    capture::CaptureConfig,
+    core::{Core32, RunInfo, UnalignedMemReader},
+    load::LoadedElf,
+};
+
+fn main() -> Result<ExitCode, Box<dyn Error>> {
+    let path = env::args().nth(1).ok_or("usage: embed PROGRAM")?;
+    let info = run(&path)?;
+
+    let output = info.output.unwrap_or_default();
+    println!(
+        "{path} stopped: {}, exit code {}",
+        info.reason.name(),
+        info.return_code
+    );
+    println!("stdout:\n{}", output.stdout_lossy());
+    println!("stderr:\n{}", output.stderr_lossy());
+    if output.truncated() {
+        println!("(output was cut short)");
+    }
+
+    Ok(if info.return_code == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn run(path: &str) -> Result<RunInfo, Box<dyn Error>> {
+    let elf = LoadedElf::load(path)?;
+    let mut core = Core32::<UnalignedMemReader<u32>>::new(elf, None, 16 << 20, false);
+    core.set_stdin(b"input for the guest\n".to_vec());
+    core.capture_output_with(CaptureConfig {
+        max_stdout: Some(1 << 20),
+        max_stderr: Some(1 << 20),
+    });
+    Ok(core.run())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the guest echoes its stdin
+    #[test]
+    fn captures_the_guests_output() {
+        let guest = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/guest/guest.elf");
+        let info = run(guest).unwrap();
+        assert_eq!(info.return_code, 0);
+        let output = info.output.unwrap();
+        assert_eq!(output.stdout_lossy(), "input for the guest\n");
+        assert_eq!(output.stderr_lossy(), "echoed stdin\n");
+        assert!(!output.truncated());
+    }
+}
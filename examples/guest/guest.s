@@ -0,0 +1,37 @@
+# A tiny guest for the examples to run: `add` for call_function, and a `_start`
+# for embed that echoes its stdin to stdout, says so on stderr and exits with 0
+
+  .text
+  .globl _start
+_start:
+  li a0, 0
+  la a1, buf
+  li a2, 64
+  li a7, 63           # read(0, buf, 64)
+  ecall
+  mv a2, a0
+  li a0, 1
+  la a1, buf
+  li a7, 64           # write(1, buf, what was read)
+  ecall
+  li a0, 2
+  la a1, note
+  li a2, 13
+  li a7, 64           # write(2, note, 13)
+  ecall
+  li a0, 0
+  li a7, 93           # exit(0)
+  ecall
+
+  .globl add
+add:
+  add a0, a0, a1
+  ret
+
+  .section .rodata
+note:
+  .ascii "echoed stdin\n"
+
+  .bss
+buf:
+  .zero 64
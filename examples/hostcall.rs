@@ -0,0 +1,46 @@
+//! Gives the guest a host function to call, here `checksum(buf, len)`.
+//!
+//! The guest calls it through `include/riscy_hostcall.h`:
+//!
+//! ```c
+//! long sum = riscy_hostcall2(CHECKSUM, buf, len);
+//! ```
+//!
+//! ```text
+//! cargo run --example hostcall -- program.elf
+//! ```
+
+use std::{env, error::Error};
+
+use risc_y::{
+    core::{Core32, UnalignedMemReader},
+    hostcall::HostcallError,
+    load::LoadedElf,
+};
+
+// any id not taken by the built in host calls
+const CHECKSUM: u32 = 1;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let path = env::args().nth(1).ok_or("usage: hostcall PROGRAM")?;
+    let elf = LoadedElf::load(&path)?;
+
+    let mut core = Core32::<UnalignedMemReader<u32>>::new(elf, None, 16 << 20, false);
+    let mut calls = 0;
+    core.register_hostcall(CHECKSUM, "checksum", move |ctx| {
+        calls += 1;
+        let len = ctx.uint(1) as usize;
+        if len > 1 << 20 {
+            return Err(HostcallError::Errno(22));
+        }
+        let bytes = ctx.bytes(0, len)?;
+        eprintln!("checksum call {calls}: {len} bytes");
+        Ok(bytes.iter().fold(0u32, |sum, &byte| {
+            sum.wrapping_mul(31).wrapping_add(byte as u32)
+        }) as i32)
+    });
+
+    let info = core.run();
+    println!("exited with {}", info.return_code);
+    Ok(())
+}
@@ -0,0 +1,36 @@
+//! Builds a machine for bare-metal firmware, with a UART where QEMU's `virt`
+//! machine has one and the console from `examples/bare-metal` to exit through.
+//!
+//! ```text
+//! cargo run --example machine -- firmware.elf
+//! ```
+
+use std::{env, error::Error, process::ExitCode};
+
+use risc_y::{
+    console::MagicConsole,
+    load::LoadedElf,
+    machine::{Machine, MachineBuilder},
+    uart::Uart,
+};
+
+fn main() -> Result<ExitCode, Box<dyn Error>> {
+    let path = env::args().nth(1).ok_or("usage: machine FIRMWARE")?;
+    let elf = LoadedElf::load(&path)?;
+
+    let mut machine: Machine = MachineBuilder::new(elf)
+        .memory(64 << 20)
+        .isa("rv32imafd_zicsr_zifencei")
+        .device(Uart::at(0x1000_0000))
+        .device(MagicConsole::at(0x2000_0000))
+        .build()?;
+
+    let info = machine.run();
+    eprintln!(
+        "{path} on {} stopped: {}, exit code {}",
+        machine.isa(),
+        info.reason.name(),
+        info.return_code
+    );
+    Ok(ExitCode::from(info.return_code as u8))
+}
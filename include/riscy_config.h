@@ -0,0 +1,56 @@
+/* Guest side of riscy's ELF note configuration, see src/elfnote.rs
+
+   A program says what machine it needs, so `riscy app.elf` runs it without
+   flags:
+
+     RISCY_CONFIG("memory=64M isa=rv32imafd device=console@0x10000000");
+
+   once, at file scope, in C, or in assembly (a .S file, so it's
+   preprocessed):
+
+     riscy_config "memory=64M device=htif"
+
+   The linker script has to keep .note.riscy, as
+   examples/bare-metal/link.ld does, or --gc-sections drops it. */
+
+#ifndef RISCY_CONFIG_H
+#define RISCY_CONFIG_H
+
+#define RISCY_NT_CONFIG 0x5259
+
+#ifdef __ASSEMBLER__
+
+/* clang-format off */
+  .macro riscy_config text
+  .pushsection .note.riscy, "a", @note
+  .balign 4
+  .4byte 6, 2f - 1f, RISCY_NT_CONFIG
+  .asciz "riscy"
+  .balign 4
+1:
+  .asciz "\text"
+2:
+  .balign 4
+  .popsection
+  .endm
+/* clang-format on */
+
+#else
+
+#define RISCY_CONFIG_CAT_(a, b) a##b
+#define RISCY_CONFIG_CAT(a, b) RISCY_CONFIG_CAT_(a, b)
+
+/* The note, laid out as an Elf32_Nhdr and its name and descriptor, padded to
+   4 bytes */
+#define RISCY_CONFIG(text)                                                     \
+  __attribute__((section(".note.riscy"), aligned(4), used)) static const      \
+      struct {                                                                 \
+    unsigned int namesz, descsz, type;                                         \
+    char name[8];                                                              \
+    char desc[(sizeof(text) + 3) & ~3];                                        \
+  } RISCY_CONFIG_CAT(riscy_config_, __LINE__) = {                              \
+      6, sizeof(text), RISCY_NT_CONFIG, "riscy", text}
+
+#endif
+
+#endif
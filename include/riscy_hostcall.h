@@ -0,0 +1,46 @@
+/* Guest side of riscy's host call interface, see src/hostcall.rs */
+
+#ifndef RISCY_HOSTCALL_H
+#define RISCY_HOSTCALL_H
+
+#include <stddef.h>
+#include <string.h>
+
+#define RISCY_SYSCALL_HOSTCALL 0x5259
+
+#define RISCY_HOSTCALL_LOG 0
+
+/* Calls host function `id`; returns its result, or a negative errno on failure */
+static inline long riscy_hostcall(unsigned long id, long a0, long a1, long a2,
+                                  long a3, long a4, long a5) {
+  register long r_a0 __asm__("a0") = a0;
+  register long r_a1 __asm__("a1") = a1;
+  register long r_a2 __asm__("a2") = a2;
+  register long r_a3 __asm__("a3") = a3;
+  register long r_a4 __asm__("a4") = a4;
+  register long r_a5 __asm__("a5") = a5;
+  register long r_a6 __asm__("a6") = (long)id;
+  register long r_a7 __asm__("a7") = RISCY_SYSCALL_HOSTCALL;
+
+  __asm__ volatile("ecall"
+                   : "+r"(r_a0)
+                   : "r"(r_a1), "r"(r_a2), "r"(r_a3), "r"(r_a4), "r"(r_a5),
+                     "r"(r_a6), "r"(r_a7)
+                   : "memory");
+
+  return r_a0;
+}
+
+#define riscy_hostcall0(id) riscy_hostcall((id), 0, 0, 0, 0, 0, 0)
+#define riscy_hostcall1(id, a) riscy_hostcall((id), (long)(a), 0, 0, 0, 0, 0)
+#define riscy_hostcall2(id, a, b)                                              \
+  riscy_hostcall((id), (long)(a), (long)(b), 0, 0, 0, 0)
+#define riscy_hostcall3(id, a, b, c)                                           \
+  riscy_hostcall((id), (long)(a), (long)(b), (long)(c), 0, 0, 0)
+
+/* Prints `msg` to the emulator's stderr */
+static inline void riscy_log(const char *msg) {
+  riscy_hostcall2(RISCY_HOSTCALL_LOG, msg, strlen(msg));
+}
+
+#endif
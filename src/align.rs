@@ -0,0 +1,176 @@
+//! Counts of misaligned loads and stores, and where they come from.
+//!
+//! `--assume-aligned` is only sound for guests that never make a misaligned
+//! access. `AlignmentCounter` counts every load and store and, for those that
+//! aren't naturally aligned, the pc that made them, so the report says whether
+//! a workload qualifies and if not, which code is to blame. Only loads, stores
+//! and atomics count; syscalls and natively serviced calls like `memcpy` don't
+//! go through the core's loads and stores. A failed `sc.w` isn't counted.
+
+use std::{cell::RefCell, collections::BTreeMap, fmt, rc::Rc};
+
+use crate::{
+    cost::OpClass,
+    hooks::{Hook, HookAction, MemRead, MemWrite},
+    instruction::Instruction,
+    load::{self, Symbol},
+};
+
+// how many pcs and symbols the report lists
+const REPORT_TOP: usize = 10;
+
+#[derive(Debug, Clone, Default)]
+struct Counts {
+    loads: u64,
+    stores: u64,
+    misaligned_loads: u64,
+    misaligned_stores: u64,
+    // misaligned (loads, stores) by pc
+    by_pc: BTreeMap<u32, (u64, u64)>,
+}
+
+/// Counts loads and stores, and misaligned ones by pc. Clones share their
+/// counts, so one can be kept to read them after the other is given to
+/// `Core32::add_hook`
+#[derive(Debug, Clone, Default)]
+pub struct AlignmentCounter {
+    counts: Rc<RefCell<Counts>>,
+}
+
+impl AlignmentCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn report(&self, symbols: &[Symbol]) -> AlignmentReport {
+        let counts = self.counts.borrow();
+
+        let mut by_pc: Vec<_> = counts
+            .by_pc
+            .iter()
+            .map(|(&pc, &(loads, stores))| (pc, loads, stores))
+            .collect();
+        by_pc.sort_by_key(|&(pc, loads, stores)| (std::cmp::Reverse(loads + stores), pc));
+
+        let mut by_symbol = BTreeMap::new();
+        for &(pc, loads, stores) in &by_pc {
+            let name = load::symbolize(symbols, pc as u64).map_or("??", |(name, _)| name);
+            *by_symbol.entry(name.to_owned()).or_insert(0) += loads + stores;
+        }
+        let mut by_symbol: Vec<_> = by_symbol.into_iter().collect();
+        by_symbol.sort_by_key(|(name, count)| (std::cmp::Reverse(*count), name.clone()));
+
+        AlignmentReport {
+            loads: counts.loads,
+            stores: counts.stores,
+            misaligned_loads: counts.misaligned_loads,
+            misaligned_stores: counts.misaligned_stores,
+            by_pc: by_pc
+                .into_iter()
+                .map(|(pc, loads, stores)| (load::describe(symbols, pc), loads, stores))
+                .collect(),
+            by_symbol,
+        }
+    }
+
+    fn count(&self, pc: u32, addr: u32, len: u32, store: bool) {
+        let counts = &mut *self.counts.borrow_mut();
+        let misaligned = !addr.is_multiple_of(len);
+        let (total, total_misaligned) = if store {
+            (&mut counts.stores, &mut counts.misaligned_stores)
+        } else {
+            (&mut counts.loads, &mut counts.misaligned_loads)
+        };
+        *total += 1;
+
+        if misaligned {
+            *total_misaligned += 1;
+            let (loads, stores) = counts.by_pc.entry(pc).or_default();
+            if store {
+                *stores += 1;
+            } else {
+                *loads += 1;
+            }
+        }
+    }
+}
+
+impl Hook for AlignmentCounter {
+    fn wants_reg_writes(&self) -> bool {
+        false
+    }
+
+    fn wants_mem_reads(&self) -> bool {
+        true
+    }
+
+    fn on_mem_read(&mut self, read: &MemRead) -> HookAction {
+        // amos both read and write, so are counted here as they always read
+        let class = OpClass::of(&read.instr);
+        if class == OpClass::Load || read.instr.is_atomic() {
+            let store = class == OpClass::Store;
+            self.count(read.pc, read.addr, read.len, store);
+        }
+        HookAction::Continue
+    }
+
+    fn wants_mem_writes(&self) -> bool {
+        true
+    }
+
+    fn on_mem_write(&mut self, write: &MemWrite) -> HookAction {
+        // `sc.w` is the only atomic that doesn't read, so isn't counted above
+        let is_store = OpClass::of(&write.instr) == OpClass::Store;
+        if is_store && (!write.instr.is_atomic() || matches!(write.instr, Instruction::ScW { .. }))
+        {
+            self.count(write.pc, write.addr, write.len, true);
+        }
+        HookAction::Continue
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AlignmentReport {
+    pub loads: u64,
+    pub stores: u64,
+    pub misaligned_loads: u64,
+    pub misaligned_stores: u64,
+    /// The pcs that made misaligned accesses, most first, described with
+    /// their symbol, and their misaligned loads and stores
+    pub by_pc: Vec<(String, u64, u64)>,
+    /// Misaligned accesses by the symbol containing their pc, most first
+    pub by_symbol: Vec<(String, u64)>,
+}
+
+impl fmt::Display for AlignmentReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "alignment:")?;
+        writeln!(
+            f,
+            "  loads  {:>14} ({} misaligned)",
+            self.loads, self.misaligned_loads
+        )?;
+        write!(
+            f,
+            "  stores {:>14} ({} misaligned)",
+            self.stores, self.misaligned_stores
+        )?;
+
+        if self.by_pc.is_empty() {
+            return write!(
+                f,
+                "\n  no misaligned accesses, so --assume-aligned is safe for this run"
+            );
+        }
+
+        write!(f, "\n  by pc:")?;
+        for (pc, loads, stores) in self.by_pc.iter().take(REPORT_TOP) {
+            write!(f, "\n    {:>10} loads {:>10} stores  {pc}", loads, stores)?;
+        }
+        write!(f, "\n  by symbol:")?;
+        for (name, count) in self.by_symbol.iter().take(REPORT_TOP) {
+            write!(f, "\n    {count:>10}  {name}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+//! Running many programs, or one program over many inputs, across threads.
+
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+use crate::{
+    core::{AdaptiveMemReader, AlignedMemReader, Core32, Fault, MemReader, StopReason},
+    fatal::GuestFatal,
+    hang::HangDetector,
+    load::LoadedElf,
+};
+
+/// A single run: a program, and optionally a file to feed it as stdin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchJob {
+    pub program: String,
+    pub input: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchConfig {
+    /// Worker threads, defaulting to the available parallelism
+    pub threads: Option<usize>,
+    pub size: usize,
+    pub assume_aligned: bool,
+    /// See `HangDetector`; without either, a hanging job hangs the batch
+    pub hang_new_pc: Option<u64>,
+    pub hang_no_progress: Option<u64>,
+    /// Where to count every job's run, see `metrics`
+    #[cfg(feature = "metrics")]
+    pub metrics: Option<Metrics>,
+}
+
+/// How a job that ran to completion ended
+#[derive(Debug, Clone)]
+pub struct RunSummary {
+    pub reason: StopReason,
+    pub return_code: i32,
+    pub instret: u64,
+    pub fatal: Option<GuestFatal>,
+    /// The instruction that stopped the run, like a load past the end of
+    /// memory, see `RunInfo::fault`
+    pub fault: Option<Fault>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatchOutcome {
+    pub job: BatchJob,
+    /// `Err` if the job couldn't be run at all
+    pub result: Result<RunSummary, String>,
+    pub duration: Duration,
+}
+
+/// Runs every job, returning the outcomes in the same order as `jobs`
+pub fn run_batch(jobs: &[BatchJob], config: &BatchConfig) -> Vec<BatchOutcome> {
+    // each program is only loaded once, however many inputs it's run with
+    let mut programs = HashMap::new();
+    for job in jobs {
+        programs.entry(job.program.as_str()).or_insert_with(|| {
+            LoadedElf::load(&job.program)
+                .map_err(|err| err.to_string())
+                .and_then(|elf| elf.check_runnable().map(|()| elf))
+                .map_err(|err| format!("{}: {err}", job.program))
+        });
+    }
+
+    let threads = config
+        .threads
+        .or_else(|| thread::available_parallelism().ok().map(|n| n.get()))
+        .unwrap_or(1)
+        .clamp(1, jobs.len().max(1));
+
+    let next = AtomicUsize::new(0);
+    let outcomes = Mutex::new(vec![None; jobs.len()]);
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                let Some(job) = jobs.get(idx) else {
+                    break;
+                };
+
+                let start = Instant::now();
+                let result = match &programs[job.program.as_str()] {
+                    Ok(elf) => run_job(elf.clone(), job, config),
+                    Err(err) => Err(err.clone()),
+                };
+
+                outcomes.lock().unwrap()[idx] = Some(BatchOutcome {
+                    job: job.clone(),
+                    result,
+                    duration: start.elapsed(),
+                });
+            });
+        }
+    });
+
+    outcomes
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|outcome| outcome.expect("job was never run"))
+        .collect()
+}
+
+/// Runs a single job on an already loaded ELF, with its output captured
+pub fn run_job(elf: LoadedElf, job: &BatchJob, config: &BatchConfig) -> Result<RunSummary, String> {
+    let input = match &job.input {
+        Some(path) => Some(std::fs::read(path).map_err(|err| format!("{path}: {err}"))?),
+        None => None,
+    };
+
+    Ok(if config.assume_aligned {
+        run_core32::<AlignedMemReader<u32>>(elf, input, config)
+    } else {
+        run_core32::<AdaptiveMemReader<u32>>(elf, input, config)
+    })
+}
+
+fn run_core32<Reader: MemReader<Idx = u32>>(
+    elf: LoadedElf,
+    input: Option<Vec<u8>>,
+    config: &BatchConfig,
+) -> RunSummary {
+    let symbols = elf.symbols.clone();
+    let mut core = Core32::<Reader>::new(elf, None, config.size, false);
+
+    core.capture_output();
+    if let Some(input) = input {
+        core.set_stdin(input);
+    }
+    if config.hang_new_pc.is_some() || config.hang_no_progress.is_some() {
+        core.add_hook(Box::new(HangDetector::new(
+            config.hang_new_pc,
+            config.hang_no_progress,
+            symbols,
+        )));
+    }
+
+    #[cfg(feature = "metrics")]
+    let instance = config
+        .metrics
+        .as_ref()
+        .map(|metrics| metrics.watch(&mut core));
+
+    let info = core.run();
+
+    #[cfg(feature = "metrics")]
+    if let Some(instance) = instance {
+        instance.finish(&core, info.reason);
+    }
+
+    RunSummary {
+        reason: info.reason,
+        return_code: info.return_code,
+        instret: core.instret(),
+        fatal: core.fatal().cloned(),
+        fault: info.fault,
+        stdout: core.captured_stdout().to_vec(),
+        stderr: core.captured_stderr().to_vec(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => Err(format!("unknown report format '{s}', expected json or csv")),
+        }
+    }
+}
+
+// what went wrong in a job that ran, if it stopped on a fatal error or a fault
+fn failure(summary: &RunSummary) -> Option<String> {
+    match (&summary.fatal, &summary.fault) {
+        (Some(fatal), _) => Some(fatal.to_string()),
+        (None, Some(fault)) => Some(format!("{} at {fault}", summary.reason.name())),
+        (None, None) => None,
+    }
+}
+
+fn status(outcome: &BatchOutcome) -> &'static str {
+    match &outcome.result {
+        Err(_) => "error",
+        Ok(summary) => summary.reason.name(),
+    }
+}
+
+/// Writes one record per outcome; only JSON includes the guest's output
+pub fn write_report(
+    outcomes: &[BatchOutcome],
+    format: ReportFormat,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    match format {
+        ReportFormat::Json => write_json(outcomes, out),
+        ReportFormat::Csv => write_csv(outcomes, out),
+    }
+}
+
+fn write_json(outcomes: &[BatchOutcome], out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "[")?;
+    for (idx, outcome) in outcomes.iter().enumerate() {
+        let BatchJob { program, input } = &outcome.job;
+
+        write!(
+            out,
+            "  {{\"program\": {}, \"input\": {}, \"status\": \"{}\", \"duration_ms\": {:.3}",
+            json_str(program),
+            input.as_deref().map_or("null".to_string(), json_str),
+            status(outcome),
+            outcome.duration.as_secs_f64() * 1e3
+        )?;
+
+        match &outcome.result {
+            Ok(summary) => {
+                write!(
+                    out,
+                    ", \"exit_code\": {}, \"instret\": {}",
+                    summary.return_code, summary.instret
+                )?;
+                if let Some(fatal) = &summary.fatal {
+                    write!(out, ", \"fatal\": {}", json_str(&fatal.to_string()))?;
+                }
+                if let Some(fault) = &summary.fault {
+                    write!(out, ", \"fault\": {}", json_str(&fault.to_string()))?;
+                }
+                write!(
+                    out,
+                    ", \"stdout\": {}, \"stderr\": {}",
+                    json_str(&String::from_utf8_lossy(&summary.stdout)),
+                    json_str(&String::from_utf8_lossy(&summary.stderr))
+                )?;
+            }
+            Err(err) => write!(out, ", \"error\": {}", json_str(err))?,
+        }
+
+        let sep = if idx + 1 == outcomes.len() { "" } else { "," };
+        writeln!(out, "}}{sep}")?;
+    }
+    writeln!(out, "]")
+}
+
+fn write_csv(outcomes: &[BatchOutcome], out: &mut impl Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "program,input,status,exit_code,instret,duration_ms,error"
+    )?;
+    for outcome in outcomes {
+        let BatchJob { program, input } = &outcome.job;
+        let (exit_code, instret, error) = match &outcome.result {
+            Ok(summary) => (
+                summary.return_code.to_string(),
+                summary.instret.to_string(),
+                failure(summary),
+            ),
+            Err(err) => (String::new(), String::new(), Some(err.clone())),
+        };
+
+        writeln!(
+            out,
+            "{},{},{},{exit_code},{instret},{:.3},{}",
+            csv_field(program),
+            csv_field(input.as_deref().unwrap_or("")),
+            status(outcome),
+            outcome.duration.as_secs_f64() * 1e3,
+            csv_field(error.as_deref().unwrap_or(""))
+        )?;
+    }
+    Ok(())
+}
+
+pub(crate) fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
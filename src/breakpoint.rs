@@ -0,0 +1,119 @@
+//! Breaking on syscalls and on calls to named functions.
+//!
+//! `Breakpoints` stops the run before a given syscall (`--break-syscall write`)
+//! or any jump to a given function (`--break-call malloc`), printing the
+//! arguments, so there's no need to look addresses up by hand. Calls stop at
+//! the call itself, so calls to functions the core services natively, like
+//! `memcpy`, stop too. In the debuggers the run can be continued from there:
+//! whatever stopped it is let through once.
+
+use std::str::FromStr;
+
+use crate::{
+    hooks::{Hook, HookAction, Jump, Syscall},
+    load::{self, Symbol},
+    register::Register,
+    syscall,
+};
+
+// the argument registers shown for a call, as the callee's arity isn't known
+const CALL_ARGS: usize = 4;
+
+/// A `--break-syscall` argument, a syscall's name (`write`) or number (`64`)
+#[derive(Debug, Clone, Copy)]
+pub struct SyscallSpec {
+    pub num: i32,
+}
+
+impl FromStr for SyscallSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let num = match syscall::by_name(s) {
+            Some(desc) => desc.num,
+            None => s
+                .parse()
+                .map_err(|_| format!("unknown syscall '{s}', expected a name or number"))?,
+        };
+        Ok(SyscallSpec { num })
+    }
+}
+
+pub struct Breakpoints {
+    syscalls: Vec<i32>,
+    // function entry points, and their names
+    calls: Vec<(u32, String)>,
+    symbols: Vec<Symbol>,
+    // the pc last stopped at, let through once when the run is resumed
+    stopped_at: Option<u32>,
+}
+
+impl Breakpoints {
+    /// Fails if a function isn't in `symbols`
+    pub fn new(
+        syscalls: &[SyscallSpec],
+        calls: &[String],
+        symbols: Vec<Symbol>,
+    ) -> Result<Self, String> {
+        let calls = calls
+            .iter()
+            .map(|name| {
+                let sym = symbols
+                    .iter()
+                    .find(|sym| &sym.name == name)
+                    .ok_or_else(|| format!("no function '{name}' to break on"))?;
+                Ok((sym.addr as u32, name.clone()))
+            })
+            .collect::<Result<_, String>>()?;
+
+        Ok(Self {
+            syscalls: syscalls.iter().map(|spec| spec.num).collect(),
+            calls,
+            symbols,
+            stopped_at: None,
+        })
+    }
+
+    fn stop(&mut self, pc: u32, what: String) -> HookAction {
+        if self.stopped_at.take() == Some(pc) {
+            return HookAction::Continue;
+        }
+
+        eprintln!("break: {what} at {}", load::describe(&self.symbols, pc));
+        self.stopped_at = Some(pc);
+        HookAction::Break
+    }
+}
+
+impl Hook for Breakpoints {
+    fn wants_reg_writes(&self) -> bool {
+        false
+    }
+
+    fn wants_syscalls(&self) -> bool {
+        !self.syscalls.is_empty()
+    }
+
+    fn on_syscall(&mut self, syscall: &Syscall) -> HookAction {
+        if !self.syscalls.contains(&syscall.num) {
+            return HookAction::Continue;
+        }
+        self.stop(syscall.pc, syscall::describe(syscall.num, &syscall.args))
+    }
+
+    fn wants_jumps(&self) -> bool {
+        !self.calls.is_empty()
+    }
+
+    fn on_jump(&mut self, jump: &Jump) -> HookAction {
+        let Some((_, name)) = self.calls.iter().find(|(addr, _)| *addr == jump.target) else {
+            return HookAction::Continue;
+        };
+
+        let args: Vec<_> = (0..CALL_ARGS)
+            .map(|n| format!("{}={:#x}", Register::A(n), jump.args[n]))
+            .collect();
+        let what = format!("call {name}({})", args.join(", "));
+        self.stop(jump.pc, what)
+    }
+}
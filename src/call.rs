@@ -0,0 +1,114 @@
+//! Entering the guest at an arbitrary function with a synthesized call frame.
+
+use std::str::FromStr;
+
+/// An argument passed according to the ilp32d calling convention
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArgValue {
+    Int(i32),
+    Single(f32),
+    Double(f64),
+}
+
+impl FromStr for ArgValue {
+    type Err = String;
+
+    /// Integers (`42`, `-1`, `0x10`) are passed in a-registers, floats in
+    /// fa-registers: as a double (`1.5`), or as a single with a C-style suffix (`1.5f`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid argument '{s}'");
+
+        if let Some(hex) = s.strip_prefix("0x") {
+            return u32::from_str_radix(hex, 16)
+                .map(|val| ArgValue::Int(val as i32))
+                .map_err(|_| err());
+        }
+
+        if let Ok(val) = s.parse::<i32>() {
+            return Ok(ArgValue::Int(val));
+        }
+
+        if let Some(single) = s.strip_suffix('f') {
+            return single.parse().map(ArgValue::Single).map_err(|_| err());
+        }
+
+        s.parse().map(ArgValue::Double).map_err(|_| err())
+    }
+}
+
+/// The function to enter with `Core32::call_function`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallTarget<'a> {
+    Symbol(&'a str),
+    Addr(u32),
+}
+
+impl<'a> From<&'a str> for CallTarget<'a> {
+    fn from(name: &'a str) -> Self {
+        CallTarget::Symbol(name)
+    }
+}
+
+impl From<u32> for CallTarget<'_> {
+    fn from(addr: u32) -> Self {
+        CallTarget::Addr(addr)
+    }
+}
+
+impl From<i32> for ArgValue {
+    fn from(val: i32) -> Self {
+        ArgValue::Int(val)
+    }
+}
+
+impl From<u32> for ArgValue {
+    fn from(val: u32) -> Self {
+        ArgValue::Int(val as i32)
+    }
+}
+
+impl From<f32> for ArgValue {
+    fn from(val: f32) -> Self {
+        ArgValue::Single(val)
+    }
+}
+
+impl From<f64> for ArgValue {
+    fn from(val: f64) -> Self {
+        ArgValue::Double(val)
+    }
+}
+
+/// The return value registers after a call returns
+///
+/// The callee's return type isn't known, so this just holds `a0`/`a1` and the
+/// raw bits of `fa0`, with accessors for reading them as the common types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetValue {
+    pub a0: i32,
+    pub a1: i32,
+    pub fa0: u64,
+}
+
+impl RetValue {
+    pub fn int(&self) -> i32 {
+        self.a0
+    }
+
+    pub fn uint(&self) -> u32 {
+        self.a0 as u32
+    }
+
+    /// A 64-bit integer, returned in the `a0`/`a1` pair
+    pub fn long(&self) -> i64 {
+        (self.a0 as u32 as i64) | ((self.a1 as i64) << 32)
+    }
+
+    pub fn single(&self) -> f32 {
+        f32::from_bits(self.fa0 as u32)
+    }
+
+    pub fn double(&self) -> f64 {
+        f64::from_bits(self.fa0)
+    }
+}
@@ -0,0 +1,87 @@
+//! Collecting the guest's stdout and stderr, for embedders.
+//!
+//! `Core32::capture_output` keeps what the guest writes to stdout and stderr
+//! in memory rather than passing it to the host's, so it doesn't interleave
+//! with a test harness's own output, and every `RunInfo` then carries a copy
+//! in `RunInfo::output`. A `CaptureConfig` bounds how much of each is kept:
+//! past the limit the guest's writes still succeed, but the bytes are only
+//! counted, so a guest printing in a loop can't use up the host's memory.
+
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CaptureConfig {
+    /// The most bytes of stdout kept, the first written
+    pub max_stdout: Option<usize>,
+    /// The most bytes of stderr kept, the first written
+    pub max_stderr: Option<usize>,
+}
+
+/// What the guest wrote to stdout and stderr
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CapturedOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// Bytes written to stdout past `CaptureConfig::max_stdout`, and so not
+    /// kept
+    pub stdout_dropped: u64,
+    /// Bytes written to stderr past `CaptureConfig::max_stderr`
+    pub stderr_dropped: u64,
+}
+
+impl CapturedOutput {
+    /// stdout as text, with invalid UTF-8 replaced
+    pub fn stdout_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stdout)
+    }
+
+    /// stderr as text, with invalid UTF-8 replaced
+    pub fn stderr_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stderr)
+    }
+
+    /// Whether either was cut short by its limit
+    pub fn truncated(&self) -> bool {
+        self.stdout_dropped > 0 || self.stderr_dropped > 0
+    }
+}
+
+/// Capturing as the core does it
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Capture {
+    config: CaptureConfig,
+    pub(crate) output: CapturedOutput,
+}
+
+impl Capture {
+    pub(crate) fn new(config: CaptureConfig) -> Self {
+        Self {
+            config,
+            output: CapturedOutput::default(),
+        }
+    }
+
+    /// Keeps what fits of `buf`, if `fd` is stdout or stderr, returning whether
+    /// it was
+    pub(crate) fn write(&mut self, fd: i32, buf: &[u8]) -> bool {
+        let (out, max, dropped) = match fd {
+            1 => (
+                &mut self.output.stdout,
+                self.config.max_stdout,
+                &mut self.output.stdout_dropped,
+            ),
+            2 => (
+                &mut self.output.stderr,
+                self.config.max_stderr,
+                &mut self.output.stderr_dropped,
+            ),
+            _ => return false,
+        };
+
+        let left = max.map_or(buf.len(), |max| max.saturating_sub(out.len()));
+        let kept = buf.len().min(left);
+        out.extend_from_slice(&buf[..kept]);
+        *dropped += (buf.len() - kept) as u64;
+        true
+    }
+}
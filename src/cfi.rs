@@ -0,0 +1,165 @@
+//! Shadow stack control-flow integrity checking.
+//!
+//! `ShadowStack` keeps its own copy of the return address of every call in
+//! flight, and stops the run when a `ret` goes anywhere else. It also watches
+//! the stack slot each return address is spilled to, so the report can point
+//! at the store that overwrote it. Non-local exits like `longjmp` return
+//! somewhere unexpected too, so are reported the same way.
+
+use crate::{
+    core::StopReason,
+    hooks::{Hook, HookAction, Jump, MemWrite},
+    instruction::Instruction,
+    load::{self, Symbol},
+};
+
+// as in the hang detector, deeper than this is more likely a bug than not
+const MAX_FRAMES: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    call_pc: u32,
+    ret: u32,
+    // where the callee spilled `ra`, once it has
+    slot: Option<u32>,
+    // the last write to `slot` other than the spill itself
+    clobbered_by: Option<MemWrite>,
+}
+
+pub struct ShadowStack {
+    symbols: Vec<Symbol>,
+    frames: Vec<Frame>,
+    // calls made past `MAX_FRAMES`, whose returns go unchecked
+    untracked: usize,
+}
+
+impl ShadowStack {
+    pub fn new(symbols: Vec<Symbol>) -> Self {
+        Self {
+            symbols,
+            frames: Vec::new(),
+            untracked: 0,
+        }
+    }
+
+    fn describe(&self, addr: u32) -> String {
+        load::describe(&self.symbols, addr)
+    }
+
+    fn report(&self, ret_pc: u32, frame: &Frame, actual: u32) -> HookAction {
+        eprintln!(
+            "control-flow violation: return at {} went to {}, expected {}",
+            self.describe(ret_pc),
+            self.describe(actual),
+            self.describe(frame.ret)
+        );
+        eprintln!("  called from {}", self.describe(frame.call_pc));
+
+        match (frame.slot, frame.clobbered_by) {
+            (Some(slot), Some(write)) => eprintln!(
+                "  return address spilled to {slot:#x} was overwritten by {} at {} ({} bytes at {:#x})",
+                write.instr,
+                self.describe(write.pc),
+                write.len,
+                write.addr
+            ),
+            (Some(slot), None) => {
+                eprintln!("  return address spilled to {slot:#x} was never overwritten")
+            }
+            (None, _) => eprintln!("  return address was never spilled"),
+        }
+
+        HookAction::Stop(StopReason::CfiViolation {
+            pc: ret_pc,
+            expected: frame.ret,
+            actual,
+        })
+    }
+}
+
+impl Hook for ShadowStack {
+    fn before_instruction(&mut self, pc: u32, _instr: &Instruction) -> HookAction {
+        // returned from a call the core serviced natively, without a `ret`
+        if self.frames.last().is_some_and(|frame| frame.ret == pc) {
+            self.frames.pop();
+        }
+
+        HookAction::Continue
+    }
+
+    fn wants_jumps(&self) -> bool {
+        true
+    }
+
+    fn on_jump(&mut self, jump: &Jump) -> HookAction {
+        match jump.instr {
+            Instruction::Jal { rd: 1, .. } | Instruction::Jalr { rd: 1, .. } => {
+                if self.frames.len() < MAX_FRAMES {
+                    self.frames.push(Frame {
+                        call_pc: jump.pc,
+                        ret: jump.pc.wrapping_add(4),
+                        slot: None,
+                        clobbered_by: None,
+                    });
+                } else {
+                    self.untracked += 1;
+                }
+            }
+            // `ret`
+            Instruction::Jalr {
+                rd: 0,
+                rs1: 1,
+                imm: 0,
+            } => {
+                if self.untracked > 0 {
+                    self.untracked -= 1;
+                } else if let Some(frame) = self.frames.pop() {
+                    if jump.target != frame.ret {
+                        return self.report(jump.pc, &frame, jump.target);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        HookAction::Continue
+    }
+
+    fn wants_reg_writes(&self) -> bool {
+        false
+    }
+
+    fn wants_mem_writes(&self) -> bool {
+        true
+    }
+
+    fn on_mem_write(&mut self, write: &MemWrite) -> HookAction {
+        let spill = matches!(write.instr, Instruction::Sw { rs2: 1, .. });
+
+        if spill {
+            if let Some(frame) = self.frames.last_mut() {
+                match frame.slot {
+                    None => {
+                        frame.slot = Some(write.addr);
+                        return HookAction::Continue;
+                    }
+                    Some(slot) if slot == write.addr => return HookAction::Continue,
+                    Some(_) => {}
+                }
+            }
+        }
+
+        let end = write.addr as u64 + write.len as u64;
+        for frame in &mut self.frames {
+            let Some(slot) = frame.slot else {
+                continue;
+            };
+
+            if (write.addr as u64) < slot as u64 + 4 && end > slot as u64 {
+                frame.clobbered_by = Some(*write);
+            }
+        }
+
+        HookAction::Continue
+    }
+}
@@ -0,0 +1,306 @@
+//! Machine snapshots, for resuming long runs after the host or guest dies.
+//!
+//! `Core32::set_checkpoint` writes a `Snapshot` of the core every so many
+//! instructions, replacing the last one, and `Core32::restore` picks up from
+//! it. Memory is run-length encoded, as most of it is never touched.
+//!
+//! Nothing outside the core is saved except the offsets of the host's stdin,
+//! stdout and stderr, where they are regular files: on restore, stdin is
+//! rewound to where the guest had read to and stdout/stderr are cut back to
+//! what had been written, so output is neither lost nor repeated. Anything
+//! else the guest's syscalls or hostcalls did to the host is not undone.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Write},
+    mem,
+    os::fd::FromRawFd,
+    path::{Path, PathBuf},
+};
+
+const MAGIC: &[u8; 8] = b"RSCYCKPT";
+const VERSION: u32 = 2;
+
+// a run of one repeated byte, rather than literal bytes
+const RUN: u32 = 1 << 31;
+// runs shorter than this are cheaper stored as literals
+const MIN_RUN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// A hash of the program's code, so a snapshot isn't restored into another
+    pub program: u64,
+    pub pc: u32,
+    pub instret: u64,
+    pub gp_regs: [i32; 32],
+    pub fp_regs: [u64; 32],
+    pub fcsr: u32,
+    /// The program break
+    pub brk: u32,
+    pub syscall_counts: BTreeMap<i32, u64>,
+    /// The state of the seeded random number generator, see `nondet`
+    pub rng: Option<u64>,
+    /// How far into a stdin buffer given with `Core32::set_stdin` the guest has
+    /// read
+    pub stdin_pos: Option<u64>,
+    /// Offsets of the host's stdin, stdout and stderr, where they're files
+    pub fd_offsets: Vec<(i32, u64)>,
+    pub memory: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        // written aside and renamed over, so a crash mid-write can't lose the
+        // last good snapshot
+        let tmp = path.with_extension("tmp");
+        let mut out = io::BufWriter::new(File::create(&tmp)?);
+        self.write_to(&mut out)?;
+        out.into_inner()?.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::read_from(&mut io::BufReader::new(File::open(path)?))
+    }
+
+    fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        write_u32(out, VERSION)?;
+        write_u64(out, self.program)?;
+        write_u32(out, self.pc)?;
+        write_u64(out, self.instret)?;
+        for reg in self.gp_regs {
+            write_u32(out, reg as u32)?;
+        }
+        for reg in self.fp_regs {
+            write_u64(out, reg)?;
+        }
+        write_u32(out, self.fcsr)?;
+        write_u32(out, self.brk)?;
+
+        write_u32(out, self.syscall_counts.len() as u32)?;
+        for (&num, &count) in &self.syscall_counts {
+            write_u32(out, num as u32)?;
+            write_u64(out, count)?;
+        }
+
+        // 0 for none, else 1 then the value
+        for opt in [self.rng, self.stdin_pos] {
+            write_u64(out, opt.is_some() as u64)?;
+            write_u64(out, opt.unwrap_or(0))?;
+        }
+
+        write_u32(out, self.fd_offsets.len() as u32)?;
+        for &(fd, offset) in &self.fd_offsets {
+            write_u32(out, fd as u32)?;
+            write_u64(out, offset)?;
+        }
+
+        write_u64(out, self.memory.len() as u64)?;
+        compress(&self.memory, out)
+    }
+
+    fn read_from(input: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a riscy checkpoint"));
+        }
+
+        let version = read_u32(input)?;
+        if version != VERSION {
+            return Err(invalid(&format!(
+                "unsupported checkpoint version {version}"
+            )));
+        }
+
+        let program = read_u64(input)?;
+        let pc = read_u32(input)?;
+        let instret = read_u64(input)?;
+        let mut gp_regs = [0; 32];
+        for reg in &mut gp_regs {
+            *reg = read_u32(input)? as i32;
+        }
+        let mut fp_regs = [0; 32];
+        for reg in &mut fp_regs {
+            *reg = read_u64(input)?;
+        }
+        let fcsr = read_u32(input)?;
+        let brk = read_u32(input)?;
+
+        let mut syscall_counts = BTreeMap::new();
+        for _ in 0..read_u32(input)? {
+            let num = read_u32(input)? as i32;
+            syscall_counts.insert(num, read_u64(input)?);
+        }
+
+        let mut read_opt = || -> io::Result<Option<u64>> {
+            let present = read_u64(input)? != 0;
+            let value = read_u64(input)?;
+            Ok(present.then_some(value))
+        };
+        let rng = read_opt()?;
+        let stdin_pos = read_opt()?;
+
+        let mut fd_offsets = Vec::new();
+        for _ in 0..read_u32(input)? {
+            let fd = read_u32(input)? as i32;
+            fd_offsets.push((fd, read_u64(input)?));
+        }
+
+        let len = read_u64(input)? as usize;
+        let memory = decompress(input, len)?;
+
+        Ok(Snapshot {
+            program,
+            pc,
+            instret,
+            gp_regs,
+            fp_regs,
+            fcsr,
+            brk,
+            syscall_counts,
+            rng,
+            stdin_pos,
+            fd_offsets,
+            memory,
+        })
+    }
+}
+
+/// When and where the core writes snapshots
+#[derive(Debug, Clone)]
+pub(crate) struct Checkpointer {
+    pub(crate) interval: u64,
+    pub(crate) path: PathBuf,
+    pub(crate) next: u64,
+}
+
+/// FNV-1a, which is stable across runs and builds unlike `DefaultHasher`
+pub(crate) fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The offsets of the host's stdin, stdout and stderr, where they're regular
+/// files
+pub(crate) fn host_fd_offsets() -> Vec<(i32, u64)> {
+    (0..3)
+        .filter_map(|fd| {
+            with_host_file(fd, |file| {
+                if !file.metadata().ok()?.is_file() {
+                    return None;
+                }
+                Some((fd, file.stream_position().ok()?))
+            })
+        })
+        .collect()
+}
+
+/// Rewinds stdin, and truncates stdout/stderr, to the offsets they were at
+pub(crate) fn restore_host_fd_offsets(offsets: &[(i32, u64)]) -> io::Result<()> {
+    for &(fd, offset) in offsets {
+        with_host_file(fd, |file| {
+            if fd != 0 {
+                file.set_len(offset)?;
+            }
+            file.seek(SeekFrom::Start(offset)).map(|_| ())
+        })?;
+    }
+    Ok(())
+}
+
+fn with_host_file<T>(fd: i32, f: impl FnOnce(&mut File) -> T) -> T {
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    let result = f(&mut file);
+
+    // IMPORTANT: don't close the file
+    mem::forget(file);
+    result
+}
+
+fn compress(data: &[u8], out: &mut impl Write) -> io::Result<()> {
+    let mut literal_start = 0;
+    let mut idx = 0;
+
+    while idx < data.len() {
+        let byte = data[idx];
+        let run = data[idx..]
+            .iter()
+            .take(RUN as usize - 1)
+            .take_while(|&&b| b == byte)
+            .count();
+
+        if run < MIN_RUN {
+            idx += run;
+            continue;
+        }
+
+        write_literal(&data[literal_start..idx], out)?;
+        write_u32(out, RUN | run as u32)?;
+        out.write_all(&[byte])?;
+
+        idx += run;
+        literal_start = idx;
+    }
+
+    write_literal(&data[literal_start..], out)
+}
+
+fn write_literal(data: &[u8], out: &mut impl Write) -> io::Result<()> {
+    for chunk in data.chunks(RUN as usize - 1) {
+        write_u32(out, chunk.len() as u32)?;
+        out.write_all(chunk)?;
+    }
+    Ok(())
+}
+
+fn decompress(input: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(len);
+
+    while data.len() < len {
+        let header = read_u32(input)?;
+        let count = (header & !RUN) as usize;
+        if data.len() + count > len {
+            return Err(invalid("memory runs past its length"));
+        }
+
+        if header & RUN != 0 {
+            let mut byte = [0];
+            input.read_exact(&mut byte)?;
+            data.resize(data.len() + count, byte[0]);
+        } else {
+            let start = data.len();
+            data.resize(start + count, 0);
+            input.read_exact(&mut data[start..])?;
+        }
+    }
+
+    Ok(data)
+}
+
+pub(crate) fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn write_u32(out: &mut impl Write, value: u32) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn write_u64(out: &mut impl Write, value: u64) -> io::Result<()> {
+    out.write_all(&value.to_le_bytes())
+}
+
+pub(crate) fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub(crate) fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
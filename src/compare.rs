@@ -0,0 +1,189 @@
+//! Running a program under riscy and a reference emulator (normally QEMU user
+//! mode) and diffing what they did, to tell guest bugs from riscy bugs.
+
+use std::{
+    fmt, fs,
+    io::Write,
+    os::unix::process::ExitStatusExt,
+    process::{Command, Stdio},
+};
+
+use crate::{
+    batch::{self, BatchConfig, BatchJob},
+    load::LoadedElf,
+};
+
+/// What a program visibly did under one emulator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Observed {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// Death by signal `n` is reported as `128 + n`, like a shell would
+    pub exit_code: i32,
+    pub instret: Option<u64>,
+}
+
+pub fn run_riscy(job: &BatchJob, config: &BatchConfig) -> Result<Observed, String> {
+    let elf = LoadedElf::load(&job.program).map_err(|err| format!("{}: {err}", job.program))?;
+    elf.check_runnable()
+        .map_err(|err| format!("{}: {err}", job.program))?;
+    let summary = batch::run_job(elf, job, config)?;
+
+    Ok(Observed {
+        stdout: summary.stdout,
+        stderr: summary.stderr,
+        exit_code: summary.return_code & 0xff,
+        instret: Some(summary.instret),
+    })
+}
+
+/// Runs `job` under `emulator`, e.g. `qemu-riscv32`
+///
+/// With `insn_plugin` (the path to QEMU's `libinsn.so`), the instruction count
+/// is read from the plugin's log.
+pub fn run_reference(
+    emulator: &str,
+    job: &BatchJob,
+    insn_plugin: Option<&str>,
+) -> Result<Observed, String> {
+    let log = std::env::temp_dir().join(format!("riscy-compare-{}.log", std::process::id()));
+
+    let mut cmd = Command::new(emulator);
+    if let Some(plugin) = insn_plugin {
+        cmd.arg("-plugin")
+            .arg(plugin)
+            .arg("-d")
+            .arg("plugin")
+            .arg("-D")
+            .arg(&log);
+    }
+    cmd.arg(&job.program)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let input = match &job.input {
+        Some(path) => fs::read(path).map_err(|err| format!("{path}: {err}"))?,
+        None => Vec::new(),
+    };
+
+    let mut child = cmd
+        .spawn()
+        .map_err(|err| format!("failed to run {emulator}: {err}"))?;
+    // a guest that doesn't read all its input closes the pipe early, which is fine
+    let _ = child.stdin.take().unwrap().write_all(&input);
+    let output = child.wait_with_output().map_err(|err| err.to_string())?;
+
+    let exit_code = match (output.status.code(), output.status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => unreachable!("process neither exited nor was signalled"),
+    };
+
+    let instret = match insn_plugin {
+        Some(_) => {
+            let log_text = fs::read_to_string(&log).unwrap_or_default();
+            let _ = fs::remove_file(&log);
+            // `insns: 12345`, summed over vcpus
+            log_text
+                .lines()
+                .filter_map(|line| line.trim().strip_prefix("insns: "))
+                .filter_map(|count| count.trim().parse::<u64>().ok())
+                .reduce(|a, b| a + b)
+        }
+        None => None,
+    };
+
+    Ok(Observed {
+        stdout: output.stdout,
+        stderr: output.stderr,
+        exit_code,
+        instret,
+    })
+}
+
+/// One way the two runs differed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub what: &'static str,
+    pub riscy: String,
+    pub reference: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} differs:\n    riscy:     {}\n    reference: {}",
+            self.what, self.riscy, self.reference
+        )
+    }
+}
+
+pub fn compare(riscy: &Observed, reference: &Observed) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+
+    if riscy.exit_code != reference.exit_code {
+        mismatches.push(Mismatch {
+            what: "exit code",
+            riscy: riscy.exit_code.to_string(),
+            reference: reference.exit_code.to_string(),
+        });
+    }
+
+    for (what, a, b) in [
+        ("stdout", &riscy.stdout, &reference.stdout),
+        ("stderr", &riscy.stderr, &reference.stderr),
+    ] {
+        if let Some(mismatch) = diff_output(what, a, b) {
+            mismatches.push(mismatch);
+        }
+    }
+
+    if let (Some(a), Some(b)) = (riscy.instret, reference.instret) {
+        if a != b {
+            mismatches.push(Mismatch {
+                what: "instruction count",
+                riscy: a.to_string(),
+                reference: b.to_string(),
+            });
+        }
+    }
+
+    mismatches
+}
+
+/// Reports the first line the outputs differ on
+fn diff_output(what: &'static str, a: &[u8], b: &[u8]) -> Option<Mismatch> {
+    if a == b {
+        return None;
+    }
+
+    let a = String::from_utf8_lossy(a);
+    let b = String::from_utf8_lossy(b);
+    let mut a_lines = a.lines();
+    let mut b_lines = b.lines();
+
+    let mut line = 1;
+    loop {
+        match (a_lines.next(), b_lines.next()) {
+            (Some(x), Some(y)) if x == y => line += 1,
+            (None, None) => {
+                // only line endings differ
+                return Some(Mismatch {
+                    what,
+                    riscy: format!("{a:?}"),
+                    reference: format!("{b:?}"),
+                });
+            }
+            (x, y) => {
+                let show = |l: Option<&str>| l.map_or("<eof>".to_string(), |l| format!("{l:?}"));
+                return Some(Mismatch {
+                    what,
+                    riscy: format!("line {line}: {}", show(x)),
+                    reference: format!("line {line}: {}", show(y)),
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! A trivial console and exit device, for bringing up bare-metal programs.
+//!
+//! `riscy --console[=ADDR]` maps a `MagicConsole` at ADDR, `0x10000000` unless
+//! given, so freestanding code can print and exit with no libc, syscalls or
+//! HTIF: a byte stored to it is written to stdout, and a word stored to it ends
+//! the run with the word as the exit code. Other stores are ignored and loads
+//! read 0. `examples/bare-metal` has startup code and a linker script for it.
+
+use std::{
+    io::{self, Write},
+    str::FromStr,
+};
+
+use tracing::warn;
+
+use crate::{
+    machine::{At, Mmio},
+    region::Device,
+};
+
+/// The size of the window the device is mapped over
+pub const WINDOW: u64 = 8;
+
+/// A `--console` argument
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsoleAddr(pub u32);
+
+impl FromStr for ConsoleAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => s.parse(),
+        }
+        .map(ConsoleAddr)
+        .map_err(|_| format!("invalid address '{s}'"))
+    }
+}
+
+pub struct MagicConsole<W: Write> {
+    out: W,
+    exit: Option<i32>,
+}
+
+impl<W: Write> MagicConsole<W> {
+    /// A console writing to `out`
+    pub fn new(out: W) -> Self {
+        Self { out, exit: None }
+    }
+}
+
+impl MagicConsole<io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+
+    /// A console on stdout at `addr`, for `MachineBuilder::device`
+    pub fn at(addr: u32) -> At<Self> {
+        At::new(addr, Self::stdout())
+    }
+}
+
+impl<W: Write + 'static> Mmio for MagicConsole<W> {
+    const WINDOW: u64 = WINDOW;
+}
+
+impl<W: Write> Device for MagicConsole<W> {
+    fn read(&mut self, _offset: u32, _len: u32) -> u64 {
+        0
+    }
+
+    fn write(&mut self, _offset: u32, len: u32, value: u64) {
+        let result = match len {
+            1 => self.out.write_all(&[value as u8]),
+            4 | 8 => {
+                self.exit = Some(value as i32);
+                self.out.flush()
+            }
+            _ => Ok(()),
+        };
+        if let Err(err) = result {
+            warn!(target: "device", "console write failed: {err}");
+        }
+    }
+
+    fn exit_code(&mut self) -> Option<i32> {
+        self.exit.take()
+    }
+
+    // for output from a guest that stopped without writing an exit code
+    fn shutdown(&mut self) {
+        if let Err(err) = self.out.flush() {
+            warn!(target: "device", "console write failed: {err}");
+        }
+    }
+}
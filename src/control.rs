@@ -0,0 +1,140 @@
+//! Pausing, resuming and stopping a running core from another thread.
+//!
+//! `Core32::control_handle` hands out a `RunHandle`, which can be cloned and
+//! sent to other threads. The core only looks at it every `POLL_INTERVAL`
+//! instructions, so requests take effect at an instruction boundary shortly
+//! after being made, rather than at the very next one.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Condvar, Mutex,
+};
+
+// how many instructions to run between looking for requests
+pub(crate) const POLL_INTERVAL: u64 = 1024;
+
+/// The state of a paused core, taken when it paused
+#[derive(Debug, Clone)]
+pub struct PausedState {
+    /// The next instruction to execute
+    pub pc: u32,
+    pub instret: u64,
+    pub gp_regs: [i32; 32],
+    pub fp_regs: [u64; 32],
+}
+
+#[derive(Debug, Clone, Default)]
+enum Status {
+    #[default]
+    Running,
+    Paused(Box<PausedState>),
+    Finished,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    pause: bool,
+    stop: bool,
+    status: Status,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    // set while a request is outstanding, so polling is just a load otherwise
+    pending: AtomicBool,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+/// Controls a core from outside the thread running it
+#[derive(Debug, Clone, Default)]
+pub struct RunHandle {
+    shared: Arc<Shared>,
+}
+
+impl RunHandle {
+    /// Asks the core to pause; `wait_paused` gives its state once it has
+    pub fn pause(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.pause = true;
+        self.shared.pending.store(true, Ordering::Relaxed);
+    }
+
+    /// Lets a paused core carry on, or withdraws a pause it hasn't reached yet
+    pub fn resume(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.pause = false;
+        self.shared.pending.store(state.stop, Ordering::Relaxed);
+        self.shared.changed.notify_all();
+    }
+
+    /// Asks the core to stop, ending the run with `StopReason::Cancelled`,
+    /// whether it is running or paused
+    pub fn stop(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.stop = true;
+        self.shared.pending.store(true, Ordering::Relaxed);
+        self.shared.changed.notify_all();
+    }
+
+    /// Blocks until the core pauses, returning its state, or `None` if the run
+    /// ends first
+    pub fn wait_paused(&self) -> Option<PausedState> {
+        let state = self.shared.state.lock().unwrap();
+        let state = self
+            .shared
+            .changed
+            .wait_while(state, |state| matches!(state.status, Status::Running))
+            .unwrap();
+
+        match &state.status {
+            Status::Paused(paused) => Some((**paused).clone()),
+            _ => None,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        matches!(self.shared.state.lock().unwrap().status, Status::Paused(_))
+    }
+
+    /// Called by the core at an instruction boundary; blocks while paused, and
+    /// returns whether the run should stop
+    pub(crate) fn poll(&self, snapshot: impl FnOnce() -> PausedState) -> bool {
+        if !self.shared.pending.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        let mut state = self.shared.state.lock().unwrap();
+        if state.pause && !state.stop {
+            state.status = Status::Paused(Box::new(snapshot()));
+            self.shared.changed.notify_all();
+
+            state = self
+                .shared
+                .changed
+                .wait_while(state, |state| state.pause && !state.stop)
+                .unwrap();
+            state.status = Status::Running;
+        }
+
+        let stop = state.stop;
+        state.stop = false;
+        self.shared.pending.store(state.pause, Ordering::Relaxed);
+        stop
+    }
+
+    /// Marks the start or end of a run; requests outstanding when it ends are
+    /// dropped rather than applied to the next run
+    pub(crate) fn set_running(&self, running: bool) {
+        let mut state = self.shared.state.lock().unwrap();
+        if running {
+            state.status = Status::Running;
+        } else {
+            state.status = Status::Finished;
+            state.pause = false;
+            state.stop = false;
+            self.shared.pending.store(false, Ordering::Relaxed);
+        }
+        self.shared.changed.notify_all();
+    }
+}
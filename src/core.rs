@@ -1,95 +1,276 @@
 use core::{f32, slice};
 use std::{
+    any::Any,
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    collections::BTreeMap,
     fmt,
-    fs::File,
-    io::{self, Read, Write},
+    io::{self, Cursor, Read},
     marker::PhantomData,
     mem,
     ops::{Add, Range},
-    os::fd::FromRawFd,
-    ptr,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
+use tracing::{debug, info, trace, warn};
+
 use crate::{
-    instruction::Instruction,
+    call::{ArgValue, CallTarget, RetValue},
+    capture::{Capture, CaptureConfig, CapturedOutput},
+    checkpoint::{self, Checkpointer, Snapshot},
+    control::{self, PausedState, RunHandle},
+    csr::{self, CsrView},
+    custom::{CustomError, CustomFn, CustomOpcode, CustomOps, Machine, Outcome},
+    driver::{GuestPipe, RunAsync},
+    events::{Events, Fired},
+    fatal::{self, FatalKind, GuestFatal},
+    fds::{self, FdTable},
+    hooks::{Hook, HookAction, Jump, MemRead, MemWrite, MemWritten, RegWrite, Syscall},
+    hostcall::{HostCtx, HostcallError, Hostcalls, SYSCALL_HOSTCALL},
+    hostio::HostIo,
+    hpm::{HpmCounter, HpmEvent},
+    htif,
+    identity::{self, Uname},
+    inject::{self, FaultSpec, Faults, Target},
+    instruction::{self, Instruction},
+    ioctl::{self, Terminals, TtyMode},
+    irq::{Interrupts, IrqSchedule, IrqStats},
+    jumptable::{self, JumpTable, JumpTables},
+    limits::{self, LimitHits, Limits, ResourceLimits},
     load::{LoadedElf, Segment},
+    mmap::{self, Hugepages, Mapping, SharedImage},
+    mux::{Mux, Stream},
+    native::{Loc, Native, Ty},
+    nondet::{self, Nondeterminism},
+    progress::{Progress, ProgressInterval, ProgressReport},
+    pseudo,
+    region::{Device, MemoryMap, Perms, Region, RegionKind},
+    register::Register,
+    replay::{SyscallLog, SyscallRecord, SyscallTape},
+    sample::{self, CallStack, Sampler, Samples},
+    stat::{self, StatSpoof},
+    stub::StubAction,
+    superblock::{self, Entry, InlineCacheStats, Prediction, Superblocks},
+    syscall,
+    throttle::{Mips, Throttle},
+    timeout::{self, IoLimits, IoTimeouts, TimeoutAction},
+    tracer::{Disassembly, FpState, NoTrace, TraceStep, Tracer},
 };
 
-pub trait IdxType: fmt::Debug + Copy + Add + Eq + Ord {
+/// How wide a guest address is: `u32` for RV32, `u64` for RV64
+pub trait AddressWidth: fmt::Debug + Copy + Add + Eq + Ord {
     fn as_usize(self) -> usize;
 }
 
-impl IdxType for u64 {
+impl AddressWidth for u64 {
     #[inline(always)]
     fn as_usize(self) -> usize {
         self as _
     }
 }
 
-impl IdxType for u32 {
+impl AddressWidth for u32 {
     #[inline(always)]
     fn as_usize(self) -> usize {
         self as _
     }
 }
 
-pub trait MemReader {
-    type Idx: IdxType;
+/// How a single access is made once it's found in the host: what it assumes
+/// of the address's alignment
+pub trait AccessStrategy {
+    /// # Safety
+    /// `ptr` must be valid for a read of `T`, and aligned to it if the
+    /// strategy assumes alignment
+    unsafe fn read<T: Copy>(ptr: *const T) -> T;
+
+    /// # Safety
+    /// `ptr` must be valid for a write of `T`, and aligned to it if the
+    /// strategy assumes alignment
+    unsafe fn write<T: Copy>(ptr: *mut T, val: T);
+}
 
-    // returning 'static is unimaginably unsafe
-    unsafe fn get_buf(data: *mut u8, offset: Self::Idx, len: Self::Idx) -> &'static mut [u8] {
-        let start = data.byte_add(offset.as_usize());
-        slice::from_raw_parts_mut(start, len.as_usize())
+/// Assumes every access is aligned
+pub struct Aligned;
+
+impl AccessStrategy for Aligned {
+    #[inline(always)]
+    unsafe fn read<T: Copy>(ptr: *const T) -> T {
+        unsafe { *ptr }
     }
 
-    unsafe fn read<T: Copy>(data: *const u8, offset: Self::Idx) -> T;
-    unsafe fn write<T: Copy>(data: *mut u8, offset: Self::Idx, val: T);
+    #[inline(always)]
+    unsafe fn write<T: Copy>(ptr: *mut T, val: T) {
+        unsafe { *ptr = val }
+    }
 }
 
-pub struct AlignedMemReader<Idx: IdxType> {
-    _phantom_data: PhantomData<Idx>,
+/// Assumes nothing, making every access as an unaligned one
+pub struct Unaligned;
+
+impl AccessStrategy for Unaligned {
+    #[inline(always)]
+    unsafe fn read<T: Copy>(ptr: *const T) -> T {
+        unsafe { ptr.read_unaligned() }
+    }
+
+    #[inline(always)]
+    unsafe fn write<T: Copy>(ptr: *mut T, val: T) {
+        unsafe { ptr.write_unaligned(val) }
+    }
 }
 
-impl<Idx: IdxType> MemReader for AlignedMemReader<Idx> {
-    type Idx = Idx;
+/// Aligned accesses wherever the address is aligned, falling back to unaligned
+/// ones where it isn't. Mostly aligned guests run at close to the speed of
+/// `Aligned` on hosts where that's faster, without assuming anything
+pub struct Adaptive;
 
+impl AccessStrategy for Adaptive {
     #[inline(always)]
-    unsafe fn read<T: Copy>(data: *const u8, offset: Self::Idx) -> T {
-        unsafe { *data.byte_add(offset.as_usize()).cast::<T>() }
+    unsafe fn read<T: Copy>(ptr: *const T) -> T {
+        if ptr.is_aligned() {
+            unsafe { *ptr }
+        } else {
+            unsafe { ptr.read_unaligned() }
+        }
     }
 
     #[inline(always)]
-    unsafe fn write<T: Copy>(data: *mut u8, offset: Idx, val: T) {
-        unsafe { *data.byte_add(offset.as_usize()).cast::<T>() = val }
+    unsafe fn write<T: Copy>(ptr: *mut T, val: T) {
+        if ptr.is_aligned() {
+            unsafe { *ptr = val }
+        } else {
+            unsafe { ptr.write_unaligned(val) }
+        }
     }
 }
 
-pub struct UnalignedMemReader<Idx: IdxType> {
-    _phantom_data: PhantomData<Idx>,
+/// Where in the host the byte at an offset into guest memory is
+pub trait Backend {
+    /// # Safety
+    /// `data` must be the arena, and `offset` within it
+    unsafe fn locate(data: *mut u8, offset: usize) -> *mut u8;
 }
 
-impl<Idx: IdxType> MemReader for UnalignedMemReader<Idx> {
-    type Idx = Idx;
+/// One contiguous arena, the offset straight into it
+pub struct Flat;
 
+impl Backend for Flat {
     #[inline(always)]
-    unsafe fn read<T: Copy>(data: *const u8, offset: Self::Idx) -> T {
-        unsafe {
-            data.byte_add(offset.as_usize())
-                .cast::<T>()
-                .read_unaligned()
-        }
+    unsafe fn locate(data: *mut u8, offset: usize) -> *mut u8 {
+        unsafe { data.byte_add(offset) }
+    }
+}
+
+/// How guest loads and stores reach the arena: only the single accesses of
+/// the hot path, which the memory has already bounds checked. Everything else
+/// borrows the arena as a slice, for as long as the memory is borrowed.
+/// `MemAccess` puts one together from an address width, access strategy and
+/// backend
+pub trait MemReader {
+    type Idx: AddressWidth;
+
+    /// # Safety
+    /// `data + offset` must be valid for a read of `T`, and aligned to it if the
+    /// reader assumes alignment
+    unsafe fn read<T: Copy>(data: *const u8, offset: Self::Idx) -> T;
+
+    /// # Safety
+    /// `data + offset` must be valid for a write of `T`, and aligned to it if the
+    /// reader assumes alignment
+    unsafe fn write<T: Copy>(data: *mut u8, offset: Self::Idx, val: T);
+}
+
+/// The `MemReader` addressing with `W`, accessing with `S` and finding the
+/// host's bytes with `B`
+pub struct MemAccess<W: AddressWidth, S: AccessStrategy, B: Backend = Flat> {
+    _phantom_data: PhantomData<(W, S, B)>,
+}
+
+impl<W: AddressWidth, S: AccessStrategy, B: Backend> MemReader for MemAccess<W, S, B> {
+    type Idx = W;
+
+    #[inline(always)]
+    unsafe fn read<T: Copy>(data: *const u8, offset: W) -> T {
+        unsafe { S::read(B::locate(data.cast_mut(), offset.as_usize()).cast::<T>()) }
     }
 
     #[inline(always)]
-    unsafe fn write<T: Copy>(data: *mut u8, offset: Idx, val: T) {
-        unsafe {
-            data.byte_add(offset.as_usize())
-                .cast::<T>()
-                .write_unaligned(val)
-        }
+    unsafe fn write<T: Copy>(data: *mut u8, offset: W, val: T) {
+        unsafe { S::write(B::locate(data, offset.as_usize()).cast::<T>(), val) }
     }
 }
 
+pub type AlignedMemReader<Idx> = MemAccess<Idx, Aligned>;
+pub type UnalignedMemReader<Idx> = MemAccess<Idx, Unaligned>;
+pub type AdaptiveMemReader<Idx> = MemAccess<Idx, Adaptive>;
+
+/// A value guest memory holds. The guest is little endian whatever the host
+/// is, so these are kept in memory as their little-endian representation
+trait Scalar: Copy {
+    fn from_le(value: Self) -> Self;
+    fn to_le(self) -> Self;
+    /// The low bytes of `value`, as a device read returns them
+    fn from_u64(value: u64) -> Self;
+    /// Zero extended, as a device is written
+    fn to_u64(self) -> u64;
+}
+
+macro_rules! int_scalar {
+    ($($ty:ty => $unsigned:ty),*) => {$(
+        impl Scalar for $ty {
+            #[inline(always)]
+            fn from_le(value: Self) -> Self {
+                <$ty>::from_le(value)
+            }
+
+            #[inline(always)]
+            fn to_le(self) -> Self {
+                <$ty>::to_le(self)
+            }
+
+            fn from_u64(value: u64) -> Self {
+                value as $unsigned as $ty
+            }
+
+            fn to_u64(self) -> u64 {
+                self as $unsigned as u64
+            }
+        }
+    )*};
+}
+
+int_scalar!(u8 => u8, i8 => u8, u16 => u16, i16 => u16, u32 => u32, i32 => u32, u64 => u64);
+
+macro_rules! float_scalar {
+    ($($ty:ty => $bits:ty),*) => {$(
+        impl Scalar for $ty {
+            #[inline(always)]
+            fn from_le(value: Self) -> Self {
+                <$ty>::from_bits(<$bits>::from_le(value.to_bits()))
+            }
+
+            #[inline(always)]
+            fn to_le(self) -> Self {
+                <$ty>::from_bits(self.to_bits().to_le())
+            }
+
+            fn from_u64(value: u64) -> Self {
+                <$ty>::from_bits(value as $bits)
+            }
+
+            fn to_u64(self) -> u64 {
+                self.to_bits() as u64
+            }
+        }
+    )*};
+}
+
+float_scalar!(f32 => u32, f64 => u64);
+
 struct Regfile {
     registers: [i32; 32],
 }
@@ -120,45 +301,18 @@ impl Regfile {
 
 #[derive(Clone, Copy)]
 union FpReg {
-    single: f32,
     double: f64,
     u32: u32,
     u64: u64,
 }
 
-#[repr(u8)]
-#[derive(Debug, Clone, Copy, Default)]
-enum RoundingMode {
-    #[default]
-    RNE = 0b000,
-    RTZ = 0b001,
-    RDN = 0b010,
-    RUP = 0b011,
-    RMM = 0b100,
-    // Reserved = 0b101,
-    // Reserved = 0b110,
-    DYN = 0b111,
-}
-
-impl TryFrom<i32> for RoundingMode {
-    type Error = &'static str;
-
-    fn try_from(value: i32) -> Result<Self, Self::Error> {
-        match value {
-            0b000 => Ok(Self::RNE),
-            0b001 => Ok(Self::RTZ),
-            0b010 => Ok(Self::RDN),
-            0b011 => Ok(Self::RUP),
-            0b100 => Ok(Self::RMM),
-            0b111 => Ok(Self::DYN),
-            _ => Err("bad rounding mode"),
-        }
-    }
-}
-
+/// The fp control and status register, which `fflags`, `frm` and `fcsr` are
+/// all views of
 #[derive(Debug, Clone, Copy, Default)]
 struct Fcsr {
-    pub rm: RoundingMode,
+    /// The dynamic rounding mode; reserved values can be written, they only
+    /// fault once used
+    pub frm: u8,
 
     pub nv: bool,
     pub dz: bool,
@@ -167,6 +321,39 @@ struct Fcsr {
     pub nx: bool,
 }
 
+impl Fcsr {
+    fn fflags(&self) -> u32 {
+        [self.nx, self.uf, self.of, self.dz, self.nv]
+            .iter()
+            .enumerate()
+            .map(|(bit, &set)| (set as u32) << bit)
+            .sum()
+    }
+
+    fn set_fflags(&mut self, value: u32) {
+        self.nx = value & 0b00001 != 0;
+        self.uf = value & 0b00010 != 0;
+        self.of = value & 0b00100 != 0;
+        self.dz = value & 0b01000 != 0;
+        self.nv = value & 0b10000 != 0;
+    }
+
+    fn bits(&self) -> u32 {
+        ((self.frm as u32) << 5) | self.fflags()
+    }
+
+    /// The rounding mode an instruction's `rm` field means, `None` if reserved
+    fn rounding_mode(&self, rm: u8) -> Option<u8> {
+        let rm = if rm == 0b111 { self.frm } else { rm };
+        (rm <= 0b100).then_some(rm)
+    }
+
+    fn set_bits(&mut self, value: u32) {
+        self.frm = ((value >> 5) & 0b111) as u8;
+        self.set_fflags(value);
+    }
+}
+
 struct FpRegfile {
     registers: [FpReg; 32],
     fcsr: Fcsr,
@@ -185,9 +372,20 @@ impl FpRegfile {
         unsafe { self.registers.get_unchecked(idx as usize).u32 }
     }
 
+    #[inline(always)]
+    pub fn read_u64(&self, idx: u8) -> u64 {
+        unsafe { self.registers.get_unchecked(idx as usize).u64 }
+    }
+
+    // a single that isn't NaN-boxed reads as the canonical NaN
     #[inline(always)]
     pub fn read_single(&self, idx: u8) -> f32 {
-        unsafe { self.registers.get_unchecked(idx as usize).single }
+        let val = self.read_u64(idx);
+        if val >> 32 == u32::MAX as u64 {
+            f32::from_bits(val as u32)
+        } else {
+            f32::NAN
+        }
     }
 
     #[inline(always)]
@@ -197,9 +395,7 @@ impl FpRegfile {
 
     #[inline(always)]
     pub fn write_single(&mut self, idx: u8, value: f32) {
-        unsafe {
-            self.registers.get_unchecked_mut(idx as usize).single = value;
-        }
+        self.write_u32(idx, value.to_bits());
     }
 
     #[inline(always)]
@@ -209,105 +405,172 @@ impl FpRegfile {
         }
     }
 
+    // NaN-boxed, with the upper half all ones
     #[inline(always)]
     pub fn write_u32(&mut self, idx: u8, value: u32) {
         unsafe {
-            self.registers.get_unchecked_mut(idx as usize).u32 = value;
-        }
-    }
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Register {
-    Zero,     // x0
-    Ra,       // x1
-    Sp,       // x2
-    Gp,       // x3
-    Tp,       // x4
-    T(usize), // t registers: t0-t2 map to x5-x7, t3-t6 map to x28-x31
-    S(usize), // s registers: s0 maps to x8, s1 to x9, s2-s11 to x18-x27
-    A(usize), // a registers: a0-a7 map to x10-x17
-
-    Fa(usize),
-}
-
-impl Register {
-    pub fn to_idx(self) -> u8 {
-        match self {
-            Register::Zero => 0,
-            Register::Ra => 1,
-            Register::Sp => 2,
-            Register::Gp => 3,
-            Register::Tp => 4,
-            Register::T(i) => match i {
-                0..=2 => i as u8 + 5,        // t0 => 5, t1 => 6, t2 => 7
-                3..=6 => (i as u8 - 3) + 28, // t3 => 28, ... t6 => 31
-                _ => unreachable!("invalid t register index"),
-            },
-            Register::S(i) => match i {
-                0 => 8,                 // s0/fp => 8
-                1 => 9,                 // s1 => 9
-                2..=11 => i as u8 + 16, // s2 => 18, ... s11 => 27
-                _ => unreachable!("invalid s register index"),
-            },
-            Register::A(i) => {
-                if i < 8 {
-                    i as u8 + 10 // a0 => 10, ... a7 => 17
-                } else {
-                    unreachable!("invalid a register index")
-                }
-            }
-
-            Register::Fa(i) => i as u8 + 10,
+            self.registers.get_unchecked_mut(idx as usize).u64 =
+                0xffff_ffff_0000_0000 | value as u64;
         }
     }
 }
 
+// in order, with what every load and store checks first, as with `Core32`
+#[allow(dead_code)]
+#[repr(C)]
 pub struct Memory<Reader: MemReader> {
-    data_owner: Box<[u8]>,
     data: *mut u8,
+    // loads below `load_end`, and stores below it outside the watched range,
+    // are to plain RAM (or ROM, for loads), so skip the memory map
+    load_end: usize,
+    // stores starting in `watch_start..watch_start + watch_len` could touch
+    // ROM or decoded code, so go the slow way
+    watch_start: usize,
+    watch_len: usize,
+    // where stores are watched for `htif` commands, `usize::MAX` unless it's on
+    tohost: usize,
+
+    // committed lazily, see `mmap`
+    mapping: Mapping,
     size: usize,
 
+    map: MemoryMap,
+    devices: Vec<RefCell<Box<dyn Device>>>,
+    // where the core's instructions were decoded from, see `Core32::code_generation`
+    code: Range<u32>,
+    // stores to ROM are allowed, see `Core32::set_writable_text`
+    writable_text: bool,
+    // set by any access to a device, see `Core32::take_device_access`
+    device_access: Cell<bool>,
+    // kept alive so later runs of the same program share it
+    rom_image: Option<Arc<SharedImage>>,
+
     elf: LoadedElf,
 
     _phantom_data: PhantomData<Reader>,
 }
 
-#[repr(C, align(16))]
-struct Align16(u8);
+// a guest store the core has to act on
+enum StoreTrap {
+    // to ROM, which stops the run
+    Rom,
+    // to decoded code, which the core re-decodes
+    Code,
+    // the high word of `tohost`, see `htif`
+    Htif,
+    // to a device that ended the run with this exit code
+    Exit(i32),
+    // to a device, which may have scheduled an event
+    Device,
+    // past the end of memory, which stops the run
+    Fault,
+}
 
 impl<Reader: MemReader> Memory<Reader> {
-    fn new(elf: LoadedElf, size: usize) -> Self {
-        let mut data_owner = vec![0xBEu8; size].into_boxed_slice();
+    fn new(
+        elf: LoadedElf,
+        size: usize,
+        hugepages: Hugepages,
+        rom: Option<(u32, u64)>,
+        code: Range<u32>,
+    ) -> Self {
+        let mapping = Mapping::new(size, hugepages)
+            .unwrap_or_else(|err| panic!("failed to map {size} bytes of guest memory: {err}"));
 
-        let data;
-        let size;
+        // page aligned, so as aligned as any access
+        let data = mapping.as_ptr();
+        let size = mapping.len();
         unsafe {
-            let (_pref, aligned, _suf) = data_owner.align_to_mut::<Align16>();
-
-            data = aligned.as_mut_ptr() as *mut u8;
-            size = std::mem::size_of_val(aligned);
-
             for seg in elf.segments.iter() {
+                if let Some(file) = &seg.file {
+                    assert!(seg.vaddr + file.len < size as u64);
+                    file.load_into(&mapping, seg.vaddr as usize)
+                        .unwrap_or_else(|err| {
+                            panic!("failed to map the segment at {:#x}: {err}", seg.vaddr)
+                        });
+                    continue;
+                }
+
                 let dest = data.byte_add(seg.vaddr as usize);
                 assert!(seg.vaddr as usize + seg.data.len() < size);
                 dest.copy_from(seg.data.as_ptr(), seg.data.len());
             }
         }
 
-        Self {
+        // the pages holding the rom, which may also hold the ends of other
+        // segments, but as they're copy-on-write that's fine
+        let rom_image = rom.and_then(|(start, len)| {
+            let page = mmap::page_size();
+            let pages = start as usize / page * page
+                ..(start as usize + len as usize).next_multiple_of(page);
+            if pages.end > size.next_multiple_of(page) {
+                return None;
+            }
+
+            let bytes = unsafe { slice::from_raw_parts(data.add(pages.start), pages.len()) };
+            let image = SharedImage::get(bytes)
+                .and_then(|image| image.map_into(&mapping, pages.start).map(|_| image));
+            // still fine without it, just not shared
+            image.ok()
+        });
+
+        let map = MemoryMap::new(size, rom, &elf.segments);
+        let mut memory = Self {
             elf,
-            data_owner,
+            mapping,
             data,
             size,
+            map,
+            devices: Vec::new(),
+            load_end: size,
+            watch_start: 0,
+            watch_len: 0,
+            code,
+            writable_text: false,
+            device_access: Cell::new(false),
+            tohost: usize::MAX,
+            rom_image,
             _phantom_data: PhantomData,
+        };
+        memory.update_fast_path();
+        memory
+    }
+
+    fn update_fast_path(&mut self) {
+        self.load_end = self
+            .map
+            .first_device()
+            .map_or(self.size, |start| self.size.min(start as usize));
+
+        let (mut start, mut end) = (self.code.start as u64, self.code.end as u64);
+        if let Some(rom) = self.map.rom() {
+            start = start.min(rom.start as u64);
+            end = end.max(rom.end());
         }
+        // an 8 byte store can start 7 bytes before either
+        self.watch_start = start.saturating_sub(7) as usize;
+        self.watch_len = (end as usize).saturating_sub(self.watch_start);
+    }
+
+    fn add_device(&mut self, start: u32, len: u64, device: Box<dyn Device>) -> Result<(), String> {
+        self.map.add_device(start, len, self.devices.len())?;
+        self.devices.push(RefCell::new(device));
+        self.update_fast_path();
+        Ok(())
     }
 
     fn size(&self) -> usize {
         self.size
     }
 
+    fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.data, self.size) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.data, self.size) }
+    }
+
     // fn get_data(&self, idx: u32) -> (&[AlignedU8], u32) {
     //     match self.elf.find_segment(idx as u64) {
     //         Some(_) => panic!(""),
@@ -317,80 +580,252 @@ impl<Reader: MemReader> Memory<Reader> {
     // }
 
     fn get_buf(&mut self, addr: Reader::Idx, len: Reader::Idx) -> &mut [u8] {
-        assert!(
-            addr.as_usize() + len.as_usize() <= self.size,
-            "{addr:?} {len:?}"
-        );
+        let start = addr.as_usize();
+        self.as_mut_slice()
+            .get_mut(start..start + len.as_usize())
+            .unwrap_or_else(|| panic!("{addr:?} {len:?}"))
+    }
 
-        // let (data, offset) = self.get_data(idx);
-        let data = self.data;
-        unsafe { Reader::get_buf(data, addr, len) }
+    /// The NUL-terminated string at `addr`, without the NUL, or up to the end
+    /// of memory if there isn't one
+    fn c_str(&self, addr: u32) -> &[u8] {
+        let rest = self.as_slice().get(addr as usize..).unwrap_or_default();
+        let len = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        &rest[..len]
     }
 
-    fn load<T: Copy>(&self, addr: Reader::Idx) -> T {
-        assert!(
-            addr.as_usize() + mem::size_of::<T>() <= self.size,
-            "addr={addr:?}, size={}, len={}",
-            mem::size_of::<T>(),
-            self.size
-        );
+    // `None` past the end of memory
+    #[inline(always)]
+    fn load<T: Scalar>(&self, addr: Reader::Idx) -> Option<T> {
+        if addr.as_usize() + mem::size_of::<T>() > self.load_end {
+            return self.load_mapped(addr);
+        }
 
-        // let (data, offset) = self.get_data(idx);
-        let data = self.data;
-        unsafe { Reader::read(data, addr) }
+        // in bounds, as `load_end` is at most the size
+        Some(T::from_le(unsafe { Reader::read(self.data, addr) }))
     }
 
-    fn store<T: Copy>(&self, addr: Reader::Idx, val: T) {
-        assert!(
-            addr.as_usize() + mem::size_of::<T>() <= self.size,
-            "addr={addr:?}, size={}, len={}",
-            mem::size_of::<T>(),
-            self.size
-        );
+    #[inline(always)]
+    fn store<T: Scalar>(&mut self, addr: Reader::Idx, val: T) -> Result<(), StoreTrap> {
+        if addr.as_usize().wrapping_sub(self.watch_start) < self.watch_len
+            || addr.as_usize() + mem::size_of::<T>() > self.load_end
+            || addr.as_usize() ^ self.tohost < 8
+        {
+            return self.store_mapped(addr, val);
+        }
 
-        // let (data, offset) = self.get_data(idx);
-        let data = self.data;
-        unsafe { Reader::write(data, addr, val) }
+        unsafe { Reader::write(self.data, addr, val.to_le()) }
+        Ok(())
     }
 
-    fn memset(&mut self, idx: i32, value: i32, length: i32) {
-        unsafe {
-            ptr::write_bytes(
-                self.data.byte_add(idx as usize),
-                value as u8,
-                length as usize,
-            );
+    #[cold]
+    #[inline(never)]
+    fn load_mapped<T: Scalar>(&self, addr: Reader::Idx) -> Option<T> {
+        let len = mem::size_of::<T>();
+        if let Some((offset, idx)) = self.device(addr) {
+            self.device_access.set(true);
+            let value = self.devices[idx].borrow_mut().read(offset, len as u32);
+            trace!(target: "device", "device {idx} read {len} bytes at {offset:#x}: {value:#x}");
+            return Some(T::from_u64(value));
+        }
+
+        if addr.as_usize() + len > self.size {
+            return None;
         }
+        Some(T::from_le(unsafe { Reader::read(self.data, addr) }))
     }
 
-    fn memcpy(&mut self, dest: i32, src: i32, length: i32) {
-        unsafe {
-            ptr::copy_nonoverlapping(
-                self.data.byte_add(src as usize),
-                self.data.byte_add(dest as usize),
-                length as usize,
-            );
+    #[cold]
+    #[inline(never)]
+    fn store_mapped<T: Scalar>(&mut self, addr: Reader::Idx, val: T) -> Result<(), StoreTrap> {
+        let len = mem::size_of::<T>();
+        if let Some((offset, idx)) = self.device(addr) {
+            self.device_access.set(true);
+            let value = val.to_u64();
+            trace!(target: "device", "device {idx} write {len} bytes at {offset:#x}: {value:#x}");
+            let mut device = self.devices[idx].borrow_mut();
+            device.write(offset, len as u32, value);
+            if let Some(code) = device.exit_code() {
+                return Err(StoreTrap::Exit(code));
+            }
+            return Err(StoreTrap::Device);
         }
+
+        if addr.as_usize() + len > self.size {
+            return Err(StoreTrap::Fault);
+        }
+        let start = addr.as_usize() as u64;
+        let end = start + len as u64;
+        if !self.writable_text
+            && self
+                .map
+                .rom()
+                .is_some_and(|rom| start < rom.end() && end > rom.start as u64)
+        {
+            return Err(StoreTrap::Rom);
+        }
+
+        unsafe { Reader::write(self.data, addr, val.to_le()) }
+
+        if addr.as_usize() ^ self.tohost < 8 && addr.as_usize() + len > self.tohost + 4 {
+            return Err(StoreTrap::Htif);
+        }
+        if start < self.code.end as u64 && end > self.code.start as u64 {
+            return Err(StoreTrap::Code);
+        }
+        Ok(())
     }
 
-    fn memmove(&mut self, dest: i32, src: i32, length: i32) {
-        unsafe {
-            ptr::copy(
-                self.data.byte_add(src as usize),
-                self.data.byte_add(dest as usize),
-                length as usize,
-            );
+    // the offset into, and index of, the device `addr` is in, if any
+    fn device(&self, addr: Reader::Idx) -> Option<(u32, usize)> {
+        let addr = addr.as_usize() as u32;
+        match self.map.find(addr)? {
+            Region {
+                start,
+                kind: RegionKind::Device(idx),
+                ..
+            } => Some((addr - start, *idx)),
+            _ => None,
         }
     }
+
+    // whether the `len` bytes at `addr` are all in memory
+    fn spans(&self, addr: i32, len: i32) -> bool {
+        addr as u32 as usize + len as u32 as usize <= self.size
+    }
+
+    fn memset(&mut self, idx: i32, value: i32, length: i32) {
+        let start = idx as u32 as usize;
+        self.as_mut_slice()[start..start + length as u32 as usize].fill(value as u8);
+    }
+
+    // as `memmove`, as a guest passing overlapping buffers mustn't corrupt the
+    // host
+    fn memcpy(&mut self, dest: i32, src: i32, length: i32) {
+        self.memmove(dest, src, length);
+    }
+
+    fn memmove(&mut self, dest: i32, src: i32, length: i32) {
+        let src = src as u32 as usize;
+        self.as_mut_slice()
+            .copy_within(src..src + length as u32 as usize, dest as u32 as usize);
+    }
+}
+
+// a function serviced by `add_native`, with where its arguments and result
+// are worked out once
+struct NativeCall {
+    addr: u32,
+    native: &'static Native,
+    args: Vec<Loc>,
+    ret: Loc,
+    calls: u64,
 }
 
+// laid out in order, so what every instruction touches is together at the
+// front, in as few cache lines as it can be
+#[repr(C)]
 pub struct Core32<Reader: MemReader> {
     pc: u32,
-    text: Segment,
-    memory: Memory<Reader>,
-    fp_regfile: FpRegfile,
+    // set when anything needs the slow, instrumented step path
+    instrumented: bool,
+    instret: u64,
+    // the instret to next report progress, poll `control` or write a
+    // checkpoint at, `u64::MAX` unless any are on
+    next_check: u64,
+    // where `ins_cache` starts, `text.vaddr`
+    code_base: u32,
+    // decoded from `text`'s addresses in guest memory a block at a time, as
+    // it first runs, and kept up to date with it, see `code_generation`
+    ins_cache: Vec<Instruction>,
     gp_regfile: Regfile,
+    fp_regfile: FpRegfile,
+    memory: Memory<Reader>,
+
+    text: Segment,
+    // the program's GNU build id, if it has one
+    build_id: Option<Vec<u8>>,
+    code_generation: u64,
+    decode_stats: DecodeStats,
+    // found decoding `text`
+    jump_tables: JumpTables,
+    // hot paths, see `superblock`, unless they're off
+    superblocks: Option<Superblocks>,
+    // runs are traced with `Disassembly`, see `run`, showing the fp
+    // registers if `debug_fp`
     debug: bool,
+    debug_fp: bool,
+
+    hooks: Vec<Box<dyn Hook>>,
+    // see `on_exit`, and whether they've been called this run
+    exit_callbacks: Vec<ExitCallback>,
+    exited: bool,
+
+    // jumping here ends the run, see `synthesize_call`
+    return_address: u32,
+
+    hostcalls: Hostcalls,
+    custom: CustomOps,
+    // the last chance for instructions that would trap, see `set_illegal_handler`
+    illegal_handler: Option<CustomFn>,
+
+    fatal_fns: Vec<(u32, FatalKind)>,
+    // functions replaced by `add_stub`
+    stubs: Vec<(u32, StubAction)>,
+    // functions serviced by `add_native`
+    natives: Vec<NativeCall>,
+    // the last recognisable fatal message written to stderr, see `fatal::scan_stderr`
+    stderr_fatal: Option<(FatalKind, String)>,
+    fatal: Option<GuestFatal>,
+
+    syscall_counts: BTreeMap<i32, u64>,
+    progress: Option<Progress>,
+    sampler: Option<Sampler>,
+    control: Option<RunHandle>,
+    checkpoint: Option<Checkpointer>,
+    instruction_limit: Option<u64>,
+    throttle: Option<Throttle>,
+    // faults still to inject, see `inject`
+    faults: Option<Faults>,
+    // events due on the virtual clock, see `events`
+    events: Events,
+    // host fds devices are waiting on, see `hostio`
+    host_io: Option<HostIo>,
+    // the pending interrupts and trap CSRs, once enabled
+    interrupts: Option<Interrupts>,
+    io_limits: Option<IoLimits>,
+    // syscalls being recorded or replayed, see `replay`
+    syscall_log: Option<SyscallLog>,
+    // where `htif` answers commands, if it's on and the guest has one
+    fromhost: Option<u32>,
+    fds: FdTable,
+    limits: Limits,
+    // the program break, which starts page aligned past the last segment
+    heap_start: u32,
+    brk: u32,
+
+    // the address of the last `lr.w`, until an `sc.w`
+    reservation: Option<u32>,
+
+    // where the guest's clocks and random bytes come from
+    nondet: Nondeterminism,
+    // what `fstat` and `newfstatat` say regardless of the file
+    stat_spoof: StatSpoof,
+    // what `uname` says, and whether `/proc` has the guest's files in it
+    uname: Uname,
+    fake_proc: bool,
+
+    // the mapped `hpmcounter`s, by number
+    hpm: Vec<(u8, HpmCounter)>,
+
+    // guest stdin, and captured stdout/stderr, instead of the host's
+    stdin: Option<Stdin>,
+    captured: Option<Capture>,
+    // what's written to stdout and stderr, and the htif console, goes through
+    // this if it isn't captured, see `mux`
+    mux: Option<Mux>,
+    // what its fds are as terminals, see `ioctl`
+    terminals: Terminals,
 
     pub wk_memmove: u32,
     pub wk_memcpy: u32,
@@ -399,33 +834,341 @@ pub struct Core32<Reader: MemReader> {
     pub wk_sin: u32,
 }
 
+enum Stdin {
+    Buffer(Cursor<Vec<u8>>),
+    Pipe(GuestPipe),
+}
+
+/// A callback given to `Core32::on_exit`
+pub type ExitCallback = Box<dyn FnMut(&RunInfo)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The guest exited (or jumped to itself)
+    Exited,
+    /// A hook requested a break before or after the instruction at `pc`
+    Breakpoint { pc: u32 },
+    /// The function entered through `synthesize_call` returned
+    Returned,
+    /// The guest aborted, failed an assertion or panicked, see `Core32::fatal`
+    Fatal(FatalKind),
+    /// A hang detector gave up on the guest, `pc` being the hottest recent instruction
+    PossibleHang { pc: u32 },
+    /// `inst` at `pc` isn't a valid encoding (or isn't implemented)
+    IllegalInstruction { pc: u32, inst: u32 },
+    /// A `RunHandle` stopped the core before the instruction at `pc`
+    Cancelled { pc: u32 },
+    /// The guest read from an empty `GuestPipe` at `pc`; running again retries
+    /// the read
+    WouldBlock { pc: u32 },
+    /// The `ret` at `pc` went to `actual` rather than the caller, see `cfi`
+    CfiViolation { pc: u32, expected: u32, actual: u32 },
+    /// The store at `pc` was to `addr`, which is in ROM, see `region`
+    RomWrite { pc: u32, addr: u32 },
+    /// The `read` or `write` of host file `fd` at `pc` timed out, see
+    /// `timeout`; running again retries it
+    IoTimeout { pc: u32, fd: i32 },
+    /// The core reached the limit set with `Core32::set_instruction_limit`
+    /// before the instruction at `pc`
+    InstructionLimit { pc: u32 },
+    /// The syscall at `pc` wasn't the `index`th of the recording being
+    /// replayed, see `replay`
+    ReplayMismatch { pc: u32, index: usize },
+    /// riscy panicked running the instruction at `pc`, a bug in riscy rather
+    /// than the guest. `RunInfo::fault` has what it panicked with. The core is
+    /// left however the instruction left it, so shouldn't be run again. Only
+    /// where panics unwind: under `panic = "abort"`, as in riscy's release
+    /// profile, the process aborts with the panic message instead
+    Panicked { pc: u32 },
+    /// The load at `pc` was from `addr`, past the end of memory
+    LoadFault { pc: u32, addr: u32 },
+    /// The store at `pc` was to `addr`, past the end of memory
+    StoreFault { pc: u32, addr: u32 },
+    /// The core jumped to `pc`, which isn't an instruction of the program's
+    /// code
+    FetchFault { pc: u32 },
+    /// The guest ran the `ebreak` at `pc`, with no debugger to trap to
+    Ebreak { pc: u32 },
+    /// The `index`th `--invariant` didn't hold at `pc`, see `invariant`
+    InvariantViolated { pc: u32, index: usize },
+}
+
+impl StopReason {
+    /// A short name for reports, like `exited` or `illegal_instruction`
+    pub fn name(&self) -> &'static str {
+        match self {
+            StopReason::Exited => "exited",
+            StopReason::Breakpoint { .. } => "breakpoint",
+            StopReason::Returned => "returned",
+            StopReason::Fatal(_) => "fatal",
+            StopReason::PossibleHang { .. } => "hang",
+            StopReason::IllegalInstruction { .. } => "illegal_instruction",
+            StopReason::Cancelled { .. } => "cancelled",
+            StopReason::WouldBlock { .. } => "blocked",
+            StopReason::CfiViolation { .. } => "cfi_violation",
+            StopReason::RomWrite { .. } => "rom_write",
+            StopReason::IoTimeout { .. } => "io_timeout",
+            StopReason::InstructionLimit { .. } => "instruction_limit",
+            StopReason::ReplayMismatch { .. } => "replay_mismatch",
+            StopReason::Panicked { .. } => "panicked",
+            StopReason::LoadFault { .. } => "load_fault",
+            StopReason::StoreFault { .. } => "store_fault",
+            StopReason::FetchFault { .. } => "fetch_fault",
+            StopReason::Ebreak { .. } => "ebreak",
+            StopReason::InvariantViolated { .. } => "invariant_violated",
+        }
+    }
+
+    /// Whether the guest is done, rather than stopped somewhere it can be run
+    /// on from, as at a breakpoint or a read that would block
+    pub fn is_exit(&self) -> bool {
+        !matches!(
+            self,
+            StopReason::Breakpoint { .. }
+                | StopReason::WouldBlock { .. }
+                | StopReason::IoTimeout { .. }
+        )
+    }
+
+    /// Where the run stopped, if the reason says
+    pub fn pc(&self) -> Option<u32> {
+        match *self {
+            StopReason::Breakpoint { pc }
+            | StopReason::PossibleHang { pc }
+            | StopReason::IllegalInstruction { pc, .. }
+            | StopReason::Cancelled { pc }
+            | StopReason::WouldBlock { pc }
+            | StopReason::CfiViolation { pc, .. }
+            | StopReason::RomWrite { pc, .. }
+            | StopReason::IoTimeout { pc, .. }
+            | StopReason::InstructionLimit { pc }
+            | StopReason::ReplayMismatch { pc, .. }
+            | StopReason::Panicked { pc }
+            | StopReason::LoadFault { pc, .. }
+            | StopReason::StoreFault { pc, .. }
+            | StopReason::FetchFault { pc }
+            | StopReason::Ebreak { pc }
+            | StopReason::InvariantViolated { pc, .. } => Some(pc),
+            StopReason::Exited | StopReason::Returned | StopReason::Fatal(_) => None,
+        }
+    }
+}
+
+/// The work done decoding the program's code, see `Core32::decode_stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeStats {
+    pub blocks: u64,
+    pub instructions: u64,
+    /// Jump tables found, whose cases' blocks were decoded with the one
+    /// switching through them, see `jumptable`
+    pub jump_tables: u64,
+    pub time: Duration,
+}
+
+// how `Core32::run_superblock` finished
+enum SuperblockExit {
+    Stop(RunInfo),
+    /// Early, or without starting
+    Left,
+    /// After its last instruction
+    End,
+}
+
+#[derive(Debug, Clone)]
 pub struct RunInfo {
     pub return_code: i32,
+    pub reason: StopReason,
+    /// What the guest was refused by its `ResourceLimits`
+    pub limit_hits: LimitHits,
+    /// What the guest wrote to stdout and stderr, if captured, see
+    /// `Core32::capture_output`
+    pub output: Option<CapturedOutput>,
+    /// The instruction that stopped the run, for the stops an instruction
+    /// makes, like an illegal instruction or a store to ROM
+    pub fault: Option<Fault>,
+}
+
+/// An instruction a run stopped at, as it was run rather than as memory holds
+/// it now
+#[derive(Debug, Clone)]
+pub struct Fault {
+    pub pc: u32,
+    /// Its encoding
+    pub raw: u32,
+    pub instr: Instruction,
+    /// Its disassembly, with symbols
+    pub disassembly: String,
+    /// What riscy panicked with, for `StopReason::Panicked`
+    pub panic: Option<String>,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#x}: {:08x}  {}", self.pc, self.raw, self.disassembly)?;
+        if let Some(panic) = &self.panic {
+            write!(f, " ({panic})")?;
+        }
+        Ok(())
+    }
 }
 
-const SYSCALL_EXIT: i32 = 93;
-// const SYSCALL_NEWFSTAT: i32 = 80;
-const SYSCALL_WRITE: i32 = 64;
-const SYSCALL_READ: i32 = 63;
-const SYSCALL_BRK: i32 = 214;
+// unaligned, so it can never be a real jump target before `synthesize_call`
+// replaces it
+const NO_RETURN_ADDRESS: u32 = u32::MAX;
+const RETURN_SENTINEL: u32 = 0xffff_fff0;
+
+const EFAULT: i32 = 14;
+
+pub(crate) const SYSCALL_EXIT: i32 = 93;
+pub(crate) const SYSCALL_GETCWD: i32 = 17;
+pub(crate) const SYSCALL_IOCTL: i32 = 29;
+pub(crate) const SYSCALL_MKDIRAT: i32 = 34;
+pub(crate) const SYSCALL_RENAMEAT: i32 = 38;
+pub(crate) const SYSCALL_CHDIR: i32 = 49;
+pub(crate) const SYSCALL_GETDENTS64: i32 = 61;
+pub(crate) const SYSCALL_NEWFSTATAT: i32 = 79;
+pub(crate) const SYSCALL_FSTAT: i32 = 80;
+pub(crate) const SYSCALL_WRITE: i32 = 64;
+pub(crate) const SYSCALL_READ: i32 = 63;
+pub(crate) const SYSCALL_OPENAT: i32 = 56;
+pub(crate) const SYSCALL_CLOSE: i32 = 57;
+pub(crate) const SYSCALL_BRK: i32 = 214;
+pub(crate) const SYSCALL_CLOCK_GETTIME: i32 = 113;
+pub(crate) const SYSCALL_UNAME: i32 = 160;
+pub(crate) const SYSCALL_GETRUSAGE: i32 = 165;
+pub(crate) const SYSCALL_GETTIMEOFDAY: i32 = 169;
+pub(crate) const SYSCALL_SYSINFO: i32 = 179;
+pub(crate) const SYSCALL_PRLIMIT64: i32 = 261;
+pub(crate) const SYSCALL_RENAMEAT2: i32 = 276;
+pub(crate) const SYSCALL_GETRANDOM: i32 = 278;
+pub(crate) const SYSCALL_CLOCK_GETTIME64: i32 = 403;
+
+const CLOCK_REALTIME: i32 = 0;
+// the part of a `struct timeval`/`struct timespec` that's written, see `write_timeval`
+const TIMEVAL_SIZE: u32 = 12;
+
+// what `ins_cache` holds until the block is first run: a `nop`'s encoding,
+// which decodes as an `addi`, so is never really `Unknown`
+const UNDECODED: u32 = 0x13;
+// how many instructions are decoded at once, see `decode_block`
+const DECODE_BLOCK: usize = 64;
 
 enum ExecResult {
     Continue,
     Jump(u32),
     Call(u32),
     Exit,
+    IllegalInstruction,
+    WouldBlock,
+    RomWrite(u32),
+    // a load from or store to this address past the end of memory
+    LoadFault(u32),
+    StoreFault(u32),
+    Ebreak,
+    // a store to this address overwrote decoded code
+    CodeWrite(u32),
+    IoTimeout(i32),
+    // the index of the syscall that didn't match, see `replay`
+    ReplayMismatch(usize),
+    // a command was written to `tohost`, see `htif`
+    Htif,
+    // a store to a device, which carries on once any event it scheduled is
+    // queued
+    DeviceWrite,
+    // a device ended the run with this exit code
+    DeviceExit(i32),
 }
 
 impl<Reader: MemReader<Idx = u32>> Core32<Reader> {
     pub fn new(elf: LoadedElf, entrypoint: Option<u64>, size: usize, debug: bool) -> Self {
-        let (text, _start, pc_offset) = elf
+        Self::with_hugepages(elf, entrypoint, size, debug, Hugepages::Off)
+    }
+
+    /// Like `new`, with the guest's memory mapped as `hugepages` says
+    pub fn with_hugepages(
+        elf: LoadedElf,
+        entrypoint: Option<u64>,
+        size: usize,
+        debug: bool,
+        hugepages: Hugepages,
+    ) -> Self {
+        let (entry, _start, pc_offset) = elf
             .find_segment(entrypoint.unwrap_or(elf.entrypoint))
             .expect("entrypoint not found!");
+        let rom = (entry.vaddr as u32, entry.size);
+        let text = elf.text(entry);
+        let code = text.vaddr as u32..(text.vaddr + text.data.len() as u64 / 4 * 4) as u32;
 
-        Self {
+        let heap_start = elf
+            .segments
+            .iter()
+            .map(|seg| seg.vaddr + seg.size)
+            .max()
+            .unwrap_or(0)
+            .next_multiple_of(mmap::page_size() as u64) as u32;
+
+        // decoded as it runs, see `decode_block`
+        let ins_cache = vec![Instruction::Unknown(UNDECODED); text.data.len() / 4];
+
+        let mut core = Self {
             debug,
-            pc: (text.vaddr + pc_offset as u64) as u32,
+            debug_fp: false,
+            instrumented: false,
+            hooks: Vec::new(),
+            exit_callbacks: Vec::new(),
+            exited: false,
+            return_address: NO_RETURN_ADDRESS,
+            hostcalls: Hostcalls::new(),
+            custom: CustomOps::new(),
+            illegal_handler: None,
+            fatal_fns: elf.fatal_fns.clone(),
+            stubs: Vec::new(),
+            natives: Vec::new(),
+            stderr_fatal: None,
+            fatal: None,
+            instret: 0,
+            syscall_counts: BTreeMap::new(),
+            progress: None,
+            sampler: None,
+            control: None,
+            checkpoint: None,
+            instruction_limit: None,
+            throttle: None,
+            faults: None,
+            events: Events::default(),
+            host_io: None,
+            interrupts: None,
+            io_limits: None,
+            syscall_log: None,
+            fromhost: None,
+            fds: FdTable::new(),
+            limits: Limits::new(
+                ResourceLimits::default(),
+                size as u64,
+                (size as u64).saturating_sub(heap_start as u64),
+            ),
+            heap_start,
+            brk: heap_start,
+            next_check: u64::MAX,
+            reservation: None,
+            nondet: Nondeterminism::host(),
+            stat_spoof: StatSpoof::default(),
+            uname: Uname::default(),
+            fake_proc: false,
+            hpm: Vec::new(),
+            stdin: None,
+            captured: None,
+            mux: None,
+            terminals: Terminals::new(TtyMode::Host),
+            pc: (entry.vaddr + pc_offset as u64) as u32,
+            code_base: text.vaddr as u32,
             text: text.clone(),
+            build_id: elf.build_id.clone(),
+            ins_cache,
+            code_generation: 0,
+            decode_stats: DecodeStats::default(),
+            jump_tables: JumpTables::default(),
+            superblocks: Some(Superblocks::new(text.data.len() / 4)),
             fp_regfile: FpRegfile::new(),
             gp_regfile: Regfile::new(),
 
@@ -435,8 +1178,13 @@ impl<Reader: MemReader<Idx = u32>> Core32<Reader> {
             wk_cos: elf.wk_cos,
             wk_sin: elf.wk_sin,
 
-            memory: Memory::new(elf, size),
-        }
+            memory: Memory::new(elf, size, hugepages, Some(rom), code),
+        };
+
+        let sp = (core.memory.size() as i32 - 128) & !0xF;
+        core.write(Register::Sp, sp);
+
+        core
     }
 
     pub fn read(&self, reg: Register) -> i32 {
@@ -447,114 +1195,2993 @@ impl<Reader: MemReader<Idx = u32>> Core32<Reader> {
         self.gp_regfile.write(reg.to_idx(), value);
     }
 
-    #[cold]
-    fn debug_print(&self, instr: &Instruction) {
-        eprintln!("pc: {:#x}: {:?}", self.pc, instr);
+    pub fn pc(&self) -> u32 {
+        self.pc
     }
 
-    #[cold]
-    fn get_exit_info(&self) -> RunInfo {
-        RunInfo {
-            return_code: self.read(Register::A(0)),
-        }
-    }
+    /// Sets up argument registers and a return address for the function at the
+    /// current pc, so that the run stops with `StopReason::Returned` when it returns
+    ///
+    /// Arguments follow the ilp32d convention: fp arguments go in fa-registers
+    /// until those run out and then in a-registers, and whatever doesn't fit in
+    /// a-registers is passed on the stack.
+    pub fn synthesize_call(&mut self, args: &[ArgValue]) {
+        self.exited = false;
+        let mut next_int = 0;
+        let mut next_fp = 0;
+        let mut stack = Vec::<u8>::new();
 
-    pub fn run(&mut self) -> RunInfo {
-        let sp = (self.memory.size() as i32 - 128) & !0xF;
-        self.write(Register::Sp, sp);
+        for &arg in args {
+            match arg {
+                ArgValue::Single(val) if next_fp < 8 => {
+                    self.fp_regfile
+                        .write_single(Register::Fa(next_fp).to_idx(), val);
+                    next_fp += 1;
+                }
+                ArgValue::Double(val) if next_fp < 8 => {
+                    self.fp_regfile
+                        .write_double(Register::Fa(next_fp).to_idx(), val);
+                    next_fp += 1;
+                }
+                ArgValue::Int(val) => self.pass_word(&mut next_int, &mut stack, val as u32),
+                ArgValue::Single(val) => self.pass_word(&mut next_int, &mut stack, val.to_bits()),
+                ArgValue::Double(val) => {
+                    let bits = val.to_bits();
+                    // doubles passed entirely on the stack are 8-byte aligned, but
+                    // one split across a7 and the stack is not
+                    if next_int == 8 && !stack.len().is_multiple_of(8) {
+                        stack.extend_from_slice(&[0; 4]);
+                    }
+                    self.pass_word(&mut next_int, &mut stack, bits as u32);
+                    self.pass_word(&mut next_int, &mut stack, (bits >> 32) as u32);
+                }
+            }
+        }
 
-        let vaddr = self.text.vaddr as usize;
-        let data = self.text.data.clone();
+        if !stack.is_empty() {
+            let len = stack.len().next_multiple_of(16) as u32;
+            let sp = self.read(Register::Sp) as u32 - len;
+            self.memory
+                .get_buf(sp, stack.len() as u32)
+                .copy_from_slice(&stack);
+            self.write(Register::Sp, sp as i32);
+        }
 
-        let mut ins_cache = Vec::with_capacity((data.len() + 3) / 4);
-        unsafe {
-            let Range { mut start, end } = data.as_ptr_range();
+        self.return_address = RETURN_SENTINEL;
+        self.write(Register::Ra, RETURN_SENTINEL as i32);
+    }
 
-            while start < end {
-                let instr = *(start as *const u32);
-                let instr = Instruction::decode(instr);
+    fn pass_word(&mut self, next_int: &mut usize, stack: &mut Vec<u8>, word: u32) {
+        if *next_int < 8 {
+            self.write(Register::A(*next_int), word as i32);
+            *next_int += 1;
+        } else {
+            stack.extend_from_slice(&word.to_le_bytes());
+        }
+    }
 
-                ins_cache.push(instr);
+    /// Return value registers as left by the function entered with `synthesize_call`
+    pub fn ret_value(&self) -> RetValue {
+        RetValue {
+            a0: self.read(Register::A(0)),
+            a1: self.read(Register::A(1)),
+            fa0: self.fp_regfile.read_u64(Register::Fa(0).to_idx()),
+        }
+    }
 
-                start = start.wrapping_add(4);
+    /// Calls a single guest function and runs it until it returns
+    ///
+    /// The stack pointer is restored afterwards, so functions can be called
+    /// repeatedly on the same core, e.g. from Rust unit tests:
+    ///
+    /// ```no_run
+    /// # use risc_y::{core::{Core32, UnalignedMemReader}, load::LoadedElf};
+    /// let elf = LoadedElf::load("add.elf").unwrap();
+    /// let mut core = Core32::<UnalignedMemReader<u32>>::new(elf, None, 1 << 20, false);
+    /// let ret = core.call_function("add2", &[5.into(), (-9).into()]).unwrap();
+    /// assert_eq!(ret.int(), -4);
+    /// ```
+    pub fn call_function<'a>(
+        &mut self,
+        target: impl Into<CallTarget<'a>>,
+        args: &[ArgValue],
+    ) -> Result<RetValue, String> {
+        let addr = match target.into() {
+            CallTarget::Addr(addr) => addr,
+            CallTarget::Symbol(name) => {
+                self.memory
+                    .elf
+                    .symbol(name)
+                    .ok_or_else(|| format!("symbol '{name}' not found"))? as u32
             }
+        };
 
-            ins_cache.set_len(data.len() / 4);
+        let text = self.text.vaddr..self.text.vaddr + self.text.size;
+        if !text.contains(&(addr as u64)) || !addr.is_multiple_of(4) {
+            return Err(format!(
+                "{addr:#x} is not an instruction in the text segment"
+            ));
         }
 
-        let wk_memmove = self.wk_memmove;
-        let wk_memcpy = self.wk_memcpy;
-        let wk_memset = self.wk_memset;
-        let wk_cos = self.wk_cos;
-        let wk_sin = self.wk_sin;
+        let sp = self.read(Register::Sp);
+        self.pc = addr;
+        self.synthesize_call(args);
 
-        loop {
-            let pc = self.pc;
+        let info = self.run();
+        self.return_address = NO_RETURN_ADDRESS;
 
-            let pc = pc as usize;
-            let rel_pc = pc - vaddr;
-            // let instr = read_unaligned(&data, rel_pc);
-            // let instr = Instruction::decode(u32::from_le_bytes(instr));
-            let instr = unsafe { *ins_cache.get_unchecked(rel_pc / 4) };
-
-            if self.debug {
-                self.debug_print(&instr);
+        match info.reason {
+            StopReason::Returned => {
+                self.write(Register::Sp, sp);
+                Ok(self.ret_value())
+            }
+            StopReason::Exited => Err(format!(
+                "guest exited with code {} before returning",
+                info.return_code
+            )),
+            StopReason::Breakpoint { pc } => Err(format!("breakpoint hit at pc {pc:#x}")),
+            StopReason::Fatal(_) => Err(self.fatal.as_ref().unwrap().to_string()),
+            StopReason::PossibleHang { pc } => Err(format!("possible hang at pc {pc:#x}")),
+            StopReason::IllegalInstruction { pc, inst } => {
+                Err(format!("illegal instruction {inst:#010x} at pc {pc:#x}"))
             }
+            StopReason::Cancelled { pc } => Err(format!("cancelled at pc {pc:#x}")),
+            StopReason::WouldBlock { pc } => Err(format!("blocked reading stdin at pc {pc:#x}")),
+            StopReason::CfiViolation {
+                pc,
+                expected,
+                actual,
+            } => Err(format!(
+                "return at pc {pc:#x} went to {actual:#x}, expected {expected:#x}"
+            )),
+            StopReason::RomWrite { pc, addr } => {
+                Err(format!("store to rom at {addr:#x} at pc {pc:#x}"))
+            }
+            StopReason::IoTimeout { pc, fd } => {
+                Err(format!("I/O on fd {fd} timed out at pc {pc:#x}"))
+            }
+            StopReason::InstructionLimit { pc } => {
+                Err(format!("instruction limit reached at pc {pc:#x}"))
+            }
+            StopReason::ReplayMismatch { pc, index } => Err(format!(
+                "syscall at pc {pc:#x} doesn't match syscall {index} of the recording"
+            )),
+            StopReason::Panicked { pc } => Err(format!("riscy panicked at pc {pc:#x}")),
+            StopReason::LoadFault { pc, addr } => Err(format!(
+                "load from {addr:#x}, past the end of memory, at pc {pc:#x}"
+            )),
+            StopReason::StoreFault { pc, addr } => Err(format!(
+                "store to {addr:#x}, past the end of memory, at pc {pc:#x}"
+            )),
+            StopReason::FetchFault { pc } => {
+                Err(format!("jumped to {pc:#x}, not an instruction of the code"))
+            }
+            StopReason::Ebreak { pc } => Err(format!("ebreak at pc {pc:#x}")),
+            StopReason::InvariantViolated { pc, index } => {
+                Err(format!("invariant {index} violated at pc {pc:#x}"))
+            }
+        }
+    }
 
-            match self.exec(instr) {
-                ExecResult::Jump(pc) => {
+    /// Calls `f` with the final `RunInfo` once the guest exits, whichever way
+    /// it does: on every stop but those it can carry on from, see
+    /// `StopReason::is_exit`, from `run`, `step` or their traced forms.
+    /// Callbacks are called in the order they were added, all before any
+    /// device's `Device::shutdown`, so they can still use them. It's once a
+    /// run: restoring a snapshot or synthesizing a call starts another
+    pub fn on_exit(&mut self, f: impl FnMut(&RunInfo) + 'static) {
+        self.exit_callbacks.push(Box::new(f));
+    }
+
+    /// Replaces the function at `addr` with `action`, see `stub`
+    pub fn add_stub(&mut self, addr: u32, action: StubAction) {
+        self.stubs.retain(|&(stub, _)| stub != addr);
+        self.stubs.push((addr, action));
+    }
+
+    /// Services calls to the function at `addr` with `native`, see `native`
+    pub fn add_native(&mut self, addr: u32, native: &'static Native) {
+        let (args, ret) = native.locations(self.memory.elf.target.float_abi);
+        self.natives.retain(|call| call.addr != addr);
+        self.natives.push(NativeCall {
+            addr,
+            native,
+            args,
+            ret,
+            calls: 0,
+        });
+    }
+
+    /// How many calls each function given to `add_native` has serviced
+    pub fn native_calls(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        self.natives
+            .iter()
+            .map(|call| (call.native.name, call.calls))
+    }
+
+    fn call_native(&mut self, idx: usize) {
+        let read = |core: &Self, loc: Loc| match loc {
+            Loc::Int(n) => core.read(Register::A(n)) as u32 as u64,
+            Loc::Pair(n) => {
+                let lo = core.read(Register::A(n)) as u32 as u64;
+                let hi = core.read(Register::A(n + 1)) as u32 as u64;
+                lo | (hi << 32)
+            }
+            Loc::Fp(n) => core.fp_regfile.read_u64(10 + n),
+        };
+        let call = &self.natives[idx];
+        let (native, ret) = (call.native, call.ret);
+        let args: Vec<u64> = call.args.iter().map(|&loc| read(self, loc)).collect();
+        let value = (native.call)(&args);
+
+        match ret {
+            Loc::Int(n) => self.write(Register::A(n), value as i32),
+            Loc::Pair(n) => {
+                self.write(Register::A(n), value as i32);
+                self.write(Register::A(n + 1), (value >> 32) as i32);
+            }
+            Loc::Fp(n) if native.ret == Ty::Float => {
+                self.fp_regfile
+                    .write_single(10 + n, f32::from_bits(value as u32));
+            }
+            Loc::Fp(n) => self.fp_regfile.write_double(10 + n, f64::from_bits(value)),
+        }
+        self.natives[idx].calls += 1;
+    }
+
+    /// Replaces the instruction at `addr` in the program's code with `word`,
+    /// returning the word it replaced, see `patch`
+    pub fn patch(&mut self, addr: u32, word: u32) -> Result<u32, String> {
+        let code_end = self.code_base as u64 + self.ins_cache.len() as u64 * 4;
+        if !addr.is_multiple_of(4) || addr < self.code_base || addr as u64 + 4 > code_end {
+            return Err(format!(
+                "{addr:#x} isn't an instruction in the program's code"
+            ));
+        }
+
+        let idx = (addr - self.code_base) as usize / 4;
+        let old = self.code_word(idx);
+        self.memory.as_mut_slice()[addr as usize..addr as usize + 4]
+            .copy_from_slice(&word.to_le_bytes());
+        self.invalidate_code(addr..addr + 4);
+        Ok(old)
+    }
+
+    /// Makes `f` callable from the guest as host call `id`, see `hostcall`
+    pub fn register_hostcall(
+        &mut self,
+        id: u32,
+        name: &str,
+        f: impl FnMut(&mut HostCtx) -> Result<i32, HostcallError> + 'static,
+    ) {
+        self.hostcalls.register(id, name, f);
+    }
+
+    /// Makes `f` execute the instructions in `opcode`'s space, see `custom`
+    pub fn register_custom(
+        &mut self,
+        opcode: CustomOpcode,
+        f: impl FnMut(u32, &mut Machine) -> Result<(), CustomError> + 'static,
+    ) {
+        self.custom.register(opcode, f);
+    }
+
+    /// Calls `f` with each instruction that's about to trap as illegal, for
+    /// emulating vendor extensions or logging what's missing. Returning
+    /// `CustomError::Illegal` lets it trap after all
+    pub fn set_illegal_handler(
+        &mut self,
+        f: impl FnMut(u32, &mut Machine) -> Result<(), CustomError> + 'static,
+    ) {
+        self.illegal_handler = Some(Box::new(f));
+    }
+
+    /// Calls `callback` with a progress report every `interval`
+    pub fn set_progress(
+        &mut self,
+        interval: ProgressInterval,
+        callback: impl FnMut(&ProgressReport) + 'static,
+    ) {
+        self.progress = Some(Progress::new(interval, Box::new(callback)));
+        self.update_next_check();
+    }
+
+    /// Records the guest's call stack every `interval`, see `sample`. The
+    /// stack is taken from `call_stack` if given, which must also be added as
+    /// a hook, and otherwise walked through frame pointers
+    pub fn set_sampling(&mut self, interval: ProgressInterval, call_stack: Option<CallStack>) {
+        self.sampler = Some(Sampler::new(interval, self.instret, call_stack));
+        self.update_next_check();
+    }
+
+    /// The call stacks recorded since `set_sampling`
+    pub fn samples(&self) -> Option<&Samples> {
+        self.sampler.as_ref().map(|sampler| &sampler.samples)
+    }
+
+    /// Stops the run with `StopReason::InstructionLimit` once `instret`
+    /// reaches `limit`
+    pub fn set_instruction_limit(&mut self, limit: Option<u64>) {
+        self.instruction_limit = limit;
+        self.update_next_check();
+    }
+
+    /// Holds the guest to `mips`, scaling the virtual clock to match, see
+    /// `throttle`
+    pub fn set_throttle(&mut self, mips: Mips) {
+        self.throttle = Some(Throttle::new(mips, self.instret));
+        self.nondet.set_rate(mips);
+        self.update_next_check();
+    }
+
+    /// Injects the faults `specs` describe, with `seed` choosing the loads
+    /// corrupted, see `inject`
+    pub fn set_faults(&mut self, specs: &[FaultSpec], seed: u64) {
+        let faults = Faults::new(specs, seed);
+        if faults.corrupts_loads() {
+            self.instrumented = true;
+        }
+        self.faults = Some(faults);
+        self.update_next_check();
+    }
+
+    /// How many of the faults given to `set_faults` have been injected
+    pub fn faults_injected(&self) -> u64 {
+        self.faults.as_ref().map_or(0, Faults::injected)
+    }
+
+    /// A handle to the queue of events due on the virtual clock, for devices
+    /// to schedule with, see `events`
+    pub fn events(&self) -> Events {
+        self.events.clone()
+    }
+
+    /// Runs the callbacks of the host fds `io` watches as they become ready,
+    /// see `hostio`
+    pub fn set_host_io(&mut self, io: HostIo) {
+        self.host_io = Some(io);
+        self.update_next_check();
+    }
+
+    /// The host I/O thread given with `set_host_io`, to watch more fds with
+    pub fn host_io_mut(&mut self) -> Option<&mut HostIo> {
+        self.host_io.as_mut()
+    }
+
+    /// Gives the guest the trap CSRs and `mret`, to take the interrupts events
+    /// raise, see `irq`
+    pub fn enable_interrupts(&mut self) {
+        let code_end = self.code_base + self.ins_cache.len() as u32 * 4;
+        self.interrupts
+            .get_or_insert_with(|| Interrupts::new(self.code_base..code_end));
+    }
+
+    /// Raises an interrupt on `schedule`, taken as a machine-mode trap, see
+    /// `irq`
+    pub fn schedule_irq(&mut self, schedule: IrqSchedule) {
+        fn raise(schedule: IrqSchedule, fired: &mut Fired) {
+            fired.raise_irq(schedule.irq);
+            if let Some(every) = schedule.every {
+                fired.schedule_in(every, move |fired| raise(schedule, fired));
+            }
+        }
+
+        self.enable_interrupts();
+        self.events
+            .schedule_at(schedule.at, move |fired| raise(schedule, fired));
+        self.update_next_check();
+    }
+
+    /// How often each interrupt was raised and taken, by number, for those
+    /// scheduled with `schedule_irq`
+    pub fn irq_stats(&self) -> Vec<(u8, IrqStats)> {
+        self.interrupts
+            .as_ref()
+            .map_or_else(Vec::new, Interrupts::stats)
+    }
+
+    /// Opens the host file or directory at `path` as the guest's fd `fd`, see
+    /// `fds`. The guest may only write to it, or create files beneath it, if
+    /// it's `writable`; a writable file is created if it doesn't exist
+    pub fn preopen(&mut self, fd: i32, path: &Path, writable: bool) -> io::Result<()> {
+        self.fds.preopen(fd, path, writable)
+    }
+
+    /// Bounds how long guest I/O on the host's files may block, see `timeout`.
+    /// Any deadline counts from now
+    pub fn set_io_timeouts(&mut self, timeouts: IoTimeouts) {
+        self.io_limits = Some(IoLimits::new(timeouts));
+    }
+
+    /// Whether `run` follows hot paths through the code as superblocks, which
+    /// it does by default, see `superblock`
+    pub fn set_superblocks(&mut self, on: bool) {
+        self.superblocks = on.then(|| Superblocks::new(self.ins_cache.len()));
+    }
+
+    /// Lets the guest store to its text segment, for programs that patch their
+    /// own code, rather than stopping the run with `StopReason::RomWrite`
+    pub fn set_writable_text(&mut self, writable: bool) {
+        self.memory.writable_text = writable;
+        self.memory.map.set_writable_text(writable);
+    }
+
+    /// How many times the decoded code has changed. The core decodes the
+    /// program's executable segments a block at a time, by address, as they
+    /// first run, and keeps that up to date: a guest store to them has what it
+    /// overwrote decoded again, and `fence.i` has all of them decoded again, for
+    /// what the host wrote there for the guest. As
+    /// there's no address translation, that's by physical address, so code
+    /// loaded over other code, as an overlay manager does, is seen too. A
+    /// cache of anything derived from the code, like a translated block, is
+    /// stale once this changes
+    pub fn code_generation(&self) -> u64 {
+        self.code_generation
+    }
+
+    /// How long decoding the program's code has taken so far
+    pub fn decode_stats(&self) -> DecodeStats {
+        self.decode_stats
+    }
+
+    /// The `switch` jump tables found in the code run so far, see `jumptable`
+    pub fn jump_tables(&self) -> &JumpTables {
+        &self.jump_tables
+    }
+
+    /// How often superblocks ending in an indirect jump predicted where it
+    /// went, see `superblock`
+    pub fn inline_cache_stats(&self) -> InlineCacheStats {
+        self.superblocks
+            .as_ref()
+            .map_or_else(InlineCacheStats::default, |superblocks| superblocks.stats)
+    }
+
+    /// Saves the superblocks formed so far to `path`, for `load_superblocks`
+    /// on a later run of the same program, returning how many there were. See
+    /// `superblock`
+    pub fn save_superblocks(&self, path: &Path) -> io::Result<usize> {
+        let (blocks, bias) = match &self.superblocks {
+            Some(superblocks) => superblocks.saved(),
+            None => Default::default(),
+        };
+        let blocks: Vec<_> = blocks
+            .into_iter()
+            .map(|pcs| {
+                let hash = self.code_hash(&pcs);
+                (pcs, hash)
+            })
+            .collect();
+
+        let count = blocks.len();
+        superblock::Saved {
+            build_id: self.build_id.clone(),
+            program: checkpoint::hash(&self.text.data),
+            blocks,
+            bias,
+        }
+        .save(path)?;
+        Ok(count)
+    }
+
+    /// Forms the superblocks saved to `path` by `save_superblocks`, returning
+    /// how many were. It's an error if they're of another program, by build
+    /// id, or by its code if it has none. Any whose code has changed since are
+    /// left out. Does nothing with superblocks off
+    pub fn load_superblocks(&mut self, path: &Path) -> io::Result<usize> {
+        let saved = superblock::Saved::load(path)?;
+        let program = checkpoint::hash(&self.text.data);
+        let same = match (&saved.build_id, &self.build_id) {
+            (Some(saved), Some(ours)) => saved == ours,
+            _ => saved.program == program,
+        };
+        if !same {
+            return Err(checkpoint::invalid(
+                "superblock cache is of a different program",
+            ));
+        }
+        if self.superblocks.is_none() {
+            return Ok(0);
+        }
+
+        let mut restored = 0;
+        for (pcs, hash) in saved.blocks {
+            if self.code_hash(&pcs) != hash {
+                debug!(target: "superblock", "dropping saved superblock at {:#x}, its code has changed", pcs[0]);
+                continue;
+            }
+            let instrs: Option<Vec<_>> = pcs
+                .iter()
+                .map(|&pc| Some((pc, self.cached_instruction(self.code_idx(pc)?)?)))
+                .collect();
+            let (Some(instrs), Some(head)) = (instrs, self.code_idx(pcs[0])) else {
+                continue;
+            };
+            let superblocks = self.superblocks.as_mut().unwrap();
+            if superblocks.restore(head, instrs) {
+                restored += 1;
+            }
+        }
+
+        let superblocks = self.superblocks.as_mut().unwrap();
+        for (pc, bias) in saved.bias {
+            superblocks.restore_bias(pc, bias);
+        }
+        Ok(restored)
+    }
+
+    // the index in `ins_cache` of the instruction at `pc`, if it's in the code
+    fn code_idx(&self, pc: u32) -> Option<usize> {
+        let idx = pc.checked_sub(self.code_base)? as usize / 4;
+        (pc & 3 == 0 && idx < self.ins_cache.len()).then_some(idx)
+    }
+
+    // a hash of the instruction words at `pcs` now, or 0 if any's outside the
+    // code
+    fn code_hash(&self, pcs: &[u32]) -> u64 {
+        let mut words = Vec::with_capacity(pcs.len() * 4);
+        for &pc in pcs {
+            let Some(idx) = self.code_idx(pc) else {
+                return 0;
+            };
+            words.extend_from_slice(&self.code_word(idx).to_le_bytes());
+        }
+        checkpoint::hash(&words)
+    }
+
+    // has the cached instructions overlapping `range` decoded again from guest
+    // memory before they next run
+    fn invalidate_code(&mut self, range: Range<u32>) {
+        let base = self.code_base;
+        let first = range.start.saturating_sub(base) as usize / 4;
+        let last = (range.end.saturating_sub(base) as usize)
+            .div_ceil(4)
+            .min(self.ins_cache.len());
+        if first >= last {
+            return;
+        }
+
+        self.ins_cache[first..last].fill(Instruction::Unknown(UNDECODED));
+        self.jump_tables.remove_in(range);
+        self.code_generation += 1;
+        if let Some(superblocks) = &mut self.superblocks {
+            superblocks.clear();
+        }
+    }
+
+    // the instruction word at `idx` in `ins_cache`, from guest memory
+    fn code_word(&self, idx: usize) -> u32 {
+        let addr = self.code_base as usize + idx * 4;
+        u32::from_le_bytes(self.memory.as_slice()[addr..addr + 4].try_into().unwrap())
+    }
+
+    // decodes the block holding `idx`, as an instruction in it is about to run
+    // for the first time, returning that instruction. The blocks of the cases
+    // of any switch in it are decoded too
+    #[cold]
+    #[inline(never)]
+    fn decode_block(&mut self, idx: usize) -> Instruction {
+        let start = Instant::now();
+        let mut pending = vec![idx];
+        while let Some(idx) = pending.pop() {
+            if !matches!(self.ins_cache[idx], Instruction::Unknown(UNDECODED)) {
+                continue;
+            }
+            let first = idx / DECODE_BLOCK * DECODE_BLOCK;
+            let block = first..(first + DECODE_BLOCK).min(self.ins_cache.len());
+            for idx in block.clone() {
+                self.ins_cache[idx] = Instruction::decode(self.code_word(idx));
+            }
+            self.decode_stats.blocks += 1;
+            self.decode_stats.instructions += block.len() as u64;
+
+            for idx in block {
+                let Some(table) = self.find_jump_table(idx) else {
+                    continue;
+                };
+                pending.extend(
+                    table
+                        .targets
+                        .iter()
+                        .map(|&target| (target - self.code_base) as usize / 4),
+                );
+                self.decode_stats.jump_tables += 1;
+                self.jump_tables.insert(table);
+            }
+        }
+
+        self.decode_stats.time += start.elapsed();
+        self.ins_cache[idx]
+    }
+
+    // the jump table the instruction at `idx` switches through, if it's a
+    // `jr` through one
+    fn find_jump_table(&self, idx: usize) -> Option<JumpTable> {
+        // `ret`, through ra, never is
+        if !matches!(
+            self.ins_cache[idx],
+            Instruction::Jalr { rd: 0, rs1, imm: 0 } if rs1 != 1
+        ) {
+            return None;
+        }
+
+        let instrs: Vec<_> = (idx.saturating_sub(jumptable::WINDOW)..=idx)
+            .map(|idx| {
+                let pc = self.code_base + idx as u32 * 4;
+                Some((pc, self.cached_instruction(idx)?))
+            })
+            .collect::<Option<_>>()?;
+        let code = self.code_base..self.code_base + self.ins_cache.len() as u32 * 4;
+        let memory = self.memory.as_slice();
+        jumptable::recognize(&instrs, code, |addr| {
+            let bytes = memory.get(addr as usize..addr as usize + 4)?;
+            Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+        })
+    }
+
+    // the instruction at `idx` in `ins_cache`, without decoding its block
+    fn cached_instruction(&self, idx: usize) -> Option<Instruction> {
+        match *self.ins_cache.get(idx)? {
+            Instruction::Unknown(UNDECODED) => Some(Instruction::decode(self.code_word(idx))),
+            instr => Some(instr),
+        }
+    }
+
+    /// Services the commands the guest writes to its `tohost`, for binaries
+    /// built for riscv-pk or libgloss-htif, see `htif`
+    pub fn enable_htif(&mut self) -> Result<(), String> {
+        let symbol = |name| {
+            self.memory
+                .elf
+                .symbols
+                .iter()
+                .find(|sym| sym.name == name)
+                .map(|sym| sym.addr as u32)
+        };
+
+        let tohost = symbol(htif::TOHOST).ok_or("no 'tohost' symbol")?;
+        if !tohost.is_multiple_of(8) {
+            return Err(format!("tohost at {tohost:#x} isn't 8-byte aligned"));
+        }
+        self.fromhost = symbol(htif::FROMHOST);
+        self.memory.tohost = tohost as usize;
+        Ok(())
+    }
+
+    /// Caps how much the guest may grow its heap, open and write, see `limits`
+    pub fn set_resource_limits(&mut self, limits: ResourceLimits) {
+        let size = self.memory.size() as u64;
+        self.limits = Limits::new(limits, size, size - self.heap_start as u64);
+    }
+
+    /// Records every syscall from here on, see `replay`
+    pub fn record_syscalls(&mut self) {
+        self.syscall_log = Some(SyscallLog::Record(SyscallTape::default()));
+    }
+
+    /// Serves syscalls from `tape` rather than the host from here on, see
+    /// `replay`
+    pub fn replay_syscalls(&mut self, tape: SyscallTape) {
+        self.syscall_log = Some(SyscallLog::Replay { tape, next: 0 });
+    }
+
+    /// The syscalls recorded, or being replayed
+    pub fn syscall_tape(&self) -> Option<&SyscallTape> {
+        self.syscall_log.as_ref().map(SyscallLog::tape)
+    }
+
+    /// A handle for pausing, resuming or stopping the core from another thread
+    pub fn control_handle(&mut self) -> RunHandle {
+        let handle = self.control.get_or_insert_with(RunHandle::default).clone();
+        self.update_next_check();
+        handle
+    }
+
+    /// Saves a snapshot of the core to `path` every `interval` instructions,
+    /// see `checkpoint`
+    pub fn set_checkpoint(&mut self, interval: u64, path: PathBuf) {
+        self.checkpoint = Some(Checkpointer {
+            interval,
+            path,
+            next: self.instret + interval,
+        });
+        self.update_next_check();
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let stdin_pos = match &self.stdin {
+            Some(Stdin::Buffer(stdin)) => Some(stdin.position()),
+            _ => None,
+        };
+
+        Snapshot {
+            program: checkpoint::hash(&self.text.data),
+            pc: self.pc,
+            instret: self.instret,
+            gp_regs: self.gp_regs(),
+            fp_regs: self.fp_regs(),
+            fcsr: self.fp_regfile.fcsr.bits(),
+            brk: self.brk,
+            syscall_counts: self.syscall_counts.clone(),
+            rng: self.nondet.rng_state(),
+            stdin_pos,
+            fd_offsets: checkpoint::host_fd_offsets(),
+            memory: self.memory.as_slice().to_vec(),
+        }
+    }
+
+    /// Carries on from `snapshot`, which must be of the same program with the
+    /// same memory size. Any stdin buffer has to be set first
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), String> {
+        if snapshot.program != checkpoint::hash(&self.text.data) {
+            return Err("checkpoint is of a different program".to_owned());
+        }
+        if snapshot.memory.len() != self.memory.size() {
+            return Err(format!(
+                "checkpoint has {} bytes of memory, not {}",
+                snapshot.memory.len(),
+                self.memory.size()
+            ));
+        }
+
+        checkpoint::restore_host_fd_offsets(&snapshot.fd_offsets)
+            .map_err(|err| format!("failed to restore host file offsets: {err}"))?;
+
+        self.pc = snapshot.pc;
+        self.instret = snapshot.instret;
+        for (idx, &value) in snapshot.gp_regs.iter().enumerate() {
+            self.gp_regfile.write(idx as u8, value);
+        }
+        for (idx, &bits) in snapshot.fp_regs.iter().enumerate() {
+            self.fp_regfile
+                .write_double(idx as u8, f64::from_bits(bits));
+        }
+        self.fp_regfile.fcsr.set_bits(snapshot.fcsr);
+        self.syscall_counts = snapshot.syscall_counts.clone();
+        self.brk = snapshot.brk;
+        self.limits.reset();
+        self.fatal = None;
+        self.stderr_fatal = None;
+        self.exited = false;
+        if let Some(rng) = snapshot.rng {
+            self.nondet = Nondeterminism::virtual_from(rng);
+        }
+        if let Some(throttle) = &mut self.throttle {
+            throttle.reset(self.instret);
+            self.nondet.set_rate(throttle.mips());
+        }
+        if let (Some(Stdin::Buffer(stdin)), Some(pos)) = (&mut self.stdin, snapshot.stdin_pos) {
+            stdin.set_position(pos);
+        }
+        self.memory.as_mut_slice().copy_from_slice(&snapshot.memory);
+        self.invalidate_code(self.memory.code.clone());
+
+        if let Some(checkpoint) = &mut self.checkpoint {
+            checkpoint.next = self.instret + checkpoint.interval;
+        }
+        self.update_next_check();
+        Ok(())
+    }
+
+    /// Gives the guest `args` as its argv, laid out at the top of memory as
+    /// Linux lays out a new process's stack, with no environment and an empty
+    /// auxv, and points sp at its argc. Without this argc is 0. Must be set
+    /// before the guest starts, and again after restoring a snapshot
+    pub fn set_args(&mut self, args: &[impl AsRef<[u8]>]) -> Result<(), String> {
+        let size = self.memory.size() as u32;
+        let strings: u32 = args.iter().map(|arg| arg.as_ref().len() as u32 + 1).sum();
+        // argc, argv and its NULL, envp's NULL and auxv's AT_NULL
+        let words = args.len() as u32 + 5;
+        let sp = size
+            .checked_sub(strings + words * 4)
+            .map(|sp| sp & !0xf)
+            .filter(|&sp| sp >= self.brk)
+            .ok_or("the arguments don't fit in guest memory")?;
+
+        let memory = self.memory.as_mut_slice();
+        let mut string = sp + words * 4;
+        let mut vector = vec![args.len() as u32];
+        for arg in args {
+            let arg = arg.as_ref();
+            let at = string as usize;
+            memory[at..at + arg.len()].copy_from_slice(arg);
+            memory[at + arg.len()] = 0;
+            vector.push(string);
+            string += arg.len() as u32 + 1;
+        }
+        vector.extend([0; 4]);
+        for (idx, word) in vector.into_iter().enumerate() {
+            let at = sp as usize + idx * 4;
+            memory[at..at + 4].copy_from_slice(&word.to_le_bytes());
+        }
+
+        self.write(Register::Sp, sp as i32);
+        Ok(())
+    }
+
+    /// The pc and the calls it's in, innermost first, as far as frame pointers
+    /// say, see `sample::walk_stack`
+    pub fn stack_trace(&self) -> Vec<u32> {
+        let text = self.text.vaddr as u32..(self.text.vaddr + self.text.size) as u32;
+        sample::walk_stack(
+            self.pc,
+            self.read(Register::Sp) as u32,
+            self.read(Register::S(0)) as u32,
+            self.memory.as_slice(),
+            text,
+        )
+    }
+
+    /// Feeds the guest `data` as its stdin, instead of the host's
+    pub fn set_stdin(&mut self, data: Vec<u8>) {
+        self.stdin = Some(Stdin::Buffer(Cursor::new(data)));
+    }
+
+    /// Feeds the guest `pipe` as its stdin; reading it while it's empty stops
+    /// the run with `StopReason::WouldBlock`, or suspends `run_async`
+    pub fn set_stdin_pipe(&mut self, pipe: GuestPipe) {
+        self.stdin = Some(Stdin::Pipe(pipe));
+    }
+
+    pub(crate) fn stdin_pipe(&self) -> Option<&GuestPipe> {
+        match &self.stdin {
+            Some(Stdin::Pipe(pipe)) => Some(pipe),
+            _ => None,
+        }
+    }
+
+    /// Has `debug` runs show the fp registers each fp instruction reads and
+    /// writes, see `tracer`
+    pub fn set_debug_fp(&mut self, on: bool) {
+        self.debug_fp = on;
+    }
+
+    /// Derives the guest's clocks from the instruction count, and its random
+    /// bytes from `seed`, instead of the host's, see `nondet`
+    pub fn set_deterministic(&mut self, seed: u64) {
+        self.nondet = Nondeterminism::virtual_from(seed);
+        if let Some(throttle) = &self.throttle {
+            self.nondet.set_rate(throttle.mips());
+        }
+    }
+
+    /// Makes the pre-opened directory `fd` the guest's root and current
+    /// directory, see `fds`
+    pub fn set_root(&mut self, fd: i32) -> io::Result<()> {
+        self.fds.set_root(fd)
+    }
+
+    /// Decides which of the guest's fds are terminals, and what they're like,
+    /// see `ioctl`
+    pub fn set_tty(&mut self, mode: TtyMode) {
+        self.terminals = Terminals::new(mode);
+    }
+
+    /// Overrides the times, owners or permissions of every file the guest
+    /// stats, see `stat`
+    pub fn set_stat_spoof(&mut self, spoof: StatSpoof) {
+        self.stat_spoof = spoof;
+    }
+
+    /// Sets what the guest's `uname` says, see `identity`
+    pub fn set_uname(&mut self, uname: Uname) {
+        self.uname = uname;
+    }
+
+    /// Gives the guest `/proc/self/maps` and `/proc/cpuinfo` describing itself,
+    /// see `identity`
+    pub fn set_fake_proc(&mut self, fake_proc: bool) {
+        self.fake_proc = fake_proc;
+    }
+
+    /// Collects what the guest writes to stdout and stderr rather than passing
+    /// it through, see `captured_stdout`/`captured_stderr` and `RunInfo::output`
+    pub fn capture_output(&mut self) {
+        self.capture_output_with(CaptureConfig::default());
+    }
+
+    /// Like `capture_output`, keeping no more than `config` allows, see
+    /// `capture`
+    pub fn capture_output_with(&mut self, config: CaptureConfig) {
+        self.captured = Some(Capture::new(config));
+    }
+
+    /// Sends what the guest writes to stdout, stderr and the htif console
+    /// through `mux`, unless it's captured, see `mux`
+    pub fn set_output_mux(&mut self, mux: Mux) {
+        self.mux = Some(mux);
+    }
+
+    pub fn captured_stdout(&self) -> &[u8] {
+        self.captured
+            .as_ref()
+            .map_or(&[], |capture| &capture.output.stdout)
+    }
+
+    pub fn captured_stderr(&self) -> &[u8] {
+        self.captured
+            .as_ref()
+            .map_or(&[], |capture| &capture.output.stderr)
+    }
+
+    /// Forgets what the guest wrote past its first `stdout` and `stderr`
+    /// bytes, for going back to a snapshot from when that was all it had
+    pub fn truncate_captured(&mut self, stdout: usize, stderr: usize) {
+        if let Some(capture) = &mut self.captured {
+            capture.output.stdout.truncate(stdout);
+            capture.output.stderr.truncate(stderr);
+        }
+    }
+
+    fn captured_output(&self) -> Option<CapturedOutput> {
+        self.captured.as_ref().map(|capture| capture.output.clone())
+    }
+
+    /// Number of instructions retired so far
+    pub fn instret(&self) -> u64 {
+        self.instret
+    }
+
+    /// Number of calls to each syscall so far, by syscall number
+    pub fn syscall_counts(&self) -> &BTreeMap<i32, u64> {
+        &self.syscall_counts
+    }
+
+    pub fn add_hook(&mut self, hook: Box<dyn Hook>) {
+        self.hooks.push(hook);
+        self.instrumented = true;
+    }
+
+    pub fn dump_registers(&self) {
+        for row in (0..32).collect::<Vec<u8>>().chunks(4) {
+            let line = row
+                .iter()
+                .map(|&idx| {
+                    format!(
+                        "{:>4}: {:#010x}",
+                        Register::gp(idx).to_string(),
+                        self.gp_regfile.read(idx)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("  ");
+            eprintln!("{line}");
+        }
+    }
+
+    pub fn gp_regs(&self) -> [i32; 32] {
+        let mut regs = [0; 32];
+        for (idx, reg) in regs.iter_mut().enumerate() {
+            *reg = self.gp_regfile.read(idx as u8);
+        }
+        regs
+    }
+
+    pub fn fp_regs(&self) -> [u64; 32] {
+        let mut regs = [0; 32];
+        for (idx, reg) in regs.iter_mut().enumerate() {
+            *reg = self.fp_regfile.read_u64(idx as u8);
+        }
+        regs
+    }
+
+    pub fn frm(&self) -> u8 {
+        self.fp_regfile.fcsr.frm
+    }
+
+    /// The value of `csr`, or `None` if it isn't implemented
+    pub fn read_csr(&self, csr: u16) -> Option<u32> {
+        if let Some(value) = self.interrupts.as_ref().and_then(|irq| irq.read_csr(csr)) {
+            return Some(value);
+        }
+        let fcsr = &self.fp_regfile.fcsr;
+        match csr {
+            csr::FFLAGS => Some(fcsr.fflags()),
+            csr::FRM => Some(fcsr.frm as u32),
+            csr::FCSR => Some(fcsr.bits()),
+            // there's no pipeline to speak of, so every instruction is a cycle
+            csr::CYCLE | csr::INSTRET => Some(self.instret as u32),
+            csr::CYCLEH | csr::INSTRETH => Some((self.instret >> 32) as u32),
+            csr::TIME => Some(self.nondet.time(self.instret) as u32),
+            csr::TIMEH => Some((self.nondet.time(self.instret) >> 32) as u32),
+            _ => {
+                let (counter, high) = csr::hpm_counter(csr)?;
+                let value = self.hpm_counter(counter);
+                Some(if high { value >> 32 } else { value } as u32)
+            }
+        }
+    }
+
+    /// `View`'s CSR with its fields named, or `None` if it isn't implemented,
+    /// see `csr::CsrView`
+    pub fn csr<View: CsrView>(&self) -> Option<View> {
+        self.read_csr(View::CSR).map(View::from_bits)
+    }
+
+    /// Writes `value` to its CSR, returning `false` if it isn't implemented
+    pub fn set_csr<View: CsrView>(&mut self, value: View) -> bool {
+        self.write_csr(View::CSR, value.bits())
+    }
+
+    /// The value of `hpmcounter<counter>`, zero unless it was set with
+    /// `set_hpm_counter`
+    pub fn hpm_counter(&self, counter: u8) -> u64 {
+        match self.hpm.iter().find(|(idx, _)| *idx == counter) {
+            Some((_, HpmCounter::Syscalls)) => self.syscall_counts.values().sum(),
+            Some((_, source)) => source.read(),
+            None => 0,
+        }
+    }
+
+    /// Makes `hpmcounter<counter>` count `event`, see `hpm`
+    pub fn set_hpm_counter(&mut self, counter: u8, event: HpmEvent) -> io::Result<()> {
+        let source = HpmCounter::open(event)?;
+        self.hpm.retain(|(idx, _)| *idx != counter);
+        self.hpm.push((counter, source));
+        Ok(())
+    }
+
+    /// Writes `csr`, returning `false` if it isn't implemented or is read-only
+    pub fn write_csr(&mut self, csr: u16, value: u32) -> bool {
+        if let Some(interrupts) = &mut self.interrupts {
+            if interrupts.write_csr(csr, value, self.instret) {
+                // which may have let one in
+                self.update_next_check();
+                return true;
+            }
+        }
+        let fcsr = &mut self.fp_regfile.fcsr;
+        match csr {
+            csr::FFLAGS => fcsr.set_fflags(value),
+            csr::FRM => fcsr.frm = (value & 0b111) as u8,
+            csr::FCSR => fcsr.set_bits(value),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        self.memory.as_slice()
+    }
+
+    /// Writes `data` to guest memory at `addr`, as a debugger would, so code
+    /// written over is decoded again
+    pub fn write_memory(&mut self, addr: u32, data: &[u8]) -> Result<(), String> {
+        let end = addr as usize + data.len();
+        let Some(dst) = self.memory.as_mut_slice().get_mut(addr as usize..end) else {
+            return Err(format!("{addr:#x}..{end:#x} is outside guest memory"));
+        };
+        dst.copy_from_slice(data);
+        self.invalidate_code(addr..end as u32);
+        Ok(())
+    }
+
+    /// How much of guest memory the host has committed, as it's only committed
+    /// once touched
+    pub fn resident_memory(&self) -> io::Result<usize> {
+        self.memory.mapping.resident()
+    }
+
+    pub fn memory_map(&self) -> &MemoryMap {
+        &self.memory.map
+    }
+
+    /// The guest-physical address `vaddr` is at, and what the guest may do
+    /// there, or `None` if it's unmapped. There's no MMU, so the guest runs in
+    /// bare mode where the two address spaces are the same, but tooling that
+    /// translates through here won't need to change when there is one
+    pub fn translate(&self, vaddr: u32) -> Option<(u32, Perms)> {
+        let paddr = vaddr;
+        let region = self.memory.map.find(paddr)?;
+        Some((paddr, region.perms))
+    }
+
+    /// `len` bytes of guest-physical memory at `paddr`, if they're all RAM or
+    /// ROM. Device windows aren't read, as reads can have side effects
+    pub fn read_phys(&self, paddr: u32, len: u32) -> Option<&[u8]> {
+        let end = paddr as u64 + len as u64;
+        let mut addr = paddr as u64;
+        while addr < end {
+            let region = self.memory.map.find(addr as u32)?;
+            if matches!(region.kind, RegionKind::Device(_)) {
+                return None;
+            }
+            addr = region.end();
+        }
+        self.memory.as_slice().get(paddr as usize..end as usize)
+    }
+
+    /// `len` bytes of memory at `vaddr`, as the guest sees it, translated a
+    /// page at a time
+    pub fn read_virt(&self, vaddr: u32, len: u32) -> Option<Vec<u8>> {
+        let page = mmap::page_size() as u64;
+        let end = vaddr as u64 + len as u64;
+        let mut bytes = Vec::with_capacity(len as usize);
+        let mut addr = vaddr as u64;
+        while addr < end {
+            let chunk = (addr / page * page + page).min(end) - addr;
+            let (paddr, perms) = self.translate(addr as u32)?;
+            if !perms.read {
+                return None;
+            }
+            bytes.extend_from_slice(self.read_phys(paddr, chunk as u32)?);
+            addr += chunk;
+        }
+        Some(bytes)
+    }
+
+    /// Sends the guest's loads and stores to `start..start + len` to `device`
+    /// rather than memory. The window can be anywhere in the 32-bit address
+    /// space, even past the end of guest memory, as long as it doesn't overlap
+    /// ROM or another device
+    pub fn map_device(
+        &mut self,
+        start: u32,
+        len: u64,
+        device: Box<dyn Device>,
+    ) -> Result<(), String> {
+        self.memory.add_device(start, len, device)
+    }
+
+    /// Whether the guest has accessed a device since this was last called
+    pub(crate) fn take_device_access(&self) -> bool {
+        self.memory.device_access.take()
+    }
+
+    /// Whether a call to `target` is serviced natively instead of being executed
+    pub fn is_intercepted(&self, target: u32) -> bool {
+        [
+            self.wk_memset,
+            self.wk_memcpy,
+            self.wk_memmove,
+            self.wk_cos,
+            self.wk_sin,
+        ]
+        .contains(&target)
+            || self.stubs.iter().any(|&(addr, _)| addr == target)
+            || self.natives.iter().any(|call| call.addr == target)
+    }
+
+    fn trace<T: Tracer>(&self, tracer: &mut T, instr: Instruction) {
+        let idx = (self.pc - self.code_base) as usize / 4;
+        tracer.trace(&TraceStep {
+            pc: self.pc,
+            instr,
+            word: self.code_word(idx),
+            prev: idx
+                .checked_sub(1)
+                .and_then(|idx| self.cached_instruction(idx)),
+            next: self.cached_instruction(idx + 1),
+            instret: self.instret,
+            symbols: &self.memory.elf.symbols,
+            jump_tables: &self.jump_tables,
+            fp: tracer.wants_fp().then(|| FpState {
+                regs: self.fp_regs(),
+                fcsr: csr::Fcsr(self.fp_regfile.fcsr.bits()),
+            }),
+        });
+    }
+
+    #[cold]
+    fn io_timed_out(&mut self, syscall: i32, fd: i32) -> ExecResult {
+        let errno = match self.io_limits.unwrap().action {
+            TimeoutAction::Stop => {
+                *self.syscall_counts.get_mut(&syscall).unwrap() -= 1;
+                return ExecResult::IoTimeout(fd);
+            }
+            TimeoutAction::Eintr => timeout::EINTR,
+            TimeoutAction::Etimedout => timeout::ETIMEDOUT,
+        };
+        warn!(target: "syscall", "I/O on fd {fd} timed out, failing with errno {errno}");
+        self.write(Register::A(0), -errno);
+        ExecResult::Continue
+    }
+
+    /// Details of how the guest died, if the run stopped with `StopReason::Fatal`
+    pub fn fatal(&self) -> Option<&GuestFatal> {
+        self.fatal.as_ref()
+    }
+
+    #[cold]
+    fn get_exit_info(&mut self) -> RunInfo {
+        let return_code = self.read(Register::A(0));
+
+        // a fatal message on stderr followed by the matching exit code, e.g. a
+        // statically linked abort that we didn't see the symbol for
+        if let Some((kind, message)) = self.stderr_fatal.take() {
+            // either code, as a rust panic exits with 134 under `panic = "abort"`
+            if matches!(return_code, 101 | 134) {
+                self.fatal = Some(GuestFatal {
+                    kind,
+                    pc: self.pc,
+                    caller: None,
+                    message: Some(message),
+                });
+                return RunInfo {
+                    return_code,
+                    reason: StopReason::Fatal(kind),
+                    limit_hits: self.limits.hits,
+                    output: self.captured_output(),
+                    fault: None,
+                };
+            }
+        }
+
+        RunInfo {
+            return_code,
+            reason: StopReason::Exited,
+            limit_hits: self.limits.hits,
+            output: self.captured_output(),
+            fault: None,
+        }
+    }
+
+    #[cold]
+    fn enter_fatal(&mut self, kind: FatalKind, pc: u32) -> RunInfo {
+        let args = [0, 1, 2, 3].map(|n| self.read(Register::A(n)) as u32);
+        let caller = self.read(Register::Ra) as u32;
+
+        let mut fatal = GuestFatal::from_call(kind, pc, caller, args, self.memory.as_slice());
+        if fatal.message.is_none() {
+            fatal.message = self.stderr_fatal.take().map(|(_, message)| message);
+        }
+        self.fatal = Some(fatal);
+
+        RunInfo {
+            return_code: kind.exit_code() as i32,
+            reason: StopReason::Fatal(kind),
+            limit_hits: self.limits.hits,
+            output: self.captured_output(),
+            fault: None,
+        }
+    }
+
+    pub fn run(&mut self) -> RunInfo {
+        if self.debug {
+            return self.run_traced(&mut Disassembly::stderr().with_fp(self.debug_fp));
+        }
+        self.run_traced(&mut NoTrace)
+    }
+
+    /// Runs the guest with `tracer` called before every instruction, see
+    /// `tracer`
+    pub fn run_traced<T: Tracer>(&mut self, tracer: &mut T) -> RunInfo {
+        if let Some(control) = &self.control {
+            control.set_running(true);
+        }
+
+        // the pc is only moved on once an instruction's done, so it's still
+        // the instruction's if riscy panics running it
+        let run = panic::catch_unwind(AssertUnwindSafe(|| loop {
+            let pc = self.pc;
+            if let Some(info) = self.step_once(tracer) {
+                break info;
+            }
+            // a traced run steps every instruction, to trace it
+            if !T::ENABLED && self.pc != pc.wrapping_add(4) && self.superblocks.is_some() {
+                if let Some(info) = self.jumped() {
+                    break info;
+                }
+            }
+        }));
+        let info = run.unwrap_or_else(|payload| self.panicked(payload.as_ref()));
+
+        if let Some(control) = &self.control {
+            control.set_running(false);
+        }
+        self.stopped(info)
+    }
+
+    // calls the exit callbacks and shuts the devices down, the first time
+    // the run stops for good, see `on_exit`
+    #[cold]
+    fn stopped(&mut self, info: RunInfo) -> RunInfo {
+        if self.exited || !info.reason.is_exit() {
+            return info;
+        }
+        self.exited = true;
+
+        for callback in &mut self.exit_callbacks {
+            callback(&info);
+        }
+        for device in &self.memory.devices {
+            device.borrow_mut().shutdown();
+        }
+        info
+    }
+
+    #[cold]
+    fn panicked(&self, payload: &(dyn Any + Send)) -> RunInfo {
+        let message = match payload.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => payload
+                .downcast_ref::<String>()
+                .cloned()
+                .unwrap_or_else(|| "unknown panic".to_owned()),
+        };
+        let pc = self.pc;
+        let fault = self
+            .cached_instruction(pc.wrapping_sub(self.code_base) as usize / 4)
+            .map(|instr| Fault {
+                panic: Some(message),
+                ..self.fault(pc, instr)
+            });
+
+        RunInfo {
+            return_code: self.read(Register::A(0)),
+            reason: StopReason::Panicked { pc },
+            limit_hits: self.limits.hits,
+            output: self.captured_output(),
+            fault,
+        }
+    }
+
+    /// Runs the guest as a future, see `driver`
+    pub fn run_async(&mut self) -> RunAsync<'_, Reader> {
+        RunAsync { core: self }
+    }
+
+    /// Executes a single instruction, returning `Some` once the program has finished
+    pub fn step(&mut self) -> Option<RunInfo> {
+        if self.debug {
+            return self.step_traced(&mut Disassembly::stderr().with_fp(self.debug_fp));
+        }
+        self.step_traced(&mut NoTrace)
+    }
+
+    /// `step`, with `tracer` called before the instruction
+    pub fn step_traced<T: Tracer>(&mut self, tracer: &mut T) -> Option<RunInfo> {
+        self.step_once(tracer).map(|info| self.stopped(info))
+    }
+
+    #[inline(always)]
+    fn step_once<T: Tracer>(&mut self, tracer: &mut T) -> Option<RunInfo> {
+        // before fetching, as taking an interrupt moves the pc
+        if self.instret >= self.next_check {
+            if let Some(info) = self.periodic_check() {
+                return Some(info);
+            }
+        }
+
+        let rel_pc = self.pc.wrapping_sub(self.code_base) as usize;
+        let mut instr = match self.ins_cache.get(rel_pc / 4) {
+            Some(&instr) if rel_pc.is_multiple_of(4) => instr,
+            // a jump anywhere but the program's code, which the guest can make
+            _ => return Some(self.fetch_fault()),
+        };
+        if let Instruction::Unknown(UNDECODED) = instr {
+            instr = self.decode_block(rel_pc / 4);
+        }
+
+        if T::ENABLED {
+            self.trace(tracer, instr);
+        }
+        self.instret += 1;
+
+        if self.instrumented {
+            return self.step_instrumented(instr);
+        }
+
+        self.retire(instr)
+    }
+
+    // after a jump in a run, enters the superblock there, if there's one
+    #[inline(never)]
+    fn jumped(&mut self) -> Option<RunInfo> {
+        if self.instrumented {
+            return None;
+        }
+
+        let mut block = self.superblock_at_pc()?;
+        loop {
+            match self.run_superblock(block) {
+                SuperblockExit::Stop(info) => return Some(info),
+                SuperblockExit::Left => return None,
+                SuperblockExit::End => {}
+            }
+
+            let superblocks = self.superblocks.as_mut().unwrap();
+            block = match superblocks.predict(block, self.pc) {
+                Prediction::Hit(next) => next,
+                Prediction::Miss => {
+                    let next = self.superblock_at_pc()?;
+                    let superblocks = self.superblocks.as_mut().unwrap();
+                    superblocks.cache(block, self.pc, next);
+                    next
+                }
+                Prediction::Direct => return None,
+            };
+        }
+    }
+
+    // the superblock starting at the pc, having jumped there, if it's hot
+    fn superblock_at_pc(&mut self) -> Option<usize> {
+        let idx = self.pc.wrapping_sub(self.code_base) as usize / 4;
+        let superblocks = self.superblocks.as_mut().unwrap();
+        match superblocks.jumped_to(idx) {
+            Entry::Cold => None,
+            Entry::Run(block) => Some(block),
+            Entry::Form => {
+                // out of the core while it reads the code
+                let mut superblocks = self.superblocks.take().unwrap();
+                let block = superblocks.form(idx, self.pc, |pc| {
+                    let offset = pc.wrapping_sub(self.code_base);
+                    if offset % 4 != 0 {
+                        return None;
+                    }
+                    self.cached_instruction(offset as usize / 4)
+                });
+                self.superblocks = Some(superblocks);
+                block
+            }
+        }
+    }
+
+    fn run_superblock(&mut self, block: usize) -> SuperblockExit {
+        let superblocks = self.superblocks.as_mut().unwrap();
+        let instrs = superblocks.instrs(block);
+        // the checks `step` makes before each instruction, made once for all
+        // of them
+        if self.instret + instrs.len() as u64 > self.next_check {
+            return SuperblockExit::Left;
+        }
+
+        let generation = self.code_generation;
+        for (at, &(_, instr)) in instrs.iter().enumerate() {
+            self.instret += 1;
+            match self.exec(instr) {
+                ExecResult::Continue => self.pc += 4,
+                result => {
+                    if let Some(info) = self.complete_cold(instr, result) {
+                        return SuperblockExit::Stop(info);
+                    }
+                    // the code changed under it, and the superblocks have been
+                    // dropped. Only instructions that don't just continue can
+                    // change it
+                    if self.code_generation != generation {
+                        return SuperblockExit::Left;
+                    }
+                    // a device has scheduled an event before the end
+                    if self.instret >= self.next_check {
+                        return SuperblockExit::Left;
+                    }
+                }
+            }
+
+            let Some(&(next, _)) = instrs.get(at + 1) else {
+                return SuperblockExit::End;
+            };
+            if self.pc != next {
+                let superblocks = self.superblocks.as_mut().unwrap();
+                superblocks.side_exit(block, at, self.pc);
+                return SuperblockExit::Left;
+            }
+        }
+        SuperblockExit::End
+    }
+
+    fn update_next_check(&mut self) {
+        let progress = self
+            .progress
+            .as_ref()
+            .map_or(u64::MAX, |progress| progress.next_check(self.instret));
+        let sampler = self
+            .sampler
+            .as_ref()
+            .map_or(u64::MAX, |sampler| sampler.next_check(self.instret));
+        let control = match (&self.control, &self.host_io) {
+            (None, None) => u64::MAX,
+            _ => self.instret + control::POLL_INTERVAL,
+        };
+        let checkpoint = self
+            .checkpoint
+            .as_ref()
+            .map_or(u64::MAX, |checkpoint| checkpoint.next);
+        let limit = self.instruction_limit.unwrap_or(u64::MAX);
+        let throttle = self
+            .throttle
+            .as_ref()
+            .map_or(u64::MAX, |throttle| throttle.next_check(self.instret));
+        let events = self.events.sync(self.instret).unwrap_or(u64::MAX);
+        let interrupts = self
+            .interrupts
+            .as_ref()
+            .map_or(u64::MAX, |interrupts| interrupts.next_check(self.instret));
+        let faults = self.faults.as_ref().map_or(u64::MAX, Faults::next_check);
+        self.next_check = progress
+            .min(sampler)
+            .min(control)
+            .min(checkpoint)
+            .min(limit)
+            .min(throttle)
+            .min(events)
+            .min(interrupts)
+            .min(faults);
+    }
+
+    #[cold]
+    fn periodic_check(&mut self) -> Option<RunInfo> {
+        self.check_progress();
+        self.check_sample();
+        self.check_checkpoint();
+        self.check_events();
+        self.check_faults();
+        if let Some(throttle) = &mut self.throttle {
+            throttle.wait(self.instret);
+        }
+
+        let stop = self.control.clone().is_some_and(|control| {
+            control.poll(|| PausedState {
+                pc: self.pc,
+                instret: self.instret,
+                gp_regs: self.gp_regs(),
+                fp_regs: self.fp_regs(),
+            })
+        });
+
+        self.update_next_check();
+
+        if self
+            .instruction_limit
+            .is_some_and(|limit| self.instret >= limit)
+        {
+            return Some(RunInfo {
+                return_code: self.read(Register::A(0)),
+                reason: StopReason::InstructionLimit { pc: self.pc },
+                limit_hits: self.limits.hits,
+                output: self.captured_output(),
+                fault: None,
+            });
+        }
+
+        stop.then(|| RunInfo {
+            return_code: self.read(Register::A(0)),
+            reason: StopReason::Cancelled { pc: self.pc },
+            limit_hits: self.limits.hits,
+            output: self.captured_output(),
+            fault: None,
+        })
+    }
+
+    // runs the events due, those of any host fds ready first, and takes an
+    // interrupt if one can be
+    fn check_events(&mut self) {
+        if let Some(io) = &mut self.host_io {
+            for event in io.take_ready() {
+                self.events.schedule_at(self.instret, event);
+            }
+        }
+        for irq in self.events.run_due(self.instret) {
+            match &mut self.interrupts {
+                Some(interrupts) => interrupts.raise(irq, self.instret),
+                None => warn!(target: "irq", "irq {irq} raised without interrupts enabled"),
+            }
+        }
+
+        let Some(interrupts) = &mut self.interrupts else {
+            return;
+        };
+        if let Some(irq) = interrupts.pending() {
+            self.pc = interrupts.take(irq, self.pc, self.instret);
+        }
+    }
+
+    fn check_faults(&mut self) {
+        let Some(faults) = &mut self.faults else {
+            return;
+        };
+        for (target, bit) in faults.due(self.instret) {
+            match target {
+                Target::Reg(reg) if reg.is_fp() => {
+                    let idx = reg.to_idx();
+                    let bits = self.fp_regfile.read_u64(idx) ^ (1 << bit);
+                    self.fp_regfile.write_double(idx, f64::from_bits(bits));
+                }
+                Target::Reg(reg) => self.write(reg, self.read(reg) ^ (1 << bit)),
+                Target::Mem(addr) => {
+                    let Some(&byte) = self.memory().get(addr as usize) else {
+                        warn!(target: "inject", "{addr:#x} is outside guest memory, not flipped");
+                        continue;
+                    };
+                    let _ = self.write_memory(addr, &[byte ^ (1 << bit)]);
+                }
+            }
+            info!(
+                target: "inject",
+                "flipped bit {bit} of {target} at pc {:#x}, after {} instructions",
+                self.pc, self.instret
+            );
+        }
+    }
+
+    fn check_checkpoint(&mut self) {
+        let Some(checkpoint) = &self.checkpoint else {
+            return;
+        };
+        if self.instret < checkpoint.next {
+            return;
+        }
+
+        let path = checkpoint.path.clone();
+        // a failed checkpoint shouldn't take the run down with it
+        if let Err(err) = self.snapshot().save(&path) {
+            warn!(target: "checkpoint", "failed to write checkpoint {}: {err}", path.display());
+        }
+
+        let checkpoint = self.checkpoint.as_mut().unwrap();
+        checkpoint.next = self.instret + checkpoint.interval;
+    }
+
+    fn check_progress(&mut self) {
+        let Some(progress) = &self.progress else {
+            return;
+        };
+
+        if progress.due(self.instret) {
+            let report = ProgressReport {
+                elapsed: Default::default(),
+                instret: self.instret,
+                mips: 0.0,
+                pc: self.pc,
+                function: self
+                    .memory
+                    .elf
+                    .symbolize(self.pc as u64)
+                    .map(|(name, _)| name.to_string()),
+                syscalls: self
+                    .syscall_counts
+                    .iter()
+                    .map(|(&num, &count)| (num, count))
+                    .collect(),
+                resident_memory: self.memory.mapping.resident().unwrap_or(0),
+            };
+
+            self.progress.as_mut().unwrap().report(report);
+        }
+    }
+
+    fn check_sample(&mut self) {
+        let Some(sampler) = &mut self.sampler else {
+            return;
+        };
+        if !sampler.due(self.instret) {
+            return;
+        }
+
+        let stack = sampler
+            .call_stack(self.pc)
+            .unwrap_or_else(|| self.stack_trace());
+        self.sampler.as_mut().unwrap().record(stack);
+    }
+
+    #[inline(never)]
+    fn step_instrumented(&mut self, instr: Instruction) -> Option<RunInfo> {
+        let stopped = self.step_hooked(instr);
+        if stopped.is_none() && self.faults.as_ref().is_some_and(Faults::corrupts_loads) {
+            self.corrupt_load(instr);
+        }
+        stopped
+    }
+
+    // flips a bit of what `instr` just loaded, if it's a load and its turn
+    #[cold]
+    fn corrupt_load(&mut self, instr: Instruction) {
+        let (gp_dest, fp_dest) = (instr.gp_dest().filter(|&rd| rd != 0), instr.fp_dest());
+        let value = match (gp_dest, fp_dest) {
+            (Some(rd), _) => self.gp_regfile.read(rd) as u32 as u64,
+            (_, Some((rd, _))) => self.fp_regfile.read_u64(rd),
+            _ => return,
+        };
+        let Some(corrupted) = self
+            .faults
+            .as_mut()
+            .and_then(|faults| faults.corrupt_load(&instr, value))
+        else {
+            return;
+        };
+        match (gp_dest, fp_dest) {
+            (Some(rd), _) => self.gp_regfile.write(rd, corrupted as i32),
+            (_, Some((rd, _))) => self.fp_regfile.write_double(rd, f64::from_bits(corrupted)),
+            _ => unreachable!(),
+        }
+        info!(target: "inject", "corrupted {instr}, {value:#x} is now {corrupted:#x}");
+    }
+
+    fn step_hooked(&mut self, instr: Instruction) -> Option<RunInfo> {
+        if self.hooks.is_empty() {
+            return self.retire(instr);
+        }
+
+        let pc = self.pc;
+
+        let mut action = HookAction::Continue;
+        for hook in &mut self.hooks {
+            action = action.or(hook.before_instruction(pc, &instr));
+        }
+
+        if let Some(info) = self.finish_hook_action(action, pc) {
+            return Some(info);
+        }
+
+        if matches!(instr, Instruction::Ecall)
+            && self.hooks.iter().any(|hook| hook.wants_syscalls())
+        {
+            let syscall = Syscall {
+                pc,
+                num: self.read(Register::A(7)),
+                args: [0, 1, 2, 3, 4, 5].map(|n| self.read(Register::A(n)) as u32),
+            };
+            for hook in &mut self.hooks {
+                action = action.or(hook.on_syscall(&syscall));
+            }
+
+            if let Some(info) = self.finish_hook_action(action, pc) {
+                return Some(info);
+            }
+        }
+
+        if self.hooks.iter().any(|hook| hook.wants_mem_reads()) {
+            if let Some((addr, len)) = self.pending_mem_read(&instr) {
+                let read = MemRead {
+                    pc,
+                    instr,
+                    addr,
+                    len,
+                };
+                for hook in &mut self.hooks {
+                    action = action.or(hook.on_mem_read(&read));
+                }
+
+                if let Some(info) = self.finish_hook_action(action, pc) {
+                    return Some(info);
+                }
+            }
+        }
+
+        if self.hooks.iter().any(|hook| hook.wants_mem_writes()) {
+            if let Some((addr, len)) = self.pending_mem_write(&instr) {
+                let write = MemWrite {
+                    pc,
+                    instr,
+                    addr,
+                    len,
+                };
+                for hook in &mut self.hooks {
+                    action = action.or(hook.on_mem_write(&write));
+                }
+
+                if let Some(info) = self.finish_hook_action(action, pc) {
+                    return Some(info);
+                }
+            }
+        }
+
+        if self.hooks.iter().any(|hook| hook.wants_jumps()) {
+            if let Some(target) = self.jump_target(&instr) {
+                let jump = Jump {
+                    pc,
+                    instr,
+                    target,
+                    args: [0, 1, 2, 3, 4, 5, 6, 7].map(|n| self.read(Register::A(n)) as u32),
+                };
+                for hook in &mut self.hooks {
+                    action = action.or(hook.on_jump(&jump));
+                }
+
+                if let Some(info) = self.finish_hook_action(action, pc) {
+                    return Some(info);
+                }
+            }
+        }
+
+        // what the write is about to go over
+        let overwritten = if self.hooks.iter().any(|hook| hook.wants_mem_written()) {
+            self.pending_mem_write(&instr)
+                .and_then(|(addr, len)| Some((addr, self.read_virt(addr, len)?)))
+        } else {
+            None
+        };
+
+        if overwritten.is_none() && !self.hooks.iter().any(|hook| hook.wants_reg_writes()) {
+            return self.retire(instr);
+        }
+
+        let before = self.reg_snapshot();
+
+        if let Some(info) = self.retire(instr) {
+            return Some(info);
+        }
+
+        if let Some((addr, old)) = overwritten {
+            if let Some(new) = self.read_virt(addr, old.len() as u32) {
+                let written = MemWritten {
+                    pc,
+                    instr,
+                    addr,
+                    old: &old,
+                    new: &new,
+                };
+                for hook in &mut self.hooks {
+                    if hook.wants_mem_written() {
+                        action = action.or(hook.on_mem_written(&written));
+                    }
+                }
+            }
+        }
+
+        if !self.hooks.iter().any(|hook| hook.wants_reg_writes()) {
+            return self.finish_hook_action(action, pc);
+        }
+
+        let after = self.reg_snapshot();
+
+        let gp_dest = instr.gp_dest().filter(|&rd| rd != 0);
+        let fp_dest = instr.fp_dest().map(|(rd, _)| rd);
+
+        let writes = (0..64u8)
+            .filter_map(|idx| {
+                let (reg, dest) = if idx < 32 {
+                    (Register::gp(idx), gp_dest)
+                } else {
+                    (Register::fp(idx - 32), fp_dest)
+                };
+
+                let old = before[idx as usize];
+                let new = after[idx as usize];
+                (dest == Some(reg.to_idx()) || old != new).then_some(RegWrite {
+                    pc,
+                    instr,
+                    reg,
+                    old,
+                    new,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for write in &writes {
+            for hook in &mut self.hooks {
+                action = action.or(hook.on_reg_write(write));
+            }
+        }
+
+        self.finish_hook_action(action, pc)
+    }
+
+    /// The memory `instr` will read when executed at the current pc, if any
+    fn pending_mem_read(&self, instr: &Instruction) -> Option<(u32, u32)> {
+        let reg = |idx| self.gp_regfile.read(idx) as u32;
+        let a = |n| self.read(Register::A(n)) as u32;
+
+        let call_target = match *instr {
+            Instruction::Lb { rs1, imm, .. } | Instruction::Lbu { rs1, imm, .. } => {
+                return Some((reg(rs1).wrapping_add(imm as u32), 1))
+            }
+            Instruction::Lh { rs1, imm, .. } | Instruction::Lhu { rs1, imm, .. } => {
+                return Some((reg(rs1).wrapping_add(imm as u32), 2))
+            }
+            Instruction::Lw { rs1, imm, .. } | Instruction::Flw { rs1, imm, .. } => {
+                return Some((reg(rs1).wrapping_add(imm as u32), 4))
+            }
+            Instruction::Fld { rs1, imm, .. } => {
+                return Some((reg(rs1).wrapping_add(imm as u32), 8))
+            }
+            Instruction::ScW { .. } => return None,
+            _ if instr.is_atomic() => return Some((reg(instr.gp_sources()[0].unwrap()), 4)),
+            Instruction::Ecall if a(7) as i32 == SYSCALL_WRITE => return Some((a(1), a(2))),
+            Instruction::Ecall if a(7) as i32 == SYSCALL_IOCTL && !ioctl::writes_arg(a(1)) => {
+                return ioctl::arg_len(a(1)).map(|len| (a(2), len))
+            }
+            Instruction::Ecall if a(7) as i32 == SYSCALL_PRLIMIT64 && a(2) != 0 => {
+                return Some((a(2), limits::RLIMIT_SIZE))
+            }
+            Instruction::Jal { rd: 1, .. } | Instruction::Jalr { rd: 1, .. } => {
+                self.jump_target(instr).unwrap()
+            }
+            _ => return None,
+        };
+
+        [self.wk_memcpy, self.wk_memmove]
+            .contains(&call_target)
+            .then(|| (a(1), a(2)))
+    }
+
+    /// The memory `instr` will write when executed at the current pc, if any
+    fn pending_mem_write(&self, instr: &Instruction) -> Option<(u32, u32)> {
+        let reg = |idx| self.gp_regfile.read(idx) as u32;
+        let a = |n| self.read(Register::A(n)) as u32;
+
+        let call_target = match *instr {
+            Instruction::Sb { rs1, imm, .. } => {
+                return Some((reg(rs1).wrapping_add(imm as u32), 1))
+            }
+            Instruction::Sh { rs1, imm, .. } => {
+                return Some((reg(rs1).wrapping_add(imm as u32), 2))
+            }
+            Instruction::Sw { rs1, imm, .. } | Instruction::Fsw { rs1, imm, .. } => {
+                return Some((reg(rs1).wrapping_add(imm as u32), 4))
+            }
+            Instruction::Fsd { rs1, imm, .. } => {
+                return Some((reg(rs1).wrapping_add(imm as u32), 8))
+            }
+            Instruction::LrW { .. } => return None,
+            // unless they fail
+            Instruction::ScW { rs1, .. } => {
+                return (self.reservation == Some(reg(rs1))).then_some((reg(rs1), 4))
+            }
+            Instruction::AmocasW { rd, rs1, .. } => {
+                let old = self.memory.load::<u32>(reg(rs1))?;
+                return (old == reg(rd)).then_some((reg(rs1), 4));
+            }
+            _ if instr.is_atomic() => return Some((reg(instr.gp_sources()[0].unwrap()), 4)),
+            Instruction::Ecall => {
+                return match a(7) as i32 {
+                    SYSCALL_READ => Some((a(1), a(2))),
+                    SYSCALL_GETTIMEOFDAY if a(0) != 0 => Some((a(0), TIMEVAL_SIZE)),
+                    SYSCALL_CLOCK_GETTIME | SYSCALL_CLOCK_GETTIME64 => Some((a(1), TIMEVAL_SIZE)),
+                    SYSCALL_GETRANDOM => Some((a(0), a(1))),
+                    SYSCALL_FSTAT => Some((a(1), stat::STAT_SIZE)),
+                    SYSCALL_GETCWD => Some((a(0), a(1))),
+                    SYSCALL_GETDENTS64 => Some((a(1), a(2))),
+                    SYSCALL_IOCTL if ioctl::writes_arg(a(1)) => {
+                        ioctl::arg_len(a(1)).map(|len| (a(2), len))
+                    }
+                    SYSCALL_NEWFSTATAT => Some((a(2), stat::STAT_SIZE)),
+                    SYSCALL_PRLIMIT64 if a(3) != 0 => Some((a(3), limits::RLIMIT_SIZE)),
+                    SYSCALL_UNAME => Some((a(0), identity::UTSNAME_SIZE)),
+                    SYSCALL_GETRUSAGE => Some((a(1), limits::RUSAGE_SIZE)),
+                    SYSCALL_SYSINFO => Some((a(0), limits::SYSINFO_SIZE)),
+                    _ => None,
+                }
+            }
+            Instruction::Jal { rd: 1, .. } | Instruction::Jalr { rd: 1, .. } => {
+                self.jump_target(instr).unwrap()
+            }
+            _ => return None,
+        };
+
+        // calls serviced natively write without executing any stores
+        [self.wk_memset, self.wk_memcpy, self.wk_memmove]
+            .contains(&call_target)
+            .then(|| (a(0), a(2)))
+    }
+
+    /// Where `instr` will go when executed at the current pc, if it's a jump
+    fn jump_target(&self, instr: &Instruction) -> Option<u32> {
+        match *instr {
+            Instruction::Jal { imm, .. } => Some(self.pc.wrapping_add(imm as u32)),
+            Instruction::Jalr { rs1, imm, .. } => {
+                Some((self.gp_regfile.read(rs1) as u32).wrapping_add(imm as u32) & !1)
+            }
+            _ => None,
+        }
+    }
+
+    fn finish_hook_action(&self, action: HookAction, pc: u32) -> Option<RunInfo> {
+        match action {
+            HookAction::Continue => None,
+            HookAction::Break => Some(self.break_at(pc)),
+            HookAction::Stop(reason) => Some(RunInfo {
+                return_code: self.read(Register::A(0)),
+                reason,
+                limit_hits: self.limits.hits,
+                output: self.captured_output(),
+                fault: None,
+            }),
+        }
+    }
+
+    // integer registers, followed by fp registers
+    fn reg_snapshot(&self) -> [u64; 64] {
+        let mut regs = [0; 64];
+        for idx in 0..32u8 {
+            regs[idx as usize] = self.gp_regfile.read(idx) as u32 as u64;
+            regs[32 + idx as usize] = self.fp_regfile.read_u64(idx);
+        }
+        regs
+    }
+
+    #[cold]
+    fn break_at(&self, pc: u32) -> RunInfo {
+        self.dump_registers();
+
+        RunInfo {
+            return_code: self.read(Register::A(0)),
+            reason: StopReason::Breakpoint { pc },
+            limit_hits: self.limits.hits,
+            output: self.captured_output(),
+            fault: None,
+        }
+    }
+
+    #[inline(always)]
+    fn retire(&mut self, instr: Instruction) -> Option<RunInfo> {
+        let result = self.exec(instr);
+        self.complete(instr, result)
+    }
+
+    #[inline(never)]
+    fn complete_cold(&mut self, instr: Instruction, result: ExecResult) -> Option<RunInfo> {
+        self.complete(instr, result)
+    }
+
+    // acts on what `instr` did
+    #[inline(always)]
+    fn complete(&mut self, instr: Instruction, mut result: ExecResult) -> Option<RunInfo> {
+        // where `instr` is, whatever the handlers below do with the pc
+        let pc = self.pc;
+        if matches!(result, ExecResult::IllegalInstruction) && self.illegal_handler.is_some() {
+            result = self.emulate_illegal(instr);
+        }
+        if matches!(result, ExecResult::Htif) {
+            result = self.htif_command();
+        }
+        if let ExecResult::CodeWrite(addr) = result {
+            self.invalidate_code(addr & !3..addr.saturating_add(8));
+            result = ExecResult::Continue;
+        }
+        if matches!(result, ExecResult::DeviceWrite) {
+            // for any event it scheduled
+            self.update_next_check();
+            result = ExecResult::Continue;
+        }
+
+        match result {
+            ExecResult::Jump(pc) => {
+                if pc == self.return_address {
+                    return Some(RunInfo {
+                        return_code: self.read(Register::A(0)),
+                        reason: StopReason::Returned,
+                        limit_hits: self.limits.hits,
+                        output: self.captured_output(),
+                        fault: None,
+                    });
+                }
+
+                self.pc = pc;
+            }
+            ExecResult::Call(pc) => {
+                if self.pc == pc {
+                    // loop
+                    return Some(RunInfo {
+                        return_code: 0,
+                        reason: StopReason::Exited,
+                        limit_hits: self.limits.hits,
+                        output: self.captured_output(),
+                        fault: None,
+                    });
+                }
+
+                if let Some(&(_, action)) = self.stubs.iter().find(|&&(addr, _)| addr == pc) {
+                    match action {
+                        StubAction::Return(value) => self.write(Register::A(0), value as i32),
+                        StubAction::Ignore => {}
+                        StubAction::Fail => {
+                            let info = self.enter_fatal(FatalKind::Stub, pc);
+                            let name = self.memory.elf.symbolize(pc as u64).map(|(name, _)| name);
+                            if let (Some(fatal), Some(name)) = (&mut self.fatal, name) {
+                                fatal.message = Some(format!("{name} is stubbed to fail"));
+                            }
+                            return Some(info);
+                        }
+                    }
+
+                    self.pc = self.read(Register::Ra) as u32;
+                } else if let Some(idx) = self.natives.iter().position(|call| call.addr == pc) {
+                    self.call_native(idx);
+
+                    self.pc = self.read(Register::Ra) as u32;
+                } else if pc == self.wk_memset {
+                    let dst = self.read(Register::A(0));
+                    let value = self.read(Register::A(1));
+                    let count = self.read(Register::A(2));
+
+                    if !self.memory.spans(dst, count) {
+                        let reason = StopReason::StoreFault {
+                            pc: self.pc,
+                            addr: dst as u32,
+                        };
+                        return Some(self.fault_stop(self.pc, instr, reason));
+                    }
+                    self.memory.memset(dst, value, count);
+
+                    self.pc = self.read(Register::Ra) as u32;
+                } else if pc == self.wk_memcpy {
+                    let dst = self.read(Register::A(0));
+                    let src = self.read(Register::A(1));
+                    let count = self.read(Register::A(2));
+
+                    if let Some(info) = self.copy_fault(instr, dst, src, count) {
+                        return Some(info);
+                    }
+                    self.memory.memcpy(dst, src, count);
+
+                    self.pc = self.read(Register::Ra) as u32;
+                } else if pc == self.wk_memmove {
+                    let dst = self.read(Register::A(0));
+                    let src = self.read(Register::A(1));
+                    let count = self.read(Register::A(2));
+
+                    if let Some(info) = self.copy_fault(instr, dst, src, count) {
+                        return Some(info);
+                    }
+                    self.memory.memmove(dst, src, count);
+
+                    self.pc = self.read(Register::Ra) as u32;
+                } else if pc == self.wk_cos {
+                    let arg = self.fp_regfile.read_double(10);
+                    self.fp_regfile.write_double(10, arg.cos());
+
+                    self.pc = self.read(Register::Ra) as u32;
+                } else if pc == self.wk_sin {
+                    let arg = self.fp_regfile.read_double(10);
+                    self.fp_regfile.write_double(10, arg.sin());
+
+                    self.pc = self.read(Register::Ra) as u32;
+                } else if let Some(&(_, kind)) =
+                    self.fatal_fns.iter().find(|&&(addr, _)| addr == pc)
+                {
+                    return Some(self.enter_fatal(kind, pc));
+                } else {
                     self.pc = pc;
                 }
-                ExecResult::Call(pc) => {
-                    if self.pc == pc {
-                        // loop
-                        return RunInfo { return_code: 0 };
+            }
+            ExecResult::Continue => self.pc += 4,
+            ExecResult::Htif => unreachable!("htif commands are run above"),
+            ExecResult::CodeWrite(_) => unreachable!("code is invalidated above"),
+            ExecResult::DeviceWrite => unreachable!("device writes carry on above"),
+            ExecResult::Exit => return Some(self.get_exit_info()),
+            ExecResult::DeviceExit(code) => {
+                return Some(RunInfo {
+                    return_code: code,
+                    reason: StopReason::Exited,
+                    limit_hits: self.limits.hits,
+                    output: self.captured_output(),
+                    fault: None,
+                });
+            }
+            ExecResult::WouldBlock => {
+                // it'll be retired when it's retried
+                self.instret -= 1;
+                return Some(RunInfo {
+                    return_code: self.read(Register::A(0)),
+                    reason: StopReason::WouldBlock { pc },
+                    limit_hits: self.limits.hits,
+                    output: self.captured_output(),
+                    fault: None,
+                });
+            }
+            ExecResult::IllegalInstruction => {
+                return Some(RunInfo {
+                    return_code: self.read(Register::A(0)),
+                    reason: StopReason::IllegalInstruction {
+                        pc,
+                        inst: self.raw_instruction(pc, instr),
+                    },
+                    limit_hits: self.limits.hits,
+                    output: self.captured_output(),
+                    fault: Some(self.fault(pc, instr)),
+                });
+            }
+            ExecResult::RomWrite(addr) => {
+                return Some(self.fault_stop(pc, instr, StopReason::RomWrite { pc, addr }));
+            }
+            ExecResult::LoadFault(addr) => {
+                return Some(self.fault_stop(pc, instr, StopReason::LoadFault { pc, addr }));
+            }
+            ExecResult::StoreFault(addr) => {
+                return Some(self.fault_stop(pc, instr, StopReason::StoreFault { pc, addr }));
+            }
+            ExecResult::Ebreak => {
+                return Some(self.fault_stop(pc, instr, StopReason::Ebreak { pc }));
+            }
+            ExecResult::IoTimeout(fd) => {
+                // it'll be retired when it's retried
+                self.instret -= 1;
+                return Some(RunInfo {
+                    return_code: self.read(Register::A(0)),
+                    reason: StopReason::IoTimeout { pc, fd },
+                    limit_hits: self.limits.hits,
+                    output: self.captured_output(),
+                    fault: Some(self.fault(pc, instr)),
+                });
+            }
+            ExecResult::ReplayMismatch(index) => {
+                return Some(RunInfo {
+                    return_code: self.read(Register::A(0)),
+                    reason: StopReason::ReplayMismatch { pc, index },
+                    limit_hits: self.limits.hits,
+                    output: self.captured_output(),
+                    fault: Some(self.fault(pc, instr)),
+                });
+            }
+        }
+
+        None
+    }
+
+    /// The word `instr` at `pc` was decoded from
+    fn raw_instruction(&self, pc: u32, instr: Instruction) -> u32 {
+        // illegal instruction is also raised by valid encodings, e.g. accesses to
+        // missing csrs or privileged instructions
+        match instr {
+            Instruction::Unknown(inst) | Instruction::Custom { inst, .. } => inst,
+            _ => self
+                .memory
+                .as_slice()
+                .get(pc as usize..pc as usize + 4)
+                .map_or(0, |bytes| u32::from_le_bytes(bytes.try_into().unwrap())),
+        }
+    }
+
+    // the stop a `memcpy` or `memmove` the call `instr` makes, of `count` bytes
+    // from `src` to `dst`, makes, if either reaches past the end of memory
+    fn copy_fault(&self, instr: Instruction, dst: i32, src: i32, count: i32) -> Option<RunInfo> {
+        let pc = self.pc;
+        let reason = if !self.memory.spans(src, count) {
+            StopReason::LoadFault {
+                pc,
+                addr: src as u32,
+            }
+        } else if !self.memory.spans(dst, count) {
+            StopReason::StoreFault {
+                pc,
+                addr: dst as u32,
+            }
+        } else {
+            return None;
+        };
+        Some(self.fault_stop(pc, instr, reason))
+    }
+
+    #[cold]
+    fn fetch_fault(&self) -> RunInfo {
+        RunInfo {
+            return_code: self.read(Register::A(0)),
+            reason: StopReason::FetchFault { pc: self.pc },
+            limit_hits: self.limits.hits,
+            output: self.captured_output(),
+            fault: None,
+        }
+    }
+
+    // the stop `instr`, run at `pc`, made for `reason`
+    #[cold]
+    fn fault_stop(&self, pc: u32, instr: Instruction, reason: StopReason) -> RunInfo {
+        RunInfo {
+            return_code: self.read(Register::A(0)),
+            reason,
+            limit_hits: self.limits.hits,
+            output: self.captured_output(),
+            fault: Some(self.fault(pc, instr)),
+        }
+    }
+
+    /// `instr`, run at `pc`, for the report of a stop it made
+    fn fault(&self, pc: u32, instr: Instruction) -> Fault {
+        Fault {
+            pc,
+            raw: self.raw_instruction(pc, instr),
+            instr,
+            disassembly: pseudo::disassemble(pc, instr, None, &self.memory.elf.symbols),
+            panic: None,
+        }
+    }
+
+    /// Replaces the word at `rs1` with `op(word, rs2)`, and reads the old word
+    /// into `rd`
+    fn amo(&mut self, rd: u8, rs1: u8, rs2: u8, op: fn(i32, i32) -> i32) -> ExecResult {
+        let addr = self.gp_regfile.read(rs1) as u32;
+        let src = self.gp_regfile.read(rs2);
+        let Some(old) = self.memory.load::<u32>(addr) else {
+            return ExecResult::LoadFault(addr);
+        };
+        let old = old as i32;
+        let result = stored(addr, self.memory.store::<u32>(addr, op(old, src) as u32));
+        if !matches!(result, ExecResult::RomWrite(_) | ExecResult::StoreFault(_)) {
+            self.gp_regfile.write(rd, old);
+        }
+        result
+    }
+
+    /// Runs the handler for a custom instruction on a copy of the registers,
+    /// writing them back if it succeeds
+    fn exec_custom(&mut self, opcode: CustomOpcode, inst: u32) -> ExecResult {
+        let (gp_regs, fp_regs) = (self.gp_regs(), self.fp_regs());
+        let csrs = csr::VIEWED.map(|csr| self.read_csr(csr));
+        let Some(handler) = self.custom.get_mut(opcode) else {
+            return ExecResult::IllegalInstruction;
+        };
+
+        let mut machine =
+            Machine::new(self.pc, gp_regs, fp_regs, self.memory.as_mut_slice()).with_csrs(csrs);
+        let result = handler(inst, &mut machine).map(|()| machine.finish());
+        self.finish_custom(result, format_args!("{opcode} instruction"))
+    }
+
+    /// Gives the illegal instruction handler, if any, the chance to emulate
+    /// `instr` rather than trap
+    #[cold]
+    fn emulate_illegal(&mut self, instr: Instruction) -> ExecResult {
+        let inst = self.raw_instruction(self.pc, instr);
+        let (gp_regs, fp_regs) = (self.gp_regs(), self.fp_regs());
+        let csrs = csr::VIEWED.map(|csr| self.read_csr(csr));
+        let Some(handler) = &mut self.illegal_handler else {
+            return ExecResult::IllegalInstruction;
+        };
+
+        let mut machine =
+            Machine::new(self.pc, gp_regs, fp_regs, self.memory.as_mut_slice()).with_csrs(csrs);
+        let result = handler(inst, &mut machine).map(|()| machine.finish());
+        self.finish_custom(result, format_args!("illegal instruction handler"))
+    }
+
+    fn finish_custom(
+        &mut self,
+        result: Result<Outcome, CustomError>,
+        what: fmt::Arguments,
+    ) -> ExecResult {
+        let Outcome {
+            gp_regs,
+            fp_regs,
+            next_pc,
+            csrs,
+        } = match result {
+            Ok(outcome) => outcome,
+            Err(CustomError::Illegal) => return ExecResult::IllegalInstruction,
+            Err(err) => {
+                warn!(target: "custom", "{what} at pc {:#x} failed: {err}", self.pc);
+                return ExecResult::IllegalInstruction;
+            }
+        };
+        for (idx, &value) in gp_regs.iter().enumerate() {
+            self.gp_regfile.write(idx as u8, value);
+        }
+        for (idx, &bits) in fp_regs.iter().enumerate() {
+            self.fp_regfile
+                .write_double(idx as u8, f64::from_bits(bits));
+        }
+        for (csr, value) in csrs {
+            self.write_csr(csr, value);
+        }
+
+        match next_pc {
+            Some(pc) => ExecResult::Jump(pc),
+            None => ExecResult::Continue,
+        }
+    }
+
+    /// Reads `csr` into `rd` and, if given a source, writes back `op(old, src)`.
+    /// Like hardware, a `csrrs`/`csrrc` without a source never writes, so can
+    /// be used on read-only csrs
+    fn access_csr(
+        &mut self,
+        csr: u16,
+        rd: u8,
+        src: Option<u32>,
+        op: fn(u32, u32) -> u32,
+    ) -> ExecResult {
+        let Some(old) = self.read_csr(csr) else {
+            return ExecResult::IllegalInstruction;
+        };
+
+        if let Some(src) = src {
+            if csr::is_read_only(csr) || !self.write_csr(csr, op(old, src)) {
+                return ExecResult::IllegalInstruction;
+            }
+        }
+
+        self.gp_regfile.write(rd, old as i32);
+        ExecResult::Continue
+    }
+
+    /// Writes a `struct timeval`/`struct timespec`, which on rv32 are both a
+    /// 64-bit `time_t` followed by a `long`
+    fn write_timeval(&mut self, addr: u32, (secs, frac): (u64, u64)) {
+        let buf = self.memory.get_buf(addr, TIMEVAL_SIZE);
+        buf[..8].copy_from_slice(&secs.to_le_bytes());
+        buf[8..].copy_from_slice(&(frac as u32).to_le_bytes());
+    }
+
+    // `prlimit64` for the guest's own limits, as the only process there is
+    fn prlimit(&mut self, pid: i32, resource: u32, new: u32, old: u32) -> Result<(), i32> {
+        if pid != 0 {
+            return Err(limits::ESRCH);
+        }
+
+        let (soft, hard) = self.limits.rlimit(resource)?;
+        if new != 0 {
+            let buf = self.memory.get_buf(new, limits::RLIMIT_SIZE);
+            let word = |n: usize| u64::from_le_bytes(buf[n * 8..n * 8 + 8].try_into().unwrap());
+            let (soft, hard) = (word(0), word(1));
+            self.limits.set_rlimit(resource, soft, hard)?;
+        }
+        if old != 0 {
+            let buf = self.memory.get_buf(old, limits::RLIMIT_SIZE);
+            buf[..8].copy_from_slice(&soft.to_le_bytes());
+            buf[8..].copy_from_slice(&hard.to_le_bytes());
+        }
+        Ok(())
+    }
+
+    // fills in the guest's `struct stat` at `addr` if `st` is one, and returns
+    // 0 or the errno
+    fn write_stat(&mut self, addr: u32, st: Result<libc::stat, i32>) {
+        let ret = match st {
+            Ok(st) => {
+                self.memory
+                    .get_buf(addr, stat::STAT_SIZE)
+                    .copy_from_slice(&stat::encode(&st, &self.stat_spoof));
+                0
+            }
+            Err(errno) => -errno,
+        };
+        self.write(Register::A(0), ret);
+    }
+
+    /// Makes the syscall described by `a7` and `a0` to `a5`
+    fn ecall(&mut self) -> ExecResult {
+        let syscall = self.read(Register::A(7));
+        *self.syscall_counts.entry(syscall).or_insert(0) += 1;
+        debug!(
+            target: "syscall",
+            "{} at pc {:#x}",
+            syscall::describe(
+                syscall,
+                &[0, 1, 2, 3, 4, 5].map(|n| self.read(Register::A(n)) as u32)
+            ),
+            self.pc
+        );
+        if let Some(errno) = self
+            .faults
+            .as_mut()
+            .and_then(|faults| faults.syscall(syscall))
+        {
+            info!(
+                target: "inject",
+                "failed {} with {} at pc {:#x}",
+                syscall::describe(
+                    syscall,
+                    &[0, 1, 2, 3, 4, 5].map(|n| self.read(Register::A(n)) as u32)
+                ),
+                inject::describe_errno(errno),
+                self.pc
+            );
+            self.write(Register::A(0), -errno);
+            return ExecResult::Continue;
+        }
+        // as the kernel refuses a buffer that isn't mapped
+        let buffers = [
+            self.pending_mem_read(&Instruction::Ecall),
+            self.pending_mem_write(&Instruction::Ecall),
+        ];
+        if buffers
+            .into_iter()
+            .flatten()
+            .any(|(addr, len)| len != 0 && !self.memory.spans(addr as i32, len as i32))
+        {
+            self.write(Register::A(0), -EFAULT);
+            return ExecResult::Continue;
+        }
+        if self.syscall_log.is_some() {
+            self.logged_syscall(syscall)
+        } else {
+            self.syscall(syscall)
+        }
+    }
+
+    /// Runs the command just written to `tohost`, see `htif`
+    #[cold]
+    fn htif_command(&mut self) -> ExecResult {
+        let tohost = self.memory.tohost as u32;
+        let Some(command) = self.memory.load::<u64>(tohost) else {
+            return ExecResult::LoadFault(tohost);
+        };
+        let command = htif::Command::decode(command);
+        match command {
+            htif::Command::Exit(code) => {
+                self.write(Register::A(0), code);
+                return ExecResult::Exit;
+            }
+            htif::Command::Syscall(magic_mem) => {
+                let result = self.htif_syscall(magic_mem);
+                if !matches!(result, ExecResult::Continue) {
+                    return result;
+                }
+            }
+            htif::Command::Putchar(ch) => {
+                if self.limits.write(1).is_ok() {
+                    let captured = self
+                        .captured
+                        .as_mut()
+                        .is_some_and(|capture| capture.write(1, &[ch]));
+                    match &self.mux {
+                        _ if captured => {}
+                        Some(mux) => write_muxed(mux, Stream::Htif, &[ch], self.instret),
+                        None => {
+                            if let Some(host) = self.fds.host(1) {
+                                fds::write(host, &[ch]);
+                            }
+                        }
+                    }
+                }
+            }
+            htif::Command::Unsupported { device, cmd } => {
+                warn!(target: "syscall", "unsupported htif command {cmd} to device {device}");
+            }
+        }
+
+        self.memory.get_buf(tohost, 8).fill(0);
+        if let (Some(fromhost), Some(value)) = (self.fromhost, htif::response(command)) {
+            self.memory
+                .get_buf(fromhost, 8)
+                .copy_from_slice(&value.to_le_bytes());
+        }
+        ExecResult::Continue
+    }
+
+    /// Makes the syscall described in the `magic_mem` at `addr` as `ecall`
+    /// would, putting the result back in it
+    fn htif_syscall(&mut self, addr: u32) -> ExecResult {
+        let mut magic_mem = [0; htif::MAGIC_MEM_WORDS];
+        for (n, word) in magic_mem.iter_mut().enumerate() {
+            let at = addr.wrapping_add(n as u32 * 8);
+            let Some(value) = self.memory.load::<u64>(at) else {
+                return ExecResult::LoadFault(at);
+            };
+            *word = value;
+        }
+
+        // `ecall` takes its arguments from registers, so they're borrowed
+        let regs = [0, 1, 2, 3, 4, 5, 7].map(|n| self.read(Register::A(n)));
+        for (n, &arg) in magic_mem[1..7].iter().enumerate() {
+            self.write(Register::A(n), arg as i32);
+        }
+        self.write(Register::A(7), magic_mem[0] as i32);
+
+        let result = self.ecall();
+        let ret = self.read(Register::A(0));
+        for (n, reg) in [0, 1, 2, 3, 4, 5, 7].into_iter().zip(regs) {
+            self.write(Register::A(n), reg);
+        }
+
+        match result {
+            ExecResult::Continue => {
+                self.memory
+                    .get_buf(addr, 8)
+                    .copy_from_slice(&(ret as i64).to_le_bytes());
+            }
+            // with the exit code where `get_exit_info` looks for it
+            ExecResult::Exit => self.write(Register::A(0), ret),
+            _ => {}
+        }
+        result
+    }
+
+    /// Services syscall `syscall` from the host
+    fn syscall(&mut self, syscall: i32) -> ExecResult {
+        match syscall {
+            SYSCALL_EXIT => return ExecResult::Exit,
+            SYSCALL_WRITE => {
+                let fd = self.read(Register::A(0));
+                let buf = self.read(Register::A(1));
+                let count = self.read(Register::A(2));
+
+                let buf = self.memory.get_buf(buf as u32, count as u32);
+
+                if fd == 2 {
+                    if let Some(fatal) = fatal::scan_stderr(buf) {
+                        self.stderr_fatal = Some(fatal);
+                    }
+                }
+
+                let buf = match self.limits.write(buf.len()) {
+                    Ok(len) => &buf[..len],
+                    Err(errno) => {
+                        self.write(Register::A(0), -errno);
+                        return ExecResult::Continue;
+                    }
+                };
+
+                let captured = self
+                    .captured
+                    .as_mut()
+                    .is_some_and(|capture| capture.write(fd, buf));
+                let muxed = match (&self.mux, fd) {
+                    (Some(mux), 1 | 2) if !captured => {
+                        let stream = if fd == 1 {
+                            Stream::Stdout
+                        } else {
+                            Stream::Stderr
+                        };
+                        write_muxed(mux, stream, buf, self.instret);
+                        true
+                    }
+                    _ => false,
+                };
+                let ret = match self.fds.host(fd) {
+                    _ if captured || muxed => buf.len() as i32,
+                    Some(host) => {
+                        if self
+                            .io_limits
+                            .is_some_and(|io| !io.wait(host, libc::POLLOUT))
+                        {
+                            return self.io_timed_out(syscall, fd);
+                        }
+                        fds::write(host, buf)
+                    }
+                    None => -fds::EBADF,
+                };
+
+                self.write(Register::A(0), ret);
+            }
+            SYSCALL_READ => {
+                let fd = self.read(Register::A(0));
+                let buf = self.read(Register::A(1));
+                let count = self.read(Register::A(2));
+
+                let buf = self.memory.get_buf(buf as u32, count as u32);
+
+                let ret = match (&mut self.stdin, fd) {
+                    (Some(Stdin::Buffer(stdin)), 0) => stdin.read(buf).expect("read failed") as i32,
+                    (Some(Stdin::Pipe(pipe)), 0) => match pipe.read(buf) {
+                        Some(count) => count as i32,
+                        None => {
+                            *self.syscall_counts.get_mut(&syscall).unwrap() -= 1;
+                            return ExecResult::WouldBlock;
+                        }
+                    },
+                    _ => match self.fds.host(fd) {
+                        Some(host) => {
+                            if self
+                                .io_limits
+                                .is_some_and(|io| !io.wait(host, libc::POLLIN))
+                            {
+                                return self.io_timed_out(syscall, fd);
+                            }
+                            fds::read(host, buf)
+                        }
+                        None => -fds::EBADF,
+                    },
+                };
+
+                self.write(Register::A(0), ret);
+            }
+            SYSCALL_OPENAT => {
+                let dirfd = self.read(Register::A(0));
+                let path = self.read(Register::A(1)) as u32;
+                let flags = self.read(Register::A(2));
+                let mode = self.read(Register::A(3)) as u32;
+
+                let path = self.memory.c_str(path);
+                let synthetic = self
+                    .fake_proc
+                    .then(|| identity::proc_file(path, &self.memory.map, self.heap_start, self.brk))
+                    .flatten();
+                let ret = self
+                    .limits
+                    .open(self.fds.len())
+                    .and_then(|()| match synthetic {
+                        Some(contents) => self.fds.open_synthetic(&contents),
+                        None => self.fds.openat(dirfd, path, flags, mode),
+                    })
+                    .unwrap_or_else(|errno| -errno);
+                self.write(Register::A(0), ret);
+            }
+            SYSCALL_CLOSE => {
+                let fd = self.read(Register::A(0));
+                let ret = self.fds.close(fd).map_or_else(|errno| -errno, |()| 0);
+                self.write(Register::A(0), ret);
+            }
+            SYSCALL_IOCTL => {
+                let fd = self.read(Register::A(0));
+                let request = self.read(Register::A(1)) as u32;
+                let arg = self.read(Register::A(2)) as u32;
+
+                let ret = match ioctl::arg_len(request) {
+                    None => -ioctl::unsupported(request),
+                    Some(_) if !self.fds.is_open(fd) => -fds::EBADF,
+                    Some(len) => {
+                        // stdio that isn't the host's never is a terminal
+                        let host = match fd {
+                            0 if self.stdin.is_some() => None,
+                            1 | 2 if self.captured.is_some() => None,
+                            _ => self.fds.host(fd),
+                        };
+                        let arg = self.memory.get_buf(arg, len);
+                        self.terminals
+                            .ioctl(fd, host, request, arg)
+                            .map_or_else(|errno| -errno, |()| 0)
+                    }
+                };
+                self.write(Register::A(0), ret);
+            }
+            SYSCALL_GETCWD => {
+                let buf = self.read(Register::A(0)) as u32;
+                let size = self.read(Register::A(1)) as u32;
+
+                // the length with the NUL, as the kernel returns it
+                let ret = match self.fds.getcwd() {
+                    Ok(cwd) if cwd.len() < size as usize => {
+                        let len = cwd.len();
+                        let buf = self.memory.get_buf(buf, len as u32 + 1);
+                        buf[..len].copy_from_slice(cwd);
+                        buf[len] = 0;
+                        len as i32 + 1
+                    }
+                    Ok(_) => -fds::ERANGE,
+                    Err(errno) => -errno,
+                };
+                self.write(Register::A(0), ret);
+            }
+            SYSCALL_CHDIR => {
+                let path = self.read(Register::A(0)) as u32;
+
+                let path = self.memory.c_str(path).to_vec();
+                let ret = self.fds.chdir(&path).map_or_else(|errno| -errno, |()| 0);
+                self.write(Register::A(0), ret);
+            }
+            SYSCALL_MKDIRAT => {
+                let dirfd = self.read(Register::A(0));
+                let path = self.read(Register::A(1)) as u32;
+                let mode = self.read(Register::A(2)) as u32;
+
+                let path = self.memory.c_str(path);
+                let ret = self
+                    .fds
+                    .mkdirat(dirfd, path, mode)
+                    .map_or_else(|errno| -errno, |()| 0);
+                self.write(Register::A(0), ret);
+            }
+            SYSCALL_RENAMEAT | SYSCALL_RENAMEAT2 => {
+                let olddirfd = self.read(Register::A(0));
+                let old = self.read(Register::A(1)) as u32;
+                let newdirfd = self.read(Register::A(2));
+                let new = self.read(Register::A(3)) as u32;
+                let flags = match syscall {
+                    SYSCALL_RENAMEAT2 => self.read(Register::A(4)) as u32,
+                    _ => 0,
+                };
+
+                let (old, new) = (self.memory.c_str(old), self.memory.c_str(new));
+                let ret = self
+                    .fds
+                    .renameat(olddirfd, old, newdirfd, new, flags)
+                    .map_or_else(|errno| -errno, |()| 0);
+                self.write(Register::A(0), ret);
+            }
+            SYSCALL_GETDENTS64 => {
+                let fd = self.read(Register::A(0));
+                let dirp = self.read(Register::A(1)) as u32;
+                let count = self.read(Register::A(2)) as u32;
+
+                let buf = self.memory.get_buf(dirp, count);
+                let ret = self
+                    .fds
+                    .getdents(fd, buf)
+                    .map_or_else(|errno| -errno, |len| len as i32);
+                self.write(Register::A(0), ret);
+            }
+            SYSCALL_FSTAT => {
+                let fd = self.read(Register::A(0));
+                let statbuf = self.read(Register::A(1)) as u32;
+
+                let st = self.fds.fstat(fd);
+                self.write_stat(statbuf, st);
+            }
+            // the flags are the host's, as both use the generic Linux numbering
+            SYSCALL_NEWFSTATAT => {
+                let dirfd = self.read(Register::A(0));
+                let path = self.read(Register::A(1)) as u32;
+                let statbuf = self.read(Register::A(2)) as u32;
+                let flags = self.read(Register::A(3));
+
+                let st = self.fds.fstatat(dirfd, self.memory.c_str(path), flags);
+                self.write_stat(statbuf, st);
+            }
+            // the heap is all of memory already, so this only moves the
+            // break. Like Linux, a break that can't be had leaves it
+            // where it was, which libc turns into `ENOMEM`
+            SYSCALL_BRK => {
+                let addr = self.read(Register::A(0)) as u32;
+                if addr >= self.heap_start
+                    && (addr as usize) < self.memory.size()
+                    && self.limits.heap((addr - self.heap_start) as u64)
+                {
+                    self.brk = addr;
+                }
+                self.write(Register::A(0), self.brk as i32);
+            }
+            SYSCALL_UNAME => {
+                let buf = self.read(Register::A(0)) as u32;
+                self.memory
+                    .get_buf(buf, identity::UTSNAME_SIZE)
+                    .copy_from_slice(&self.uname.encode());
+                self.write(Register::A(0), 0);
+            }
+            SYSCALL_PRLIMIT64 => {
+                let pid = self.read(Register::A(0));
+                let resource = self.read(Register::A(1)) as u32;
+                let new = self.read(Register::A(2)) as u32;
+                let old = self.read(Register::A(3)) as u32;
+
+                let ret = self
+                    .prlimit(pid, resource, new, old)
+                    .map_or_else(|errno| -errno, |()| 0);
+                self.write(Register::A(0), ret);
+            }
+            SYSCALL_GETRUSAGE => {
+                let who = self.read(Register::A(0));
+                let usage = self.read(Register::A(1)) as u32;
+
+                let ns = self.nondet.monotonic_ns(self.instret);
+                let rss = (self.brk - self.heap_start) as u64;
+                let ret = match limits::rusage(who, nondet::split_ns(ns, 1_000), rss) {
+                    Ok(buf) => {
+                        self.memory
+                            .get_buf(usage, limits::RUSAGE_SIZE)
+                            .copy_from_slice(&buf);
+                        0
                     }
+                    Err(errno) => -errno,
+                };
+                self.write(Register::A(0), ret);
+            }
+            SYSCALL_SYSINFO => {
+                let info = self.read(Register::A(0)) as u32;
+
+                let (uptime, _) = nondet::split_ns(self.nondet.monotonic_ns(self.instret), 1);
+                let total = self.memory.size() as u64;
+                let buf = limits::sysinfo(uptime, total, total - self.brk as u64);
+                self.memory
+                    .get_buf(info, limits::SYSINFO_SIZE)
+                    .copy_from_slice(&buf);
+                self.write(Register::A(0), 0);
+            }
+            SYSCALL_GETTIMEOFDAY => {
+                let tv = self.read(Register::A(0)) as u32;
+                if tv != 0 {
+                    let ns = self.nondet.realtime_ns(self.instret);
+                    self.write_timeval(tv, nondet::split_ns(ns, 1_000));
+                }
+                self.write(Register::A(0), 0);
+            }
+            SYSCALL_CLOCK_GETTIME | SYSCALL_CLOCK_GETTIME64 => {
+                // every other clock is treated as monotonic
+                let ns = match self.read(Register::A(0)) {
+                    CLOCK_REALTIME => self.nondet.realtime_ns(self.instret),
+                    _ => self.nondet.monotonic_ns(self.instret),
+                };
+                let ts = self.read(Register::A(1)) as u32;
+                self.write_timeval(ts, nondet::split_ns(ns, 1));
+                self.write(Register::A(0), 0);
+            }
+            SYSCALL_GETRANDOM => {
+                let buf = self.read(Register::A(0));
+                let count = self.read(Register::A(1));
 
-                    if pc == wk_memset {
-                        let dst = self.read(Register::A(0));
-                        let value = self.read(Register::A(1));
-                        let count = self.read(Register::A(2));
+                let buf = self.memory.get_buf(buf as u32, count as u32);
+                self.nondet.fill_random(buf);
 
-                        self.memory.memset(dst, value, count);
+                self.write(Register::A(0), count);
+            }
+            SYSCALL_HOSTCALL => {
+                let id = self.read(Register::A(6)) as u32;
+                let args = [0, 1, 2, 3, 4, 5].map(|n| self.read(Register::A(n)) as u32);
 
-                        self.pc = self.read(Register::Ra) as u32;
-                    } else if pc == wk_memcpy {
-                        let dst = self.read(Register::A(0));
-                        let src = self.read(Register::A(1));
-                        let count = self.read(Register::A(2));
+                let mut ctx = HostCtx::new(args, self.memory.as_mut_slice());
+                let ret = self.hostcalls.call(id, &mut ctx);
 
-                        self.memory.memcpy(dst, src, count);
+                self.write(Register::A(0), ret);
+            }
+            _ => warn!(target: "syscall", "unknown syscall '{syscall}'"),
+            // _ => panic!("unknown syscall '{syscall}'"),
+        }
+        ExecResult::Continue
+    }
 
-                        self.pc = self.read(Register::Ra) as u32;
-                    } else if pc == wk_memmove {
-                        let dst = self.read(Register::A(0));
-                        let src = self.read(Register::A(1));
-                        let count = self.read(Register::A(2));
+    /// Services syscall `syscall` while recording or replaying, see `replay`
+    #[cold]
+    fn logged_syscall(&mut self, syscall: i32) -> ExecResult {
+        if syscall == SYSCALL_HOSTCALL {
+            return self.syscall(syscall);
+        }
 
-                        self.memory.memmove(dst, src, count);
+        let args = [0, 1, 2, 3, 4, 5].map(|n| self.read(Register::A(n)) as u32);
+        let input = match syscall {
+            SYSCALL_OPENAT | SYSCALL_NEWFSTATAT | SYSCALL_MKDIRAT => {
+                self.memory.c_str(args[1]).to_vec()
+            }
+            SYSCALL_CHDIR => self.memory.c_str(args[0]).to_vec(),
+            // both paths, with the NUL between them
+            SYSCALL_RENAMEAT | SYSCALL_RENAMEAT2 => {
+                [self.memory.c_str(args[1]), self.memory.c_str(args[3])].join(&0)
+            }
+            _ => match self.pending_mem_read(&Instruction::Ecall) {
+                Some((addr, len)) => self.memory.get_buf(addr, len).to_vec(),
+                None => Vec::new(),
+            },
+        };
+        // before the syscall, which may overwrite the arguments
+        let output = self.pending_mem_write(&Instruction::Ecall);
 
-                        self.pc = self.read(Register::Ra) as u32;
-                    } else if pc == wk_cos {
-                        let arg = self.fp_regfile.read_double(10);
-                        self.fp_regfile.write_double(10, arg.cos());
+        let Some(SyscallLog::Replay { tape, next }) = &mut self.syscall_log else {
+            let result = self.syscall(syscall);
+            // only what completed, as anything else is retried
+            if matches!(result, ExecResult::Continue | ExecResult::Exit) {
+                let ret = self.read(Register::A(0));
+                let output = match output {
+                    Some((addr, len)) => {
+                        let len = match syscall {
+                            SYSCALL_READ | SYSCALL_GETCWD | SYSCALL_GETDENTS64 => {
+                                len.min(ret.max(0) as u32)
+                            }
+                            _ => len,
+                        };
+                        self.memory.get_buf(addr, len).to_vec()
+                    }
+                    None => Vec::new(),
+                };
+                if let Some(SyscallLog::Record(tape)) = &mut self.syscall_log {
+                    tape.records.push(SyscallRecord {
+                        num: syscall,
+                        args,
+                        input,
+                        ret,
+                        output,
+                    });
+                }
+            }
+            return result;
+        };
 
-                        self.pc = self.read(Register::Ra) as u32;
-                    } else if pc == wk_sin {
-                        let arg = self.fp_regfile.read_double(10);
-                        self.fp_regfile.write_double(10, arg.sin());
+        let index = *next;
+        let record = match tape.records.get(index) {
+            Some(record)
+                if record.num == syscall && record.args == args && record.input == input =>
+            {
+                *next += 1;
+                record.clone()
+            }
+            Some(_) => {
+                warn!(
+                    target: "syscall",
+                    "{} doesn't match syscall {index} of the recording",
+                    syscall::describe(syscall, &args)
+                );
+                return ExecResult::ReplayMismatch(index);
+            }
+            None => {
+                warn!(
+                    target: "syscall",
+                    "{} is past the end of the recording",
+                    syscall::describe(syscall, &args)
+                );
+                return ExecResult::ReplayMismatch(index);
+            }
+        };
 
-                        self.pc = self.read(Register::Ra) as u32;
-                    } else {
-                        self.pc = pc;
-                    }
+        match syscall {
+            SYSCALL_EXIT => return ExecResult::Exit,
+            SYSCALL_WRITE if args[0] == 2 => {
+                if let Some(fatal) = fatal::scan_stderr(&input) {
+                    self.stderr_fatal = Some(fatal);
                 }
-                ExecResult::Continue => self.pc += 4,
-                ExecResult::Exit => return self.get_exit_info(),
             }
+            _ => {}
+        }
+        if let Some((addr, len)) = output {
+            let len = (record.output.len() as u32).min(len);
+            self.memory
+                .get_buf(addr, len)
+                .copy_from_slice(&record.output[..len as usize]);
         }
+        self.write(Register::A(0), record.ret);
+        ExecResult::Continue
     }
 
+    // inlined into the run loop, so the core's hot state can stay in host
+    // registers from one instruction to the next
+    #[inline(always)]
     fn exec(&mut self, instr: Instruction) -> ExecResult {
         let fp_reg = &mut self.fp_regfile;
         let reg = &mut self.gp_regfile;
@@ -619,63 +4246,78 @@ impl<Reader: MemReader<Idx = u32>> Core32<Reader> {
             }
             Instruction::Lb { rd, rs1, imm } => {
                 let addr = (reg.read(rs1) as u32).wrapping_add(imm as u32);
-                let val = self.memory.load::<i8>(addr) as i32;
-                reg.write(rd, val);
+                let Some(val) = self.memory.load::<i8>(addr) else {
+                    return ExecResult::LoadFault(addr);
+                };
+                reg.write(rd, val as i32);
             }
             Instruction::Lh { rd, rs1, imm } => {
                 let addr = (reg.read(rs1) as u32).wrapping_add(imm as u32);
-                let val = self.memory.load::<i16>(addr) as i32;
-                reg.write(rd, val);
+                let Some(val) = self.memory.load::<i16>(addr) else {
+                    return ExecResult::LoadFault(addr);
+                };
+                reg.write(rd, val as i32);
             }
             Instruction::Lw { rd, rs1, imm } => {
                 let addr = (reg.read(rs1) as u32).wrapping_add(imm as u32);
-                let val = self.memory.load::<u32>(addr) as i32;
-                reg.write(rd, val);
+                let Some(val) = self.memory.load::<u32>(addr) else {
+                    return ExecResult::LoadFault(addr);
+                };
+                reg.write(rd, val as i32);
             }
             Instruction::Lbu { rd, rs1, imm } => {
                 let addr = (reg.read(rs1) as u32).wrapping_add(imm as u32);
-                let val = self.memory.load::<u8>(addr) as i32;
-                reg.write(rd, val);
+                let Some(val) = self.memory.load::<u8>(addr) else {
+                    return ExecResult::LoadFault(addr);
+                };
+                reg.write(rd, val as i32);
             }
             Instruction::Lhu { rd, rs1, imm } => {
                 let addr = (reg.read(rs1) as u32).wrapping_add(imm as u32);
-                let val = self.memory.load::<u16>(addr) as i32;
-                reg.write(rd, val);
+                let Some(val) = self.memory.load::<u16>(addr) else {
+                    return ExecResult::LoadFault(addr);
+                };
+                reg.write(rd, val as i32);
             }
             Instruction::Flw { rd, rs1, imm } => {
                 let addr = (reg.read(rs1) as u32).wrapping_add(imm as u32);
-                let val = self.memory.load::<f32>(addr);
+                let Some(val) = self.memory.load::<f32>(addr) else {
+                    return ExecResult::LoadFault(addr);
+                };
                 fp_reg.write_single(rd, val);
             }
             Instruction::Fld { rd, rs1, imm } => {
                 let addr = (reg.read(rs1) as u32).wrapping_add(imm as u32);
-                let val = self.memory.load::<f64>(addr);
+                let Some(val) = self.memory.load::<f64>(addr) else {
+                    return ExecResult::LoadFault(addr);
+                };
                 fp_reg.write_double(rd, val);
             }
             Instruction::Sb { rs1, rs2, imm } => {
                 let addr = (reg.read(rs1) as u32).wrapping_add(imm as u32);
                 let val = reg.read(rs2) as u8;
-                self.memory.store::<u8>(addr, val);
+                return stored(addr, self.memory.store::<u8>(addr, val));
             }
             Instruction::Sh { rs1, rs2, imm } => {
                 let addr = (reg.read(rs1) as u32).wrapping_add(imm as u32);
                 let val = reg.read(rs2) as u16;
-                self.memory.store::<u16>(addr, val);
+                return stored(addr, self.memory.store::<u16>(addr, val));
             }
             Instruction::Sw { rs1, rs2, imm } => {
                 let addr = (reg.read(rs1) as u32).wrapping_add(imm as u32);
                 let val = reg.read(rs2) as u32;
-                self.memory.store::<u32>(addr, val);
+                return stored(addr, self.memory.store::<u32>(addr, val));
             }
             Instruction::Fsw { rs1, rs2, imm } => {
                 let addr = (reg.read(rs1) as u32).wrapping_add(imm as u32);
-                let val = fp_reg.read_single(rs2);
-                self.memory.store::<f32>(addr, val);
+                // the low half as it is, boxed or not
+                let val = fp_reg.read_u32(rs2);
+                return stored(addr, self.memory.store::<u32>(addr, val));
             }
             Instruction::Fsd { rs1, rs2, imm } => {
                 let addr = (reg.read(rs1) as u32).wrapping_add(imm as u32);
                 let val = fp_reg.read_double(rs2);
-                self.memory.store::<f64>(addr, val);
+                return stored(addr, self.memory.store::<f64>(addr, val));
             }
             Instruction::Addi { rd, rs1, imm } => {
                 let res = reg.read(rs1).wrapping_add(imm);
@@ -804,14 +4446,7 @@ impl<Reader: MemReader<Idx = u32>> Core32<Reader> {
             Instruction::Divu { rd, rs1, rs2 } => {
                 let dividend = reg.read(rs1) as u32;
                 let divisor = reg.read(rs2) as u32;
-                reg.write(
-                    rd,
-                    if divisor == 0 {
-                        -1
-                    } else {
-                        (dividend / divisor) as i32
-                    },
-                );
+                reg.write(rd, dividend.checked_div(divisor).map_or(-1, |q| q as i32));
             }
             Instruction::Rem { rd, rs1, rs2 } => {
                 let dividend = reg.read(rs1);
@@ -840,16 +4475,84 @@ impl<Reader: MemReader<Idx = u32>> Core32<Reader> {
                 );
             }
 
+            Instruction::LrW { rd, rs1, .. } => {
+                let addr = reg.read(rs1) as u32;
+                let Some(val) = self.memory.load::<u32>(addr) else {
+                    return ExecResult::LoadFault(addr);
+                };
+                reg.write(rd, val as i32);
+                self.reservation = Some(addr);
+            }
+            Instruction::ScW { rd, rs1, rs2, .. } => {
+                let addr = reg.read(rs1) as u32;
+                // there are no other harts to break a reservation, only another
+                // `sc.w`, so this is a plain compare of addresses
+                let success = self.reservation.take() == Some(addr);
+                let result = if success {
+                    stored(addr, self.memory.store::<u32>(addr, reg.read(rs2) as u32))
+                } else {
+                    ExecResult::Continue
+                };
+                if !matches!(result, ExecResult::RomWrite(_) | ExecResult::StoreFault(_)) {
+                    self.gp_regfile.write(rd, !success as i32);
+                }
+                return result;
+            }
+            Instruction::AmoswapW { rd, rs1, rs2, .. } => {
+                return self.amo(rd, rs1, rs2, |_, src| src);
+            }
+            Instruction::AmoaddW { rd, rs1, rs2, .. } => {
+                return self.amo(rd, rs1, rs2, |old, src| old.wrapping_add(src));
+            }
+            Instruction::AmoxorW { rd, rs1, rs2, .. } => {
+                return self.amo(rd, rs1, rs2, |old, src| old ^ src);
+            }
+            Instruction::AmoandW { rd, rs1, rs2, .. } => {
+                return self.amo(rd, rs1, rs2, |old, src| old & src);
+            }
+            Instruction::AmoorW { rd, rs1, rs2, .. } => {
+                return self.amo(rd, rs1, rs2, |old, src| old | src);
+            }
+            Instruction::AmominW { rd, rs1, rs2, .. } => {
+                return self.amo(rd, rs1, rs2, |old, src| old.min(src));
+            }
+            Instruction::AmomaxW { rd, rs1, rs2, .. } => {
+                return self.amo(rd, rs1, rs2, |old, src| old.max(src));
+            }
+            Instruction::AmominuW { rd, rs1, rs2, .. } => {
+                return self.amo(rd, rs1, rs2, |old, src| (old as u32).min(src as u32) as i32);
+            }
+            Instruction::AmomaxuW { rd, rs1, rs2, .. } => {
+                return self.amo(rd, rs1, rs2, |old, src| (old as u32).max(src as u32) as i32);
+            }
+            Instruction::AmocasW { rd, rs1, rs2, .. } => {
+                let addr = reg.read(rs1) as u32;
+                let Some(old) = self.memory.load::<u32>(addr) else {
+                    return ExecResult::LoadFault(addr);
+                };
+                let old = old as i32;
+                let result = if old == reg.read(rd) {
+                    stored(addr, self.memory.store::<u32>(addr, reg.read(rs2) as u32))
+                } else {
+                    ExecResult::Continue
+                };
+                if !matches!(result, ExecResult::RomWrite(_) | ExecResult::StoreFault(_)) {
+                    self.gp_regfile.write(rd, old);
+                }
+                return result;
+            }
+            // only this hart could invalidate the reservation set being waited
+            // on, so waiting would be forever; the spec allows giving up at once
+            Instruction::WrsNto | Instruction::WrsSto => { /* no-op */ }
+
             // f/d arithmetic using fp_reg
-            Instruction::FaddS {
-                rd,
-                rs1,
-                rs2,
-                rm: _,
-            } => {
+            Instruction::FaddS { rd, rs1, rs2, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_single(rs1);
                 let b = fp_reg.read_single(rs2);
-                fp_reg.write_single(rd, a + b);
+                fp_reg.write_single(rd, add_s(a, b, rm));
             }
 
             Instruction::FclassS { rd, rs1 } => {
@@ -932,140 +4635,164 @@ impl<Reader: MemReader<Idx = u32>> Core32<Reader> {
 
                 reg.write(rd, mask);
             }
-            Instruction::FsqrtS { rd, rs1, rm: _ } => {
+            Instruction::FsqrtS { rd, rs1, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_single(rs1);
-                fp_reg.write_single(rd, a.sqrt());
+                fp_reg.write_single(rd, sqrt_s(a, rm));
             }
-            Instruction::FsqrtD { rd, rs1, rm: _ } => {
+            Instruction::FsqrtD { rd, rs1, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_double(rs1);
-                fp_reg.write_double(rd, a.sqrt());
+                fp_reg.write_double(rd, sqrt_d(a, rm));
             }
-            Instruction::FsubS {
-                rd,
-                rs1,
-                rs2,
-                rm: _,
-            } => {
+            Instruction::FsubS { rd, rs1, rs2, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_single(rs1);
                 let b = fp_reg.read_single(rs2);
-                fp_reg.write_single(rd, a - b);
+                fp_reg.write_single(rd, add_s(a, -b, rm));
             }
-            Instruction::FmulS {
-                rd,
-                rs1,
-                rs2,
-                rm: _,
-            } => {
+            Instruction::FmulS { rd, rs1, rs2, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_single(rs1);
                 let b = fp_reg.read_single(rs2);
-                fp_reg.write_single(rd, a * b);
+                fp_reg.write_single(rd, mul_s(a, b, rm));
             }
             Instruction::FmaddS {
                 rd,
                 rs1,
                 rs2,
                 rs3,
-                rm: _,
+                rm,
             } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_single(rs1);
                 let b = fp_reg.read_single(rs2);
                 let c = fp_reg.read_single(rs3);
-                fp_reg.write_single(rd, a * b + c);
+                fp_reg.write_single(rd, fma_s(a, b, c, rm));
             }
             Instruction::FmsubS {
                 rd,
                 rs1,
                 rs2,
                 rs3,
-                rm: _,
+                rm,
             } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_single(rs1);
                 let b = fp_reg.read_single(rs2);
                 let c = fp_reg.read_single(rs3);
-                fp_reg.write_single(rd, a * b - c);
+                fp_reg.write_single(rd, fma_s(a, b, -c, rm));
             }
             Instruction::FmaddD {
                 rd,
                 rs1,
                 rs2,
                 rs3,
-                rm: _,
+                rm,
             } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_double(rs1);
                 let b = fp_reg.read_double(rs2);
                 let c = fp_reg.read_double(rs3);
-                fp_reg.write_double(rd, a * b + c);
+                fp_reg.write_double(rd, fma_d(a, b, c, rm));
             }
             Instruction::FmsubD {
                 rd,
                 rs1,
                 rs2,
                 rs3,
-                rm: _,
+                rm,
             } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_double(rs1);
                 let b = fp_reg.read_double(rs2);
                 let c = fp_reg.read_double(rs3);
-                fp_reg.write_double(rd, a * b - c);
+                fp_reg.write_double(rd, fma_d(a, b, -c, rm));
             }
             Instruction::FnmaddS {
                 rd,
                 rs1,
                 rs2,
                 rs3,
-                rm: _,
+                rm,
             } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_single(rs1);
                 let b = fp_reg.read_single(rs2);
                 let c = fp_reg.read_single(rs3);
-                fp_reg.write_single(rd, -(a * b) + c);
+                fp_reg.write_single(rd, fma_s(-a, b, -c, rm));
             }
             Instruction::FnmsubS {
                 rd,
                 rs1,
                 rs2,
                 rs3,
-                rm: _,
+                rm,
             } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_single(rs1);
                 let b = fp_reg.read_single(rs2);
                 let c = fp_reg.read_single(rs3);
-                fp_reg.write_single(rd, -(a * b) - c);
+                fp_reg.write_single(rd, fma_s(-a, b, c, rm));
             }
             Instruction::FnmaddD {
                 rd,
                 rs1,
                 rs2,
                 rs3,
-                rm: _,
+                rm,
             } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_double(rs1);
                 let b = fp_reg.read_double(rs2);
                 let c = fp_reg.read_double(rs3);
-                fp_reg.write_double(rd, a * b + c);
+                fp_reg.write_double(rd, fma_d(-a, b, -c, rm));
             }
             Instruction::FnmsubD {
                 rd,
                 rs1,
                 rs2,
                 rs3,
-                rm: _,
+                rm,
             } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_double(rs1);
                 let b = fp_reg.read_double(rs2);
                 let c = fp_reg.read_double(rs3);
-                fp_reg.write_double(rd, a * b - c);
+                fp_reg.write_double(rd, fma_d(-a, b, c, rm));
             }
 
-            Instruction::FdivS {
-                rd,
-                rs1,
-                rs2,
-                rm: _,
-            } => {
+            Instruction::FdivS { rd, rs1, rs2, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_single(rs1);
                 let b = fp_reg.read_single(rs2);
-                fp_reg.write_single(rd, a / b);
+                fp_reg.write_single(rd, div_s(a, b, rm));
             }
             Instruction::FsgnjS { rd, rs1, rs2 } => {
                 let a = fp_reg.read_single(rs1);
@@ -1085,52 +4812,44 @@ impl<Reader: MemReader<Idx = u32>> Core32<Reader> {
             Instruction::FminS { rd, rs1, rs2 } => {
                 let a = fp_reg.read_single(rs1);
                 let b = fp_reg.read_single(rs2);
-                fp_reg.write_single(rd, a.min(b));
+                fp_reg.write_single(rd, fmin(a as f64, b as f64) as f32);
             }
             Instruction::FmaxS { rd, rs1, rs2 } => {
                 let a = fp_reg.read_single(rs1);
                 let b = fp_reg.read_single(rs2);
-                fp_reg.write_single(rd, a.max(b));
+                fp_reg.write_single(rd, fmax(a as f64, b as f64) as f32);
             }
-            Instruction::FaddD {
-                rd,
-                rs1,
-                rs2,
-                rm: _,
-            } => {
+            Instruction::FaddD { rd, rs1, rs2, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_double(rs1);
                 let b = fp_reg.read_double(rs2);
-                fp_reg.write_double(rd, a + b);
+                fp_reg.write_double(rd, add_d(a, b, rm));
             }
-            Instruction::FsubD {
-                rd,
-                rs1,
-                rs2,
-                rm: _,
-            } => {
+            Instruction::FsubD { rd, rs1, rs2, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_double(rs1);
                 let b = fp_reg.read_double(rs2);
-                fp_reg.write_double(rd, a - b);
+                fp_reg.write_double(rd, add_d(a, -b, rm));
             }
-            Instruction::FmulD {
-                rd,
-                rs1,
-                rs2,
-                rm: _,
-            } => {
+            Instruction::FmulD { rd, rs1, rs2, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_double(rs1);
                 let b = fp_reg.read_double(rs2);
-                fp_reg.write_double(rd, a * b);
+                fp_reg.write_double(rd, mul_d(a, b, rm));
             }
-            Instruction::FdivD {
-                rd,
-                rs1,
-                rs2,
-                rm: _,
-            } => {
+            Instruction::FdivD { rd, rs1, rs2, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = fp_reg.read_double(rs1);
                 let b = fp_reg.read_double(rs2);
-                fp_reg.write_double(rd, a / b);
+                fp_reg.write_double(rd, div_d(a, b, rm));
             }
             Instruction::FsgnjD { rd, rs1, rs2 } => {
                 let a = fp_reg.read_double(rs1);
@@ -1150,12 +4869,12 @@ impl<Reader: MemReader<Idx = u32>> Core32<Reader> {
             Instruction::FminD { rd, rs1, rs2 } => {
                 let a = fp_reg.read_double(rs1);
                 let b = fp_reg.read_double(rs2);
-                fp_reg.write_double(rd, a.min(b));
+                fp_reg.write_double(rd, fmin(a, b));
             }
             Instruction::FmaxD { rd, rs1, rs2 } => {
                 let a = fp_reg.read_double(rs1);
                 let b = fp_reg.read_double(rs2);
-                fp_reg.write_double(rd, a.max(b));
+                fp_reg.write_double(rd, fmax(a, b));
             }
 
             // fmv Instructions
@@ -1179,45 +4898,88 @@ impl<Reader: MemReader<Idx = u32>> Core32<Reader> {
             }
 
             // fcvt Instructions
-            Instruction::FcvtSW { rd, rs1 } => {
+            Instruction::FcvtSW { rd, rs1, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = reg.read(rs1);
-                fp_reg.write_single(rd, a as f32);
+                fp_reg.write_single(rd, to_single(a as f64, rm));
             }
-            Instruction::FcvtSWu { rd, rs1 } => {
+            Instruction::FcvtSWu { rd, rs1, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let a = reg.read(rs1) as u32;
-                fp_reg.write_single(rd, a as f32);
+                fp_reg.write_single(rd, to_single(a as f64, rm));
             }
-            Instruction::FcvtWS { rd, rs1 } => {
-                let f = fp_reg.read_single(rs1);
-                reg.write(rd, f as i32);
+            Instruction::FcvtWS { rd, rs1, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
+                let val = fp_reg.read_single(rs1);
+                let (res, nv, nx) = fcvt_int(val as f64, rm, i32::MIN as f64, i32::MAX as f64);
+                fp_reg.fcsr.nv |= nv;
+                fp_reg.fcsr.nx |= nx;
+                reg.write(rd, res as i32);
             }
-            Instruction::FcvtWuS { rd, rs1 } => {
-                let f = fp_reg.read_single(rs1);
-                reg.write(rd, f as u32 as i32);
+            Instruction::FcvtWuS { rd, rs1, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
+                let val = fp_reg.read_single(rs1);
+                let (res, nv, nx) = fcvt_int(val as f64, rm, 0.0, u32::MAX as f64);
+                fp_reg.fcsr.nv |= nv;
+                fp_reg.fcsr.nx |= nx;
+                reg.write(rd, res as u32 as i32);
             }
-            Instruction::FcvtDW { rd, rs1 } => {
+            // exact, but a reserved rounding mode is still illegal
+            Instruction::FcvtDW { rd, rs1, rm } => {
+                if fp_reg.fcsr.rounding_mode(rm).is_none() {
+                    return ExecResult::IllegalInstruction;
+                }
                 let a = reg.read(rs1);
                 fp_reg.write_double(rd, a as f64);
             }
-            Instruction::FcvtDWu { rd, rs1 } => {
+            Instruction::FcvtDWu { rd, rs1, rm } => {
+                if fp_reg.fcsr.rounding_mode(rm).is_none() {
+                    return ExecResult::IllegalInstruction;
+                }
                 let a = reg.read(rs1) as u32;
                 fp_reg.write_double(rd, a as f64);
             }
-            Instruction::FcvtWD { rd, rs1 } => {
-                let d = fp_reg.read_double(rs1);
-                reg.write(rd, d as i32);
+            Instruction::FcvtWD { rd, rs1, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
+                let val = fp_reg.read_double(rs1);
+                let (res, nv, nx) = fcvt_int(val, rm, i32::MIN as f64, i32::MAX as f64);
+                fp_reg.fcsr.nv |= nv;
+                fp_reg.fcsr.nx |= nx;
+                reg.write(rd, res as i32);
             }
-            Instruction::FcvtWuD { rd, rs1 } => {
-                let d = fp_reg.read_double(rs1);
-                reg.write(rd, d as u32 as i32);
+            Instruction::FcvtWuD { rd, rs1, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
+                let val = fp_reg.read_double(rs1);
+                let (res, nv, nx) = fcvt_int(val, rm, 0.0, u32::MAX as f64);
+                fp_reg.fcsr.nv |= nv;
+                fp_reg.fcsr.nx |= nx;
+                reg.write(rd, res as u32 as i32);
             }
-            Instruction::FcvtSD { rd, rs1 } => {
+            Instruction::FcvtSD { rd, rs1, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
                 let d = fp_reg.read_double(rs1);
-                fp_reg.write_single(rd, d as f32);
+                fp_reg.write_single(rd, to_single(d, rm));
             }
-            Instruction::FcvtDS { rd, rs1 } => {
+            Instruction::FcvtDS { rd, rs1, rm } => {
+                if fp_reg.fcsr.rounding_mode(rm).is_none() {
+                    return ExecResult::IllegalInstruction;
+                }
                 let f = fp_reg.read_single(rs1);
-                fp_reg.write_double(rd, f as f64);
+                fp_reg.write_double(rd, canonical_d(f as f64));
             }
 
             // fp compare Instructions
@@ -1251,69 +5013,638 @@ impl<Reader: MemReader<Idx = u32>> Core32<Reader> {
                 let b = fp_reg.read_double(rs2);
                 reg.write(rd, if a <= b { 1 } else { 0 });
             }
+            // zfa
+            Instruction::FliS { rd, idx } => fp_reg.write_single(rd, instruction::fli_single(idx)),
+            Instruction::FliD { rd, idx } => fp_reg.write_double(rd, instruction::fli_double(idx)),
+            Instruction::FminmS { rd, rs1, rs2 } => {
+                let a = fp_reg.read_single(rs1);
+                let b = fp_reg.read_single(rs2);
+                fp_reg.write_single(rd, fminm(a as f64, b as f64) as f32);
+            }
+            Instruction::FmaxmS { rd, rs1, rs2 } => {
+                let a = fp_reg.read_single(rs1);
+                let b = fp_reg.read_single(rs2);
+                fp_reg.write_single(rd, fmaxm(a as f64, b as f64) as f32);
+            }
+            Instruction::FminmD { rd, rs1, rs2 } => {
+                let a = fp_reg.read_double(rs1);
+                let b = fp_reg.read_double(rs2);
+                fp_reg.write_double(rd, fminm(a, b));
+            }
+            Instruction::FmaxmD { rd, rs1, rs2 } => {
+                let a = fp_reg.read_double(rs1);
+                let b = fp_reg.read_double(rs2);
+                fp_reg.write_double(rd, fmaxm(a, b));
+            }
+            Instruction::FroundS { rd, rs1, rm } | Instruction::FroundnxS { rd, rs1, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
+                let a = fp_reg.read_single(rs1);
+                let res = round_to_integral(a as f64, rm) as f32;
+                if matches!(instr, Instruction::FroundnxS { .. }) && res != a && !a.is_nan() {
+                    fp_reg.fcsr.nx = true;
+                }
+                fp_reg.write_single(rd, res);
+            }
+            Instruction::FroundD { rd, rs1, rm } | Instruction::FroundnxD { rd, rs1, rm } => {
+                let Some(rm) = fp_reg.fcsr.rounding_mode(rm) else {
+                    return ExecResult::IllegalInstruction;
+                };
+                let a = fp_reg.read_double(rs1);
+                let res = round_to_integral(a, rm);
+                if matches!(instr, Instruction::FroundnxD { .. }) && res != a && !a.is_nan() {
+                    fp_reg.fcsr.nx = true;
+                }
+                fp_reg.write_double(rd, res);
+            }
+            Instruction::FcvtmodWD { rd, rs1 } => {
+                let d = fp_reg.read_double(rs1);
+                reg.write(rd, fcvtmod_w(d));
+            }
+
             Instruction::Fence { .. } => { /* no-op */ }
-            Instruction::FenceI => { /* no-op */ }
-            Instruction::Ecall => {
-                let syscall = self.read(Register::A(7));
-                match syscall {
-                    SYSCALL_EXIT => return ExecResult::Exit,
-                    SYSCALL_WRITE => {
-                        let fd = self.read(Register::A(0));
-                        let buf = self.read(Register::A(1));
-                        let count = self.read(Register::A(2));
+            // stores by the guest re-decode as they go, but not the host's, from
+            // syscalls, natively run calls and host calls
+            Instruction::FenceI => self.invalidate_code(self.memory.code.clone()),
+            Instruction::Ecall => return self.ecall(),
+            Instruction::Csrrw { rd, rs1, csr } => {
+                let src = reg.read(rs1) as u32;
+                return self.access_csr(csr, rd, Some(src), |_, src| src);
+            }
+            Instruction::Csrrs { rd, rs1, csr } => {
+                let src = (rs1 != 0).then(|| reg.read(rs1) as u32);
+                return self.access_csr(csr, rd, src, |old, src| old | src);
+            }
+            Instruction::Csrrc { rd, rs1, csr } => {
+                let src = (rs1 != 0).then(|| reg.read(rs1) as u32);
+                return self.access_csr(csr, rd, src, |old, src| old & !src);
+            }
+            Instruction::Csrrwi { rd, zimm, csr } => {
+                return self.access_csr(csr, rd, Some(zimm as u32), |_, src| src);
+            }
+            Instruction::Csrrsi { rd, zimm, csr } => {
+                let src = (zimm != 0).then_some(zimm as u32);
+                return self.access_csr(csr, rd, src, |old, src| old | src);
+            }
+            Instruction::Csrrci { rd, zimm, csr } => {
+                let src = (zimm != 0).then_some(zimm as u32);
+                return self.access_csr(csr, rd, src, |old, src| old & !src);
+            }
+            // a hint is a valid implementation, with interrupts scheduled or not
+            Instruction::Wfi => { /* no-op */ }
+            // from an interrupt handler, see `irq`
+            Instruction::Mret if self.interrupts.is_some() => {
+                let target = self.interrupts.as_mut().unwrap().mret();
+                // which may have let another in
+                self.update_next_check();
+                return ExecResult::Jump(target);
+            }
+            // with no privileged state to return to or translate with, these
+            // trap as they would in user mode, the hypervisor loads and stores
+            // as they would with `hstatus.HU` clear
+            Instruction::Mret
+            | Instruction::Sret
+            | Instruction::SfenceVma { .. }
+            | Instruction::HfenceVvma { .. }
+            | Instruction::HfenceGvma { .. }
+            | Instruction::HlvB { .. }
+            | Instruction::HlvBu { .. }
+            | Instruction::HlvH { .. }
+            | Instruction::HlvHu { .. }
+            | Instruction::HlvxHu { .. }
+            | Instruction::HlvW { .. }
+            | Instruction::HlvxWu { .. }
+            | Instruction::HsvB { .. }
+            | Instruction::HsvH { .. }
+            | Instruction::HsvW { .. } => return ExecResult::IllegalInstruction,
+            Instruction::Ebreak => return ExecResult::Ebreak,
 
-                        let buf = self.memory.get_buf(buf as u32, count as u32);
+            Instruction::Custom { opcode, inst } => return self.exec_custom(opcode, inst),
+            Instruction::Unknown(_) => return ExecResult::IllegalInstruction,
+        }
+        ExecResult::Continue
+    }
+}
 
-                        let mut f = unsafe { File::from_raw_fd(fd) };
-                        let count = f.write(buf).expect("write failed");
+// a failed write to the host's terminal or a file isn't the guest's problem
+fn write_muxed(mux: &Mux, stream: Stream, buf: &[u8], instret: u64) {
+    if let Err(err) = mux.write(stream, buf, Some(instret)) {
+        warn!(target: "mux", "failed to write {stream}: {err}");
+    }
+}
 
-                        self.write(Register::A(0), count as i32);
+// `Continue` after a store, unless it was to ROM, decoded code, `tohost`, a
+// device or past the end of memory
+fn stored(addr: u32, result: Result<(), StoreTrap>) -> ExecResult {
+    match result {
+        Ok(()) => ExecResult::Continue,
+        Err(StoreTrap::Rom) => ExecResult::RomWrite(addr),
+        Err(StoreTrap::Code) => ExecResult::CodeWrite(addr),
+        Err(StoreTrap::Htif) => ExecResult::Htif,
+        Err(StoreTrap::Exit(code)) => ExecResult::DeviceExit(code),
+        Err(StoreTrap::Device) => ExecResult::DeviceWrite,
+        Err(StoreTrap::Fault) => ExecResult::StoreFault(addr),
+    }
+}
 
-                        // IMPORTANT: don't close the file
-                        mem::forget(f);
-                    }
-                    SYSCALL_READ => {
-                        let fd = self.read(Register::A(0));
-                        let buf = self.read(Register::A(1));
-                        let count = self.read(Register::A(2));
+// fmin/fmax: a single NaN operand is ignored, two give the canonical NaN, and
+// -0.0 is below +0.0. Singles go through these exactly, widened
+fn fmin(a: f64, b: f64) -> f64 {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => f64::NAN,
+        (true, false) => b,
+        (false, true) => a,
+        _ if a == b => f64::from_bits(a.to_bits() | b.to_bits()),
+        _ => a.min(b),
+    }
+}
 
-                        let buf = self.memory.get_buf(buf as u32, count as u32);
+fn fmax(a: f64, b: f64) -> f64 {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => f64::NAN,
+        (true, false) => b,
+        (false, true) => a,
+        _ if a == b => f64::from_bits(a.to_bits() & b.to_bits()),
+        _ => a.max(b),
+    }
+}
 
-                        let mut f = unsafe { File::from_raw_fd(fd) };
-                        let count = f.read(buf).expect("write failed");
+// IEEE 754-2019 minimum and maximum: a NaN operand makes the result NaN, unlike
+// `fmin`/`fmax`. Singles go through these exactly, widened
+fn fminm(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if a == b {
+        // -0.0 is the smaller zero
+        f64::from_bits(a.to_bits() | b.to_bits())
+    } else {
+        a.min(b)
+    }
+}
 
-                        self.write(Register::A(0), count as i32);
+fn fmaxm(a: f64, b: f64) -> f64 {
+    if a.is_nan() || b.is_nan() {
+        f64::NAN
+    } else if a == b {
+        f64::from_bits(a.to_bits() & b.to_bits())
+    } else {
+        a.max(b)
+    }
+}
 
-                        // IMPORTANT: don't close the file
-                        mem::forget(f);
-                    }
-                    SYSCALL_BRK => {
-                        let p = self.read(Register::A(0));
-                        eprintln!("brk to {:#x}", p);
-                    }
-                    _ => eprintln!("unknown syscall '{syscall}'"),
-                    // _ => panic!("unknown syscall '{syscall}'"),
-                }
-            }
-            Instruction::Frrm { rd } => {
-                let rm = fp_reg.fcsr.rm;
-                reg.write(rd, rm as i32);
-            }
-            Instruction::Fsrm { rd, rs1 } => {
-                let rm = fp_reg.fcsr.rm;
-                reg.write(rd, rm as i32);
+/// `val` rounded to an integer in the rounding mode `rm`, which must be valid
+fn round_to_integral(val: f64, rm: u8) -> f64 {
+    let res = match rm {
+        0b000 => val.round_ties_even(),
+        0b001 => val.trunc(),
+        0b010 => val.floor(),
+        0b011 => val.ceil(),
+        _ => val.round(),
+    };
+    if res.is_nan() {
+        f64::NAN
+    } else {
+        res
+    }
+}
+
+/// `fcvt.w.*` and `fcvt.wu.*`: `val` rounded to an integer in the rounding mode `rm`, which must
+/// be valid, and saturated to `min..=max`, NaN going to `max`. Also whether that was invalid and
+/// whether it was inexact
+fn fcvt_int(val: f64, rm: u8, min: f64, max: f64) -> (i64, bool, bool) {
+    let rounded = round_to_integral(val, rm);
+    if val.is_nan() || rounded > max {
+        (max as i64, true, false)
+    } else if rounded < min {
+        (min as i64, true, false)
+    } else {
+        (rounded as i64, false, rounded != val)
+    }
+}
+
+// the canonical NaN in place of any other
+fn canonical_s(val: f32) -> f32 {
+    if val.is_nan() {
+        f32::NAN
+    } else {
+        val
+    }
+}
+
+fn canonical_d(val: f64) -> f64 {
+    if val.is_nan() {
+        f64::NAN
+    } else {
+        val
+    }
+}
+
+/// `a + b` rounded in the rounding mode `rm`, which must be valid
+fn add_s(a: f32, b: f32, rm: u8) -> f32 {
+    let nearest = a + b;
+    if rm == 0b000 || !(a.is_finite() && b.is_finite()) {
+        return canonical_s(nearest);
+    }
+
+    let (down, up) = (nearest.next_down(), nearest.next_up());
+    round_sum(
+        nearest as f64,
+        down as f64,
+        up as f64,
+        &[a as f64, b as f64],
+        rm,
+    ) as f32
+}
+
+/// `a + b` rounded in the rounding mode `rm`, which must be valid
+fn add_d(a: f64, b: f64, rm: u8) -> f64 {
+    let nearest = a + b;
+    if rm == 0b000 || !(a.is_finite() && b.is_finite()) {
+        return canonical_d(nearest);
+    }
+
+    round_sum(nearest, nearest.next_down(), nearest.next_up(), &[a, b], rm)
+}
+
+/// `a * b` rounded in the rounding mode `rm`, which must be valid
+fn mul_s(a: f32, b: f32, rm: u8) -> f32 {
+    let nearest = a * b;
+    if rm == 0b000 || !(a.is_finite() && b.is_finite()) {
+        return canonical_s(nearest);
+    }
+
+    // a product of singles is exact as a double
+    let (down, up) = (nearest.next_down(), nearest.next_up());
+    round_sum(
+        nearest as f64,
+        down as f64,
+        up as f64,
+        &[a as f64 * b as f64],
+        rm,
+    ) as f32
+}
+
+/// `a * b` rounded in the rounding mode `rm`, which must be valid
+fn mul_d(a: f64, b: f64, rm: u8) -> f64 {
+    // a zero product is exact, and `fma_d` would make +0 - 0 -0 rounding down
+    if a == 0.0 || b == 0.0 {
+        return canonical_d(a * b);
+    }
+    fma_d(a, b, -0.0, rm)
+}
+
+/// `a / b` rounded in the rounding mode `rm`, which must be valid
+fn div_s(a: f32, b: f32, rm: u8) -> f32 {
+    let nearest = a / b;
+    if rm == 0b000 || a == 0.0 || b == 0.0 || !(a.is_finite() && b.is_finite()) {
+        return canonical_s(nearest);
+    }
+
+    // the quotient is above `x` when `a - b * x` has the sign of `b`, and a product of singles
+    // is exact as a double
+    let (a, b) = (a as f64, b as f64);
+    let excess = |xs: &[f64]| {
+        let terms = xs.iter().flat_map(|x| [a, -b * x]);
+        with_sign_of(sum_sign(terms.collect()), b)
+    };
+    let (down, up) = (nearest.next_down(), nearest.next_up());
+    round_directed(nearest as f64, down as f64, up as f64, rm, excess) as f32
+}
+
+/// `a / b` rounded in the rounding mode `rm`, which must be valid
+fn div_d(a: f64, b: f64, rm: u8) -> f64 {
+    let nearest = a / b;
+    if rm == 0b000 || a == 0.0 || b == 0.0 || !(a.is_finite() && b.is_finite()) {
+        return canonical_d(nearest);
+    }
 
-                let new_rm = reg.read(rs1);
-                fp_reg.fcsr.rm = new_rm.try_into().expect("bad rounding mode");
+    // the quotient is above `x` when `a - b * x` has the sign of `b`, with the product split,
+    // which needs it well clear of the subnormals and infinity. So scale a tiny `a` up, with
+    // the `x`s, and a huge one down, with `b`, which can't be tiny too without overflowing
+    let (a, b, scale) = if a.abs() < 2f64.powi(-900) {
+        (a * 2f64.powi(600), b, 2f64.powi(600))
+    } else if a.abs() > 2f64.powi(900) {
+        (a * 2f64.powi(-600), b * 2f64.powi(-600), 1.0)
+    } else {
+        (a, b, 1.0)
+    };
+    let excess = |xs: &[f64]| {
+        let terms = xs.iter().flat_map(|x| {
+            let [hi, lo] = split(-b, x * scale);
+            [a, hi, lo]
+        });
+        with_sign_of(sum_sign(terms.collect()), b)
+    };
+    round_directed(nearest, nearest.next_down(), nearest.next_up(), rm, excess)
+}
+
+/// The square root of `a` rounded in the rounding mode `rm`, which must be valid
+fn sqrt_s(a: f32, rm: u8) -> f32 {
+    let nearest = a.sqrt();
+    if rm == 0b000 || !(a.is_finite() && a > 0.0) {
+        return canonical_s(nearest);
+    }
+
+    // the root is above `x` when `a - x * x` is positive, which is exact as a double. It's
+    // never exactly between two singles, so never a tie
+    let a = a as f64;
+    let excess = |xs: &[f64]| match xs {
+        [x] => sum_sign(vec![a, -x * x]),
+        _ => Ordering::Less,
+    };
+    let (down, up) = (nearest.next_down(), nearest.next_up());
+    round_directed(nearest as f64, down as f64, up as f64, rm, excess) as f32
+}
+
+/// The square root of `a` rounded in the rounding mode `rm`, which must be valid
+fn sqrt_d(a: f64, rm: u8) -> f64 {
+    let nearest = a.sqrt();
+    if rm == 0b000 || !(a.is_finite() && a > 0.0) {
+        return canonical_d(nearest);
+    }
+
+    // the root is above `x` when `a - x * x` is positive, with the square split, so scaled up
+    // when tiny. It's never exactly between two doubles, so never a tie
+    let (a, scale) = if a < 2f64.powi(-800) {
+        (a * 2f64.powi(600), 2f64.powi(300))
+    } else {
+        (a, 1.0)
+    };
+    let excess = |xs: &[f64]| match xs {
+        [x] => {
+            let x = x * scale;
+            let [hi, lo] = split(-x, x);
+            sum_sign(vec![a, hi, lo])
+        }
+        _ => Ordering::Less,
+    };
+    round_directed(nearest, nearest.next_down(), nearest.next_up(), rm, excess)
+}
+
+/// `val` rounded to a single in the rounding mode `rm`, which must be valid
+fn to_single(val: f64, rm: u8) -> f32 {
+    let nearest = val as f32;
+    if rm == 0b000 || !val.is_finite() {
+        return canonical_s(nearest);
+    }
+
+    let (down, up) = (nearest.next_down(), nearest.next_up());
+    round_sum(nearest as f64, down as f64, up as f64, &[val], rm) as f32
+}
+
+/// `a * b + c` rounded once, in the rounding mode `rm`, which must be valid
+fn fma_s(a: f32, b: f32, c: f32, rm: u8) -> f32 {
+    let nearest = a.mul_add(b, c);
+    if rm == 0b000 || !(a.is_finite() && b.is_finite() && c.is_finite()) {
+        return canonical_s(nearest);
+    }
+
+    // a product of singles is exact as a double
+    let exact = [a as f64 * b as f64, c as f64];
+    let (down, up) = (nearest.next_down(), nearest.next_up());
+    round_sum(nearest as f64, down as f64, up as f64, &exact, rm) as f32
+}
+
+/// `a * b + c` rounded once, in the rounding mode `rm`, which must be valid
+fn fma_d(a: f64, b: f64, c: f64, rm: u8) -> f64 {
+    let nearest = a.mul_add(b, c);
+    if rm == 0b000 || !(a.is_finite() && b.is_finite() && c.is_finite()) {
+        return canonical_d(nearest);
+    }
+
+    let product = (a * b).abs();
+    let (exact, scale) = if product > 2f64.powi(900) {
+        // too near overflowing, so scale it all down, bar a `c` too small to matter but for
+        // its sign, as below
+        let scale = 2f64.powi(-300);
+        let [hi, lo] = split(a * scale * scale, b);
+        let c = if c == 0.0 || c.abs() >= 2f64.powi(-400) {
+            c * scale * scale
+        } else {
+            2f64.powi(-1000).copysign(c)
+        };
+        ([hi, lo, c], scale)
+    } else if a == 0.0 || b == 0.0 || product >= 2f64.powi(-900) {
+        let [hi, lo] = split(a, b);
+        ([hi, lo, c], 1.0)
+    } else if c.abs() >= 2f64.powi(-800) {
+        // under a quarter ulp of c, so anything that small with its sign rounds the same
+        ([2f64.powi(-1000).copysign(a * b), 0.0, c], 1.0)
+    } else {
+        // all of it is tiny, so scale it up until the product splits
+        let scale = 2f64.powi(600);
+        let [hi, lo] = split(a * scale, b * scale);
+        ([hi, lo, c * scale * scale], scale)
+    };
+    let [nearest, down, up] =
+        [nearest, nearest.next_down(), nearest.next_up()].map(|x| x * scale * scale);
+    round_sum(nearest, down, up, &exact, rm) / scale / scale
+}
+
+// `a * b` as two doubles that sum to it exactly, which only works while its low half is above
+// the subnormals
+fn split(a: f64, b: f64) -> [f64; 2] {
+    let p = a * b;
+    [p, a.mul_add(b, -p)]
+}
+
+fn with_sign_of(sign: Ordering, val: f64) -> Ordering {
+    if val < 0.0 {
+        sign.reverse()
+    } else {
+        sign
+    }
+}
+
+/// `nearest`, what the terms of `exact` sum to rounded to nearest, rounded in the directed
+/// rounding mode `rm` instead, as itself, `down` or `up`, its neighbours
+fn round_sum(nearest: f64, down: f64, up: f64, exact: &[f64], rm: u8) -> f64 {
+    // each copy of `exact` next to what it's less, so nothing adds up to overflowing
+    let excess = |xs: &[f64]| {
+        let terms = xs.iter().flat_map(|x| exact.iter().copied().chain([-x]));
+        sum_sign(terms.collect())
+    };
+    // an exact zero is -0 rounding down, unless it's +0 + +0
+    if rm == 0b010
+        && nearest == 0.0
+        && exact.iter().any(|t| t.to_bits() != 0)
+        && excess(&[0.0]) == Ordering::Equal
+    {
+        return -0.0;
+    }
+    round_directed(nearest, down, up, rm, excess)
+}
+
+/// `nearest`, the exact result rounded to nearest, rounded in the directed rounding mode `rm`
+/// instead, as itself, `down` or `up`, its neighbours. `excess(xs)` is the sign of the exact
+/// result times the number of `xs`, less their sum
+fn round_directed(
+    nearest: f64,
+    down: f64,
+    up: f64,
+    rm: u8,
+    excess: impl Fn(&[f64]) -> Ordering,
+) -> f64 {
+    let above = if nearest.is_infinite() {
+        // finite terms that overflowed
+        0.0.partial_cmp(&nearest).unwrap()
+    } else {
+        excess(&[nearest])
+    };
+
+    match (rm, above) {
+        (_, Ordering::Equal) => nearest,
+        (0b001, Ordering::Less) if nearest > 0.0 => down,
+        (0b001, Ordering::Greater) if nearest < 0.0 => up,
+        (0b010, Ordering::Less) => down,
+        (0b011, Ordering::Greater) => up,
+        (0b100, _) => {
+            // to nearest already, bar a tie that went to even rather than away
+            let other = if above == Ordering::Greater { up } else { down };
+            let tie = nearest.is_finite()
+                && other.is_finite()
+                && excess(&[nearest, other]) == Ordering::Equal;
+            if tie && other.abs() > nearest.abs() {
+                other
+            } else {
+                nearest
             }
-            Instruction::Ebreak => {
-                todo!("ebreak encountered");
+        }
+        _ => nearest,
+    }
+}
+
+/// The sign of the exact sum of `terms`, which must be finite
+fn sum_sign(mut terms: Vec<f64>) -> Ordering {
+    // two-sum each pair up the list, which keeps the total exact, until nothing moves, when the
+    // last term is the sum rounded and the rest are too small to change its sign
+    loop {
+        let mut moved = false;
+        for i in 1..terms.len() {
+            let (a, b) = (terms[i - 1], terms[i]);
+            let sum = a + b;
+            if !sum.is_finite() {
+                return sum.partial_cmp(&0.0).unwrap_or(Ordering::Equal);
             }
+            // the larger first, so nothing overflows while the sum doesn't
+            let (big, small) = if a.abs() >= b.abs() { (a, b) } else { (b, a) };
+            let err = small - (sum - big);
+            moved |= sum != b;
+            terms[i] = sum;
+            terms[i - 1] = err;
+        }
+        if !moved {
+            break;
+        }
+    }
+    terms
+        .last()
+        .map_or(Ordering::Equal, |t| t.partial_cmp(&0.0).unwrap())
+}
+
+/// `fcvtmod.w.d`: `val` truncated, modulo 2^32, and 0 if it isn't finite
+fn fcvtmod_w(val: f64) -> i32 {
+    if !val.is_finite() {
+        return 0;
+    }
+
+    if val.abs() < 2f64.powi(63) {
+        return val as i64 as i32;
+    }
+
+    // far too big to have a fraction, so shift the mantissa into place
+    let bits = val.to_bits();
+    let exp = ((bits >> 52) & 0x7ff) as u32 - 1075;
+    let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+    let low = if exp < 32 {
+        (mantissa << exp) as u32
+    } else {
+        0
+    };
+    if val < 0.0 {
+        low.wrapping_neg() as i32
+    } else {
+        low as i32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn core() -> Core32<AdaptiveMemReader<u32>> {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/guests/fp.elf");
+        Core32::new(LoadedElf::load(path).unwrap(), None, 16 << 20, false)
+    }
+
+    // `fcvt.{w,wu}.{s,d} a0, fa0, rm` on `val`, with the result and fflags, or `None` if it
+    // was illegal
+    fn fcvt(core: &mut Core32<AdaptiveMemReader<u32>>, word: u32, val: f64) -> Option<(i32, u32)> {
+        let double = word >> 25 & 1 != 0;
+        if double {
+            core.fp_regfile.write_double(10, val);
+        } else {
+            core.fp_regfile.write_single(10, val as f32);
+        }
+        core.fp_regfile.fcsr.set_fflags(0);
+        let instr = Instruction::decode(word);
+        match core.exec(instr) {
+            ExecResult::Continue => {}
+            ExecResult::IllegalInstruction => return None,
+            _ => panic!("{instr} didn't continue"),
+        }
+        Some((core.gp_regfile.read(10), core.fp_regfile.fcsr.fflags()))
+    }
+
+    #[test]
+    fn fcvt_rounds_in_its_rounding_mode_and_saturates() {
+        const NV: u32 = 0b10000;
+        const NX: u32 = 0b00001;
+        // per rounding mode, rne, rtz, rdn, rup, rmm, and with the fflags for the unsigned ones,
+        // where rounding a negative number to anything but 0 is invalid
+        let (v, x) = (NV, NX);
+        #[rustfmt::skip]
+        let cases: [(f64, _, _, [(u32, _); 5]); 7] = [
+            (2.5, [2, 2, 2, 3, 3], x, [(2, x), (2, x), (2, x), (3, x), (3, x)]),
+            (-2.5, [-2, -2, -3, -2, -3], x, [(0, v); 5]),
+            (2.75, [3, 2, 2, 3, 3], x, [(3, x), (2, x), (2, x), (3, x), (3, x)]),
+            (-0.5, [0, 0, -1, 0, -1], x, [(0, x), (0, x), (0, v), (0, x), (0, v)]),
+            (f64::NAN, [i32::MAX; 5], v, [(u32::MAX, v); 5]),
+            (f64::INFINITY, [i32::MAX; 5], v, [(u32::MAX, v); 5]),
+            (-1e10, [i32::MIN; 5], v, [(0, v); 5]),
+        ];
 
-            Instruction::Unknown(val) => {
-                panic!("unknown instruction {val:#x} at pc {:#x}!", self.pc);
+        let mut core = core();
+        for (val, signed, signed_flags, unsigned) in cases {
+            let signed = signed.map(|res| (res, signed_flags));
+            let unsigned = unsigned.map(|(res, flags)| (res as i32, flags));
+            for (funct7, width) in [(0b1100000, "s"), (0b1100001, "d")] {
+                for (rs2, expected) in [(0, signed), (1, unsigned)] {
+                    for rm in 0..5 {
+                        let word = funct7 << 25 | rs2 << 20 | 10 << 15 | rm << 12 | 10 << 7 | 0x53;
+                        assert_eq!(
+                            fcvt(&mut core, word, val),
+                            Some(expected[rm as usize]),
+                            "{} of {val} in .{width}",
+                            Instruction::decode(word)
+                        );
+                    }
+                }
             }
         }
-        ExecResult::Continue
+
+        // the dynamic mode follows frm, and the reserved ones are illegal
+        let word = 0b1100001 << 25 | 10 << 15 | 0b111 << 12 | 10 << 7 | 0x53;
+        core.fp_regfile.fcsr.frm = 0b011;
+        assert_eq!(fcvt(&mut core, word, 2.25), Some((3, NX)));
+        for rm in [0b101, 0b110] {
+            assert_eq!(
+                fcvt(&mut core, word & !(0b111 << 12) | rm << 12, 2.25),
+                None
+            );
+        }
+        core.fp_regfile.fcsr.frm = 0b101;
+        assert_eq!(fcvt(&mut core, word, 2.25), None);
     }
 }
@@ -0,0 +1,238 @@
+//! Per instruction class counts, weighted into a rough energy estimate.
+//!
+//! `CostCounter` is a hook, so counting is only paid for when asked for. The
+//! weights are whatever unit the user's model is in (pJ per instruction, say);
+//! the defaults are only meant to be in a plausible ratio to each other.
+
+use std::{cell::RefCell, fmt, rc::Rc, str::FromStr};
+
+use crate::{
+    hooks::{Hook, HookAction},
+    instruction::Instruction,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpClass {
+    Alu,
+    MulDiv,
+    Load,
+    Store,
+    Fp,
+    /// Branches and jumps
+    Branch,
+    /// Syscalls, fences and csr accesses
+    System,
+}
+
+impl OpClass {
+    pub const ALL: [OpClass; 7] = [
+        OpClass::Alu,
+        OpClass::MulDiv,
+        OpClass::Load,
+        OpClass::Store,
+        OpClass::Fp,
+        OpClass::Branch,
+        OpClass::System,
+    ];
+
+    pub fn of(instr: &Instruction) -> OpClass {
+        use Instruction::*;
+
+        match instr {
+            Lb { .. }
+            | Lh { .. }
+            | Lw { .. }
+            | Lbu { .. }
+            | Lhu { .. }
+            | Flw { .. }
+            | Fld { .. }
+            | LrW { .. } => OpClass::Load,
+            Sb { .. } | Sh { .. } | Sw { .. } | Fsw { .. } | Fsd { .. } => OpClass::Store,
+            // a read-modify-write, which costs most like its store
+            _ if instr.is_atomic() => OpClass::Store,
+            Mul { .. }
+            | Mulh { .. }
+            | Mulhsu { .. }
+            | Mulhu { .. }
+            | Div { .. }
+            | Divu { .. }
+            | Rem { .. }
+            | Remu { .. } => OpClass::MulDiv,
+            Jal { .. }
+            | Jalr { .. }
+            | Beq { .. }
+            | Bne { .. }
+            | Blt { .. }
+            | Bge { .. }
+            | Bltu { .. }
+            | Bgeu { .. } => OpClass::Branch,
+            Fence { .. }
+            | FenceI
+            | Ecall
+            | Ebreak
+            | Wfi
+            | Mret
+            | Sret
+            | SfenceVma { .. }
+            | WrsNto
+            | WrsSto
+            | Unknown(_)
+            | Custom { .. } => OpClass::System,
+            _ if instr.csr().is_some() || instr.is_privileged() => OpClass::System,
+            // anything left that touches an fp register is fp, including the
+            // compares and moves to integer registers
+            _ if instr.fp_dest().is_some() || is_fp_to_int(instr) => OpClass::Fp,
+            _ => OpClass::Alu,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OpClass::Alu => "alu",
+            OpClass::MulDiv => "muldiv",
+            OpClass::Load => "load",
+            OpClass::Store => "store",
+            OpClass::Fp => "fp",
+            OpClass::Branch => "branch",
+            OpClass::System => "system",
+        }
+    }
+
+    fn idx(self) -> usize {
+        self as usize
+    }
+}
+
+fn is_fp_to_int(instr: &Instruction) -> bool {
+    use Instruction::*;
+
+    matches!(
+        instr,
+        FmvSW { .. }
+            | FmvXD { .. }
+            | FclassS { .. }
+            | FclassD { .. }
+            | FcvtWS { .. }
+            | FcvtWuS { .. }
+            | FcvtWD { .. }
+            | FcvtWuD { .. }
+            | FcvtmodWD { .. }
+            | FeqS { .. }
+            | FltS { .. }
+            | FleS { .. }
+            | FeqD { .. }
+            | FltD { .. }
+            | FleD { .. }
+    )
+}
+
+/// The cost of one instruction of each class, parsed from e.g. `load=5,fp=3.5`;
+/// classes not mentioned keep their default
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CostWeights([f64; OpClass::ALL.len()]);
+
+impl CostWeights {
+    pub fn get(&self, class: OpClass) -> f64 {
+        self.0[class.idx()]
+    }
+}
+
+impl Default for CostWeights {
+    fn default() -> Self {
+        //         alu  muldiv load store fp   branch system
+        CostWeights([1.0, 4.0, 3.0, 3.0, 5.0, 1.0, 2.0])
+    }
+}
+
+impl FromStr for CostWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = CostWeights::default();
+
+        for part in s.split(',').filter(|part| !part.is_empty()) {
+            let (name, weight) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected CLASS=WEIGHT, got '{part}'"))?;
+            let class = OpClass::ALL
+                .into_iter()
+                .find(|class| class.name() == name)
+                .ok_or_else(|| format!("unknown instruction class '{name}'"))?;
+            let weight = weight
+                .parse()
+                .map_err(|_| format!("invalid weight '{weight}' for {name}"))?;
+
+            weights.0[class.idx()] = weight;
+        }
+
+        Ok(weights)
+    }
+}
+
+/// Counts retired instructions by class. Clones share their counts, so one can
+/// be kept to read them after the other is given to `Core32::add_hook`
+#[derive(Debug, Clone, Default)]
+pub struct CostCounter {
+    counts: Rc<RefCell<[u64; OpClass::ALL.len()]>>,
+}
+
+impl CostCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn count(&self, class: OpClass) -> u64 {
+        self.counts.borrow()[class.idx()]
+    }
+
+    pub fn report(&self, weights: CostWeights) -> EnergyReport {
+        EnergyReport {
+            counts: *self.counts.borrow(),
+            weights,
+        }
+    }
+}
+
+impl Hook for CostCounter {
+    fn before_instruction(&mut self, _pc: u32, instr: &Instruction) -> HookAction {
+        self.counts.borrow_mut()[OpClass::of(instr).idx()] += 1;
+        HookAction::Continue
+    }
+
+    fn wants_reg_writes(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EnergyReport {
+    counts: [u64; OpClass::ALL.len()],
+    weights: CostWeights,
+}
+
+impl EnergyReport {
+    pub fn cost(&self, class: OpClass) -> f64 {
+        self.counts[class.idx()] as f64 * self.weights.get(class)
+    }
+
+    pub fn total(&self) -> f64 {
+        OpClass::ALL.into_iter().map(|class| self.cost(class)).sum()
+    }
+}
+
+impl fmt::Display for EnergyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "energy estimate:")?;
+        for class in OpClass::ALL {
+            writeln!(
+                f,
+                "  {:<8} {:>14} x {:<8} = {:.1}",
+                class.name(),
+                self.counts[class.idx()],
+                self.weights.get(class),
+                self.cost(class)
+            )?;
+        }
+        write!(f, "  total {:.1}", self.total())
+    }
+}
@@ -0,0 +1,427 @@
+//! The control and status registers riscy implements.
+//!
+//! Only the user-level ones are present: the fp `fflags`/`frm`/`fcsr` views of
+//! the fp control register, and the read-only counters, including the
+//! `hpmcounter`s of `hpm`, and the machine-mode trap CSRs once interrupts are
+//! scheduled, see `irq`. Accessing anything else is an illegal instruction.
+//!
+//! The hypervisor extension's CSRs are named, for disassembling firmware that
+//! uses them, but like the rest of the privileged architecture they aren't
+//! there to access. Running a hypervisor needs supervisor mode and an MMU
+//! first, which the H extension's second, guest-physical stage of translation
+//! would then be added to.
+//!
+//! Tooling reading the CSRs with fields, `mstatus`, `mtvec`, `mcause`, `mie`,
+//! `mip` and `fcsr`, gets them through a `CsrView`, as in
+//! `core.csr::<Mstatus>()?.mie()`, rather than picking out the bits itself.
+//! `fields` names them all for showing a CSR by number, as the debug adapter
+//! does. There's no `satp` to view until there's an MMU.
+
+pub const FFLAGS: u16 = 0x001;
+pub const FRM: u16 = 0x002;
+pub const FCSR: u16 = 0x003;
+
+pub const CYCLE: u16 = 0xc00;
+pub const TIME: u16 = 0xc01;
+pub const INSTRET: u16 = 0xc02;
+pub const CYCLEH: u16 = 0xc80;
+pub const TIMEH: u16 = 0xc81;
+pub const INSTRETH: u16 = 0xc82;
+pub const MSTATUS: u16 = 0x300;
+pub const MIE: u16 = 0x304;
+pub const MTVEC: u16 = 0x305;
+pub const MSCRATCH: u16 = 0x340;
+pub const MEPC: u16 = 0x341;
+pub const MCAUSE: u16 = 0x342;
+pub const MTVAL: u16 = 0x343;
+pub const MIP: u16 = 0x344;
+pub const MHARTID: u16 = 0xf14;
+
+pub const MSTATUS_MIE: u32 = 1 << 3;
+pub const MSTATUS_MPIE: u32 = 1 << 7;
+pub const MSTATUS_MPP: u32 = 0b11 << 11;
+pub const MCAUSE_INTERRUPT: u32 = 1 << 31;
+pub const MTVEC_VECTORED: u32 = 1;
+
+pub const HPMCOUNTER3: u16 = 0xc03;
+pub const HPMCOUNTER31: u16 = 0xc1f;
+pub const HPMCOUNTER3H: u16 = 0xc83;
+pub const HPMCOUNTER31H: u16 = 0xc9f;
+
+/// The assembler name of `csr`, if riscy implements it
+pub fn name(csr: u16) -> Option<&'static str> {
+    match csr {
+        FFLAGS => Some("fflags"),
+        FRM => Some("frm"),
+        FCSR => Some("fcsr"),
+        CYCLE => Some("cycle"),
+        TIME => Some("time"),
+        INSTRET => Some("instret"),
+        CYCLEH => Some("cycleh"),
+        TIMEH => Some("timeh"),
+        INSTRETH => Some("instreth"),
+        MSTATUS => Some("mstatus"),
+        MIE => Some("mie"),
+        MTVEC => Some("mtvec"),
+        MSCRATCH => Some("mscratch"),
+        MEPC => Some("mepc"),
+        MCAUSE => Some("mcause"),
+        MTVAL => Some("mtval"),
+        MIP => Some("mip"),
+        MHARTID => Some("mhartid"),
+        _ => None,
+    }
+}
+
+// the hypervisor extension's, and the virtual supervisor ones it adds
+const HYPERVISOR: &[(u16, &str)] = &[
+    (0x600, "hstatus"),
+    (0x602, "hedeleg"),
+    (0x603, "hideleg"),
+    (0x604, "hie"),
+    (0x605, "htimedelta"),
+    (0x606, "hcounteren"),
+    (0x607, "hgeie"),
+    (0x60a, "henvcfg"),
+    (0x615, "htimedeltah"),
+    (0x61a, "henvcfgh"),
+    (0x643, "htval"),
+    (0x644, "hip"),
+    (0x645, "hvip"),
+    (0x64a, "htinst"),
+    (0x680, "hgatp"),
+    (0xe12, "hgeip"),
+    (0x200, "vsstatus"),
+    (0x204, "vsie"),
+    (0x205, "vstvec"),
+    (0x240, "vsscratch"),
+    (0x241, "vsepc"),
+    (0x242, "vscause"),
+    (0x243, "vstval"),
+    (0x244, "vsip"),
+    (0x280, "vsatp"),
+    (0x34a, "mtinst"),
+    (0x34b, "mtval2"),
+];
+
+/// The assembler name of `csr` if it's one of the hypervisor extension's,
+/// which riscy doesn't implement
+pub fn hypervisor_name(csr: u16) -> Option<&'static str> {
+    HYPERVISOR
+        .iter()
+        .find(|&&(num, _)| num == csr)
+        .map(|&(_, name)| name)
+}
+
+/// Which `hpmcounter` `csr` is, and whether it's the high half of it
+pub fn hpm_counter(csr: u16) -> Option<(u8, bool)> {
+    match csr {
+        HPMCOUNTER3..=HPMCOUNTER31 => Some(((csr - CYCLE) as u8, false)),
+        HPMCOUNTER3H..=HPMCOUNTER31H => Some(((csr - CYCLEH) as u8, true)),
+        _ => None,
+    }
+}
+
+/// Whether writes to `csr` are illegal, which the encoding of the number says
+pub fn is_read_only(csr: u16) -> bool {
+    csr >> 10 == 0b11
+}
+
+/// A CSR's value with its fields named, see the module docs. `Core32::csr`
+/// reads one and `Core32::set_csr` writes one
+pub trait CsrView: Copy {
+    /// The CSR it's a view of
+    const CSR: u16;
+
+    fn from_bits(bits: u32) -> Self;
+
+    fn bits(self) -> u32;
+
+    /// Its fields and their values, for showing it
+    fn fields(self) -> Vec<(&'static str, u32)>;
+}
+
+/// The CSRs with a `CsrView`
+pub const VIEWED: [u16; 6] = [MSTATUS, MTVEC, MCAUSE, MIE, MIP, FCSR];
+
+/// The fields of `csr` holding `value`, or `None` if it has none
+pub fn fields(csr: u16, value: u32) -> Option<Vec<(&'static str, u32)>> {
+    fn of<View: CsrView>(value: u32) -> Vec<(&'static str, u32)> {
+        View::from_bits(value).fields()
+    }
+
+    Some(match csr {
+        MSTATUS => of::<Mstatus>(value),
+        MTVEC => of::<Mtvec>(value),
+        MCAUSE => of::<Mcause>(value),
+        MIE => of::<Mie>(value),
+        MIP => of::<Mip>(value),
+        FCSR => of::<Fcsr>(value),
+        _ => return None,
+    })
+}
+
+/// `mstatus`, of which only the interrupt enables are writable, and `MPP`
+/// always reads as machine mode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Mstatus(pub u32);
+
+impl Mstatus {
+    /// Whether interrupts are enabled
+    pub fn mie(self) -> bool {
+        self.0 & MSTATUS_MIE != 0
+    }
+
+    /// Whether they were before the trap being handled
+    pub fn mpie(self) -> bool {
+        self.0 & MSTATUS_MPIE != 0
+    }
+
+    /// The privilege mode the trap being handled came from
+    pub fn mpp(self) -> u8 {
+        ((self.0 & MSTATUS_MPP) >> MSTATUS_MPP.trailing_zeros()) as u8
+    }
+
+    pub fn with_mie(self, mie: bool) -> Self {
+        Self(with_bit(self.0, MSTATUS_MIE, mie))
+    }
+
+    pub fn with_mpie(self, mpie: bool) -> Self {
+        Self(with_bit(self.0, MSTATUS_MPIE, mpie))
+    }
+}
+
+impl CsrView for Mstatus {
+    const CSR: u16 = MSTATUS;
+
+    fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    fn bits(self) -> u32 {
+        self.0
+    }
+
+    fn fields(self) -> Vec<(&'static str, u32)> {
+        vec![
+            ("MIE", self.mie() as u32),
+            ("MPIE", self.mpie() as u32),
+            ("MPP", self.mpp() as u32),
+        ]
+    }
+}
+
+/// `mtvec`, where traps go
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Mtvec(pub u32);
+
+impl Mtvec {
+    pub fn base(self) -> u32 {
+        self.0 & !3
+    }
+
+    /// Whether each interrupt has a handler of its own, 4 bytes apart from the
+    /// base
+    pub fn vectored(self) -> bool {
+        self.0 & 3 == MTVEC_VECTORED
+    }
+
+    /// Where the handler for interrupt `irq` is
+    pub fn vector(self, irq: u8) -> u32 {
+        if self.vectored() {
+            self.base().wrapping_add(4 * irq as u32)
+        } else {
+            self.base()
+        }
+    }
+}
+
+impl CsrView for Mtvec {
+    const CSR: u16 = MTVEC;
+
+    fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    fn bits(self) -> u32 {
+        self.0
+    }
+
+    fn fields(self) -> Vec<(&'static str, u32)> {
+        vec![("BASE", self.base()), ("MODE", self.0 & 3)]
+    }
+}
+
+/// `mcause`, what the trap being handled was
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Mcause(pub u32);
+
+impl Mcause {
+    pub fn interrupt(self) -> bool {
+        self.0 & MCAUSE_INTERRUPT != 0
+    }
+
+    /// The interrupt's number, or the exception's
+    pub fn code(self) -> u32 {
+        self.0 & !MCAUSE_INTERRUPT
+    }
+}
+
+impl CsrView for Mcause {
+    const CSR: u16 = MCAUSE;
+
+    fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    fn bits(self) -> u32 {
+        self.0
+    }
+
+    fn fields(self) -> Vec<(&'static str, u32)> {
+        vec![
+            ("INTERRUPT", self.interrupt() as u32),
+            ("CODE", self.code()),
+        ]
+    }
+}
+
+/// `mie`, the interrupts enabled, one bit for each
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Mie(pub u32);
+
+impl Mie {
+    pub fn enabled(self, irq: u8) -> bool {
+        irq < 32 && self.0 & (1 << irq) != 0
+    }
+}
+
+impl CsrView for Mie {
+    const CSR: u16 = MIE;
+
+    fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    fn bits(self) -> u32 {
+        self.0
+    }
+
+    fn fields(self) -> Vec<(&'static str, u32)> {
+        irq_fields(self.0)
+    }
+}
+
+/// `mip`, the interrupts pending, one bit for each
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Mip(pub u32);
+
+impl Mip {
+    pub fn pending(self, irq: u8) -> bool {
+        irq < 32 && self.0 & (1 << irq) != 0
+    }
+}
+
+impl CsrView for Mip {
+    const CSR: u16 = MIP;
+
+    fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    fn bits(self) -> u32 {
+        self.0
+    }
+
+    fn fields(self) -> Vec<(&'static str, u32)> {
+        irq_fields(self.0)
+    }
+}
+
+// the interrupts set in `mie` or `mip`, which are too many to name
+fn irq_fields(bits: u32) -> Vec<(&'static str, u32)> {
+    (0..32)
+        .filter(|irq| bits & (1 << irq) != 0)
+        .map(|irq| ("IRQ", irq))
+        .collect()
+}
+
+/// `fcsr`, the fp rounding mode and accrued exception flags, of which
+/// `fflags` and `frm` are the two halves
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Fcsr(pub u32);
+
+impl Fcsr {
+    /// The dynamic rounding mode
+    pub fn frm(self) -> u8 {
+        ((self.0 >> 5) & 0b111) as u8
+    }
+
+    pub fn fflags(self) -> u32 {
+        self.0 & 0b11111
+    }
+
+    /// Invalid operation
+    pub fn nv(self) -> bool {
+        self.0 & 0b10000 != 0
+    }
+
+    /// Divide by zero
+    pub fn dz(self) -> bool {
+        self.0 & 0b01000 != 0
+    }
+
+    /// Overflow
+    pub fn of(self) -> bool {
+        self.0 & 0b00100 != 0
+    }
+
+    /// Underflow
+    pub fn uf(self) -> bool {
+        self.0 & 0b00010 != 0
+    }
+
+    /// Inexact
+    pub fn nx(self) -> bool {
+        self.0 & 0b00001 != 0
+    }
+
+    pub fn with_frm(self, frm: u8) -> Self {
+        Self((self.0 & !(0b111 << 5)) | ((frm as u32 & 0b111) << 5))
+    }
+
+    pub fn with_fflags(self, fflags: u32) -> Self {
+        Self((self.0 & !0b11111) | (fflags & 0b11111))
+    }
+}
+
+impl CsrView for Fcsr {
+    const CSR: u16 = FCSR;
+
+    fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    fn bits(self) -> u32 {
+        self.0
+    }
+
+    fn fields(self) -> Vec<(&'static str, u32)> {
+        vec![
+            ("FRM", self.frm() as u32),
+            ("NV", self.nv() as u32),
+            ("DZ", self.dz() as u32),
+            ("OF", self.of() as u32),
+            ("UF", self.uf() as u32),
+            ("NX", self.nx() as u32),
+        ]
+    }
+}
+
+fn with_bit(bits: u32, bit: u32, set: bool) -> u32 {
+    if set {
+        bits | bit
+    } else {
+        bits & !bit
+    }
+}
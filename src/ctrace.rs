@@ -0,0 +1,537 @@
+//! A compact binary trace of every instruction run, for
+//! `riscy --trace-compact`, and what `riscy trace-stats` and `riscy trace-grep`
+//! make of one.
+//!
+//! A text trace of a long run, as `--trace-pcs` writes, is gigabytes before it
+//! gets interesting. This one costs a few bytes per taken jump and nothing per
+//! instruction: what runs is cut into segments of straight-line code, each
+//! written as where it starts, relative to where the last one ended, and how
+//! many instructions it has, and a segment the same as the last, as each time
+//! round a loop with no branches in it is, is only counted. Instruction words
+//! are kept in a dictionary by pc, each written the first time its pc runs,
+//! and again only if the code there changes.
+//!
+//! All integers are LEB128 varints, signed ones zigzag-encoded, but for the
+//! version and the words:
+//!
+//! ```text
+//! header:  b"RSCYCTRC", version: u32 le (currently 1)
+//! segment: 0x01, delta: signed, count    `count` instructions, the first
+//!                                        `delta` bytes after where the last
+//!                                        segment ended, or after 0
+//! word:    0x02, pc, word: u32 le        the instruction at pc, from the next
+//!                                        segment on
+//! repeat:  0x03, times                   the last segment's delta and count
+//!                                        again, `times` times over
+//! ```
+//!
+//! `TraceStats` counts the instructions run at each pc, and how many times
+//! each loop went round each time it was entered. A loop is found by its back
+//! edge, a branch or `j` to an earlier pc, so a tail call backwards looks like
+//! one. `executions` finds each time the instruction at an address ran, which
+//! at a function's address is each call to it, with where it came from.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use tracing::warn;
+
+use crate::{
+    checkpoint::{invalid, read_u32, write_u32},
+    instruction::Instruction,
+    load::{self, Symbol},
+    tracer::{TraceStep, Tracer},
+};
+
+const MAGIC: &[u8; 8] = b"RSCYCTRC";
+const VERSION: u32 = 1;
+
+const RECORD_SEGMENT: u8 = 0x01;
+const RECORD_WORD: u8 = 0x02;
+const RECORD_REPEAT: u8 = 0x03;
+
+// how many functions or pcs, and loops, the statistics list
+const STATS_TOP: usize = 10;
+
+fn write_varint(out: &mut impl Write, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return out.write_all(&[byte]);
+        }
+        out.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint(input: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        input.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint too long"))
+}
+
+fn zigzag(value: i32) -> u64 {
+    ((value << 1) ^ (value >> 31)) as u32 as u64
+}
+
+fn unzigzag(value: u64) -> i32 {
+    let value = value as u32;
+    ((value >> 1) as i32) ^ -((value & 1) as i32)
+}
+
+/// Writes a trace as the guest runs, see the module docs
+pub struct TraceWriter<W: Write> {
+    // `None` once writing has failed
+    out: Option<BufWriter<W>>,
+    words: HashMap<u32, u32>,
+    // the segment running, and where the last one written ended
+    start: u32,
+    count: u64,
+    last_end: u32,
+    // the last segment's delta and count, and how many times since it's been
+    // repeated
+    last: Option<(i32, u64)>,
+    repeats: u64,
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn create(out: W) -> io::Result<Self> {
+        let mut out = BufWriter::new(out);
+        out.write_all(MAGIC)?;
+        write_u32(&mut out, VERSION)?;
+
+        Ok(Self {
+            out: Some(out),
+            words: HashMap::new(),
+            start: 0,
+            count: 0,
+            last_end: 0,
+            last: None,
+            repeats: 0,
+        })
+    }
+
+    /// Writes the segment still running and flushes the trace
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.end_segment();
+        self.end_repeats();
+        match &mut self.out {
+            Some(out) => out.flush(),
+            None => Err(io::Error::other("writing the trace failed")),
+        }
+    }
+
+    fn end_segment(&mut self) {
+        if self.count == 0 {
+            return;
+        }
+        let count = std::mem::take(&mut self.count);
+        let delta = self.start.wrapping_sub(self.last_end) as i32;
+        self.last_end = self.start.wrapping_add(count as u32 * 4);
+        if self.last == Some((delta, count)) {
+            self.repeats += 1;
+            return;
+        }
+
+        self.end_repeats();
+        self.last = Some((delta, count));
+        self.emit(|out| {
+            out.write_all(&[RECORD_SEGMENT])?;
+            write_varint(out, zigzag(delta))?;
+            write_varint(out, count)
+        });
+    }
+
+    fn end_repeats(&mut self) {
+        let repeats = std::mem::take(&mut self.repeats);
+        if repeats == 0 {
+            return;
+        }
+        self.emit(|out| {
+            out.write_all(&[RECORD_REPEAT])?;
+            write_varint(out, repeats)
+        });
+    }
+
+    fn emit(&mut self, record: impl FnOnce(&mut BufWriter<W>) -> io::Result<()>) {
+        let Some(out) = &mut self.out else {
+            return;
+        };
+        if let Err(err) = record(out) {
+            warn!(target: "ctrace", "failed to write the trace, no longer tracing: {err}");
+            self.out = None;
+        }
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn trace(&mut self, step: &TraceStep<'_>) {
+        if self.count > 0 && step.pc != self.start.wrapping_add(self.count as u32 * 4) {
+            self.end_segment();
+        }
+
+        match self.words.insert(step.pc, step.word) {
+            Some(word) if word == step.word => {}
+            old => {
+                // the segment so far ran the old word
+                if old.is_some() {
+                    self.end_segment();
+                }
+                self.end_repeats();
+                self.emit(|out| {
+                    out.write_all(&[RECORD_WORD])?;
+                    write_varint(out, step.pc as u64)?;
+                    write_u32(out, step.word)
+                });
+            }
+        }
+
+        if self.count == 0 {
+            self.start = step.pc;
+        }
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Record {
+    /// `count` instructions run from `start` on
+    Segment { start: u32, count: u64 },
+    /// The instruction at `pc` is `word`
+    Word { pc: u32, word: u32 },
+}
+
+/// Reads a trace back, record by record
+pub struct TraceReader<R: Read> {
+    input: R,
+    last_end: u32,
+    // the last segment's delta and count, and how many more times it's
+    // repeated
+    last: (i32, u64),
+    repeats: u64,
+}
+
+impl TraceReader<BufReader<File>> {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> TraceReader<R> {
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a riscy compact trace"));
+        }
+        if read_u32(&mut input)? != VERSION {
+            return Err(invalid("unsupported compact trace version"));
+        }
+        Ok(Self {
+            input,
+            last_end: 0,
+            last: (0, 0),
+            repeats: 0,
+        })
+    }
+
+    /// The next record, or `None` at the end of the trace
+    pub fn next_record(&mut self) -> io::Result<Option<Record>> {
+        if self.repeats > 0 {
+            self.repeats -= 1;
+            return Ok(Some(self.segment(self.last)));
+        }
+
+        let mut tag = [0];
+        match self.input.read_exact(&mut tag) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err),
+        }
+
+        match tag[0] {
+            RECORD_SEGMENT => {
+                let delta = unzigzag(read_varint(&mut self.input)?);
+                let count = read_varint(&mut self.input)?;
+                self.last = (delta, count);
+                Ok(Some(self.segment(self.last)))
+            }
+            RECORD_REPEAT => {
+                self.repeats = read_varint(&mut self.input)?;
+                self.next_record()
+            }
+            RECORD_WORD => Ok(Some(Record::Word {
+                pc: read_varint(&mut self.input)? as u32,
+                word: read_u32(&mut self.input)?,
+            })),
+            tag => Err(invalid(&format!("unknown record {tag:#04x}"))),
+        }
+    }
+
+    fn segment(&mut self, (delta, count): (i32, u64)) -> Record {
+        let start = self.last_end.wrapping_add(delta as u32);
+        self.last_end = start.wrapping_add(count as u32 * 4);
+        Record::Segment { start, count }
+    }
+}
+
+impl<R: Read> Iterator for TraceReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+// a branch, or a jump that doesn't link, as a loop's back edge is
+fn is_loop_jump(word: u32) -> bool {
+    matches!(
+        Instruction::decode(word),
+        Instruction::Jal { rd: 0, .. }
+            | Instruction::Beq { .. }
+            | Instruction::Bne { .. }
+            | Instruction::Blt { .. }
+            | Instruction::Bge { .. }
+            | Instruction::Bltu { .. }
+            | Instruction::Bgeu { .. }
+    )
+}
+
+/// How many times a loop went round each time it was entered
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoopStats {
+    /// Its first instruction
+    pub head: u32,
+    /// The jump back to `head` first seen
+    pub back_edge: u32,
+    pub entries: u64,
+    /// Times round, over all the entries
+    pub trips: u64,
+    pub max_trips: u64,
+    /// Entries by how many times round, bucketed by powers of two: bucket `n`
+    /// counts those of `2^n..2^(n+1)` trips
+    pub histogram: BTreeMap<u32, u64>,
+    // times round since it was last entered
+    pending: u64,
+}
+
+impl LoopStats {
+    // counts the entry that's ended, if any, and starts another
+    fn enter(&mut self) {
+        self.end();
+        self.pending = 1;
+    }
+
+    fn end(&mut self) {
+        let trips = std::mem::take(&mut self.pending);
+        if trips == 0 {
+            return;
+        }
+        self.entries += 1;
+        self.trips += trips;
+        self.max_trips = self.max_trips.max(trips);
+        *self.histogram.entry(trips.ilog2()).or_default() += 1;
+    }
+}
+
+/// What a trace ran, see the module docs
+#[derive(Debug, Clone, Default)]
+pub struct TraceStats {
+    pub instructions: u64,
+    pub segments: u64,
+    /// Instructions run at each pc
+    pub by_pc: HashMap<u32, u64>,
+    /// By `LoopStats::head`
+    pub loops: BTreeMap<u32, LoopStats>,
+}
+
+impl TraceStats {
+    pub fn collect<R: Read>(trace: TraceReader<R>) -> io::Result<Self> {
+        let mut stats = Self::default();
+        let mut words = HashMap::new();
+        // as segments repeat, they're counted as they are and only split up
+        // into pcs at the end
+        let mut segments: HashMap<(u32, u64), u64> = HashMap::new();
+        let mut last = None;
+
+        for record in trace {
+            let (start, count) = match record? {
+                Record::Word { pc, word } => {
+                    words.insert(pc, word);
+                    continue;
+                }
+                Record::Segment { start, count } => (start, count),
+            };
+            stats.instructions += count;
+            stats.segments += 1;
+            *segments.entry((start, count)).or_default() += 1;
+
+            let back_edge = last.filter(|&from| {
+                start <= from && words.get(&from).is_some_and(|&word| is_loop_jump(word))
+            });
+            let end = (start as u64 + count * 4).min(1 << 32);
+            for (&head, lp) in stats.loops.range_mut(start..) {
+                if head as u64 >= end {
+                    break;
+                }
+                if head == start && back_edge.is_some() {
+                    lp.pending += 1;
+                } else {
+                    lp.enter();
+                }
+            }
+            if let Some(from) = back_edge {
+                // found going round a second time
+                stats.loops.entry(start).or_insert_with(|| LoopStats {
+                    head: start,
+                    back_edge: from,
+                    pending: 2,
+                    ..Default::default()
+                });
+            }
+            last = Some((end - 4) as u32);
+        }
+
+        for lp in stats.loops.values_mut() {
+            lp.end();
+        }
+        for ((start, count), times) in segments {
+            for idx in 0..count {
+                *stats
+                    .by_pc
+                    .entry(start.wrapping_add(idx as u32 * 4))
+                    .or_default() += times;
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Shows the statistics, naming pcs, and totalling them by function, by
+    /// `symbols`
+    pub fn display<'a>(&'a self, symbols: &'a [Symbol]) -> impl fmt::Display + 'a {
+        DisplayStats {
+            stats: self,
+            symbols,
+        }
+    }
+}
+
+struct DisplayStats<'a> {
+    stats: &'a TraceStats,
+    symbols: &'a [Symbol],
+}
+
+impl fmt::Display for DisplayStats<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stats = self.stats;
+        writeln!(
+            f,
+            "{} instructions in {} segments, at {} pcs",
+            stats.instructions,
+            stats.segments,
+            stats.by_pc.len()
+        )?;
+
+        let mut hottest: Vec<(String, u64)> = if self.symbols.is_empty() {
+            writeln!(f, "hottest pcs:")?;
+            stats
+                .by_pc
+                .iter()
+                .map(|(&pc, &count)| (format!("{pc:#x}"), count))
+                .collect()
+        } else {
+            writeln!(f, "hottest functions:")?;
+            let mut by_function: HashMap<String, u64> = HashMap::new();
+            for (&pc, &count) in &stats.by_pc {
+                let name = match load::symbolize(self.symbols, pc as u64) {
+                    Some((name, _)) => name.to_string(),
+                    None => format!("{pc:#x}"),
+                };
+                *by_function.entry(name).or_default() += count;
+            }
+            by_function.into_iter().collect()
+        };
+        hottest.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        for (name, count) in hottest.iter().take(STATS_TOP) {
+            let share = *count as f64 / stats.instructions.max(1) as f64 * 100.0;
+            writeln!(f, "  {count:>12} {share:5.1}%  {name}")?;
+        }
+
+        if stats.loops.is_empty() {
+            return Ok(());
+        }
+        writeln!(f, "loops, by trips:")?;
+        let mut loops: Vec<&LoopStats> = stats.loops.values().collect();
+        loops.sort_by_key(|lp| (std::cmp::Reverse(lp.trips), lp.head));
+        for lp in loops.iter().take(STATS_TOP) {
+            writeln!(
+                f,
+                "  {} back from {}: entered {} times, {} trips, {:.1} mean, {} max",
+                load::describe(self.symbols, lp.head),
+                load::describe(self.symbols, lp.back_edge),
+                lp.entries,
+                lp.trips,
+                lp.trips as f64 / lp.entries.max(1) as f64,
+                lp.max_trips
+            )?;
+            for (&bucket, &entries) in &lp.histogram {
+                let lo = 1u64 << bucket;
+                let hi = (lo << 1) - 1;
+                let trips = if lo == hi {
+                    lo.to_string()
+                } else {
+                    format!("{lo}..={hi}")
+                };
+                writeln!(f, "    {trips:>16} trips: {entries}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A time an instruction ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Execution {
+    /// How many instructions ran before it
+    pub index: u64,
+    /// Where it was jumped to from, if it was
+    pub from: Option<u32>,
+}
+
+/// Calls `f` with each time the instruction at `pc` ran, in order
+pub fn executions<R: Read>(
+    trace: TraceReader<R>,
+    pc: u32,
+    mut f: impl FnMut(Execution),
+) -> io::Result<()> {
+    let mut index = 0;
+    let mut last = None;
+
+    for record in trace {
+        let Record::Segment { start, count } = record? else {
+            continue;
+        };
+        let offset = pc.wrapping_sub(start) as u64;
+        if offset.is_multiple_of(4) && offset / 4 < count {
+            f(Execution {
+                index: index + offset / 4,
+                from: if offset == 0 { last } else { None },
+            });
+        }
+        index += count;
+        last = Some(start.wrapping_add((count as u32).wrapping_sub(1) * 4));
+    }
+    Ok(())
+}
@@ -0,0 +1,281 @@
+//! Handlers for the custom-0 to custom-3 major opcodes.
+//!
+//! The base ISA leaves four major opcodes free for vendor extensions. A handler
+//! registered for one with `Core32::register_custom` is called with every
+//! instruction word in that space and a `Machine`, through which it reads and
+//! writes the guest's registers, memory and the CSRs with a `csr::CsrView`.
+//! That's enough to model an accelerator or try out an ISA extension without
+//! changing the decoder. How the rest of the word is laid out is up to the
+//! handler; the standard fields can be pulled out with
+//! `opcodes::Field::extract`.
+//!
+//! Instructions in a space with no handler are illegal, as on hardware without
+//! the extension. Before any instruction traps as illegal, though, it's offered
+//! to the handler given to `Core32::set_illegal_handler`, if any, which gets the
+//! same `Machine` and so can emulate it instead. `--self-check` can't know what a handler does, so it takes
+//! the core's word for it, like it does for syscalls.
+//!
+//! Some vendors' extensions come built in, as an `IsaVendor` (`riscy
+//! --isa-vendor xthead`) with a handler for the space they're in.
+
+use std::{error::Error, fmt, str::FromStr};
+
+use crate::{
+    csr::{self, CsrView},
+    xthead,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CustomOpcode {
+    Custom0,
+    Custom1,
+    Custom2,
+    Custom3,
+}
+
+impl CustomOpcode {
+    pub const ALL: [CustomOpcode; 4] = [
+        CustomOpcode::Custom0,
+        CustomOpcode::Custom1,
+        CustomOpcode::Custom2,
+        CustomOpcode::Custom3,
+    ];
+
+    /// The major opcode, bits 6..0 of the instruction
+    pub fn major(self) -> u32 {
+        match self {
+            CustomOpcode::Custom0 => 0x0b,
+            CustomOpcode::Custom1 => 0x2b,
+            CustomOpcode::Custom2 => 0x5b,
+            CustomOpcode::Custom3 => 0x7b,
+        }
+    }
+
+    /// The custom space `inst` is in, if any
+    pub fn of(inst: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|op| inst & 0x7f == op.major())
+    }
+}
+
+impl fmt::Display for CustomOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "custom-{}", *self as u8)
+    }
+}
+
+/// A vendor whose extensions riscy implements, parsed from its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsaVendor {
+    /// T-Head's, see `xthead`
+    XThead,
+}
+
+/// A built-in vendor's handler, which keeps no state of its own
+pub type VendorFn = fn(u32, &mut Machine) -> Result<(), CustomError>;
+
+impl IsaVendor {
+    /// The space its instructions are in, and the handler to register there
+    pub fn handler(self) -> (CustomOpcode, VendorFn) {
+        match self {
+            IsaVendor::XThead => (CustomOpcode::Custom0, xthead::execute),
+        }
+    }
+}
+
+impl FromStr for IsaVendor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xthead" => Ok(IsaVendor::XThead),
+            _ => Err(format!("unknown ISA vendor '{s}', expected xthead")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CustomError {
+    /// The handler doesn't implement this encoding
+    Illegal,
+    /// An access outside guest memory
+    Fault { addr: u32, len: u32 },
+}
+
+impl fmt::Display for CustomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CustomError::Illegal => write!(f, "illegal instruction"),
+            CustomError::Fault { addr, len } => {
+                write!(f, "bad guest buffer {addr:#x} (len {len})")
+            }
+        }
+    }
+}
+
+impl Error for CustomError {}
+
+/// The guest state a custom instruction can see and change. Registers are
+/// written back only if the handler succeeds, so one that fails leaves them
+/// as they were
+pub struct Machine<'a> {
+    pc: u32,
+    gp_regs: [i32; 32],
+    fp_regs: [u64; 32],
+    next_pc: Option<u32>,
+    memory: &'a mut [u8],
+    // those of `csr::VIEWED` implemented, and which have been set
+    csrs: [Option<u32>; csr::VIEWED.len()],
+    csrs_set: u8,
+}
+
+impl<'a> Machine<'a> {
+    pub fn new(pc: u32, gp_regs: [i32; 32], fp_regs: [u64; 32], memory: &'a mut [u8]) -> Self {
+        Self {
+            pc,
+            gp_regs,
+            fp_regs,
+            next_pc: None,
+            memory,
+            csrs: [None; csr::VIEWED.len()],
+            csrs_set: 0,
+        }
+    }
+
+    /// Gives the handler the values of the CSRs in `csr::VIEWED`
+    pub(crate) fn with_csrs(mut self, csrs: [Option<u32>; csr::VIEWED.len()]) -> Self {
+        self.csrs = csrs;
+        self
+    }
+
+    pub(crate) fn finish(self) -> Outcome {
+        Outcome {
+            gp_regs: self.gp_regs,
+            fp_regs: self.fp_regs,
+            next_pc: self.next_pc,
+            csrs: (0..csr::VIEWED.len())
+                .filter(|idx| self.csrs_set & (1 << idx) != 0)
+                .filter_map(|idx| Some((csr::VIEWED[idx], self.csrs[idx]?)))
+                .collect(),
+        }
+    }
+
+    /// The address of the instruction being executed
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    pub fn x(&self, idx: u8) -> u32 {
+        if idx == 0 {
+            0
+        } else {
+            self.gp_regs[idx as usize] as u32
+        }
+    }
+
+    /// Writes to `x0` are dropped
+    pub fn set_x(&mut self, idx: u8, value: u32) {
+        if idx != 0 {
+            self.gp_regs[idx as usize] = value as i32;
+        }
+    }
+
+    /// The raw bits of `f<idx>`; singles are NaN-boxed in the low half
+    pub fn f(&self, idx: u8) -> u64 {
+        self.fp_regs[idx as usize]
+    }
+
+    pub fn set_f(&mut self, idx: u8, bits: u64) {
+        self.fp_regs[idx as usize] = bits;
+    }
+
+    /// `View`'s CSR, or `None` if it isn't implemented, see `csr::CsrView`
+    pub fn csr<View: CsrView>(&self) -> Option<View> {
+        let idx = csr::VIEWED.iter().position(|&csr| csr == View::CSR)?;
+        self.csrs[idx].map(View::from_bits)
+    }
+
+    /// Writes `value` to its CSR, like the registers only if the handler
+    /// succeeds, returning `false` if it isn't implemented
+    pub fn set_csr<View: CsrView>(&mut self, value: View) -> bool {
+        let Some(idx) = csr::VIEWED.iter().position(|&csr| csr == View::CSR) else {
+            return false;
+        };
+        let Some(old) = &mut self.csrs[idx] else {
+            return false;
+        };
+        *old = value.bits();
+        self.csrs_set |= 1 << idx;
+        true
+    }
+
+    /// Carries on at `target` rather than the next instruction
+    pub fn jump(&mut self, target: u32) {
+        self.next_pc = Some(target);
+    }
+
+    pub fn bytes(&self, addr: u32, len: u32) -> Result<&[u8], CustomError> {
+        let range = self.range(addr, len)?;
+        Ok(&self.memory[range])
+    }
+
+    pub fn bytes_mut(&mut self, addr: u32, len: u32) -> Result<&mut [u8], CustomError> {
+        let range = self.range(addr, len)?;
+        Ok(&mut self.memory[range])
+    }
+
+    pub fn load_u32(&self, addr: u32) -> Result<u32, CustomError> {
+        let bytes = self.bytes(addr, 4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn store_u32(&mut self, addr: u32, value: u32) -> Result<(), CustomError> {
+        self.bytes_mut(addr, 4)?
+            .copy_from_slice(&value.to_le_bytes());
+        Ok(())
+    }
+
+    fn range(&self, addr: u32, len: u32) -> Result<std::ops::Range<usize>, CustomError> {
+        let start = addr as usize;
+        let end = start + len as usize;
+        if end > self.memory.len() {
+            return Err(CustomError::Fault { addr, len });
+        }
+        Ok(start..end)
+    }
+}
+
+/// The registers to write back after a handler succeeds, and where to carry on
+/// if not the next instruction
+pub(crate) struct Outcome {
+    pub(crate) gp_regs: [i32; 32],
+    pub(crate) fp_regs: [u64; 32],
+    pub(crate) next_pc: Option<u32>,
+    pub(crate) csrs: Vec<(u16, u32)>,
+}
+
+pub type CustomFn = Box<dyn FnMut(u32, &mut Machine) -> Result<(), CustomError>>;
+
+/// The handlers for each custom space
+#[derive(Default)]
+pub struct CustomOps {
+    handlers: [Option<CustomFn>; 4],
+}
+
+impl CustomOps {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `f` for `opcode`, replacing any previous handler
+    pub fn register(
+        &mut self,
+        opcode: CustomOpcode,
+        f: impl FnMut(u32, &mut Machine) -> Result<(), CustomError> + 'static,
+    ) {
+        self.handlers[opcode as usize] = Some(Box::new(f));
+    }
+
+    pub fn get_mut(&mut self, opcode: CustomOpcode) -> Option<&mut CustomFn> {
+        self.handlers[opcode as usize].as_mut()
+    }
+}
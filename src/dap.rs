@@ -0,0 +1,638 @@
+//! A Debug Adapter Protocol server, so IDEs like VS Code can debug guests.
+//!
+//! `serve` speaks DAP over a pair of streams (stdio, or a TCP connection) for
+//! a single session: `launch` loads the program, breakpoints can be set by
+//! source line (through the DWARF line table, see `lines`) or by address, and
+//! the guest can be continued, paused and stepped by line or instruction. There
+//! is one thread, and one stack frame, as riscy doesn't unwind; registers are
+//! shown as variables, as are the CSRs implemented, with their fields named.
+//! The guest's output is forwarded as output events, and it gets no stdin.
+//! `launch` also takes `breakSyscalls` and `breakCalls`, lists of syscalls and
+//! functions to stop at as with `--break-syscall` and `--break-call`.
+//!
+//! Requests are read on a thread of their own, so a running guest can be
+//! paused.
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::Path,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    breakpoint::{Breakpoints, SyscallSpec},
+    core::{Core32, RunInfo, StopReason, UnalignedMemReader},
+    csr,
+    instruction::{self, Instruction},
+    lines::LineTable,
+    load::{self, LoadedElf, Symbol},
+    register::Register,
+};
+
+// instructions to run between looking for requests while the guest runs
+const SLICE: u64 = 1 << 16;
+
+const MEMORY_SIZE: usize = 16777215;
+
+const THREAD_ID: i64 = 1;
+const GP_REGS_REF: i64 = 1;
+const FP_REGS_REF: i64 = 2;
+const CSRS_REF: i64 = 3;
+
+/// What the guest is doing between requests
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Resume {
+    Stopped,
+    Continue,
+    /// Step to the start of a different line (or back to the start of this
+    /// one); `over` runs calls made on the way to completion
+    Line {
+        from: Option<(u32, u32)>,
+        over: bool,
+        // the return address and sp of a call being stepped over
+        returning_to: Option<(u32, u32)>,
+    },
+    /// Step until a return leaves the frame with this sp
+    Out {
+        sp: u32,
+    },
+}
+
+struct Program {
+    core: Core32<UnalignedMemReader<u32>>,
+    symbols: Vec<Symbol>,
+    lines: LineTable,
+    // how much of the captured output has been sent
+    stdout_sent: usize,
+    stderr_sent: usize,
+}
+
+struct Session<W: Write> {
+    out: W,
+    seq: i64,
+    program: Option<Program>,
+    stop_on_entry: bool,
+    resume: Resume,
+    // by source file, and set by address
+    source_breakpoints: Vec<(String, Vec<u32>)>,
+    instruction_breakpoints: Vec<u32>,
+    breakpoints: HashSet<u32>,
+}
+
+/// Serves one debug session, returning once the client disconnects
+pub fn serve(input: impl Read + Send + 'static, output: impl Write) -> io::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        while let Ok(Some(msg)) = read_message(&mut input) {
+            if tx.send(msg).is_err() {
+                break;
+            }
+        }
+    });
+
+    Session {
+        out: output,
+        seq: 1,
+        program: None,
+        stop_on_entry: false,
+        resume: Resume::Stopped,
+        source_breakpoints: Vec::new(),
+        instruction_breakpoints: Vec::new(),
+        breakpoints: HashSet::new(),
+    }
+    .run(rx)
+}
+
+/// Reads one `Content-Length` framed message, or `None` at the end of input
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            len = value.trim().parse().ok();
+        }
+    }
+
+    let len = len.ok_or_else(|| io::Error::other("message without a Content-Length"))?;
+    let mut body = vec![0; len];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(io::Error::other)
+}
+
+impl<W: Write> Session<W> {
+    fn run(mut self, rx: Receiver<Value>) -> io::Result<()> {
+        loop {
+            let msg = if self.resume == Resume::Stopped {
+                match rx.recv() {
+                    Ok(msg) => msg,
+                    Err(_) => return Ok(()),
+                }
+            } else {
+                match rx.try_recv() {
+                    Ok(msg) => msg,
+                    Err(TryRecvError::Empty) => {
+                        self.run_slice()?;
+                        continue;
+                    }
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            };
+
+            if !self.handle(&msg)? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn send(&mut self, mut msg: Value) -> io::Result<()> {
+        msg["seq"] = json!(self.seq);
+        self.seq += 1;
+
+        let body = msg.to_string();
+        write!(self.out, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+        self.out.flush()
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn respond_err(&mut self, request: &Value, message: &str) -> io::Result<()> {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn stopped(&mut self, reason: &str) -> io::Result<()> {
+        self.resume = Resume::Stopped;
+        self.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        )
+    }
+
+    /// Handles a request, returning `false` once the session is over
+    fn handle(&mut self, request: &Value) -> io::Result<bool> {
+        let args = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or_default();
+
+        if self.program.is_none() && !matches!(command, "initialize" | "launch" | "disconnect") {
+            self.respond_err(request, "no program has been launched")?;
+            return Ok(true);
+        }
+
+        match command {
+            "initialize" => {
+                self.respond(
+                    request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsInstructionBreakpoints": true,
+                        "supportsSteppingGranularity": true,
+                        "supportsTerminateRequest": true,
+                    }),
+                )?;
+            }
+            "launch" => match launch(args) {
+                Ok(program) => {
+                    self.program = Some(program);
+                    self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                    self.respond(request, json!({}))?;
+                    // breakpoints need the program's line table, so wait for it
+                    self.event("initialized", json!({}))?;
+                }
+                Err(err) => self.respond_err(request, &err.to_string())?,
+            },
+            "setBreakpoints" => {
+                let path = args["source"]["path"].as_str().unwrap_or_default();
+                let lines = args["breakpoints"].as_array().cloned().unwrap_or_default();
+
+                let program = self.program.as_ref().unwrap();
+                let mut addrs = Vec::new();
+                let breakpoints: Vec<_> = lines
+                    .iter()
+                    .map(|bp| {
+                        let line = bp["line"].as_u64().unwrap_or(0) as u32;
+                        match program.lines.line_addrs(Path::new(path), line) {
+                            Some((line, found)) => {
+                                addrs.extend(found);
+                                json!({ "verified": true, "line": line })
+                            }
+                            None => json!({ "verified": false, "message": "no code at this line" }),
+                        }
+                    })
+                    .collect();
+
+                self.source_breakpoints.retain(|(source, _)| source != path);
+                self.source_breakpoints.push((path.to_owned(), addrs));
+                self.update_breakpoints();
+                self.respond(request, json!({ "breakpoints": breakpoints }))?;
+            }
+            "setInstructionBreakpoints" => {
+                let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+                let addrs: Vec<_> = requested
+                    .iter()
+                    .map(|bp| {
+                        let reference = bp["instructionReference"].as_str().unwrap_or_default();
+                        let offset = bp["offset"].as_i64().unwrap_or(0);
+                        parse_addr(reference).map(|addr| addr.wrapping_add(offset as u32))
+                    })
+                    .collect();
+
+                self.instruction_breakpoints = addrs.iter().flatten().copied().collect();
+                self.update_breakpoints();
+
+                let breakpoints: Vec<_> = addrs
+                    .iter()
+                    .map(|addr| json!({ "verified": addr.is_some() }))
+                    .collect();
+                self.respond(request, json!({ "breakpoints": breakpoints }))?;
+            }
+            "configurationDone" => {
+                self.respond(request, json!({}))?;
+                if self.stop_on_entry {
+                    self.stopped("entry")?;
+                } else {
+                    self.resume = Resume::Continue;
+                }
+            }
+            "threads" => {
+                self.respond(
+                    request,
+                    json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }),
+                )?;
+            }
+            "stackTrace" => {
+                let frame = self.frame();
+                self.respond(request, json!({ "stackFrames": [frame], "totalFrames": 1 }))?;
+            }
+            "scopes" => {
+                self.respond(
+                    request,
+                    json!({ "scopes": [
+                        { "name": "Registers", "variablesReference": GP_REGS_REF, "expensive": false },
+                        { "name": "FP registers", "variablesReference": FP_REGS_REF, "expensive": false },
+                        { "name": "CSRs", "variablesReference": CSRS_REF, "expensive": false },
+                    ] }),
+                )?;
+            }
+            "variables" => {
+                let core = &self.program.as_ref().unwrap().core;
+                let variables: Vec<_> = match args["variablesReference"].as_i64() {
+                    Some(GP_REGS_REF) => {
+                        let mut variables = vec![
+                            json!({ "name": "pc", "value": format!("{:#010x}", core.pc()), "variablesReference": 0 }),
+                        ];
+                        variables.extend(core.gp_regs().iter().enumerate().map(|(idx, &value)| {
+                            json!({
+                                "name": Register::gp(idx as u8).to_string(),
+                                "value": format!("{:#010x} ({value})", value as u32),
+                                "variablesReference": 0,
+                            })
+                        }));
+                        variables
+                    }
+                    Some(FP_REGS_REF) => core
+                        .fp_regs()
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, &bits)| {
+                            json!({
+                                "name": Register::fp(idx as u8).to_string(),
+                                "value": format!("{bits:#018x} ({})", f64::from_bits(bits)),
+                                "variablesReference": 0,
+                            })
+                        })
+                        .collect(),
+                    Some(CSRS_REF) => (0..0x1000)
+                        .filter_map(|csr| Some((csr, core.read_csr(csr)?)))
+                        // the counters that weren't given an event are just noise
+                        .filter(|&(csr, value)| value != 0 || csr::hpm_counter(csr).is_none())
+                        .map(|(csr, value)| {
+                            let mut text = format!("{value:#010x}");
+                            if let Some(fields) = csr::fields(csr, value) {
+                                for (name, value) in fields {
+                                    text += &format!(" {name}={value}");
+                                }
+                            }
+                            json!({
+                                "name": instruction::csr_name(csr),
+                                "value": text,
+                                "variablesReference": 0,
+                            })
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                self.respond(request, json!({ "variables": variables }))?;
+            }
+            "continue" => {
+                self.resume = Resume::Continue;
+                self.respond(request, json!({ "allThreadsContinued": true }))?;
+            }
+            "next" | "stepIn" => {
+                let program = self.program.as_ref().unwrap();
+                let by_instruction =
+                    args["granularity"] == "instruction" || program.lines.is_empty();
+                let pc = program.core.pc();
+                let from = program.lines.location(pc).map(|(_, line)| (pc, line));
+
+                self.respond(request, json!({}))?;
+                if by_instruction {
+                    self.step_instruction()?;
+                } else {
+                    self.resume = Resume::Line {
+                        from,
+                        over: command == "next",
+                        returning_to: None,
+                    };
+                }
+            }
+            "stepOut" => {
+                let sp = self.program.as_ref().unwrap().core.read(Register::Sp) as u32;
+                self.resume = Resume::Out { sp };
+                self.respond(request, json!({}))?;
+            }
+            "pause" => {
+                self.respond(request, json!({}))?;
+                self.stopped("pause")?;
+            }
+            "disconnect" | "terminate" => {
+                self.respond(request, json!({}))?;
+                if command == "terminate" {
+                    self.event("terminated", json!({}))?;
+                }
+                return Ok(false);
+            }
+            _ => self.respond_err(request, &format!("unsupported request '{command}'"))?,
+        }
+
+        Ok(true)
+    }
+
+    fn update_breakpoints(&mut self) {
+        self.breakpoints = self
+            .source_breakpoints
+            .iter()
+            .flat_map(|(_, addrs)| addrs)
+            .chain(&self.instruction_breakpoints)
+            .copied()
+            .collect();
+    }
+
+    fn frame(&self) -> Value {
+        let program = self.program.as_ref().unwrap();
+        let pc = program.core.pc();
+        let name = load::symbolize(&program.symbols, pc as u64)
+            .map_or_else(|| format!("{pc:#x}"), |(name, _)| name.to_owned());
+
+        let mut frame = json!({
+            "id": 0,
+            "name": name,
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": format!("{pc:#x}"),
+        });
+        if let Some((path, line)) = program.lines.location(pc) {
+            frame["source"] = json!({ "path": path });
+            frame["line"] = json!(line);
+            frame["column"] = json!(1);
+        }
+        frame
+    }
+
+    fn step_instruction(&mut self) -> io::Result<()> {
+        let finished = self.program.as_mut().unwrap().core.step();
+        self.forward_output()?;
+        match finished {
+            Some(RunInfo {
+                reason: StopReason::Breakpoint { .. },
+                ..
+            }) => self.stopped("breakpoint"),
+            Some(info) => self.finish(info),
+            None => self.stopped("step"),
+        }
+    }
+
+    /// Runs the guest for a while, until it stops or `SLICE` instructions pass
+    fn run_slice(&mut self) -> io::Result<()> {
+        let program = self.program.as_mut().unwrap();
+        let mut stop = None;
+
+        for _ in 0..SLICE {
+            let pc = program.core.pc();
+            let sp = program.core.read(Register::Sp) as u32;
+            let instr = instr_at(&program.core, pc);
+
+            let leaving = match &mut self.resume {
+                Resume::Out { sp: frame_sp } => is_ret(instr) && sp >= *frame_sp,
+                Resume::Line {
+                    over: true,
+                    returning_to: returning_to @ None,
+                    ..
+                } if is_call(instr) => {
+                    *returning_to = Some((pc.wrapping_add(4), sp));
+                    false
+                }
+                _ => false,
+            };
+
+            match program.core.step() {
+                // from `breakSyscalls` or `breakCalls`
+                Some(RunInfo {
+                    reason: StopReason::Breakpoint { .. },
+                    ..
+                }) => {
+                    stop = Some(Ok("breakpoint"));
+                    break;
+                }
+                Some(info) => {
+                    stop = Some(Err(info));
+                    break;
+                }
+                None => {}
+            }
+
+            let pc = program.core.pc();
+            if self.breakpoints.contains(&pc) {
+                stop = Some(Ok("breakpoint"));
+                break;
+            }
+
+            let done = match &mut self.resume {
+                Resume::Line {
+                    from, returning_to, ..
+                } => {
+                    // back from a call being stepped over
+                    if let Some((ret, call_sp)) = *returning_to {
+                        if pc == ret && program.core.read(Register::Sp) as u32 >= call_sp {
+                            *returning_to = None;
+                        }
+                    }
+
+                    let line = program.lines.location(pc).map(|(_, line)| line);
+                    returning_to.is_none()
+                        && program.lines.is_line_start(pc)
+                        && from.is_none_or(|(from_pc, from_line)| {
+                            Some(from_line) != line || pc == from_pc
+                        })
+                }
+                Resume::Out { .. } => leaving,
+                _ => false,
+            };
+            if done {
+                stop = Some(Ok("step"));
+                break;
+            }
+        }
+
+        self.forward_output()?;
+        match stop {
+            Some(Ok(reason)) => self.stopped(reason),
+            Some(Err(info)) => self.finish(info),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self, info: RunInfo) -> io::Result<()> {
+        self.resume = Resume::Stopped;
+
+        let code = match info.reason {
+            StopReason::Exited | StopReason::Fatal(_) => info.return_code,
+            StopReason::IllegalInstruction { .. } => 128 + 4,
+            _ => 1,
+        };
+        if !matches!(info.reason, StopReason::Exited) {
+            let message = format!("stopped: {:?}\n", info.reason);
+            self.event(
+                "output",
+                json!({ "category": "console", "output": message }),
+            )?;
+        }
+
+        self.event("exited", json!({ "exitCode": code }))?;
+        self.event("terminated", json!({}))
+    }
+
+    fn forward_output(&mut self) -> io::Result<()> {
+        let program = self.program.as_mut().unwrap();
+        let stdout = &program.core.captured_stdout()[program.stdout_sent..];
+        let stderr = &program.core.captured_stderr()[program.stderr_sent..];
+        program.stdout_sent += stdout.len();
+        program.stderr_sent += stderr.len();
+        let stdout = String::from_utf8_lossy(stdout).into_owned();
+        let stderr = String::from_utf8_lossy(stderr).into_owned();
+
+        for (category, output) in [("stdout", stdout), ("stderr", stderr)] {
+            if !output.is_empty() {
+                self.event("output", json!({ "category": category, "output": output }))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn launch(args: &Value) -> Result<Program, Box<dyn Error>> {
+    let path = args["program"].as_str().ok_or("launch needs a `program`")?;
+
+    let elf = LoadedElf::load(path)?;
+    elf.check_runnable()?;
+    let symbols = elf.symbols.clone();
+    let lines = LineTable::load(path)?;
+
+    let mut core = Core32::new(elf, None, MEMORY_SIZE, false);
+    core.set_stdin(Vec::new());
+    core.capture_output();
+
+    // `breakSyscalls` and `breakCalls`, as `--break-syscall` and `--break-call`
+    let strings = |key: &str| -> Vec<String> {
+        args[key]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|value| value.as_str().map(str::to_owned))
+            .collect()
+    };
+    let syscalls = strings("breakSyscalls")
+        .iter()
+        .map(|name| name.parse())
+        .collect::<Result<Vec<SyscallSpec>, _>>()?;
+    let calls = strings("breakCalls");
+    if !syscalls.is_empty() || !calls.is_empty() {
+        core.add_hook(Box::new(Breakpoints::new(
+            &syscalls,
+            &calls,
+            symbols.clone(),
+        )?));
+    }
+
+    Ok(Program {
+        core,
+        symbols,
+        lines,
+        stdout_sent: 0,
+        stderr_sent: 0,
+    })
+}
+
+fn instr_at(core: &Core32<UnalignedMemReader<u32>>, pc: u32) -> Option<Instruction> {
+    let bytes = core.memory().get(pc as usize..pc as usize + 4)?;
+    Some(Instruction::decode(u32::from_le_bytes(
+        bytes.try_into().unwrap(),
+    )))
+}
+
+fn is_call(instr: Option<Instruction>) -> bool {
+    matches!(
+        instr,
+        Some(Instruction::Jal { rd: 1, .. } | Instruction::Jalr { rd: 1, .. })
+    )
+}
+
+fn is_ret(instr: Option<Instruction>) -> bool {
+    matches!(
+        instr,
+        Some(Instruction::Jalr {
+            rd: 0,
+            rs1: 1,
+            imm: 0
+        })
+    )
+}
+
+fn parse_addr(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
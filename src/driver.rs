@@ -0,0 +1,116 @@
+//! Running a core as a future, so many guests can share one executor thread.
+//!
+//! `Core32::run_async` runs the guest in slices of `SLICE` instructions,
+//! yielding to the executor between them. A guest reading from an empty
+//! `GuestPipe` stdin doesn't block the thread: the future waits for the host to
+//! write to (or close) the pipe instead. This only depends on `std::future`, so
+//! it works with any executor, though as `Core32` isn't `Send` the future has to
+//! be run on a local one (e.g. tokio's `LocalSet`).
+//!
+//! `read` is the only syscall riscy emulates that can block; there is no
+//! `poll` or `futex`.
+
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use crate::core::{Core32, MemReader, RunInfo, StopReason};
+
+// instructions to run before yielding to the executor
+const SLICE: u64 = 1 << 16;
+
+#[derive(Debug, Default)]
+struct PipeState {
+    buf: VecDeque<u8>,
+    closed: bool,
+    waker: Option<Waker>,
+}
+
+/// A guest stdin the host writes to while the guest runs, see
+/// `Core32::set_stdin_pipe`
+#[derive(Debug, Clone, Default)]
+pub struct GuestPipe {
+    state: Arc<Mutex<PipeState>>,
+}
+
+impl GuestPipe {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&self, data: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        state.buf.extend(data);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Ends the input; once drained, guest reads return 0
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Reads what's available into `buf`, or `None` if that would block
+    pub(crate) fn read(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.buf.is_empty() && !state.closed {
+            return None;
+        }
+
+        let count = buf.len().min(state.buf.len());
+        for (dst, src) in buf.iter_mut().zip(state.buf.drain(..count)) {
+            *dst = src;
+        }
+        Some(count)
+    }
+
+    /// Whether a read wouldn't block; if it would, `waker` is woken once it won't
+    fn poll_readable(&self, waker: &Waker) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !state.buf.is_empty() || state.closed {
+            return true;
+        }
+
+        state.waker = Some(waker.clone());
+        false
+    }
+}
+
+/// The future returned by `Core32::run_async`
+pub struct RunAsync<'a, Reader: MemReader<Idx = u32>> {
+    pub(crate) core: &'a mut Core32<Reader>,
+}
+
+impl<Reader: MemReader<Idx = u32>> Future for RunAsync<'_, Reader> {
+    type Output = RunInfo;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<RunInfo> {
+        for _ in 0..SLICE {
+            let Some(info) = self.core.step() else {
+                continue;
+            };
+
+            if !matches!(info.reason, StopReason::WouldBlock { .. }) {
+                return Poll::Ready(info);
+            }
+
+            let pipe = self.core.stdin_pipe().expect("blocked without a pipe");
+            if !pipe.poll_readable(cx.waker()) {
+                return Poll::Pending;
+            }
+        }
+
+        // give other guests a turn
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
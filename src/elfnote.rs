@@ -0,0 +1,138 @@
+//! Emulator configuration a guest binary carries in an ELF note, so `riscy
+//! app.elf` runs it the way it needs without flags.
+//!
+//! The note is in a `.note.riscy` section, named `riscy` with type
+//! `NT_RISCY_CONFIG`, and its descriptor is text, settings separated by
+//! whitespace:
+//!
+//! - `memory=SIZE`, how much memory, e.g. `64M`
+//! - `isa=ISA`, what the program needs, e.g. `rv32imafd_zicsr`, which is
+//!   refused if riscy doesn't implement it, see `machine::Isa`
+//! - `device=uart@ADDR`, a 16550 UART on stdout, see `uart`
+//! - `device=console@ADDR`, a magic console, see `console`
+//! - `device=htif`, HTIF through the `tohost` symbol, see `htif`
+//!
+//! `include/riscy_config.h` has a macro writing the note, from C or assembly.
+//! The linker script has to keep the section, as `examples/bare-metal/link.ld`
+//! does. What's given on the command line, or to `MachineBuilder`, wins over
+//! the note.
+
+use std::{fmt, str::FromStr};
+
+use crate::limits::ByteSize;
+
+/// The note's name
+pub const NOTE_NAME: &str = "riscy";
+/// The note's type, `RY` as the host call syscall is
+pub const NT_RISCY_CONFIG: u64 = 0x5259;
+
+/// A device the note asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceSpec {
+    Uart(u32),
+    Console(u32),
+    Htif,
+}
+
+impl FromStr for DeviceSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid device '{s}', expected uart@ADDR, console@ADDR or htif");
+        if s == "htif" {
+            return Ok(DeviceSpec::Htif);
+        }
+
+        let (kind, addr) = s.split_once('@').ok_or_else(invalid)?;
+        let addr = match addr.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => addr.parse(),
+        }
+        .map_err(|_| invalid())?;
+        match kind {
+            "uart" => Ok(DeviceSpec::Uart(addr)),
+            "console" => Ok(DeviceSpec::Console(addr)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for DeviceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSpec::Uart(addr) => write!(f, "uart@{addr:#x}"),
+            DeviceSpec::Console(addr) => write!(f, "console@{addr:#x}"),
+            DeviceSpec::Htif => f.write_str("htif"),
+        }
+    }
+}
+
+/// What the note says, see the module docs, empty for a binary without one
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ElfConfig {
+    pub memory: Option<usize>,
+    /// The ISA string as written, checked by `LoadedElf::check_runnable`
+    pub isa: Option<String>,
+    pub devices: Vec<DeviceSpec>,
+}
+
+impl ElfConfig {
+    /// The note's descriptor, text up to any NUL padding
+    pub fn parse(desc: &[u8]) -> Result<Self, String> {
+        let len = desc.iter().position(|&b| b == 0).unwrap_or(desc.len());
+        let text = std::str::from_utf8(&desc[..len])
+            .map_err(|_| "the riscy note isn't text".to_owned())?;
+
+        let mut config = Self::default();
+        for setting in text.split_whitespace() {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("invalid setting '{setting}' in the riscy note"))?;
+            match key {
+                "memory" => {
+                    let ByteSize(bytes) = value.parse()?;
+                    config.memory = Some(bytes as usize);
+                }
+                "isa" => config.isa = Some(value.to_owned()),
+                "device" => config.devices.push(value.parse()?),
+                _ => return Err(format!("unknown setting '{key}' in the riscy note")),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Where it asks for a magic console, if it does
+    pub fn console(&self) -> Option<u32> {
+        self.devices.iter().find_map(|device| match device {
+            DeviceSpec::Console(addr) => Some(*addr),
+            _ => None,
+        })
+    }
+
+    /// Where it asks for UARTs
+    pub fn uarts(&self) -> impl Iterator<Item = u32> + '_ {
+        self.devices.iter().filter_map(|device| match device {
+            DeviceSpec::Uart(addr) => Some(*addr),
+            _ => None,
+        })
+    }
+
+    pub fn htif(&self) -> bool {
+        self.devices.contains(&DeviceSpec::Htif)
+    }
+}
+
+/// The settings as they'd be written in the note
+impl fmt::Display for ElfConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut settings = Vec::new();
+        if let Some(memory) = self.memory {
+            settings.push(format!("memory={memory}"));
+        }
+        if let Some(isa) = &self.isa {
+            settings.push(format!("isa={isa}"));
+        }
+        settings.extend(self.devices.iter().map(|device| format!("device={device}")));
+        f.write_str(&settings.join(" "))
+    }
+}
@@ -0,0 +1,167 @@
+//! A queue of events keyed on the virtual clock, for devices and embedders
+//! that need something done a number of cycles from now: a timer firing, a
+//! UART's FIFO draining, a request completing.
+//!
+//! The clock is the instruction count, as every instruction is a cycle. Each
+//! event is a callback run between two instructions, once the guest has run
+//! exactly as many as it was scheduled for, so a run with events is as
+//! deterministic as one without. Events due at the same time run in the order
+//! they were scheduled. A callback gets a `Fired`, to schedule more events and
+//! raise interrupts, see `irq`.
+//!
+//! `Core32::events` hands out an `Events`, a handle to the core's queue that a
+//! device keeps to schedule with. The core only looks at the queue between
+//! instructions when it has to, so `Events::schedule_in` counts from the next
+//! time it does: for an event scheduled by a device's `write`, straight after
+//! that store. A device's `read` can't schedule, as the core doesn't look
+//! after a load.
+//!
+//! Nothing in the queue survives a snapshot, as callbacks can't be saved.
+
+use std::{cell::RefCell, collections::BTreeMap, fmt, rc::Rc};
+
+/// A callback run when an event is due
+pub type Event = Box<dyn FnOnce(&mut Fired)>;
+
+/// What `Events::cancel` takes to cancel an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EventId(u64);
+
+#[derive(Default)]
+struct Queue {
+    // the clock when the core last looked
+    now: u64,
+    // pending events, by when they're due and then the order they were
+    // scheduled in, which their ids are
+    due: BTreeMap<(u64, u64), Event>,
+    // events scheduled to run a number of cycles after whenever the core next
+    // looks, with their ids
+    relative: Vec<(u64, u64, Event)>,
+    next_id: u64,
+}
+
+/// A handle to the core's event queue, see the module docs
+#[derive(Clone, Default)]
+pub struct Events(Rc<RefCell<Queue>>);
+
+impl fmt::Debug for Events {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let queue = self.0.borrow();
+        f.debug_struct("Events")
+            .field("now", &queue.now)
+            .field("pending", &(queue.due.len() + queue.relative.len()))
+            .finish()
+    }
+}
+
+impl Events {
+    /// The clock when the core last looked at the queue, which may be behind,
+    /// use `Fired::now` in a callback
+    pub fn now(&self) -> u64 {
+        self.0.borrow().now
+    }
+
+    /// Runs `event` once the clock reaches `at`, or straight away if it
+    /// already has
+    pub fn schedule_at(&self, at: u64, event: impl FnOnce(&mut Fired) + 'static) -> EventId {
+        let mut queue = self.0.borrow_mut();
+        let id = queue.next_id;
+        queue.next_id += 1;
+        queue.due.insert((at, id), Box::new(event));
+        EventId(id)
+    }
+
+    /// Runs `event` `delay` cycles from now, see the module docs for when now
+    /// is
+    pub fn schedule_in(&self, delay: u64, event: impl FnOnce(&mut Fired) + 'static) -> EventId {
+        let mut queue = self.0.borrow_mut();
+        let id = queue.next_id;
+        queue.next_id += 1;
+        queue.relative.push((delay, id, Box::new(event)));
+        EventId(id)
+    }
+
+    /// Cancels `id`, returning `false` if it's already run or been cancelled
+    pub fn cancel(&self, id: EventId) -> bool {
+        let mut queue = self.0.borrow_mut();
+        let before = queue.due.len() + queue.relative.len();
+        queue.due.retain(|&(_, event), _| event != id.0);
+        queue.relative.retain(|&(_, event, _)| event != id.0);
+        queue.due.len() + queue.relative.len() != before
+    }
+
+    /// How many events are still to run
+    pub fn pending(&self) -> usize {
+        let queue = self.0.borrow();
+        queue.due.len() + queue.relative.len()
+    }
+
+    /// Sets the clock to `now`, fixing when the events scheduled relative to
+    /// it are due, and returns when the next is
+    pub(crate) fn sync(&self, now: u64) -> Option<u64> {
+        let mut queue = self.0.borrow_mut();
+        queue.now = now;
+        for (delay, id, event) in std::mem::take(&mut queue.relative) {
+            queue.due.insert((now.saturating_add(delay), id), event);
+        }
+        queue.due.keys().next().map(|&(at, _)| at)
+    }
+
+    /// Runs the events due by `now`, returning the interrupts they raised
+    pub(crate) fn run_due(&self, now: u64) -> Vec<u8> {
+        let mut irqs = Vec::new();
+        loop {
+            self.sync(now);
+            let event = {
+                let mut queue = self.0.borrow_mut();
+                match queue.due.first_entry() {
+                    Some(entry) if entry.key().0 <= now => entry.remove(),
+                    _ => break,
+                }
+            };
+            // with the queue free, for the callback to schedule more
+            let mut fired = Fired {
+                now,
+                events: self,
+                irqs: Vec::new(),
+            };
+            event(&mut fired);
+            irqs.append(&mut fired.irqs);
+        }
+        irqs
+    }
+}
+
+/// What an event's callback can do
+pub struct Fired<'a> {
+    now: u64,
+    events: &'a Events,
+    irqs: Vec<u8>,
+}
+
+impl Fired<'_> {
+    /// The clock, when the event was due
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// The queue, to schedule more events or cancel them with
+    pub fn events(&self) -> &Events {
+        self.events
+    }
+
+    /// Runs `event` `delay` cycles after this one, or straight after it if
+    /// `delay` is 0
+    pub fn schedule_in(&self, delay: u64, event: impl FnOnce(&mut Fired) + 'static) -> EventId {
+        self.events
+            .schedule_at(self.now.saturating_add(delay), event)
+    }
+
+    /// Raises interrupt `irq`, below 32, as an `IrqSchedule` does. It's
+    /// dropped, with a warning, unless the guest has been given interrupts
+    /// with `Core32::enable_interrupts`
+    pub fn raise_irq(&mut self, irq: u8) {
+        assert!(irq < 32, "irq {irq} out of range");
+        self.irqs.push(irq);
+    }
+}
@@ -0,0 +1,300 @@
+//! Driving an interactive guest through its stdin, expect-style, for
+//! `riscy --expect`, so a program that prompts for input can be run in CI
+//! without a terminal or expect(1).
+//!
+//! A script is a list of steps, one to a line or separated by commas, with
+//! `#` starting a comment:
+//!
+//! ```text
+//! prompt '# '
+//! expect 'login:', send 'root\n'
+//! expect prompt, send 'ls /\n'
+//! expect prompt
+//! ```
+//!
+//! - `expect TEXT` waits for the guest to write TEXT to stdout, after whatever
+//!   the previous `expect` matched
+//! - `expect prompt` waits for the text last given with `prompt TEXT`
+//! - `send TEXT` makes TEXT the guest's next input
+//! - `close` ends the guest's input, so its next read gets EOF
+//!
+//! where TEXT is quoted with `'` or `"` and can have `\n`, `\r`, `\t`, `\0`,
+//! `\xHH`, `\\` and the quotes escaped in it. Once the script is done, stdin
+//! is closed.
+//!
+//! `Interaction` runs the guest with its stdin a `GuestPipe`, and takes the
+//! next steps whenever it reads the pipe empty. There's nothing to wait for:
+//! the guest only writes while it runs, so an `expect` that hasn't matched by
+//! the time it waits for input never will, and the run stops there, with
+//! `StopReason::WouldBlock`, rather than after a timeout. Only stdin is
+//! driven; a UART's input is all given up front, see `uart`.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    core::{Core32, MemReader, RunInfo, StopReason},
+    driver::GuestPipe,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Expect(Vec<u8>),
+    Send(Vec<u8>),
+    Close,
+}
+
+/// A step of a script, with the line it's on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub line: usize,
+    pub action: Action,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.action {
+            Action::Expect(text) => write!(f, "expect '{}'", escape(text)),
+            Action::Send(text) => write!(f, "send '{}'", escape(text)),
+            Action::Close => f.write_str("close"),
+        }
+    }
+}
+
+fn escape(text: &[u8]) -> String {
+    text.escape_ascii().to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Text(Vec<u8>),
+}
+
+/// The steps of a script, see the module docs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExpectScript {
+    pub steps: Vec<Step>,
+}
+
+impl ExpectScript {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {err}", path.display()))?;
+        Self::parse(&text).map_err(|err| format!("{}:{err}", path.display()))
+    }
+
+    /// Parses a script, failing with the line number and what's wrong
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut steps = Vec::new();
+        let mut prompt = None;
+
+        for (idx, line) in text.lines().enumerate() {
+            let line_no = idx + 1;
+            for statement in tokenize(line).map_err(|err| format!("{line_no}: {err}"))? {
+                let action = match statement.as_slice() {
+                    [] => continue,
+                    [Token::Word(word), Token::Text(text)] if word == "prompt" => {
+                        prompt = Some(text.clone());
+                        continue;
+                    }
+                    [Token::Word(word), Token::Text(text)] if word == "expect" => {
+                        Action::Expect(text.clone())
+                    }
+                    [Token::Word(word), Token::Word(prompt_word)]
+                        if word == "expect" && prompt_word == "prompt" =>
+                    {
+                        let prompt = prompt.clone().ok_or_else(|| {
+                            format!("{line_no}: 'expect prompt' before any 'prompt'")
+                        })?;
+                        Action::Expect(prompt)
+                    }
+                    [Token::Word(word), Token::Text(text)] if word == "send" => {
+                        Action::Send(text.clone())
+                    }
+                    [Token::Word(word)] if word == "close" => Action::Close,
+                    _ => {
+                        return Err(format!(
+                            "{line_no}: invalid step, expected expect TEXT, expect prompt, \
+                             send TEXT, prompt TEXT or close"
+                        ));
+                    }
+                };
+                steps.push(Step {
+                    line: line_no,
+                    action,
+                });
+            }
+        }
+
+        Ok(Self { steps })
+    }
+}
+
+// the statements on a line, split at commas
+fn tokenize(line: &str) -> Result<Vec<Vec<Token>>, String> {
+    let mut statements = vec![Vec::new()];
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '#' => break,
+            ',' => statements.push(Vec::new()),
+            '\'' | '"' => {
+                let mut text = Vec::new();
+                loop {
+                    match chars.next() {
+                        None => return Err("unterminated text".to_string()),
+                        Some(end) if end == c => break,
+                        Some('\\') => {
+                            let byte = match chars.next() {
+                                Some('n') => b'\n',
+                                Some('r') => b'\r',
+                                Some('t') => b'\t',
+                                Some('0') => 0,
+                                Some(c @ ('\\' | '\'' | '"')) => c as u8,
+                                Some('x') => {
+                                    let hex: String = chars.by_ref().take(2).collect();
+                                    u8::from_str_radix(&hex, 16)
+                                        .map_err(|_| format!("invalid escape '\\x{hex}'"))?
+                                }
+                                other => {
+                                    return Err(format!(
+                                        "invalid escape '\\{}'",
+                                        other.map(String::from).unwrap_or_default()
+                                    ));
+                                }
+                            };
+                            text.push(byte);
+                        }
+                        Some(c) => {
+                            let mut buf = [0; 4];
+                            text.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                        }
+                    }
+                }
+                statements.last_mut().unwrap().push(Token::Text(text));
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, ',' | '#' | '\'' | '"') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                statements.last_mut().unwrap().push(Token::Word(word));
+            }
+        }
+    }
+
+    Ok(statements)
+}
+
+/// Runs a guest through a script, see the module docs
+pub struct Interaction {
+    steps: Vec<Step>,
+    next: usize,
+    pipe: GuestPipe,
+    // how much of stdout the `expect`s so far have matched
+    matched: usize,
+    // how much of the guest's output has been passed on to the host's
+    echoed: (usize, usize),
+}
+
+impl Interaction {
+    pub fn new(script: ExpectScript) -> Self {
+        Self {
+            steps: script.steps,
+            next: 0,
+            pipe: GuestPipe::new(),
+            matched: 0,
+            echoed: (0, 0),
+        }
+    }
+
+    /// The first step not taken, if the script isn't done
+    pub fn pending(&self) -> Option<&Step> {
+        self.steps.get(self.next)
+    }
+
+    /// Runs `core` to the end, taking the steps as it waits for input, and
+    /// passing its output on to the host's as it comes. The run stops with
+    /// `StopReason::WouldBlock` if the guest waits for input while an
+    /// `expect` is `pending`; otherwise the script may still be pending if
+    /// the guest stopped before getting that far
+    pub fn run<Reader: MemReader<Idx = u32>>(&mut self, core: &mut Core32<Reader>) -> RunInfo {
+        core.set_stdin_pipe(self.pipe.clone());
+        core.capture_output();
+
+        loop {
+            let info = core.run();
+            let stdout = info
+                .output
+                .as_ref()
+                .map(|output| output.stdout.as_slice())
+                .unwrap_or_default();
+            self.echo(&info);
+            let progressed = self.advance(stdout);
+
+            if !matches!(info.reason, StopReason::WouldBlock { .. }) || !progressed {
+                return info;
+            }
+        }
+    }
+
+    // takes the steps that can be now, returning whether the guest has more
+    // to read
+    fn advance(&mut self, stdout: &[u8]) -> bool {
+        let mut progressed = false;
+        while let Some(step) = self.steps.get(self.next) {
+            match &step.action {
+                Action::Expect(text) => {
+                    let Some(at) = find(&stdout[self.matched..], text) else {
+                        return progressed;
+                    };
+                    self.matched += at + text.len();
+                }
+                Action::Send(text) => {
+                    self.pipe.write(text);
+                    progressed = true;
+                }
+                Action::Close => {
+                    self.pipe.close();
+                    progressed = true;
+                }
+            }
+            self.next += 1;
+        }
+
+        self.pipe.close();
+        true
+    }
+
+    fn echo(&mut self, info: &RunInfo) {
+        let Some(output) = &info.output else {
+            return;
+        };
+        let (stdout, stderr) = &mut self.echoed;
+        // the host's going away is no reason to stop the guest
+        let _ = io::stdout().write_all(&output.stdout[*stdout..]);
+        let _ = io::stdout().flush();
+        let _ = io::stderr().write_all(&output.stderr[*stderr..]);
+        *stdout = output.stdout.len();
+        *stderr = output.stderr.len();
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
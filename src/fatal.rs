@@ -0,0 +1,165 @@
+//! Recognising the ways a guest dies: `abort()`, failed C assertions and Rust
+//! panics, so they can be reported properly instead of as a bare exit code.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatalKind {
+    Abort,
+    /// glibc's `__assert_fail`
+    AssertFail,
+    /// newlib's `__assert_func`
+    AssertFunc,
+    RustPanic,
+    /// A function stubbed out with `fail`, see `stub`
+    Stub,
+}
+
+impl FatalKind {
+    /// The kind of fatal path entered by calling the function `name`
+    pub fn from_symbol(name: &str) -> Option<Self> {
+        match name {
+            "abort" => Some(FatalKind::Abort),
+            "__assert_fail" => Some(FatalKind::AssertFail),
+            "__assert_func" => Some(FatalKind::AssertFunc),
+            "rust_begin_unwind" => Some(FatalKind::RustPanic),
+            // `core::panicking::panic_fmt`, under both legacy and v0 mangling
+            _ if name.contains("9panicking9panic_fmt") => Some(FatalKind::RustPanic),
+            _ => None,
+        }
+    }
+
+    /// The exit code a native process dying this way would have
+    pub fn exit_code(self) -> u8 {
+        match self {
+            FatalKind::RustPanic => 101,
+            // SIGABRT
+            FatalKind::Abort | FatalKind::AssertFail | FatalKind::AssertFunc | FatalKind::Stub => {
+                134
+            }
+        }
+    }
+}
+
+impl fmt::Display for FatalKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FatalKind::Abort => write!(f, "abort"),
+            FatalKind::AssertFail | FatalKind::AssertFunc => write!(f, "assertion failure"),
+            FatalKind::RustPanic => write!(f, "panic"),
+            FatalKind::Stub => write!(f, "call to a failing stub"),
+        }
+    }
+}
+
+/// A guest that died through one of the recognised fatal paths
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuestFatal {
+    pub kind: FatalKind,
+    /// The fatal function, or the `exit` ecall
+    pub pc: u32,
+    /// Return address of the call into the fatal function, if there was one
+    pub caller: Option<u32>,
+    pub message: Option<String>,
+}
+
+impl GuestFatal {
+    /// Pulls what can be found about the failure out of the arguments of the
+    /// call to the fatal function
+    pub fn from_call(kind: FatalKind, pc: u32, caller: u32, args: [u32; 4], memory: &[u8]) -> Self {
+        let message = match kind {
+            FatalKind::Abort | FatalKind::Stub => None,
+            // __assert_fail(assertion, file, line, function)
+            FatalKind::AssertFail => {
+                let [assertion, file, line, function] = args;
+                assert_message(memory, assertion, file, line, function)
+            }
+            // __assert_func(file, line, function, assertion)
+            FatalKind::AssertFunc => {
+                let [file, line, function, assertion] = args;
+                assert_message(memory, assertion, file, line, function)
+            }
+            // panic_fmt(&Arguments, &Location); the message itself needs the
+            // guest's formatting machinery, but the location is easy to find
+            FatalKind::RustPanic => {
+                panic_location(memory, args[1]).map(|loc| format!("panicked at {loc}"))
+            }
+        };
+
+        Self {
+            kind,
+            pc,
+            caller: Some(caller),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for GuestFatal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "guest {} at pc {:#x}", self.kind, self.pc)?;
+        if let Some(caller) = self.caller {
+            write!(f, " (called from {caller:#x})")?;
+        }
+        if let Some(message) = &self.message {
+            write!(f, ": {message}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Recognises the messages fatal paths print to stderr before exiting
+pub fn scan_stderr(text: &[u8]) -> Option<(FatalKind, String)> {
+    let text = String::from_utf8_lossy(text);
+    let kind = if text.contains("panicked at") {
+        FatalKind::RustPanic
+    } else if text.contains("Assertion") || text.contains("assertion") {
+        FatalKind::AssertFail
+    } else {
+        return None;
+    };
+
+    Some((kind, text.trim_end().to_string()))
+}
+
+fn assert_message(
+    memory: &[u8],
+    assertion: u32,
+    file: u32,
+    line: u32,
+    function: u32,
+) -> Option<String> {
+    let assertion = read_c_str(memory, assertion)?;
+    let file = read_c_str(memory, file).unwrap_or_else(|| "?".to_string());
+    let function = read_c_str(memory, function).unwrap_or_else(|| "?".to_string());
+
+    Some(format!(
+        "{file}:{line}: {function}: Assertion `{assertion}' failed."
+    ))
+}
+
+/// Reads a `core::panic::Location`, which in practice is laid out as
+/// `{ file: &str, line: u32, col: u32 }`
+fn panic_location(memory: &[u8], addr: u32) -> Option<String> {
+    let word = |offset: u32| -> Option<u32> {
+        let start = addr.checked_add(offset)? as usize;
+        let bytes = memory.get(start..start + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+
+    let (ptr, len, line, col) = (word(0)?, word(4)?, word(8)?, word(12)?);
+    let file = memory.get(ptr as usize..(ptr as usize).checked_add(len as usize)?)?;
+    let file = std::str::from_utf8(file).ok()?;
+
+    Some(format!("{file}:{line}:{col}"))
+}
+
+fn read_c_str(memory: &[u8], addr: u32) -> Option<String> {
+    const MAX_LEN: usize = 4096;
+
+    let rest = memory.get(addr as usize..)?;
+    let rest = &rest[..rest.len().min(MAX_LEN)];
+    let len = rest.iter().position(|&b| b == 0)?;
+
+    Some(String::from_utf8_lossy(&rest[..len]).into_owned())
+}
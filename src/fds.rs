@@ -0,0 +1,495 @@
+//! The guest's file descriptors, and the host files behind them.
+//!
+//! The guest only gets the host files it's given: stdin, stdout and stderr,
+//! and whatever the embedder pre-opens with `Core32::preopen` (`riscy
+//! --preopen 5=data.bin`) under the fd number it chooses. Any other fd is
+//! `EBADF`, however many the host process has open.
+//!
+//! As in WASI, a pre-opened directory is a capability: `openat` relative to it
+//! can open what's beneath it, but nothing outside it, whether by `..`, an
+//! absolute path or a symlink. Directories pre-opened read-only only give
+//! read-only access to their contents. `newfstatat`, `mkdirat`, `renameat` and
+//! `getdents64` only see beneath it the same way.
+//!
+//! There's no current directory for `AT_FDCWD` to mean, and no `/` for an
+//! absolute path, unless one pre-opened directory is made the guest's root
+//! with `Core32::set_root` (`riscy --root FD`). Then it's both, as if the guest
+//! were chrooted into it: `..` and absolute symlinks stop at it, and `chdir`
+//! and `getcwd` move around and report paths beneath it.
+
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    fs::{File, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+pub(crate) const EBADF: i32 = 9;
+pub(crate) const EACCES: i32 = 13;
+const ENOENT: i32 = 2;
+pub(crate) const ENOTDIR: i32 = 20;
+const EINVAL: i32 = 22;
+pub(crate) const ERANGE: i32 = 34;
+pub(crate) const EROFS: i32 = 30;
+
+// the guest's `openat` flags, the same on the host as both use the generic
+// Linux numbering
+const O_ACCMODE: i32 = 0o3;
+const O_RDONLY: i32 = 0o0;
+const GUEST_FLAGS: i32 = O_ACCMODE
+    | libc::O_CREAT
+    | libc::O_EXCL
+    | libc::O_TRUNC
+    | libc::O_APPEND
+    | libc::O_DIRECTORY
+    | libc::O_NOFOLLOW;
+const WRITE_FLAGS: i32 = libc::O_CREAT | libc::O_TRUNC | libc::O_APPEND;
+
+/// A `--preopen` argument: `FD=PATH`, a file or directory to hand the guest as
+/// `FD`
+#[derive(Debug, Clone)]
+pub struct Preopen {
+    pub fd: i32,
+    pub path: PathBuf,
+}
+
+impl FromStr for Preopen {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (fd, path) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid preopen '{s}', expected FD=PATH"))?;
+        let fd = fd
+            .parse()
+            .ok()
+            .filter(|&fd| fd >= 0)
+            .ok_or_else(|| format!("invalid fd '{fd}'"))?;
+
+        Ok(Preopen {
+            fd,
+            path: path.into(),
+        })
+    }
+}
+
+#[derive(Debug)]
+enum Host {
+    /// One of the host's stdin, stdout and stderr, which is never closed
+    Stdio(RawFd),
+    Owned(OwnedFd),
+}
+
+#[derive(Debug)]
+struct GuestFd {
+    host: Host,
+    dir: bool,
+    writable: bool,
+}
+
+impl GuestFd {
+    fn raw(&self) -> RawFd {
+        match &self.host {
+            Host::Stdio(fd) => *fd,
+            Host::Owned(fd) => fd.as_raw_fd(),
+        }
+    }
+}
+
+// the pre-opened directory the guest sees as `/`, see `FdTable::set_root`
+#[derive(Debug)]
+struct Root {
+    dir: OwnedFd,
+    writable: bool,
+    // the current directory, as an absolute path beneath it
+    cwd: Vec<u8>,
+}
+
+// where a path the guest gives is looked up from, and how
+struct Base {
+    dir: RawFd,
+    path: Vec<u8>,
+    resolve: u64,
+    writable: bool,
+}
+
+#[derive(Debug)]
+pub(crate) struct FdTable {
+    fds: BTreeMap<i32, GuestFd>,
+    root: Option<Root>,
+}
+
+impl FdTable {
+    /// Just stdin, stdout and stderr
+    pub(crate) fn new() -> Self {
+        let fds = (0..3)
+            .map(|fd| {
+                let stdio = GuestFd {
+                    host: Host::Stdio(fd),
+                    dir: false,
+                    writable: fd != 0,
+                };
+                (fd, stdio)
+            })
+            .collect();
+        Self { fds, root: None }
+    }
+
+    /// How many fds the guest has open, stdin, stdout and stderr included
+    pub(crate) fn len(&self) -> usize {
+        self.fds.len()
+    }
+
+    pub(crate) fn is_open(&self, fd: i32) -> bool {
+        self.fds.contains_key(&fd)
+    }
+
+    /// The host fd behind guest fd `fd`, if it's a file
+    pub(crate) fn host(&self, fd: i32) -> Option<RawFd> {
+        self.fds
+            .get(&fd)
+            .filter(|guest| !guest.dir)
+            .map(GuestFd::raw)
+    }
+
+    /// Opens `path` on the host as guest fd `fd`, replacing whatever was
+    /// there. Files that don't exist are created if `writable`
+    pub(crate) fn preopen(&mut self, fd: i32, path: &Path, writable: bool) -> io::Result<()> {
+        let dir = path.is_dir();
+        let file = if dir {
+            File::open(path)?
+        } else {
+            OpenOptions::new()
+                .read(true)
+                .write(writable)
+                .create(writable)
+                .truncate(false)
+                .open(path)?
+        };
+
+        self.fds.insert(
+            fd,
+            GuestFd {
+                host: Host::Owned(file.into()),
+                dir,
+                writable,
+            },
+        );
+        Ok(())
+    }
+
+    /// Makes the pre-opened directory `fd` the guest's `/` and its current
+    /// directory, see the module docs. It stays so if the guest closes `fd`
+    pub(crate) fn set_root(&mut self, fd: i32) -> io::Result<()> {
+        let guest = self
+            .fds
+            .get(&fd)
+            .filter(|guest| guest.dir)
+            .ok_or_else(|| io::Error::other(format!("fd {fd} isn't a pre-opened directory")))?;
+        let dir = match &guest.host {
+            Host::Owned(dir) => dir.try_clone()?,
+            Host::Stdio(_) => unreachable!("stdio is never a directory"),
+        };
+
+        self.root = Some(Root {
+            dir,
+            writable: guest.writable,
+            cwd: b"/".to_vec(),
+        });
+        Ok(())
+    }
+
+    // where `path` is looked up from, relative to `dirfd` as for the `*at`
+    // syscalls
+    fn base(&self, dirfd: i32, path: &[u8]) -> Result<Base, i32> {
+        let absolute = path.starts_with(b"/");
+        if !absolute && dirfd != libc::AT_FDCWD {
+            let dir = match self.fds.get(&dirfd) {
+                Some(dir) if dir.dir => dir,
+                Some(_) => return Err(ENOTDIR),
+                None => return Err(EBADF),
+            };
+            return Ok(Base {
+                dir: dir.raw(),
+                path: path.to_vec(),
+                resolve: libc::RESOLVE_BENEATH,
+                writable: dir.writable,
+            });
+        }
+
+        let root = self.root.as_ref().ok_or(EACCES)?;
+        let path = if absolute {
+            path.to_vec()
+        } else {
+            [&root.cwd, &b"/"[..], path].concat()
+        };
+        Ok(Base {
+            dir: root.dir.as_raw_fd(),
+            path,
+            resolve: libc::RESOLVE_IN_ROOT,
+            writable: root.writable,
+        })
+    }
+
+    /// The guest's `openat`, returning the new fd or an errno
+    pub(crate) fn openat(
+        &mut self,
+        dirfd: i32,
+        path: &[u8],
+        flags: i32,
+        mode: u32,
+    ) -> Result<i32, i32> {
+        let base = self.base(dirfd, path)?;
+
+        let flags = flags & GUEST_FLAGS;
+        let writes = flags & O_ACCMODE != O_RDONLY || flags & WRITE_FLAGS != 0;
+        if writes && !base.writable {
+            return Err(EROFS);
+        }
+
+        let mode = if flags & libc::O_CREAT != 0 {
+            mode as u64 & 0o777
+        } else {
+            0
+        };
+        let file = open_beneath(&base, flags, mode)?;
+        let guest = GuestFd {
+            dir: file.metadata().is_ok_and(|meta| meta.is_dir()),
+            host: Host::Owned(file.into()),
+            writable: base.writable,
+        };
+
+        let fd = (0..).find(|fd| !self.fds.contains_key(fd)).unwrap();
+        self.fds.insert(fd, guest);
+        Ok(fd)
+    }
+
+    /// Opens a read-only file holding `contents`, as a file the emulator makes
+    /// up rather than one on the host, returning the new fd or an errno
+    pub(crate) fn open_synthetic(&mut self, contents: &[u8]) -> Result<i32, i32> {
+        let fd = unsafe {
+            libc::memfd_create(
+                c"riscy".as_ptr(),
+                libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+            )
+        };
+        if fd < 0 {
+            return Err(errno(&io::Error::last_os_error()));
+        }
+        let file = unsafe { File::from_raw_fd(fd) };
+        (&file)
+            .write_all(contents)
+            .and_then(|()| (&file).seek(SeekFrom::Start(0)).map(drop))
+            .map_err(|err| errno(&err))?;
+        // so the guest's writes fail, though the memfd is open for them
+        let seals =
+            libc::F_SEAL_WRITE | libc::F_SEAL_GROW | libc::F_SEAL_SHRINK | libc::F_SEAL_SEAL;
+        if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) } < 0 {
+            return Err(errno(&io::Error::last_os_error()));
+        }
+
+        let guest = GuestFd {
+            host: Host::Owned(file.into()),
+            dir: false,
+            writable: false,
+        };
+        let fd = (0..).find(|fd| !self.fds.contains_key(fd)).unwrap();
+        self.fds.insert(fd, guest);
+        Ok(fd)
+    }
+
+    /// The guest's `fstat`, of any fd, directories included
+    pub(crate) fn fstat(&self, fd: i32) -> Result<libc::stat, i32> {
+        let guest = self.fds.get(&fd).ok_or(EBADF)?;
+        stat(guest.raw())
+    }
+
+    /// The guest's `newfstatat`, of what's beneath `dirfd` as for `openat`
+    pub(crate) fn fstatat(&self, dirfd: i32, path: &[u8], flags: i32) -> Result<libc::stat, i32> {
+        if path.is_empty() && flags & libc::AT_EMPTY_PATH != 0 {
+            return self.fstat(dirfd);
+        }
+
+        let base = self.base(dirfd, path)?;
+        let nofollow = if flags & libc::AT_SYMLINK_NOFOLLOW != 0 {
+            libc::O_NOFOLLOW
+        } else {
+            0
+        };
+        let file = open_beneath(&base, libc::O_PATH | nofollow, 0)?;
+        stat(file.as_raw_fd())
+    }
+
+    /// The guest's `getcwd`: the current directory beneath its root
+    pub(crate) fn getcwd(&self) -> Result<&[u8], i32> {
+        self.root.as_ref().map(|root| &root.cwd[..]).ok_or(ENOENT)
+    }
+
+    /// The guest's `chdir`, to a directory beneath its root
+    pub(crate) fn chdir(&mut self, path: &[u8]) -> Result<(), i32> {
+        let base = self.base(libc::AT_FDCWD, path)?;
+        let dir = open_beneath(&base, libc::O_PATH | libc::O_DIRECTORY, 0)?;
+
+        // where it really is, past any `..` and symlinks
+        let root = self.root.as_mut().unwrap();
+        let path = |fd: RawFd| std::fs::read_link(format!("/proc/self/fd/{fd}"));
+        let (Ok(root_path), Ok(dir_path)) = (path(root.dir.as_raw_fd()), path(dir.as_raw_fd()))
+        else {
+            return Err(ENOENT);
+        };
+        let beneath = dir_path.strip_prefix(&root_path).map_err(|_| EACCES)?;
+
+        root.cwd = Path::new("/")
+            .join(beneath)
+            .into_os_string()
+            .into_encoded_bytes();
+        Ok(())
+    }
+
+    /// The guest's `mkdirat`
+    pub(crate) fn mkdirat(&self, dirfd: i32, path: &[u8], mode: u32) -> Result<(), i32> {
+        let (parent, name) = self.parent(dirfd, path)?;
+        let ret = unsafe { libc::mkdirat(parent.as_raw_fd(), name.as_ptr(), mode & 0o777) };
+        if ret < 0 {
+            return Err(errno(&io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    /// The guest's `renameat2`, and so `renameat` with no `flags`
+    pub(crate) fn renameat(
+        &self,
+        olddirfd: i32,
+        old: &[u8],
+        newdirfd: i32,
+        new: &[u8],
+        flags: u32,
+    ) -> Result<(), i32> {
+        let (old_parent, old_name) = self.parent(olddirfd, old)?;
+        let (new_parent, new_name) = self.parent(newdirfd, new)?;
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_renameat2,
+                old_parent.as_raw_fd(),
+                old_name.as_ptr(),
+                new_parent.as_raw_fd(),
+                new_name.as_ptr(),
+                flags,
+            )
+        };
+        if ret < 0 {
+            return Err(errno(&io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+
+    // the directory `path` is in, opened beneath where it's looked up from,
+    // and its last component, for syscalls that change that directory
+    fn parent(&self, dirfd: i32, path: &[u8]) -> Result<(File, CString), i32> {
+        let mut base = self.base(dirfd, path)?;
+        if !base.writable {
+            return Err(EROFS);
+        }
+
+        let trimmed = match base.path.iter().rposition(|&b| b != b'/') {
+            Some(end) => &base.path[..=end],
+            None => return Err(EINVAL),
+        };
+        let (parent, name) = match trimmed.iter().rposition(|&b| b == b'/') {
+            Some(0) => (&b"/"[..], &trimmed[1..]),
+            Some(slash) => (&trimmed[..slash], &trimmed[slash + 1..]),
+            None => (&b"."[..], trimmed),
+        };
+        // which would be the parent itself, or above it
+        if name == b"." || name == b".." {
+            return Err(EINVAL);
+        }
+
+        let name = CString::new(name).map_err(|_| EINVAL)?;
+        base.path = parent.to_vec();
+        let parent = open_beneath(&base, libc::O_PATH | libc::O_DIRECTORY, 0)?;
+        Ok((parent, name))
+    }
+
+    /// The guest's `getdents64` on directory `fd`, returning the bytes filled
+    pub(crate) fn getdents(&self, fd: i32, buf: &mut [u8]) -> Result<usize, i32> {
+        let dir = match self.fds.get(&fd) {
+            Some(dir) if dir.dir => dir,
+            Some(_) => return Err(ENOTDIR),
+            None => return Err(EBADF),
+        };
+        // `struct linux_dirent64` is the same on every architecture, so the
+        // host's can go straight to the guest
+        let ret =
+            unsafe { libc::syscall(libc::SYS_getdents64, dir.raw(), buf.as_mut_ptr(), buf.len()) };
+        if ret < 0 {
+            return Err(errno(&io::Error::last_os_error()));
+        }
+        Ok(ret as usize)
+    }
+
+    /// The guest's `close`; the host's stdio stays open
+    pub(crate) fn close(&mut self, fd: i32) -> Result<(), i32> {
+        self.fds.remove(&fd).map(drop).ok_or(EBADF)
+    }
+}
+
+// opens the path in `base` beneath its directory, and nowhere else
+fn open_beneath(base: &Base, flags: i32, mode: u64) -> Result<File, i32> {
+    let path = CString::new(&base.path[..]).map_err(|_| EACCES)?;
+    // non-exhaustive, as the kernel may grow it
+    let mut how: libc::open_how = unsafe { mem::zeroed() };
+    how.flags = (flags | libc::O_CLOEXEC) as u64;
+    how.mode = mode;
+    how.resolve = base.resolve | libc::RESOLVE_NO_MAGICLINKS;
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            base.dir,
+            path.as_ptr(),
+            &how as *const libc::open_how,
+            mem::size_of::<libc::open_how>(),
+        )
+    };
+    if ret < 0 {
+        return Err(errno(&io::Error::last_os_error()));
+    }
+    Ok(unsafe { File::from_raw_fd(ret as RawFd) })
+}
+
+fn stat(fd: RawFd) -> Result<libc::stat, i32> {
+    let mut st: libc::stat = unsafe { mem::zeroed() };
+    if unsafe { libc::fstat(fd, &mut st) } < 0 {
+        return Err(errno(&io::Error::last_os_error()));
+    }
+    Ok(st)
+}
+
+/// Reads from host fd `fd`, returning the count or a negative errno, as the
+/// guest's `read` does
+pub(crate) fn read(fd: RawFd, buf: &mut [u8]) -> i32 {
+    let ret = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+    result(ret)
+}
+
+/// Writes to host fd `fd`, returning the count or a negative errno
+pub(crate) fn write(fd: RawFd, buf: &[u8]) -> i32 {
+    let ret = unsafe { libc::write(fd, buf.as_ptr().cast(), buf.len()) };
+    result(ret)
+}
+
+fn result(ret: isize) -> i32 {
+    if ret < 0 {
+        -errno(&io::Error::last_os_error())
+    } else {
+        ret as i32
+    }
+}
+
+fn errno(err: &io::Error) -> i32 {
+    err.raw_os_error().unwrap_or(libc::EIO)
+}
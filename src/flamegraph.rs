@@ -0,0 +1,142 @@
+//! Flame graphs of sampled call stacks, as standalone SVG.
+//!
+//! `riscy --flamegraph out.svg` samples the guest with a `sample::CallStack`
+//! and draws the folded stacks the way `flamegraph.pl` does: callers below
+//! callees, each frame as wide as the share of samples it was on the stack
+//! for, and siblings in alphabetical order, so the x axis isn't time. Hovering
+//! over a frame shows its name and sample count.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use crate::checkpoint;
+
+const WIDTH: f64 = 1200.0;
+const FRAME_HEIGHT: f64 = 16.0;
+const PAD: f64 = 10.0;
+// room for the title above the frames
+const TITLE_HEIGHT: f64 = 40.0;
+// a guess at the width of a character of the 12px font, to cut names to fit
+const CHAR_WIDTH: f64 = 7.0;
+// frames narrower than this are left out, as they couldn't be seen anyway
+const MIN_WIDTH: f64 = 0.1;
+
+#[derive(Default)]
+struct Node {
+    samples: u64,
+    children: BTreeMap<String, Node>,
+}
+
+impl Node {
+    fn depth(&self) -> usize {
+        1 + self.children.values().map(Node::depth).max().unwrap_or(0)
+    }
+}
+
+/// Draws `folded` stacks, as from `Samples::folded`, under `title`
+pub fn write_svg(
+    folded: &BTreeMap<String, u64>,
+    title: &str,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let mut root = Node::default();
+    for (stack, &count) in folded {
+        root.samples += count;
+        let mut node = &mut root;
+        for frame in stack.split(';') {
+            node = node.children.entry(frame.to_string()).or_default();
+            node.samples += count;
+        }
+    }
+
+    let depth = root.depth();
+    let height = TITLE_HEIGHT + depth as f64 * FRAME_HEIGHT + PAD;
+    writeln!(
+        out,
+        r##"<?xml version="1.0" standalone="no"?>
+<svg version="1.1" width="{WIDTH}" height="{height}" viewBox="0 0 {WIDTH} {height}" xmlns="http://www.w3.org/2000/svg">
+<rect x="0" y="0" width="100%" height="100%" fill="#f8f8f8"/>
+<text x="{}" y="24" font-size="17" font-family="Verdana" text-anchor="middle">{}</text>
+<g font-size="12" font-family="Verdana">"##,
+        WIDTH / 2.0,
+        escape(title)
+    )?;
+
+    let mut drawer = Drawer {
+        out,
+        total: root.samples.max(1),
+        scale: (WIDTH - 2.0 * PAD) / root.samples.max(1) as f64,
+        bottom: height - PAD,
+    };
+    drawer.frame("all", &root, PAD, 0)?;
+
+    writeln!(drawer.out, "</g>\n</svg>")
+}
+
+struct Drawer<'a, W: Write> {
+    out: &'a mut W,
+    total: u64,
+    // pixels per sample
+    scale: f64,
+    // the y of the bottom of the outermost frames
+    bottom: f64,
+}
+
+impl<W: Write> Drawer<'_, W> {
+    fn frame(&mut self, name: &str, node: &Node, x: f64, depth: usize) -> io::Result<()> {
+        let width = node.samples as f64 * self.scale;
+        if width < MIN_WIDTH {
+            return Ok(());
+        }
+
+        let y = self.bottom - (depth + 1) as f64 * FRAME_HEIGHT;
+        let percent = node.samples as f64 / self.total as f64 * 100.0;
+        let fits = (width / CHAR_WIDTH) as usize;
+        let label = match name.chars().count() {
+            len if len <= fits => name.to_string(),
+            _ if fits < 3 => String::new(),
+            _ => name.chars().take(fits - 2).chain("..".chars()).collect(),
+        };
+        writeln!(
+            self.out,
+            r#"<g><title>{} ({} samples, {percent:.2}%)</title><rect x="{x:.1}" y="{y:.1}" width="{:.1}" height="{}" fill="{}" rx="2"/><text x="{:.1}" y="{:.1}">{}</text></g>"#,
+            escape(name),
+            node.samples,
+            width,
+            FRAME_HEIGHT - 1.0,
+            color(name),
+            x + 3.0,
+            y + FRAME_HEIGHT - 4.0,
+            escape(&label)
+        )?;
+
+        let mut child_x = x;
+        for (child_name, child) in &node.children {
+            self.frame(child_name, child, child_x, depth + 1)?;
+            child_x += child.samples as f64 * self.scale;
+        }
+        Ok(())
+    }
+}
+
+/// flamegraph.pl's warm palette, picked by a hash of the name so a function
+/// is the same color wherever it appears
+fn color(name: &str) -> String {
+    let hash = checkpoint::hash(name.as_bytes());
+    let v1 = (hash & 0xff) as f64 / 255.0;
+    let v2 = ((hash >> 8) & 0xff) as f64 / 255.0;
+    let v3 = ((hash >> 16) & 0xff) as f64 / 255.0;
+    let r = 205.0 + 50.0 * v3;
+    let g = 230.0 * v1;
+    let b = 55.0 * v2;
+    format!("rgb({},{},{})", r as u8, g as u8, b as u8)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
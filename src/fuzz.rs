@@ -0,0 +1,358 @@
+//! Fuzzing a program through its arguments and stdin, for `riscy fuzz`.
+//!
+//! A `FuzzInput` is what the fuzzer controls: the arguments the guest gets
+//! after its name, and its stdin. `Harness` runs the program over one, each
+//! run starting from a `Snapshot` taken before the first instruction, as
+//! `tmin` does, with its clocks and random bytes deterministic and its
+//! instructions capped, so a hang is just a run that doesn't crash.
+//!
+//! A crash is a run that stops on anything but an exit or the cap, like a load
+//! past the end of memory, a jump outside the code or a guest fatal error. It's
+//! bucketed by `BucketKey`: how it crashed, as in `CrashSignature`, and a hash
+//! of the call stack it crashed in, see `Core32::stack_trace`, so a bug
+//! reached from different callers is kept as many. `Buckets` keeps the first
+//! input into each.
+//!
+//! For fuzzers working on bytes, like libFuzzer, `FuzzInput::from_bytes` and
+//! `to_bytes` map an input to and from bytes: a count of arguments, each then
+//! NUL-terminated, and the rest stdin. `riscy fuzz` drives a harness with
+//! `Mutator`, which has no coverage to go on, so it mutates the seeds it's
+//! given and the inputs that found a new bucket, and keeps each bucket's
+//! first input in the crashes directory in that form.
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::{
+    checkpoint::{self, Snapshot},
+    core::{Core32, Fault, MemReader},
+    load::{self, LoadedElf, Symbol},
+    nondet::splitmix64,
+    tmin::CrashSignature,
+};
+
+/// The most arguments an input has, so its count fits in a byte
+pub const MAX_ARGS: usize = 32;
+// how long `Mutator` lets an argument or stdin grow
+const MAX_ARG_LEN: usize = 4096;
+const MAX_STDIN_LEN: usize = 64 << 10;
+// how many of the innermost frames the bucket's stack hash is of
+const STACK_FRAMES: usize = 8;
+
+/// What the fuzzer controls, see the module docs
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FuzzInput {
+    /// The guest's arguments after its name, `argv[1..]`
+    pub args: Vec<Vec<u8>>,
+    pub stdin: Vec<u8>,
+}
+
+impl FuzzInput {
+    /// The input `data` encodes, see the module docs. Every `data` is some
+    /// input: a missing NUL ends the last argument at the end, and an
+    /// argument can't have a NUL in it
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let Some((&count, mut rest)) = data.split_first() else {
+            return Self::default();
+        };
+
+        let mut args = Vec::new();
+        for _ in 0..(count as usize).min(MAX_ARGS) {
+            let len = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+            args.push(rest[..len].to_vec());
+            rest = rest.get(len + 1..).unwrap_or_default();
+        }
+
+        Self {
+            args,
+            stdin: rest.to_vec(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![self.args.len().min(MAX_ARGS) as u8];
+        for arg in self.args.iter().take(MAX_ARGS) {
+            data.extend(arg.iter().filter(|&&b| b != 0));
+            data.push(0);
+        }
+        data.extend_from_slice(&self.stdin);
+        data
+    }
+}
+
+impl fmt::Display for FuzzInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for arg in &self.args {
+            write!(f, "{:?} ", String::from_utf8_lossy(arg))?;
+        }
+        write!(f, "< {} bytes", self.stdin.len())
+    }
+}
+
+/// Which bug a crash is, as far as can be told, see the module docs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BucketKey {
+    pub reason: &'static str,
+    pub pc: Option<u32>,
+    pub stack_hash: u64,
+}
+
+impl fmt::Display for BucketKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)?;
+        if let Some(pc) = self.pc {
+            write!(f, "-{pc:x}")?;
+        }
+        write!(f, "-{:016x}", self.stack_hash)
+    }
+}
+
+/// A run that crashed
+#[derive(Debug, Clone)]
+pub struct Crash {
+    pub signature: CrashSignature,
+    /// The stack it crashed in, innermost first
+    pub stack: Vec<u32>,
+    /// The instruction it crashed on, if it was one
+    pub fault: Option<Fault>,
+}
+
+impl Crash {
+    pub fn bucket(&self) -> BucketKey {
+        let frames: Vec<u8> = self
+            .stack
+            .iter()
+            .take(STACK_FRAMES)
+            .flat_map(|pc| pc.to_le_bytes())
+            .collect();
+        BucketKey {
+            reason: self.signature.reason,
+            pc: self.signature.pc,
+            stack_hash: checkpoint::hash(&frames),
+        }
+    }
+}
+
+pub struct Harness<Reader: MemReader<Idx = u32>> {
+    core: Core32<Reader>,
+    start: Snapshot,
+    program: Vec<u8>,
+    limit: u64,
+    runs: u64,
+}
+
+impl<Reader: MemReader<Idx = u32>> Harness<Reader> {
+    /// Fuzzes `elf`, which gets `program` as its name, `argv[0]`, with runs of
+    /// at most `limit` instructions
+    pub fn new(elf: LoadedElf, program: &str, size: usize, limit: u64) -> Self {
+        let mut core = Core32::<Reader>::new(elf, None, size, false);
+        core.set_deterministic(0);
+        core.capture_output();
+        core.set_stdin(Vec::new());
+
+        let mut start = core.snapshot();
+        // the guest's output is captured, so there's nothing of the host's to
+        // put back
+        start.fd_offsets.clear();
+
+        Self {
+            core,
+            start,
+            program: program.as_bytes().to_vec(),
+            limit,
+            runs: 0,
+        }
+    }
+
+    /// How many times the program has been run
+    pub fn runs(&self) -> u64 {
+        self.runs
+    }
+
+    pub fn core(&self) -> &Core32<Reader> {
+        &self.core
+    }
+
+    /// Runs the program over `input`, returning how it crashed if it did.
+    /// Fails if the arguments don't fit in guest memory
+    pub fn run(&mut self, input: &FuzzInput) -> Result<Option<Crash>, String> {
+        self.runs += 1;
+        self.core.set_stdin(input.stdin.clone());
+        self.core.capture_output();
+        self.core
+            .restore(&self.start)
+            .expect("restoring the core's own snapshot");
+        let argv: Vec<&[u8]> = std::iter::once(self.program.as_slice())
+            .chain(input.args.iter().map(Vec::as_slice))
+            .collect();
+        self.core.set_args(&argv)?;
+        self.core
+            .set_instruction_limit(Some(self.start.instret + self.limit));
+
+        let info = self.core.run();
+        let Some(signature) = CrashSignature::of(&self.core, &info) else {
+            return Ok(None);
+        };
+        Ok(Some(Crash {
+            signature,
+            stack: self.core.stack_trace(),
+            fault: info.fault,
+        }))
+    }
+}
+
+/// A bucket's crashes
+#[derive(Debug, Clone)]
+pub struct Bucket {
+    /// The first crash into it, and its input
+    pub crash: Crash,
+    pub input: FuzzInput,
+    pub hits: u64,
+}
+
+impl Bucket {
+    /// Shows the bucket, naming the pcs by `symbols`
+    pub fn display<'a>(&'a self, symbols: &'a [Symbol]) -> impl fmt::Display + 'a {
+        DisplayBucket {
+            bucket: self,
+            symbols,
+        }
+    }
+}
+
+struct DisplayBucket<'a> {
+    bucket: &'a Bucket,
+    symbols: &'a [Symbol],
+}
+
+impl fmt::Display for DisplayBucket<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Bucket { crash, input, hits } = self.bucket;
+        writeln!(f, "{} ({hits} hits): {input}", crash.signature)?;
+        if let Some(fault) = &crash.fault {
+            writeln!(f, "  at {fault}")?;
+        }
+        for &pc in crash.stack.iter().take(STACK_FRAMES) {
+            writeln!(f, "  {}", load::describe(self.symbols, pc))?;
+        }
+        Ok(())
+    }
+}
+
+/// The crashes found so far, by bucket
+#[derive(Debug, Clone, Default)]
+pub struct Buckets(BTreeMap<BucketKey, Bucket>);
+
+impl Buckets {
+    /// Counts `crash` into its bucket, returning its key if it's the first
+    /// there
+    pub fn record(&mut self, crash: Crash, input: &FuzzInput) -> Option<BucketKey> {
+        let key = crash.bucket();
+        match self.0.get_mut(&key) {
+            Some(bucket) => {
+                bucket.hits += 1;
+                None
+            }
+            None => {
+                self.0.insert(
+                    key,
+                    Bucket {
+                        crash,
+                        input: input.clone(),
+                        hits: 1,
+                    },
+                );
+                Some(key)
+            }
+        }
+    }
+
+    pub fn get(&self, key: &BucketKey) -> Option<&Bucket> {
+        self.0.get(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&BucketKey, &Bucket)> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Makes new inputs from old ones, see the module docs
+pub struct Mutator {
+    rng: u64,
+}
+
+// arguments that tend to find option parsing bugs
+const INTERESTING_ARGS: &[&[u8]] = &[b"", b"-", b"--", b"-h", b"--help", b"-v", b"%s%n", b"-1"];
+const INTERESTING_BYTES: &[u8] = &[0, 0xff, 0x7f, 0x80, b'\n', b' ', b'-', b'%'];
+
+impl Mutator {
+    pub fn new(seed: u64) -> Self {
+        Self { rng: seed }
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (splitmix64(&mut self.rng) % n.max(1) as u64) as usize
+    }
+
+    /// `input` with one to four mutations
+    pub fn mutate(&mut self, input: &FuzzInput) -> FuzzInput {
+        let mut input = input.clone();
+        for _ in 0..1 + self.below(4) {
+            self.mutate_once(&mut input);
+        }
+        input
+    }
+
+    fn mutate_once(&mut self, input: &mut FuzzInput) {
+        match self.below(6) {
+            // an argument more, or one fewer
+            0 if input.args.len() < MAX_ARGS => {
+                let arg = INTERESTING_ARGS[self.below(INTERESTING_ARGS.len())].to_vec();
+                let at = self.below(input.args.len() + 1);
+                input.args.insert(at, arg);
+            }
+            1 if !input.args.is_empty() => {
+                let at = self.below(input.args.len());
+                input.args.remove(at);
+            }
+            // an argument's bytes, which can't be NUL
+            2 | 3 if !input.args.is_empty() => {
+                let at = self.below(input.args.len());
+                let mut arg = std::mem::take(&mut input.args[at]);
+                self.mutate_bytes(&mut arg, MAX_ARG_LEN);
+                arg.retain(|&b| b != 0);
+                input.args[at] = arg;
+            }
+            _ => self.mutate_bytes(&mut input.stdin, MAX_STDIN_LEN),
+        }
+    }
+
+    fn mutate_bytes(&mut self, bytes: &mut Vec<u8>, max_len: usize) {
+        let at = self.below(bytes.len());
+        match self.below(5) {
+            0 if !bytes.is_empty() => bytes[at] ^= 1 << self.below(8),
+            1 if !bytes.is_empty() => {
+                bytes[at] = INTERESTING_BYTES[self.below(INTERESTING_BYTES.len())]
+            }
+            2 if !bytes.is_empty() => {
+                let end = (at + 1 + self.below(16)).min(bytes.len());
+                bytes.drain(at..end);
+            }
+            // a run of the same byte, for overflows
+            3 if bytes.len() < max_len => {
+                let byte = bytes.get(at).copied().unwrap_or(b'A');
+                let len = (1 << self.below(10)).min(max_len - bytes.len());
+                bytes.splice(at..at, std::iter::repeat_n(byte, len));
+            }
+            _ if bytes.len() < max_len => bytes.insert(at, self.below(256) as u8),
+            _ => {}
+        }
+    }
+}
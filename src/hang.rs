@@ -0,0 +1,147 @@
+//! Heuristic hang detection for guests that spin forever.
+//!
+//! The core only catches `j .`; `HangDetector` gives up on the guest once it
+//! stops reaching new code, or stops storing to memory and making syscalls, for
+//! a configurable number of instructions.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::{
+    core::StopReason,
+    hooks::{Hook, HookAction},
+    instruction::Instruction,
+    load::{self, Symbol},
+};
+
+// how many recent pcs are kept to find the hot one
+const RECENT_PCS: usize = 4096;
+// deep recursion is more likely than not a hang itself, so just cap it
+const MAX_FRAMES: usize = 1024;
+
+pub struct HangDetector {
+    /// Give up after this many instructions without executing a new pc
+    pub max_without_new_pc: Option<u64>,
+    /// Give up after this many instructions without a store or syscall
+    pub max_without_progress: Option<u64>,
+
+    symbols: Vec<Symbol>,
+
+    seen: HashSet<u32>,
+    since_new_pc: u64,
+    since_progress: u64,
+    recent: VecDeque<u32>,
+    // return addresses of the calls currently in flight
+    frames: Vec<u32>,
+}
+
+impl HangDetector {
+    pub fn new(
+        max_without_new_pc: Option<u64>,
+        max_without_progress: Option<u64>,
+        symbols: Vec<Symbol>,
+    ) -> Self {
+        Self {
+            max_without_new_pc,
+            max_without_progress,
+            symbols,
+            seen: HashSet::new(),
+            since_new_pc: 0,
+            since_progress: 0,
+            recent: VecDeque::with_capacity(RECENT_PCS),
+            frames: Vec::new(),
+        }
+    }
+
+    fn hot_pc(&self) -> u32 {
+        let mut counts = HashMap::new();
+        for &pc in &self.recent {
+            *counts.entry(pc).or_insert(0u32) += 1;
+        }
+
+        counts
+            .into_iter()
+            .max_by_key(|&(pc, count)| (count, pc))
+            .map_or(0, |(pc, _)| pc)
+    }
+
+    fn describe(&self, addr: u32) -> String {
+        load::describe(&self.symbols, addr)
+    }
+
+    fn report(&self, why: &str, pc: u32) -> HookAction {
+        let hot_pc = self.hot_pc();
+
+        eprintln!("possible hang: {why}");
+        eprintln!("  hot pc: {}", self.describe(hot_pc));
+        eprintln!("  backtrace:");
+        eprintln!("    #0 {}", self.describe(pc));
+        for (depth, &ret) in self.frames.iter().rev().enumerate() {
+            // the call itself, rather than where it returns to
+            eprintln!("    #{} {}", depth + 1, self.describe(ret.wrapping_sub(4)));
+        }
+
+        HookAction::Stop(StopReason::PossibleHang { pc: hot_pc })
+    }
+}
+
+impl Hook for HangDetector {
+    fn before_instruction(&mut self, pc: u32, instr: &Instruction) -> HookAction {
+        // returned from a call, either by `ret` or by the core servicing it natively
+        while self.frames.last() == Some(&pc) {
+            self.frames.pop();
+        }
+
+        match *instr {
+            Instruction::Jal { rd: 1, .. } | Instruction::Jalr { rd: 1, .. }
+                if self.frames.len() < MAX_FRAMES =>
+            {
+                self.frames.push(pc.wrapping_add(4));
+            }
+            _ => {}
+        }
+
+        if self.recent.len() == RECENT_PCS {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(pc);
+
+        if self.seen.insert(pc) {
+            self.since_new_pc = 0;
+        } else {
+            self.since_new_pc += 1;
+        }
+
+        let progress = matches!(
+            instr,
+            Instruction::Sb { .. }
+                | Instruction::Sh { .. }
+                | Instruction::Sw { .. }
+                | Instruction::Fsw { .. }
+                | Instruction::Fsd { .. }
+                | Instruction::Ecall
+        );
+        if progress {
+            self.since_progress = 0;
+        } else {
+            self.since_progress += 1;
+        }
+
+        if let Some(max) = self.max_without_new_pc {
+            if self.since_new_pc >= max {
+                return self.report(&format!("no new pc in {max} instructions"), pc);
+            }
+        }
+
+        if let Some(max) = self.max_without_progress {
+            if self.since_progress >= max {
+                return self.report(&format!("no stores or syscalls in {max} instructions"), pc);
+            }
+        }
+
+        HookAction::Continue
+    }
+
+    fn wants_reg_writes(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,324 @@
+//! Guest heap statistics and leak reports.
+//!
+//! `HeapTracker` watches calls to the guest's own allocator, found by symbol:
+//! `malloc`, `calloc`, `realloc`, `free`, `memalign` and `aligned_alloc` as
+//! musl and newlib export them, and newlib's reentrant `_malloc_r` and friends.
+//! A call's arguments are read as it's made and its result from `a0` at the
+//! `ret` back to the caller, so a block is only known once its allocator has
+//! returned it. Calls the allocator makes to itself, like `realloc` calling
+//! `malloc`, aren't counted again.
+//!
+//! Once the run stops, `HeapTracker::report` (`riscy --heap-report`) gives the
+//! totals, the peak, and every block still live as leaks, grouped by the pc
+//! that called the allocator. A program that exits with memory it never freed
+//! on purpose shows up there too, so it's a lightweight leak checker, not a
+//! precise one.
+
+use std::{cell::RefCell, collections::BTreeMap, fmt, rc::Rc};
+
+use crate::{
+    hooks::{Hook, HookAction, Jump},
+    instruction::Instruction,
+    load::{self, Symbol},
+};
+
+// how many leaking call sites the report lists
+const REPORT_TOP: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Function {
+    Malloc,
+    Calloc,
+    Realloc,
+    Free,
+    /// `memalign` or `aligned_alloc`, alignment first
+    Memalign,
+}
+
+// each function's symbols, and whether it takes newlib's `struct _reent *`
+// before its arguments
+const FUNCTIONS: &[(&str, Function, bool)] = &[
+    ("malloc", Function::Malloc, false),
+    ("calloc", Function::Calloc, false),
+    ("realloc", Function::Realloc, false),
+    ("free", Function::Free, false),
+    ("memalign", Function::Memalign, false),
+    ("aligned_alloc", Function::Memalign, false),
+    ("_malloc_r", Function::Malloc, true),
+    ("_calloc_r", Function::Calloc, true),
+    ("_realloc_r", Function::Realloc, true),
+    ("_free_r", Function::Free, true),
+    ("_memalign_r", Function::Memalign, true),
+];
+
+#[derive(Debug, Clone, Copy)]
+struct Call {
+    function: Function,
+    args: [u32; 3],
+    // the `jal` or `jalr` that made it, and where it returns to
+    site: u32,
+    ret: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    size: u32,
+    site: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Heap {
+    // the allocator call in flight, if any
+    call: Option<Call>,
+    // by address
+    live: BTreeMap<u32, Block>,
+    live_bytes: u64,
+    peak_bytes: u64,
+    peak_blocks: usize,
+    allocations: u64,
+    allocated_bytes: u64,
+    frees: u64,
+    // allocations that returned null
+    failed: u64,
+    // frees of a pointer that isn't live, with the first one's site
+    bad_frees: u64,
+    first_bad_free: Option<(u32, u32)>,
+}
+
+impl Heap {
+    fn allocated(&mut self, addr: u32, size: u32, site: u32) {
+        if addr == 0 {
+            self.failed += 1;
+            return;
+        }
+
+        self.allocations += 1;
+        self.allocated_bytes += size as u64;
+        // a block the allocator handed out again without it being freed, so
+        // one of its frees was missed
+        if let Some(old) = self.live.insert(addr, Block { size, site }) {
+            self.live_bytes -= old.size as u64;
+        }
+        self.live_bytes += size as u64;
+        if self.live_bytes > self.peak_bytes {
+            self.peak_bytes = self.live_bytes;
+            self.peak_blocks = self.live.len();
+        }
+    }
+
+    fn freed(&mut self, addr: u32, site: u32) {
+        if addr == 0 {
+            return;
+        }
+
+        match self.live.remove(&addr) {
+            Some(block) => {
+                self.frees += 1;
+                self.live_bytes -= block.size as u64;
+            }
+            None => {
+                self.bad_frees += 1;
+                self.first_bad_free.get_or_insert((site, addr));
+            }
+        }
+    }
+
+    fn returned(&mut self, call: Call, result: u32) {
+        match call.function {
+            Function::Malloc => self.allocated(result, call.args[0], call.site),
+            Function::Calloc => {
+                let size = call.args[0].saturating_mul(call.args[1]);
+                self.allocated(result, size, call.site)
+            }
+            Function::Memalign => self.allocated(result, call.args[1], call.site),
+            Function::Realloc => {
+                let [ptr, size, _] = call.args;
+                // `realloc(p, 0)` may free `p` and return null, which isn't a
+                // failure
+                if result != 0 || size == 0 {
+                    self.freed(ptr, call.site);
+                }
+                if result != 0 || size != 0 {
+                    self.allocated(result, size, call.site);
+                }
+            }
+            Function::Free => self.freed(call.args[0], call.site),
+        }
+    }
+}
+
+/// Tracks the guest's heap through its allocator. Clones share their state,
+/// so one can be kept to report on after the other is given to
+/// `Core32::add_hook`
+#[derive(Debug, Clone)]
+pub struct HeapTracker {
+    // allocator functions by address
+    functions: Rc<[(u32, Function, bool)]>,
+    heap: Rc<RefCell<Heap>>,
+}
+
+impl HeapTracker {
+    /// Watches the allocator functions in `symbols`, failing if there are none
+    pub fn new(symbols: &[Symbol]) -> Result<Self, String> {
+        let functions: Vec<_> = symbols
+            .iter()
+            .filter_map(|sym| {
+                let &(_, function, reentrant) =
+                    FUNCTIONS.iter().find(|(name, ..)| *name == sym.name)?;
+                Some((sym.addr as u32, function, reentrant))
+            })
+            .collect();
+        if functions.is_empty() {
+            return Err("no malloc or free symbols to watch the heap through".to_owned());
+        }
+
+        Ok(Self {
+            functions: functions.into(),
+            heap: Rc::default(),
+        })
+    }
+
+    pub fn report(&self, symbols: &[Symbol]) -> HeapReport {
+        let heap = self.heap.borrow();
+
+        let mut by_site: BTreeMap<u32, (u64, usize)> = BTreeMap::new();
+        for block in heap.live.values() {
+            let (bytes, blocks) = by_site.entry(block.site).or_default();
+            *bytes += block.size as u64;
+            *blocks += 1;
+        }
+        let mut leaks: Vec<_> = by_site.into_iter().collect();
+        leaks.sort_by_key(|&(site, (bytes, _))| (std::cmp::Reverse(bytes), site));
+
+        HeapReport {
+            allocations: heap.allocations,
+            allocated_bytes: heap.allocated_bytes,
+            frees: heap.frees,
+            failed: heap.failed,
+            peak_bytes: heap.peak_bytes,
+            peak_blocks: heap.peak_blocks,
+            leaked_bytes: heap.live_bytes,
+            leaked_blocks: heap.live.len(),
+            leaks: leaks
+                .into_iter()
+                .map(|(site, (bytes, blocks))| (load::describe(symbols, site), bytes, blocks))
+                .collect(),
+            bad_frees: heap.bad_frees,
+            first_bad_free: heap
+                .first_bad_free
+                .map(|(site, addr)| (load::describe(symbols, site), addr)),
+        }
+    }
+}
+
+impl Hook for HeapTracker {
+    fn before_instruction(&mut self, pc: u32, _instr: &Instruction) -> HookAction {
+        // returned without a `ret`, from a call the core serviced natively or
+        // stubbed out, so there's no result to see
+        let heap = &mut *self.heap.borrow_mut();
+        if heap.call.is_some_and(|call| call.ret == pc) {
+            heap.call = None;
+        }
+        HookAction::Continue
+    }
+
+    fn wants_reg_writes(&self) -> bool {
+        false
+    }
+
+    fn wants_jumps(&self) -> bool {
+        true
+    }
+
+    fn on_jump(&mut self, jump: &Jump) -> HookAction {
+        let heap = &mut *self.heap.borrow_mut();
+        match heap.call {
+            Some(call) => {
+                if matches!(jump.instr, Instruction::Jalr { rd: 0, .. }) && jump.target == call.ret
+                {
+                    heap.call = None;
+                    heap.returned(call, jump.args[0]);
+                }
+            }
+            None => {
+                let is_call = matches!(
+                    jump.instr,
+                    Instruction::Jal { rd: 1, .. } | Instruction::Jalr { rd: 1, .. }
+                );
+                let function = self
+                    .functions
+                    .iter()
+                    .find(|&&(addr, ..)| addr == jump.target);
+                if let (true, Some(&(_, function, reentrant))) = (is_call, function) {
+                    let args = &jump.args[reentrant as usize..];
+                    heap.call = Some(Call {
+                        function,
+                        args: [args[0], args[1], args[2]],
+                        site: jump.pc,
+                        ret: jump.pc.wrapping_add(4),
+                    });
+                }
+            }
+        }
+        HookAction::Continue
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HeapReport {
+    pub allocations: u64,
+    pub allocated_bytes: u64,
+    pub frees: u64,
+    /// Allocations that returned null
+    pub failed: u64,
+    pub peak_bytes: u64,
+    pub peak_blocks: usize,
+    pub leaked_bytes: u64,
+    pub leaked_blocks: usize,
+    /// Blocks live when the run stopped, by the call site that allocated
+    /// them, most bytes first, with their bytes and blocks
+    pub leaks: Vec<(String, u64, usize)>,
+    /// Frees of pointers that weren't live: double frees, or of memory the
+    /// allocator never returned
+    pub bad_frees: u64,
+    /// The first one's call site, and the pointer
+    pub first_bad_free: Option<(String, u32)>,
+}
+
+impl fmt::Display for HeapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "heap:")?;
+        writeln!(
+            f,
+            "  allocations {:>12} ({} bytes, {} failed)",
+            self.allocations, self.allocated_bytes, self.failed
+        )?;
+        writeln!(f, "  frees       {:>12}", self.frees)?;
+        write!(
+            f,
+            "  peak        {:>12} bytes in {} blocks",
+            self.peak_bytes, self.peak_blocks
+        )?;
+
+        if let Some((site, addr)) = &self.first_bad_free {
+            write!(
+                f,
+                "\n  {} frees of pointers not allocated, the first of {addr:#x} at {site}",
+                self.bad_frees
+            )?;
+        }
+
+        if self.leaks.is_empty() {
+            return write!(f, "\n  no leaks");
+        }
+        write!(
+            f,
+            "\n  leaked      {:>12} bytes in {} blocks, by call site:",
+            self.leaked_bytes, self.leaked_blocks
+        )?;
+        for (site, bytes, blocks) in self.leaks.iter().take(REPORT_TOP) {
+            write!(f, "\n    {bytes:>10} bytes {blocks:>6} blocks  {site}")?;
+        }
+        Ok(())
+    }
+}
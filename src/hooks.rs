@@ -0,0 +1,239 @@
+//! Instrumentation hooks run by the core's slow step path.
+//!
+//! Anything that wants to observe execution implements `Hook` and is registered
+//! with `Core32::add_hook`. Hooks only cost anything when at least one is
+//! installed, as the fast path skips straight past them.
+
+use std::str::FromStr;
+
+use crate::{core::StopReason, instruction::Instruction, register::Register};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookAction {
+    Continue,
+    /// Stop the run, reporting a breakpoint at the current instruction
+    Break,
+    /// Stop the run for some other reason
+    Stop(StopReason),
+}
+
+impl HookAction {
+    /// Combines the actions of two hooks, the first one to stop the run wins
+    pub fn or(self, other: HookAction) -> HookAction {
+        match self {
+            HookAction::Continue => other,
+            _ => self,
+        }
+    }
+}
+
+/// A write to an integer or fp register by a single instruction
+///
+/// Writes that leave the value unchanged are still reported when the register
+/// is the instruction's destination.
+#[derive(Debug, Clone, Copy)]
+pub struct RegWrite {
+    pub pc: u32,
+    pub instr: Instruction,
+    pub reg: Register,
+    pub old: u64,
+    pub new: u64,
+}
+
+/// A write to guest memory about to be made by a single instruction: a store,
+/// a syscall filling a buffer, or a call serviced natively like `memcpy`
+#[derive(Debug, Clone, Copy)]
+pub struct MemWrite {
+    pub pc: u32,
+    pub instr: Instruction,
+    pub addr: u32,
+    pub len: u32,
+}
+
+/// A write to guest memory just made by a single instruction, as `MemWrite`,
+/// with what was there before and is now
+#[derive(Debug, Clone, Copy)]
+pub struct MemWritten<'a> {
+    pub pc: u32,
+    pub instr: Instruction,
+    pub addr: u32,
+    pub old: &'a [u8],
+    pub new: &'a [u8],
+}
+
+/// A read of guest memory about to be made by a single instruction: a load, a
+/// syscall reading a buffer, or the source of a natively serviced call like
+/// `memcpy`
+#[derive(Debug, Clone, Copy)]
+pub struct MemRead {
+    pub pc: u32,
+    pub instr: Instruction,
+    pub addr: u32,
+    pub len: u32,
+}
+
+/// A syscall about to be made, with its arguments `a0`-`a5`
+#[derive(Debug, Clone, Copy)]
+pub struct Syscall {
+    pub pc: u32,
+    pub num: i32,
+    pub args: [u32; 6],
+}
+
+/// A `jal` or `jalr` about to be executed, and where it goes
+#[derive(Debug, Clone, Copy)]
+pub struct Jump {
+    pub pc: u32,
+    pub instr: Instruction,
+    pub target: u32,
+    /// `a0`-`a7`, the arguments if it's a call
+    pub args: [u32; 8],
+}
+
+pub trait Hook {
+    /// Called before each instruction is executed
+    fn before_instruction(&mut self, _pc: u32, _instr: &Instruction) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// Whether the hook needs `on_reg_write`, which means diffing all the
+    /// registers around every instruction
+    fn wants_reg_writes(&self) -> bool {
+        true
+    }
+
+    /// Called after an instruction for every register it wrote
+    fn on_reg_write(&mut self, _write: &RegWrite) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// Whether the hook needs `on_syscall`
+    fn wants_syscalls(&self) -> bool {
+        false
+    }
+
+    /// Called before each `ecall`
+    fn on_syscall(&mut self, _syscall: &Syscall) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// Whether the hook needs `on_mem_read`
+    fn wants_mem_reads(&self) -> bool {
+        false
+    }
+
+    /// Called before an instruction that reads memory is executed
+    fn on_mem_read(&mut self, _read: &MemRead) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// Whether the hook needs `on_mem_write`
+    fn wants_mem_writes(&self) -> bool {
+        false
+    }
+
+    /// Called before an instruction that writes to memory is executed
+    fn on_mem_write(&mut self, _write: &MemWrite) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// Whether the hook needs `on_mem_written`, which means copying what's
+    /// written over around every instruction that writes to memory
+    fn wants_mem_written(&self) -> bool {
+        false
+    }
+
+    /// Called after an instruction that wrote to memory, for writes to RAM
+    fn on_mem_written(&mut self, _written: &MemWritten) -> HookAction {
+        HookAction::Continue
+    }
+
+    /// Whether the hook needs `on_jump`
+    fn wants_jumps(&self) -> bool {
+        false
+    }
+
+    /// Called before each `jal` and `jalr`, so before any call or return
+    fn on_jump(&mut self, _jump: &Jump) -> HookAction {
+        HookAction::Continue
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchMode {
+    /// Log every write
+    Log,
+    /// Log every write, and break once the value actually changes
+    BreakOnChange,
+    /// Break on the first write, even if it leaves the value unchanged
+    BreakOnWrite,
+}
+
+/// Watches a single register, logging writes to it
+pub struct RegisterWatch {
+    reg: Register,
+    mode: WatchMode,
+}
+
+impl RegisterWatch {
+    pub fn new(reg: Register, mode: WatchMode) -> Self {
+        Self { reg, mode }
+    }
+}
+
+impl Hook for RegisterWatch {
+    fn on_reg_write(&mut self, write: &RegWrite) -> HookAction {
+        if write.reg != self.reg {
+            return HookAction::Continue;
+        }
+
+        let RegWrite {
+            pc,
+            instr,
+            reg,
+            old,
+            new,
+        } = *write;
+
+        match self.mode {
+            WatchMode::Log => {
+                eprintln!("watch: {reg} = {new:#x} (was {old:#x}) at pc {pc:#x} ({instr})");
+                HookAction::Continue
+            }
+            WatchMode::BreakOnChange if old == new => {
+                eprintln!("watch: {reg} = {new:#x} (unchanged) at pc {pc:#x} ({instr})");
+                HookAction::Continue
+            }
+            WatchMode::BreakOnChange | WatchMode::BreakOnWrite => {
+                eprintln!(
+                    "break: {reg} ({reg:#}) written at pc {pc:#x} ({instr}): {old:#x} -> {new:#x}"
+                );
+                HookAction::Break
+            }
+        }
+    }
+}
+
+/// A `--watch-reg` argument: `REG` to log writes, `REG:break` to also stop on change
+#[derive(Debug, Clone, Copy)]
+pub struct WatchSpec {
+    pub reg: Register,
+    pub mode: WatchMode,
+}
+
+impl FromStr for WatchSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (reg, mode) = match s.split_once(':') {
+            None => (s, WatchMode::Log),
+            Some((reg, "break")) => (reg, WatchMode::BreakOnChange),
+            Some((_, action)) => return Err(format!("unknown watch action '{action}'")),
+        };
+
+        Ok(WatchSpec {
+            reg: reg.parse()?,
+            mode,
+        })
+    }
+}
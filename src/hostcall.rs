@@ -0,0 +1,173 @@
+//! Guest to host calls through a reserved `ecall` number.
+//!
+//! The guest puts `SYSCALL_HOSTCALL` in `a7`, the id of the host function in
+//! `a6` and up to six arguments in `a0`-`a5`, and gets the result back in `a0`.
+//! Failures are returned as negative errno values, like regular syscalls.
+//! `include/riscy_hostcall.h` wraps this up for C guests.
+
+use std::{collections::HashMap, error::Error, fmt};
+
+use tracing::warn;
+
+/// `ecall` number for host calls, well clear of the Linux syscall numbers
+pub const SYSCALL_HOSTCALL: i32 = 0x5259;
+
+/// Built in: `log(const char *msg, size_t len)`, prints the message to stderr
+pub const HOSTCALL_LOG: u32 = 0;
+
+const ENOSYS: i32 = 38;
+const EFAULT: i32 = 14;
+const EINVAL: i32 = 22;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostcallError {
+    /// No function is registered under the id
+    NoSuchFunction(u32),
+    /// An argument pointed outside guest memory
+    Fault { addr: u32, len: u32 },
+    /// A string argument was unterminated or not UTF-8
+    InvalidString { addr: u32 },
+    /// The host function failed, returning the given errno to the guest
+    Errno(i32),
+}
+
+impl HostcallError {
+    pub fn errno(&self) -> i32 {
+        match self {
+            HostcallError::NoSuchFunction(_) => ENOSYS,
+            HostcallError::Fault { .. } => EFAULT,
+            HostcallError::InvalidString { .. } => EINVAL,
+            HostcallError::Errno(errno) => *errno,
+        }
+    }
+}
+
+impl fmt::Display for HostcallError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostcallError::NoSuchFunction(id) => write!(f, "no host function with id {id}"),
+            HostcallError::Fault { addr, len } => {
+                write!(f, "bad guest buffer {addr:#x} (len {len})")
+            }
+            HostcallError::InvalidString { addr } => write!(f, "bad guest string at {addr:#x}"),
+            HostcallError::Errno(errno) => write!(f, "failed with errno {errno}"),
+        }
+    }
+}
+
+impl Error for HostcallError {}
+
+/// The arguments of a single host call, with access to guest memory for
+/// reading and writing through pointer arguments
+pub struct HostCtx<'a> {
+    args: [u32; 6],
+    memory: &'a mut [u8],
+}
+
+impl<'a> HostCtx<'a> {
+    pub fn new(args: [u32; 6], memory: &'a mut [u8]) -> Self {
+        Self { args, memory }
+    }
+
+    pub fn int(&self, n: usize) -> i32 {
+        self.args[n] as i32
+    }
+
+    pub fn uint(&self, n: usize) -> u32 {
+        self.args[n]
+    }
+
+    /// The guest buffer pointed to by argument `ptr`, with its length in argument `len`
+    pub fn bytes(&self, ptr: usize, len: usize) -> Result<&[u8], HostcallError> {
+        let range = self.range(self.args[ptr], self.args[len])?;
+        Ok(&self.memory[range])
+    }
+
+    pub fn bytes_mut(&mut self, ptr: usize, len: usize) -> Result<&mut [u8], HostcallError> {
+        let range = self.range(self.args[ptr], self.args[len])?;
+        Ok(&mut self.memory[range])
+    }
+
+    /// A `(ptr, len)` string argument pair
+    pub fn str(&self, ptr: usize, len: usize) -> Result<&str, HostcallError> {
+        let addr = self.args[ptr];
+        std::str::from_utf8(self.bytes(ptr, len)?)
+            .map_err(|_| HostcallError::InvalidString { addr })
+    }
+
+    /// A NUL terminated string argument
+    pub fn c_str(&self, ptr: usize) -> Result<&str, HostcallError> {
+        let addr = self.args[ptr];
+        let rest = self
+            .memory
+            .get(addr as usize..)
+            .ok_or(HostcallError::Fault { addr, len: 1 })?;
+        let len = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(HostcallError::InvalidString { addr })?;
+
+        std::str::from_utf8(&rest[..len]).map_err(|_| HostcallError::InvalidString { addr })
+    }
+
+    fn range(&self, addr: u32, len: u32) -> Result<std::ops::Range<usize>, HostcallError> {
+        let start = addr as usize;
+        let end = start + len as usize;
+        if end > self.memory.len() {
+            return Err(HostcallError::Fault { addr, len });
+        }
+        Ok(start..end)
+    }
+}
+
+pub type HostFn = Box<dyn FnMut(&mut HostCtx) -> Result<i32, HostcallError>>;
+
+/// The host functions callable by the guest, by id
+pub struct Hostcalls {
+    fns: HashMap<u32, (String, HostFn)>,
+}
+
+impl Hostcalls {
+    pub fn new() -> Self {
+        let mut hostcalls = Self {
+            fns: HashMap::new(),
+        };
+        hostcalls.register(HOSTCALL_LOG, "log", |ctx| {
+            eprintln!("guest: {}", ctx.str(0, 1)?);
+            Ok(0)
+        });
+        hostcalls
+    }
+
+    /// Registers `f` under `id`, replacing any previous function with that id
+    pub fn register(
+        &mut self,
+        id: u32,
+        name: &str,
+        f: impl FnMut(&mut HostCtx) -> Result<i32, HostcallError> + 'static,
+    ) {
+        self.fns.insert(id, (name.to_string(), Box::new(f)));
+    }
+
+    /// Calls the function `id`, returning what should be written back to `a0`
+    pub fn call(&mut self, id: u32, ctx: &mut HostCtx) -> i32 {
+        let Some((name, f)) = self.fns.get_mut(&id) else {
+            warn!(target: "hostcall", "hostcall failed: {}", HostcallError::NoSuchFunction(id));
+            return -ENOSYS;
+        };
+
+        match f(ctx) {
+            Ok(ret) => ret,
+            Err(err) => {
+                warn!(target: "hostcall", "hostcall '{name}' failed: {err}");
+                -err.errno()
+            }
+        }
+    }
+}
+
+impl Default for Hostcalls {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,299 @@
+//! Readiness of host fds, for devices whose backends are host sockets, pipes
+//! or terminals.
+//!
+//! A `HostIo` runs a thread blocked in `epoll_wait` on the fds it's been asked
+//! to `watch`, so the core never makes a syscall for them while it spins. When
+//! one becomes ready, the thread sends it over a channel (std's `mpsc`, which
+//! is lock-free) and sets a flag, and the core, given it with
+//! `Core32::set_host_io`, looks at that flag every `control::POLL_INTERVAL`
+//! instructions. The fd's callback is run as an event, see `events`, at the
+//! instruction boundary the core noticed at, so it can raise interrupts and
+//! schedule more events like any other.
+//!
+//! Each fd is watched one-shot: once it's reported, it isn't again until its
+//! callback has run, which is when a device reads what's waiting. A run with
+//! host I/O isn't deterministic, as when that happens depends on the host.
+//!
+//! Dropping the `HostIo` wakes the thread through an `eventfd` and joins it.
+//! The fds being watched are the caller's, and aren't closed.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use tracing::warn;
+
+use crate::events::{Event, Fired};
+
+// the `epoll_event` data of the wakeup `eventfd`, which no watch has
+const WAKE: u64 = u64::MAX;
+
+/// Which of reading and writing to watch an fd for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interest {
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Interest {
+    pub const READ: Interest = Interest {
+        read: true,
+        write: false,
+    };
+    pub const WRITE: Interest = Interest {
+        read: false,
+        write: true,
+    };
+    pub const BOTH: Interest = Interest {
+        read: true,
+        write: true,
+    };
+
+    fn epoll_events(self) -> u32 {
+        let mut events = libc::EPOLLONESHOT as u32;
+        if self.read {
+            events |= libc::EPOLLIN as u32;
+        }
+        if self.write {
+            events |= libc::EPOLLOUT as u32;
+        }
+        events
+    }
+}
+
+/// What an fd was ready for when it was reported
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Readiness {
+    pub readable: bool,
+    pub writable: bool,
+    /// The other end is closed, or the fd is in error, and it won't be
+    /// reported again
+    pub hangup: bool,
+}
+
+impl Readiness {
+    fn from_epoll(events: u32) -> Self {
+        Self {
+            readable: events & libc::EPOLLIN as u32 != 0,
+            writable: events & libc::EPOLLOUT as u32 != 0,
+            hangup: events & (libc::EPOLLHUP | libc::EPOLLERR) as u32 != 0,
+        }
+    }
+}
+
+/// A callback run when a watched fd is ready
+pub type IoCallback = dyn FnMut(Readiness, &mut Fired);
+
+/// What `HostIo::unwatch` takes to stop watching an fd
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Watch(u64);
+
+struct Watched {
+    fd: RawFd,
+    interest: Interest,
+    callback: Rc<RefCell<IoCallback>>,
+}
+
+/// The host I/O thread, see the module docs
+pub struct HostIo {
+    epoll: Arc<OwnedFd>,
+    wake: OwnedFd,
+    thread: Option<JoinHandle<()>>,
+    ready: Receiver<(u64, Readiness)>,
+    // set while there's something in `ready`, so polling is just a load
+    pending: Arc<AtomicBool>,
+    watched: HashMap<u64, Watched>,
+    next_watch: u64,
+}
+
+impl HostIo {
+    /// Starts the thread, with nothing to watch yet
+    pub fn new() -> io::Result<Self> {
+        let epoll = owned(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+        let wake = owned(unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) })?;
+        epoll_ctl(
+            &epoll,
+            libc::EPOLL_CTL_ADD,
+            wake.as_raw_fd(),
+            libc::EPOLLIN as u32,
+            WAKE,
+        )?;
+
+        let epoll = Arc::new(epoll);
+        let (tx, ready) = mpsc::channel();
+        let pending = Arc::new(AtomicBool::new(false));
+        let thread = thread::Builder::new().name("riscy-io".to_owned()).spawn({
+            let (epoll, pending) = (epoll.clone(), pending.clone());
+            move || wait_ready(&epoll, &tx, &pending)
+        })?;
+
+        Ok(Self {
+            epoll,
+            wake,
+            thread: Some(thread),
+            ready,
+            pending,
+            watched: HashMap::new(),
+            next_watch: 0,
+        })
+    }
+
+    /// Runs `callback` whenever host fd `fd` is ready for `interest`, until
+    /// `unwatch`. `fd` has to stay open until then
+    pub fn watch(
+        &mut self,
+        fd: RawFd,
+        interest: Interest,
+        callback: impl FnMut(Readiness, &mut Fired) + 'static,
+    ) -> io::Result<Watch> {
+        let watch = self.next_watch;
+        epoll_ctl(
+            &self.epoll,
+            libc::EPOLL_CTL_ADD,
+            fd,
+            interest.epoll_events(),
+            watch,
+        )?;
+        self.next_watch += 1;
+        self.watched.insert(
+            watch,
+            Watched {
+                fd,
+                interest,
+                callback: Rc::new(RefCell::new(callback)),
+            },
+        );
+        Ok(Watch(watch))
+    }
+
+    /// Watches `watch`'s fd for `interest` instead, from now on
+    pub fn set_interest(&mut self, watch: Watch, interest: Interest) -> io::Result<()> {
+        let watched = self
+            .watched
+            .get_mut(&watch.0)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        watched.interest = interest;
+        epoll_ctl(
+            &self.epoll,
+            libc::EPOLL_CTL_MOD,
+            watched.fd,
+            interest.epoll_events(),
+            watch.0,
+        )
+    }
+
+    /// Stops watching `watch`'s fd. Its callback isn't run again, even if it
+    /// was ready before this
+    pub fn unwatch(&mut self, watch: Watch) -> io::Result<()> {
+        let watched = self
+            .watched
+            .remove(&watch.0)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+        epoll_ctl(&self.epoll, libc::EPOLL_CTL_DEL, watched.fd, 0, 0)
+    }
+
+    /// The callbacks of the fds reported since this was last called, as
+    /// events, each re-arming its fd once it's run
+    pub(crate) fn take_ready(&mut self) -> Vec<Event> {
+        if !self.pending.swap(false, Ordering::Acquire) {
+            return Vec::new();
+        }
+
+        let mut events = Vec::new();
+        for (watch, readiness) in self.ready.try_iter() {
+            // unwatched since
+            let Some(watched) = self.watched.get(&watch) else {
+                continue;
+            };
+            let (epoll, fd, interest) = (self.epoll.clone(), watched.fd, watched.interest);
+            let callback = watched.callback.clone();
+            events.push(Box::new(move |fired: &mut Fired| {
+                (callback.borrow_mut())(readiness, fired);
+                if readiness.hangup {
+                    return;
+                }
+                let rearmed = epoll_ctl(
+                    &epoll,
+                    libc::EPOLL_CTL_MOD,
+                    fd,
+                    interest.epoll_events(),
+                    watch,
+                );
+                if let Err(err) = rearmed {
+                    warn!(target: "hostio", "failed to re-arm fd {fd}: {err}");
+                }
+            }) as Event);
+        }
+        events
+    }
+}
+
+impl Drop for HostIo {
+    fn drop(&mut self) {
+        let one = 1u64.to_ne_bytes();
+        unsafe { libc::write(self.wake.as_raw_fd(), one.as_ptr().cast(), one.len()) };
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// the thread, sending what's ready until woken through the `eventfd`
+fn wait_ready(epoll: &OwnedFd, tx: &Sender<(u64, Readiness)>, pending: &AtomicBool) {
+    let mut events = [libc::epoll_event { events: 0, u64: 0 }; 16];
+    loop {
+        let n = unsafe {
+            libc::epoll_wait(
+                epoll.as_raw_fd(),
+                events.as_mut_ptr(),
+                events.len() as i32,
+                -1,
+            )
+        };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            warn!(target: "hostio", "epoll_wait failed, no more host I/O: {err}");
+            return;
+        }
+
+        for event in &events[..n as usize] {
+            let (watch, ready) = (event.u64, event.events);
+            if watch == WAKE {
+                return;
+            }
+            // the core's gone
+            if tx.send((watch, Readiness::from_epoll(ready))).is_err() {
+                return;
+            }
+        }
+        pending.store(true, Ordering::Release);
+    }
+}
+
+fn owned(fd: RawFd) -> io::Result<OwnedFd> {
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn epoll_ctl(epoll: &OwnedFd, op: i32, fd: RawFd, events: u32, data: u64) -> io::Result<()> {
+    let mut event = libc::epoll_event { events, u64: data };
+    if unsafe { libc::epoll_ctl(epoll.as_raw_fd(), op, fd, &mut event) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
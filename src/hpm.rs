@@ -0,0 +1,187 @@
+//! Host and emulator events as the guest's `hpmcounter3`-`hpmcounter31`.
+//!
+//! Each counter given an `HpmEvent` with `Core32::set_hpm_counter` counts it
+//! from then on, so a guest benchmark can read how much work emulating it cost
+//! the host alongside `cycle` and `instret`. The others read zero, as they do
+//! on hardware without them.
+//!
+//! The host's hardware counters come from perf_event_open(2), which needs the
+//! `perf` feature (and so Linux). They count the emulator's thread in user
+//! mode only, which is what an unprivileged process is allowed to see.
+
+use std::{fmt, io, str::FromStr, time::Instant};
+
+#[cfg(feature = "perf")]
+use std::{fs::File, io::Read, os::fd::FromRawFd};
+
+pub const FIRST_COUNTER: u8 = 3;
+pub const LAST_COUNTER: u8 = 31;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HpmEvent {
+    /// Syscalls the guest has made
+    Syscalls,
+    /// Nanoseconds of wall-clock time on the host
+    HostNs,
+    /// Nanoseconds the host has spent running the emulator
+    HostTaskClock,
+    HostCycles,
+    HostInstructions,
+    HostCacheMisses,
+    HostBranchMisses,
+    HostPageFaults,
+}
+
+const EVENTS: &[(&str, HpmEvent)] = &[
+    ("syscalls", HpmEvent::Syscalls),
+    ("host-ns", HpmEvent::HostNs),
+    ("host-task-clock", HpmEvent::HostTaskClock),
+    ("host-cycles", HpmEvent::HostCycles),
+    ("host-instructions", HpmEvent::HostInstructions),
+    ("host-cache-misses", HpmEvent::HostCacheMisses),
+    ("host-branch-misses", HpmEvent::HostBranchMisses),
+    ("host-page-faults", HpmEvent::HostPageFaults),
+];
+
+impl FromStr for HpmEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EVENTS
+            .iter()
+            .find(|(name, _)| *name == s)
+            .map(|&(_, event)| event)
+            .ok_or_else(|| {
+                let names: Vec<_> = EVENTS.iter().map(|(name, _)| *name).collect();
+                format!("unknown event '{s}', expected one of {}", names.join(", "))
+            })
+    }
+}
+
+impl fmt::Display for HpmEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, _) = EVENTS.iter().find(|(_, event)| event == self).unwrap();
+        f.write_str(name)
+    }
+}
+
+/// Which counter counts what, parsed from `N=EVENT`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HpmMapping {
+    pub counter: u8,
+    pub event: HpmEvent,
+}
+
+impl FromStr for HpmMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (counter, event) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid counter mapping '{s}', expected N=EVENT"))?;
+
+        let counter = counter
+            .parse()
+            .ok()
+            .filter(|n| (FIRST_COUNTER..=LAST_COUNTER).contains(n))
+            .ok_or_else(|| {
+                format!("invalid counter '{counter}', expected {FIRST_COUNTER} to {LAST_COUNTER}")
+            })?;
+
+        Ok(HpmMapping {
+            counter,
+            event: event.parse()?,
+        })
+    }
+}
+
+/// Where a counter's value comes from
+pub(crate) enum HpmCounter {
+    // counted by the core itself
+    Syscalls,
+    Clock(Instant),
+    #[cfg(feature = "perf")]
+    Perf(File),
+}
+
+impl HpmCounter {
+    pub(crate) fn open(event: HpmEvent) -> io::Result<Self> {
+        match event {
+            HpmEvent::Syscalls => Ok(HpmCounter::Syscalls),
+            HpmEvent::HostNs => Ok(HpmCounter::Clock(Instant::now())),
+            #[cfg(feature = "perf")]
+            _ => perf_open(event).map(HpmCounter::Perf),
+            #[cfg(not(feature = "perf"))]
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{event} needs riscy to be built with the perf feature"),
+            )),
+        }
+    }
+
+    /// The count, for those the core doesn't keep itself
+    pub(crate) fn read(&self) -> u64 {
+        match self {
+            HpmCounter::Syscalls => 0,
+            HpmCounter::Clock(start) => start.elapsed().as_nanos() as u64,
+            #[cfg(feature = "perf")]
+            HpmCounter::Perf(file) => {
+                let mut buf = [0; 8];
+                // a counter that can't be read reads zero, like one never set
+                match (&*file).read_exact(&mut buf) {
+                    Ok(()) => u64::from_ne_bytes(buf),
+                    Err(_) => 0,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "perf")]
+fn perf_open(event: HpmEvent) -> io::Result<File> {
+    use perf_event_open_sys::{bindings::*, perf_event_open};
+
+    let (type_, config) = match event {
+        HpmEvent::HostTaskClock => (
+            perf_type_id_PERF_TYPE_SOFTWARE,
+            perf_sw_ids_PERF_COUNT_SW_TASK_CLOCK,
+        ),
+        HpmEvent::HostPageFaults => (
+            perf_type_id_PERF_TYPE_SOFTWARE,
+            perf_sw_ids_PERF_COUNT_SW_PAGE_FAULTS,
+        ),
+        HpmEvent::HostCycles => (
+            perf_type_id_PERF_TYPE_HARDWARE,
+            perf_hw_id_PERF_COUNT_HW_CPU_CYCLES,
+        ),
+        HpmEvent::HostInstructions => (
+            perf_type_id_PERF_TYPE_HARDWARE,
+            perf_hw_id_PERF_COUNT_HW_INSTRUCTIONS,
+        ),
+        HpmEvent::HostCacheMisses => (
+            perf_type_id_PERF_TYPE_HARDWARE,
+            perf_hw_id_PERF_COUNT_HW_CACHE_MISSES,
+        ),
+        HpmEvent::HostBranchMisses => (
+            perf_type_id_PERF_TYPE_HARDWARE,
+            perf_hw_id_PERF_COUNT_HW_BRANCH_MISSES,
+        ),
+        HpmEvent::Syscalls | HpmEvent::HostNs => unreachable!("not a perf event"),
+    };
+
+    let mut attr = perf_event_attr {
+        type_,
+        size: std::mem::size_of::<perf_event_attr>() as u32,
+        config: config as u64,
+        ..Default::default()
+    };
+    attr.set_exclude_kernel(1);
+    attr.set_exclude_hv(1);
+
+    // this thread, on any cpu
+    let fd = unsafe { perf_event_open(&mut attr, 0, -1, -1, PERF_FLAG_FD_CLOEXEC as _) };
+    if fd < 0 {
+        return Err(io::Error::from_raw_os_error(-fd));
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
@@ -0,0 +1,63 @@
+//! The host-target interface of Spike and riscv-pk, for bare-metal binaries.
+//!
+//! Binaries built for riscv-pk's frontend, or against libgloss-htif, don't make
+//! syscalls with `ecall`: they write a command to the 64-bit `tohost` symbol
+//! and poll `fromhost` for the answer. `Core32::enable_htif` (`riscy --htif`)
+//! services those commands:
+//!
+//! - device 0 with the low bit set exits, with the rest of the value as the
+//!   exit code, as the riscv-tests do
+//! - any other device 0 command points at the guest's `magic_mem`: a syscall
+//!   number and its arguments, as 64-bit words. The syscall is made as if by
+//!   `ecall`, its result goes back in the first word and `fromhost` is set to 1
+//! - device 1 command 1 writes the low byte to stdout, and is acknowledged in
+//!   `fromhost`
+//!
+//! `tohost` is cleared once a command is done. On rv32 the guest writes it a
+//! word at a time, low word first, so a command runs once its high word is
+//! written; `tohost` must be 8-byte aligned, as the toolchains put it. Console
+//! input, device 1 command 0, is never answered, so the guest sees none.
+
+pub(crate) const TOHOST: &str = "tohost";
+pub(crate) const FROMHOST: &str = "fromhost";
+
+// the number of 64-bit words in `magic_mem`
+pub(crate) const MAGIC_MEM_WORDS: usize = 8;
+
+/// A command written to `tohost`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Command {
+    Exit(i32),
+    /// The syscall described at this address
+    Syscall(u32),
+    Putchar(u8),
+    Unsupported {
+        device: u8,
+        cmd: u8,
+    },
+}
+
+impl Command {
+    pub(crate) fn decode(value: u64) -> Self {
+        let device = (value >> 56) as u8;
+        let cmd = (value >> 48) as u8;
+        let payload = value & ((1 << 48) - 1);
+
+        match (device, cmd) {
+            (0, 0) if payload & 1 == 1 => Command::Exit((payload >> 1) as i32),
+            (0, 0) => Command::Syscall(payload as u32),
+            (1, 1) => Command::Putchar(payload as u8),
+            _ => Command::Unsupported { device, cmd },
+        }
+    }
+}
+
+/// What's written to `fromhost` once `command` is done, if anything
+pub(crate) fn response(command: Command) -> Option<u64> {
+    match command {
+        Command::Syscall(_) => Some(1),
+        // the byte back, as Spike's console does
+        Command::Putchar(ch) => Some((1 << 56) | (1 << 48) | 0x100 | ch as u64),
+        Command::Exit(_) | Command::Unsupported { .. } => None,
+    }
+}
@@ -0,0 +1,160 @@
+//! What the guest is told about the machine it's running on.
+//!
+//! libcs and language runtimes ask at startup: `uname` for the architecture
+//! and kernel version they're on, `/proc/self/maps` for where the stack is,
+//! and `/proc/cpuinfo` for which extensions they can use. `uname` says what a
+//! `Uname` (`riscy --uname release=5.15.0,nodename=build`) says, by default a
+//! Linux 6.6 riscv32 machine.
+//!
+//! With `Core32::set_fake_proc` (`riscy --fake-proc`), opening
+//! `/proc/self/maps` or `/proc/cpuinfo` gives a read-only file describing the
+//! guest rather than the host, whether or not it has a root: its memory map,
+//! with the heap up to the break and the stack above it, and the one hart with
+//! the extensions riscy implements. Nothing else under `/proc` is there.
+
+use std::{fmt::Write, str::FromStr};
+
+use crate::region::{MemoryMap, RegionKind};
+
+/// The size of the guest's `struct utsname`, six fields
+pub(crate) const UTSNAME_SIZE: u32 = 6 * FIELD_LEN as u32;
+// each field's, its NUL included
+const FIELD_LEN: usize = 65;
+
+// the extensions riscy implements, as the kernel lists them
+const ISA: &str = "rv32imafd_zicntr_zicsr_zifencei_zihpm_zacas";
+
+/// What `uname` says, parsed from `FIELD=VALUE,...` over the defaults, with
+/// `sysname`, `nodename`, `release`, `version`, `machine` and `domainname` the
+/// fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uname {
+    pub sysname: String,
+    pub nodename: String,
+    pub release: String,
+    pub version: String,
+    pub machine: String,
+    pub domainname: String,
+}
+
+impl Default for Uname {
+    fn default() -> Self {
+        Self {
+            sysname: "Linux".to_owned(),
+            nodename: "riscy".to_owned(),
+            release: "6.6.0".to_owned(),
+            version: "#1 SMP".to_owned(),
+            machine: "riscv32".to_owned(),
+            domainname: "(none)".to_owned(),
+        }
+    }
+}
+
+impl FromStr for Uname {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut uname = Uname::default();
+
+        for part in s.split(',').filter(|part| !part.is_empty()) {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected FIELD=VALUE, got '{part}'"))?;
+            if value.len() >= FIELD_LEN || value.contains('\0') {
+                return Err(format!(
+                    "invalid value '{value}' for {name}, which takes at most {} bytes",
+                    FIELD_LEN - 1
+                ));
+            }
+
+            let field = match name {
+                "sysname" => &mut uname.sysname,
+                "nodename" => &mut uname.nodename,
+                "release" => &mut uname.release,
+                "version" => &mut uname.version,
+                "machine" => &mut uname.machine,
+                "domainname" => &mut uname.domainname,
+                _ => {
+                    return Err(format!(
+                        "unknown uname field '{name}', expected one of sysname, nodename, \
+                         release, version, machine, domainname"
+                    ))
+                }
+            };
+            *field = value.to_owned();
+        }
+
+        Ok(uname)
+    }
+}
+
+impl Uname {
+    /// The guest's `struct utsname`
+    pub(crate) fn encode(&self) -> [u8; UTSNAME_SIZE as usize] {
+        let mut buf = [0; UTSNAME_SIZE as usize];
+        let fields = [
+            &self.sysname,
+            &self.nodename,
+            &self.release,
+            &self.version,
+            &self.machine,
+            &self.domainname,
+        ];
+        for (field, value) in buf.chunks_mut(FIELD_LEN).zip(fields) {
+            // NUL-terminated by the zeroes after it
+            field[..value.len()].copy_from_slice(value.as_bytes());
+        }
+        buf
+    }
+}
+
+/// The contents of `path` if it's one of the `/proc` files the guest is given,
+/// for a guest with memory `map` and its heap from `heap_start` to `brk`
+pub(crate) fn proc_file(
+    path: &[u8],
+    map: &MemoryMap,
+    heap_start: u32,
+    brk: u32,
+) -> Option<Vec<u8>> {
+    match path {
+        b"/proc/self/maps" => Some(maps(map, heap_start, brk).into_bytes()),
+        b"/proc/cpuinfo" => Some(cpuinfo().into_bytes()),
+        _ => None,
+    }
+}
+
+// `/proc/self/maps`, RAM split into what's below the heap, the heap, and the
+// stack above it
+fn maps(map: &MemoryMap, heap_start: u32, brk: u32) -> String {
+    let mut maps = String::new();
+    let mut line = |start: u64, end: u64, perms: String, name: &str| {
+        if start >= end {
+            return;
+        }
+        let entry = format!("{start:08x}-{end:08x} {perms}p 00000000 00:00 0");
+        if name.is_empty() {
+            writeln!(maps, "{entry}").unwrap();
+        } else {
+            writeln!(maps, "{entry:<48} {name}").unwrap();
+        }
+    };
+
+    for region in map.regions() {
+        let (start, end) = (region.start as u64, region.end());
+        let perms = region.perms.to_string();
+        if region.kind != RegionKind::Ram {
+            line(start, end, perms, "");
+            continue;
+        }
+
+        let (heap_start, brk) = (heap_start as u64, brk as u64);
+        line(start, end.min(heap_start), perms.clone(), "");
+        line(start.max(heap_start), end.min(brk), perms.clone(), "[heap]");
+        line(start.max(brk), end, perms, "[stack]");
+    }
+    maps
+}
+
+fn cpuinfo() -> String {
+    format!("processor\t: 0\nhart\t\t: 0\nisa\t\t: {ISA}\nmmu\t\t: none\nuarch\t\t: riscy\n\n")
+}
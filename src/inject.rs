@@ -0,0 +1,278 @@
+//! Faults injected into a run, for `riscy --inject`, to test how a guest
+//! handles errors, and how its ECC or recovery code copes with corruption,
+//! the same way every run.
+//!
+//! - `flip:REG:BIT@N` flips bit BIT of register REG once N instructions have
+//!   run, e.g. `flip:a0:3@1_000`
+//! - `flip:ADDR:BIT@N` flips bit BIT of the byte at ADDR, likewise
+//! - `syscall:NAME=ERRNO` fails every call to syscall NAME with ERRNO, without
+//!   making it, e.g. `syscall:write=ENOSPC`, and `syscall:NAME=ERRNO@K` only
+//!   its Kth call
+//! - `load:P` flips a bit of what a load read with probability P, e.g.
+//!   `load:0.001`, as if memory had lost it
+//!
+//! NAME is a syscall's name or number, as for `--break-syscall`, and ERRNO an
+//! errno's name or number. Which loads are corrupted, and which of their bits,
+//! comes from a seed, so a run can be repeated. Corrupting loads takes the
+//! slow step path, as hooks do, see `hooks`; the rest is free until it's due.
+//! Each fault is logged, under `inject`, as it's injected. A snapshot doesn't
+//! include which have been, so restoring one doesn't inject them again.
+
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use crate::{breakpoint::SyscallSpec, instruction::Instruction, nondet, register::Register};
+
+// the errnos a guest is likeliest to have to handle, by name
+const ERRNOS: &[(&str, i32)] = &[
+    ("EPERM", 1),
+    ("ENOENT", 2),
+    ("EINTR", 4),
+    ("EIO", 5),
+    ("EBADF", 9),
+    ("EAGAIN", 11),
+    ("ENOMEM", 12),
+    ("EACCES", 13),
+    ("EFAULT", 14),
+    ("EBUSY", 16),
+    ("EEXIST", 17),
+    ("EINVAL", 22),
+    ("ENFILE", 23),
+    ("EMFILE", 24),
+    ("EFBIG", 27),
+    ("ENOSPC", 28),
+    ("ESPIPE", 29),
+    ("EROFS", 30),
+    ("EPIPE", 32),
+    ("ERANGE", 34),
+    ("ENOSYS", 38),
+    ("ETIMEDOUT", 110),
+];
+
+fn errno_name(errno: i32) -> Option<&'static str> {
+    ERRNOS
+        .iter()
+        .find(|&&(_, num)| num == errno)
+        .map(|&(name, _)| name)
+}
+
+/// What a `flip` flips a bit of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Reg(Register),
+    Mem(u32),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Reg(reg) => write!(f, "{reg}"),
+            Target::Mem(addr) => write!(f, "the byte at {addr:#x}"),
+        }
+    }
+}
+
+/// A `--inject` argument, see the module docs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FaultSpec {
+    Flip {
+        target: Target,
+        bit: u8,
+        at: u64,
+    },
+    Syscall {
+        num: i32,
+        errno: i32,
+        call: Option<u64>,
+    },
+    Load {
+        probability: f64,
+    },
+}
+
+impl FromStr for FaultSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid fault '{s}', expected flip:REG:BIT@N, flip:ADDR:BIT@N, \
+                 syscall:NAME=ERRNO[@K] or load:P"
+            )
+        };
+        let count = |n: &str| n.replace('_', "").parse::<u64>().map_err(|_| invalid());
+        let (kind, rest) = s.split_once(':').ok_or_else(invalid)?;
+
+        match kind {
+            "flip" => {
+                let (what, at) = rest.split_once('@').ok_or_else(invalid)?;
+                let (what, bit) = what.rsplit_once(':').ok_or_else(invalid)?;
+                let bit: u8 = bit.parse().map_err(|_| invalid())?;
+                let (target, bits) = match what.parse::<Register>() {
+                    Ok(reg) if reg.is_fp() => (Target::Reg(reg), 64),
+                    Ok(reg) => (Target::Reg(reg), 32),
+                    Err(_) => {
+                        let addr = match what.strip_prefix("0x") {
+                            Some(hex) => u32::from_str_radix(hex, 16),
+                            None => what.parse(),
+                        }
+                        .map_err(|_| invalid())?;
+                        (Target::Mem(addr), 8)
+                    }
+                };
+                if bit >= bits {
+                    return Err(format!("{target} has no bit {bit}, only {bits}"));
+                }
+                Ok(FaultSpec::Flip {
+                    target,
+                    bit,
+                    at: count(at)?,
+                })
+            }
+            "syscall" => {
+                let (name, errno) = rest.split_once('=').ok_or_else(invalid)?;
+                let (errno, call) = match errno.split_once('@') {
+                    Some((errno, call)) => (errno, Some(count(call)?)),
+                    None => (errno, None),
+                };
+                let errno = ERRNOS
+                    .iter()
+                    .find(|&&(known, _)| known.eq_ignore_ascii_case(errno))
+                    .map(|&(_, num)| num)
+                    .or_else(|| errno.parse().ok().filter(|&num| num > 0))
+                    .ok_or_else(|| format!("unknown errno '{errno}'"))?;
+                Ok(FaultSpec::Syscall {
+                    num: name.parse::<SyscallSpec>()?.num,
+                    errno,
+                    call,
+                })
+            }
+            "load" => {
+                let probability: f64 = rest.parse().map_err(|_| invalid())?;
+                if !(0.0..=1.0).contains(&probability) {
+                    return Err(format!(
+                        "invalid probability '{rest}', expected one from 0 to 1"
+                    ));
+                }
+                Ok(FaultSpec::Load { probability })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The faults a core has yet to inject, see the module docs
+pub(crate) struct Faults {
+    // flips not yet made, latest first
+    flips: Vec<(u64, Target, u8)>,
+    // syscalls to fail, with the errno and which call
+    syscalls: Vec<(i32, i32, Option<u64>)>,
+    // calls made to each syscall failed
+    calls: BTreeMap<i32, u64>,
+    load_probability: Option<f64>,
+    rng: u64,
+    injected: u64,
+}
+
+impl Faults {
+    pub(crate) fn new(specs: &[FaultSpec], seed: u64) -> Self {
+        let mut faults = Self {
+            flips: Vec::new(),
+            syscalls: Vec::new(),
+            calls: BTreeMap::new(),
+            load_probability: None,
+            rng: seed,
+            injected: 0,
+        };
+        for spec in specs {
+            match *spec {
+                FaultSpec::Flip { target, bit, at } => faults.flips.push((at, target, bit)),
+                FaultSpec::Syscall { num, errno, call } => faults.syscalls.push((num, errno, call)),
+                FaultSpec::Load { probability } => faults.load_probability = Some(probability),
+            }
+        }
+        faults.flips.sort_by_key(|&(at, ..)| std::cmp::Reverse(at));
+        faults
+    }
+
+    /// How many faults have been injected
+    pub(crate) fn injected(&self) -> u64 {
+        self.injected
+    }
+
+    pub(crate) fn corrupts_loads(&self) -> bool {
+        self.load_probability.is_some()
+    }
+
+    /// When the next flip is due
+    pub(crate) fn next_check(&self) -> u64 {
+        self.flips.last().map_or(u64::MAX, |&(at, ..)| at)
+    }
+
+    /// The flips due once `instret` instructions have run, taken off the
+    /// list
+    pub(crate) fn due(&mut self, instret: u64) -> Vec<(Target, u8)> {
+        let mut due = Vec::new();
+        while let Some(&(at, target, bit)) = self.flips.last() {
+            if at > instret {
+                break;
+            }
+            self.flips.pop();
+            due.push((target, bit));
+        }
+        self.injected += due.len() as u64;
+        due
+    }
+
+    /// The errno to fail a call to syscall `num` with, if it's to fail
+    pub(crate) fn syscall(&mut self, num: i32) -> Option<i32> {
+        if !self.syscalls.iter().any(|&(failed, ..)| failed == num) {
+            return None;
+        }
+        let calls = self.calls.entry(num).or_insert(0);
+        *calls += 1;
+        let calls = *calls;
+        let errno = self
+            .syscalls
+            .iter()
+            .find(|&&(failed, _, call)| failed == num && call.is_none_or(|call| call == calls))
+            .map(|&(_, errno, _)| errno)?;
+        self.injected += 1;
+        Some(errno)
+    }
+
+    /// What `instr`, having just loaded `value` into its destination, is to
+    /// have loaded instead, if it's a load that's to be corrupted. `value` is
+    /// the raw bits of an fp register for `flw` and `fld`
+    pub(crate) fn corrupt_load(&mut self, instr: &Instruction, value: u64) -> Option<u64> {
+        let probability = self.load_probability?;
+        let bits = match instr {
+            Instruction::Lb { .. } | Instruction::Lbu { .. } => 8,
+            Instruction::Lh { .. } | Instruction::Lhu { .. } => 16,
+            Instruction::Lw { .. } | Instruction::LrW { .. } | Instruction::Flw { .. } => 32,
+            Instruction::Fld { .. } => 64,
+            _ => return None,
+        };
+        // the top 53 bits, as a fraction of 1
+        let roll = (nondet::splitmix64(&mut self.rng) >> 11) as f64 / (1u64 << 53) as f64;
+        if roll >= probability {
+            return None;
+        }
+        let mask = 1u64 << (nondet::splitmix64(&mut self.rng) % bits);
+        self.injected += 1;
+
+        // loaded again with the bit flipped, extended as the load extends
+        Some(match instr {
+            Instruction::Lb { .. } => (value as u8 ^ mask as u8) as i8 as i32 as u32 as u64,
+            Instruction::Lh { .. } => (value as u16 ^ mask as u16) as i16 as i32 as u32 as u64,
+            _ => value ^ mask,
+        })
+    }
+}
+
+/// `errno` by name, for the log
+pub(crate) fn describe_errno(errno: i32) -> String {
+    match errno_name(errno) {
+        Some(name) => name.to_owned(),
+        None => format!("errno {errno}"),
+    }
+}
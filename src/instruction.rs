@@ -1,6 +1,36 @@
+use std::fmt;
+
+use crate::{
+    csr,
+    custom::CustomOpcode,
+    opcodes::{self, Field, Opcode},
+    register::Register,
+};
+
+/// Width of the integer registers, which decides some encodings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Xlen {
+    Rv32,
+    Rv64,
+}
+
+impl Xlen {
+    pub fn bits(self) -> u32 {
+        match self {
+            Xlen::Rv32 => 32,
+            Xlen::Rv64 => 64,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum Instruction {
     Unknown(u32),
+    /// In one of the spaces left for extensions, see `custom`
+    Custom {
+        opcode: CustomOpcode,
+        inst: u32,
+    },
 
     Lui {
         rd: u8,
@@ -195,13 +225,97 @@ pub enum Instruction {
 
     Ebreak,
 
-    Frrm {
+    // privileged, which riscy only decodes as it only runs user mode
+    Wfi,
+    Mret,
+    Sret,
+    SfenceVma {
+        rs1: u8,
+        rs2: u8,
+    },
+
+    // h-extension, which needs hypervisor-capable privilege levels riscy
+    // doesn't have, so it's only decoded too
+    HfenceVvma {
+        rs1: u8,
+        rs2: u8,
+    },
+    HfenceGvma {
+        rs1: u8,
+        rs2: u8,
+    },
+    HlvB {
+        rd: u8,
+        rs1: u8,
+    },
+    HlvBu {
         rd: u8,
+        rs1: u8,
+    },
+    HlvH {
+        rd: u8,
+        rs1: u8,
+    },
+    HlvHu {
+        rd: u8,
+        rs1: u8,
+    },
+    HlvxHu {
+        rd: u8,
+        rs1: u8,
+    },
+    HlvW {
+        rd: u8,
+        rs1: u8,
     },
-    Fsrm {
+    HlvxWu {
         rd: u8,
         rs1: u8,
     },
+    HsvB {
+        rs1: u8,
+        rs2: u8,
+    },
+    HsvH {
+        rs1: u8,
+        rs2: u8,
+    },
+    HsvW {
+        rs1: u8,
+        rs2: u8,
+    },
+
+    // zicsr-extension
+    Csrrw {
+        rd: u8,
+        rs1: u8,
+        csr: u16,
+    },
+    Csrrs {
+        rd: u8,
+        rs1: u8,
+        csr: u16,
+    },
+    Csrrc {
+        rd: u8,
+        rs1: u8,
+        csr: u16,
+    },
+    Csrrwi {
+        rd: u8,
+        zimm: u8,
+        csr: u16,
+    },
+    Csrrsi {
+        rd: u8,
+        zimm: u8,
+        csr: u16,
+    },
+    Csrrci {
+        rd: u8,
+        zimm: u8,
+        csr: u16,
+    },
 
     // m-extension
     Mul {
@@ -245,6 +359,85 @@ pub enum Instruction {
         rs2: u8,
     },
 
+    // a-extension, `aqrl` being the acquire (bit 1) and release (bit 0) bits
+    LrW {
+        rd: u8,
+        rs1: u8,
+        aqrl: u8,
+    },
+    ScW {
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+        aqrl: u8,
+    },
+    AmoswapW {
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+        aqrl: u8,
+    },
+    AmoaddW {
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+        aqrl: u8,
+    },
+    AmoxorW {
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+        aqrl: u8,
+    },
+    AmoandW {
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+        aqrl: u8,
+    },
+    AmoorW {
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+        aqrl: u8,
+    },
+    AmominW {
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+        aqrl: u8,
+    },
+    AmomaxW {
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+        aqrl: u8,
+    },
+    AmominuW {
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+        aqrl: u8,
+    },
+    AmomaxuW {
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+        aqrl: u8,
+    },
+
+    // zacas-extension, where `rd` is also the value compared against
+    AmocasW {
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+        aqrl: u8,
+    },
+
+    // zawrs-extension
+    WrsNto,
+    WrsSto,
+
     // f/d arithmetic (fp add/sub/mul/div, etc)
     FaddS {
         rd: u8,
@@ -441,44 +634,54 @@ pub enum Instruction {
     FcvtSW {
         rd: u8,
         rs1: u8,
+        rm: u8,
     }, // signed int -> single
     FcvtSWu {
         rd: u8,
         rs1: u8,
+        rm: u8,
     }, // unsigned int -> single
     FcvtWS {
         rd: u8,
         rs1: u8,
+        rm: u8,
     }, // single -> signed int
     FcvtWuS {
         rd: u8,
         rs1: u8,
+        rm: u8,
     }, // single -> unsigned int
 
     FcvtDW {
         rd: u8,
         rs1: u8,
+        rm: u8,
     }, // signed int -> double
     FcvtDWu {
         rd: u8,
         rs1: u8,
+        rm: u8,
     }, // unsigned int -> double
     FcvtWD {
         rd: u8,
         rs1: u8,
+        rm: u8,
     }, // double -> signed int
     FcvtWuD {
         rd: u8,
         rs1: u8,
+        rm: u8,
     }, // double -> unsigned int
 
     FcvtSD {
         rd: u8,
         rs1: u8,
+        rm: u8,
     }, // double -> single
     FcvtDS {
         rd: u8,
         rs1: u8,
+        rm: u8,
     }, // single -> double
 
     // floating point compares (set int reg to 1 if true, else 0)
@@ -535,448 +738,1405 @@ pub enum Instruction {
         rs2: u8,
         imm: i32,
     },
+
+    // zfa-extension; `idx` picks a constant from `fli_single`/`fli_double`
+    FliS {
+        rd: u8,
+        idx: u8,
+    },
+    FliD {
+        rd: u8,
+        idx: u8,
+    },
+    FminmS {
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+    },
+    FmaxmS {
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+    },
+    FminmD {
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+    },
+    FmaxmD {
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+    },
+    FroundS {
+        rd: u8,
+        rs1: u8,
+        rm: u8,
+    },
+    FroundnxS {
+        rd: u8,
+        rs1: u8,
+        rm: u8,
+    },
+    FroundD {
+        rd: u8,
+        rs1: u8,
+        rm: u8,
+    },
+    FroundnxD {
+        rd: u8,
+        rs1: u8,
+        rm: u8,
+    },
+    FcvtmodWD {
+        rd: u8,
+        rs1: u8,
+    }, // double -> signed int, modulo 2^32
+}
+
+// the `fli` constants, bar the minimum normal and the canonical NaN
+const FLI_CONSTANTS: [f64; 32] = [
+    -1.0,
+    0.0, // minimum positive normal
+    1.0 / 65536.0,
+    1.0 / 32768.0,
+    1.0 / 256.0,
+    1.0 / 128.0,
+    0.0625,
+    0.125,
+    0.25,
+    0.3125,
+    0.375,
+    0.4375,
+    0.5,
+    0.625,
+    0.75,
+    0.875,
+    1.0,
+    1.25,
+    1.5,
+    1.75,
+    2.0,
+    2.5,
+    3.0,
+    4.0,
+    8.0,
+    16.0,
+    128.0,
+    256.0,
+    32768.0,
+    65536.0,
+    f64::INFINITY,
+    0.0, // canonical NaN
+];
+
+/// The single `fli.s` loads for `idx`
+pub fn fli_single(idx: u8) -> f32 {
+    match idx {
+        1 => f32::MIN_POSITIVE,
+        31 => f32::from_bits(0x7fc0_0000),
+        _ => FLI_CONSTANTS[idx as usize] as f32,
+    }
+}
+
+/// The double `fli.d` loads for `idx`
+pub fn fli_double(idx: u8) -> f64 {
+    match idx {
+        1 => f64::MIN_POSITIVE,
+        31 => f64::from_bits(0x7ff8_0000_0000_0000),
+        _ => FLI_CONSTANTS[idx as usize],
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FpWidth {
+    Single,
+    Double,
 }
 
 impl Instruction {
+    /// Decodes `inst` for RV32
     pub fn decode(inst: u32) -> Instruction {
+        Self::decode_for(inst, Xlen::Rv32)
+    }
+
+    /// Decodes `inst` for the given base ISA width; reserved bits must be zero,
+    /// so e.g. an RV64 shift amount of 32 or more is `Unknown` on RV32
+    pub fn decode_for(inst: u32, xlen: Xlen) -> Instruction {
         // helper for sign extension
         fn sign_extend(val: u32, bits: u8) -> i32 {
             let shift = 32 - bits;
             ((val << shift) as i32) >> shift
         }
 
-        let opcode = inst & 0x7f;
-        let rd = ((inst >> 7) & 0x1f) as u8;
-        let funct3 = (inst >> 12) & 0x7;
-        let rs1 = ((inst >> 15) & 0x1f) as u8;
-        let rs2 = ((inst >> 20) & 0x1f) as u8;
-        let sz = ((inst >> 25) & 0x3) as u8;
-        let rs3 = ((inst >> 27) & 0x1f) as u8;
-        let funct7 = (inst >> 25) & 0x7f;
-        let rm = ((inst >> 12) & 0x7) as u8;
-        match opcode {
-            0x37 => {
-                // lui
-                let rd = ((inst >> 7) & 0x1f) as u8;
-                let imm = (inst & 0xfffff000) as i32;
-                Instruction::Lui { rd, imm }
-            }
-            0x17 => {
-                // auipc
-                let rd = ((inst >> 7) & 0x1f) as u8;
-                let imm = (inst & 0xfffff000) as i32;
-                Instruction::Auipc { rd, imm }
-            }
-            0x6f => {
-                // jal
-                let rd = ((inst >> 7) & 0x1f) as u8;
-                let imm_raw = (((inst >> 31) & 0x1) << 20)
-                    | (((inst >> 12) & 0xff) << 12)
-                    | (((inst >> 20) & 0x1) << 11)
-                    | (((inst >> 21) & 0x3ff) << 1);
-                let imm = sign_extend(imm_raw, 21);
-                Instruction::Jal { rd, imm }
-            }
-            0x67 => {
-                // jalr (i-type)
-                if ((inst >> 12) & 0x7) == 0 {
-                    let rd = ((inst >> 7) & 0x1f) as u8;
-                    let rs1 = ((inst >> 15) & 0x1f) as u8;
-                    let imm = sign_extend((inst >> 20) & 0xfff, 12);
-                    Instruction::Jalr { rd, rs1, imm }
-                } else {
-                    Instruction::Unknown(inst)
-                }
+        if let Some(opcode) = CustomOpcode::of(inst) {
+            return Instruction::Custom { opcode, inst };
+        }
+
+        let Some(info) = opcodes::lookup(inst, xlen.bits()) else {
+            return Instruction::Unknown(inst);
+        };
+
+        let rd = Field::Rd.extract(inst) as u8;
+        let rs1 = Field::Rs1.extract(inst) as u8;
+        let rs2 = Field::Rs2.extract(inst) as u8;
+        let rs3 = Field::Rs3.extract(inst) as u8;
+        let rm = Field::Rm.extract(inst) as u8;
+        let csr = Field::Csr.extract(inst) as u16;
+        let zimm = Field::Zimm.extract(inst) as u8;
+        let aqrl = ((Field::Aq.extract(inst) << 1) | Field::Rl.extract(inst)) as u8;
+
+        // only meaningful for the encodings that have the corresponding fields
+        let imm_i = sign_extend(Field::Imm12.extract(inst), 12);
+        let imm_s = sign_extend(
+            (Field::Imm12Hi.extract(inst) << 5) | Field::Imm12Lo.extract(inst),
+            12,
+        );
+        let imm_b = {
+            let hi = Field::Bimm12Hi.extract(inst);
+            let lo = Field::Bimm12Lo.extract(inst);
+            let raw = ((hi >> 6) << 12) | ((lo & 1) << 11) | ((hi & 0x3f) << 5) | (lo & !1);
+            sign_extend(raw, 13)
+        };
+        let imm_u = (Field::Imm20.extract(inst) << 12) as i32;
+        let imm_j = {
+            let raw = Field::Jimm20.extract(inst);
+            let raw = ((raw >> 19) << 20)
+                | ((raw & 0xff) << 12)
+                | (((raw >> 8) & 1) << 11)
+                | (((raw >> 9) & 0x3ff) << 1);
+            sign_extend(raw, 21)
+        };
+        let shamt = match xlen {
+            Xlen::Rv32 => Field::Shamtw.extract(inst) as u8,
+            Xlen::Rv64 => Field::Shamtd.extract(inst) as u8,
+        };
+
+        match info.opcode {
+            Opcode::Lui => Instruction::Lui { rd, imm: imm_u },
+            Opcode::Auipc => Instruction::Auipc { rd, imm: imm_u },
+            Opcode::Jal => Instruction::Jal { rd, imm: imm_j },
+            Opcode::Jalr => Instruction::Jalr {
+                rd,
+                rs1,
+                imm: imm_i,
+            },
+
+            Opcode::Beq => Instruction::Beq {
+                rs1,
+                rs2,
+                imm: imm_b,
+            },
+            Opcode::Bne => Instruction::Bne {
+                rs1,
+                rs2,
+                imm: imm_b,
+            },
+            Opcode::Blt => Instruction::Blt {
+                rs1,
+                rs2,
+                imm: imm_b,
+            },
+            Opcode::Bge => Instruction::Bge {
+                rs1,
+                rs2,
+                imm: imm_b,
+            },
+            Opcode::Bltu => Instruction::Bltu {
+                rs1,
+                rs2,
+                imm: imm_b,
+            },
+            Opcode::Bgeu => Instruction::Bgeu {
+                rs1,
+                rs2,
+                imm: imm_b,
+            },
+
+            Opcode::Lb => Instruction::Lb {
+                rd,
+                rs1,
+                imm: imm_i,
+            },
+            Opcode::Lh => Instruction::Lh {
+                rd,
+                rs1,
+                imm: imm_i,
+            },
+            Opcode::Lw => Instruction::Lw {
+                rd,
+                rs1,
+                imm: imm_i,
+            },
+            Opcode::Lbu => Instruction::Lbu {
+                rd,
+                rs1,
+                imm: imm_i,
+            },
+            Opcode::Lhu => Instruction::Lhu {
+                rd,
+                rs1,
+                imm: imm_i,
+            },
+
+            Opcode::Sb => Instruction::Sb {
+                rs1,
+                rs2,
+                imm: imm_s,
+            },
+            Opcode::Sh => Instruction::Sh {
+                rs1,
+                rs2,
+                imm: imm_s,
+            },
+            Opcode::Sw => Instruction::Sw {
+                rs1,
+                rs2,
+                imm: imm_s,
+            },
+
+            Opcode::Addi => Instruction::Addi {
+                rd,
+                rs1,
+                imm: imm_i,
+            },
+            Opcode::Slti => Instruction::Slti {
+                rd,
+                rs1,
+                imm: imm_i,
+            },
+            Opcode::Sltiu => Instruction::Sltiu {
+                rd,
+                rs1,
+                imm: imm_i,
+            },
+            Opcode::Xori => Instruction::Xori {
+                rd,
+                rs1,
+                imm: imm_i,
+            },
+            Opcode::Ori => Instruction::Ori {
+                rd,
+                rs1,
+                imm: imm_i,
+            },
+            Opcode::Andi => Instruction::Andi {
+                rd,
+                rs1,
+                imm: imm_i,
+            },
+            Opcode::Slli => Instruction::Slli { rd, rs1, shamt },
+            Opcode::Srli => Instruction::Srli { rd, rs1, shamt },
+            Opcode::Srai => Instruction::Srai { rd, rs1, shamt },
+
+            Opcode::Add => Instruction::Add { rd, rs1, rs2 },
+            Opcode::Sub => Instruction::Sub { rd, rs1, rs2 },
+            Opcode::Sll => Instruction::Sll { rd, rs1, rs2 },
+            Opcode::Slt => Instruction::Slt { rd, rs1, rs2 },
+            Opcode::Sltu => Instruction::Sltu { rd, rs1, rs2 },
+            Opcode::Xor => Instruction::Xor { rd, rs1, rs2 },
+            Opcode::Srl => Instruction::Srl { rd, rs1, rs2 },
+            Opcode::Sra => Instruction::Sra { rd, rs1, rs2 },
+            Opcode::Or => Instruction::Or { rd, rs1, rs2 },
+            Opcode::And => Instruction::And { rd, rs1, rs2 },
+
+            Opcode::Fence => Instruction::Fence {
+                pred: Field::Pred.extract(inst) as u8,
+                succ: Field::Succ.extract(inst) as u8,
+            },
+            Opcode::FenceI => Instruction::FenceI,
+            Opcode::Ecall => Instruction::Ecall,
+            Opcode::Ebreak => Instruction::Ebreak,
+            Opcode::Wfi => Instruction::Wfi,
+            Opcode::Mret => Instruction::Mret,
+            Opcode::Sret => Instruction::Sret,
+            Opcode::SfenceVma => Instruction::SfenceVma { rs1, rs2 },
+
+            Opcode::HfenceVvma => Instruction::HfenceVvma { rs1, rs2 },
+            Opcode::HfenceGvma => Instruction::HfenceGvma { rs1, rs2 },
+            Opcode::HlvB => Instruction::HlvB { rd, rs1 },
+            Opcode::HlvBu => Instruction::HlvBu { rd, rs1 },
+            Opcode::HlvH => Instruction::HlvH { rd, rs1 },
+            Opcode::HlvHu => Instruction::HlvHu { rd, rs1 },
+            Opcode::HlvxHu => Instruction::HlvxHu { rd, rs1 },
+            Opcode::HlvW => Instruction::HlvW { rd, rs1 },
+            Opcode::HlvxWu => Instruction::HlvxWu { rd, rs1 },
+            Opcode::HsvB => Instruction::HsvB { rs1, rs2 },
+            Opcode::HsvH => Instruction::HsvH { rs1, rs2 },
+            Opcode::HsvW => Instruction::HsvW { rs1, rs2 },
+
+            Opcode::Csrrw => Instruction::Csrrw { rd, rs1, csr },
+            Opcode::Csrrs => Instruction::Csrrs { rd, rs1, csr },
+            Opcode::Csrrc => Instruction::Csrrc { rd, rs1, csr },
+            Opcode::Csrrwi => Instruction::Csrrwi { rd, zimm, csr },
+            Opcode::Csrrsi => Instruction::Csrrsi { rd, zimm, csr },
+            Opcode::Csrrci => Instruction::Csrrci { rd, zimm, csr },
+
+            Opcode::Mul => Instruction::Mul { rd, rs1, rs2 },
+            Opcode::Mulh => Instruction::Mulh { rd, rs1, rs2 },
+            Opcode::Mulhsu => Instruction::Mulhsu { rd, rs1, rs2 },
+            Opcode::Mulhu => Instruction::Mulhu { rd, rs1, rs2 },
+            Opcode::Div => Instruction::Div { rd, rs1, rs2 },
+            Opcode::Divu => Instruction::Divu { rd, rs1, rs2 },
+            Opcode::Rem => Instruction::Rem { rd, rs1, rs2 },
+            Opcode::Remu => Instruction::Remu { rd, rs1, rs2 },
+
+            Opcode::LrW => Instruction::LrW { rd, rs1, aqrl },
+            Opcode::ScW => Instruction::ScW { rd, rs1, rs2, aqrl },
+            Opcode::AmoswapW => Instruction::AmoswapW { rd, rs1, rs2, aqrl },
+            Opcode::AmoaddW => Instruction::AmoaddW { rd, rs1, rs2, aqrl },
+            Opcode::AmoxorW => Instruction::AmoxorW { rd, rs1, rs2, aqrl },
+            Opcode::AmoandW => Instruction::AmoandW { rd, rs1, rs2, aqrl },
+            Opcode::AmoorW => Instruction::AmoorW { rd, rs1, rs2, aqrl },
+            Opcode::AmominW => Instruction::AmominW { rd, rs1, rs2, aqrl },
+            Opcode::AmomaxW => Instruction::AmomaxW { rd, rs1, rs2, aqrl },
+            Opcode::AmominuW => Instruction::AmominuW { rd, rs1, rs2, aqrl },
+            Opcode::AmomaxuW => Instruction::AmomaxuW { rd, rs1, rs2, aqrl },
+            Opcode::AmocasW => Instruction::AmocasW { rd, rs1, rs2, aqrl },
+            Opcode::WrsNto => Instruction::WrsNto,
+            Opcode::WrsSto => Instruction::WrsSto,
+
+            Opcode::FaddS => Instruction::FaddS { rd, rs1, rs2, rm },
+            Opcode::FsubS => Instruction::FsubS { rd, rs1, rs2, rm },
+            Opcode::FmulS => Instruction::FmulS { rd, rs1, rs2, rm },
+            Opcode::FdivS => Instruction::FdivS { rd, rs1, rs2, rm },
+            Opcode::FsqrtS => Instruction::FsqrtS { rd, rs1, rm },
+            Opcode::FsgnjS => Instruction::FsgnjS { rd, rs1, rs2 },
+            Opcode::FsgnjnS => Instruction::FsgnjnS { rd, rs1, rs2 },
+            Opcode::FsgnjxS => Instruction::FsgnjxS { rd, rs1, rs2 },
+            Opcode::FminS => Instruction::FminS { rd, rs1, rs2 },
+            Opcode::FmaxS => Instruction::FmaxS { rd, rs1, rs2 },
+            Opcode::FleS => Instruction::FleS { rd, rs1, rs2 },
+            Opcode::FltS => Instruction::FltS { rd, rs1, rs2 },
+            Opcode::FeqS => Instruction::FeqS { rd, rs1, rs2 },
+            Opcode::FcvtWS => Instruction::FcvtWS { rd, rs1, rm },
+            Opcode::FcvtWuS => Instruction::FcvtWuS { rd, rs1, rm },
+            Opcode::FcvtSW => Instruction::FcvtSW { rd, rs1, rm },
+            Opcode::FcvtSWu => Instruction::FcvtSWu { rd, rs1, rm },
+            Opcode::FmvXW => Instruction::FmvSW { rd, rs1 },
+            Opcode::FmvWX => Instruction::FmvWS { rd, rs1 },
+            Opcode::FclassS => Instruction::FclassS { rd, rs1 },
+            Opcode::Flw => Instruction::Flw {
+                rd,
+                rs1,
+                imm: imm_i,
+            },
+            Opcode::Fsw => Instruction::Fsw {
+                rs1,
+                rs2,
+                imm: imm_s,
+            },
+            Opcode::FmaddS => Instruction::FmaddS {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            },
+            Opcode::FmsubS => Instruction::FmsubS {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            },
+            Opcode::FnmsubS => Instruction::FnmsubS {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            },
+            Opcode::FnmaddS => Instruction::FnmaddS {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            },
+
+            Opcode::FaddD => Instruction::FaddD { rd, rs1, rs2, rm },
+            Opcode::FsubD => Instruction::FsubD { rd, rs1, rs2, rm },
+            Opcode::FmulD => Instruction::FmulD { rd, rs1, rs2, rm },
+            Opcode::FdivD => Instruction::FdivD { rd, rs1, rs2, rm },
+            Opcode::FsqrtD => Instruction::FsqrtD { rd, rs1, rm },
+            Opcode::FsgnjD => Instruction::FsgnjD { rd, rs1, rs2 },
+            Opcode::FsgnjnD => Instruction::FsgnjnD { rd, rs1, rs2 },
+            Opcode::FsgnjxD => Instruction::FsgnjxD { rd, rs1, rs2 },
+            Opcode::FminD => Instruction::FminD { rd, rs1, rs2 },
+            Opcode::FmaxD => Instruction::FmaxD { rd, rs1, rs2 },
+            Opcode::FleD => Instruction::FleD { rd, rs1, rs2 },
+            Opcode::FltD => Instruction::FltD { rd, rs1, rs2 },
+            Opcode::FeqD => Instruction::FeqD { rd, rs1, rs2 },
+            Opcode::FcvtSD => Instruction::FcvtSD { rd, rs1, rm },
+            Opcode::FcvtDS => Instruction::FcvtDS { rd, rs1, rm },
+            Opcode::FcvtWD => Instruction::FcvtWD { rd, rs1, rm },
+            Opcode::FcvtWuD => Instruction::FcvtWuD { rd, rs1, rm },
+            Opcode::FcvtDW => Instruction::FcvtDW { rd, rs1, rm },
+            Opcode::FcvtDWu => Instruction::FcvtDWu { rd, rs1, rm },
+            Opcode::FclassD => Instruction::FclassD { rd, rs1 },
+            Opcode::FmvXD => Instruction::FmvXD { rd, rs1 },
+            Opcode::FmvDX => Instruction::FmvDX { rd, rs1 },
+            Opcode::Fld => Instruction::Fld {
+                rd,
+                rs1,
+                imm: imm_i,
+            },
+            Opcode::Fsd => Instruction::Fsd {
+                rs1,
+                rs2,
+                imm: imm_s,
+            },
+            Opcode::FmaddD => Instruction::FmaddD {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            },
+            Opcode::FmsubD => Instruction::FmsubD {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            },
+            Opcode::FnmsubD => Instruction::FnmsubD {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            },
+            Opcode::FnmaddD => Instruction::FnmaddD {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            },
+
+            Opcode::FliS => Instruction::FliS { rd, idx: rs1 },
+            Opcode::FliD => Instruction::FliD { rd, idx: rs1 },
+            Opcode::FminmS => Instruction::FminmS { rd, rs1, rs2 },
+            Opcode::FmaxmS => Instruction::FmaxmS { rd, rs1, rs2 },
+            Opcode::FminmD => Instruction::FminmD { rd, rs1, rs2 },
+            Opcode::FmaxmD => Instruction::FmaxmD { rd, rs1, rs2 },
+            Opcode::FroundS => Instruction::FroundS { rd, rs1, rm },
+            Opcode::FroundnxS => Instruction::FroundnxS { rd, rs1, rm },
+            Opcode::FroundD => Instruction::FroundD { rd, rs1, rm },
+            Opcode::FroundnxD => Instruction::FroundnxD { rd, rs1, rm },
+            Opcode::FcvtmodWD => Instruction::FcvtmodWD { rd, rs1 },
+        }
+    }
+}
+
+impl Instruction {
+    /// Whether this instruction needs a higher privilege level than user mode
+    pub fn is_privileged(&self) -> bool {
+        use Instruction::*;
+
+        matches!(
+            self,
+            Mret | Sret
+                | SfenceVma { .. }
+                | HfenceVvma { .. }
+                | HfenceGvma { .. }
+                | HlvB { .. }
+                | HlvBu { .. }
+                | HlvH { .. }
+                | HlvHu { .. }
+                | HlvxHu { .. }
+                | HlvW { .. }
+                | HlvxWu { .. }
+                | HsvB { .. }
+                | HsvH { .. }
+                | HsvW { .. }
+        )
+    }
+
+    /// Whether this is an `lr.w`, `sc.w` or AMO, all of which access the word
+    /// at `rs1`
+    pub fn is_atomic(&self) -> bool {
+        use Instruction::*;
+
+        matches!(
+            self,
+            LrW { .. }
+                | ScW { .. }
+                | AmoswapW { .. }
+                | AmoaddW { .. }
+                | AmoxorW { .. }
+                | AmoandW { .. }
+                | AmoorW { .. }
+                | AmominW { .. }
+                | AmomaxW { .. }
+                | AmominuW { .. }
+                | AmomaxuW { .. }
+                | AmocasW { .. }
+        )
+    }
+
+    /// The csr accessed by this instruction, if any
+    pub fn csr(&self) -> Option<u16> {
+        use Instruction::*;
+
+        match *self {
+            Csrrw { csr, .. }
+            | Csrrs { csr, .. }
+            | Csrrc { csr, .. }
+            | Csrrwi { csr, .. }
+            | Csrrsi { csr, .. }
+            | Csrrci { csr, .. } => Some(csr),
+            _ => None,
+        }
+    }
+
+    /// The integer registers read by this instruction
+    pub fn gp_sources(&self) -> [Option<u8>; 3] {
+        use Instruction::*;
+
+        match *self {
+            Beq { rs1, rs2, .. }
+            | Bne { rs1, rs2, .. }
+            | Blt { rs1, rs2, .. }
+            | Bge { rs1, rs2, .. }
+            | Bltu { rs1, rs2, .. }
+            | Bgeu { rs1, rs2, .. }
+            | Sb { rs1, rs2, .. }
+            | Sh { rs1, rs2, .. }
+            | Sw { rs1, rs2, .. }
+            | Add { rs1, rs2, .. }
+            | Sub { rs1, rs2, .. }
+            | Sll { rs1, rs2, .. }
+            | Slt { rs1, rs2, .. }
+            | Sltu { rs1, rs2, .. }
+            | Xor { rs1, rs2, .. }
+            | Srl { rs1, rs2, .. }
+            | Sra { rs1, rs2, .. }
+            | Or { rs1, rs2, .. }
+            | And { rs1, rs2, .. }
+            | Mul { rs1, rs2, .. }
+            | Mulh { rs1, rs2, .. }
+            | Mulhsu { rs1, rs2, .. }
+            | Mulhu { rs1, rs2, .. }
+            | Div { rs1, rs2, .. }
+            | Divu { rs1, rs2, .. }
+            | Rem { rs1, rs2, .. }
+            | Remu { rs1, rs2, .. }
+            | ScW { rs1, rs2, .. }
+            | AmoswapW { rs1, rs2, .. }
+            | AmoaddW { rs1, rs2, .. }
+            | AmoxorW { rs1, rs2, .. }
+            | AmoandW { rs1, rs2, .. }
+            | AmoorW { rs1, rs2, .. }
+            | AmominW { rs1, rs2, .. }
+            | AmomaxW { rs1, rs2, .. }
+            | AmominuW { rs1, rs2, .. }
+            | AmomaxuW { rs1, rs2, .. }
+            | SfenceVma { rs1, rs2 }
+            | HfenceVvma { rs1, rs2 }
+            | HfenceGvma { rs1, rs2 }
+            | HsvB { rs1, rs2 }
+            | HsvH { rs1, rs2 }
+            | HsvW { rs1, rs2 } => [Some(rs1), Some(rs2), None],
+            AmocasW { rd, rs1, rs2, .. } => [Some(rs1), Some(rs2), Some(rd)],
+            Jalr { rs1, .. }
+            | HlvB { rs1, .. }
+            | HlvBu { rs1, .. }
+            | HlvH { rs1, .. }
+            | HlvHu { rs1, .. }
+            | HlvxHu { rs1, .. }
+            | HlvW { rs1, .. }
+            | HlvxWu { rs1, .. }
+            | Lb { rs1, .. }
+            | Lh { rs1, .. }
+            | Lw { rs1, .. }
+            | Lbu { rs1, .. }
+            | Lhu { rs1, .. }
+            | Addi { rs1, .. }
+            | Slti { rs1, .. }
+            | Sltiu { rs1, .. }
+            | Xori { rs1, .. }
+            | Ori { rs1, .. }
+            | Andi { rs1, .. }
+            | Slli { rs1, .. }
+            | Srli { rs1, .. }
+            | Srai { rs1, .. }
+            | Csrrw { rs1, .. }
+            | Csrrs { rs1, .. }
+            | Csrrc { rs1, .. }
+            | Flw { rs1, .. }
+            | Fld { rs1, .. }
+            | Fsw { rs1, .. }
+            | Fsd { rs1, .. }
+            | FmvWS { rs1, .. }
+            | FmvDX { rs1, .. }
+            | FcvtSW { rs1, .. }
+            | FcvtSWu { rs1, .. }
+            | FcvtDW { rs1, .. }
+            | FcvtDWu { rs1, .. }
+            | LrW { rs1, .. } => [Some(rs1), None, None],
+            _ => [None, None, None],
+        }
+    }
+
+    /// The fp registers read by this instruction
+    pub fn fp_sources(&self) -> [Option<u8>; 3] {
+        use Instruction::*;
+
+        match *self {
+            FmaddS { rs1, rs2, rs3, .. }
+            | FmsubS { rs1, rs2, rs3, .. }
+            | FnmaddS { rs1, rs2, rs3, .. }
+            | FnmsubS { rs1, rs2, rs3, .. }
+            | FmaddD { rs1, rs2, rs3, .. }
+            | FmsubD { rs1, rs2, rs3, .. }
+            | FnmaddD { rs1, rs2, rs3, .. }
+            | FnmsubD { rs1, rs2, rs3, .. } => [Some(rs1), Some(rs2), Some(rs3)],
+            FaddS { rs1, rs2, .. }
+            | FsubS { rs1, rs2, .. }
+            | FmulS { rs1, rs2, .. }
+            | FdivS { rs1, rs2, .. }
+            | FsgnjS { rs1, rs2, .. }
+            | FsgnjnS { rs1, rs2, .. }
+            | FsgnjxS { rs1, rs2, .. }
+            | FminS { rs1, rs2, .. }
+            | FmaxS { rs1, rs2, .. }
+            | FaddD { rs1, rs2, .. }
+            | FsubD { rs1, rs2, .. }
+            | FmulD { rs1, rs2, .. }
+            | FdivD { rs1, rs2, .. }
+            | FsgnjD { rs1, rs2, .. }
+            | FsgnjnD { rs1, rs2, .. }
+            | FsgnjxD { rs1, rs2, .. }
+            | FminD { rs1, rs2, .. }
+            | FmaxD { rs1, rs2, .. }
+            | FminmS { rs1, rs2, .. }
+            | FmaxmS { rs1, rs2, .. }
+            | FminmD { rs1, rs2, .. }
+            | FmaxmD { rs1, rs2, .. }
+            | FeqS { rs1, rs2, .. }
+            | FltS { rs1, rs2, .. }
+            | FleS { rs1, rs2, .. }
+            | FeqD { rs1, rs2, .. }
+            | FltD { rs1, rs2, .. }
+            | FleD { rs1, rs2, .. } => [Some(rs1), Some(rs2), None],
+            FsqrtS { rs1, .. }
+            | FsqrtD { rs1, .. }
+            | FmvSW { rs1, .. }
+            | FmvXD { rs1, .. }
+            | FclassS { rs1, .. }
+            | FclassD { rs1, .. }
+            | FcvtWS { rs1, .. }
+            | FcvtWuS { rs1, .. }
+            | FcvtWD { rs1, .. }
+            | FcvtWuD { rs1, .. }
+            | FcvtSD { rs1, .. }
+            | FcvtDS { rs1, .. }
+            | FroundS { rs1, .. }
+            | FroundnxS { rs1, .. }
+            | FroundD { rs1, .. }
+            | FroundnxD { rs1, .. }
+            | FcvtmodWD { rs1, .. } => [Some(rs1), None, None],
+            Fsw { rs2, .. } | Fsd { rs2, .. } => [Some(rs2), None, None],
+            _ => [None, None, None],
+        }
+    }
+
+    /// The integer register written by this instruction, if any
+    pub fn gp_dest(&self) -> Option<u8> {
+        use Instruction::*;
+
+        match *self {
+            Lui { rd, .. }
+            | Auipc { rd, .. }
+            | Jal { rd, .. }
+            | Jalr { rd, .. }
+            | HlvB { rd, .. }
+            | HlvBu { rd, .. }
+            | HlvH { rd, .. }
+            | HlvHu { rd, .. }
+            | HlvxHu { rd, .. }
+            | HlvW { rd, .. }
+            | HlvxWu { rd, .. }
+            | Lb { rd, .. }
+            | Lh { rd, .. }
+            | Lw { rd, .. }
+            | Lbu { rd, .. }
+            | Lhu { rd, .. }
+            | Addi { rd, .. }
+            | Slti { rd, .. }
+            | Sltiu { rd, .. }
+            | Xori { rd, .. }
+            | Ori { rd, .. }
+            | Andi { rd, .. }
+            | Slli { rd, .. }
+            | Srli { rd, .. }
+            | Srai { rd, .. }
+            | Add { rd, .. }
+            | Sub { rd, .. }
+            | Sll { rd, .. }
+            | Slt { rd, .. }
+            | Sltu { rd, .. }
+            | Xor { rd, .. }
+            | Srl { rd, .. }
+            | Sra { rd, .. }
+            | Or { rd, .. }
+            | And { rd, .. }
+            | Csrrw { rd, .. }
+            | Csrrs { rd, .. }
+            | Csrrc { rd, .. }
+            | Csrrwi { rd, .. }
+            | Csrrsi { rd, .. }
+            | Csrrci { rd, .. }
+            | Mul { rd, .. }
+            | Mulh { rd, .. }
+            | Mulhsu { rd, .. }
+            | Mulhu { rd, .. }
+            | Div { rd, .. }
+            | Divu { rd, .. }
+            | Rem { rd, .. }
+            | Remu { rd, .. }
+            | LrW { rd, .. }
+            | ScW { rd, .. }
+            | AmoswapW { rd, .. }
+            | AmoaddW { rd, .. }
+            | AmoxorW { rd, .. }
+            | AmoandW { rd, .. }
+            | AmoorW { rd, .. }
+            | AmominW { rd, .. }
+            | AmomaxW { rd, .. }
+            | AmominuW { rd, .. }
+            | AmomaxuW { rd, .. }
+            | AmocasW { rd, .. }
+            | FmvSW { rd, .. }
+            | FmvXD { rd, .. }
+            | FclassS { rd, .. }
+            | FclassD { rd, .. }
+            | FcvtWS { rd, .. }
+            | FcvtWuS { rd, .. }
+            | FcvtWD { rd, .. }
+            | FcvtWuD { rd, .. }
+            | FcvtmodWD { rd, .. }
+            | FeqS { rd, .. }
+            | FltS { rd, .. }
+            | FleS { rd, .. }
+            | FeqD { rd, .. }
+            | FltD { rd, .. }
+            | FleD { rd, .. } => Some(rd),
+            _ => None,
+        }
+    }
+
+    /// The fp register written by this instruction, if any, and the width written
+    pub fn fp_dest(&self) -> Option<(u8, FpWidth)> {
+        use Instruction::*;
+
+        match *self {
+            FaddS { rd, .. }
+            | FsubS { rd, .. }
+            | FmulS { rd, .. }
+            | FdivS { rd, .. }
+            | FmaddS { rd, .. }
+            | FmsubS { rd, .. }
+            | FnmaddS { rd, .. }
+            | FnmsubS { rd, .. }
+            | FsqrtS { rd, .. }
+            | FsgnjS { rd, .. }
+            | FsgnjnS { rd, .. }
+            | FsgnjxS { rd, .. }
+            | FminS { rd, .. }
+            | FmaxS { rd, .. }
+            | FmvWS { rd, .. }
+            | FcvtSW { rd, .. }
+            | FcvtSWu { rd, .. }
+            | FcvtSD { rd, .. }
+            | Flw { rd, .. }
+            | FliS { rd, .. }
+            | FminmS { rd, .. }
+            | FmaxmS { rd, .. }
+            | FroundS { rd, .. }
+            | FroundnxS { rd, .. } => Some((rd, FpWidth::Single)),
+
+            FaddD { rd, .. }
+            | FsubD { rd, .. }
+            | FmulD { rd, .. }
+            | FdivD { rd, .. }
+            | FmaddD { rd, .. }
+            | FmsubD { rd, .. }
+            | FnmaddD { rd, .. }
+            | FnmsubD { rd, .. }
+            | FsqrtD { rd, .. }
+            | FsgnjD { rd, .. }
+            | FsgnjnD { rd, .. }
+            | FsgnjxD { rd, .. }
+            | FminD { rd, .. }
+            | FmaxD { rd, .. }
+            | FmvDX { rd, .. }
+            | FcvtDW { rd, .. }
+            | FcvtDWu { rd, .. }
+            | FcvtDS { rd, .. }
+            | Fld { rd, .. }
+            | FliD { rd, .. }
+            | FminmD { rd, .. }
+            | FmaxmD { rd, .. }
+            | FroundD { rd, .. }
+            | FroundnxD { rd, .. } => Some((rd, FpWidth::Double)),
+
+            _ => None,
+        }
+    }
+}
+
+// explicit rounding modes are printed, the dynamic one is implied
+fn rm_suffix(rm: u8) -> &'static str {
+    match rm {
+        0b000 => ", rne",
+        0b001 => ", rtz",
+        0b010 => ", rdn",
+        0b011 => ", rup",
+        0b100 => ", rmm",
+        0b111 => "",
+        _ => ", <reserved>",
+    }
+}
+
+// as the assembler takes them, by value or name
+fn fli_operand(idx: u8) -> String {
+    match idx {
+        1 => "min".to_string(),
+        30 => "inf".to_string(),
+        31 => "nan".to_string(),
+        _ => format!("{:?}", FLI_CONSTANTS[idx as usize]),
+    }
+}
+
+fn aqrl_suffix(aqrl: u8) -> &'static str {
+    match aqrl {
+        0b00 => "",
+        0b01 => ".rl",
+        0b10 => ".aq",
+        _ => ".aqrl",
+    }
+}
+
+// unimplemented csrs are printed by number, but for the hypervisor's
+pub(crate) fn csr_name(csr: u16) -> String {
+    match (csr::name(csr), csr::hpm_counter(csr)) {
+        (Some(name), _) => name.to_string(),
+        (None, Some((counter, false))) => format!("hpmcounter{counter}"),
+        (None, Some((counter, true))) => format!("hpmcounter{counter}h"),
+        (None, None) => match csr::hypervisor_name(csr) {
+            Some(name) => name.to_string(),
+            None => format!("{csr:#x}"),
+        },
+    }
+}
+
+fn fence_set(bits: u8) -> String {
+    let set: String = [(8, 'i'), (4, 'o'), (2, 'r'), (1, 'w')]
+        .iter()
+        .filter(|(bit, _)| bits & bit != 0)
+        .map(|(_, c)| c)
+        .collect();
+
+    if set.is_empty() {
+        "0".to_string()
+    } else {
+        set
+    }
+}
+
+/// Disassembly using ABI register names, e.g. `addi sp, sp, -16`
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instruction::*;
+
+        let x = Register::gp;
+        let fr = Register::fp;
+
+        match *self {
+            Unknown(inst) => write!(f, ".word {inst:#010x}"),
+            Custom { opcode, inst } => write!(f, "{opcode} {inst:#010x}"),
+
+            Lui { rd, imm } => write!(f, "lui {}, {:#x}", x(rd), (imm as u32) >> 12),
+            Auipc { rd, imm } => write!(f, "auipc {}, {:#x}", x(rd), (imm as u32) >> 12),
+            Jal { rd, imm } => write!(f, "jal {}, {imm}", x(rd)),
+            Jalr { rd, rs1, imm } => write!(f, "jalr {}, {imm}({})", x(rd), x(rs1)),
+
+            Beq { rs1, rs2, imm } => write!(f, "beq {}, {}, {imm}", x(rs1), x(rs2)),
+            Bne { rs1, rs2, imm } => write!(f, "bne {}, {}, {imm}", x(rs1), x(rs2)),
+            Blt { rs1, rs2, imm } => write!(f, "blt {}, {}, {imm}", x(rs1), x(rs2)),
+            Bge { rs1, rs2, imm } => write!(f, "bge {}, {}, {imm}", x(rs1), x(rs2)),
+            Bltu { rs1, rs2, imm } => write!(f, "bltu {}, {}, {imm}", x(rs1), x(rs2)),
+            Bgeu { rs1, rs2, imm } => write!(f, "bgeu {}, {}, {imm}", x(rs1), x(rs2)),
+
+            Lb { rd, rs1, imm } => write!(f, "lb {}, {imm}({})", x(rd), x(rs1)),
+            Lh { rd, rs1, imm } => write!(f, "lh {}, {imm}({})", x(rd), x(rs1)),
+            Lw { rd, rs1, imm } => write!(f, "lw {}, {imm}({})", x(rd), x(rs1)),
+            Lbu { rd, rs1, imm } => write!(f, "lbu {}, {imm}({})", x(rd), x(rs1)),
+            Lhu { rd, rs1, imm } => write!(f, "lhu {}, {imm}({})", x(rd), x(rs1)),
+            Sb { rs1, rs2, imm } => write!(f, "sb {}, {imm}({})", x(rs2), x(rs1)),
+            Sh { rs1, rs2, imm } => write!(f, "sh {}, {imm}({})", x(rs2), x(rs1)),
+            Sw { rs1, rs2, imm } => write!(f, "sw {}, {imm}({})", x(rs2), x(rs1)),
+
+            Addi { rd, rs1, imm } => write!(f, "addi {}, {}, {imm}", x(rd), x(rs1)),
+            Slti { rd, rs1, imm } => write!(f, "slti {}, {}, {imm}", x(rd), x(rs1)),
+            Sltiu { rd, rs1, imm } => write!(f, "sltiu {}, {}, {imm}", x(rd), x(rs1)),
+            Xori { rd, rs1, imm } => write!(f, "xori {}, {}, {imm}", x(rd), x(rs1)),
+            Ori { rd, rs1, imm } => write!(f, "ori {}, {}, {imm}", x(rd), x(rs1)),
+            Andi { rd, rs1, imm } => write!(f, "andi {}, {}, {imm}", x(rd), x(rs1)),
+            Slli { rd, rs1, shamt } => write!(f, "slli {}, {}, {shamt}", x(rd), x(rs1)),
+            Srli { rd, rs1, shamt } => write!(f, "srli {}, {}, {shamt}", x(rd), x(rs1)),
+            Srai { rd, rs1, shamt } => write!(f, "srai {}, {}, {shamt}", x(rd), x(rs1)),
+
+            Add { rd, rs1, rs2 } => write!(f, "add {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            Sub { rd, rs1, rs2 } => write!(f, "sub {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            Sll { rd, rs1, rs2 } => write!(f, "sll {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            Slt { rd, rs1, rs2 } => write!(f, "slt {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            Sltu { rd, rs1, rs2 } => write!(f, "sltu {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            Xor { rd, rs1, rs2 } => write!(f, "xor {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            Srl { rd, rs1, rs2 } => write!(f, "srl {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            Sra { rd, rs1, rs2 } => write!(f, "sra {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            Or { rd, rs1, rs2 } => write!(f, "or {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            And { rd, rs1, rs2 } => write!(f, "and {}, {}, {}", x(rd), x(rs1), x(rs2)),
+
+            Fence { pred, succ } => write!(f, "fence {}, {}", fence_set(pred), fence_set(succ)),
+            FenceI => write!(f, "fence.i"),
+            Ecall => write!(f, "ecall"),
+            Ebreak => write!(f, "ebreak"),
+            Wfi => write!(f, "wfi"),
+            Mret => write!(f, "mret"),
+            Sret => write!(f, "sret"),
+            SfenceVma { rs1, rs2 } => write!(f, "sfence.vma {}, {}", x(rs1), x(rs2)),
+            HfenceVvma { rs1, rs2 } => write!(f, "hfence.vvma {}, {}", x(rs1), x(rs2)),
+            HfenceGvma { rs1, rs2 } => write!(f, "hfence.gvma {}, {}", x(rs1), x(rs2)),
+            HlvB { rd, rs1 } => write!(f, "hlv.b {}, ({})", x(rd), x(rs1)),
+            HlvBu { rd, rs1 } => write!(f, "hlv.bu {}, ({})", x(rd), x(rs1)),
+            HlvH { rd, rs1 } => write!(f, "hlv.h {}, ({})", x(rd), x(rs1)),
+            HlvHu { rd, rs1 } => write!(f, "hlv.hu {}, ({})", x(rd), x(rs1)),
+            HlvxHu { rd, rs1 } => write!(f, "hlvx.hu {}, ({})", x(rd), x(rs1)),
+            HlvW { rd, rs1 } => write!(f, "hlv.w {}, ({})", x(rd), x(rs1)),
+            HlvxWu { rd, rs1 } => write!(f, "hlvx.wu {}, ({})", x(rd), x(rs1)),
+            HsvB { rs1, rs2 } => write!(f, "hsv.b {}, ({})", x(rs2), x(rs1)),
+            HsvH { rs1, rs2 } => write!(f, "hsv.h {}, ({})", x(rs2), x(rs1)),
+            HsvW { rs1, rs2 } => write!(f, "hsv.w {}, ({})", x(rs2), x(rs1)),
+
+            Csrrw { rd, rs1, csr } => write!(f, "csrrw {}, {}, {}", x(rd), csr_name(csr), x(rs1)),
+            Csrrs { rd, rs1, csr } => write!(f, "csrrs {}, {}, {}", x(rd), csr_name(csr), x(rs1)),
+            Csrrc { rd, rs1, csr } => write!(f, "csrrc {}, {}, {}", x(rd), csr_name(csr), x(rs1)),
+            Csrrwi { rd, zimm, csr } => write!(f, "csrrwi {}, {}, {zimm}", x(rd), csr_name(csr)),
+            Csrrsi { rd, zimm, csr } => write!(f, "csrrsi {}, {}, {zimm}", x(rd), csr_name(csr)),
+            Csrrci { rd, zimm, csr } => write!(f, "csrrci {}, {}, {zimm}", x(rd), csr_name(csr)),
+
+            Mul { rd, rs1, rs2 } => write!(f, "mul {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            Mulh { rd, rs1, rs2 } => write!(f, "mulh {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            Mulhsu { rd, rs1, rs2 } => write!(f, "mulhsu {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            Mulhu { rd, rs1, rs2 } => write!(f, "mulhu {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            Div { rd, rs1, rs2 } => write!(f, "div {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            Divu { rd, rs1, rs2 } => write!(f, "divu {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            Rem { rd, rs1, rs2 } => write!(f, "rem {}, {}, {}", x(rd), x(rs1), x(rs2)),
+            Remu { rd, rs1, rs2 } => write!(f, "remu {}, {}, {}", x(rd), x(rs1), x(rs2)),
+
+            LrW { rd, rs1, aqrl } => write!(f, "lr.w{} {}, ({})", aqrl_suffix(aqrl), x(rd), x(rs1)),
+            ScW { rd, rs1, rs2, aqrl } => write!(
+                f,
+                "sc.w{} {}, {}, ({})",
+                aqrl_suffix(aqrl),
+                x(rd),
+                x(rs2),
+                x(rs1)
+            ),
+            AmoswapW { rd, rs1, rs2, aqrl } => write!(
+                f,
+                "amoswap.w{} {}, {}, ({})",
+                aqrl_suffix(aqrl),
+                x(rd),
+                x(rs2),
+                x(rs1)
+            ),
+            AmoaddW { rd, rs1, rs2, aqrl } => write!(
+                f,
+                "amoadd.w{} {}, {}, ({})",
+                aqrl_suffix(aqrl),
+                x(rd),
+                x(rs2),
+                x(rs1)
+            ),
+            AmoxorW { rd, rs1, rs2, aqrl } => write!(
+                f,
+                "amoxor.w{} {}, {}, ({})",
+                aqrl_suffix(aqrl),
+                x(rd),
+                x(rs2),
+                x(rs1)
+            ),
+            AmoandW { rd, rs1, rs2, aqrl } => write!(
+                f,
+                "amoand.w{} {}, {}, ({})",
+                aqrl_suffix(aqrl),
+                x(rd),
+                x(rs2),
+                x(rs1)
+            ),
+            AmoorW { rd, rs1, rs2, aqrl } => write!(
+                f,
+                "amoor.w{} {}, {}, ({})",
+                aqrl_suffix(aqrl),
+                x(rd),
+                x(rs2),
+                x(rs1)
+            ),
+            AmominW { rd, rs1, rs2, aqrl } => write!(
+                f,
+                "amomin.w{} {}, {}, ({})",
+                aqrl_suffix(aqrl),
+                x(rd),
+                x(rs2),
+                x(rs1)
+            ),
+            AmomaxW { rd, rs1, rs2, aqrl } => write!(
+                f,
+                "amomax.w{} {}, {}, ({})",
+                aqrl_suffix(aqrl),
+                x(rd),
+                x(rs2),
+                x(rs1)
+            ),
+            AmominuW { rd, rs1, rs2, aqrl } => write!(
+                f,
+                "amominu.w{} {}, {}, ({})",
+                aqrl_suffix(aqrl),
+                x(rd),
+                x(rs2),
+                x(rs1)
+            ),
+            AmomaxuW { rd, rs1, rs2, aqrl } => write!(
+                f,
+                "amomaxu.w{} {}, {}, ({})",
+                aqrl_suffix(aqrl),
+                x(rd),
+                x(rs2),
+                x(rs1)
+            ),
+            AmocasW { rd, rs1, rs2, aqrl } => write!(
+                f,
+                "amocas.w{} {}, {}, ({})",
+                aqrl_suffix(aqrl),
+                x(rd),
+                x(rs2),
+                x(rs1)
+            ),
+            WrsNto => write!(f, "wrs.nto"),
+            WrsSto => write!(f, "wrs.sto"),
+
+            FaddS { rd, rs1, rs2, rm } => {
+                let rm = rm_suffix(rm);
+                write!(f, "fadd.s {}, {}, {}{rm}", fr(rd), fr(rs1), fr(rs2))
             }
-            0x63 => {
-                // branch (b-type)
-                let funct3 = (inst >> 12) & 0x7;
-                let rs1 = ((inst >> 15) & 0x1f) as u8;
-                let rs2 = ((inst >> 20) & 0x1f) as u8;
-                let imm_raw = (((inst >> 31) & 0x1) << 12)
-                    | (((inst >> 7) & 0x1) << 11)
-                    | (((inst >> 25) & 0x3f) << 5)
-                    | (((inst >> 8) & 0xf) << 1);
-                let imm = sign_extend(imm_raw, 13);
-                match funct3 {
-                    0 => Instruction::Beq { rs1, rs2, imm },
-                    1 => Instruction::Bne { rs1, rs2, imm },
-                    4 => Instruction::Blt { rs1, rs2, imm },
-                    5 => Instruction::Bge { rs1, rs2, imm },
-                    6 => Instruction::Bltu { rs1, rs2, imm },
-                    7 => Instruction::Bgeu { rs1, rs2, imm },
-                    _ => Instruction::Unknown(inst),
-                }
+            FsubS { rd, rs1, rs2, rm } => {
+                let rm = rm_suffix(rm);
+                write!(f, "fsub.s {}, {}, {}{rm}", fr(rd), fr(rs1), fr(rs2))
             }
-            0x03 => {
-                // load (i-type)
-                let funct3 = (inst >> 12) & 0x7;
-                let rd = ((inst >> 7) & 0x1f) as u8;
-                let rs1 = ((inst >> 15) & 0x1f) as u8;
-                let imm = sign_extend((inst >> 20) & 0xfff, 12);
-                match funct3 {
-                    0 => Instruction::Lb { rd, rs1, imm },
-                    1 => Instruction::Lh { rd, rs1, imm },
-                    2 => Instruction::Lw { rd, rs1, imm },
-                    4 => Instruction::Lbu { rd, rs1, imm },
-                    5 => Instruction::Lhu { rd, rs1, imm },
-                    _ => Instruction::Unknown(inst),
-                }
+            FmulS { rd, rs1, rs2, rm } => {
+                let rm = rm_suffix(rm);
+                write!(f, "fmul.s {}, {}, {}{rm}", fr(rd), fr(rs1), fr(rs2))
             }
-            0x23 => {
-                // store (s-type)
-                let funct3 = (inst >> 12) & 0x7;
-                let rs1 = ((inst >> 15) & 0x1f) as u8;
-                let rs2 = ((inst >> 20) & 0x1f) as u8;
-                let imm_raw = (((inst >> 25) & 0x7f) << 5) | ((inst >> 7) & 0x1f);
-                let imm = sign_extend(imm_raw, 12);
-                match funct3 {
-                    0 => Instruction::Sb { rs1, rs2, imm },
-                    1 => Instruction::Sh { rs1, rs2, imm },
-                    2 => Instruction::Sw { rs1, rs2, imm },
-                    _ => Instruction::Unknown(inst),
-                }
+            FdivS { rd, rs1, rs2, rm } => {
+                let rm = rm_suffix(rm);
+                write!(f, "fdiv.s {}, {}, {}{rm}", fr(rd), fr(rs1), fr(rs2))
             }
-            0x13 => {
-                // op-imm (i-type)
-                let funct3 = (inst >> 12) & 0x7;
-                let rd = ((inst >> 7) & 0x1f) as u8;
-                let rs1 = ((inst >> 15) & 0x1f) as u8;
-                match funct3 {
-                    0 => {
-                        let imm = sign_extend((inst >> 20) & 0xfff, 12);
-                        Instruction::Addi { rd, rs1, imm }
-                    }
-                    2 => {
-                        let imm = sign_extend((inst >> 20) & 0xfff, 12);
-                        Instruction::Slti { rd, rs1, imm }
-                    }
-                    3 => {
-                        let imm = sign_extend((inst >> 20) & 0xfff, 12);
-                        Instruction::Sltiu { rd, rs1, imm }
-                    }
-                    4 => {
-                        let imm = sign_extend((inst >> 20) & 0xfff, 12);
-                        Instruction::Xori { rd, rs1, imm }
-                    }
-                    6 => {
-                        let imm = sign_extend((inst >> 20) & 0xfff, 12);
-                        Instruction::Ori { rd, rs1, imm }
-                    }
-                    7 => {
-                        let imm = sign_extend((inst >> 20) & 0xfff, 12);
-                        Instruction::Andi { rd, rs1, imm }
-                    }
-                    1 => {
-                        // slli; ensure upper bits are 0
-                        let shamt = ((inst >> 20) & 0x1f) as u8;
-                        Instruction::Slli { rd, rs1, shamt }
-                    }
-                    5 => {
-                        // srli / srai
-                        let shamt = ((inst >> 20) & 0x1f) as u8;
-                        let funct7 = inst >> 25;
-                        if funct7 == 0 {
-                            Instruction::Srli { rd, rs1, shamt }
-                        } else if funct7 == 0x20 {
-                            Instruction::Srai { rd, rs1, shamt }
-                        } else {
-                            Instruction::Unknown(inst)
-                        }
-                    }
-                    _ => Instruction::Unknown(inst),
-                }
+            FaddD { rd, rs1, rs2, rm } => {
+                let rm = rm_suffix(rm);
+                write!(f, "fadd.d {}, {}, {}{rm}", fr(rd), fr(rs1), fr(rs2))
             }
-            0x33 => {
-                // m-extension: only when funct7==0x1
-                if funct7 == 0x1 {
-                    match funct3 {
-                        0x0 => Instruction::Mul { rd, rs1, rs2 },
-                        0x1 => Instruction::Mulh { rd, rs1, rs2 },
-                        0x2 => Instruction::Mulhsu { rd, rs1, rs2 },
-                        0x3 => Instruction::Mulhu { rd, rs1, rs2 },
-                        0x4 => Instruction::Div { rd, rs1, rs2 },
-                        0x5 => Instruction::Divu { rd, rs1, rs2 },
-                        0x6 => Instruction::Rem { rd, rs1, rs2 },
-                        0x7 => Instruction::Remu { rd, rs1, rs2 },
-                        _ => Instruction::Unknown(inst),
-                    }
-                } else {
-                    match funct3 {
-                        0 => {
-                            if funct7 == 0 {
-                                Instruction::Add { rd, rs1, rs2 }
-                            } else if funct7 == 0x20 {
-                                Instruction::Sub { rd, rs1, rs2 }
-                            } else {
-                                Instruction::Unknown(inst)
-                            }
-                        }
-                        1 => Instruction::Sll { rd, rs1, rs2 },
-                        2 => Instruction::Slt { rd, rs1, rs2 },
-                        3 => Instruction::Sltu { rd, rs1, rs2 },
-                        4 => Instruction::Xor { rd, rs1, rs2 },
-                        5 => {
-                            if funct7 == 0 {
-                                Instruction::Srl { rd, rs1, rs2 }
-                            } else if funct7 == 0x20 {
-                                Instruction::Sra { rd, rs1, rs2 }
-                            } else {
-                                Instruction::Unknown(inst)
-                            }
-                        }
-                        6 => Instruction::Or { rd, rs1, rs2 },
-                        7 => Instruction::And { rd, rs1, rs2 },
-                        _ => Instruction::Unknown(inst),
-                    }
-                }
+            FsubD { rd, rs1, rs2, rm } => {
+                let rm = rm_suffix(rm);
+                write!(f, "fsub.d {}, {}, {}{rm}", fr(rd), fr(rs1), fr(rs2))
             }
-            0x0f => {
-                // fence / fence.i
-                let funct3 = (inst >> 12) & 0x7;
-                if funct3 == 0 {
-                    let pred = ((inst >> 24) & 0xf) as u8;
-                    let succ = ((inst >> 20) & 0xf) as u8;
-                    Instruction::Fence { pred, succ }
-                } else if funct3 == 1 {
-                    Instruction::FenceI
-                } else {
-                    Instruction::Unknown(inst)
-                }
+            FmulD { rd, rs1, rs2, rm } => {
+                let rm = rm_suffix(rm);
+                write!(f, "fmul.d {}, {}, {}{rm}", fr(rd), fr(rs1), fr(rs2))
             }
-            0x73 => {
-                let funct3 = (inst >> 12) & 0x7;
-                let imm = (inst >> 20) & 0xfff;
-                match (funct3, imm) {
-                    (0b000000000000, 0b000) => Instruction::Ecall,
-                    (0b000000000000, 0b001) => Instruction::Ebreak,
-                    (0b000000000010, 0b010) => Instruction::Frrm { rd },
-                    (0b000000000010, 0b001) => Instruction::Fsrm { rd, rs1 },
-                    _ => Instruction::Unknown(inst),
-                }
+            FdivD { rd, rs1, rs2, rm } => {
+                let rm = rm_suffix(rm);
+                write!(f, "fdiv.d {}, {}, {}{rm}", fr(rd), fr(rs1), fr(rs2))
             }
-            0x53 => match funct7 {
-                // Single-precision arithmetic
-                0x00 => {
-                    let rm = ((inst >> 12) & 0x7) as u8;
-                    Instruction::FaddS { rd, rs1, rs2, rm }
-                }
 
-                0x2C if rs2 == 0 => {
-                    let rm = ((inst >> 12) & 0x7) as u8;
-                    Instruction::FsqrtS { rd, rs1, rm }
-                }
+            FmaddS {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            }
+            | FmsubS {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            }
+            | FnmaddS {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            }
+            | FnmsubS {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            }
+            | FmaddD {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            }
+            | FmsubD {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            }
+            | FnmaddD {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            }
+            | FnmsubD {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            } => {
+                let mnemonic = match self {
+                    FmaddS { .. } => "fmadd.s",
+                    FmsubS { .. } => "fmsub.s",
+                    FnmaddS { .. } => "fnmadd.s",
+                    FnmsubS { .. } => "fnmsub.s",
+                    FmaddD { .. } => "fmadd.d",
+                    FmsubD { .. } => "fmsub.d",
+                    FnmaddD { .. } => "fnmadd.d",
+                    _ => "fnmsub.d",
+                };
+                let rm = rm_suffix(rm);
+                write!(
+                    f,
+                    "{mnemonic} {}, {}, {}, {}{rm}",
+                    fr(rd),
+                    fr(rs1),
+                    fr(rs2),
+                    fr(rs3)
+                )
+            }
 
-                0x2D if rs2 == 0 => {
-                    let rm = ((inst >> 12) & 0x7) as u8;
-                    Instruction::FsqrtS { rd, rs1, rm }
-                }
+            FsqrtS { rd, rs1, rm } => {
+                let rm = rm_suffix(rm);
+                write!(f, "fsqrt.s {}, {}{rm}", fr(rd), fr(rs1))
+            }
+            FsqrtD { rd, rs1, rm } => {
+                let rm = rm_suffix(rm);
+                write!(f, "fsqrt.d {}, {}{rm}", fr(rd), fr(rs1))
+            }
 
-                0x04 => {
-                    let rm = ((inst >> 12) & 0x7) as u8;
-                    Instruction::FsubS { rd, rs1, rs2, rm }
-                }
+            FsgnjS { rd, rs1, rs2 } => write!(f, "fsgnj.s {}, {}, {}", fr(rd), fr(rs1), fr(rs2)),
+            FsgnjnS { rd, rs1, rs2 } => {
+                write!(f, "fsgnjn.s {}, {}, {}", fr(rd), fr(rs1), fr(rs2))
+            }
+            FsgnjxS { rd, rs1, rs2 } => {
+                write!(f, "fsgnjx.s {}, {}, {}", fr(rd), fr(rs1), fr(rs2))
+            }
+            FsgnjD { rd, rs1, rs2 } => write!(f, "fsgnj.d {}, {}, {}", fr(rd), fr(rs1), fr(rs2)),
+            FsgnjnD { rd, rs1, rs2 } => {
+                write!(f, "fsgnjn.d {}, {}, {}", fr(rd), fr(rs1), fr(rs2))
+            }
+            FsgnjxD { rd, rs1, rs2 } => {
+                write!(f, "fsgnjx.d {}, {}, {}", fr(rd), fr(rs1), fr(rs2))
+            }
+            FminS { rd, rs1, rs2 } => write!(f, "fmin.s {}, {}, {}", fr(rd), fr(rs1), fr(rs2)),
+            FmaxS { rd, rs1, rs2 } => write!(f, "fmax.s {}, {}, {}", fr(rd), fr(rs1), fr(rs2)),
+            FminD { rd, rs1, rs2 } => write!(f, "fmin.d {}, {}, {}", fr(rd), fr(rs1), fr(rs2)),
+            FmaxD { rd, rs1, rs2 } => write!(f, "fmax.d {}, {}, {}", fr(rd), fr(rs1), fr(rs2)),
 
-                0x08 => {
-                    let rm = ((inst >> 12) & 0x7) as u8;
-                    Instruction::FmulS { rd, rs1, rs2, rm }
-                }
+            FmvSW { rd, rs1 } => write!(f, "fmv.x.w {}, {}", x(rd), fr(rs1)),
+            FmvWS { rd, rs1 } => write!(f, "fmv.w.x {}, {}", fr(rd), x(rs1)),
+            FmvXD { rd, rs1 } => write!(f, "fmv.x.d {}, {}", x(rd), fr(rs1)),
+            FmvDX { rd, rs1 } => write!(f, "fmv.d.x {}, {}", fr(rd), x(rs1)),
+            FclassS { rd, rs1 } => write!(f, "fclass.s {}, {}", x(rd), fr(rs1)),
+            FclassD { rd, rs1 } => write!(f, "fclass.d {}, {}", x(rd), fr(rs1)),
 
-                0x0c => {
-                    let rm = ((inst >> 12) & 0x7) as u8;
-                    Instruction::FdivS { rd, rs1, rs2, rm }
-                }
+            FcvtSW { rd, rs1, rm } => {
+                write!(f, "fcvt.s.w {}, {}{}", fr(rd), x(rs1), rm_suffix(rm))
+            }
+            FcvtSWu { rd, rs1, rm } => {
+                write!(f, "fcvt.s.wu {}, {}{}", fr(rd), x(rs1), rm_suffix(rm))
+            }
+            FcvtWS { rd, rs1, rm } => {
+                write!(f, "fcvt.w.s {}, {}{}", x(rd), fr(rs1), rm_suffix(rm))
+            }
+            FcvtWuS { rd, rs1, rm } => {
+                write!(f, "fcvt.wu.s {}, {}{}", x(rd), fr(rs1), rm_suffix(rm))
+            }
+            FcvtDW { rd, rs1, rm } => {
+                write!(f, "fcvt.d.w {}, {}{}", fr(rd), x(rs1), rm_suffix(rm))
+            }
+            FcvtDWu { rd, rs1, rm } => {
+                write!(f, "fcvt.d.wu {}, {}{}", fr(rd), x(rs1), rm_suffix(rm))
+            }
+            FcvtWD { rd, rs1, rm } => {
+                write!(f, "fcvt.w.d {}, {}{}", x(rd), fr(rs1), rm_suffix(rm))
+            }
+            FcvtWuD { rd, rs1, rm } => {
+                write!(f, "fcvt.wu.d {}, {}{}", x(rd), fr(rs1), rm_suffix(rm))
+            }
+            FcvtSD { rd, rs1, rm } => {
+                write!(f, "fcvt.s.d {}, {}{}", fr(rd), fr(rs1), rm_suffix(rm))
+            }
+            FcvtDS { rd, rs1, rm } => {
+                write!(f, "fcvt.d.s {}, {}{}", fr(rd), fr(rs1), rm_suffix(rm))
+            }
 
-                // Single-precision sign-injection ops
-                0x10 => match funct3 {
-                    0x0 => Instruction::FsgnjS { rd, rs1, rs2 },
-                    0x1 => Instruction::FsgnjnS { rd, rs1, rs2 },
-                    0x2 => Instruction::FsgnjxS { rd, rs1, rs2 },
-                    _ => Instruction::Unknown(inst),
-                },
-
-                0x11 => match funct3 {
-                    0x0 => Instruction::FsgnjD { rd, rs1, rs2 },
-                    0x1 => Instruction::FsgnjnD { rd, rs1, rs2 },
-                    0x2 => Instruction::FsgnjxD { rd, rs1, rs2 },
-                    _ => Instruction::Unknown(inst),
-                },
-
-                // Single-precision min/max
-                0x14 => match funct3 {
-                    0x0 => Instruction::FminS { rd, rs1, rs2 },
-                    0x1 => Instruction::FmaxS { rd, rs1, rs2 },
-                    _ => Instruction::Unknown(inst),
-                },
-
-                0x15 => match funct3 {
-                    0x0 => Instruction::FminD { rd, rs1, rs2 },
-                    0x1 => Instruction::FmaxD { rd, rs1, rs2 },
-                    _ => Instruction::Unknown(inst),
-                },
-
-                // Single-precision comparisons
-                0x50 => match funct3 {
-                    0x0 => Instruction::FleS { rd, rs1, rs2 },
-                    0x1 => Instruction::FltS { rd, rs1, rs2 },
-                    0x2 => Instruction::FeqS { rd, rs1, rs2 },
-                    _ => Instruction::Unknown(inst),
-                },
-
-                // Double-precision arithmetic
-                0x01 => {
-                    let rm = ((inst >> 12) & 0x7) as u8;
-                    Instruction::FaddD { rd, rs1, rs2, rm }
-                }
-                0x05 => {
-                    let rm = ((inst >> 12) & 0x7) as u8;
-                    Instruction::FsubD { rd, rs1, rs2, rm }
-                }
-                0x09 => {
-                    let rm = ((inst >> 12) & 0x7) as u8;
-                    Instruction::FmulD { rd, rs1, rs2, rm }
-                }
-                0x0d => {
-                    let rm = ((inst >> 12) & 0x7) as u8;
-                    Instruction::FdivD { rd, rs1, rs2, rm }
-                }
+            FeqS { rd, rs1, rs2 } => write!(f, "feq.s {}, {}, {}", x(rd), fr(rs1), fr(rs2)),
+            FltS { rd, rs1, rs2 } => write!(f, "flt.s {}, {}, {}", x(rd), fr(rs1), fr(rs2)),
+            FleS { rd, rs1, rs2 } => write!(f, "fle.s {}, {}, {}", x(rd), fr(rs1), fr(rs2)),
+            FeqD { rd, rs1, rs2 } => write!(f, "feq.d {}, {}, {}", x(rd), fr(rs1), fr(rs2)),
+            FltD { rd, rs1, rs2 } => write!(f, "flt.d {}, {}, {}", x(rd), fr(rs1), fr(rs2)),
+            FleD { rd, rs1, rs2 } => write!(f, "fle.d {}, {}, {}", x(rd), fr(rs1), fr(rs2)),
 
-                // Double-precision comparisons
-                0x51 => match funct3 {
-                    0x0 => Instruction::FleD { rd, rs1, rs2 },
-                    0x1 => Instruction::FltD { rd, rs1, rs2 },
-                    0x2 => Instruction::FeqD { rd, rs1, rs2 },
-                    _ => Instruction::Unknown(inst),
-                },
-
-                // Conversions & moves (same for both)
-                0x60 => match rs2 {
-                    0x0 => Instruction::FcvtWS { rd, rs1 },
-                    0x1 => Instruction::FcvtWuS { rd, rs1 },
-                    _ => Instruction::Unknown(inst),
-                },
-                0x68 => match rs2 {
-                    0x0 => Instruction::FcvtSW { rd, rs1 },
-                    0x1 => Instruction::FcvtSWu { rd, rs1 },
-                    _ => Instruction::Unknown(inst),
-                },
-                0x61 => match rs2 {
-                    0x0 => Instruction::FcvtWD { rd, rs1 },
-                    0x1 => Instruction::FcvtWuD { rd, rs1 },
-                    _ => Instruction::Unknown(inst),
-                },
-                0x69 => match rs2 {
-                    0x0 => Instruction::FcvtDW { rd, rs1 },
-                    0x1 => Instruction::FcvtDWu { rd, rs1 },
-                    _ => Instruction::Unknown(inst),
-                },
-                0x78 => Instruction::FmvWS { rd, rs1 },
-                0x79 => Instruction::FmvXD { rd, rs1 },
-                0x70 => match funct3 {
-                    0x0 => Instruction::FmvSW { rd, rs1 },
-                    0x1 => Instruction::FclassS { rd, rs1 },
-                    _ => Instruction::Unknown(inst),
-                },
-                0x71 => match funct3 {
-                    0x0 => Instruction::FmvDX { rd, rs1 },
-                    0x1 => Instruction::FclassD { rd, rs1 },
-                    _ => Instruction::Unknown(inst),
-                },
-
-                0x7d => Instruction::FcvtWuD { rd, rs1 },
-                0x20 => Instruction::FcvtSD { rd, rs1 },
-                0x21 => Instruction::FcvtDS { rd, rs1 },
-                _ => Instruction::Unknown(inst),
-            },
-            0x43 => match sz {
-                0x0 => Instruction::FmaddS {
-                    rd,
-                    rs1,
-                    rs2,
-                    rs3,
-                    rm,
-                },
-                0x1 => Instruction::FmaddD {
-                    rd,
-                    rs1,
-                    rs2,
-                    rs3,
-                    rm,
-                },
-                _ => Instruction::Unknown(inst),
-            },
-            0x47 => match sz {
-                0x0 => Instruction::FmsubS {
-                    rd,
-                    rs1,
-                    rs2,
-                    rs3,
-                    rm,
-                },
-                0x1 => Instruction::FmsubD {
-                    rd,
-                    rs1,
-                    rs2,
-                    rs3,
-                    rm,
-                },
-                _ => Instruction::Unknown(inst),
-            },
-
-            0x4F => match sz {
-                0x0 => Instruction::FnmaddS {
-                    rd,
-                    rs1,
-                    rs2,
-                    rs3,
-                    rm,
-                },
-                0x1 => Instruction::FnmaddD {
-                    rd,
-                    rs1,
-                    rs2,
-                    rs3,
-                    rm,
-                },
-                _ => Instruction::Unknown(inst),
-            },
-            0x4B => match sz {
-                0x0 => Instruction::FnmsubS {
-                    rd,
-                    rs1,
-                    rs2,
-                    rs3,
-                    rm,
-                },
-                0x1 => Instruction::FnmsubD {
-                    rd,
-                    rs1,
-                    rs2,
-                    rs3,
-                    rm,
-                },
-                _ => Instruction::Unknown(inst),
-            },
-            0x07 => {
-                // fp loads (I-type): imm in bits[31:20]
-                let imm = sign_extend(inst >> 20, 12);
-                match funct3 {
-                    0x2 => Instruction::Flw { rd, rs1, imm },
-                    0x3 => Instruction::Fld { rd, rs1, imm },
-                    _ => Instruction::Unknown(inst),
-                }
+            Flw { rd, rs1, imm } => write!(f, "flw {}, {imm}({})", fr(rd), x(rs1)),
+            Fld { rd, rs1, imm } => write!(f, "fld {}, {imm}({})", fr(rd), x(rs1)),
+            Fsw { rs1, rs2, imm } => write!(f, "fsw {}, {imm}({})", fr(rs2), x(rs1)),
+            Fsd { rs1, rs2, imm } => write!(f, "fsd {}, {imm}({})", fr(rs2), x(rs1)),
+
+            FliS { rd, idx } => write!(f, "fli.s {}, {}", fr(rd), fli_operand(idx)),
+            FliD { rd, idx } => write!(f, "fli.d {}, {}", fr(rd), fli_operand(idx)),
+            FminmS { rd, rs1, rs2 } => write!(f, "fminm.s {}, {}, {}", fr(rd), fr(rs1), fr(rs2)),
+            FmaxmS { rd, rs1, rs2 } => write!(f, "fmaxm.s {}, {}, {}", fr(rd), fr(rs1), fr(rs2)),
+            FminmD { rd, rs1, rs2 } => write!(f, "fminm.d {}, {}, {}", fr(rd), fr(rs1), fr(rs2)),
+            FmaxmD { rd, rs1, rs2 } => write!(f, "fmaxm.d {}, {}, {}", fr(rd), fr(rs1), fr(rs2)),
+            FroundS { rd, rs1, rm }
+            | FroundnxS { rd, rs1, rm }
+            | FroundD { rd, rs1, rm }
+            | FroundnxD { rd, rs1, rm } => {
+                let mnemonic = match self {
+                    FroundS { .. } => "fround.s",
+                    FroundnxS { .. } => "froundnx.s",
+                    FroundD { .. } => "fround.d",
+                    _ => "froundnx.d",
+                };
+                let rm = rm_suffix(rm);
+                write!(f, "{mnemonic} {}, {}{rm}", fr(rd), fr(rs1))
             }
-            0x27 => {
-                // fp stores (S-type): imm = {inst[31:25], inst[11:7]}
-                let imm_val = ((inst >> 25) << 5) | ((inst >> 7) & 0x1f);
-                let imm = sign_extend(imm_val, 12);
-                match funct3 {
-                    0x2 => Instruction::Fsw { rs1, rs2, imm },
-                    0x3 => Instruction::Fsd { rs1, rs2, imm },
-                    _ => Instruction::Unknown(inst),
+            FcvtmodWD { rd, rs1 } => write!(f, "fcvtmod.w.d {}, {}, rtz", x(rd), fr(rs1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+
+    use super::*;
+    use crate::opcodes::OPCODES;
+
+    // the operand fields of an encoding the spec reserves, which decoding ignores
+    fn ignored(name: &str) -> &'static [Field] {
+        match name {
+            "fence" => &[Field::Fm, Field::Rs1, Field::Rd],
+            "fence.i" => &[Field::Imm12, Field::Rs1, Field::Rd],
+            _ => &[],
+        }
+    }
+
+    #[test]
+    fn every_encoding_decodes_to_its_own_disassembly() {
+        for info in &OPCODES {
+            let xlen = match info.xlen {
+                Some(64) => Xlen::Rv64,
+                _ => Xlen::Rv32,
+            };
+            let operands = info
+                .fields
+                .iter()
+                .filter(|field| !ignored(info.name).contains(field))
+                .map(|field| {
+                    let (hi, lo) = field.bits();
+                    (((1u64 << (hi - lo + 1)) - 1) as u32) << lo
+                })
+                .fold(0, |acc, bits| acc | bits);
+
+            // no operand bits, all of them and each on its own, which must all disassemble
+            // differently, so the text keeps every bit of the word
+            let mut words = BTreeSet::from([info.match_, info.match_ | operands]);
+            words.extend(
+                (0..32)
+                    .map(|bit| 1 << bit)
+                    .filter(|bit| operands & bit != 0)
+                    .map(|bit| info.match_ | bit),
+            );
+
+            let mut seen = HashMap::new();
+            for inst in words {
+                // a more specific encoding, like a pseudo-op's, wins
+                if opcodes::lookup(inst, xlen.bits()).map(|other| other.name) != Some(info.name) {
+                    continue;
+                }
+
+                let instr = Instruction::decode_for(inst, xlen);
+                let text = instr.to_string();
+                let mnemonic = text.split_whitespace().next().unwrap();
+                let mnemonic = [".aqrl", ".aq", ".rl"]
+                    .iter()
+                    .find_map(|suffix| mnemonic.strip_suffix(suffix))
+                    .unwrap_or(mnemonic);
+                assert_eq!(mnemonic, info.name, "{inst:#010x} disassembles as {text}");
+                if let Some(other) = seen.insert(text.clone(), inst) {
+                    panic!("{other:#010x} and {inst:#010x} both disassemble as {text}");
                 }
             }
-            _ => Instruction::Unknown(inst),
         }
     }
+
+    #[test]
+    fn reserved_encodings_stay_unknown() {
+        for (inst, what) in [
+            (0x0000_0000, "all zeros"),
+            (0xffff_ffff, "all ones"),
+            (0x0000_3003, "ld on RV32"),
+            (0x0000_7003, "a load with funct3 7"),
+            (0x0000_3023, "sd on RV32"),
+            (0x0000_2063, "a branch with funct3 2"),
+            (0x0000_1067, "jalr with funct3 1"),
+            (0x0200_1013, "slli of 32 on RV32"),
+            (0x4000_1033, "sll with sub's funct7"),
+            (0x0000_00f3, "ecall with rd set"),
+            (0x0010_00f3, "ebreak with rd set"),
+            (0xf800_202f, "an AMO with funct5 0b11111"),
+            (0x0600_0053, "fadd.q"),
+            (0xd020_0053, "fcvt.s.l on RV32"),
+        ] {
+            let instr = Instruction::decode(inst);
+            assert!(
+                matches!(instr, Instruction::Unknown(_)),
+                "{what}, {inst:#010x}, decodes as {instr}"
+            );
+        }
+
+        // only reserved on RV32
+        assert!(!matches!(
+            Instruction::decode_for(0x0200_1013, Xlen::Rv64),
+            Instruction::Unknown(_)
+        ));
+    }
 }
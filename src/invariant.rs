@@ -0,0 +1,405 @@
+//! Conditions on the integer registers checked as the guest runs, for
+//! `riscy --invariant`, to catch ABI bugs like a clobbered `gp` where they
+//! happen rather than where they crash.
+//!
+//! An invariant is one of
+//!
+//! - `REG in LO..HI` or `REG in LO..=HI`, e.g. `sp in __stack_start..=__stack_top`
+//! - `REG OP VALUE`, OP being `==`, `!=`, `<`, `<=`, `>` or `>=`, compared
+//!   unsigned, e.g. `s0 != 0`
+//! - `REG unchanged`, the register keeping the value it had when checking
+//!   started
+//!
+//! where a value is a number, a register, or an address, `SYMBOL` or
+//! `SYMBOL+OFFSET`. It's checked whenever an instruction writes a register it
+//! names, which is as good as after every instruction, as well as before the
+//! first, and the run stops on the first that breaks it. Ending it with `at LOCATION` checks it only
+//! before the instruction at LOCATION instead, and with `after LOCATION` only
+//! once the guest has got there, as in `gp unchanged after _start`.
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+    core::StopReason,
+    hooks::{Hook, HookAction, RegWrite},
+    instruction::Instruction,
+    load::{self, Symbol},
+    patch::Location,
+    register::Register,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Op {
+    fn holds(self, lhs: u32, rhs: u32) -> bool {
+        match self {
+            Op::Eq => lhs == rhs,
+            Op::Ne => lhs != rhs,
+            Op::Lt => lhs < rhs,
+            Op::Le => lhs <= rhs,
+            Op::Gt => lhs > rhs,
+            Op::Ge => lhs >= rhs,
+        }
+    }
+}
+
+impl FromStr for Op {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "==" => Op::Eq,
+            "!=" => Op::Ne,
+            "<" => Op::Lt,
+            "<=" => Op::Le,
+            ">" => Op::Gt,
+            ">=" => Op::Ge,
+            _ => return Err(format!("unknown comparison '{s}'")),
+        })
+    }
+}
+
+/// A value compared against, before any symbol in it is looked up
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    Reg(Register),
+    Addr(Location),
+}
+
+impl FromStr for Operand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(reg) => Ok(Operand::Reg(gp_reg(reg)?)),
+            Err(_) => Ok(Operand::Addr(s.parse()?)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Condition {
+    InRange {
+        reg: Register,
+        lo: Operand,
+        hi: Operand,
+        inclusive: bool,
+    },
+    Compare {
+        reg: Register,
+        op: Op,
+        rhs: Operand,
+    },
+    Unchanged(Register),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum When {
+    Always,
+    At(Location),
+    After(Location),
+}
+
+/// An `--invariant` argument, see the module docs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvariantSpec {
+    text: String,
+    condition: Condition,
+    when: When,
+}
+
+impl FromStr for InvariantSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid invariant '{s}', expected REG in LO..HI, REG OP VALUE or REG unchanged, \
+                 optionally followed by at LOCATION or after LOCATION"
+            )
+        };
+
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let (words, when) = match words.as_slice() {
+            [rest @ .., "at", at] => (rest, When::At(at.parse()?)),
+            [rest @ .., "after", after] => (rest, When::After(after.parse()?)),
+            words => (words, When::Always),
+        };
+
+        let condition = match *words {
+            [reg, "unchanged"] => Condition::Unchanged(gp_reg(reg.parse()?)?),
+            [reg, "in", range] => {
+                let (lo, hi, inclusive) = match range.split_once("..=") {
+                    Some((lo, hi)) => (lo, hi, true),
+                    None => {
+                        let (lo, hi) = range.split_once("..").ok_or_else(invalid)?;
+                        (lo, hi, false)
+                    }
+                };
+                Condition::InRange {
+                    reg: gp_reg(reg.parse()?)?,
+                    lo: lo.parse()?,
+                    hi: hi.parse()?,
+                    inclusive,
+                }
+            }
+            [reg, op, rhs] => Condition::Compare {
+                reg: gp_reg(reg.parse()?)?,
+                op: op.parse()?,
+                rhs: rhs.parse()?,
+            },
+            _ => return Err(invalid()),
+        };
+
+        Ok(InvariantSpec {
+            text: s.split_whitespace().collect::<Vec<_>>().join(" "),
+            condition,
+            when,
+        })
+    }
+}
+
+impl fmt::Display for InvariantSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+fn gp_reg(reg: Register) -> Result<Register, String> {
+    if reg.is_fp() {
+        return Err(format!("invariants are on integer registers, not {reg}"));
+    }
+    Ok(reg)
+}
+
+/// An operand with its symbols looked up
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    Reg(u8),
+    Const(u32),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Check {
+    InRange {
+        reg: u8,
+        lo: Value,
+        hi: Value,
+        inclusive: bool,
+    },
+    Compare {
+        reg: u8,
+        op: Op,
+        rhs: Value,
+    },
+    // with the value it has to keep, once checking has started
+    Unchanged {
+        reg: u8,
+        value: Option<u32>,
+    },
+}
+
+struct Invariant {
+    spec: InvariantSpec,
+    check: Check,
+    // where it's checked, if only there
+    at: Option<u32>,
+    // where checking starts, until it has
+    after: Option<u32>,
+    // just started, so checked before the next instruction whatever it writes
+    started: bool,
+}
+
+impl Invariant {
+    fn reads(&self, idx: u8) -> bool {
+        let value = |value: Value| matches!(value, Value::Reg(reg) if reg == idx);
+        match self.check {
+            Check::InRange { reg, lo, hi, .. } => reg == idx || value(lo) || value(hi),
+            Check::Compare { reg, rhs, .. } => reg == idx || value(rhs),
+            Check::Unchanged { reg, .. } => reg == idx,
+        }
+    }
+
+    fn holds(&self, regs: &[u32; 32]) -> bool {
+        let value = |value: Value| match value {
+            Value::Reg(reg) => regs[reg as usize],
+            Value::Const(value) => value,
+        };
+        match self.check {
+            Check::InRange {
+                reg,
+                lo,
+                hi,
+                inclusive,
+            } => {
+                let reg = regs[reg as usize];
+                reg >= value(lo)
+                    && if inclusive {
+                        reg <= value(hi)
+                    } else {
+                        reg < value(hi)
+                    }
+            }
+            Check::Compare { reg, op, rhs } => op.holds(regs[reg as usize], value(rhs)),
+            Check::Unchanged { reg, value } => {
+                value.is_none_or(|value| regs[reg as usize] == value)
+            }
+        }
+    }
+
+    // starts checking, from the registers as they are
+    fn start(&mut self, regs: &[u32; 32]) {
+        self.after = None;
+        self.started = true;
+        if let Check::Unchanged { reg, value } = &mut self.check {
+            *value = Some(regs[*reg as usize]);
+        }
+    }
+
+    // the registers it reads, `sp = 0x...`
+    fn registers(&self, regs: &[u32; 32]) -> String {
+        (0..32)
+            .filter(|&idx| self.reads(idx))
+            .map(|idx| format!("{} = {:#x}", Register::gp(idx), regs[idx as usize]))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Checks `--invariant`s, stopping the run on the first broken, see the
+/// module docs
+pub struct Invariants {
+    invariants: Vec<Invariant>,
+    symbols: Vec<Symbol>,
+    // shadowed from the writes, as the hook can't read the core
+    regs: [u32; 32],
+}
+
+impl Invariants {
+    /// Checks `specs`, looking symbols up in `symbols`, from the guest's
+    /// registers as they are now, `gp_regs`
+    pub fn new(
+        specs: &[InvariantSpec],
+        symbols: Vec<Symbol>,
+        gp_regs: [i32; 32],
+    ) -> Result<Self, String> {
+        let regs = gp_regs.map(|value| value as u32);
+        let resolve = |operand: &Operand| match operand {
+            Operand::Reg(reg) => Ok(Value::Reg(reg.to_idx())),
+            Operand::Addr(addr) => addr.resolve(&symbols).map(Value::Const),
+        };
+
+        let mut invariants = Vec::new();
+        for spec in specs {
+            let check = match &spec.condition {
+                Condition::InRange {
+                    reg,
+                    lo,
+                    hi,
+                    inclusive,
+                } => Check::InRange {
+                    reg: reg.to_idx(),
+                    lo: resolve(lo)?,
+                    hi: resolve(hi)?,
+                    inclusive: *inclusive,
+                },
+                Condition::Compare { reg, op, rhs } => Check::Compare {
+                    reg: reg.to_idx(),
+                    op: *op,
+                    rhs: resolve(rhs)?,
+                },
+                Condition::Unchanged(reg) => Check::Unchanged {
+                    reg: reg.to_idx(),
+                    value: None,
+                },
+            };
+            let (at, after) = match &spec.when {
+                When::Always => (None, None),
+                When::At(at) => (Some(at.resolve(&symbols)?), None),
+                When::After(after) => (None, Some(after.resolve(&symbols)?)),
+            };
+
+            let mut invariant = Invariant {
+                spec: spec.clone(),
+                check,
+                at,
+                after,
+                started: false,
+            };
+            if after.is_none() {
+                invariant.start(&regs);
+            }
+            invariants.push(invariant);
+        }
+
+        Ok(Self {
+            invariants,
+            symbols,
+            regs,
+        })
+    }
+
+    fn violated(&self, index: usize, pc: u32, by: Option<&RegWrite>) -> HookAction {
+        let invariant = &self.invariants[index];
+        eprintln!("invariant violated: {}", invariant.spec);
+        match by {
+            Some(write) => eprintln!(
+                "  by {} at {}: {} {:#x} -> {:#x}",
+                write.instr,
+                load::describe(&self.symbols, pc),
+                write.reg,
+                write.old as u32,
+                write.new as u32
+            ),
+            None => eprintln!("  at {}", load::describe(&self.symbols, pc)),
+        }
+        eprintln!("  {}", invariant.registers(&self.regs));
+
+        HookAction::Stop(StopReason::InvariantViolated { pc, index })
+    }
+}
+
+impl Hook for Invariants {
+    fn before_instruction(&mut self, pc: u32, _instr: &Instruction) -> HookAction {
+        for index in 0..self.invariants.len() {
+            let invariant = &mut self.invariants[index];
+            if invariant.after == Some(pc) {
+                invariant.start(&self.regs);
+            }
+            if !invariant.started && invariant.at != Some(pc) {
+                continue;
+            }
+            invariant.started = false;
+            if !invariant.holds(&self.regs) {
+                return self.violated(index, pc, None);
+            }
+        }
+        HookAction::Continue
+    }
+
+    fn on_reg_write(&mut self, write: &RegWrite) -> HookAction {
+        if write.reg.is_fp() {
+            return HookAction::Continue;
+        }
+        let idx = write.reg.to_idx();
+        self.regs[idx as usize] = write.new as u32;
+
+        for (index, invariant) in self.invariants.iter().enumerate() {
+            if invariant.at.is_some() || invariant.after.is_some() || !invariant.reads(idx) {
+                continue;
+            }
+            if !invariant.holds(&self.regs) {
+                return self.violated(index, write.pc, Some(write));
+            }
+        }
+        HookAction::Continue
+    }
+}
@@ -0,0 +1,289 @@
+//! The guest's `ioctl`, for asking whether its fds are terminals and driving
+//! them.
+//!
+//! libc decides how to buffer stdout, and curses how to draw, from `isatty`
+//! and `tcgetattr`, which are `TCGETS` and `TIOCGWINSZ` underneath. `Terminals`
+//! answers those and `TCSETS` (and its `W`/`F` variants) for the guest's fds
+//! according to its `TtyMode` (`riscy --tty`):
+//!
+//! - `host`, the default: whatever the host fd behind it is, so a guest
+//!   run from a terminal sees that terminal, and can put it in raw mode. Any
+//!   setting the guest changes is put back once it's done
+//! - `none`: nothing is a terminal
+//! - `COLSxROWS`: stdin, stdout and stderr are terminals of that size, in
+//!   `stty sane`'s settings, which the guest can change without it reaching
+//!   the host, for running interactive programs under a harness
+//!
+//! Guest and host share the generic Linux `termios` flags, so they're passed
+//! through as they are. Any other request is `ENOTTY`, as from an fd that isn't
+//! a terminal.
+
+use std::{collections::BTreeMap, fmt, mem, os::fd::RawFd, str::FromStr};
+
+use tracing::warn;
+
+const ENOTTY: i32 = 25;
+
+pub(crate) const TCGETS: u32 = 0x5401;
+pub(crate) const TCSETS: u32 = 0x5402;
+pub(crate) const TCSETSW: u32 = 0x5403;
+pub(crate) const TCSETSF: u32 = 0x5404;
+pub(crate) const TIOCGWINSZ: u32 = 0x5413;
+
+/// The size of the guest's `struct termios`, the kernel's rather than libc's
+pub(crate) const TERMIOS_SIZE: u32 = 36;
+/// The size of a `struct winsize`
+pub(crate) const WINSIZE_SIZE: u32 = 8;
+
+// the kernel's `NCCS`
+const NCCS: usize = 19;
+
+/// What the guest's fds are as terminals, parsed from `host`, `none` or
+/// `COLSxROWS`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TtyMode {
+    /// Whatever the host fd behind each is
+    #[default]
+    Host,
+    /// None are terminals
+    None,
+    /// stdin, stdout and stderr are terminals this size, and no others
+    Fake { cols: u16, rows: u16 },
+}
+
+impl FromStr for TtyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "host" => return Ok(TtyMode::Host),
+            "none" => return Ok(TtyMode::None),
+            _ => {}
+        }
+
+        let (cols, rows) = s
+            .split_once('x')
+            .and_then(|(cols, rows)| Some((cols.parse().ok()?, rows.parse().ok()?)))
+            .ok_or_else(|| format!("invalid tty '{s}', expected host, none or COLSxROWS"))?;
+        Ok(TtyMode::Fake { cols, rows })
+    }
+}
+
+impl fmt::Display for TtyMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TtyMode::Host => f.write_str("host"),
+            TtyMode::None => f.write_str("none"),
+            TtyMode::Fake { cols, rows } => write!(f, "{cols}x{rows}"),
+        }
+    }
+}
+
+/// The size of the guest's buffer `request` reads or writes, if it's one
+/// `Terminals` knows
+pub(crate) fn arg_len(request: u32) -> Option<u32> {
+    match request {
+        TCGETS | TCSETS | TCSETSW | TCSETSF => Some(TERMIOS_SIZE),
+        TIOCGWINSZ => Some(WINSIZE_SIZE),
+        _ => None,
+    }
+}
+
+/// Whether `request` writes its buffer, rather than reading it
+pub(crate) fn writes_arg(request: u32) -> bool {
+    matches!(request, TCGETS | TIOCGWINSZ)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Termios {
+    iflag: u32,
+    oflag: u32,
+    cflag: u32,
+    lflag: u32,
+    line: u8,
+    cc: [u8; NCCS],
+}
+
+impl Termios {
+    // `stty sane`, at 38400 baud
+    const SANE: Termios = Termios {
+        iflag: 0o2400,   // ICRNL | IXON
+        oflag: 0o5,      // OPOST | ONLCR
+        cflag: 0o277,    // B38400 | CS8 | CREAD
+        lflag: 0o105073, // ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN
+        line: 0,
+        cc: [
+            3, 0x1c, 0x7f, 0x15, 4, 0, 1, 0, 0x11, 0x13, 0x1a, 0, 0x12, 0x0f, 0x17, 0x16, 0, 0, 0,
+        ],
+    };
+
+    fn decode(buf: &[u8]) -> Self {
+        let word = |n: usize| u32::from_le_bytes(buf[n * 4..n * 4 + 4].try_into().unwrap());
+        Termios {
+            iflag: word(0),
+            oflag: word(1),
+            cflag: word(2),
+            lflag: word(3),
+            line: buf[16],
+            cc: buf[17..17 + NCCS].try_into().unwrap(),
+        }
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        for (n, word) in [self.iflag, self.oflag, self.cflag, self.lflag]
+            .into_iter()
+            .enumerate()
+        {
+            buf[n * 4..n * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        buf[16] = self.line;
+        buf[17..17 + NCCS].copy_from_slice(&self.cc);
+    }
+
+    fn from_host(host: &libc::termios) -> Self {
+        Termios {
+            iflag: host.c_iflag,
+            oflag: host.c_oflag,
+            cflag: host.c_cflag,
+            lflag: host.c_lflag,
+            line: host.c_line,
+            cc: host.c_cc[..NCCS].try_into().unwrap(),
+        }
+    }
+
+    // over the host's, keeping the speeds and any control characters the
+    // guest doesn't know about
+    fn apply(&self, host: &mut libc::termios) {
+        host.c_iflag = self.iflag;
+        host.c_oflag = self.oflag;
+        host.c_cflag = self.cflag;
+        host.c_lflag = self.lflag;
+        host.c_line = self.line;
+        host.c_cc[..NCCS].copy_from_slice(&self.cc);
+    }
+}
+
+/// The guest's terminals, see the module docs
+#[derive(Debug)]
+pub(crate) struct Terminals {
+    mode: TtyMode,
+    // the fake terminals' settings, by guest fd, once changed
+    fake: BTreeMap<i32, Termios>,
+    // host terminals as they were before the guest first changed them
+    saved: Vec<(RawFd, libc::termios)>,
+}
+
+impl Terminals {
+    pub(crate) fn new(mode: TtyMode) -> Self {
+        Self {
+            mode,
+            fake: BTreeMap::new(),
+            saved: Vec::new(),
+        }
+    }
+
+    /// Makes `request` on guest fd `fd`, `host` being the host fd behind it if
+    /// it's one that might be a terminal, with `arg` its buffer if it has one
+    pub(crate) fn ioctl(
+        &mut self,
+        fd: i32,
+        host: Option<RawFd>,
+        request: u32,
+        arg: &mut [u8],
+    ) -> Result<(), i32> {
+        match (self.mode, host) {
+            (TtyMode::Host, Some(host)) => self.host_ioctl(host, request, arg),
+            (TtyMode::Fake { cols, rows }, Some(_)) if (0..3).contains(&fd) => {
+                self.fake_ioctl(fd, cols, rows, request, arg)
+            }
+            _ => Err(ENOTTY),
+        }
+    }
+
+    fn host_ioctl(&mut self, host: RawFd, request: u32, arg: &mut [u8]) -> Result<(), i32> {
+        match request {
+            TCGETS => Termios::from_host(&tcgetattr(host)?).encode(arg),
+            TCSETS | TCSETSW | TCSETSF => {
+                let mut termios = tcgetattr(host)?;
+                if !self.saved.iter().any(|&(fd, _)| fd == host) {
+                    self.saved.push((host, termios));
+                }
+                Termios::decode(arg).apply(&mut termios);
+
+                let action = match request {
+                    TCSETS => libc::TCSANOW,
+                    TCSETSW => libc::TCSADRAIN,
+                    _ => libc::TCSAFLUSH,
+                };
+                if unsafe { libc::tcsetattr(host, action, &termios) } < 0 {
+                    return Err(last_errno());
+                }
+            }
+            TIOCGWINSZ => {
+                let mut winsize: libc::winsize = unsafe { mem::zeroed() };
+                if unsafe { libc::ioctl(host, libc::TIOCGWINSZ, &mut winsize) } < 0 {
+                    return Err(last_errno());
+                }
+                encode_winsize(winsize.ws_col, winsize.ws_row, arg);
+            }
+            // not one `arg_len` knows
+            _ => return Err(ENOTTY),
+        }
+        Ok(())
+    }
+
+    fn fake_ioctl(
+        &mut self,
+        fd: i32,
+        cols: u16,
+        rows: u16,
+        request: u32,
+        arg: &mut [u8],
+    ) -> Result<(), i32> {
+        match request {
+            TCGETS => self.fake.get(&fd).unwrap_or(&Termios::SANE).encode(arg),
+            TCSETS | TCSETSW | TCSETSF => {
+                self.fake.insert(fd, Termios::decode(arg));
+            }
+            TIOCGWINSZ => encode_winsize(cols, rows, arg),
+            // not one `arg_len` knows
+            _ => return Err(ENOTTY),
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Terminals {
+    fn drop(&mut self) {
+        for (host, termios) in &self.saved {
+            unsafe { libc::tcsetattr(*host, libc::TCSANOW, termios) };
+        }
+    }
+}
+
+fn tcgetattr(host: RawFd) -> Result<libc::termios, i32> {
+    let mut termios: libc::termios = unsafe { mem::zeroed() };
+    if unsafe { libc::tcgetattr(host, &mut termios) } < 0 {
+        return Err(last_errno());
+    }
+    Ok(termios)
+}
+
+fn encode_winsize(cols: u16, rows: u16, buf: &mut [u8]) {
+    buf[..2].copy_from_slice(&rows.to_le_bytes());
+    buf[2..4].copy_from_slice(&cols.to_le_bytes());
+    // no pixel size
+    buf[4..].fill(0);
+}
+
+/// Warns about a request `Terminals` doesn't know, and returns `ENOTTY` for it
+pub(crate) fn unsupported(request: u32) -> i32 {
+    warn!(target: "syscall", "unsupported ioctl {request:#x}");
+    ENOTTY
+}
+
+fn last_errno() -> i32 {
+    std::io::Error::last_os_error()
+        .raw_os_error()
+        .unwrap_or(libc::EIO)
+}
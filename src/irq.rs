@@ -0,0 +1,255 @@
+//! Interrupts raised on a schedule, for testing interrupt-driven code like an
+//! RTOS's scheduler deterministically.
+//!
+//! Interrupts are raised by events, see `events`, so a device can raise one
+//! from its own. Without a device to model, an `IrqSchedule` (`riscy --irq
+//! 7@1_000_000+10_000`) raises interrupt 7 once exactly 1,000,000 instructions
+//! have run, then every 10,000 after. Raising one sets its bit in `mip`. It's taken before the next instruction if `mstatus.MIE`
+//! and its bit in `mie` are set, or as soon as they are, as a machine-mode
+//! interrupt: `mepc` gets the pc, `mcause` its number with the top bit set,
+//! `mstatus.MPIE` gets `MIE`, which is cleared, and the guest carries on at
+//! `mtvec`, at its base or, vectored, 4 bytes in for each interrupt number.
+//! `mret` goes back. Of several pending, the lowest-numbered is taken first.
+//!
+//! Each raise is an edge: its bit in `mip` is cleared as it's taken, so the
+//! handler has no device to acknowledge. The guest can also write `mip`, to
+//! raise one itself or drop one still pending. None is taken until `mtvec`
+//! points into the program's code, so one raised before the guest has set up
+//! its handler waits for it.
+//!
+//! `Core32::enable_interrupts`, which scheduling one does, gives the guest the
+//! CSRs a trap handler needs, `mstatus`,
+//! `mie`, `mip`, `mtvec`, `mscratch`, `mepc`, `mcause`, `mtval` and `mhartid`,
+//! and `mret`. Otherwise they're illegal, as they've always been. Snapshots
+//! don't include them, so a run with interrupts can't be checkpointed.
+//!
+//! `Core32::irq_stats` counts how many of each were raised and taken, and the
+//! latency between the two in instructions, which is how long the guest kept
+//! them masked.
+
+use std::{collections::BTreeMap, fmt, ops::Range, str::FromStr};
+
+use crate::csr::{self, Mtvec, MCAUSE_INTERRUPT, MSTATUS_MIE, MSTATUS_MPIE, MSTATUS_MPP};
+
+/// When to raise an interrupt, parsed from `IRQ@AT` or `IRQ@AT+EVERY`, in
+/// instructions run, with `_` allowed between digits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqSchedule {
+    /// Its number, which goes in `mcause`, below 32
+    pub irq: u8,
+    pub at: u64,
+    pub every: Option<u64>,
+}
+
+impl FromStr for IrqSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid irq schedule '{s}', expected IRQ@AT or IRQ@AT+EVERY");
+        let count = |n: &str| n.replace('_', "").parse::<u64>().map_err(|_| invalid());
+
+        let (irq, when) = s.split_once('@').ok_or_else(invalid)?;
+        let irq = irq
+            .parse()
+            .ok()
+            .filter(|&irq| irq < 32)
+            .ok_or_else(|| format!("invalid irq '{irq}', expected 0 to 31"))?;
+        let (at, every) = match when.split_once('+') {
+            Some((at, every)) => (count(at)?, Some(count(every)?)),
+            None => (count(when)?, None),
+        };
+        if every == Some(0) {
+            return Err(invalid());
+        }
+
+        Ok(IrqSchedule { irq, at, every })
+    }
+}
+
+impl fmt::Display for IrqSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.irq, self.at)?;
+        if let Some(every) = self.every {
+            write!(f, "+{every}")?;
+        }
+        Ok(())
+    }
+}
+
+/// How often an interrupt was raised and taken, see `Core32::irq_stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IrqStats {
+    pub raised: u64,
+    pub taken: u64,
+    /// Raised again while still pending, so lost
+    pub dropped: u64,
+    /// Instructions from each being raised to being taken
+    pub total_latency: u64,
+    pub max_latency: u64,
+}
+
+impl IrqStats {
+    pub fn mean_latency(&self) -> f64 {
+        match self.taken {
+            0 => 0.0,
+            taken => self.total_latency as f64 / taken as f64,
+        }
+    }
+}
+
+/// The guest's interrupt state, see the module docs
+#[derive(Debug, Clone)]
+pub(crate) struct Interrupts {
+    // the program's code, where a handler has to be
+    code: Range<u32>,
+    mstatus: u32,
+    mie: u32,
+    mip: u32,
+    mtvec: u32,
+    mscratch: u32,
+    mepc: u32,
+    mcause: u32,
+    mtval: u32,
+    // when each one pending was raised
+    raised_at: [u64; 32],
+    stats: BTreeMap<u8, IrqStats>,
+}
+
+impl Interrupts {
+    pub(crate) fn new(code: Range<u32>) -> Self {
+        Self {
+            code,
+            mstatus: 0,
+            mie: 0,
+            mip: 0,
+            mtvec: 0,
+            mscratch: 0,
+            mepc: 0,
+            mcause: 0,
+            mtval: 0,
+            raised_at: [0; 32],
+            stats: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn stats(&self) -> Vec<(u8, IrqStats)> {
+        self.stats
+            .iter()
+            .map(|(&irq, &stats)| (irq, stats))
+            .collect()
+    }
+
+    /// When one next needs taking, having run `instret` instructions: now if
+    /// one can be
+    pub(crate) fn next_check(&self, instret: u64) -> u64 {
+        match self.pending() {
+            Some(_) => instret,
+            None => u64::MAX,
+        }
+    }
+
+    /// Raises `irq`, having run `at` instructions
+    pub(crate) fn raise(&mut self, irq: u8, at: u64) {
+        let stats = self.stats.entry(irq).or_default();
+        stats.raised += 1;
+        if self.mip & (1 << irq) != 0 {
+            stats.dropped += 1;
+            return;
+        }
+        self.mip |= 1 << irq;
+        self.raised_at[irq as usize] = at;
+    }
+
+    /// The interrupt to take next, if one's pending and enabled, and its
+    /// handler is in the code
+    pub(crate) fn pending(&self) -> Option<u8> {
+        if self.mstatus & MSTATUS_MIE == 0 {
+            return None;
+        }
+        let pending = self.mip & self.mie;
+        let irq = (pending != 0).then(|| pending.trailing_zeros() as u8)?;
+        let vector = self.vector(irq);
+        (self.code.contains(&vector) && vector & 3 == 0).then_some(irq)
+    }
+
+    // where the handler for `irq` is
+    fn vector(&self, irq: u8) -> u32 {
+        Mtvec(self.mtvec).vector(irq)
+    }
+
+    /// Takes `irq`, interrupting the instruction at `pc` after `instret`
+    /// others, and returns where the guest carries on
+    pub(crate) fn take(&mut self, irq: u8, pc: u32, instret: u64) -> u32 {
+        self.mip &= !(1 << irq);
+        self.mepc = pc;
+        self.mcause = MCAUSE_INTERRUPT | irq as u32;
+        self.mtval = 0;
+        let mie = self.mstatus & MSTATUS_MIE != 0;
+        self.mstatus &= !(MSTATUS_MIE | MSTATUS_MPIE);
+        if mie {
+            self.mstatus |= MSTATUS_MPIE;
+        }
+
+        let latency = instret - self.raised_at[irq as usize];
+        let stats = self.stats.entry(irq).or_default();
+        stats.taken += 1;
+        stats.total_latency += latency;
+        stats.max_latency = stats.max_latency.max(latency);
+
+        self.vector(irq)
+    }
+
+    /// `mret`, returning where the guest carries on
+    pub(crate) fn mret(&mut self) -> u32 {
+        let mpie = self.mstatus & MSTATUS_MPIE != 0;
+        self.mstatus |= MSTATUS_MPIE;
+        self.mstatus &= !MSTATUS_MIE;
+        if mpie {
+            self.mstatus |= MSTATUS_MIE;
+        }
+        self.mepc
+    }
+
+    pub(crate) fn read_csr(&self, csr: u16) -> Option<u32> {
+        Some(match csr {
+            // always machine mode, as there's no other to have come from
+            csr::MSTATUS => self.mstatus | MSTATUS_MPP,
+            csr::MIE => self.mie,
+            csr::MIP => self.mip,
+            csr::MTVEC => self.mtvec,
+            csr::MSCRATCH => self.mscratch,
+            csr::MEPC => self.mepc,
+            csr::MCAUSE => self.mcause,
+            csr::MTVAL => self.mtval,
+            csr::MHARTID => 0,
+            _ => return None,
+        })
+    }
+
+    /// Writes `csr`, with `instret` instructions run, returning `false` if it
+    /// isn't one of these or is read-only
+    pub(crate) fn write_csr(&mut self, csr: u16, value: u32, instret: u64) -> bool {
+        match csr {
+            csr::MSTATUS => self.mstatus = value & (MSTATUS_MIE | MSTATUS_MPIE),
+            csr::MIE => self.mie = value,
+            csr::MIP => {
+                // the ones the guest raises itself, as if just now
+                for irq in 0..32 {
+                    if value & !self.mip & (1 << irq) != 0 {
+                        self.raise(irq, instret);
+                    }
+                }
+                self.mip = value;
+            }
+            // the reserved modes are treated as direct
+            csr::MTVEC => self.mtvec = value & !2,
+            csr::MSCRATCH => self.mscratch = value,
+            // only ever 4-byte aligned, with no compressed instructions
+            csr::MEPC => self.mepc = value & !3,
+            csr::MCAUSE => self.mcause = value,
+            csr::MTVAL => self.mtval = value,
+            _ => return false,
+        }
+        true
+    }
+}
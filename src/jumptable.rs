@@ -0,0 +1,244 @@
+//! Recognising the jump tables compilers emit for `switch` statements.
+//!
+//! A dense `switch` compiles to a bounds check, then a load from a table of
+//! where each case is, indexed by the value switched on, and a `jr` to what
+//! it loaded:
+//!
+//! ```text
+//! li    a1, 4
+//! bltu  a1, a0, .Ldefault
+//! slli  a0, a0, 2
+//! lui   a1, %hi(.LJTI0_0)
+//! addi  a1, a1, %lo(.LJTI0_0)
+//! add   a0, a0, a1
+//! lw    a0, 0(a0)
+//! jr    a0
+//! ```
+//!
+//! `recognize` finds that in the straight-line code before a `jr`, however
+//! the compiler has scheduled it. The table's address can be built with `lui`
+//! or, in position-independent code, `auipc`, with its low half in the `addi`
+//! or the `lw`, and its entries can be addresses or, as LLVM emits for
+//! position-independent code, offsets from the table added back after the
+//! load. The bounds check says how many entries there are, `bltu` past the
+//! last or `bgeu` at the count, so a table without one isn't recognised, as
+//! there'd be no knowing where it ends.
+//!
+//! The core looks for a table in each block of code it decodes, and decodes
+//! the blocks of all its cases there and then, rather than each as it first
+//! runs, see `Core32::decode_stats`. `Core32::jump_tables` has those it's
+//! found, and `--debug` and the debugger note them: a `jr` switching through
+//! one, and which cases each target is. A table whose `jr` is overwritten is
+//! forgotten, and found again if the new code has one.
+
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::instruction::Instruction;
+
+/// How many instructions before a `jr` `recognize` looks at
+pub(crate) const WINDOW: usize = 16;
+// past this, the bounds check probably isn't one
+const MAX_ENTRIES: u32 = 4096;
+
+/// A `switch`'s jump table, see the module docs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpTable {
+    /// The `jr` through it
+    pub dispatch: u32,
+    /// Where it is in memory
+    pub addr: u32,
+    /// Whether its entries are offsets from `addr`, rather than addresses
+    pub relative: bool,
+    /// Where each case goes, by the value switched on
+    pub targets: Vec<u32>,
+}
+
+impl JumpTable {
+    /// The cases that go to `target`
+    pub fn cases_of(&self, target: u32) -> impl Iterator<Item = usize> + '_ {
+        self.targets
+            .iter()
+            .enumerate()
+            .filter(move |&(_, &to)| to == target)
+            .map(|(case, _)| case)
+    }
+}
+
+/// The jump tables found so far, by the `jr` through each
+#[derive(Debug, Clone, Default)]
+pub struct JumpTables(BTreeMap<u32, JumpTable>);
+
+impl JumpTables {
+    /// The table the `jr` at `dispatch` switches through
+    pub fn get(&self, dispatch: u32) -> Option<&JumpTable> {
+        self.0.get(&dispatch)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &JumpTable> {
+        self.0.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The tables with a case going to `target`
+    pub fn switching_to(&self, target: u32) -> impl Iterator<Item = &JumpTable> {
+        self.0
+            .values()
+            .filter(move |table| table.targets.contains(&target))
+    }
+
+    pub(crate) fn insert(&mut self, table: JumpTable) {
+        self.0.insert(table.dispatch, table);
+    }
+
+    /// Forgets the tables whose `jr` is in `range`, as it's been overwritten
+    pub(crate) fn remove_in(&mut self, range: Range<u32>) {
+        self.0.retain(|dispatch, _| !range.contains(dispatch));
+    }
+}
+
+/// The jump table the last of `instrs`, each with its address, switches
+/// through, if it's a `jr` through one. Entries are read with `read`, and the
+/// table's only taken if every one of them goes somewhere in `code`
+pub(crate) fn recognize(
+    instrs: &[(u32, Instruction)],
+    code: Range<u32>,
+    read: impl Fn(u32) -> Option<u32>,
+) -> Option<JumpTable> {
+    let (&(dispatch, jr), before) = instrs.split_last()?;
+    let Instruction::Jalr {
+        rd: 0,
+        rs1: target,
+        imm: 0,
+    } = jr
+    else {
+        return None;
+    };
+
+    // the entry, loaded, and for a relative table the table's address added
+    // back to it
+    let (at, instr) = def(before, target)?;
+    let (load_at, load, relative_base) = match instr {
+        Instruction::Lw { .. } => (at, instr, None),
+        Instruction::Add { rs1, rs2, .. } => {
+            [(rs1, rs2), (rs2, rs1)]
+                .into_iter()
+                .find_map(|(entry, base)| {
+                    let (load_at, load) = def(&before[..at], entry)?;
+                    let base = value(&before[..at], base)?;
+                    matches!(load, Instruction::Lw { .. }).then_some((load_at, load, Some(base)))
+                })?
+        }
+        _ => return None,
+    };
+    let Instruction::Lw {
+        rs1: entry_addr,
+        imm: offset,
+        ..
+    } = load
+    else {
+        return None;
+    };
+
+    // the entry's address, the index scaled and added to the table's
+    let (at, instr) = def(&before[..load_at], entry_addr)?;
+    let Instruction::Add { rs1, rs2, .. } = instr else {
+        return None;
+    };
+    let (scale_at, index, base) =
+        [(rs1, rs2), (rs2, rs1)]
+            .into_iter()
+            .find_map(|(scaled, base)| {
+                let (scale_at, scale) = def(&before[..at], scaled)?;
+                let Instruction::Slli {
+                    rs1: index,
+                    shamt: 2,
+                    ..
+                } = scale
+                else {
+                    return None;
+                };
+                Some((scale_at, index, value(&before[..at], base)?))
+            })?;
+    let addr = base.wrapping_add(offset as u32);
+    if relative_base.is_some_and(|base| base != addr) {
+        return None;
+    }
+
+    let entries = bound(&before[..scale_at], index)?;
+    let targets = (0..entries)
+        .map(|case| {
+            let entry = read(addr.wrapping_add(case * 4))?;
+            let target = match relative_base {
+                Some(base) => base.wrapping_add(entry),
+                None => entry,
+            };
+            (code.contains(&target) && target & 3 == 0).then_some(target)
+        })
+        .collect::<Option<_>>()?;
+
+    Some(JumpTable {
+        dispatch,
+        addr,
+        relative: relative_base.is_some(),
+        targets,
+    })
+}
+
+// the last of `instrs` to write `reg`, and where it is in them, unless
+// there's an unconditional jump after it
+fn def(instrs: &[(u32, Instruction)], reg: u8) -> Option<(usize, Instruction)> {
+    if reg == 0 {
+        return None;
+    }
+    for (at, &(_, instr)) in instrs.iter().enumerate().rev() {
+        if instr.gp_dest() == Some(reg) {
+            return Some((at, instr));
+        }
+        if matches!(instr, Instruction::Jal { .. } | Instruction::Jalr { .. }) {
+            return None;
+        }
+    }
+    None
+}
+
+// what `reg` holds after `instrs`, if it's an address or constant they built
+fn value(instrs: &[(u32, Instruction)], reg: u8) -> Option<u32> {
+    if reg == 0 {
+        return Some(0);
+    }
+    let (at, instr) = def(instrs, reg)?;
+    match instr {
+        Instruction::Lui { imm, .. } => Some(imm as u32),
+        Instruction::Auipc { imm, .. } => Some(instrs[at].0.wrapping_add(imm as u32)),
+        Instruction::Addi { rs1, imm, .. } => {
+            Some(value(&instrs[..at], rs1)?.wrapping_add(imm as u32))
+        }
+        _ => None,
+    }
+}
+
+// how many entries the bounds check on `index` at the end of `instrs` allows
+fn bound(instrs: &[(u32, Instruction)], index: u8) -> Option<u32> {
+    for (at, &(_, instr)) in instrs.iter().enumerate().rev() {
+        let entries = match instr {
+            // past the last
+            Instruction::Bltu { rs1, rs2, .. } if rs2 == index => {
+                value(&instrs[..at], rs1)?.checked_add(1)?
+            }
+            // at the count
+            Instruction::Bgeu { rs1, rs2, .. } if rs1 == index => value(&instrs[..at], rs2)?,
+            _ if instr.gp_dest() == Some(index) => return None,
+            Instruction::Jal { .. } | Instruction::Jalr { .. } => return None,
+            _ => continue,
+        };
+        return (1..=MAX_ENTRIES).contains(&entries).then_some(entries);
+    }
+    None
+}
@@ -0,0 +1,87 @@
+//! A fast RV32IMAFD user-mode emulator.
+//!
+//! The `riscy` binary is a thin wrapper around this library; embedders can use
+//! `core::Core32` directly, for instance to call individual guest functions
+//! with `Core32::call_function`.
+
+pub mod align;
+pub mod batch;
+pub mod breakpoint;
+pub mod call;
+pub mod capture;
+pub mod cfi;
+pub mod checkpoint;
+pub mod compare;
+pub mod console;
+pub mod control;
+pub mod core;
+pub mod cost;
+pub mod csr;
+pub mod ctrace;
+pub mod custom;
+#[cfg(feature = "dap")]
+pub mod dap;
+pub mod driver;
+pub mod elfnote;
+pub mod events;
+pub mod expect;
+pub mod fatal;
+pub mod fds;
+pub mod flamegraph;
+pub mod fuzz;
+pub mod hang;
+pub mod heap;
+pub mod hooks;
+pub mod hostcall;
+pub mod hostio;
+pub mod hpm;
+pub mod htif;
+pub mod identity;
+pub mod inject;
+pub mod instruction;
+pub mod invariant;
+pub mod ioctl;
+pub mod irq;
+pub mod jumptable;
+pub mod limits;
+#[cfg(feature = "dap")]
+pub mod lines;
+pub mod load;
+pub mod lockstep;
+pub mod machine;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod mmap;
+pub mod mux;
+pub mod native;
+pub mod nondet;
+pub mod opcodes;
+pub mod oracle;
+pub mod patch;
+pub mod pipeline;
+pub mod progress;
+pub mod pseudo;
+pub mod region;
+pub mod register;
+pub mod replay;
+pub mod report;
+pub mod sample;
+#[cfg(feature = "script")]
+pub mod script;
+pub mod session;
+pub mod signature;
+pub mod stat;
+pub mod stub;
+pub mod superblock;
+pub mod syscall;
+pub mod taint;
+pub mod throttle;
+pub mod timeout;
+pub mod tmin;
+pub mod trace;
+pub mod tracer;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod uart;
+pub mod writelog;
+pub mod xthead;
@@ -0,0 +1,263 @@
+//! Limits on how much of the host a guest may use.
+//!
+//! Guest memory is a fixed size, but within it an untrusted guest can still
+//! grow its heap as far as it likes, open files until the host runs out, and
+//! write without end. `ResourceLimits` caps each of these, and the guest sees
+//! the error a Linux process over its rlimits would:
+//!
+//! - `brk` past `memory` bytes above where the heap starts leaves the break
+//!   where it was, which libc reports as `ENOMEM`. There's no `mmap`, so that's
+//!   the only way the heap grows; guests whose heap is just a region of memory
+//!   from their linker script aren't limited
+//! - `openat` with `fds` files already open, stdin, stdout and stderr included,
+//!   fails with `EMFILE`
+//! - `write` past `output` bytes in total, to any fd, writes what it can and
+//!   then fails with `EFBIG`
+//!
+//! Each refusal is counted in `LimitHits`, which comes back in `RunInfo`.
+//! Output is counted from the start of the run or the last `Core32::restore`.
+//!
+//! The guest sees these as its rlimits through `prlimit64`, `getrlimit` and
+//! `setrlimit`: `memory` as `RLIMIT_DATA`, `fds` as `RLIMIT_NOFILE` and `output`
+//! as `RLIMIT_FSIZE`, though it counts all writes rather than any one file's
+//! size. Without a limit, `RLIMIT_DATA` is as much as the heap could grow, and
+//! `RLIMIT_AS` is always the size of guest memory. Like an unprivileged
+//! process, the guest can lower its limits, which then apply, but not raise
+//! them again.
+//!
+//! `getrusage` and `sysinfo` answer from the same place: the guest's time is
+//! its monotonic clock, its resident size is its heap up to the break, and the
+//! machine's memory is the guest's, with what's above the break free.
+
+use std::{fmt, str::FromStr};
+
+pub(crate) const EPERM: i32 = 1;
+pub(crate) const ESRCH: i32 = 3;
+pub(crate) const EMFILE: i32 = 24;
+pub(crate) const EFBIG: i32 = 27;
+const EINVAL: i32 = 22;
+
+pub(crate) const RLIM_INFINITY: u64 = u64::MAX;
+const RLIMIT_FSIZE: u32 = 1;
+const RLIMIT_DATA: u32 = 2;
+const RLIMIT_NOFILE: u32 = 7;
+const RLIMIT_AS: u32 = 9;
+const RLIM_NLIMITS: usize = 16;
+
+/// The size of a `struct rlimit64`
+pub(crate) const RLIMIT_SIZE: u32 = 16;
+/// The size of the guest's `struct rusage`, with 64-bit times
+pub(crate) const RUSAGE_SIZE: u32 = 88;
+/// The size of the guest's `struct sysinfo`
+pub(crate) const SYSINFO_SIZE: u32 = 64;
+
+const RUSAGE_SELF: i32 = 0;
+const RUSAGE_CHILDREN: i32 = -1;
+const RUSAGE_THREAD: i32 = 1;
+
+/// A `--max-memory` or `--max-output` argument, in bytes, like `64M` or `4k`.
+/// The suffixes are powers of 1024
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (count, shift) = match s.as_bytes().last() {
+            Some(b'k' | b'K') => (&s[..s.len() - 1], 10),
+            Some(b'm' | b'M') => (&s[..s.len() - 1], 20),
+            Some(b'g' | b'G') => (&s[..s.len() - 1], 30),
+            _ => (s, 0),
+        };
+
+        count
+            .parse::<u64>()
+            .ok()
+            .and_then(|count| count.checked_mul(1 << shift))
+            .map(ByteSize)
+            .ok_or_else(|| format!("invalid size '{s}', expected e.g. 4096, 64k or 16M"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResourceLimits {
+    /// The most the heap may grow by with `brk`, in bytes
+    pub memory: Option<u64>,
+    /// The most files the guest may have open at once
+    pub fds: Option<usize>,
+    /// The most bytes the guest may write, to all fds together
+    pub output: Option<u64>,
+}
+
+/// How many times the guest was refused something by `ResourceLimits`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LimitHits {
+    pub memory: u64,
+    pub fds: u64,
+    pub output: u64,
+}
+
+impl LimitHits {
+    pub fn any(&self) -> bool {
+        *self != LimitHits::default()
+    }
+}
+
+impl fmt::Display for LimitHits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hits = [
+            ("memory", self.memory),
+            ("fds", self.fds),
+            ("output", self.output),
+        ];
+        let mut first = true;
+        for (name, count) in hits.into_iter().filter(|&(_, count)| count > 0) {
+            if !first {
+                write!(f, ", ")?;
+            }
+            write!(f, "{name} {count}")?;
+            first = false;
+        }
+        Ok(())
+    }
+}
+
+/// `ResourceLimits` as the core keeps them, with what's been used so far
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits {
+    limits: ResourceLimits,
+    // the guest's (soft, hard) rlimits, by resource
+    rlimits: [(u64, u64); RLIM_NLIMITS],
+    written: u64,
+    pub(crate) hits: LimitHits,
+}
+
+impl Limits {
+    /// `limits` for a guest with `memory` bytes, of which the heap could grow
+    /// to `heap`
+    pub(crate) fn new(limits: ResourceLimits, memory: u64, heap: u64) -> Self {
+        let limit = |limit: Option<u64>| {
+            let limit = limit.unwrap_or(RLIM_INFINITY);
+            (limit, limit)
+        };
+        let mut rlimits = [(RLIM_INFINITY, RLIM_INFINITY); RLIM_NLIMITS];
+        rlimits[RLIMIT_FSIZE as usize] = limit(limits.output);
+        rlimits[RLIMIT_DATA as usize] = limit(Some(limits.memory.unwrap_or(heap)));
+        rlimits[RLIMIT_NOFILE as usize] = limit(limits.fds.map(|fds| fds as u64));
+        rlimits[RLIMIT_AS as usize] = limit(Some(memory));
+
+        Self {
+            limits,
+            rlimits,
+            written: 0,
+            hits: LimitHits::default(),
+        }
+    }
+
+    /// The guest's soft and hard limits on `resource`
+    pub(crate) fn rlimit(&self, resource: u32) -> Result<(u64, u64), i32> {
+        self.rlimits.get(resource as usize).copied().ok_or(EINVAL)
+    }
+
+    /// Sets the guest's limits on `resource`, which may only go down
+    pub(crate) fn set_rlimit(&mut self, resource: u32, soft: u64, hard: u64) -> Result<(), i32> {
+        let (_, old_hard) = self.rlimit(resource)?;
+        if soft > hard {
+            return Err(EINVAL);
+        }
+        if hard > old_hard {
+            return Err(EPERM);
+        }
+        self.rlimits[resource as usize] = (soft, hard);
+
+        let limit = (soft != RLIM_INFINITY).then_some(soft);
+        match resource {
+            RLIMIT_FSIZE => self.limits.output = limit,
+            RLIMIT_DATA => self.limits.memory = limit,
+            RLIMIT_NOFILE => self.limits.fds = limit.map(|fds| fds as usize),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Starts counting afresh, as after a restore
+    pub(crate) fn reset(&mut self) {
+        self.written = 0;
+        self.hits = LimitHits::default();
+    }
+
+    /// Whether the heap may be `size` bytes
+    pub(crate) fn heap(&mut self, size: u64) -> bool {
+        let allowed = self.limits.memory.is_none_or(|max| size <= max);
+        if !allowed {
+            self.hits.memory += 1;
+        }
+        allowed
+    }
+
+    /// Whether another file may be opened with `open` already open
+    pub(crate) fn open(&mut self, open: usize) -> Result<(), i32> {
+        if self.limits.fds.is_some_and(|max| open >= max) {
+            self.hits.fds += 1;
+            return Err(EMFILE);
+        }
+        Ok(())
+    }
+
+    /// How many of `len` bytes may be written, or `EFBIG` if none can
+    pub(crate) fn write(&mut self, len: usize) -> Result<usize, i32> {
+        let Some(max) = self.limits.output else {
+            return Ok(len);
+        };
+
+        let left = max.saturating_sub(self.written);
+        if left == 0 && len > 0 {
+            self.hits.output += 1;
+            return Err(EFBIG);
+        }
+        let len = len.min(left as usize);
+        self.written += len as u64;
+        Ok(len)
+    }
+}
+
+/// The guest's `struct rusage` for `who`, having run for `(secs, usecs)` with
+/// `rss` bytes resident. It has no children, and never sleeps in the kernel
+pub(crate) fn rusage(
+    who: i32,
+    (secs, usecs): (u64, u64),
+    rss: u64,
+) -> Result<[u8; RUSAGE_SIZE as usize], i32> {
+    let mut buf = [0; RUSAGE_SIZE as usize];
+    match who {
+        RUSAGE_SELF | RUSAGE_THREAD => {
+            // `ru_utime`, then `ru_maxrss` in KiB past `ru_stime`
+            buf[..8].copy_from_slice(&secs.to_le_bytes());
+            buf[8..12].copy_from_slice(&(usecs as u32).to_le_bytes());
+            buf[32..36].copy_from_slice(&((rss / 1024) as u32).to_le_bytes());
+        }
+        RUSAGE_CHILDREN => {}
+        _ => return Err(EINVAL),
+    }
+    Ok(buf)
+}
+
+/// The guest's `struct sysinfo`, up for `uptime` seconds with `total` bytes of
+/// memory, `free` of them free
+pub(crate) fn sysinfo(uptime: u64, total: u64, free: u64) -> [u8; SYSINFO_SIZE as usize] {
+    // 4GiB doesn't fit the guest's `unsigned long`
+    let unit = if total > u32::MAX as u64 { 1024 } else { 1 };
+
+    let mut buf = [0; SYSINFO_SIZE as usize];
+    let mut put = |offset: usize, bytes: &[u8]| {
+        buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    put(0, &(uptime.min(i32::MAX as u64) as u32).to_le_bytes());
+    put(16, &((total / unit) as u32).to_le_bytes());
+    put(20, &((free / unit) as u32).to_le_bytes());
+    // `procs`, just the guest
+    put(40, &1u16.to_le_bytes());
+    put(52, &(unit as u32).to_le_bytes());
+    buf
+}
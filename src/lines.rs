@@ -0,0 +1,174 @@
+//! Mapping between addresses and source lines, from an ELF's DWARF line table.
+
+use std::{
+    borrow::Cow,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use elf::{endian::AnyEndian, ElfBytes};
+use gimli::{EndianSlice, LittleEndian, SectionId};
+
+#[derive(Debug, Clone, Copy)]
+struct Row {
+    addr: u32,
+    file: usize,
+    // 0 past the end of a sequence
+    line: u32,
+    // the first instruction of a line, where a debugger should stop
+    starts_line: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LineTable {
+    files: Vec<PathBuf>,
+    // sorted by address
+    rows: Vec<Row>,
+}
+
+impl LineTable {
+    /// Reads the line table of the ELF at `path`, which is empty if it has no
+    /// debug info
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let data = fs::read(path)?;
+        let elf = ElfBytes::<AnyEndian>::minimal_parse(&data)?;
+
+        let load_section = |id: SectionId| -> Result<Cow<[u8]>, gimli::Error> {
+            let data = match elf.section_header_by_name(id.name()) {
+                Ok(Some(shdr)) => elf.section_data(&shdr).map_or(&[][..], |(data, _)| data),
+                _ => &[],
+            };
+            Ok(Cow::Borrowed(data))
+        };
+        let sections = gimli::DwarfSections::load(load_section)?;
+        let dwarf = sections.borrow(|section| EndianSlice::new(section, LittleEndian));
+
+        let mut table = LineTable::default();
+        let mut units = dwarf.units();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
+            let Some(program) = unit.line_program.clone() else {
+                continue;
+            };
+            let comp_dir = unit
+                .comp_dir
+                .map(|dir| PathBuf::from(dir.to_string_lossy().as_ref()))
+                .unwrap_or_default();
+
+            let mut prev: Option<Row> = None;
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row()? {
+                let addr = row.address() as u32;
+                if row.end_sequence() {
+                    table.rows.push(Row {
+                        addr,
+                        file: 0,
+                        line: 0,
+                        starts_line: false,
+                    });
+                    prev = None;
+                    continue;
+                }
+
+                let Some(entry) = row.file(header) else {
+                    continue;
+                };
+                let mut path = comp_dir.clone();
+                if let Some(dir) = entry.directory(header) {
+                    path.push(dwarf.attr_string(&unit, dir)?.to_string_lossy().as_ref());
+                }
+                path.push(
+                    dwarf
+                        .attr_string(&unit, entry.path_name())?
+                        .to_string_lossy()
+                        .as_ref(),
+                );
+
+                let file = table.file_idx(path);
+                let line = row.line().map_or(0, |line| line.get() as u32);
+                let starts_line = row.is_stmt()
+                    && line != 0
+                    && prev.is_none_or(|prev| prev.file != file || prev.line != line);
+
+                let row = Row {
+                    addr,
+                    file,
+                    line,
+                    starts_line,
+                };
+                table.rows.push(row);
+                prev = Some(row);
+            }
+        }
+
+        // stable, so an end of sequence stays before a sequence starting there
+        table.rows.sort_by_key(|row| row.addr);
+        Ok(table)
+    }
+
+    fn file_idx(&mut self, path: PathBuf) -> usize {
+        let path = path.canonicalize().unwrap_or(path);
+        match self.files.iter().position(|file| *file == path) {
+            Some(idx) => idx,
+            None => {
+                self.files.push(path);
+                self.files.len() - 1
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// The source line `addr` is part of
+    pub fn location(&self, addr: u32) -> Option<(&Path, u32)> {
+        let idx = self.rows.partition_point(|row| row.addr <= addr);
+        let row = self.rows[..idx].last()?;
+        (row.line != 0).then(|| (self.files[row.file].as_path(), row.line))
+    }
+
+    /// Whether `addr` is the first instruction of a line
+    pub fn is_line_start(&self, addr: u32) -> bool {
+        let idx = self.rows.partition_point(|row| row.addr < addr);
+        self.rows[idx..]
+            .iter()
+            .take_while(|row| row.addr == addr)
+            .any(|row| row.starts_line)
+    }
+
+    /// Where to break for `line` of `path`: the first line at or after it with
+    /// any code, and the start of each run of instructions for it
+    pub fn line_addrs(&self, path: &Path, line: u32) -> Option<(u32, Vec<u32>)> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+        let file = self
+            .files
+            .iter()
+            .position(|file| *file == path)
+            // fall back to the file name, for binaries built elsewhere
+            .or_else(|| {
+                let name = path.file_name()?;
+                self.files
+                    .iter()
+                    .position(|file| file.file_name() == Some(name))
+            })?;
+
+        let rows = || {
+            self.rows
+                .iter()
+                .filter(move |row| row.file == file && row.starts_line)
+        };
+        let line = rows()
+            .map(|row| row.line)
+            .filter(|&found| found >= line)
+            .min()?;
+
+        let mut addrs: Vec<_> = rows()
+            .filter(|row| row.line == line)
+            .map(|row| row.addr)
+            .collect();
+        addrs.dedup();
+        Some((line, addrs))
+    }
+}
@@ -1,35 +1,220 @@
 use anyhow::anyhow;
-use elf::{abi, endian::AnyEndian, ElfBytes};
+use elf::{
+    abi,
+    endian::AnyEndian,
+    note::{Note, NoteAny, NoteGnuBuildId},
+    ElfBytes,
+};
 use std::error::Error;
-use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
 
+use crate::elfnote::{self, ElfConfig};
+use crate::fatal::FatalKind;
+use crate::machine::Isa;
+use crate::mmap::{self, MappedFile, Mapping};
+
+#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct Segment {
     pub offset: u64, // relative address
     pub vaddr: u64,
     pub size: u64,
+    /// Empty when the segment is paged in from `file` instead
     pub data: Vec<u8>,
+    pub executable: bool,
+    pub file: Option<FileData>,
+}
+
+/// A segment's contents in the ELF file, for big data segments, which are
+/// mapped into guest memory to be read as the guest touches them rather than
+/// copied up front. The file mustn't change while the guest runs
+#[derive(Debug, Clone)]
+pub struct FileData {
+    mapped: Arc<MappedFile>,
+    /// Where the contents start in the file
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl FileData {
+    /// Puts the contents at `addr` in `mapping`
+    pub(crate) fn load_into(&self, mapping: &Mapping, addr: usize) -> io::Result<()> {
+        self.mapped
+            .load_into(mapping, addr, self.offset as usize, self.len as usize)
+    }
+}
+
+// data segments at least this big are paged in from the file, smaller ones
+// are cheaper to copy
+const LAZY_SEGMENT_SIZE: u64 = 1 << 20;
+
+/// A `--load-extra` argument: `PATH@ADDR`, an ELF to load alongside the
+/// program with its lowest segment at `ADDR`
+#[derive(Debug, Clone)]
+pub struct ExtraElf {
+    pub path: PathBuf,
+    pub addr: u64,
 }
 
-#[derive(Debug)]
+impl FromStr for ExtraElf {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, addr) = s
+            .rsplit_once('@')
+            .ok_or_else(|| format!("invalid extra ELF '{s}', expected PATH@ADDR"))?;
+        let addr = match addr.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => addr.parse(),
+        }
+        .map_err(|_| format!("invalid address '{addr}'"))?;
+
+        Ok(ExtraElf {
+            path: path.into(),
+            addr,
+        })
+    }
+}
+
+// the RISC-V `e_flags`
+const EF_RISCV_RVC: u32 = 0x1;
+const EF_RISCV_FLOAT_ABI: u32 = 0x6;
+const EF_RISCV_RVE: u32 = 0x8;
+const EF_RISCV_TSO: u32 = 0x10;
+
+/// How a program passes floats, from its `e_flags`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FloatAbi {
+    /// `ilp32`, in integer registers
+    #[default]
+    Soft,
+    /// `ilp32f`
+    Single,
+    /// `ilp32d`
+    Double,
+    /// `ilp32q`
+    Quad,
+}
+
+/// What the ELF header says a program was built for
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ElfTarget {
+    pub machine: u16,
+    pub rv64: bool,
+    /// Has compressed instructions
+    pub rvc: bool,
+    pub rve: bool,
+    pub float_abi: FloatAbi,
+    /// Assumes the TSO memory model, see `machine::MemoryModel`
+    pub tso: bool,
+}
+
+impl ElfTarget {
+    fn from_header(machine: u16, rv64: bool, flags: u32) -> Self {
+        Self {
+            machine,
+            rv64,
+            rvc: flags & EF_RISCV_RVC != 0,
+            rve: flags & EF_RISCV_RVE != 0,
+            float_abi: match flags & EF_RISCV_FLOAT_ABI {
+                0x0 => FloatAbi::Soft,
+                0x2 => FloatAbi::Single,
+                0x4 => FloatAbi::Double,
+                _ => FloatAbi::Quad,
+            },
+            tso: flags & EF_RISCV_TSO != 0,
+        }
+    }
+
+    /// Whether harts implementing `isa` can run the program, or what it needs
+    /// that they don't
+    pub fn check(&self, isa: &Isa) -> Result<(), String> {
+        if self.machine != abi::EM_RISCV {
+            return Err(format!("not a RISC-V binary (e_machine {})", self.machine));
+        }
+        if self.rv64 {
+            return Err("the binary is RV64, riscy only runs RV32".to_owned());
+        }
+        if self.rve {
+            return Err("the binary is RV32E, which riscy doesn't implement".to_owned());
+        }
+        if self.rvc {
+            return Err(
+                "the binary has compressed instructions (RVC), which riscy doesn't \
+                        implement; build it without the C extension, e.g. -march=rv32imafd"
+                    .to_owned(),
+            );
+        }
+        let needs = match self.float_abi {
+            FloatAbi::Soft => None,
+            FloatAbi::Single => Some(('f', "ilp32f")),
+            FloatAbi::Double => Some(('d', "ilp32d")),
+            FloatAbi::Quad => {
+                return Err(
+                    "the binary requires the Q extension hard-float ABI (ilp32q), which \
+                            riscy doesn't implement"
+                        .to_owned(),
+                )
+            }
+        };
+        match needs {
+            Some((letter, abi)) if !isa.letters.contains(letter) => Err(format!(
+                "the binary requires the {} extension hard-float ABI ({abi}), which {isa} \
+                 doesn't have",
+                letter.to_ascii_uppercase()
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub addr: u64,
+}
+
+#[derive(Debug, Clone)]
 pub struct LoadedElf {
     pub base: u64,
     pub entrypoint: u64,
     pub segments: Vec<Segment>,
+    pub symbols: Vec<Symbol>,
 
     pub wk_memmove: u32,
     pub wk_memcpy: u32,
     pub wk_memset: u32,
     pub wk_cos: u32,
     pub wk_sin: u32,
+
+    /// Functions that end the program abnormally, see `fatal`
+    pub fatal_fns: Vec<(u32, FatalKind)>,
+
+    /// The GNU build id, if the linker gave it one
+    pub build_id: Option<Vec<u8>>,
+
+    /// What it was built for, from its header
+    pub target: ElfTarget,
+
+    /// What its `.note.riscy` asks of the machine, see `elfnote`
+    pub config: ElfConfig,
 }
 
 impl LoadedElf {
     pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
-        let data = fs::read(path)?;
-        let elf = ElfBytes::<AnyEndian>::minimal_parse(&data)?;
+        let mapped = Arc::new(MappedFile::open(Path::new(path))?);
+        let data = mapped.as_slice();
+        let elf = ElfBytes::<AnyEndian>::minimal_parse(data)?;
 
         let segments = elf.segments().ok_or(anyhow!("no segments in ELF"))?;
+        let target = ElfTarget::from_header(
+            elf.ehdr.e_machine,
+            elf.ehdr.class == elf::file::Class::ELF64,
+            elf.ehdr.e_flags,
+        );
 
         let base = segments
             .iter()
@@ -43,10 +228,10 @@ impl LoadedElf {
         if let Some((symbol_table, string_table)) = elf.symbol_table()? {
             for sym in symbol_table {
                 if sym.st_name != 0 {
-                    symbols.push((
-                        string_table.get(sym.st_name as usize)?.to_string(),
-                        sym.st_value,
-                    ));
+                    symbols.push(Symbol {
+                        name: string_table.get(sym.st_name as usize)?.to_string(),
+                        addr: sym.st_value,
+                    });
                 }
             }
         }
@@ -56,14 +241,44 @@ impl LoadedElf {
         let mut wk_memset = 0;
         let mut wk_cos = 0;
         let mut wk_sin = 0;
-        for (sym, offset) in symbols {
-            match sym.as_str() {
+        let mut fatal_fns = Vec::new();
+        for Symbol { name, addr, .. } in &symbols {
+            let offset = *addr;
+            match name.as_str() {
                 "memset" => wk_memset = offset as u32,
                 "memmove" => wk_memmove = offset as u32,
                 "memcpy" => wk_memcpy = offset as u32,
                 "cos" => wk_cos = offset as u32,
                 "sin" => wk_sin = offset as u32,
-                _ => {}
+                _ => {
+                    if let Some(kind) = FatalKind::from_symbol(name) {
+                        fatal_fns.push((offset as u32, kind));
+                    }
+                }
+            }
+        }
+
+        let build_id = segments
+            .iter()
+            .filter(|ph| ph.p_type == abi::PT_NOTE)
+            .filter_map(|ph| elf.segment_data_as_notes(&ph).ok())
+            .flatten()
+            .find_map(|note| match note {
+                Note::GnuBuildId(NoteGnuBuildId(id)) => Some(id.to_vec()),
+                _ => None,
+            });
+
+        let mut config = ElfConfig::default();
+        if let Some(shdr) = elf.section_header_by_name(".note.riscy")? {
+            for note in elf.section_data_as_notes(&shdr)? {
+                if let Note::Unknown(NoteAny {
+                    n_type: elfnote::NT_RISCY_CONFIG,
+                    name: elfnote::NOTE_NAME,
+                    desc,
+                }) = note
+                {
+                    config = ElfConfig::parse(desc).map_err(|err| anyhow!(err))?;
+                }
             }
         }
 
@@ -77,6 +292,34 @@ impl LoadedElf {
             let mem_size = ph.p_memsz as usize;
             let offset_in_file = ph.p_offset as usize;
             let rel_offset = ph.p_vaddr - base;
+            let executable = ph.p_flags & abi::PF_X != 0;
+            if offset_in_file + file_size > data.len() {
+                return Err(
+                    anyhow!("segment at {:#x} is past the end of the file", ph.p_vaddr).into(),
+                );
+            }
+
+            // code is always copied, as it's decoded and hashed
+            let page = mmap::page_size() as u64;
+            if !executable
+                && ph.p_filesz >= LAZY_SEGMENT_SIZE
+                && ph.p_vaddr % page == ph.p_offset % page
+            {
+                loaded_segments.push(Segment {
+                    offset: rel_offset,
+                    vaddr: ph.p_vaddr,
+                    size: ph.p_memsz,
+                    data: Vec::new(),
+                    executable,
+                    file: Some(FileData {
+                        mapped: mapped.clone(),
+                        offset: ph.p_offset,
+                        len: ph.p_filesz,
+                    }),
+                });
+                continue;
+            }
+
             let mut seg_data = vec![0u8; mem_size];
             seg_data[..file_size]
                 .copy_from_slice(&data[offset_in_file..offset_in_file + file_size]);
@@ -85,6 +328,8 @@ impl LoadedElf {
                 vaddr: ph.p_vaddr,
                 size: ph.p_memsz,
                 data: seg_data,
+                executable,
+                file: None,
             });
         }
         Ok(LoadedElf {
@@ -95,10 +340,125 @@ impl LoadedElf {
             wk_memcpy,
             wk_cos,
             wk_sin,
+            fatal_fns,
+            build_id,
+            target,
+            config,
             segments: loaded_segments,
+            symbols,
         })
     }
 
+    /// Fails if the program needs what riscy doesn't implement, see
+    /// `ElfTarget::check`
+    pub fn check_runnable(&self) -> Result<(), String> {
+        self.target.check(&Isa::implemented())?;
+        // an ISA parses only if riscy implements all of it
+        if let Some(isa) = &self.config.isa {
+            isa.parse::<Isa>()
+                .map_err(|err| format!("the binary's riscy note asks for {isa}: {err}"))?;
+        }
+        Ok(())
+    }
+
+    /// Loads `extra` into the same address space, moved so its lowest segment
+    /// is at `addr`, which only works for position-independent code unless
+    /// `addr` is where it was linked. Its symbols are added to the program's,
+    /// as are the well-known functions the program doesn't have itself
+    pub fn add(&mut self, extra: LoadedElf, addr: u64) -> Result<(), String> {
+        let bias = addr.wrapping_sub(extra.base);
+        let moved = |addr: u64| addr.wrapping_add(bias);
+        let moved32 = |addr: u32| {
+            if addr == 0 {
+                0
+            } else {
+                moved(addr as u64) as u32
+            }
+        };
+
+        for mut seg in extra.segments {
+            seg.vaddr = moved(seg.vaddr);
+            if let Some(other) = self.segments.iter().find(|other| {
+                seg.vaddr < other.vaddr + other.size && other.vaddr < seg.vaddr + seg.size
+            }) {
+                return Err(format!(
+                    "segment at {:#x} overlaps the one at {:#x}",
+                    seg.vaddr, other.vaddr
+                ));
+            }
+            seg.offset = seg.vaddr.wrapping_sub(self.base);
+            self.segments.push(seg);
+        }
+
+        self.symbols
+            .extend(extra.symbols.into_iter().map(|sym| Symbol {
+                addr: moved(sym.addr),
+                ..sym
+            }));
+        self.fatal_fns.extend(
+            extra
+                .fatal_fns
+                .into_iter()
+                .map(|(addr, kind)| (moved32(addr), kind)),
+        );
+        for (known, theirs) in [
+            (&mut self.wk_memmove, extra.wk_memmove),
+            (&mut self.wk_memcpy, extra.wk_memcpy),
+            (&mut self.wk_memset, extra.wk_memset),
+            (&mut self.wk_cos, extra.wk_cos),
+            (&mut self.wk_sin, extra.wk_sin),
+        ] {
+            if *known == 0 {
+                *known = moved32(theirs);
+            }
+        }
+        Ok(())
+    }
+
+    /// The code to decode for a program entered in `entry`: `entry` itself,
+    /// stretched over any other executable segments, with what's between them
+    /// left zero, so running it is an illegal instruction
+    pub fn text(&self, entry: &Segment) -> Segment {
+        let exec: Vec<_> = self
+            .segments
+            .iter()
+            .filter(|seg| seg.executable || seg.vaddr == entry.vaddr)
+            .collect();
+        if exec.len() <= 1 {
+            return entry.clone();
+        }
+
+        let start = exec.iter().map(|seg| seg.vaddr).min().unwrap();
+        let end = exec.iter().map(|seg| seg.vaddr + seg.size).max().unwrap();
+        let mut data = vec![0; (end - start) as usize];
+        for seg in exec {
+            let offset = (seg.vaddr - start) as usize;
+            data[offset..offset + seg.data.len()].copy_from_slice(&seg.data);
+        }
+
+        Segment {
+            offset: start.wrapping_sub(self.base),
+            vaddr: start,
+            size: end - start,
+            data,
+            executable: true,
+            file: None,
+        }
+    }
+
+    /// Address of the named symbol
+    pub fn symbol(&self, name: &str) -> Option<u64> {
+        self.symbols
+            .iter()
+            .find(|sym| sym.name == name)
+            .map(|sym| sym.addr)
+    }
+
+    /// The symbol containing `addr`, and the offset into it
+    pub fn symbolize(&self, addr: u64) -> Option<(&str, u64)> {
+        symbolize(&self.symbols, addr)
+    }
+
     pub fn find_segment(&self, vaddr: u64) -> Option<(&Segment, usize, usize)> {
         if vaddr < self.base {
             return None;
@@ -115,3 +475,21 @@ impl LoadedElf {
         None
     }
 }
+
+/// The closest symbol at or before `addr`, and the offset from it
+pub fn symbolize(symbols: &[Symbol], addr: u64) -> Option<(&str, u64)> {
+    symbols
+        .iter()
+        // skip section and mapping symbols like `$x` and `.L0`
+        .filter(|sym| sym.addr <= addr && !sym.name.starts_with(['$', '.']))
+        .max_by_key(|sym| sym.addr)
+        .map(|sym| (sym.name.as_str(), addr - sym.addr))
+}
+
+/// `addr` for reports, e.g. `0x110c8 <spin+0x4>`
+pub fn describe(symbols: &[Symbol], addr: u32) -> String {
+    match symbolize(symbols, addr as u64) {
+        Some((name, offset)) => format!("{addr:#x} <{name}+{offset:#x}>"),
+        None => format!("{addr:#x}"),
+    }
+}
@@ -0,0 +1,454 @@
+//! Running a program under two of riscy's own configurations side by side,
+//! for `riscy lockstep`, to check an optimization doesn't change what the
+//! guest sees: the memory reader, superblocks, or how the arena's mapped.
+//!
+//! Both cores are run a chunk of instructions at a time, and compared after
+//! each: the pc, the integer and fp registers, `fcsr`, all of memory, what the
+//! guest has written to stdout and stderr, and how the run stopped. Once a
+//! chunk ends differently, both are put back to where it started and it's
+//! bisected, each half run with `run`, as a step doesn't take the paths a
+//! run does, down to the one instruction after which they differ.
+//!
+//! The cores have to run the program the same way for the comparison to mean
+//! anything, so their clocks and random bytes are virtual, from the same seed,
+//! and stdin is a buffer, see `nondet`. Their output is captured, not written
+//! anywhere. Anything else the program does to the host, like writing a file,
+//! it does twice, and more while bisecting.
+
+use std::{fmt, str::FromStr};
+
+use crate::{
+    checkpoint::Snapshot,
+    core::{Core32, MemReader, RunInfo, StopReason},
+    csr,
+    instruction::Instruction,
+    load::LoadedElf,
+    mmap::Hugepages,
+    register::Register,
+};
+
+/// Instructions run between comparisons, unless told otherwise
+pub const DEFAULT_CHUNK: u64 = 100_000;
+
+/// Which `MemReader` a configuration's core is built with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReaderKind {
+    Adaptive,
+    Aligned,
+    Unaligned,
+}
+
+/// One of riscy's configurations, written `READER[+OPTION...]`, with READER
+/// `adaptive`, `aligned` or `unaligned` and OPTION `no-superblocks` or
+/// `hugepages=MODE`, e.g. `aligned+no-superblocks`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoreConfig {
+    pub reader: ReaderKind,
+    pub superblocks: bool,
+    pub hugepages: Hugepages,
+}
+
+impl FromStr for CoreConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('+');
+        let reader = match parts.next().unwrap_or_default() {
+            "adaptive" => ReaderKind::Adaptive,
+            "aligned" => ReaderKind::Aligned,
+            "unaligned" => ReaderKind::Unaligned,
+            reader => {
+                return Err(format!(
+                    "invalid reader '{reader}', expected adaptive, aligned or unaligned"
+                ))
+            }
+        };
+
+        let mut config = CoreConfig {
+            reader,
+            superblocks: true,
+            hugepages: Hugepages::Off,
+        };
+        for option in parts {
+            match option.split_once('=') {
+                None if option == "no-superblocks" => config.superblocks = false,
+                Some(("hugepages", mode)) => config.hugepages = mode.parse()?,
+                _ => {
+                    return Err(format!(
+                        "invalid option '{option}', expected no-superblocks or hugepages=MODE"
+                    ))
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl fmt::Display for CoreConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self.reader {
+            ReaderKind::Adaptive => "adaptive",
+            ReaderKind::Aligned => "aligned",
+            ReaderKind::Unaligned => "unaligned",
+        })?;
+        if !self.superblocks {
+            f.write_str("+no-superblocks")?;
+        }
+        if self.hugepages != Hugepages::Off {
+            write!(f, "+hugepages={}", self.hugepages)?;
+        }
+        Ok(())
+    }
+}
+
+impl CoreConfig {
+    /// A core running `elf` as configured, where `Reader` has to be the one
+    /// `reader` names
+    pub fn build<Reader: MemReader<Idx = u32>>(
+        &self,
+        elf: LoadedElf,
+        size: usize,
+    ) -> Core32<Reader> {
+        let mut core = Core32::with_hugepages(elf, None, size, false, self.hugepages);
+        core.set_superblocks(self.superblocks);
+        core
+    }
+}
+
+/// What differs between the cores after an instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    Pc(u32, u32),
+    GpReg {
+        reg: u8,
+        a: u32,
+        b: u32,
+    },
+    FpReg {
+        reg: u8,
+        a: u64,
+        b: u64,
+    },
+    Fcsr(u32, u32),
+    /// The first bytes that differ, from the word they're in
+    Memory {
+        addr: u32,
+        a: Vec<u8>,
+        b: Vec<u8>,
+    },
+    /// The first byte written to `stream` that differs, or that only one wrote
+    Output {
+        stream: &'static str,
+        offset: usize,
+        a: Option<u8>,
+        b: Option<u8>,
+    },
+    /// How each stopped, if either did
+    Stopped(String, String),
+}
+
+/// Where the cores first differ, see the module docs
+#[derive(Debug, Clone)]
+pub struct Divergence {
+    /// The configurations, as given
+    pub configs: (String, String),
+    /// How many instructions both ran alike
+    pub instret: u64,
+    /// The instruction after which they differ, and where it is
+    pub pc: u32,
+    pub instr: Option<Instruction>,
+    pub difference: Difference,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (a, b) = &self.configs;
+        write!(
+            f,
+            "after instruction {}, at pc {:#x}",
+            self.instret + 1,
+            self.pc
+        )?;
+        if let Some(instr) = self.instr {
+            write!(f, " ({instr})")?;
+        }
+        f.write_str(": ")?;
+        match &self.difference {
+            Difference::Pc(pc_a, pc_b) => {
+                write!(f, "next pc {pc_a:#x} under {a}, {pc_b:#x} under {b}")
+            }
+            Difference::GpReg {
+                reg,
+                a: value_a,
+                b: value_b,
+            } => write!(
+                f,
+                "{} is {value_a:#x} under {a}, {value_b:#x} under {b}",
+                Register::gp(*reg)
+            ),
+            Difference::FpReg {
+                reg,
+                a: value_a,
+                b: value_b,
+            } => write!(
+                f,
+                "{} is {value_a:#x} under {a}, {value_b:#x} under {b}",
+                Register::fp(*reg)
+            ),
+            Difference::Fcsr(fcsr_a, fcsr_b) => {
+                write!(f, "fcsr is {fcsr_a:#x} under {a}, {fcsr_b:#x} under {b}")
+            }
+            Difference::Memory {
+                addr,
+                a: bytes_a,
+                b: bytes_b,
+            } => write!(
+                f,
+                "memory at {addr:#x} is {bytes_a:02x?} under {a}, {bytes_b:02x?} under {b}"
+            ),
+            Difference::Output {
+                stream,
+                offset,
+                a: byte_a,
+                b: byte_b,
+            } => {
+                let byte = |byte: &Option<u8>| match byte {
+                    Some(byte) => format!("{byte:#04x}"),
+                    None => "nothing".to_owned(),
+                };
+                write!(
+                    f,
+                    "byte {offset} of {stream} is {} under {a}, {} under {b}",
+                    byte(byte_a),
+                    byte(byte_b)
+                )
+            }
+            Difference::Stopped(stop_a, stop_b) => {
+                write!(f, "{stop_a} under {a}, {stop_b} under {b}")
+            }
+        }
+    }
+}
+
+// both cores as they were at a point they agreed, with how much they'd
+// written to stdout and stderr
+struct Saved {
+    a: Snapshot,
+    b: Snapshot,
+    output: (usize, usize),
+}
+
+/// Two cores running the same program, see the module docs
+pub struct Lockstep<A: MemReader<Idx = u32>, B: MemReader<Idx = u32>> {
+    a: Core32<A>,
+    b: Core32<B>,
+    configs: (String, String),
+    chunk: u64,
+}
+
+impl<A: MemReader<Idx = u32>, B: MemReader<Idx = u32>> Lockstep<A, B> {
+    /// Compares `a` and `b` every `chunk` instructions, naming them by
+    /// `configs`. Both are to have been set up alike, and with their
+    /// output captured
+    pub fn new(a: Core32<A>, b: Core32<B>, configs: (String, String), chunk: u64) -> Self {
+        Self {
+            a,
+            b,
+            configs,
+            chunk: chunk.max(1),
+        }
+    }
+
+    /// Runs both to the end, returning how they stopped, or where they
+    /// first differ
+    pub fn run(&mut self) -> Result<RunInfo, Box<Divergence>> {
+        loop {
+            let start = self.save();
+            let (info, difference) = self.advance(self.chunk);
+            if let Some(difference) = difference {
+                return Err(Box::new(self.narrow(start, self.chunk, difference)));
+            }
+            if !matches!(info.reason, StopReason::InstructionLimit { .. }) {
+                return Ok(info);
+            }
+        }
+    }
+
+    fn save(&self) -> Saved {
+        let snapshot = |mut snapshot: Snapshot| {
+            // the output's captured, so there's nothing of the host's to
+            // put back
+            snapshot.fd_offsets.clear();
+            snapshot
+        };
+        Saved {
+            a: snapshot(self.a.snapshot()),
+            b: snapshot(self.b.snapshot()),
+            output: (
+                self.a.captured_stdout().len(),
+                self.a.captured_stderr().len(),
+            ),
+        }
+    }
+
+    fn restore(&mut self, saved: &Saved) {
+        self.a
+            .restore(&saved.a)
+            .expect("a core's own snapshot restores");
+        self.b
+            .restore(&saved.b)
+            .expect("a core's own snapshot restores");
+        let (stdout, stderr) = saved.output;
+        self.a.truncate_captured(stdout, stderr);
+        self.b.truncate_captured(stdout, stderr);
+    }
+
+    // runs both up to `count` instructions, returning how `a` stopped and
+    // what differs after
+    fn advance(&mut self, count: u64) -> (RunInfo, Option<Difference>) {
+        // their output so far is the same, or they'd have been stopped
+        let output = (
+            self.a.captured_stdout().len(),
+            self.a.captured_stderr().len(),
+        );
+
+        let limit = self.a.instret() + count;
+        self.a.set_instruction_limit(Some(limit));
+        self.b.set_instruction_limit(Some(limit));
+        let info_a = self.a.run();
+        let info_b = self.b.run();
+        self.a.set_instruction_limit(None);
+        self.b.set_instruction_limit(None);
+
+        let difference = self.compare(&info_a, &info_b, output);
+        (info_a, difference)
+    }
+
+    // bisects the `len` instructions after `start`, which end with
+    // `difference`, down to the first after which the cores differ
+    fn narrow(&mut self, mut start: Saved, len: u64, mut difference: Difference) -> Divergence {
+        let (mut lo, mut hi) = (0, len);
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            self.restore(&start);
+            match self.advance(mid - lo).1 {
+                Some(found) => {
+                    difference = found;
+                    hi = mid;
+                }
+                None => {
+                    start = self.save();
+                    lo = mid;
+                }
+            }
+        }
+
+        self.restore(&start);
+        let pc = self.a.pc();
+        let instr = self
+            .a
+            .memory()
+            .get(pc as usize..pc as usize + 4)
+            .map(|word| Instruction::decode(u32::from_le_bytes(word.try_into().unwrap())));
+        let instret = self.a.instret();
+        // were the cores not to do the same again, the difference last found
+        // still stands
+        if let Some(found) = self.advance(1).1 {
+            difference = found;
+        }
+
+        Divergence {
+            configs: self.configs.clone(),
+            instret,
+            pc,
+            instr,
+            difference,
+        }
+    }
+
+    fn compare(
+        &self,
+        info_a: &RunInfo,
+        info_b: &RunInfo,
+        (stdout, stderr): (usize, usize),
+    ) -> Option<Difference> {
+        let (a, b) = (&self.a, &self.b);
+        if a.pc() != b.pc() {
+            return Some(Difference::Pc(a.pc(), b.pc()));
+        }
+
+        let (gp_a, gp_b) = (a.gp_regs(), b.gp_regs());
+        if let Some(reg) = (0..32).find(|&reg| gp_a[reg] != gp_b[reg]) {
+            return Some(Difference::GpReg {
+                reg: reg as u8,
+                a: gp_a[reg] as u32,
+                b: gp_b[reg] as u32,
+            });
+        }
+        let (fp_a, fp_b) = (a.fp_regs(), b.fp_regs());
+        if let Some(reg) = (0..32).find(|&reg| fp_a[reg] != fp_b[reg]) {
+            return Some(Difference::FpReg {
+                reg: reg as u8,
+                a: fp_a[reg],
+                b: fp_b[reg],
+            });
+        }
+        let (fcsr_a, fcsr_b) = (a.read_csr(csr::FCSR), b.read_csr(csr::FCSR));
+        if fcsr_a != fcsr_b {
+            return Some(Difference::Fcsr(
+                fcsr_a.unwrap_or_default(),
+                fcsr_b.unwrap_or_default(),
+            ));
+        }
+
+        let (memory_a, memory_b) = (a.memory(), b.memory());
+        if memory_a != memory_b {
+            let at = memory_a
+                .iter()
+                .zip(memory_b)
+                .position(|(byte_a, byte_b)| byte_a != byte_b)
+                .unwrap_or(memory_a.len().min(memory_b.len()));
+            let word = at & !3..(at & !3) + 4;
+            let bytes = |memory: &[u8]| memory.get(word.clone()).unwrap_or_default().to_vec();
+            return Some(Difference::Memory {
+                addr: word.start as u32,
+                a: bytes(memory_a),
+                b: bytes(memory_b),
+            });
+        }
+
+        for (stream, new_a, new_b, offset) in [
+            (
+                "stdout",
+                &a.captured_stdout()[stdout..],
+                &b.captured_stdout()[stdout..],
+                stdout,
+            ),
+            (
+                "stderr",
+                &a.captured_stderr()[stderr..],
+                &b.captured_stderr()[stderr..],
+                stderr,
+            ),
+        ] {
+            if new_a != new_b {
+                let at = (0..).find(|&at| new_a.get(at) != new_b.get(at)).unwrap();
+                return Some(Difference::Output {
+                    stream,
+                    offset: offset + at,
+                    a: new_a.get(at).copied(),
+                    b: new_b.get(at).copied(),
+                });
+            }
+        }
+
+        let stopped = |info: &RunInfo| match info.reason {
+            StopReason::InstructionLimit { .. } => "still running".to_owned(),
+            reason => format!("{} with {}", reason.name(), info.return_code),
+        };
+        if info_a.reason != info_b.reason || info_a.return_code != info_b.return_code {
+            return Some(Difference::Stopped(stopped(info_a), stopped(info_b)));
+        }
+        None
+    }
+}
@@ -0,0 +1,410 @@
+//! Putting a machine together from named parts, the structured way to make a
+//! core.
+//!
+//! `Core32::new` takes a program, entry point, memory size and debug flag in a
+//! row, and everything else is set on the core after. A `MachineBuilder` names
+//! each part, checks they fit together, and builds a `Machine` owning the
+//! harts, their memory and the devices on it:
+//!
+//! ```no_run
+//! # use risc_y::{load::LoadedElf, machine::{Machine, MachineBuilder}, uart::Uart};
+//! let elf = LoadedElf::load("firmware.elf").unwrap();
+//! let mut machine: Machine = MachineBuilder::new(elf)
+//!     .memory(64 << 20)
+//!     .isa("rv32imafd_zicsr")
+//!     .device(Uart::at(0x1000_0000))
+//!     .build()
+//!     .unwrap();
+//! let info = machine.run();
+//! ```
+//!
+//! A device is attached by its address alone, as its type says how big its
+//! window is, see `Mmio`. The ISA string is checked against what riscy
+//! implements, which is RV32 `imafd` with `g`'s `zicsr` and `zifencei`, and
+//! `zicntr`, `zihpm`, `zfa`, `zacas`, `zawrs` and `ztso`, so a program built
+//! for more is refused rather than stopping at its first unknown instruction,
+//! as is a program whose ELF header says it needs more, see
+//! `ElfTarget::check`.
+//! Naming fewer doesn't make the rest illegal. A vendor's extensions, like
+//! `xthead`, have its handler registered, as `riscy --isa-vendor` does. Only
+//! one hart is supported for now, as the core has no way to share memory with
+//! another.
+//!
+//! The memory model, RVWMO or TSO, is chosen with
+//! `MachineBuilder::memory_model`, or by `ztso` in the ISA string, or by the
+//! ELF header for a program built for TSO, which is refused under RVWMO. One hart running in order already
+//! has every ordering either allows, so it makes no difference yet: it's for
+//! the atomics and fences once harts share memory, and a torture mode
+//! randomizing the reorderings RVWMO allows needs another hart to see them.
+//!
+//! A program can ask for its memory, ISA and devices itself, in a
+//! `.note.riscy`, see `elfnote`. What the builder is given wins, and a device
+//! the note asks for isn't attached where the builder already has one.
+
+use std::{fmt, io, str::FromStr};
+
+use crate::{
+    console::{self, MagicConsole},
+    core::{AdaptiveMemReader, Core32, MemReader, RunInfo},
+    custom::IsaVendor,
+    elfnote::DeviceSpec,
+    load::LoadedElf,
+    region::Device,
+    uart::Uart,
+};
+
+// as `riscy --size`
+const DEFAULT_MEMORY: usize = 16 << 20;
+
+// the single-letter extensions implemented, in canonical order
+const LETTERS: &str = "imafd";
+const Z_EXTENSIONS: &[&str] = &[
+    "zicsr", "zifencei", "zicntr", "zihpm", "zfa", "zacas", "zawrs", "ztso",
+];
+
+/// How the harts' memory accesses may be reordered as seen by each other, see
+/// the module docs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MemoryModel {
+    /// The RISC-V weak memory ordering, the base ISA's
+    #[default]
+    Rvwmo,
+    /// Total store ordering, the Ztso extension's
+    Tso,
+}
+
+impl FromStr for MemoryModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rvwmo" => Ok(MemoryModel::Rvwmo),
+            "tso" => Ok(MemoryModel::Tso),
+            _ => Err(format!("unknown memory model '{s}', expected rvwmo or tso")),
+        }
+    }
+}
+
+impl fmt::Display for MemoryModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MemoryModel::Rvwmo => "rvwmo",
+            MemoryModel::Tso => "tso",
+        })
+    }
+}
+
+/// A device with a window of a fixed size, so it can be attached at an
+/// address alone
+pub trait Mmio: Device + Sized + 'static {
+    /// How many bytes of the address space its registers take
+    const WINDOW: u64;
+}
+
+/// A device and where it goes, for `MachineBuilder::device`
+pub struct At<D> {
+    pub addr: u32,
+    pub device: D,
+}
+
+impl<D: Mmio> At<D> {
+    pub fn new(addr: u32, device: D) -> Self {
+        Self { addr, device }
+    }
+}
+
+/// What a machine's harts implement, parsed from an ISA string like
+/// `rv32imafd_zicsr_zifencei` or `rv32g`, see the module docs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Isa {
+    /// The single-letter extensions, `i` first
+    pub letters: String,
+    /// The multi-letter ones, like `zicsr`
+    pub extensions: Vec<String>,
+    pub vendor: Option<IsaVendor>,
+}
+
+impl FromStr for Isa {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        let rest = match lower.strip_prefix("rv32") {
+            Some(rest) => rest,
+            None if lower.starts_with("rv64") => {
+                return Err(format!("'{s}' is RV64, riscy only runs RV32"))
+            }
+            None => return Err(format!("invalid ISA '{s}', expected one like rv32imafd")),
+        };
+
+        let mut parts = rest.split('_');
+        let base = parts.next().unwrap_or_default();
+        let mut letters = String::new();
+        let mut extensions = Vec::new();
+
+        // the letters run until the first multi-letter extension
+        let (single, multi) = base
+            .find(['z', 'x', 's'])
+            .map_or((base, ""), |at| base.split_at(at));
+        for (idx, letter) in single.chars().enumerate() {
+            match letter {
+                'i' if idx == 0 => {}
+                'g' if idx == 0 => {
+                    letters.push_str("mafd");
+                    add(&mut extensions, "zicsr");
+                    add(&mut extensions, "zifencei");
+                }
+                'e' if idx == 0 => return Err("RV32E isn't supported".to_owned()),
+                _ if idx == 0 => return Err(format!("invalid ISA '{s}', expected rv32i or rv32g")),
+                'c' => {
+                    return Err(
+                        "the C extension isn't supported, as riscy has no compressed \
+                                instructions"
+                            .to_owned(),
+                    )
+                }
+                _ if LETTERS.contains(letter) => letters.push(letter),
+                _ => {
+                    return Err(format!(
+                        "the {} extension isn't supported",
+                        letter.to_ascii_uppercase()
+                    ))
+                }
+            }
+        }
+        if letters.contains('d') && !letters.contains('f') {
+            return Err("D needs F".to_owned());
+        }
+
+        let mut vendor = None;
+        for ext in std::iter::once(multi)
+            .chain(parts)
+            .filter(|ext| !ext.is_empty())
+        {
+            if Z_EXTENSIONS.contains(&ext) {
+                add(&mut extensions, ext);
+            } else if ext.starts_with('x') {
+                vendor = Some(ext.parse()?);
+            } else {
+                return Err(format!("the {ext} extension isn't supported"));
+            }
+        }
+
+        let mut sorted = String::from("i");
+        sorted.extend(LETTERS.chars().filter(|&letter| letters.contains(letter)));
+        Ok(Isa {
+            letters: sorted,
+            extensions,
+            vendor,
+        })
+    }
+}
+
+impl Isa {
+    /// Everything riscy implements, short of a vendor's extensions
+    pub fn implemented() -> Self {
+        Isa {
+            letters: LETTERS.to_owned(),
+            extensions: Z_EXTENSIONS.iter().map(|ext| ext.to_string()).collect(),
+            vendor: None,
+        }
+    }
+}
+
+// `ext`, unless it's already there
+fn add(extensions: &mut Vec<String>, ext: &str) {
+    if !extensions.iter().any(|have| have == ext) {
+        extensions.push(ext.to_owned());
+    }
+}
+
+impl fmt::Display for Isa {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rv32{}", self.letters)?;
+        for ext in &self.extensions {
+            write!(f, "_{ext}")?;
+        }
+        match self.vendor {
+            Some(IsaVendor::XThead) => write!(f, "_xthead"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Builds a `Machine`, see the module docs
+pub struct MachineBuilder {
+    elf: LoadedElf,
+    entrypoint: Option<u64>,
+    memory: Option<usize>,
+    isa: Option<String>,
+    devices: Vec<(u32, u64, Box<dyn Device>)>,
+    harts: usize,
+    memory_model: Option<MemoryModel>,
+    debug: bool,
+}
+
+impl MachineBuilder {
+    /// A machine running `elf`, with 16 MiB of memory, unless its note asks
+    /// for more or less, and one hart
+    pub fn new(elf: LoadedElf) -> Self {
+        Self {
+            elf,
+            entrypoint: None,
+            memory: None,
+            isa: None,
+            devices: Vec::new(),
+            harts: 1,
+            memory_model: None,
+            debug: false,
+        }
+    }
+
+    /// Starts at `addr` rather than the ELF's entry point
+    pub fn entrypoint(mut self, addr: u64) -> Self {
+        self.entrypoint = Some(addr);
+        self
+    }
+
+    /// `bytes` of guest memory
+    pub fn memory(mut self, bytes: usize) -> Self {
+        self.memory = Some(bytes);
+        self
+    }
+
+    /// What the harts implement, checked by `build`, see `Isa`
+    pub fn isa(mut self, isa: &str) -> Self {
+        self.isa = Some(isa.to_owned());
+        self
+    }
+
+    /// Attaches `device` at its address
+    pub fn device<D: Mmio>(mut self, device: At<D>) -> Self {
+        self.devices
+            .push((device.addr, D::WINDOW, Box::new(device.device)));
+        self
+    }
+
+    /// How many harts to have, of which `build` only supports 1
+    pub fn hart_count(mut self, harts: usize) -> Self {
+        self.harts = harts;
+        self
+    }
+
+    /// Orders memory accesses as `model` does, rather than as the ISA or the
+    /// ELF header say, see the module docs
+    pub fn memory_model(mut self, model: MemoryModel) -> Self {
+        self.memory_model = Some(model);
+        self
+    }
+
+    /// Traces every instruction, as `riscy --debug`
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// The machine, or why the parts don't fit
+    pub fn build<Reader: MemReader<Idx = u32>>(self) -> Result<Machine<Reader>, String> {
+        if self.harts != 1 {
+            return Err(format!(
+                "riscy only supports a single hart, not {}",
+                self.harts
+            ));
+        }
+        let config = self.elf.config.clone();
+        let isa = match self.isa.as_ref().or(config.isa.as_ref()) {
+            Some(isa) => isa.parse()?,
+            None => Isa::from_str("rv32g")?,
+        };
+        self.elf.target.check(&isa)?;
+        let ztso = isa.extensions.iter().any(|ext| ext == "ztso");
+        let memory_model = match self.memory_model {
+            Some(MemoryModel::Rvwmo) if ztso => {
+                return Err(format!("{isa} has Ztso, so can't be RVWMO"))
+            }
+            Some(MemoryModel::Rvwmo) if self.elf.target.tso => {
+                return Err("the binary was built for TSO, so can't run under RVWMO".to_owned())
+            }
+            Some(model) => model,
+            None if ztso || self.elf.target.tso => MemoryModel::Tso,
+            None => MemoryModel::Rvwmo,
+        };
+
+        let memory = self.memory.or(config.memory).unwrap_or(DEFAULT_MEMORY);
+        let mut core = Core32::new(self.elf, self.entrypoint, memory, self.debug);
+        if let Some(vendor) = isa.vendor {
+            let (opcode, handler) = vendor.handler();
+            core.register_custom(opcode, handler);
+        }
+        let mut devices = self.devices;
+        for device in &config.devices {
+            let (addr, window, mapped): (u32, u64, Box<dyn Device>) = match *device {
+                DeviceSpec::Uart(addr) => {
+                    (addr, Uart::<io::Stdout>::WINDOW, Box::new(Uart::stdout()))
+                }
+                DeviceSpec::Console(addr) => {
+                    (addr, console::WINDOW, Box::new(MagicConsole::stdout()))
+                }
+                DeviceSpec::Htif => {
+                    core.enable_htif()
+                        .map_err(|err| format!("the binary's riscy note asks for htif: {err}"))?;
+                    continue;
+                }
+            };
+            if !devices.iter().any(|&(have, ..)| have == addr) {
+                devices.push((addr, window, mapped));
+            }
+        }
+        for (addr, len, device) in devices {
+            core.map_device(addr, len, device)
+                .map_err(|err| format!("device at {addr:#x}: {err}"))?;
+        }
+
+        Ok(Machine {
+            harts: vec![core],
+            isa,
+            memory_model,
+        })
+    }
+}
+
+/// A built machine, owning its harts, their memory and devices. Not to be
+/// confused with `custom::Machine`, what a custom instruction sees of one
+pub struct Machine<Reader: MemReader<Idx = u32> = AdaptiveMemReader<u32>> {
+    harts: Vec<Core32<Reader>>,
+    isa: Isa,
+    memory_model: MemoryModel,
+}
+
+impl<Reader: MemReader<Idx = u32>> Machine<Reader> {
+    pub fn isa(&self) -> &Isa {
+        &self.isa
+    }
+
+    pub fn hart_count(&self) -> usize {
+        self.harts.len()
+    }
+
+    pub fn memory_model(&self) -> MemoryModel {
+        self.memory_model
+    }
+
+    /// Hart `hart`'s core
+    pub fn hart(&self, hart: usize) -> &Core32<Reader> {
+        &self.harts[hart]
+    }
+
+    pub fn hart_mut(&mut self, hart: usize) -> &mut Core32<Reader> {
+        &mut self.harts[hart]
+    }
+
+    /// Runs the machine until it stops
+    pub fn run(&mut self) -> RunInfo {
+        self.harts[0].run()
+    }
+
+    /// The core, for a machine of one hart
+    pub fn into_core(self) -> Core32<Reader> {
+        self.harts.into_iter().next().unwrap()
+    }
+}
@@ -1,57 +1,1865 @@
-use std::{error::Error, process::ExitCode};
+use std::{
+    env,
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter, IsTerminal, Write},
+    num::NonZeroUsize,
+    path::PathBuf,
+    process::ExitCode,
+    time::Instant,
+};
 
-use clap::Parser;
-use core::{AlignedMemReader, Core32, MemReader, RunInfo, UnalignedMemReader};
-use load::LoadedElf;
+use anyhow::anyhow;
+use clap::{Parser, Subcommand};
+use risc_y::{
+    align::AlignmentCounter,
+    batch::{self, BatchConfig, BatchJob, ReportFormat},
+    breakpoint::{Breakpoints, SyscallSpec},
+    call::ArgValue,
+    cfi::ShadowStack,
+    checkpoint::Snapshot,
+    compare,
+    console::{self, ConsoleAddr, MagicConsole},
+    core::{
+        AdaptiveMemReader, AlignedMemReader, Core32, MemReader, RunInfo, StopReason,
+        UnalignedMemReader,
+    },
+    cost::{CostCounter, CostWeights},
+    ctrace::{self, TraceReader, TraceStats, TraceWriter},
+    custom::IsaVendor,
+    elfnote::ElfConfig,
+    expect::{ExpectScript, Interaction},
+    fds::Preopen,
+    flamegraph,
+    fuzz::{Buckets, FuzzInput, Harness, Mutator},
+    hang::HangDetector,
+    heap::HeapTracker,
+    hooks::{RegisterWatch, WatchMode, WatchSpec},
+    hpm::HpmMapping,
+    identity::Uname,
+    inject::FaultSpec,
+    invariant::{InvariantSpec, Invariants},
+    ioctl::TtyMode,
+    irq::IrqSchedule,
+    limits::{ByteSize, ResourceLimits},
+    load::{self, ExtraElf, LoadedElf, Symbol},
+    lockstep::{self, CoreConfig, Lockstep, ReaderKind},
+    machine::{Isa, Mmio},
+    mmap::Hugepages,
+    mux::{Mux, MuxConfig, Stream, StreamFile, Timestamps},
+    native, oracle,
+    patch::{Location, PatchSpec},
+    pipeline::{PipelineConfig, PipelineModel},
+    progress::ProgressInterval,
+    region::Device,
+    register::Register,
+    replay::SyscallTape,
+    report::RunReport,
+    sample::CallStack,
+    session::Session,
+    signature,
+    stat::StatSpoof,
+    stub::StubSpec,
+    taint::{TaintSource, TaintTracker},
+    throttle::Mips,
+    timeout::{IoDuration, IoTimeouts, TimeoutAction},
+    tmin::{CrashSignature, Minimizer},
+    trace::{TraceAddr, TraceServer},
+    tracer::PcTrace,
+    uart::Uart,
+    writelog::{self, WriteLog, WriteRange, WriteRecorder},
+};
+use tracing::{error, info, info_span, warn};
+use tracing_subscriber::{fmt::writer::BoxMakeWriter, EnvFilter};
 
-mod core;
-mod instruction;
-mod load;
+#[cfg(feature = "metrics")]
+use risc_y::metrics::Metrics;
+#[cfg(feature = "script")]
+use risc_y::script::Script;
+#[cfg(feature = "tui")]
+use risc_y::tui;
+
+// guest memory unless `--size` or the binary's note says
+const DEFAULT_SIZE: usize = 16777215;
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(required = true)]
+    file: Option<String>,
+
+    #[arg(short, long, conflicts_with = "entry_symbol")]
+    entrypoint: Option<u64>,
+
+    /// Start execution at the named symbol instead of the ELF entrypoint
+    #[arg(long, value_name = "SYMBOL")]
+    entry_symbol: Option<String>,
+
+    /// Load the ELF at PATH into the same memory, its lowest segment at ADDR,
+    /// with its symbols alongside the program's (e.g. `boot.elf@0x1000`)
+    #[arg(long, value_name = "PATH@ADDR")]
+    load_extra: Vec<ExtraElf>,
+
+    /// Enter the entrypoint as a function call, stopping and reporting the return value when it returns
+    #[arg(long)]
+    call: bool,
+
+    /// Argument for `--call`: an integer (`42`, `0x10`), double (`1.5`) or single (`1.5f`)
+    #[arg(long, requires = "call", allow_negative_numbers = true)]
+    arg: Vec<ArgValue>,
+
+    /// Skip checking loads and stores for misalignment, which is only sound if
+    /// the guest never makes a misaligned one (see `--alignment-report`)
+    #[arg(long)]
+    assume_aligned: bool,
+
+    /// Bytes of guest memory, 16777215 unless given or the binary's riscy
+    /// note asks for more or less, see `elfnote`
+    #[arg(short, long)]
+    size: Option<usize>,
+
+    #[arg(short, long)]
+    debug: bool,
+
+    /// With --debug, also show the fp registers each fp instruction reads and
+    /// writes, and fflags after it
+    #[arg(long, requires = "debug")]
+    debug_fp: bool,
+
+    /// Write the pc of every instruction run to FILE, in hex one to a line
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["debug", "self_check", "tui"])]
+    trace_pcs: Option<PathBuf>,
+
+    /// Write a compact binary trace of every instruction run to FILE, for
+    /// `riscy trace-stats` and `riscy trace-grep`
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["debug", "self_check", "tui", "trace_pcs"]
+    )]
+    trace_compact: Option<PathBuf>,
+
+    /// Drive the guest's stdin with the expect-style script at FILE, see
+    /// `expect`, failing if the guest stops before the script is done
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["self_check", "tui", "trace_pcs"])]
+    expect: Option<PathBuf>,
+
+    /// Run a reference interpreter in lockstep and stop at the first divergence
+    #[arg(long)]
+    self_check: bool,
+
+    /// Stop as soon as the given register (e.g. `a5`, `x14`, `fs3`) is written
+    #[arg(long, value_name = "REG")]
+    break_on_write: Vec<Register>,
+
+    /// Stop before every call of the given syscall, by name (`write`) or number
+    #[arg(long, value_name = "SYSCALL")]
+    break_syscall: Vec<SyscallSpec>,
+
+    /// Stop before every call to the given function (e.g. `malloc`)
+    #[arg(long, value_name = "FUNCTION")]
+    break_call: Vec<String>,
+
+    /// Replace the guest function FUNCTION with a host behaviour: `return:VALUE`,
+    /// `ignore` or `fail` (e.g. `malloc=return:0x80001000`)
+    #[arg(long, value_name = "FUNCTION=ACTION")]
+    stub: Vec<StubSpec>,
+
+    /// Service calls to the libm or compiler-rt function FUNCTION natively,
+    /// e.g. `expf` or `__muldf3`, or `all` for every one riscy knows
+    #[arg(long, value_name = "FUNCTION")]
+    native: Vec<String>,
+
+    /// Replace the instruction at LOCATION (an address, SYMBOL or SYMBOL+OFFSET)
+    /// with `nop`, `ret`, `j:LOCATION` or a word in hex (e.g. `poll+0x8=nop`)
+    #[arg(long, value_name = "LOCATION=PATCH")]
+    patch: Vec<PatchSpec>,
+
+    /// Log every write to a register (e.g. `a5`); `a5:break` also stops once it changes
+    #[arg(long, value_name = "REG[:break]")]
+    watch_reg: Vec<WatchSpec>,
+
+    /// Check every return against a shadow stack, stopping at the first that
+    /// doesn't go back to its caller
+    #[arg(long)]
+    shadow_stack: bool,
+
+    /// Stop as soon as a condition on the registers stops holding, e.g.
+    /// `sp in __stack_start..=__stack_top` or `gp unchanged after _start`
+    #[arg(long, value_name = "CONDITION")]
+    invariant: Vec<InvariantSpec>,
+
+    /// Track data from SOURCE (`stdin`, `fd:N` or `mem:ADDR:LEN`) through the
+    /// program, reporting when it reaches a jump target, syscall or sink
+    #[arg(long, value_name = "SOURCE")]
+    taint: Vec<TaintSource>,
+
+    /// Also report tainted arguments to calls to FUNCTION
+    #[arg(long, value_name = "FUNCTION", requires = "taint")]
+    taint_sink: Vec<String>,
+
+    /// Stop with a possible hang once no new pc has been reached in N instructions
+    #[arg(long, value_name = "N")]
+    hang_new_pc: Option<u64>,
+
+    /// Stop with a possible hang after N instructions without a store or syscall
+    #[arg(long, value_name = "N")]
+    hang_no_progress: Option<u64>,
+
+    /// Print a progress report every INTERVAL, either a time (`10s`, `500ms`) or a
+    /// number of instructions (`100M`)
+    #[arg(long, value_name = "INTERVAL", num_args = 0..=1, default_missing_value = "5s")]
+    progress: Option<ProgressInterval>,
+
+    /// Sample the guest's call stack every `--sample-every` and write the
+    /// samples to FILE as folded stacks, for flamegraph tools
+    #[arg(long, value_name = "FILE")]
+    sample_profile: Option<PathBuf>,
+
+    /// Sample the guest's call stack, tracking every call so stacks are exact,
+    /// and write a flame graph to FILE, as SVG if it ends in `.svg` and folded
+    /// stacks otherwise
+    #[arg(long, value_name = "FILE")]
+    flamegraph: Option<PathBuf>,
+
+    /// How often `--sample-profile` and `--flamegraph` sample, a time (`1ms`)
+    /// or a number of instructions (`100k`)
+    #[arg(long, value_name = "INTERVAL", default_value = "100k")]
+    sample_every: ProgressInterval,
+
+    /// Count instructions by class and print an energy estimate, weighting each
+    /// class as in WEIGHTS (e.g. `load=5,fp=3.5`) or by the defaults
+    #[arg(
+        long,
+        value_name = "WEIGHTS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    energy: Option<CostWeights>,
+
+    /// Model a 5-stage in-order pipeline alongside the run and print its CPI and
+    /// stalls, configured as in CONFIG (e.g. `forwarding=off,branch-penalty=1`)
+    #[arg(
+        long,
+        value_name = "CONFIG",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    pipeline: Option<PipelineConfig>,
+
+    /// Map guest memory 2 MiB aligned (`align`), and also ask for transparent
+    /// hugepages (`madvise`, the default)
+    #[arg(
+        long,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "madvise",
+        default_value = "off"
+    )]
+    hugepages: Hugepages,
+
+    /// Report how much of guest memory the host committed
+    #[arg(long)]
+    memory_stats: bool,
+
+    /// Report the instructions retired and the time spent decoding the
+    /// guest's code
+    #[arg(long)]
+    stats: bool,
+
+    /// Write a JSON summary of the run to FILE once it stops, see `report`
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+
+    /// Write the memory between the `begin_signature` and `end_signature`
+    /// symbols to FILE once the run stops, as the RISC-V architecture tests
+    /// expect, see `signature`
+    #[arg(long, value_name = "FILE")]
+    signature: Option<PathBuf>,
+
+    /// Bytes per line of the --signature file
+    #[arg(long, value_name = "N", default_value = "4")]
+    signature_granularity: NonZeroUsize,
+
+    /// Count loads and stores and report the misaligned ones, by pc and symbol
+    #[arg(long)]
+    alignment_report: bool,
+
+    /// Track the guest's malloc and free, and report its heap use and the
+    /// blocks still live at exit, by the pc that allocated them
+    #[arg(long)]
+    heap_report: bool,
+
+    /// Hand the guest the host file or directory PATH as fd FD, read-only; it
+    /// gets no other host files but stdin, stdout and stderr
+    #[arg(long, value_name = "FD=PATH")]
+    preopen: Vec<Preopen>,
+
+    /// Like `--preopen`, but writable, creating a file that doesn't exist
+    #[arg(long, value_name = "FD=PATH")]
+    preopen_rw: Vec<Preopen>,
+
+    /// Make the pre-opened directory FD the guest's `/` and its current
+    /// directory, as if chrooted into it
+    #[arg(long, value_name = "FD")]
+    root: Option<i32>,
+
+    /// Give up on any single guest read or write of a host file, like stdin,
+    /// that blocks for longer than DURATION (e.g. `5s`, `500ms`)
+    #[arg(long, value_name = "DURATION")]
+    io_timeout: Option<IoDuration>,
+
+    /// Give up on any guest read or write of a host file still blocking
+    /// DURATION after the start of the run
+    #[arg(long, value_name = "DURATION")]
+    io_deadline: Option<IoDuration>,
+
+    /// What a read or write that gives up does: `stop` the run (exiting with
+    /// 124), or fail in the guest with `eintr` or `etimedout`
+    #[arg(long, value_name = "ACTION", default_value = "stop")]
+    io_timeout_action: TimeoutAction,
+
+    /// Refuse to grow the guest's heap with brk past SIZE bytes (e.g. `64M`)
+    #[arg(long, value_name = "SIZE")]
+    max_memory: Option<ByteSize>,
+
+    /// Fail guest opens with EMFILE once N files are open, stdio included
+    #[arg(long, value_name = "N")]
+    max_fds: Option<usize>,
+
+    /// Fail guest writes with EFBIG once SIZE bytes have been written in all
+    #[arg(long, value_name = "SIZE")]
+    max_output: Option<ByteSize>,
+
+    /// Map a console at ADDR, 0x10000000 unless given, where storing a byte
+    /// prints it and storing a word exits with it, see `console`
+    #[arg(
+        long,
+        value_name = "ADDR",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "0x10000000"
+    )]
+    console: Option<ConsoleAddr>,
+
+    /// Service the commands the guest writes to its `tohost` symbol, for
+    /// binaries built for riscv-pk or libgloss-htif, see `htif`
+    #[arg(long)]
+    htif: bool,
+
+    /// Implement VENDOR's extensions, for binaries built for its cores:
+    /// `xthead` for T-Head's C906 and C910
+    #[arg(long, value_name = "VENDOR")]
+    isa_vendor: Option<IsaVendor>,
+
+    /// Raise interrupt IRQ (0 to 31) once AT instructions have run, then every
+    /// EVERY after, for the guest to take as a machine-mode trap through
+    /// `mtvec` (e.g. `7@1_000_000+10_000`), see `irq`
+    #[arg(
+        long,
+        value_name = "IRQ@AT[+EVERY]",
+        conflicts_with_all = ["self_check", "checkpoint_every", "resume"]
+    )]
+    irq: Vec<IrqSchedule>,
+
+    /// Let the guest store to its text segment, for self-modifying code,
+    /// instead of stopping the run
+    #[arg(long)]
+    writable_text: bool,
+
+    /// Step every instruction through the run loop, rather than running hot
+    /// paths as superblocks
+    #[arg(long)]
+    no_superblocks: bool,
+
+    /// Form the superblocks saved in FILE by an earlier run of the same
+    /// program before this one starts, and save this run's there after
+    #[arg(long, value_name = "FILE", conflicts_with = "no_superblocks")]
+    superblock_cache: Option<PathBuf>,
+
+    /// Record every syscall the guest makes, and what it got back, to FILE
+    #[arg(long, value_name = "FILE", conflicts_with = "replay_syscalls")]
+    record_syscalls: Option<PathBuf>,
+
+    /// Log every write the guest makes to memory, with what it wrote over, to
+    /// FILE, which `riscy writes` answers questions about
+    #[arg(long, value_name = "FILE")]
+    record_writes: Option<PathBuf>,
+
+    /// Only log writes to ADDR:LEN (e.g. `0x80012000:0x1000`), which can be
+    /// given more than once
+    #[arg(long, value_name = "ADDR:LEN", requires = "record_writes")]
+    record_writes_range: Vec<WriteRange>,
+
+    /// Serve the guest's syscalls from a recording made with
+    /// --record-syscalls, failing at the first that doesn't match
+    #[arg(long, value_name = "FILE")]
+    replay_syscalls: Option<PathBuf>,
+
+    /// Derive the guest's clocks from the instruction count and its random bytes
+    /// from SEED, so every run is identical
+    #[arg(
+        long,
+        value_name = "SEED",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "0"
+    )]
+    deterministic: Option<u64>,
+
+    /// Run at most MIPS million instructions a second, for timing-sensitive
+    /// code, with --deterministic's clock counting at that rate too
+    #[arg(long, value_name = "MIPS")]
+    throttle: Option<Mips>,
+
+    /// Inject a fault: flip:REG:BIT@N or flip:ADDR:BIT@N to flip a bit once N
+    /// instructions have run, syscall:NAME=ERRNO[@K] to fail a syscall, or
+    /// load:P to corrupt loads with probability P, see `inject`
+    #[arg(long, value_name = "FAULT", conflicts_with = "self_check")]
+    inject: Vec<FaultSpec>,
+
+    /// Seed for which loads `--inject load:P` corrupts
+    #[arg(long, value_name = "SEED", default_value = "0")]
+    inject_seed: u64,
+
+    /// Report fixed values for these fields of every file the guest stats:
+    /// `atime`, `mtime`, `ctime` or `time` for all three, in seconds since the
+    /// epoch, `uid`, `gid` and `mode` in octal (e.g. `mtime=0,uid=0,gid=0`)
+    #[arg(long, value_name = "FIELD=VALUE,...")]
+    spoof_stat: Option<StatSpoof>,
+
+    /// What the guest's `uname` says, over a Linux 6.6 riscv32 machine's:
+    /// `sysname`, `nodename`, `release`, `version`, `machine` or `domainname`
+    /// (e.g. `release=5.15.0,nodename=build`)
+    #[arg(long, value_name = "FIELD=VALUE,...")]
+    uname: Option<Uname>,
+
+    /// Give the guest a `/proc/self/maps` and `/proc/cpuinfo` describing
+    /// itself rather than the host
+    #[arg(long)]
+    fake_proc: bool,
+
+    /// Prefix each line of output with the stream it came from: `stdout`,
+    /// `stderr`, `console`, `htif`, or `log` for riscy's own
+    #[arg(long)]
+    tag_output: bool,
+
+    /// Prefix each line of output with when it was written: `instret` for the
+    /// guest's instruction count, or `wall` for seconds since the start
+    #[arg(long, value_name = "CLOCK")]
+    timestamp_output: Option<Timestamps>,
+
+    /// Colour each stream's output differently where it goes to a terminal
+    #[arg(long)]
+    color_output: bool,
+
+    /// Send STREAM's output (`stdout`, `stderr`, `console`, `htif` or `log`)
+    /// to FILE rather than the terminal, several to the same FILE if named
+    /// more than once
+    #[arg(long, value_name = "STREAM=FILE")]
+    output_file: Vec<StreamFile>,
+
+    /// What the guest's fds are as terminals: `host` for the host's, `none`,
+    /// or `COLSxROWS` for stdin, stdout and stderr as terminals that size
+    #[arg(long, value_name = "MODE", default_value_t = TtyMode::Host)]
+    tty: TtyMode,
+
+    /// Make the guest's hpmcounterN (3 to 31) count EVENT: `syscalls`,
+    /// `host-ns`, or with the perf feature `host-task-clock`, `host-cycles`,
+    /// `host-instructions`, `host-cache-misses`, `host-branch-misses` or
+    /// `host-page-faults`
+    #[arg(long, value_name = "N=EVENT")]
+    hpm_counter: Vec<HpmMapping>,
+
+    /// Save a checkpoint every N instructions, which `--resume` carries on from
+    #[arg(long, value_name = "N")]
+    checkpoint_every: Option<u64>,
+
+    /// Where to save checkpoints, and resume from; `<FILE>.ckpt` by default
+    #[arg(long, value_name = "PATH")]
+    checkpoint_file: Option<PathBuf>,
+
+    /// Carry on from the last checkpoint instead of starting afresh
+    #[arg(long, conflicts_with = "call")]
+    resume: bool,
+
+    /// Wait for a client on ADDR (`unix:PATH` or `tcp:HOST:PORT`) and stream it
+    /// execution events, see `trace` for the format
+    #[arg(long, value_name = "ADDR")]
+    trace_server: Option<TraceAddr>,
+
+    /// Run the rhai script at FILE alongside the program, see `script` for the
+    /// functions it can define
+    #[cfg_attr(not(feature = "script"), arg(hide = true))]
+    #[arg(long, value_name = "FILE")]
+    script: Option<PathBuf>,
+
+    /// Step through the program in an interactive terminal debugger
+    #[cfg_attr(not(feature = "tui"), arg(hide = true))]
+    #[arg(long, conflicts_with_all = ["debug", "self_check", "call"])]
+    tui: bool,
+
+    /// Show the memory at ADDR (an address or symbol) in the debugger
+    #[cfg_attr(not(feature = "tui"), arg(hide = true))]
+    #[arg(long, value_name = "ADDR", requires = "tui")]
+    watch_mem: Vec<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run many programs, or one program over many inputs, across threads
+    Batch(BatchArgs),
+    /// Run a program under riscy and a reference emulator, and diff the results
+    Compare(CompareArgs),
+    /// Run a program under two of riscy's own configurations side by side, and
+    /// find the first instruction after which they differ
+    Lockstep(LockstepArgs),
+    /// Shrink an input that crashes a program, keeping the crash the same
+    Tmin(TminArgs),
+    /// Fuzz a program through its arguments and stdin, bucketing the crashes
+    Fuzz(FuzzArgs),
+    /// Say what wrote an address, or count the writes, from a log made with
+    /// --record-writes
+    Writes(WritesArgs),
+    /// Hold a machine for clients to load programs into and drive over a unix
+    /// socket, see `session`
+    Serve(ServeArgs),
+    /// Count what a trace from --trace-compact ran, by function, and how many
+    /// times its loops went round
+    TraceStats(TraceStatsArgs),
+    /// Find each time an instruction ran in a trace from --trace-compact
+    TraceGrep(TraceGrepArgs),
+    /// Serve the Debug Adapter Protocol, for debugging from an IDE
+    #[cfg(feature = "dap")]
+    Dap(DapArgs),
+}
+
+#[cfg(feature = "dap")]
+#[derive(clap::Args, Debug)]
+struct DapArgs {
+    /// Listen for the IDE on PORT instead of talking over stdio
+    #[arg(long)]
+    port: Option<u16>,
+}
+
+#[cfg(feature = "dap")]
+fn run_dap(args: &DapArgs) -> Result<ExitCode, Box<dyn Error>> {
+    match args.port {
+        Some(port) => {
+            let listener = std::net::TcpListener::bind(("127.0.0.1", port))?;
+            info!("waiting for a debugger on port {port}...");
+            let (stream, _) = listener.accept()?;
+            risc_y::dap::serve(stream.try_clone()?, stream)?;
+        }
+        None => risc_y::dap::serve(io::stdin(), io::stdout())?,
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+#[derive(clap::Args, Debug)]
+struct CompareArgs {
     file: String,
 
+    /// Reference emulator to run the program under
+    #[arg(long, default_value = "qemu-riscv32")]
+    against: String,
+
+    /// File to feed both runs as stdin
     #[arg(short, long)]
-    entrypoint: Option<u64>,
+    input: Option<String>,
 
+    /// Also compare instruction counts, using QEMU's insn plugin at PATH (`libinsn.so`)
+    #[arg(long, value_name = "PATH")]
+    insn_plugin: Option<String>,
+
+    /// Skip checking loads and stores for misalignment, which is only sound if
+    /// the guest never makes a misaligned one (see `--alignment-report`)
     #[arg(long)]
     assume_aligned: bool,
 
     #[arg(short, long, default_value = "16777215")]
     size: usize,
+}
+
+fn run_compare(args: &CompareArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let job = BatchJob {
+        program: args.file.clone(),
+        input: args.input.clone(),
+    };
+    let config = BatchConfig {
+        threads: None,
+        size: args.size,
+        assume_aligned: args.assume_aligned,
+        hang_new_pc: None,
+        hang_no_progress: None,
+        #[cfg(feature = "metrics")]
+        metrics: None,
+    };
+
+    let riscy = compare::run_riscy(&job, &config).map_err(|err| anyhow!(err))?;
+    let reference = compare::run_reference(&args.against, &job, args.insn_plugin.as_deref())
+        .map_err(|err| anyhow!(err))?;
+
+    let mismatches = compare::compare(&riscy, &reference);
+    if mismatches.is_empty() {
+        eprintln!("riscy and {} agree", args.against);
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    for mismatch in &mismatches {
+        eprintln!("{mismatch}");
+    }
+    Ok(ExitCode::FAILURE)
+}
+
+#[derive(clap::Args, Debug)]
+struct LockstepArgs {
+    file: String,
+
+    /// The first configuration, as READER[+OPTION...], see `lockstep`
+    #[arg(long, value_name = "CONFIG", default_value = "adaptive")]
+    a: CoreConfig,
+
+    /// The configuration to compare it with
+    #[arg(
+        long,
+        value_name = "CONFIG",
+        default_value = "unaligned+no-superblocks"
+    )]
+    b: CoreConfig,
 
+    /// File to feed both runs as stdin
     #[arg(short, long)]
-    debug: bool,
+    input: Option<PathBuf>,
+
+    /// Compare the two every N instructions, bisecting once they differ
+    #[arg(long, value_name = "N", default_value_t = lockstep::DEFAULT_CHUNK)]
+    chunk: u64,
+
+    /// Seed for the guest's random bytes, which with its clocks are virtual
+    #[arg(long, default_value = "0")]
+    seed: u64,
+
+    #[arg(short, long, default_value = "16777215")]
+    size: usize,
+}
+
+fn run_lockstep(args: &LockstepArgs) -> Result<ExitCode, Box<dyn Error>> {
+    match args.a.reader {
+        ReaderKind::Adaptive => run_lockstep_a::<AdaptiveMemReader<u32>>(args),
+        ReaderKind::Aligned => run_lockstep_a::<AlignedMemReader<u32>>(args),
+        ReaderKind::Unaligned => run_lockstep_a::<UnalignedMemReader<u32>>(args),
+    }
+}
+
+fn run_lockstep_a<A: MemReader<Idx = u32>>(
+    args: &LockstepArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    match args.b.reader {
+        ReaderKind::Adaptive => run_lockstep_ab::<A, AdaptiveMemReader<u32>>(args),
+        ReaderKind::Aligned => run_lockstep_ab::<A, AlignedMemReader<u32>>(args),
+        ReaderKind::Unaligned => run_lockstep_ab::<A, UnalignedMemReader<u32>>(args),
+    }
+}
+
+fn run_lockstep_ab<A: MemReader<Idx = u32>, B: MemReader<Idx = u32>>(
+    args: &LockstepArgs,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let elf = LoadedElf::load(&args.file)?;
+    elf.check_runnable()
+        .map_err(|err| anyhow!("{}: {err}", args.file))?;
+    let symbols = elf.symbols.clone();
+    let input = match &args.input {
+        Some(path) => fs::read(path)?,
+        None => Vec::new(),
+    };
+    let a = lockstep_core(args.a.build::<A>(elf.clone(), args.size), args, &input);
+    let b = lockstep_core(args.b.build::<B>(elf, args.size), args, &input);
+
+    let configs = (args.a.to_string(), args.b.to_string());
+    match Lockstep::new(a, b, configs, args.chunk).run() {
+        Ok(info) => {
+            eprintln!(
+                "{} and {} agree, both {} with {}",
+                args.a,
+                args.b,
+                info.reason.name(),
+                info.return_code
+            );
+            Ok(ExitCode::SUCCESS)
+        }
+        Err(divergence) => {
+            eprintln!("{divergence}");
+            eprintln!("  in {}", load::describe(&symbols, divergence.pc));
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+// both cores run the program alike, see `lockstep`
+fn lockstep_core<Reader: MemReader<Idx = u32>>(
+    mut core: Core32<Reader>,
+    args: &LockstepArgs,
+    input: &[u8],
+) -> Core32<Reader> {
+    core.set_deterministic(args.seed);
+    core.set_stdin(input.to_vec());
+    core.capture_output();
+    core
+}
+
+#[derive(clap::Args, Debug)]
+struct TminArgs {
+    file: String,
+
+    /// The crashing input, which is fed to the program as stdin
+    input: PathBuf,
+
+    /// Where to write the shrunk input; `<INPUT>.min` by default
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// Skip checking loads and stores for misalignment, which is only sound if
+    /// the guest never makes a misaligned one (see `--alignment-report`)
+    #[arg(long)]
+    assume_aligned: bool,
+
+    #[arg(short, long, default_value = "16777215")]
+    size: usize,
+}
+
+fn run_tmin(args: &TminArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let elf = LoadedElf::load(&args.file)?;
+    elf.check_runnable()
+        .map_err(|err| anyhow!("{}: {err}", args.file))?;
+    let input = fs::read(&args.input)?;
+
+    let (minimized, signature, runs) = if args.assume_aligned {
+        minimize::<AlignedMemReader<u32>>(elf, args.size, &input)?
+    } else {
+        minimize::<AdaptiveMemReader<u32>>(elf, args.size, &input)?
+    };
+
+    let output = args.output.clone().unwrap_or_else(|| {
+        let mut path = args.input.clone().into_os_string();
+        path.push(".min");
+        PathBuf::from(path)
+    });
+    fs::write(&output, &minimized)?;
+    info!(
+        "{} bytes shrunk to {} in {runs} runs, still {signature}, written to {}",
+        input.len(),
+        minimized.len(),
+        output.display()
+    );
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn minimize<Reader: MemReader<Idx = u32>>(
+    elf: LoadedElf,
+    size: usize,
+    input: &[u8],
+) -> Result<(Vec<u8>, CrashSignature, u64), Box<dyn Error>> {
+    let mut minimizer = Minimizer::<Reader>::new(elf, size);
+    let (minimized, signature) = minimizer.minimize(input).map_err(|err| anyhow!(err))?;
+    Ok((minimized, signature, minimizer.runs()))
+}
+
+#[derive(clap::Args, Debug)]
+struct FuzzArgs {
+    file: String,
+
+    /// Start from the inputs in DIR, each a file as `riscy fuzz` writes them,
+    /// see `fuzz`
+    #[arg(long, value_name = "DIR")]
+    corpus: Option<PathBuf>,
+
+    /// An argument to start from, alongside an empty stdin
+    #[arg(long, value_name = "ARG", allow_hyphen_values = true)]
+    arg: Vec<String>,
+
+    /// How many runs to make
+    #[arg(long, value_name = "N", default_value = "10000")]
+    runs: u64,
+
+    /// Stop each run after N instructions
+    #[arg(long, value_name = "N", default_value = "10000000")]
+    limit: u64,
+
+    /// Seed for the mutations, so a campaign can be repeated
+    #[arg(long, default_value = "0")]
+    seed: u64,
+
+    /// Write the first input into each bucket to DIR
+    #[arg(long, value_name = "DIR")]
+    crashes: Option<PathBuf>,
+
+    /// Skip checking loads and stores for misalignment, which is only sound if
+    /// the guest never makes a misaligned one (see `--alignment-report`)
+    #[arg(long)]
+    assume_aligned: bool,
+
+    #[arg(short, long, default_value = "16777215")]
+    size: usize,
+}
+
+fn run_fuzz(args: &FuzzArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let elf = LoadedElf::load(&args.file)?;
+    elf.check_runnable()
+        .map_err(|err| anyhow!("{}: {err}", args.file))?;
+    let symbols = elf.symbols.clone();
+
+    let mut seeds = vec![FuzzInput {
+        args: args.arg.iter().map(|arg| arg.as_bytes().to_vec()).collect(),
+        stdin: Vec::new(),
+    }];
+    if let Some(dir) = &args.corpus {
+        for entry in fs::read_dir(dir)? {
+            seeds.push(FuzzInput::from_bytes(&fs::read(entry?.path())?));
+        }
+    }
+    if let Some(dir) = &args.crashes {
+        fs::create_dir_all(dir)?;
+    }
+
+    let buckets = if args.assume_aligned {
+        fuzz::<AlignedMemReader<u32>>(elf, args, seeds)?
+    } else {
+        fuzz::<AdaptiveMemReader<u32>>(elf, args, seeds)?
+    };
+
+    for (key, bucket) in buckets.iter() {
+        eprint!("{key}: {}", bucket.display(&symbols));
+    }
+    info!("{} runs, {} buckets", args.runs, buckets.len());
+
+    Ok(if buckets.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn fuzz<Reader: MemReader<Idx = u32>>(
+    elf: LoadedElf,
+    args: &FuzzArgs,
+    mut corpus: Vec<FuzzInput>,
+) -> Result<Buckets, Box<dyn Error>> {
+    let mut harness = Harness::<Reader>::new(elf, &args.file, args.size, args.limit);
+    let mut mutator = Mutator::new(args.seed);
+    let mut buckets = Buckets::default();
+
+    // the seeds as they are, then mutations of them and of what crashes
+    let seeds = corpus.len();
+    for run in 0..args.runs {
+        let input = if (run as usize) < seeds {
+            corpus[run as usize].clone()
+        } else {
+            mutator.mutate(&corpus[run as usize % corpus.len()])
+        };
+        let Some(crash) = harness.run(&input).map_err(|err| anyhow!(err))? else {
+            continue;
+        };
+        let Some(key) = buckets.record(crash, &input) else {
+            continue;
+        };
+
+        info!("run {run}: new bucket {key}: {input}");
+        if let Some(dir) = &args.crashes {
+            fs::write(dir.join(format!("{key}.input")), input.to_bytes())?;
+        }
+        corpus.push(input);
+    }
+
+    Ok(buckets)
+}
+
+#[derive(clap::Args, Debug)]
+struct WritesArgs {
+    /// The log, from --record-writes
+    log: PathBuf,
+
+    /// What last wrote ADDR
+    #[arg(long, value_name = "ADDR", value_parser = writelog::parse_addr)]
+    addr: Option<u32>,
+
+    /// Every write to ADDR, in order, rather than the last
+    #[arg(long, requires = "addr")]
+    all: bool,
+
+    /// Name pcs by the symbols of PROGRAM
+    #[arg(long, value_name = "PROGRAM")]
+    elf: Option<String>,
+}
+
+fn run_writes(args: &WritesArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let log = WriteLog::load(&args.log)?;
+    let symbols = match &args.elf {
+        Some(path) => LoadedElf::load(path)?.symbols,
+        None => Vec::new(),
+    };
+
+    let Some(addr) = args.addr else {
+        eprintln!("{} writes", log.records.len());
+        for stats in log.stats() {
+            eprint!("{}", stats.display(&symbols));
+        }
+        return Ok(ExitCode::SUCCESS);
+    };
+
+    let mut writes: Vec<_> = log.writes_to(addr).collect();
+    if !args.all {
+        writes.drain(..writes.len().saturating_sub(1));
+    }
+    if writes.is_empty() {
+        eprintln!("nothing wrote {addr:#x}");
+        return Ok(ExitCode::FAILURE);
+    }
+
+    for (idx, record) in writes {
+        let at = (addr - record.addr) as usize;
+        eprintln!(
+            "#{idx}: {} wrote {:#x}..{:#x}, {addr:#x} {:#04x} -> {:#04x}",
+            load::describe(&symbols, record.pc),
+            record.range().start,
+            record.range().end,
+            record.old[at],
+            record.new[at],
+        );
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[derive(clap::Args, Debug)]
+struct TraceStatsArgs {
+    /// The trace, from --trace-compact
+    trace: PathBuf,
+
+    /// Name pcs, and total them by function, by the symbols of PROGRAM
+    #[arg(long, value_name = "PROGRAM")]
+    elf: Option<String>,
+}
+
+fn run_trace_stats(args: &TraceStatsArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let symbols = match &args.elf {
+        Some(path) => LoadedElf::load(path)?.symbols,
+        None => Vec::new(),
+    };
+    let size = std::fs::metadata(&args.trace)?.len();
+    let stats = TraceStats::collect(TraceReader::open(&args.trace)?)?;
+
+    eprintln!(
+        "{size} bytes, {:.3} bits per instruction",
+        size as f64 * 8.0 / stats.instructions.max(1) as f64
+    );
+    eprint!("{}", stats.display(&symbols));
+    Ok(ExitCode::SUCCESS)
+}
+
+#[derive(clap::Args, Debug)]
+struct TraceGrepArgs {
+    /// The trace, from --trace-compact
+    trace: PathBuf,
+
+    /// The instruction to find, an address, SYMBOL or SYMBOL+OFFSET; at a
+    /// function's address, that's each call to it
+    location: Location,
+
+    /// Look symbols up in, and name pcs by, PROGRAM
+    #[arg(long, value_name = "PROGRAM")]
+    elf: Option<String>,
+
+    /// Only count the times it ran
+    #[arg(short, long)]
+    count: bool,
+}
+
+fn run_trace_grep(args: &TraceGrepArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let symbols = match &args.elf {
+        Some(path) => LoadedElf::load(path)?.symbols,
+        None => Vec::new(),
+    };
+    let pc = args
+        .location
+        .resolve(&symbols)
+        .map_err(|err| anyhow!(err))?;
+
+    let mut found = 0u64;
+    ctrace::executions(TraceReader::open(&args.trace)?, pc, |execution| {
+        found += 1;
+        if args.count {
+            return;
+        }
+        match execution.from {
+            Some(from) => eprintln!(
+                "#{}: {} from {}",
+                execution.index,
+                load::describe(&symbols, pc),
+                load::describe(&symbols, from)
+            ),
+            None => eprintln!("#{}: {}", execution.index, load::describe(&symbols, pc)),
+        }
+    })?;
+
+    eprintln!("{} ran {found} times", load::describe(&symbols, pc));
+    Ok(if found == 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    /// The unix socket to listen on
+    socket: PathBuf,
+
+    #[arg(short, long, default_value = "16777215")]
+    size: usize,
+}
+
+fn run_serve(args: &ServeArgs) -> Result<ExitCode, Box<dyn Error>> {
+    Session::<AdaptiveMemReader<u32>>::new(args.size).serve(&args.socket)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+#[derive(clap::Args, Debug)]
+struct BatchArgs {
+    /// Programs to run
+    programs: Vec<String>,
+
+    /// Run every program once per input file, which is fed to it as stdin
+    #[arg(short, long)]
+    input: Vec<String>,
+
+    /// Read more jobs from FILE, one `PROGRAM [INPUT]` per line
+    #[arg(long, value_name = "FILE")]
+    jobs: Option<String>,
+
+    /// Number of worker threads (default: available parallelism)
+    #[arg(short = 'j', long)]
+    threads: Option<usize>,
+
+    /// Write the report to FILE instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    report: Option<String>,
+
+    /// Report format, `json` or `csv`
+    #[arg(long, default_value = "json")]
+    format: ReportFormat,
+
+    /// Skip checking loads and stores for misalignment, which is only sound if
+    /// the guest never makes a misaligned one (see `--alignment-report`)
+    #[arg(long)]
+    assume_aligned: bool,
+
+    #[arg(short, long, default_value = "16777215")]
+    size: usize,
+
+    /// Give up on a job once no new pc has been reached in N instructions
+    #[arg(long, value_name = "N")]
+    hang_new_pc: Option<u64>,
+
+    /// Give up on a job after N instructions without a store or syscall
+    #[arg(long, value_name = "N")]
+    hang_no_progress: Option<u64>,
+
+    /// Serve metrics for Prometheus at http://ADDR/metrics while the batch
+    /// runs (requires the `metrics` feature)
+    #[arg(long, value_name = "ADDR")]
+    metrics_addr: Option<String>,
+}
+
+fn run_batch(args: &BatchArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let mut jobs = Vec::new();
+    for program in &args.programs {
+        if args.input.is_empty() {
+            jobs.push(BatchJob {
+                program: program.clone(),
+                input: None,
+            });
+        }
+        for input in &args.input {
+            jobs.push(BatchJob {
+                program: program.clone(),
+                input: Some(input.clone()),
+            });
+        }
+    }
+
+    if let Some(path) = &args.jobs {
+        for line in fs::read_to_string(path)?.lines() {
+            let mut parts = line.split_whitespace();
+            if let Some(program) = parts.next() {
+                jobs.push(BatchJob {
+                    program: program.to_string(),
+                    input: parts.next().map(str::to_string),
+                });
+            }
+        }
+    }
+
+    if jobs.is_empty() {
+        return Err(anyhow!("no jobs given").into());
+    }
+
+    #[cfg(feature = "metrics")]
+    let metrics = match &args.metrics_addr {
+        Some(addr) => {
+            let metrics = Metrics::new();
+            let addr = metrics.serve(addr.as_str())?;
+            info!("serving metrics at http://{addr}/metrics");
+            Some(metrics)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "metrics"))]
+    if args.metrics_addr.is_some() {
+        return Err(anyhow!("riscy was built without the `metrics` feature").into());
+    }
+
+    let config = BatchConfig {
+        threads: args.threads,
+        size: args.size,
+        assume_aligned: args.assume_aligned,
+        hang_new_pc: args.hang_new_pc,
+        hang_no_progress: args.hang_no_progress,
+        #[cfg(feature = "metrics")]
+        metrics,
+    };
+
+    info!("running {} jobs...", jobs.len());
+    let outcomes = batch::run_batch(&jobs, &config);
+
+    let mut out: Box<dyn Write> = match &args.report {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock()),
+    };
+    batch::write_report(&outcomes, args.format, &mut out)?;
+    out.flush()?;
+
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| outcome.result.is_err())
+        .count();
+    info!("{} jobs run, {failed} failed to run", outcomes.len());
+
+    Ok(if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
 }
 
 fn run_core32<Reader: MemReader<Idx = u32>>(
     elf: LoadedElf,
     entrypoint: Option<u64>,
-    size: usize,
-    debug: bool,
-) -> RunInfo {
-    let mut core = Core32::<Reader>::new(elf, entrypoint, size, debug);
-    core.run()
+    args: &Args,
+    mux: Option<Mux>,
+) -> Result<ExitCode, Box<dyn Error>> {
+    let Args {
+        debug, self_check, ..
+    } = *args;
+
+    let symbols = elf.symbols.clone();
+    let config = elf.config.clone();
+    if config != ElfConfig::default() {
+        info!("the binary's riscy note asks for {config}");
+    }
+    let size = args.size.or(config.memory).unwrap_or(DEFAULT_SIZE);
+    let mut core = Core32::<Reader>::with_hugepages(elf, entrypoint, size, debug, args.hugepages);
+    core.set_debug_fp(args.debug_fp);
+    for &reg in &args.break_on_write {
+        core.add_hook(Box::new(RegisterWatch::new(reg, WatchMode::BreakOnWrite)));
+    }
+    for &WatchSpec { reg, mode } in &args.watch_reg {
+        core.add_hook(Box::new(RegisterWatch::new(reg, mode)));
+    }
+    if !args.break_syscall.is_empty() || !args.break_call.is_empty() {
+        let breakpoints = Breakpoints::new(&args.break_syscall, &args.break_call, symbols.clone())
+            .map_err(|err| anyhow!(err))?;
+        core.add_hook(Box::new(breakpoints));
+    }
+    for stub in &args.stub {
+        let sym = symbols
+            .iter()
+            .find(|sym| sym.name == stub.function)
+            .ok_or_else(|| anyhow!("symbol '{}' not found", stub.function))?;
+        core.add_stub(sym.addr as u32, stub.action);
+    }
+    let mut natives = Vec::new();
+    for name in &args.native {
+        if name == "all" {
+            natives.extend(native::all().iter().filter_map(|native| {
+                let sym = symbols.iter().find(|sym| sym.name == native.name)?;
+                Some((native, sym.addr as u32))
+            }));
+            continue;
+        }
+        let native =
+            native::lookup(name).ok_or_else(|| anyhow!("riscy can't service '{name}' natively"))?;
+        let sym = symbols
+            .iter()
+            .find(|sym| sym.name == *name)
+            .ok_or_else(|| anyhow!("symbol '{name}' not found"))?;
+        natives.push((native, sym.addr as u32));
+    }
+    if !natives.is_empty() {
+        let names: Vec<_> = natives.iter().map(|(native, _)| native.name).collect();
+        info!("servicing {} natively", names.join(", "));
+    }
+    for (native, addr) in natives {
+        core.add_native(addr, native);
+    }
+    for PatchSpec { at, patch } in &args.patch {
+        let addr = at.resolve(&symbols).map_err(|err| anyhow!(err))?;
+        let word = patch
+            .encode(addr, &symbols)
+            .map_err(|err| anyhow!("{at}: {err}"))?;
+        let old = core
+            .patch(addr, word)
+            .map_err(|err| anyhow!("{at}: {err}"))?;
+        info!("patched {at} ({addr:#x}): {old:08x} -> {word:08x}");
+    }
+    if !args.taint.is_empty() {
+        let tracker = TaintTracker::new(&args.taint, &args.taint_sink, symbols.clone())
+            .map_err(|err| anyhow!(err))?;
+        core.add_hook(Box::new(tracker));
+    }
+    if let Some(addr) = &args.trace_server {
+        info!("waiting for a trace client on {addr}...");
+        core.add_hook(Box::new(TraceServer::accept(addr)?));
+    }
+    if args.shadow_stack {
+        core.add_hook(Box::new(ShadowStack::new(symbols.clone())));
+    }
+    if !args.invariant.is_empty() {
+        let invariants = Invariants::new(&args.invariant, symbols.clone(), core.gp_regs())
+            .map_err(|err| anyhow!(err))?;
+        core.add_hook(Box::new(invariants));
+    }
+    if args.hang_new_pc.is_some() || args.hang_no_progress.is_some() {
+        core.add_hook(Box::new(HangDetector::new(
+            args.hang_new_pc,
+            args.hang_no_progress,
+            symbols.clone(),
+        )));
+    }
+
+    let cost = args.energy.map(|weights| {
+        let counter = CostCounter::new();
+        core.add_hook(Box::new(counter.clone()));
+        (counter, weights)
+    });
+
+    let pipeline = args.pipeline.map(|config| {
+        let model = PipelineModel::new(config);
+        core.add_hook(Box::new(model.clone()));
+        model
+    });
+
+    let writes = match &args.record_writes {
+        Some(path) => {
+            let recorder = WriteRecorder::create(path, &args.record_writes_range)?;
+            core.add_hook(Box::new(recorder.clone()));
+            Some(recorder)
+        }
+        None => None,
+    };
+
+    let alignment = args.alignment_report.then(|| {
+        let counter = AlignmentCounter::new();
+        core.add_hook(Box::new(counter.clone()));
+        counter
+    });
+
+    let heap = if args.heap_report {
+        let tracker = HeapTracker::new(&symbols).map_err(|err| anyhow!(err))?;
+        core.add_hook(Box::new(tracker.clone()));
+        Some(tracker)
+    } else {
+        None
+    };
+
+    let preopens = args.preopen.iter().map(|preopen| (preopen, false));
+    for (preopen, writable) in preopens.chain(args.preopen_rw.iter().map(|preopen| (preopen, true)))
+    {
+        core.preopen(preopen.fd, &preopen.path, writable)
+            .map_err(|err| anyhow!("{}: {err}", preopen.path.display()))?;
+    }
+    if let Some(fd) = args.root {
+        core.set_root(fd)?;
+    }
+
+    if args.io_timeout.is_some() || args.io_deadline.is_some() {
+        core.set_io_timeouts(IoTimeouts {
+            per_call: args.io_timeout.map(|timeout| timeout.0),
+            deadline: args.io_deadline.map(|deadline| deadline.0),
+            action: args.io_timeout_action,
+        });
+    }
+
+    if args.max_memory.is_some() || args.max_fds.is_some() || args.max_output.is_some() {
+        core.set_resource_limits(ResourceLimits {
+            memory: args.max_memory.map(|size| size.0),
+            fds: args.max_fds,
+            output: args.max_output.map(|size| size.0),
+        });
+    }
+
+    if let Some(seed) = args.deterministic {
+        core.set_deterministic(seed);
+    }
+    if let Some(mips) = args.throttle {
+        core.set_throttle(mips);
+    }
+    if !args.inject.is_empty() {
+        core.set_faults(&args.inject, args.inject_seed);
+    }
+    if let Some(spoof) = args.spoof_stat {
+        core.set_stat_spoof(spoof);
+    }
+    if let Some(uname) = &args.uname {
+        core.set_uname(uname.clone());
+    }
+    core.set_fake_proc(args.fake_proc);
+    core.set_tty(args.tty);
+
+    let console = args
+        .console
+        .map(|ConsoleAddr(addr)| addr)
+        .or(config.console());
+    if let Some(addr) = console {
+        let device: Box<dyn Device> = match &mux {
+            Some(mux) => Box::new(MagicConsole::new(mux.writer(Stream::Console))),
+            None => Box::new(MagicConsole::stdout()),
+        };
+        core.map_device(addr, console::WINDOW, device)
+            .map_err(|err| anyhow!(err))?;
+    }
+    if let Some(mux) = mux {
+        core.set_output_mux(mux);
+    }
+
+    for addr in config.uarts() {
+        core.map_device(addr, Uart::<io::Stdout>::WINDOW, Box::new(Uart::stdout()))
+            .map_err(|err| anyhow!(err))?;
+    }
+
+    if args.htif || config.htif() {
+        core.enable_htif().map_err(|err| anyhow!(err))?;
+    }
+    core.set_writable_text(args.writable_text);
+    let note_vendor = config
+        .isa
+        .as_deref()
+        .and_then(|isa| isa.parse::<Isa>().ok())
+        .and_then(|isa| isa.vendor);
+    if let Some(vendor) = args.isa_vendor.or(note_vendor) {
+        let (opcode, handler) = vendor.handler();
+        core.register_custom(opcode, handler);
+    }
+    if args.no_superblocks {
+        core.set_superblocks(false);
+    }
+    if let Some(path) = &args.superblock_cache {
+        match core.load_superblocks(path) {
+            Ok(count) => info!("loaded {count} superblocks from {}", path.display()),
+            // the first run
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => warn!("not using {}: {err}", path.display()),
+        }
+    }
+    for &schedule in &args.irq {
+        core.schedule_irq(schedule);
+    }
+
+    if args.record_syscalls.is_some() {
+        core.record_syscalls();
+    }
+    if let Some(path) = &args.replay_syscalls {
+        let tape = SyscallTape::load(path)
+            .map_err(|err| anyhow!("failed to load {}: {err}", path.display()))?;
+        core.replay_syscalls(tape);
+    }
+
+    for mapping in &args.hpm_counter {
+        core.set_hpm_counter(mapping.counter, mapping.event)
+            .map_err(|err| anyhow!("hpmcounter{}: {err}", mapping.counter))?;
+    }
+
+    let checkpoint_file = args.checkpoint_file.clone().unwrap_or_else(|| {
+        let file = args.file.as_deref().unwrap_or_default();
+        PathBuf::from(format!("{file}.ckpt"))
+    });
+    if args.resume {
+        let snapshot = Snapshot::load(&checkpoint_file).map_err(|err| {
+            anyhow!(
+                "failed to load checkpoint {}: {err}",
+                checkpoint_file.display()
+            )
+        })?;
+        core.restore(&snapshot).map_err(|err| anyhow!(err))?;
+        info!(
+            "resumed from {} at {} instructions",
+            checkpoint_file.display(),
+            snapshot.instret
+        );
+    }
+    if let Some(interval) = args.checkpoint_every {
+        core.set_checkpoint(interval, checkpoint_file);
+    }
+
+    if let Some(interval) = args.progress {
+        core.set_progress(interval, |report| eprintln!("{report}"));
+    }
+    let call_stack = args.flamegraph.is_some().then(|| {
+        let stack = CallStack::new();
+        core.add_hook(Box::new(stack.clone()));
+        stack
+    });
+    if args.sample_profile.is_some() || args.flamegraph.is_some() {
+        core.set_sampling(args.sample_every, call_stack);
+    }
+
+    if args.call {
+        core.synthesize_call(&args.arg);
+    }
+
+    #[cfg(feature = "script")]
+    let script = match &args.script {
+        Some(path) => {
+            let script = Script::load(path, &core, symbols.clone()).map_err(|err| anyhow!(err))?;
+            core.add_hook(Box::new(script.clone()));
+            Some(script)
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "script"))]
+    if args.script.is_some() {
+        return Err(anyhow!("riscy was built without the `script` feature").into());
+    }
+
+    let mut interaction = match &args.expect {
+        Some(path) => Some(Interaction::new(
+            ExpectScript::load(path).map_err(|err| anyhow!(err))?,
+        )),
+        None => None,
+    };
+
+    let _run = info_span!("run").entered();
+    let start = Instant::now();
+    let info = if self_check {
+        match oracle::run_self_check(&mut core) {
+            Ok(info) => info,
+            Err(divergence) => {
+                eprintln!("self-check failed: {divergence}");
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    } else if args.tui {
+        match run_tui(&mut core, &symbols, &args.watch_mem)? {
+            Some(info) => info,
+            // quit before the program finished
+            None => return Ok(ExitCode::SUCCESS),
+        }
+    } else if let Some(path) = &args.trace_pcs {
+        let mut tracer = PcTrace::new(File::create(path)?);
+        let info = core.run_traced(&mut tracer);
+        tracer.flush()?;
+        info
+    } else if let Some(path) = &args.trace_compact {
+        let mut tracer = TraceWriter::create(File::create(path)?)?;
+        let info = core.run_traced(&mut tracer);
+        tracer.finish()?;
+        info
+    } else if let Some(interaction) = &mut interaction {
+        interaction.run(&mut core)
+    } else {
+        core.run()
+    };
+
+    #[cfg(feature = "script")]
+    if let Some(script) = &script {
+        script.finish(&core, &info);
+    }
+
+    if let Some(path) = &args.report {
+        let program = args.file.as_deref().unwrap_or_default();
+        let report = RunReport::new(program, &core, &info, start.elapsed());
+        let mut out = BufWriter::new(File::create(path)?);
+        report.write_json(&mut out)?;
+        out.flush()?;
+    }
+
+    if let Some(path) = &args.signature {
+        let range = signature::find(&symbols).map_err(|err| anyhow!(err))?;
+        let mut out = BufWriter::new(File::create(path)?);
+        signature::write_signature(
+            core.memory(),
+            range,
+            args.signature_granularity.get(),
+            &mut out,
+        )?;
+        out.flush()?;
+    }
+
+    if !args.inject.is_empty() {
+        info!("injected {} faults", core.faults_injected());
+    }
+    for (name, calls) in core.native_calls() {
+        info!("{name}: {calls} calls serviced natively");
+    }
+
+    if let Some(path) = &args.superblock_cache {
+        let count = core.save_superblocks(path)?;
+        info!("saved {count} superblocks to {}", path.display());
+    }
+
+    if let (Some(path), Some(tape)) = (&args.record_syscalls, core.syscall_tape()) {
+        tape.save(path)?;
+        info!(
+            "recorded {} syscalls to {}",
+            tape.records.len(),
+            path.display()
+        );
+    }
+
+    if let (Some(path), Some(recorder)) = (&args.record_writes, &writes) {
+        let count = recorder.finish()?;
+        info!("recorded {count} writes to {}", path.display());
+    }
+
+    if let (Some(path), Some(samples)) = (&args.sample_profile, core.samples()) {
+        let mut out = BufWriter::new(File::create(path)?);
+        samples.write_folded(&symbols, &mut out)?;
+        out.flush()?;
+        info!("wrote {} samples to {}", samples.total(), path.display());
+    }
+    if let (Some(path), Some(samples)) = (&args.flamegraph, core.samples()) {
+        let mut out = BufWriter::new(File::create(path)?);
+        if path.extension().is_some_and(|ext| ext == "svg") {
+            let program = args.file.as_deref().unwrap_or_default();
+            flamegraph::write_svg(&samples.folded(&symbols), program, &mut out)?;
+        } else {
+            samples.write_folded(&symbols, &mut out)?;
+        }
+        out.flush()?;
+        info!(
+            "wrote a flame graph of {} samples to {}",
+            samples.total(),
+            path.display()
+        );
+    }
+
+    if info.limit_hits.any() {
+        warn!("resource limits hit: {}", info.limit_hits);
+    }
+
+    if let Some((counter, weights)) = cost {
+        eprintln!("{}", counter.report(weights));
+    }
+    if let Some(model) = pipeline {
+        eprintln!("{}", model.report());
+    }
+    if let Some(counter) = alignment {
+        eprintln!("{}", counter.report(&symbols));
+    }
+    if let Some(tracker) = heap {
+        eprintln!("{}", tracker.report(&symbols));
+    }
+    for (irq, stats) in core.irq_stats() {
+        eprintln!(
+            "irq {irq}: {} raised, {} taken, {} dropped, latency {:.1} mean, {} max",
+            stats.raised,
+            stats.taken,
+            stats.dropped,
+            stats.mean_latency(),
+            stats.max_latency
+        );
+    }
+    if args.stats {
+        let decode = core.decode_stats();
+        eprintln!("instructions: {}", core.instret());
+        eprintln!(
+            "decode: {} instructions in {} blocks, {:?}, {} jump tables",
+            decode.instructions, decode.blocks, decode.time, decode.jump_tables
+        );
+        let inline_cache = core.inline_cache_stats();
+        eprintln!(
+            "indirect jumps: {} of {} predicted ({:.1}%)",
+            inline_cache.hits,
+            inline_cache.hits + inline_cache.misses,
+            inline_cache.hit_rate() * 100.0
+        );
+    }
+    if args.memory_stats {
+        let resident = core.resident_memory()?;
+        eprintln!(
+            "memory: {resident} of {} bytes resident ({:.1}%)",
+            size,
+            resident as f64 / size as f64 * 100.0
+        );
+        eprintln!("{}", core.memory_map());
+    }
+
+    if let Some(step) = interaction.as_ref().and_then(Interaction::pending) {
+        error!(
+            "the guest stopped before the script was done, at line {}: {step}",
+            step.line
+        );
+        return Ok(ExitCode::FAILURE);
+    }
+
+    match info.reason {
+        StopReason::Exited => Ok(ExitCode::from(info.return_code as u8)),
+        StopReason::Breakpoint { .. }
+        | StopReason::PossibleHang { .. }
+        | StopReason::Cancelled { .. }
+        | StopReason::WouldBlock { .. }
+        | StopReason::CfiViolation { .. }
+        | StopReason::InvariantViolated { .. }
+        | StopReason::InstructionLimit { .. }
+        | StopReason::ReplayMismatch { .. } => Ok(ExitCode::FAILURE),
+        StopReason::IllegalInstruction { pc, inst } => {
+            match &info.fault {
+                Some(fault) => error!("illegal instruction at {fault}"),
+                None => error!("illegal instruction {inst:#010x} at pc {pc:#x}"),
+            }
+            // what a native process would get from SIGILL
+            Ok(ExitCode::from(128 + 4))
+        }
+        StopReason::RomWrite { pc, addr } => {
+            match &info.fault {
+                Some(fault) => error!("store to rom at {addr:#x} by {fault}"),
+                None => error!("store to rom at {addr:#x} at pc {pc:#x}"),
+            }
+            // what a native process would get from SIGSEGV
+            Ok(ExitCode::from(128 + 11))
+        }
+        StopReason::LoadFault { pc, addr } | StopReason::StoreFault { pc, addr } => {
+            let access = match info.reason {
+                StopReason::LoadFault { .. } => "load from",
+                _ => "store to",
+            };
+            match &info.fault {
+                Some(fault) => error!("{access} {addr:#x}, past the end of memory, by {fault}"),
+                None => error!("{access} {addr:#x}, past the end of memory, at pc {pc:#x}"),
+            }
+            // what a native process would get from SIGSEGV
+            Ok(ExitCode::from(128 + 11))
+        }
+        StopReason::FetchFault { pc } => {
+            error!("jumped to {pc:#x}, not an instruction of the program's code");
+            // what a native process would get from SIGSEGV
+            Ok(ExitCode::from(128 + 11))
+        }
+        StopReason::Ebreak { pc } => {
+            match &info.fault {
+                Some(fault) => error!("ebreak at {fault}"),
+                None => error!("ebreak at pc {pc:#x}"),
+            }
+            // what a native process would get from SIGTRAP
+            Ok(ExitCode::from(128 + 5))
+        }
+        StopReason::IoTimeout { pc, fd } => {
+            match &info.fault {
+                Some(fault) => error!("I/O on fd {fd} timed out at {fault}"),
+                None => error!("I/O on fd {fd} timed out at pc {pc:#x}"),
+            }
+            // what timeout(1) exits with
+            Ok(ExitCode::from(124))
+        }
+        StopReason::Panicked { pc } => {
+            match &info.fault {
+                Some(fault) => error!("riscy panicked at {fault}"),
+                None => error!("riscy panicked at pc {pc:#x}"),
+            }
+            // what a panicking Rust program exits with
+            Ok(ExitCode::from(101))
+        }
+        StopReason::Fatal(_) => {
+            if let Some(fatal) = core.fatal() {
+                eprintln!("{fatal}");
+            }
+            Ok(ExitCode::from(info.return_code as u8))
+        }
+        StopReason::Returned => {
+            let ret = core.ret_value();
+            eprintln!(
+                "returned: a0 = {} ({:#x}), fa0 = {:?} (double) / {:?} (single)",
+                ret.a0,
+                ret.a0,
+                ret.double(),
+                ret.single()
+            );
+            Ok(ExitCode::from(ret.a0 as u8))
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+fn run_tui<Reader: MemReader<Idx = u32>>(
+    core: &mut Core32<Reader>,
+    symbols: &[Symbol],
+    watch_mem: &[String],
+) -> Result<Option<RunInfo>, Box<dyn Error>> {
+    use std::io::{IsTerminal, Read};
+
+    let watches = watch_mem
+        .iter()
+        .map(|addr| {
+            let parsed = match addr.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => addr.parse().ok(),
+            };
+            parsed
+                .or_else(|| {
+                    let sym = symbols.iter().find(|sym| &sym.name == addr)?;
+                    Some(sym.addr as u32)
+                })
+                .ok_or_else(|| anyhow!("no symbol or address '{addr}'"))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // the debugger owns the terminal, so the guest only gets piped input
+    let mut stdin = Vec::new();
+    if !io::stdin().is_terminal() {
+        io::stdin().read_to_end(&mut stdin)?;
+    }
+    core.set_stdin(stdin);
+    core.capture_output();
+
+    Ok(tui::Debugger::new(core, symbols, &watches).run()?)
+}
+
+#[cfg(not(feature = "tui"))]
+fn run_tui<Reader: MemReader<Idx = u32>>(
+    _core: &mut Core32<Reader>,
+    _symbols: &[Symbol],
+    _watch_mem: &[String],
+) -> Result<Option<RunInfo>, Box<dyn Error>> {
+    Err(anyhow!("riscy was built without the `tui` feature").into())
+}
+
+/// Logs to stderr, or through `mux` if there is one, filtered by `RISCY_LOG`
+/// (e.g. `syscall=debug`) and as JSON lines if `RISCY_LOG_FORMAT=json`
+fn init_logging(mux: Option<&Mux>) {
+    // per-subsystem directives like `syscall=debug` leave the rest at info
+    let directives = env::var("RISCY_LOG").unwrap_or_default();
+    let filter = EnvFilter::builder().parse_lossy(format!("info,{directives}"));
+    let (writer, ansi) = match mux {
+        Some(mux) => {
+            let writer = mux.writer(Stream::Log);
+            (
+                BoxMakeWriter::new(move || writer.clone()),
+                mux.is_terminal(Stream::Log),
+            )
+        }
+        None => (BoxMakeWriter::new(io::stderr), io::stderr().is_terminal()),
+    };
+    let logger = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .with_ansi(ansi)
+        .with_target(false)
+        .without_time();
+
+    if env::var("RISCY_LOG_FORMAT").is_ok_and(|format| format == "json") {
+        logger.json().init();
+    } else {
+        logger.init();
+    }
 }
 
 fn main() -> Result<ExitCode, Box<dyn Error>> {
     let args = Args::parse();
+    let mux = if args.tag_output
+        || args.timestamp_output.is_some()
+        || args.color_output
+        || !args.output_file.is_empty()
+    {
+        Some(Mux::new(MuxConfig {
+            tag: args.tag_output,
+            timestamps: args.timestamp_output,
+            color: args.color_output,
+            files: args.output_file.clone(),
+        })?)
+    } else {
+        None
+    };
+    init_logging(mux.as_ref());
 
-    eprintln!("running {}...", args.file);
+    match &args.command {
+        Some(Command::Batch(batch)) => return run_batch(batch),
+        Some(Command::Compare(compare)) => return run_compare(compare),
+        Some(Command::Lockstep(lockstep)) => return run_lockstep(lockstep),
+        Some(Command::Tmin(tmin)) => return run_tmin(tmin),
+        Some(Command::Fuzz(fuzz)) => return run_fuzz(fuzz),
+        Some(Command::Writes(writes)) => return run_writes(writes),
+        Some(Command::Serve(serve)) => return run_serve(serve),
+        Some(Command::TraceStats(stats)) => return run_trace_stats(stats),
+        Some(Command::TraceGrep(grep)) => return run_trace_grep(grep),
+        #[cfg(feature = "dap")]
+        Some(Command::Dap(dap)) => return run_dap(dap),
+        None => {}
+    }
 
-    let loaded = LoadedElf::load(&args.file)?;
-    eprintln!(
+    let file = args
+        .file
+        .as_deref()
+        .expect("file is required without a subcommand");
+    let load = info_span!("load").entered();
+    info!("running {file}...");
+
+    let mut loaded = LoadedElf::load(file)?;
+    loaded
+        .check_runnable()
+        .map_err(|err| anyhow!("{file}: {err}"))?;
+    info!(
         "loaded elf with base {:#x}, entrypoint {:#x}",
         loaded.base, loaded.entrypoint
     );
+    for extra in &args.load_extra {
+        let path = extra.path.to_string_lossy();
+        let elf = LoadedElf::load(&path)?;
+        loaded
+            .add(elf, extra.addr)
+            .map_err(|err| anyhow!("{path}: {err}"))?;
+        info!("loaded {path} at {:#x}", extra.addr);
+    }
 
-    let info = if args.assume_aligned {
-        run_core32::<AlignedMemReader<u32>>(loaded, args.entrypoint, args.size, args.debug)
-    } else {
-        run_core32::<UnalignedMemReader<u32>>(loaded, args.entrypoint, args.size, args.debug)
+    let entrypoint = match &args.entry_symbol {
+        Some(name) => Some(
+            loaded
+                .symbol(name)
+                .ok_or_else(|| anyhow!("symbol '{name}' not found"))?,
+        ),
+        None => args.entrypoint,
     };
+    load.exit();
 
-    Ok(ExitCode::from(info.return_code as u8))
+    let code = if args.assume_aligned {
+        run_core32::<AlignedMemReader<u32>>(loaded, entrypoint, &args, mux.clone())
+    } else {
+        run_core32::<AdaptiveMemReader<u32>>(loaded, entrypoint, &args, mux.clone())
+    };
+    if let Some(mux) = &mux {
+        mux.flush()?;
+    }
+    code
 }
@@ -0,0 +1,280 @@
+//! Metrics for running riscy as a service, served for Prometheus to scrape.
+//!
+//! A `Metrics` is shared by every core in the process, as `riscy batch` runs
+//! many across threads. `Metrics::watch` counts one in, taking its progress
+//! reports, see `Core32::set_progress`, to bring what it's done up to date
+//! every second while it runs. `Instance::finish` counts how it stopped, and
+//! it's counted out once it and the core are dropped.
+//! `Metrics::serve` answers `GET /metrics` (`riscy batch --metrics-addr
+//! 127.0.0.1:9464`) with, in Prometheus' text format:
+//!
+//! - `riscy_instructions_retired_total`, by every instance
+//! - `riscy_instances_active`, running now
+//! - `riscy_instances_finished_total{reason="exited"}`, by how they stopped,
+//!   as in `StopReason::name`
+//! - `riscy_guest_memory_bytes`, the guest memory the running instances have,
+//!   and `riscy_guest_memory_resident_bytes`, how much of it the host has
+//!   committed, see `Core32::resident_memory`
+//! - `riscy_syscalls_total{num="64",name="write"}`, by every instance, by
+//!   syscall
+//!
+//! The server is a thread answering one request at a time, which is plenty
+//! for a scraper, and runs until the process exits.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    rc::Rc,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use tracing::warn;
+
+use crate::{
+    core::{Core32, MemReader, StopReason},
+    progress::ProgressInterval,
+    syscall,
+};
+
+// how often a running instance's counts are brought up to date
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+// how long a scraper has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+struct Registry {
+    instret: u64,
+    active: u64,
+    memory: u64,
+    resident: u64,
+    finished: BTreeMap<&'static str, u64>,
+    syscalls: BTreeMap<i32, u64>,
+}
+
+/// The metrics, see the module docs. Clones share them
+#[derive(Clone, Default)]
+pub struct Metrics(Arc<Mutex<Registry>>);
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Metrics")
+            .field(&self.0.lock().unwrap())
+            .finish()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts `core` in as running, keeping up with it through its progress
+    /// reports, which replaces any `Core32::set_progress` callback
+    pub fn watch<Reader: MemReader<Idx = u32>>(&self, core: &mut Core32<Reader>) -> Instance {
+        let memory = core.memory().len() as u64;
+        let mut registry = self.0.lock().unwrap();
+        registry.active += 1;
+        registry.memory += memory;
+        drop(registry);
+
+        let state = Rc::new(RefCell::new(InstanceState {
+            metrics: self.clone(),
+            memory,
+            instret: 0,
+            resident: 0,
+            syscalls: BTreeMap::new(),
+        }));
+        core.set_progress(ProgressInterval::Time(UPDATE_INTERVAL), {
+            let state = state.clone();
+            move |report| {
+                state
+                    .borrow_mut()
+                    .update(report.instret, report.resident_memory, &report.syscalls)
+            }
+        });
+        Instance(state)
+    }
+
+    /// The metrics, in Prometheus' text format
+    pub fn render(&self) -> String {
+        let registry = self.0.lock().unwrap();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, u64)]| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} {kind}");
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{name}{labels} {value}");
+            }
+        };
+
+        metric(
+            "riscy_instructions_retired_total",
+            "counter",
+            "Instructions retired by every instance",
+            &[(String::new(), registry.instret)],
+        );
+        metric(
+            "riscy_instances_active",
+            "gauge",
+            "Instances running",
+            &[(String::new(), registry.active)],
+        );
+        let finished: Vec<_> = registry
+            .finished
+            .iter()
+            .map(|(reason, &count)| (format!("{{reason=\"{reason}\"}}"), count))
+            .collect();
+        metric(
+            "riscy_instances_finished_total",
+            "counter",
+            "Instances finished, by how they stopped",
+            &finished,
+        );
+        metric(
+            "riscy_guest_memory_bytes",
+            "gauge",
+            "Guest memory of the instances running",
+            &[(String::new(), registry.memory)],
+        );
+        metric(
+            "riscy_guest_memory_resident_bytes",
+            "gauge",
+            "Guest memory the host has committed for the instances running",
+            &[(String::new(), registry.resident)],
+        );
+        let syscalls: Vec<_> = registry
+            .syscalls
+            .iter()
+            .map(|(&num, &count)| {
+                let name = syscall::by_num(num).map_or("unknown", |desc| desc.name);
+                (format!("{{num=\"{num}\",name=\"{name}\"}}"), count)
+            })
+            .collect();
+        metric(
+            "riscy_syscalls_total",
+            "counter",
+            "Syscalls made by every instance, by syscall",
+            &syscalls,
+        );
+        out
+    }
+
+    /// Serves the metrics at `/metrics` over HTTP on `addr`, from a thread of
+    /// its own, returning the address it's listening on
+    pub fn serve(&self, addr: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let local = listener.local_addr()?;
+        let metrics = self.clone();
+        thread::Builder::new()
+            .name("riscy-metrics".to_owned())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let answered = stream.and_then(|stream| metrics.answer(stream));
+                    if let Err(err) = answered {
+                        warn!(target: "metrics", "failed to answer a scrape: {err}");
+                    }
+                }
+            })?;
+        Ok(local)
+    }
+
+    // answers the one request on `stream`
+    fn answer(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // the headers, which don't matter
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 2 {
+            line.clear();
+        }
+
+        let mut parts = request.split_whitespace();
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+            (Some("GET"), _) => ("404 Not Found", "not found\n".to_owned()),
+            _ => ("405 Method Not Allowed", "only GET\n".to_owned()),
+        };
+
+        let mut out = &stream;
+        write!(
+            out,
+            "HTTP/1.1 {status}\r\n\
+             Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        out.flush()
+    }
+}
+
+/// A core counted in by `Metrics::watch`
+pub struct Instance(Rc<RefCell<InstanceState>>);
+
+impl Instance {
+    /// Brings what `core` has done up to date, as it's stopped for `reason`
+    pub fn finish<Reader: MemReader<Idx = u32>>(self, core: &Core32<Reader>, reason: StopReason) {
+        let syscalls: Vec<_> = core
+            .syscall_counts()
+            .iter()
+            .map(|(&num, &count)| (num, count))
+            .collect();
+        let mut state = self.0.borrow_mut();
+        state.update(
+            core.instret(),
+            core.resident_memory().unwrap_or(0),
+            &syscalls,
+        );
+        *state
+            .metrics
+            .0
+            .lock()
+            .unwrap()
+            .finished
+            .entry(reason.name())
+            .or_default() += 1;
+    }
+}
+
+// what an instance has added to the metrics so far
+struct InstanceState {
+    metrics: Metrics,
+    memory: u64,
+    instret: u64,
+    resident: u64,
+    syscalls: BTreeMap<i32, u64>,
+}
+
+impl InstanceState {
+    fn update(&mut self, instret: u64, resident: usize, syscalls: &[(i32, u64)]) {
+        let mut registry = self.metrics.0.lock().unwrap();
+        registry.instret += instret - self.instret;
+        self.instret = instret;
+        registry.resident = registry.resident - self.resident + resident as u64;
+        self.resident = resident as u64;
+        for &(num, count) in syscalls {
+            let seen = self.syscalls.entry(num).or_default();
+            *registry.syscalls.entry(num).or_default() += count - *seen;
+            *seen = count;
+        }
+    }
+}
+
+// counted out once the core, which keeps a reference for its progress
+// reports, is gone too
+impl Drop for InstanceState {
+    fn drop(&mut self) {
+        let mut registry = self.metrics.0.lock().unwrap();
+        registry.active -= 1;
+        registry.memory -= self.memory;
+        registry.resident -= self.resident;
+    }
+}
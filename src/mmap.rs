@@ -0,0 +1,374 @@
+//! Guest memory backed by an anonymous host mapping.
+//!
+//! The whole guest address space is reserved up front with `MAP_NORESERVE`,
+//! but the host only commits a page once the guest (or the loader) first
+//! touches it, so a `--size` of 4 GiB costs no more than the memory actually
+//! used. Untouched memory reads as zero, as it would in a fresh process.
+//!
+//! Big guests that roam over a lot of memory spend much of their time in host
+//! TLB misses. `Hugepages` can align the mapping to 2 MiB, which is all a host
+//! with transparent hugepages always on needs to back it with them, and ask for
+//! them with `madvise` on hosts where they're opt-in.
+//! `benches/arena.rs` compares the two.
+//!
+//! The guest's ROM is mapped copy-on-write from a `SharedImage`, so every core
+//! running the same program shares the pages holding its code. Big data
+//! segments are mapped copy-on-write from the ELF file itself, see
+//! `MappedFile`, so the host only reads the pages the guest touches.
+
+use std::{
+    fmt,
+    fs::File,
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::Path,
+    ptr,
+    str::FromStr,
+    sync::{Arc, Mutex, Weak},
+};
+
+const HUGEPAGE_SIZE: usize = 2 << 20;
+
+/// How the guest memory arena is mapped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Hugepages {
+    /// Wherever the host puts it, in ordinary pages
+    #[default]
+    Off,
+    /// Aligned to 2 MiB, so the host can use hugepages if it would anyway
+    Align,
+    /// Aligned, and advised to use transparent hugepages
+    Madvise,
+}
+
+impl FromStr for Hugepages {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Hugepages::Off),
+            "align" => Ok(Hugepages::Align),
+            "madvise" => Ok(Hugepages::Madvise),
+            _ => Err(format!(
+                "unknown hugepages mode '{s}', expected off, align or madvise"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Hugepages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Hugepages::Off => "off",
+            Hugepages::Align => "align",
+            Hugepages::Madvise => "madvise",
+        })
+    }
+}
+
+pub(crate) struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    pub(crate) fn new(len: usize, hugepages: Hugepages) -> io::Result<Self> {
+        if hugepages == Hugepages::Off {
+            let ptr = map(len)?;
+            return Ok(Self { ptr, len });
+        }
+
+        // over-allocate by a hugepage and trim either side to align it
+        let raw = map(len + HUGEPAGE_SIZE)?;
+        let ptr = raw.wrapping_add(raw.align_offset(HUGEPAGE_SIZE));
+        unsafe {
+            let head = ptr.offset_from(raw) as usize;
+            if head != 0 {
+                libc::munmap(raw.cast(), head);
+            }
+            let tail = HUGEPAGE_SIZE - head;
+            if tail != 0 {
+                let end = ptr.add(len.next_multiple_of(page_size()));
+                libc::munmap(end.cast(), tail);
+            }
+        }
+        let mapping = Self { ptr, len };
+
+        if hugepages == Hugepages::Madvise {
+            let ret = unsafe { libc::madvise(ptr.cast(), len, libc::MADV_HUGEPAGE) };
+            if ret != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(mapping)
+    }
+
+    /// The start of the mapping, which is page aligned
+    pub(crate) fn as_ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// How many bytes of the mapping the host has committed, see mincore(2)
+    pub(crate) fn resident(&self) -> io::Result<usize> {
+        let page = page_size();
+        let mut pages = vec![0u8; self.len.div_ceil(page)];
+        let ret = unsafe { libc::mincore(self.ptr.cast(), self.len, pages.as_mut_ptr().cast()) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(pages.iter().filter(|&&page| page & 1 != 0).count() * page)
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr.cast(), self.len);
+        }
+    }
+}
+
+/// Page aligned contents kept in a memfd, to be mapped into any number of
+/// arenas without a copy each
+pub(crate) struct SharedImage {
+    fd: OwnedFd,
+    // a read-only view, to compare against
+    view: *const u8,
+    len: usize,
+}
+
+// the view is never written through
+unsafe impl Send for SharedImage {}
+unsafe impl Sync for SharedImage {}
+
+// images still mapped somewhere, to be reused by later mappings of the same bytes
+static IMAGES: Mutex<Vec<Weak<SharedImage>>> = Mutex::new(Vec::new());
+
+impl SharedImage {
+    /// An image of `bytes`, which must be whole pages, shared with any other
+    /// live image of the same bytes
+    pub(crate) fn get(bytes: &[u8]) -> io::Result<Arc<Self>> {
+        let mut images = IMAGES.lock().unwrap();
+        images.retain(|image| image.strong_count() != 0);
+        if let Some(image) = images
+            .iter()
+            .filter_map(Weak::upgrade)
+            .find(|image| image.as_slice() == bytes)
+        {
+            return Ok(image);
+        }
+
+        let image = Arc::new(Self::new(bytes)?);
+        images.push(Arc::downgrade(&image));
+        Ok(image)
+    }
+
+    fn new(bytes: &[u8]) -> io::Result<Self> {
+        let len = bytes.len();
+        let fd = unsafe { libc::memfd_create(c"riscy-rom".as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        if unsafe { libc::ftruncate(fd.as_raw_fd(), len as libc::off_t) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let view = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if view == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), view.cast(), len);
+            libc::mprotect(view, len, libc::PROT_READ);
+        }
+
+        Ok(Self {
+            fd,
+            view: view.cast(),
+            len,
+        })
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.view, self.len) }
+    }
+
+    /// Replaces `offset..offset + len` of `mapping` with a private,
+    /// copy-on-write mapping of the image. `offset` must be page aligned
+    pub(crate) fn map_into(&self, mapping: &Mapping, offset: usize) -> io::Result<()> {
+        assert!(offset + self.len <= mapping.len());
+        let ptr = unsafe {
+            libc::mmap(
+                mapping.as_ptr().add(offset).cast(),
+                self.len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_FIXED,
+                self.fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SharedImage {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.view as *mut _, self.len);
+        }
+    }
+}
+
+/// A file mapped read-only, for loading an ELF without reading all of it
+pub(crate) struct MappedFile {
+    file: File,
+    ptr: *const u8,
+    len: usize,
+}
+
+// the mapping is never written through
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    pub(crate) fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Ok(Self {
+                file,
+                ptr: ptr::NonNull::dangling().as_ptr(),
+                len,
+            });
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            file,
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    /// Puts the `len` bytes of the file at `offset` at `addr` in `mapping`.
+    /// The whole pages are mapped copy-on-write, to be read from the file when
+    /// the guest first touches them, and the partial pages at either end,
+    /// which other segments may share, are copied. `addr` and `offset` must be
+    /// the same distance into a page
+    pub(crate) fn load_into(
+        &self,
+        mapping: &Mapping,
+        addr: usize,
+        offset: usize,
+        len: usize,
+    ) -> io::Result<()> {
+        let page = page_size();
+        assert!(addr + len <= mapping.len() && offset + len <= self.len);
+        assert_eq!(addr % page, offset % page);
+
+        let end = addr + len;
+        let first = addr.next_multiple_of(page).min(end);
+        let last = (end / page * page).max(first);
+        let copy = |start: usize, end: usize| unsafe {
+            ptr::copy_nonoverlapping(
+                self.ptr.add(offset + (start - addr)),
+                mapping.as_ptr().add(start),
+                end - start,
+            );
+        };
+        copy(addr, first);
+        copy(last, end);
+        if first == last {
+            return Ok(());
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                mapping.as_ptr().add(first).cast(),
+                last - first,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_FIXED,
+                self.file.as_raw_fd(),
+                (offset + (first - addr)) as libc::off_t,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for MappedFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MappedFile")
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            unsafe {
+                libc::munmap(self.ptr as *mut _, self.len);
+            }
+        }
+    }
+}
+
+fn map(len: usize) -> io::Result<*mut u8> {
+    let ptr = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+            -1,
+            0,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ptr.cast())
+}
+
+pub(crate) fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
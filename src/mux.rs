@@ -0,0 +1,353 @@
+//! Telling the guest's output streams apart on the host.
+//!
+//! A guest can print several ways at once: `write` to stdout and stderr, a
+//! `--console` device, `--htif`'s putchar, and riscy logs alongside them, all
+//! interleaving on the host's terminal. A `Mux` sits between them and the
+//! host, and for each line of each stream can:
+//!
+//! - tag it with the stream it came from (`riscy --tag-output`), `[stdout]`,
+//!   `[stderr]`, `[console]`, `[htif]` or `[log]`
+//! - timestamp it (`--timestamp-output instret|wall`), with the guest's
+//!   instruction count or the seconds since the run started
+//! - colour it by stream (`--color-output`), where it's going to a terminal
+//! - send it to a file instead (`--output-file stderr=err.log`), several
+//!   streams to the same file if they name it
+//!
+//! A line another stream breaks into is ended first, so every line starts
+//! with its prefix. A partial line, like a prompt, is written straight away
+//! rather than held back for the rest of it. Devices and the log don't know
+//! the instruction count, so their lines are stamped with the guest's as of
+//! the last line the core wrote.
+//!
+//! Anything `Core32::capture_output` keeps doesn't reach the mux.
+
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufWriter, IsTerminal, Write},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+/// Where output comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    /// The guest's fd 1
+    Stdout,
+    /// The guest's fd 2
+    Stderr,
+    /// A `console::MagicConsole`
+    Console,
+    /// HTIF's putchar, see `htif`
+    Htif,
+    /// riscy's own logging
+    Log,
+}
+
+impl Stream {
+    const ALL: [Stream; 5] = [
+        Stream::Stdout,
+        Stream::Stderr,
+        Stream::Console,
+        Stream::Htif,
+        Stream::Log,
+    ];
+
+    fn idx(self) -> usize {
+        self as usize
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+            Stream::Console => "console",
+            Stream::Htif => "htif",
+            Stream::Log => "log",
+        }
+    }
+
+    // the SGR parameters of its colour
+    fn color(self) -> &'static str {
+        match self {
+            Stream::Stdout => "0",
+            Stream::Stderr => "31",
+            Stream::Console => "36",
+            Stream::Htif => "32",
+            Stream::Log => "2",
+        }
+    }
+
+    // whether it goes to the host's stderr unless sent to a file
+    fn is_err(self) -> bool {
+        matches!(self, Stream::Stderr | Stream::Log)
+    }
+}
+
+impl fmt::Display for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Stream {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Stream::ALL
+            .into_iter()
+            .find(|stream| stream.name() == s)
+            .ok_or_else(|| {
+                format!("unknown stream '{s}', expected stdout, stderr, console, htif or log")
+            })
+    }
+}
+
+/// What lines are timestamped with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timestamps {
+    /// The guest's instruction count
+    Instret,
+    /// Seconds since the mux was made
+    Wall,
+}
+
+impl FromStr for Timestamps {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "instret" => Ok(Timestamps::Instret),
+            "wall" => Ok(Timestamps::Wall),
+            _ => Err(format!("invalid clock '{s}', expected instret or wall")),
+        }
+    }
+}
+
+/// A stream sent to a file, parsed from `STREAM=PATH`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamFile {
+    pub stream: Stream,
+    pub path: PathBuf,
+}
+
+impl FromStr for StreamFile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (stream, path) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected STREAM=PATH, got '{s}'"))?;
+        Ok(StreamFile {
+            stream: stream.parse()?,
+            path: path.into(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MuxConfig {
+    pub tag: bool,
+    pub timestamps: Option<Timestamps>,
+    /// Only for streams going to a terminal
+    pub color: bool,
+    pub files: Vec<StreamFile>,
+}
+
+struct Sink {
+    out: Box<dyn Write + Send>,
+    terminal: bool,
+    // the stream part way through a line here
+    mid_line: Option<Stream>,
+}
+
+struct Inner {
+    config: MuxConfig,
+    start: Instant,
+    // the guest's instruction count as of the core's last write
+    instret: u64,
+    sinks: Vec<Sink>,
+    // the sink each stream goes to
+    routes: [usize; 5],
+    at_line_start: [bool; 5],
+}
+
+/// The mux, see the module docs. Clones share it
+#[derive(Clone)]
+pub struct Mux(Arc<Mutex<Inner>>);
+
+impl fmt::Debug for Mux {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Mux")
+            .field(&self.0.lock().unwrap().config)
+            .finish()
+    }
+}
+
+impl Mux {
+    /// A mux as `config` says, creating its files
+    pub fn new(config: MuxConfig) -> io::Result<Self> {
+        let mut sinks = vec![
+            Sink {
+                out: Box::new(io::stdout()),
+                terminal: io::stdout().is_terminal(),
+                mid_line: None,
+            },
+            Sink {
+                out: Box::new(io::stderr()),
+                terminal: io::stderr().is_terminal(),
+                mid_line: None,
+            },
+        ];
+        // one sink for both if they're the same file, as when run from a
+        // terminal, for a line from one to end one from the other
+        let shared = same_file(libc::STDOUT_FILENO, libc::STDERR_FILENO);
+        let mut routes = Stream::ALL.map(|stream| (stream.is_err() && !shared) as usize);
+        let mut paths: Vec<&PathBuf> = Vec::new();
+        for file in &config.files {
+            let sink = match paths.iter().position(|&path| *path == file.path) {
+                Some(idx) => idx + 2,
+                None => {
+                    let out = File::create(&file.path).map_err(|err| {
+                        io::Error::new(err.kind(), format!("{}: {err}", file.path.display()))
+                    })?;
+                    paths.push(&file.path);
+                    sinks.push(Sink {
+                        out: Box::new(BufWriter::new(out)),
+                        terminal: false,
+                        mid_line: None,
+                    });
+                    sinks.len() - 1
+                }
+            };
+            routes[file.stream.idx()] = sink;
+        }
+
+        Ok(Mux(Arc::new(Mutex::new(Inner {
+            config: config.clone(),
+            start: Instant::now(),
+            instret: 0,
+            sinks,
+            routes,
+            at_line_start: [true; 5],
+        }))))
+    }
+
+    /// Writes `buf` to `stream`, with the guest's instruction count `instret`
+    /// if the writer knows it
+    pub fn write(&self, stream: Stream, buf: &[u8], instret: Option<u64>) -> io::Result<()> {
+        let mut inner = self.0.lock().unwrap();
+        if let Some(instret) = instret {
+            inner.instret = instret;
+        }
+        inner.write(stream, buf)
+    }
+
+    /// A writer into `stream`, for devices and loggers
+    pub fn writer(&self, stream: Stream) -> MuxWriter {
+        MuxWriter {
+            mux: self.clone(),
+            stream,
+        }
+    }
+
+    /// Whether `stream` goes to a terminal, as loggers want to know
+    pub fn is_terminal(&self, stream: Stream) -> bool {
+        let inner = self.0.lock().unwrap();
+        inner.sinks[inner.routes[stream.idx()]].terminal
+    }
+
+    /// Flushes the files, which are buffered
+    pub fn flush(&self) -> io::Result<()> {
+        let mut inner = self.0.lock().unwrap();
+        for sink in &mut inner.sinks {
+            sink.out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Inner {
+    fn write(&mut self, stream: Stream, buf: &[u8]) -> io::Result<()> {
+        let prefix = self.prefix(stream);
+        let sink = &mut self.sinks[self.routes[stream.idx()]];
+        let color = self.config.color && sink.terminal;
+
+        // a line another stream left unfinished ends here
+        if let Some(other) = sink.mid_line.filter(|&other| other != stream) {
+            sink.out.write_all(b"\n")?;
+            self.at_line_start[other.idx()] = true;
+        }
+
+        let at_line_start = &mut self.at_line_start[stream.idx()];
+        for line in buf.split_inclusive(|&byte| byte == b'\n') {
+            let (text, newline) = match line.strip_suffix(b"\n") {
+                Some(text) => (text, true),
+                None => (line, false),
+            };
+            if color {
+                write!(sink.out, "\x1b[{}m", stream.color())?;
+            }
+            if *at_line_start {
+                sink.out.write_all(prefix.as_bytes())?;
+            }
+            sink.out.write_all(text)?;
+            if color {
+                sink.out.write_all(b"\x1b[0m")?;
+            }
+            if newline {
+                sink.out.write_all(b"\n")?;
+            }
+            *at_line_start = newline;
+        }
+
+        sink.mid_line = (!*at_line_start).then_some(stream);
+        // straight through to the terminal, for prompts
+        if self.routes[stream.idx()] < 2 {
+            sink.out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn prefix(&self, stream: Stream) -> String {
+        let time = self.config.timestamps.map(|clock| match clock {
+            Timestamps::Instret => format!("{:>12}", self.instret),
+            Timestamps::Wall => format!("{:>10.6}", self.start.elapsed().as_secs_f64()),
+        });
+        let tag = self.config.tag.then(|| stream.name());
+        match (time, tag) {
+            (Some(time), Some(tag)) => format!("[{time} {tag:<7}] "),
+            (Some(time), None) => format!("[{time}] "),
+            (None, Some(tag)) => format!("[{tag:<7}] "),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+fn same_file(a: i32, b: i32) -> bool {
+    let stat = |fd| {
+        let mut st: libc::stat = unsafe { std::mem::zeroed() };
+        (unsafe { libc::fstat(fd, &mut st) } == 0).then_some((st.st_dev, st.st_ino))
+    };
+    stat(a).is_some_and(|a| Some(a) == stat(b))
+}
+
+/// A stream of a `Mux`, as a `Write`
+#[derive(Clone)]
+pub struct MuxWriter {
+    mux: Mux,
+    stream: Stream,
+}
+
+impl Write for MuxWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.mux.write(self.stream, buf, None)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.mux.flush()
+    }
+}
@@ -0,0 +1,419 @@
+//! Library functions serviced by the host, for `riscy --native`.
+//!
+//! A call to a function riscy knows, libm's like `expf` and `pow`, or
+//! compiler-rt's soft-float and 64-bit helpers like `__muldf3` and
+//! `__udivdi3`, is computed natively and goes straight back to the caller, as
+//! calls to `cos` and `sin` always are. It's for programs spending their time
+//! in them, soft-float builds above all. The helpers' results are exactly what
+//! compiler-rt's would be, rounding to nearest, down to which NaN comes back and
+//! what a conversion out of range gives; libm's are as accurate as the host's,
+//! which can differ from the guest's own in the last bit.
+//!
+//! Arguments and results are where the program's float ABI puts them, see
+//! `load::FloatAbi`, except for the helpers, which take floats in integer
+//! registers whatever it is. Dividing by zero in `__divdi3` and friends gives
+//! what `div` and `rem` would. Only calls are caught, not tail calls jumping
+//! into the function.
+
+use std::cmp::Ordering;
+
+use crate::load::FloatAbi;
+
+/// The type of an argument or result
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Ty {
+    Int,
+    Long,
+    Float,
+    Double,
+}
+
+/// Where an argument or result is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Loc {
+    /// `a{n}`
+    Int(usize),
+    /// `a{n}` and `a{n + 1}`, low half first
+    Pair(usize),
+    /// `fa{n}`
+    Fp(u8),
+}
+
+trait Value: Copy {
+    const TY: Ty;
+
+    fn from_bits(bits: u64) -> Self;
+    fn into_bits(self) -> u64;
+}
+
+macro_rules! value {
+    ($($ty:ty => $kind:ident, |$bits:ident| $from:expr, |$value:ident| $into:expr;)*) => {
+        $(impl Value for $ty {
+            const TY: Ty = Ty::$kind;
+
+            fn from_bits($bits: u64) -> Self {
+                $from
+            }
+
+            fn into_bits(self) -> u64 {
+                let $value = self;
+                $into
+            }
+        })*
+    };
+}
+
+value! {
+    i32 => Int, |bits| bits as i32, |value| value as u32 as u64;
+    u32 => Int, |bits| bits as u32, |value| value as u64;
+    i64 => Long, |bits| bits as i64, |value| value as u64;
+    u64 => Long, |bits| bits, |value| value;
+    f32 => Float, |bits| f32::from_bits(bits as u32), |value| value.to_bits() as u64;
+    f64 => Double, |bits| f64::from_bits(bits), |value| value.to_bits();
+}
+
+/// A function riscy can service, see the module docs
+#[derive(Debug)]
+pub struct Native {
+    pub name: &'static str,
+    pub(crate) args: &'static [Ty],
+    pub(crate) ret: Ty,
+    // a compiler-rt helper, taking floats in integer registers
+    helper: bool,
+    // the arguments' bits to the result's
+    pub(crate) call: fn(&[u64]) -> u64,
+}
+
+impl Native {
+    /// Where its arguments are, then its result, for a program with `abi`
+    pub(crate) fn locations(&self, abi: FloatAbi) -> (Vec<Loc>, Loc) {
+        let abi = if self.helper { FloatAbi::Soft } else { abi };
+        let place = |ty: Ty, int: &mut usize, fp: &mut u8| {
+            let in_fp = match ty {
+                Ty::Int | Ty::Long => false,
+                Ty::Float => abi != FloatAbi::Soft,
+                Ty::Double => matches!(abi, FloatAbi::Double | FloatAbi::Quad),
+            };
+            if in_fp {
+                *fp += 1;
+                Loc::Fp(*fp - 1)
+            } else if matches!(ty, Ty::Long | Ty::Double) {
+                *int += 2;
+                Loc::Pair(*int - 2)
+            } else {
+                *int += 1;
+                Loc::Int(*int - 1)
+            }
+        };
+
+        let (mut int, mut fp) = (0, 0);
+        let args = self
+            .args
+            .iter()
+            .map(|&ty| place(ty, &mut int, &mut fp))
+            .collect();
+        (args, place(self.ret, &mut 0, &mut 0))
+    }
+}
+
+macro_rules! native {
+    (@ $helper:literal $name:literal |$($arg:ident: $ty:ty),*| -> $ret:ty $body:block) => {
+        Native {
+            name: $name,
+            args: &[$(<$ty as Value>::TY),*],
+            ret: <$ret as Value>::TY,
+            helper: $helper,
+            call: |args| {
+                let mut args = args.iter();
+                $(let $arg = <$ty as Value>::from_bits(*args.next().unwrap());)*
+                <$ret as Value>::into_bits($body)
+            },
+        }
+    };
+    (libm $($rest:tt)*) => {
+        native!(@ false $($rest)*)
+    };
+    (helper $($rest:tt)*) => {
+        native!(@ true $($rest)*)
+    };
+}
+
+// compiler-rt's `__ledf2`, also `__ltdf2`, `__eqdf2` and `__nedf2`: 1 if either
+// is NaN
+fn cmp_le<T: PartialOrd>(a: T, b: T) -> i32 {
+    match a.partial_cmp(&b) {
+        Some(Ordering::Less) => -1,
+        Some(Ordering::Equal) => 0,
+        Some(Ordering::Greater) | None => 1,
+    }
+}
+
+// `__gedf2`, also `__gtdf2`: -1 if either is NaN
+fn cmp_ge<T: PartialOrd>(a: T, b: T) -> i32 {
+    match a.partial_cmp(&b) {
+        Some(Ordering::Less) | None => -1,
+        Some(Ordering::Equal) => 0,
+        Some(Ordering::Greater) => 1,
+    }
+}
+
+// compiler-rt's NaN results, which the host's needn't be: the first operand that's
+// NaN, quieted, or a positive quiet NaN if neither is but the result is
+fn nan_d(a: f64, b: f64, res: f64) -> f64 {
+    match [a, b].into_iter().find(|val| val.is_nan()) {
+        Some(nan) => f64::from_bits(nan.to_bits() | 1 << 51),
+        None if res.is_nan() => f64::NAN,
+        None => res,
+    }
+}
+
+fn nan_s(a: f32, b: f32, res: f32) -> f32 {
+    match [a, b].into_iter().find(|val| val.is_nan()) {
+        Some(nan) => f32::from_bits(nan.to_bits() | 1 << 22),
+        None if res.is_nan() => f32::NAN,
+        None => res,
+    }
+}
+
+// `__extendsfdf2`, which keeps a NaN's sign and payload, quiet or not
+fn extend(a: f32) -> f64 {
+    if a.is_nan() {
+        let bits = a.to_bits() as u64;
+        f64::from_bits((bits >> 31) << 63 | 0x7ff << 52 | (bits & 0x7f_ffff) << 29)
+    } else {
+        a as f64
+    }
+}
+
+// `__truncdfsf2`, which keeps a NaN's sign and the top of its payload, quieted
+fn trunc(a: f64) -> f32 {
+    if a.is_nan() {
+        let bits = a.to_bits();
+        f32::from_bits(((bits >> 63) as u32) << 31 | 0x7fc0_0000 | (bits >> 29) as u32 & 0x3f_ffff)
+    } else {
+        a as f32
+    }
+}
+
+// `__fixdfsi` and friends, on the bits of a float with `frac` bits of fraction, to
+// an integer `width` bits wide: toward zero, saturating by the sign (a NaN's too)
+// once the exponent is `width` or more, and wrapping when it's just under
+fn fixint(bits: u64, frac: u32, width: u32) -> u64 {
+    let (exponent, significand, negative) = unpack(bits, frac);
+    let max = u64::MAX >> (64 - width) >> 1;
+    if exponent < 0 {
+        0
+    } else if exponent >= width as i32 {
+        if negative {
+            !max
+        } else {
+            max
+        }
+    } else {
+        let magnitude = shift(significand, exponent - frac as i32);
+        if negative {
+            magnitude.wrapping_neg()
+        } else {
+            magnitude
+        }
+    }
+}
+
+// `__fixunsdfsi` and friends: 0 for anything negative, even -0.5 or a negative
+// NaN, and all ones once the exponent is `width` or more
+fn fixuint(bits: u64, frac: u32, width: u32) -> u64 {
+    let (exponent, significand, negative) = unpack(bits, frac);
+    if negative || exponent < 0 {
+        0
+    } else if exponent >= width as i32 {
+        u64::MAX >> (64 - width)
+    } else {
+        shift(significand, exponent - frac as i32)
+    }
+}
+
+// the unbiased exponent, the significand with its implicit bit and the sign of a
+// single (`frac` 23) or a double (52)
+fn unpack(bits: u64, frac: u32) -> (i32, u64, bool) {
+    let exp_bits = if frac == 23 { 8 } else { 11 };
+    let exponent = (bits >> frac) as u32 & ((1 << exp_bits) - 1);
+    let significand = bits & ((1 << frac) - 1) | 1 << frac;
+    let negative = bits >> (frac + exp_bits) & 1 != 0;
+    (
+        exponent as i32 - ((1 << (exp_bits - 1)) - 1),
+        significand,
+        negative,
+    )
+}
+
+fn shift(val: u64, by: i32) -> u64 {
+    if by < 0 {
+        val >> -by
+    } else {
+        val << by
+    }
+}
+
+static NATIVES: &[Native] = &[
+    native!(libm "sin" |x: f64| -> f64 { x.sin() }),
+    native!(libm "cos" |x: f64| -> f64 { x.cos() }),
+    native!(libm "tan" |x: f64| -> f64 { x.tan() }),
+    native!(libm "asin" |x: f64| -> f64 { x.asin() }),
+    native!(libm "acos" |x: f64| -> f64 { x.acos() }),
+    native!(libm "atan" |x: f64| -> f64 { x.atan() }),
+    native!(libm "atan2" |y: f64, x: f64| -> f64 { y.atan2(x) }),
+    native!(libm "sinh" |x: f64| -> f64 { x.sinh() }),
+    native!(libm "cosh" |x: f64| -> f64 { x.cosh() }),
+    native!(libm "tanh" |x: f64| -> f64 { x.tanh() }),
+    native!(libm "exp" |x: f64| -> f64 { x.exp() }),
+    native!(libm "exp2" |x: f64| -> f64 { x.exp2() }),
+    native!(libm "expm1" |x: f64| -> f64 { x.exp_m1() }),
+    native!(libm "log" |x: f64| -> f64 { x.ln() }),
+    native!(libm "log2" |x: f64| -> f64 { x.log2() }),
+    native!(libm "log10" |x: f64| -> f64 { x.log10() }),
+    native!(libm "log1p" |x: f64| -> f64 { x.ln_1p() }),
+    native!(libm "pow" |x: f64, y: f64| -> f64 { x.powf(y) }),
+    native!(libm "sqrt" |x: f64| -> f64 { x.sqrt() }),
+    native!(libm "cbrt" |x: f64| -> f64 { x.cbrt() }),
+    native!(libm "hypot" |x: f64, y: f64| -> f64 { x.hypot(y) }),
+    native!(libm "fmod" |x: f64, y: f64| -> f64 { x % y }),
+    native!(libm "floor" |x: f64| -> f64 { x.floor() }),
+    native!(libm "ceil" |x: f64| -> f64 { x.ceil() }),
+    native!(libm "trunc" |x: f64| -> f64 { x.trunc() }),
+    native!(libm "round" |x: f64| -> f64 { x.round() }),
+    native!(libm "fabs" |x: f64| -> f64 { x.abs() }),
+    native!(libm "fmin" |x: f64, y: f64| -> f64 { x.min(y) }),
+    native!(libm "fmax" |x: f64, y: f64| -> f64 { x.max(y) }),
+    native!(libm "copysign" |x: f64, y: f64| -> f64 { x.copysign(y) }),
+    native!(libm "sinf" |x: f32| -> f32 { x.sin() }),
+    native!(libm "cosf" |x: f32| -> f32 { x.cos() }),
+    native!(libm "tanf" |x: f32| -> f32 { x.tan() }),
+    native!(libm "asinf" |x: f32| -> f32 { x.asin() }),
+    native!(libm "acosf" |x: f32| -> f32 { x.acos() }),
+    native!(libm "atanf" |x: f32| -> f32 { x.atan() }),
+    native!(libm "atan2f" |y: f32, x: f32| -> f32 { y.atan2(x) }),
+    native!(libm "sinhf" |x: f32| -> f32 { x.sinh() }),
+    native!(libm "coshf" |x: f32| -> f32 { x.cosh() }),
+    native!(libm "tanhf" |x: f32| -> f32 { x.tanh() }),
+    native!(libm "expf" |x: f32| -> f32 { x.exp() }),
+    native!(libm "exp2f" |x: f32| -> f32 { x.exp2() }),
+    native!(libm "expm1f" |x: f32| -> f32 { x.exp_m1() }),
+    native!(libm "logf" |x: f32| -> f32 { x.ln() }),
+    native!(libm "log2f" |x: f32| -> f32 { x.log2() }),
+    native!(libm "log10f" |x: f32| -> f32 { x.log10() }),
+    native!(libm "log1pf" |x: f32| -> f32 { x.ln_1p() }),
+    native!(libm "powf" |x: f32, y: f32| -> f32 { x.powf(y) }),
+    native!(libm "sqrtf" |x: f32| -> f32 { x.sqrt() }),
+    native!(libm "cbrtf" |x: f32| -> f32 { x.cbrt() }),
+    native!(libm "hypotf" |x: f32, y: f32| -> f32 { x.hypot(y) }),
+    native!(libm "fmodf" |x: f32, y: f32| -> f32 { x % y }),
+    native!(libm "floorf" |x: f32| -> f32 { x.floor() }),
+    native!(libm "ceilf" |x: f32| -> f32 { x.ceil() }),
+    native!(libm "truncf" |x: f32| -> f32 { x.trunc() }),
+    native!(libm "roundf" |x: f32| -> f32 { x.round() }),
+    native!(libm "fabsf" |x: f32| -> f32 { x.abs() }),
+    native!(libm "fminf" |x: f32, y: f32| -> f32 { x.min(y) }),
+    native!(libm "fmaxf" |x: f32, y: f32| -> f32 { x.max(y) }),
+    native!(libm "copysignf" |x: f32, y: f32| -> f32 { x.copysign(y) }),
+    native!(helper "__adddf3" |a: f64, b: f64| -> f64 { nan_d(a, b, a + b) }),
+    native!(helper "__subdf3" |a: f64, b: f64| -> f64 { nan_d(a, -b, a - b) }),
+    native!(helper "__muldf3" |a: f64, b: f64| -> f64 { nan_d(a, b, a * b) }),
+    native!(helper "__divdf3" |a: f64, b: f64| -> f64 { nan_d(a, b, a / b) }),
+    native!(helper "__negdf2" |a: f64| -> f64 { -a }),
+    native!(helper "__eqdf2" |a: f64, b: f64| -> i32 { cmp_le(a, b) }),
+    native!(helper "__nedf2" |a: f64, b: f64| -> i32 { cmp_le(a, b) }),
+    native!(helper "__ltdf2" |a: f64, b: f64| -> i32 { cmp_le(a, b) }),
+    native!(helper "__ledf2" |a: f64, b: f64| -> i32 { cmp_le(a, b) }),
+    native!(helper "__gtdf2" |a: f64, b: f64| -> i32 { cmp_ge(a, b) }),
+    native!(helper "__gedf2" |a: f64, b: f64| -> i32 { cmp_ge(a, b) }),
+    native!(helper "__unorddf2" |a: f64, b: f64| -> i32 { (a.is_nan() || b.is_nan()) as i32 }),
+    native!(helper "__floatsidf" |a: i32| -> f64 { a as f64 }),
+    native!(helper "__floatunsidf" |a: u32| -> f64 { a as f64 }),
+    native!(helper "__floatdidf" |a: i64| -> f64 { a as f64 }),
+    native!(helper "__floatundidf" |a: u64| -> f64 { a as f64 }),
+    native!(helper "__fixdfsi" |a: f64| -> i32 { fixint(a.to_bits(), 52, 32) as i32 }),
+    native!(helper "__fixunsdfsi" |a: f64| -> u32 { fixuint(a.to_bits(), 52, 32) as u32 }),
+    native!(helper "__fixdfdi" |a: f64| -> i64 { fixint(a.to_bits(), 52, 64) as i64 }),
+    native!(helper "__fixunsdfdi" |a: f64| -> u64 { fixuint(a.to_bits(), 52, 64) }),
+    native!(helper "__extendsfdf2" |a: f32| -> f64 { extend(a) }),
+    native!(helper "__truncdfsf2" |a: f64| -> f32 { trunc(a) }),
+    native!(helper "__addsf3" |a: f32, b: f32| -> f32 { nan_s(a, b, a + b) }),
+    native!(helper "__subsf3" |a: f32, b: f32| -> f32 { nan_s(a, -b, a - b) }),
+    native!(helper "__mulsf3" |a: f32, b: f32| -> f32 { nan_s(a, b, a * b) }),
+    native!(helper "__divsf3" |a: f32, b: f32| -> f32 { nan_s(a, b, a / b) }),
+    native!(helper "__negsf2" |a: f32| -> f32 { -a }),
+    native!(helper "__eqsf2" |a: f32, b: f32| -> i32 { cmp_le(a, b) }),
+    native!(helper "__nesf2" |a: f32, b: f32| -> i32 { cmp_le(a, b) }),
+    native!(helper "__ltsf2" |a: f32, b: f32| -> i32 { cmp_le(a, b) }),
+    native!(helper "__lesf2" |a: f32, b: f32| -> i32 { cmp_le(a, b) }),
+    native!(helper "__gtsf2" |a: f32, b: f32| -> i32 { cmp_ge(a, b) }),
+    native!(helper "__gesf2" |a: f32, b: f32| -> i32 { cmp_ge(a, b) }),
+    native!(helper "__unordsf2" |a: f32, b: f32| -> i32 { (a.is_nan() || b.is_nan()) as i32 }),
+    native!(helper "__floatsisf" |a: i32| -> f32 { a as f32 }),
+    native!(helper "__floatunsisf" |a: u32| -> f32 { a as f32 }),
+    native!(helper "__floatdisf" |a: i64| -> f32 { a as f32 }),
+    native!(helper "__floatundisf" |a: u64| -> f32 { a as f32 }),
+    native!(helper "__fixsfsi" |a: f32| -> i32 { fixint(a.to_bits() as u64, 23, 32) as i32 }),
+    native!(helper "__fixunssfsi" |a: f32| -> u32 { fixuint(a.to_bits() as u64, 23, 32) as u32 }),
+    native!(helper "__fixsfdi" |a: f32| -> i64 { fixint(a.to_bits() as u64, 23, 64) as i64 }),
+    native!(helper "__fixunssfdi" |a: f32| -> u64 { fixuint(a.to_bits() as u64, 23, 64) }),
+    native!(helper "__muldi3" |a: i64, b: i64| -> i64 { a.wrapping_mul(b) }),
+    native!(helper "__divdi3" |a: i64, b: i64| -> i64 { if b == 0 { -1 } else { a.wrapping_div(b) } }),
+    native!(helper "__moddi3" |a: i64, b: i64| -> i64 { if b == 0 { a } else { a.wrapping_rem(b) } }),
+    native!(helper "__udivdi3" |a: u64, b: u64| -> u64 { a.checked_div(b).unwrap_or(u64::MAX) }),
+    native!(helper "__umoddi3" |a: u64, b: u64| -> u64 { a.checked_rem(b).unwrap_or(a) }),
+    native!(helper "__ashldi3" |a: u64, b: u32| -> u64 { a.wrapping_shl(b) }),
+    native!(helper "__lshrdi3" |a: u64, b: u32| -> u64 { a.wrapping_shr(b) }),
+    native!(helper "__ashrdi3" |a: i64, b: u32| -> i64 { a.wrapping_shr(b) }),
+    native!(helper "__clzsi2" |a: u32| -> i32 { a.leading_zeros() as i32 }),
+    native!(helper "__ctzsi2" |a: u32| -> i32 { a.trailing_zeros() as i32 }),
+    native!(helper "__popcountsi2" |a: u32| -> i32 { a.count_ones() as i32 }),
+];
+
+/// Every function riscy can service
+pub fn all() -> &'static [Native] {
+    NATIVES
+}
+
+/// The function riscy services as `name`, if there's one
+pub fn lookup(name: &str) -> Option<&'static Native> {
+    NATIVES.iter().find(|native| native.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::{AdaptiveMemReader, Core32},
+        load::LoadedElf,
+    };
+
+    // the guest has compiler-rt's float helpers twice over and exits with the first
+    // case where the copy serviced here disagrees with the one it runs, on NaNs,
+    // infinities and conversions out of range
+    #[test]
+    fn float_helpers_match_compiler_rt() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/benches/guests/compiler_rt.elf"
+        );
+        let elf = LoadedElf::load(path).unwrap();
+        let symbols = elf.symbols.clone();
+        let mut core = Core32::<AdaptiveMemReader<u32>>::new(elf, None, 16 << 20, false);
+        let floats = NATIVES.iter().filter(|native| {
+            let mut tys = native.args.iter().chain([&native.ret]);
+            native.helper && tys.any(|ty| matches!(ty, Ty::Float | Ty::Double))
+        });
+        for native in floats {
+            let sym = symbols.iter().find(|sym| sym.name == native.name);
+            let sym = sym.unwrap_or_else(|| panic!("the guest has no {}", native.name));
+            core.add_native(sym.addr as u32, native);
+        }
+
+        let info = core.run();
+        assert_eq!(info.return_code, 0, "case {} disagrees", info.return_code);
+        for (name, calls) in core.native_calls() {
+            assert!(calls > 0, "{name} wasn't serviced");
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! What the guest can observe that differs from run to run: the time, and
+//! random bytes.
+//!
+//! By default both come from the host. `Nondeterminism::Virtual` instead
+//! derives the time from the number of instructions retired, as if each took
+//! a nanosecond, or as long as `set_rate` says, and random bytes from a fixed seed, so two runs of a
+//! program over the same input do exactly the same thing. riscy delivers no
+//! interrupts, so there's nothing else to pin down; hostcalls are up to
+//! whoever registers them.
+
+use std::{
+    fs::File,
+    io::Read,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::throttle::Mips;
+
+/// The frequency the `time` csr counts at
+pub const TIMEBASE_HZ: u64 = 10_000_000;
+
+// a 1GHz core that retires an instruction every cycle
+const PS_PER_INSTRUCTION: u64 = 1_000;
+
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(Debug, Clone)]
+pub enum Nondeterminism {
+    Host {
+        start: Instant,
+    },
+    /// Time starts at the unix epoch when the run does
+    Virtual {
+        rng: u64,
+        ps_per_instruction: u64,
+    },
+}
+
+impl Nondeterminism {
+    pub fn host() -> Self {
+        Nondeterminism::Host {
+            start: Instant::now(),
+        }
+    }
+
+    pub fn virtual_from(seed: u64) -> Self {
+        Nondeterminism::Virtual {
+            rng: seed,
+            ps_per_instruction: PS_PER_INSTRUCTION,
+        }
+    }
+
+    /// Has the virtual clock count each instruction as taking as long as it
+    /// would at `mips`, see `throttle`. The host's clock is left alone
+    pub fn set_rate(&mut self, mips: Mips) {
+        if let Nondeterminism::Virtual {
+            ps_per_instruction, ..
+        } = self
+        {
+            *ps_per_instruction = ((mips.ns_per_instruction() * 1e3) as u64).max(1);
+        }
+    }
+
+    /// The state of the seeded generator, which `virtual_from` carries on from
+    pub fn rng_state(&self) -> Option<u64> {
+        match self {
+            Nondeterminism::Host { .. } => None,
+            Nondeterminism::Virtual { rng, .. } => Some(*rng),
+        }
+    }
+
+    /// Nanoseconds since the unix epoch
+    pub fn realtime_ns(&self, instret: u64) -> u64 {
+        match self {
+            Nondeterminism::Host { .. } => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_nanos() as u64),
+            Nondeterminism::Virtual { .. } => self.monotonic_ns(instret),
+        }
+    }
+
+    /// Nanoseconds since the run started
+    pub fn monotonic_ns(&self, instret: u64) -> u64 {
+        match self {
+            Nondeterminism::Host { start } => start.elapsed().as_nanos() as u64,
+            Nondeterminism::Virtual {
+                ps_per_instruction, ..
+            } => {
+                (instret as u128 * *ps_per_instruction as u128 / 1_000).min(u64::MAX as u128) as u64
+            }
+        }
+    }
+
+    /// The value of the `time` csr
+    pub fn time(&self, instret: u64) -> u64 {
+        self.monotonic_ns(instret) / (NS_PER_SEC / TIMEBASE_HZ)
+    }
+
+    pub fn fill_random(&mut self, buf: &mut [u8]) {
+        match self {
+            Nondeterminism::Host { .. } => File::open("/dev/urandom")
+                .and_then(|mut f| f.read_exact(buf))
+                .expect("reading /dev/urandom failed"),
+            Nondeterminism::Virtual { rng, .. } => {
+                for chunk in buf.chunks_mut(8) {
+                    let bytes = splitmix64(rng).to_le_bytes();
+                    chunk.copy_from_slice(&bytes[..chunk.len()]);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Splits `ns` into a `(seconds, remainder)` pair with the remainder in `unit`
+/// (`1_000` for microseconds, `1` for nanoseconds)
+pub fn split_ns(ns: u64, unit: u64) -> (u64, u64) {
+    (ns / NS_PER_SEC, ns % NS_PER_SEC / unit)
+}
@@ -0,0 +1,86 @@
+//! Instruction encoding tables, generated by build.rs from the riscv-opcodes
+//! data in `opcodes/`.
+
+/// An operand field of an encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Rd,
+    Rs1,
+    Rs2,
+    Rs3,
+    Rm,
+    Imm12,
+    Imm20,
+    Jimm20,
+    Imm12Hi,
+    Imm12Lo,
+    Bimm12Hi,
+    Bimm12Lo,
+    Shamtw,
+    Shamtd,
+    Fm,
+    Pred,
+    Succ,
+    Csr,
+    Zimm,
+    Aq,
+    Rl,
+}
+
+impl Field {
+    /// The bits `hi..=lo` the field occupies
+    pub fn bits(self) -> (u32, u32) {
+        match self {
+            Field::Rd => (11, 7),
+            Field::Rs1 => (19, 15),
+            Field::Rs2 => (24, 20),
+            Field::Rs3 => (31, 27),
+            Field::Rm => (14, 12),
+            Field::Imm12 => (31, 20),
+            Field::Imm20 | Field::Jimm20 => (31, 12),
+            Field::Imm12Hi | Field::Bimm12Hi => (31, 25),
+            Field::Imm12Lo | Field::Bimm12Lo => (11, 7),
+            Field::Shamtw => (24, 20),
+            Field::Shamtd => (25, 20),
+            Field::Fm => (31, 28),
+            Field::Pred => (27, 24),
+            Field::Succ => (23, 20),
+            Field::Csr => (31, 20),
+            Field::Zimm => (19, 15),
+            Field::Aq => (26, 26),
+            Field::Rl => (25, 25),
+        }
+    }
+
+    /// The raw, unshifted and unscrambled bits of the field
+    #[inline(always)]
+    pub fn extract(self, inst: u32) -> u32 {
+        let (hi, lo) = self.bits();
+        (inst >> lo) & ((1 << (hi - lo + 1)) - 1)
+    }
+}
+
+/// A single instruction encoding: `inst & mask == match_`
+#[derive(Debug)]
+pub struct OpcodeInfo {
+    pub opcode: Opcode,
+    pub name: &'static str,
+    /// The riscv-opcodes file it came from, e.g. `rv_f`
+    pub extension: &'static str,
+    /// Set if the encoding is only valid for one xlen, like RV32's 5-bit shamt
+    pub xlen: Option<u32>,
+    pub mask: u32,
+    pub match_: u32,
+    pub fields: &'static [Field],
+}
+
+include!(concat!(env!("OUT_DIR"), "/opcodes.rs"));
+
+/// The encoding `inst` is an instance of on an `xlen`-bit hart, if any
+pub fn lookup(inst: u32, xlen: u32) -> Option<&'static OpcodeInfo> {
+    candidates(inst & 0x7f)
+        .iter()
+        .map(|&idx| &OPCODES[idx as usize])
+        .filter(|info| info.xlen.is_none_or(|only| only == xlen))
+        .find(|info| inst & info.mask == info.match_)
+}
@@ -0,0 +1,1232 @@
+//! A deliberately simple reference interpreter used by `--self-check`.
+//!
+//! Nothing in here is optimised: memory is a plain byte vector accessed through
+//! little-endian conversions, FP registers are raw bit patterns, and every
+//! instruction is fetched and decoded from memory each time it runs. The point is
+//! for the semantics to be easy to compare against the spec, so the fast core can
+//! be run against it in lockstep. FP arithmetic is done on integers, in `softfloat`,
+//! so every rounding mode is worked out the long way rather than the core's.
+
+use std::{error::Error, fmt};
+
+use crate::{
+    core::{Core32, MemReader, RunInfo},
+    csr,
+    instruction::{self, Instruction},
+    register::Register,
+};
+
+mod softfloat;
+
+use softfloat::{DOUBLE, SINGLE};
+
+const CANONICAL_NAN_S: u32 = 0x7fc0_0000;
+const CANONICAL_NAN_D: u64 = 0x7ff8_0000_0000_0000;
+
+// fflags bits
+const FFLAG_NX: u8 = 1 << 0;
+const FFLAG_NV: u8 = 1 << 4;
+
+// upper half of a NaN-boxed single
+const NAN_BOX: u64 = 0xffff_ffff_0000_0000;
+
+pub struct Oracle {
+    pc: u32,
+    x: [u32; 32],
+    f: [u64; 32],
+    frm: u8,
+    fflags: u8,
+    memory: Vec<u8>,
+
+    // byte range written by the last instruction, if any
+    last_store: Option<(u32, u32)>,
+    // address reserved by the last `lr.w`
+    reservation: Option<u32>,
+}
+
+#[derive(Debug)]
+pub enum Divergence {
+    Pc {
+        pc: u32,
+        instr: Instruction,
+        expected: u32,
+        actual: u32,
+    },
+    GpReg {
+        pc: u32,
+        instr: Instruction,
+        reg: u8,
+        expected: u32,
+        actual: u32,
+    },
+    FpReg {
+        pc: u32,
+        instr: Instruction,
+        reg: u8,
+        expected: u64,
+        actual: u64,
+    },
+    Memory {
+        pc: u32,
+        instr: Instruction,
+        addr: u32,
+        expected: Vec<u8>,
+        actual: Vec<u8>,
+    },
+    Csr {
+        pc: u32,
+        instr: Instruction,
+        csr: u16,
+        expected: u32,
+        actual: u32,
+    },
+    Unsupported {
+        pc: u32,
+        instr: Instruction,
+    },
+    // not a divergence: the instruction is illegal as encoded, say for a reserved rounding
+    // mode, so the core should trap on it
+    Illegal {
+        pc: u32,
+        instr: Instruction,
+    },
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Divergence::Pc {
+                pc,
+                instr,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "divergence at pc {pc:#x} ({instr}): next pc expected {expected:#x}, got {actual:#x}"
+            ),
+            Divergence::GpReg {
+                pc,
+                instr,
+                reg,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "divergence at pc {pc:#x} ({instr}): {} expected {expected:#x}, got {actual:#x}",
+                Register::gp(*reg)
+            ),
+            Divergence::FpReg {
+                pc,
+                instr,
+                reg,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "divergence at pc {pc:#x} ({instr}): {} expected {expected:#x}, got {actual:#x}",
+                Register::fp(*reg)
+            ),
+            Divergence::Memory {
+                pc,
+                instr,
+                addr,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "divergence at pc {pc:#x} ({instr}): memory at {addr:#x} expected {expected:02x?}, got {actual:02x?}"
+            ),
+            Divergence::Csr {
+                pc,
+                instr,
+                csr,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "divergence at pc {pc:#x} ({instr}): csr {csr:#x} expected {expected:#x}, got {actual:#x}"
+            ),
+            Divergence::Unsupported { pc, instr } => write!(
+                f,
+                "oracle cannot execute instruction at pc {pc:#x} ({instr})"
+            ),
+            Divergence::Illegal { pc, instr } => {
+                write!(f, "illegal instruction at pc {pc:#x} ({instr})")
+            }
+        }
+    }
+}
+
+impl Error for Divergence {}
+
+fn nan_box(val: f32) -> u64 {
+    NAN_BOX | val.to_bits() as u64
+}
+
+fn canonicalize_s(val: f32) -> f32 {
+    if val.is_nan() {
+        f32::from_bits(CANONICAL_NAN_S)
+    } else {
+        val
+    }
+}
+
+fn canonicalize_d(val: f64) -> f64 {
+    if val.is_nan() {
+        f64::from_bits(CANONICAL_NAN_D)
+    } else {
+        val
+    }
+}
+
+// IEEE 754-2008 minNum/maxNum, with -0.0 ordered below +0.0
+fn fmin_s(a: f32, b: f32) -> f32 {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => f32::from_bits(CANONICAL_NAN_S),
+        (true, false) => b,
+        (false, true) => a,
+        _ if a == b => f32::from_bits(a.to_bits() | b.to_bits()),
+        _ => {
+            if a < b {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+fn fmax_s(a: f32, b: f32) -> f32 {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => f32::from_bits(CANONICAL_NAN_S),
+        (true, false) => b,
+        (false, true) => a,
+        _ if a == b => f32::from_bits(a.to_bits() & b.to_bits()),
+        _ => {
+            if a > b {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+fn fmin_d(a: f64, b: f64) -> f64 {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => f64::from_bits(CANONICAL_NAN_D),
+        (true, false) => b,
+        (false, true) => a,
+        _ if a == b => f64::from_bits(a.to_bits() | b.to_bits()),
+        _ => {
+            if a < b {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+fn fmax_d(a: f64, b: f64) -> f64 {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => f64::from_bits(CANONICAL_NAN_D),
+        (true, false) => b,
+        (false, true) => a,
+        _ if a == b => f64::from_bits(a.to_bits() & b.to_bits()),
+        _ => {
+            if a > b {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+// fround.*: the integer nearest `val` in rounding mode `rm`
+fn round_integral(val: f64, rm: u8) -> f64 {
+    match rm {
+        0b000 => val.round_ties_even(),
+        0b001 => val.trunc(),
+        0b010 => val.floor(),
+        0b011 => val.ceil(),
+        _ => val.round(),
+    }
+}
+
+// fcvt.w.* and fcvt.wu.*: round in rounding mode `rm`, saturate out-of-range values to
+// `min`/`max` and NaN to `max`, returning the result and the fflags it raises
+fn to_int(val: f64, rm: u8, min: i64, max: i64) -> (u32, u8) {
+    if val.is_nan() {
+        return (max as u32, FFLAG_NV);
+    }
+    let rounded = round_integral(val, rm);
+    if rounded < min as f64 {
+        (min as u32, FFLAG_NV)
+    } else if rounded > max as f64 {
+        (max as u32, FFLAG_NV)
+    } else if rounded != val {
+        (rounded as i64 as u32, FFLAG_NX)
+    } else {
+        (rounded as i64 as u32, 0)
+    }
+}
+
+fn fclass(sign: bool, exp_all_ones: bool, exp_zero: bool, frac_zero: bool, quiet: bool) -> u32 {
+    let bit = match (exp_all_ones, exp_zero, frac_zero) {
+        (true, _, true) if sign => 0,
+        (true, _, true) => 7,
+        (true, _, false) if quiet => 9,
+        (true, _, false) => 8,
+        (_, true, true) if sign => 3,
+        (_, true, true) => 4,
+        (_, true, false) if sign => 2,
+        (_, true, false) => 5,
+        _ if sign => 1,
+        _ => 6,
+    };
+    1 << bit
+}
+
+fn fclass_s(val: u32) -> u32 {
+    let exp = (val >> 23) & 0xff;
+    let frac = val & 0x7f_ffff;
+    fclass(
+        val >> 31 != 0,
+        exp == 0xff,
+        exp == 0,
+        frac == 0,
+        frac & (1 << 22) != 0,
+    )
+}
+
+fn fclass_d(val: u64) -> u32 {
+    let exp = (val >> 52) & 0x7ff;
+    let frac = val & 0xf_ffff_ffff_ffff;
+    fclass(
+        val >> 63 != 0,
+        exp == 0x7ff,
+        exp == 0,
+        frac == 0,
+        frac & (1 << 51) != 0,
+    )
+}
+
+impl Oracle {
+    pub fn from_core<Reader: MemReader<Idx = u32>>(core: &Core32<Reader>) -> Self {
+        let mut oracle = Self {
+            pc: 0,
+            x: [0; 32],
+            f: [0; 32],
+            frm: 0,
+            fflags: 0,
+            memory: core.memory().to_vec(),
+            last_store: None,
+            reservation: None,
+        };
+        oracle.sync_registers(core);
+        oracle
+    }
+
+    /// Copies all architectural state from `core`, for instructions the oracle
+    /// cannot model itself (syscalls, custom instructions, natively serviced
+    /// calls and device accesses)
+    pub fn sync_from<Reader: MemReader<Idx = u32>>(&mut self, core: &Core32<Reader>) {
+        self.sync_registers(core);
+        self.memory.copy_from_slice(core.memory());
+    }
+
+    fn sync_registers<Reader: MemReader<Idx = u32>>(&mut self, core: &Core32<Reader>) {
+        self.pc = core.pc();
+        self.x = core.gp_regs().map(|r| r as u32);
+        self.f = core.fp_regs();
+        self.frm = core.frm();
+        self.fflags = core.read_csr(csr::FFLAGS).unwrap() as u8;
+    }
+
+    fn read_x(&self, idx: u8) -> u32 {
+        if idx == 0 {
+            0
+        } else {
+            self.x[idx as usize]
+        }
+    }
+
+    fn write_x(&mut self, idx: u8, val: u32) {
+        if idx != 0 {
+            self.x[idx as usize] = val;
+        }
+    }
+
+    // a single that isn't NaN-boxed reads as the canonical NaN
+    fn read_s(&self, idx: u8) -> f32 {
+        let val = self.f[idx as usize];
+        if val & NAN_BOX == NAN_BOX {
+            f32::from_bits(val as u32)
+        } else {
+            f32::from_bits(CANONICAL_NAN_S)
+        }
+    }
+
+    fn write_s(&mut self, idx: u8, val: f32) {
+        self.f[idx as usize] = nan_box(val);
+    }
+
+    fn read_d(&self, idx: u8) -> f64 {
+        f64::from_bits(self.f[idx as usize])
+    }
+
+    fn write_d(&mut self, idx: u8, val: f64) {
+        self.f[idx as usize] = val.to_bits();
+    }
+
+    // past the end of memory can only be a device window, which isn't
+    // modelled; the state after the access is synced from the core
+    fn load<const N: usize>(&self, addr: u32) -> [u8; N] {
+        let addr = addr as usize;
+        self.memory
+            .get(addr..addr + N)
+            .map_or([0; N], |bytes| bytes.try_into().unwrap())
+    }
+
+    fn store<const N: usize>(&mut self, addr: u32, bytes: [u8; N]) {
+        let start = addr as usize;
+        if let Some(dest) = self.memory.get_mut(start..start + N) {
+            dest.copy_from_slice(&bytes);
+        }
+        self.last_store = Some((addr, N as u32));
+    }
+
+    fn check_rm(&self, rm: u8) -> Result<u8, ()> {
+        let rm = if rm == 0b111 { self.frm } else { rm };
+        match rm {
+            0b000..=0b100 => Ok(rm),
+            _ => Err(()),
+        }
+    }
+
+    fn read_csr(&self, csr: u16) -> u32 {
+        match csr {
+            csr::FFLAGS => self.fflags as u32,
+            csr::FRM => self.frm as u32,
+            csr::FCSR => ((self.frm as u32) << 5) | self.fflags as u32,
+            _ => unreachable!("csr {csr:#x} is not modelled"),
+        }
+    }
+
+    fn amo(&mut self, rd: u8, rs1: u8, rs2: u8, op: fn(u32, u32) -> u32) {
+        let addr = self.read_x(rs1);
+        let old = u32::from_le_bytes(self.load(addr));
+        self.store(addr, op(old, self.read_x(rs2)).to_le_bytes());
+        self.write_x(rd, old);
+    }
+
+    // accesses to csrs that aren't modelled are synced from the fast core
+    fn access_csr(&mut self, csr: u16, rd: u8, src: Option<u32>, op: fn(u32, u32) -> u32) {
+        if !models_csr(csr) {
+            return;
+        }
+
+        let old = self.read_csr(csr);
+        if let Some(src) = src {
+            let new = op(old, src);
+            match csr {
+                csr::FFLAGS => self.fflags = (new & 0x1f) as u8,
+                csr::FRM => self.frm = (new & 0x7) as u8,
+                _ => {
+                    self.frm = ((new >> 5) & 0x7) as u8;
+                    self.fflags = (new & 0x1f) as u8;
+                }
+            }
+        }
+        self.write_x(rd, old);
+    }
+
+    /// Fetches, decodes, and executes the instruction at the current pc
+    pub fn step(&mut self) -> Result<Instruction, Divergence> {
+        let pc = self.pc;
+        let instr = Instruction::decode(u32::from_le_bytes(self.load(pc)));
+
+        self.last_store = None;
+
+        let unsupported = Divergence::Unsupported { pc, instr };
+        let illegal = Divergence::Illegal { pc, instr };
+
+        let mut next_pc = pc.wrapping_add(4);
+
+        let branch = |taken: bool, imm: i32| {
+            if taken {
+                pc.wrapping_add(imm as u32)
+            } else {
+                pc.wrapping_add(4)
+            }
+        };
+
+        match instr {
+            Instruction::Unknown(_)
+            | Instruction::Ebreak
+            | Instruction::Mret
+            | Instruction::Sret
+            | Instruction::SfenceVma { .. }
+            | Instruction::HfenceVvma { .. }
+            | Instruction::HfenceGvma { .. }
+            | Instruction::HlvB { .. }
+            | Instruction::HlvBu { .. }
+            | Instruction::HlvH { .. }
+            | Instruction::HlvHu { .. }
+            | Instruction::HlvxHu { .. }
+            | Instruction::HlvW { .. }
+            | Instruction::HlvxWu { .. }
+            | Instruction::HsvB { .. }
+            | Instruction::HsvH { .. }
+            | Instruction::HsvW { .. } => return Err(unsupported),
+
+            Instruction::Lui { rd, imm } => self.write_x(rd, imm as u32),
+            Instruction::Auipc { rd, imm } => self.write_x(rd, pc.wrapping_add(imm as u32)),
+            Instruction::Jal { rd, imm } => {
+                self.write_x(rd, pc.wrapping_add(4));
+                next_pc = pc.wrapping_add(imm as u32);
+            }
+            Instruction::Jalr { rd, rs1, imm } => {
+                let target = self.read_x(rs1).wrapping_add(imm as u32) & !1;
+                self.write_x(rd, pc.wrapping_add(4));
+                next_pc = target;
+            }
+
+            Instruction::Beq { rs1, rs2, imm } => {
+                next_pc = branch(self.read_x(rs1) == self.read_x(rs2), imm)
+            }
+            Instruction::Bne { rs1, rs2, imm } => {
+                next_pc = branch(self.read_x(rs1) != self.read_x(rs2), imm)
+            }
+            Instruction::Blt { rs1, rs2, imm } => {
+                next_pc = branch((self.read_x(rs1) as i32) < (self.read_x(rs2) as i32), imm)
+            }
+            Instruction::Bge { rs1, rs2, imm } => {
+                next_pc = branch((self.read_x(rs1) as i32) >= (self.read_x(rs2) as i32), imm)
+            }
+            Instruction::Bltu { rs1, rs2, imm } => {
+                next_pc = branch(self.read_x(rs1) < self.read_x(rs2), imm)
+            }
+            Instruction::Bgeu { rs1, rs2, imm } => {
+                next_pc = branch(self.read_x(rs1) >= self.read_x(rs2), imm)
+            }
+
+            Instruction::Lb { rd, rs1, imm } => {
+                let addr = self.read_x(rs1).wrapping_add(imm as u32);
+                let val = i8::from_le_bytes(self.load(addr));
+                self.write_x(rd, val as i32 as u32);
+            }
+            Instruction::Lh { rd, rs1, imm } => {
+                let addr = self.read_x(rs1).wrapping_add(imm as u32);
+                let val = i16::from_le_bytes(self.load(addr));
+                self.write_x(rd, val as i32 as u32);
+            }
+            Instruction::Lw { rd, rs1, imm } => {
+                let addr = self.read_x(rs1).wrapping_add(imm as u32);
+                let val = u32::from_le_bytes(self.load(addr));
+                self.write_x(rd, val);
+            }
+            Instruction::Lbu { rd, rs1, imm } => {
+                let addr = self.read_x(rs1).wrapping_add(imm as u32);
+                let val = u8::from_le_bytes(self.load(addr));
+                self.write_x(rd, val as u32);
+            }
+            Instruction::Lhu { rd, rs1, imm } => {
+                let addr = self.read_x(rs1).wrapping_add(imm as u32);
+                let val = u16::from_le_bytes(self.load(addr));
+                self.write_x(rd, val as u32);
+            }
+            Instruction::Sb { rs1, rs2, imm } => {
+                let addr = self.read_x(rs1).wrapping_add(imm as u32);
+                self.store(addr, (self.read_x(rs2) as u8).to_le_bytes());
+            }
+            Instruction::Sh { rs1, rs2, imm } => {
+                let addr = self.read_x(rs1).wrapping_add(imm as u32);
+                self.store(addr, (self.read_x(rs2) as u16).to_le_bytes());
+            }
+            Instruction::Sw { rs1, rs2, imm } => {
+                let addr = self.read_x(rs1).wrapping_add(imm as u32);
+                self.store(addr, self.read_x(rs2).to_le_bytes());
+            }
+
+            Instruction::Addi { rd, rs1, imm } => {
+                self.write_x(rd, self.read_x(rs1).wrapping_add(imm as u32))
+            }
+            Instruction::Slti { rd, rs1, imm } => {
+                self.write_x(rd, ((self.read_x(rs1) as i32) < imm) as u32)
+            }
+            Instruction::Sltiu { rd, rs1, imm } => {
+                self.write_x(rd, (self.read_x(rs1) < imm as u32) as u32)
+            }
+            Instruction::Xori { rd, rs1, imm } => self.write_x(rd, self.read_x(rs1) ^ imm as u32),
+            Instruction::Ori { rd, rs1, imm } => self.write_x(rd, self.read_x(rs1) | imm as u32),
+            Instruction::Andi { rd, rs1, imm } => self.write_x(rd, self.read_x(rs1) & imm as u32),
+            Instruction::Slli { rd, rs1, shamt } => {
+                self.write_x(rd, self.read_x(rs1) << (shamt & 0x1f))
+            }
+            Instruction::Srli { rd, rs1, shamt } => {
+                self.write_x(rd, self.read_x(rs1) >> (shamt & 0x1f))
+            }
+            Instruction::Srai { rd, rs1, shamt } => {
+                self.write_x(rd, ((self.read_x(rs1) as i32) >> (shamt & 0x1f)) as u32)
+            }
+
+            Instruction::Add { rd, rs1, rs2 } => {
+                self.write_x(rd, self.read_x(rs1).wrapping_add(self.read_x(rs2)))
+            }
+            Instruction::Sub { rd, rs1, rs2 } => {
+                self.write_x(rd, self.read_x(rs1).wrapping_sub(self.read_x(rs2)))
+            }
+            Instruction::Sll { rd, rs1, rs2 } => {
+                self.write_x(rd, self.read_x(rs1) << (self.read_x(rs2) & 0x1f))
+            }
+            Instruction::Slt { rd, rs1, rs2 } => self.write_x(
+                rd,
+                ((self.read_x(rs1) as i32) < (self.read_x(rs2) as i32)) as u32,
+            ),
+            Instruction::Sltu { rd, rs1, rs2 } => {
+                self.write_x(rd, (self.read_x(rs1) < self.read_x(rs2)) as u32)
+            }
+            Instruction::Xor { rd, rs1, rs2 } => {
+                self.write_x(rd, self.read_x(rs1) ^ self.read_x(rs2))
+            }
+            Instruction::Srl { rd, rs1, rs2 } => {
+                self.write_x(rd, self.read_x(rs1) >> (self.read_x(rs2) & 0x1f))
+            }
+            Instruction::Sra { rd, rs1, rs2 } => self.write_x(
+                rd,
+                ((self.read_x(rs1) as i32) >> (self.read_x(rs2) & 0x1f)) as u32,
+            ),
+            Instruction::Or { rd, rs1, rs2 } => {
+                self.write_x(rd, self.read_x(rs1) | self.read_x(rs2))
+            }
+            Instruction::And { rd, rs1, rs2 } => {
+                self.write_x(rd, self.read_x(rs1) & self.read_x(rs2))
+            }
+
+            Instruction::Fence { .. } | Instruction::FenceI | Instruction::Wfi => {}
+
+            // syscalls and custom instructions are never re-executed, the caller
+            // syncs from the fast core
+            Instruction::Ecall | Instruction::Custom { .. } => {}
+
+            Instruction::Csrrw { rd, rs1, csr } => {
+                self.access_csr(csr, rd, Some(self.read_x(rs1)), |_, src| src)
+            }
+            Instruction::Csrrs { rd, rs1, csr } => {
+                let src = (rs1 != 0).then(|| self.read_x(rs1));
+                self.access_csr(csr, rd, src, |old, src| old | src)
+            }
+            Instruction::Csrrc { rd, rs1, csr } => {
+                let src = (rs1 != 0).then(|| self.read_x(rs1));
+                self.access_csr(csr, rd, src, |old, src| old & !src)
+            }
+            Instruction::Csrrwi { rd, zimm, csr } => {
+                self.access_csr(csr, rd, Some(zimm as u32), |_, src| src)
+            }
+            Instruction::Csrrsi { rd, zimm, csr } => {
+                let src = (zimm != 0).then_some(zimm as u32);
+                self.access_csr(csr, rd, src, |old, src| old | src)
+            }
+            Instruction::Csrrci { rd, zimm, csr } => {
+                let src = (zimm != 0).then_some(zimm as u32);
+                self.access_csr(csr, rd, src, |old, src| old & !src)
+            }
+
+            Instruction::Mul { rd, rs1, rs2 } => {
+                self.write_x(rd, self.read_x(rs1).wrapping_mul(self.read_x(rs2)))
+            }
+            Instruction::Mulh { rd, rs1, rs2 } => {
+                let a = self.read_x(rs1) as i32 as i64;
+                let b = self.read_x(rs2) as i32 as i64;
+                self.write_x(rd, ((a * b) >> 32) as u32);
+            }
+            Instruction::Mulhsu { rd, rs1, rs2 } => {
+                let a = self.read_x(rs1) as i32 as i64;
+                let b = self.read_x(rs2) as i64;
+                self.write_x(rd, ((a * b) >> 32) as u32);
+            }
+            Instruction::Mulhu { rd, rs1, rs2 } => {
+                let a = self.read_x(rs1) as u64;
+                let b = self.read_x(rs2) as u64;
+                self.write_x(rd, ((a * b) >> 32) as u32);
+            }
+            Instruction::Div { rd, rs1, rs2 } => {
+                let a = self.read_x(rs1) as i32;
+                let b = self.read_x(rs2) as i32;
+                let res = if b == 0 {
+                    -1
+                } else if a == i32::MIN && b == -1 {
+                    i32::MIN
+                } else {
+                    a / b
+                };
+                self.write_x(rd, res as u32);
+            }
+            Instruction::Divu { rd, rs1, rs2 } => {
+                let a = self.read_x(rs1);
+                let b = self.read_x(rs2);
+                self.write_x(rd, a.checked_div(b).unwrap_or(u32::MAX));
+            }
+            Instruction::Rem { rd, rs1, rs2 } => {
+                let a = self.read_x(rs1) as i32;
+                let b = self.read_x(rs2) as i32;
+                let res = if b == 0 {
+                    a
+                } else if a == i32::MIN && b == -1 {
+                    0
+                } else {
+                    a % b
+                };
+                self.write_x(rd, res as u32);
+            }
+            Instruction::Remu { rd, rs1, rs2 } => {
+                let a = self.read_x(rs1);
+                let b = self.read_x(rs2);
+                self.write_x(rd, if b == 0 { a } else { a % b });
+            }
+
+            Instruction::LrW { rd, rs1, .. } => {
+                let addr = self.read_x(rs1);
+                self.write_x(rd, u32::from_le_bytes(self.load(addr)));
+                self.reservation = Some(addr);
+            }
+            Instruction::ScW { rd, rs1, rs2, .. } => {
+                let addr = self.read_x(rs1);
+                let success = self.reservation.take() == Some(addr);
+                if success {
+                    self.store(addr, self.read_x(rs2).to_le_bytes());
+                }
+                self.write_x(rd, !success as u32);
+            }
+            Instruction::AmoswapW { rd, rs1, rs2, .. } => self.amo(rd, rs1, rs2, |_, b| b),
+            Instruction::AmoaddW { rd, rs1, rs2, .. } => {
+                self.amo(rd, rs1, rs2, |a, b| a.wrapping_add(b))
+            }
+            Instruction::AmoxorW { rd, rs1, rs2, .. } => self.amo(rd, rs1, rs2, |a, b| a ^ b),
+            Instruction::AmoandW { rd, rs1, rs2, .. } => self.amo(rd, rs1, rs2, |a, b| a & b),
+            Instruction::AmoorW { rd, rs1, rs2, .. } => self.amo(rd, rs1, rs2, |a, b| a | b),
+            Instruction::AmominW { rd, rs1, rs2, .. } => {
+                self.amo(rd, rs1, rs2, |a, b| (a as i32).min(b as i32) as u32)
+            }
+            Instruction::AmomaxW { rd, rs1, rs2, .. } => {
+                self.amo(rd, rs1, rs2, |a, b| (a as i32).max(b as i32) as u32)
+            }
+            Instruction::AmominuW { rd, rs1, rs2, .. } => self.amo(rd, rs1, rs2, u32::min),
+            Instruction::AmomaxuW { rd, rs1, rs2, .. } => self.amo(rd, rs1, rs2, u32::max),
+            Instruction::AmocasW { rd, rs1, rs2, .. } => {
+                let addr = self.read_x(rs1);
+                let old = u32::from_le_bytes(self.load(addr));
+                if old == self.read_x(rd) {
+                    self.store(addr, self.read_x(rs2).to_le_bytes());
+                }
+                self.write_x(rd, old);
+            }
+            Instruction::WrsNto | Instruction::WrsSto => {}
+
+            Instruction::Flw { rd, rs1, imm } => {
+                let addr = self.read_x(rs1).wrapping_add(imm as u32);
+                let val = f32::from_le_bytes(self.load(addr));
+                self.write_s(rd, val);
+            }
+            Instruction::Fld { rd, rs1, imm } => {
+                let addr = self.read_x(rs1).wrapping_add(imm as u32);
+                self.f[rd as usize] = u64::from_le_bytes(self.load(addr));
+            }
+            Instruction::Fsw { rs1, rs2, imm } => {
+                let addr = self.read_x(rs1).wrapping_add(imm as u32);
+                self.store(addr, (self.f[rs2 as usize] as u32).to_le_bytes());
+            }
+            Instruction::Fsd { rs1, rs2, imm } => {
+                let addr = self.read_x(rs1).wrapping_add(imm as u32);
+                self.store(addr, self.f[rs2 as usize].to_le_bytes());
+            }
+
+            Instruction::FaddS { rd, rs1, rs2, rm }
+            | Instruction::FsubS { rd, rs1, rs2, rm }
+            | Instruction::FmulS { rd, rs1, rs2, rm }
+            | Instruction::FdivS { rd, rs1, rs2, rm } => {
+                let rm = self.check_rm(rm).map_err(|_| illegal)?;
+
+                let a = self.read_s(rs1).to_bits() as u64;
+                let b = self.read_s(rs2).to_bits() as u64;
+                let res = match instr {
+                    Instruction::FaddS { .. } => softfloat::add(SINGLE, a, b, rm),
+                    Instruction::FsubS { .. } => softfloat::add(SINGLE, a, b ^ SINGLE.sign(), rm),
+                    Instruction::FmulS { .. } => softfloat::mul(SINGLE, a, b, rm),
+                    _ => softfloat::div(SINGLE, a, b, rm),
+                };
+                self.write_s(rd, f32::from_bits(res as u32));
+            }
+            Instruction::FaddD { rd, rs1, rs2, rm }
+            | Instruction::FsubD { rd, rs1, rs2, rm }
+            | Instruction::FmulD { rd, rs1, rs2, rm }
+            | Instruction::FdivD { rd, rs1, rs2, rm } => {
+                let rm = self.check_rm(rm).map_err(|_| illegal)?;
+
+                let a = self.f[rs1 as usize];
+                let b = self.f[rs2 as usize];
+                self.f[rd as usize] = match instr {
+                    Instruction::FaddD { .. } => softfloat::add(DOUBLE, a, b, rm),
+                    Instruction::FsubD { .. } => softfloat::add(DOUBLE, a, b ^ DOUBLE.sign(), rm),
+                    Instruction::FmulD { .. } => softfloat::mul(DOUBLE, a, b, rm),
+                    _ => softfloat::div(DOUBLE, a, b, rm),
+                };
+            }
+
+            // the negated forms negate the product and the addend exactly, before rounding
+            Instruction::FmaddS {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            }
+            | Instruction::FmsubS {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            }
+            | Instruction::FnmaddS {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            }
+            | Instruction::FnmsubS {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            } => {
+                let rm = self.check_rm(rm).map_err(|_| illegal)?;
+
+                let neg = SINGLE.sign();
+                let a = self.read_s(rs1).to_bits() as u64;
+                let b = self.read_s(rs2).to_bits() as u64;
+                let c = self.read_s(rs3).to_bits() as u64;
+                let res = match instr {
+                    Instruction::FmaddS { .. } => softfloat::fma(SINGLE, a, b, c, rm),
+                    Instruction::FmsubS { .. } => softfloat::fma(SINGLE, a, b, c ^ neg, rm),
+                    Instruction::FnmsubS { .. } => softfloat::fma(SINGLE, a ^ neg, b, c, rm),
+                    _ => softfloat::fma(SINGLE, a ^ neg, b, c ^ neg, rm),
+                };
+                self.write_s(rd, f32::from_bits(res as u32));
+            }
+            Instruction::FmaddD {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            }
+            | Instruction::FmsubD {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            }
+            | Instruction::FnmaddD {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            }
+            | Instruction::FnmsubD {
+                rd,
+                rs1,
+                rs2,
+                rs3,
+                rm,
+            } => {
+                let rm = self.check_rm(rm).map_err(|_| illegal)?;
+
+                let neg = DOUBLE.sign();
+                let (a, b, c) = (
+                    self.f[rs1 as usize],
+                    self.f[rs2 as usize],
+                    self.f[rs3 as usize],
+                );
+                self.f[rd as usize] = match instr {
+                    Instruction::FmaddD { .. } => softfloat::fma(DOUBLE, a, b, c, rm),
+                    Instruction::FmsubD { .. } => softfloat::fma(DOUBLE, a, b, c ^ neg, rm),
+                    Instruction::FnmsubD { .. } => softfloat::fma(DOUBLE, a ^ neg, b, c, rm),
+                    _ => softfloat::fma(DOUBLE, a ^ neg, b, c ^ neg, rm),
+                };
+            }
+
+            Instruction::FsqrtS { rd, rs1, rm } => {
+                let rm = self.check_rm(rm).map_err(|_| illegal)?;
+                let res = softfloat::sqrt(SINGLE, self.read_s(rs1).to_bits() as u64, rm);
+                self.write_s(rd, f32::from_bits(res as u32));
+            }
+            Instruction::FsqrtD { rd, rs1, rm } => {
+                let rm = self.check_rm(rm).map_err(|_| illegal)?;
+                self.f[rd as usize] = softfloat::sqrt(DOUBLE, self.f[rs1 as usize], rm);
+            }
+
+            Instruction::FsgnjS { rd, rs1, rs2 } => {
+                let a = self.read_s(rs1).to_bits();
+                let b = self.read_s(rs2).to_bits();
+                self.write_s(rd, f32::from_bits((a & !(1 << 31)) | (b & (1 << 31))));
+            }
+            Instruction::FsgnjnS { rd, rs1, rs2 } => {
+                let a = self.read_s(rs1).to_bits();
+                let b = self.read_s(rs2).to_bits();
+                self.write_s(rd, f32::from_bits((a & !(1 << 31)) | (!b & (1 << 31))));
+            }
+            Instruction::FsgnjxS { rd, rs1, rs2 } => {
+                let a = self.read_s(rs1).to_bits();
+                let b = self.read_s(rs2).to_bits();
+                self.write_s(rd, f32::from_bits(a ^ (b & (1 << 31))));
+            }
+            Instruction::FsgnjD { rd, rs1, rs2 } => {
+                let a = self.f[rs1 as usize];
+                let b = self.f[rs2 as usize];
+                self.f[rd as usize] = (a & !(1 << 63)) | (b & (1 << 63));
+            }
+            Instruction::FsgnjnD { rd, rs1, rs2 } => {
+                let a = self.f[rs1 as usize];
+                let b = self.f[rs2 as usize];
+                self.f[rd as usize] = (a & !(1 << 63)) | (!b & (1 << 63));
+            }
+            Instruction::FsgnjxD { rd, rs1, rs2 } => {
+                let a = self.f[rs1 as usize];
+                let b = self.f[rs2 as usize];
+                self.f[rd as usize] = a ^ (b & (1 << 63));
+            }
+
+            Instruction::FminS { rd, rs1, rs2 } => {
+                self.write_s(rd, fmin_s(self.read_s(rs1), self.read_s(rs2)))
+            }
+            Instruction::FmaxS { rd, rs1, rs2 } => {
+                self.write_s(rd, fmax_s(self.read_s(rs1), self.read_s(rs2)))
+            }
+            Instruction::FminD { rd, rs1, rs2 } => {
+                self.write_d(rd, fmin_d(self.read_d(rs1), self.read_d(rs2)))
+            }
+            Instruction::FmaxD { rd, rs1, rs2 } => {
+                self.write_d(rd, fmax_d(self.read_d(rs1), self.read_d(rs2)))
+            }
+
+            Instruction::FmvSW { rd, rs1 } => self.write_x(rd, self.f[rs1 as usize] as u32),
+            Instruction::FmvWS { rd, rs1 } => self.write_s(rd, f32::from_bits(self.read_x(rs1))),
+            // rv64 only
+            Instruction::FmvXD { .. } | Instruction::FmvDX { .. } => return Err(unsupported),
+
+            Instruction::FclassS { rd, rs1 } => {
+                self.write_x(rd, fclass_s(self.read_s(rs1).to_bits()))
+            }
+            Instruction::FclassD { rd, rs1 } => self.write_x(rd, fclass_d(self.f[rs1 as usize])),
+
+            Instruction::FcvtSW { rd, rs1, rm }
+            | Instruction::FcvtSWu { rd, rs1, rm }
+            | Instruction::FcvtDW { rd, rs1, rm }
+            | Instruction::FcvtDWu { rd, rs1, rm } => {
+                let rm = self.check_rm(rm).map_err(|_| illegal)?;
+                let val = self.read_x(rs1);
+                let signed = matches!(
+                    instr,
+                    Instruction::FcvtSW { .. } | Instruction::FcvtDW { .. }
+                );
+                let negative = signed && (val as i32) < 0;
+                let val = if negative {
+                    (val as i32).unsigned_abs()
+                } else {
+                    val
+                };
+                match instr {
+                    Instruction::FcvtSW { .. } | Instruction::FcvtSWu { .. } => {
+                        let res = softfloat::from_int(SINGLE, negative, val, rm);
+                        self.write_s(rd, f32::from_bits(res as u32));
+                    }
+                    _ => self.f[rd as usize] = softfloat::from_int(DOUBLE, negative, val, rm),
+                }
+            }
+            Instruction::FcvtWS { rd, rs1, rm }
+            | Instruction::FcvtWuS { rd, rs1, rm }
+            | Instruction::FcvtWD { rd, rs1, rm }
+            | Instruction::FcvtWuD { rd, rs1, rm } => {
+                let rm = self.check_rm(rm).map_err(|_| illegal)?;
+                let val = match instr {
+                    Instruction::FcvtWS { .. } | Instruction::FcvtWuS { .. } => {
+                        self.read_s(rs1) as f64
+                    }
+                    _ => self.read_d(rs1),
+                };
+                let (res, flags) = match instr {
+                    Instruction::FcvtWS { .. } | Instruction::FcvtWD { .. } => {
+                        to_int(val, rm, i32::MIN as i64, i32::MAX as i64)
+                    }
+                    _ => to_int(val, rm, 0, u32::MAX as i64),
+                };
+                self.fflags |= flags;
+                self.write_x(rd, res);
+            }
+            Instruction::FcvtSD { rd, rs1, rm } => {
+                let rm = self.check_rm(rm).map_err(|_| illegal)?;
+                let res = softfloat::convert(DOUBLE, SINGLE, self.f[rs1 as usize], rm);
+                self.write_s(rd, f32::from_bits(res as u32));
+            }
+            Instruction::FcvtDS { rd, rs1, rm } => {
+                let rm = self.check_rm(rm).map_err(|_| illegal)?;
+                let val = self.read_s(rs1).to_bits() as u64;
+                self.f[rd as usize] = softfloat::convert(SINGLE, DOUBLE, val, rm);
+            }
+
+            Instruction::FeqS { rd, rs1, rs2 } => {
+                self.write_x(rd, (self.read_s(rs1) == self.read_s(rs2)) as u32)
+            }
+            Instruction::FltS { rd, rs1, rs2 } => {
+                self.write_x(rd, (self.read_s(rs1) < self.read_s(rs2)) as u32)
+            }
+            Instruction::FleS { rd, rs1, rs2 } => {
+                self.write_x(rd, (self.read_s(rs1) <= self.read_s(rs2)) as u32)
+            }
+            Instruction::FeqD { rd, rs1, rs2 } => {
+                self.write_x(rd, (self.read_d(rs1) == self.read_d(rs2)) as u32)
+            }
+            Instruction::FltD { rd, rs1, rs2 } => {
+                self.write_x(rd, (self.read_d(rs1) < self.read_d(rs2)) as u32)
+            }
+            Instruction::FleD { rd, rs1, rs2 } => {
+                self.write_x(rd, (self.read_d(rs1) <= self.read_d(rs2)) as u32)
+            }
+
+            Instruction::FliS { rd, idx } => self.write_s(rd, instruction::fli_single(idx)),
+            Instruction::FliD { rd, idx } => self.write_d(rd, instruction::fli_double(idx)),
+            Instruction::FminmS { rd, rs1, rs2 } => {
+                let (a, b) = (self.read_s(rs1), self.read_s(rs2));
+                let res = if a.is_nan() || b.is_nan() {
+                    f32::from_bits(CANONICAL_NAN_S)
+                } else {
+                    fmin_s(a, b)
+                };
+                self.write_s(rd, res);
+            }
+            Instruction::FmaxmS { rd, rs1, rs2 } => {
+                let (a, b) = (self.read_s(rs1), self.read_s(rs2));
+                let res = if a.is_nan() || b.is_nan() {
+                    f32::from_bits(CANONICAL_NAN_S)
+                } else {
+                    fmax_s(a, b)
+                };
+                self.write_s(rd, res);
+            }
+            Instruction::FminmD { rd, rs1, rs2 } => {
+                let (a, b) = (self.read_d(rs1), self.read_d(rs2));
+                let res = if a.is_nan() || b.is_nan() {
+                    f64::from_bits(CANONICAL_NAN_D)
+                } else {
+                    fmin_d(a, b)
+                };
+                self.write_d(rd, res);
+            }
+            Instruction::FmaxmD { rd, rs1, rs2 } => {
+                let (a, b) = (self.read_d(rs1), self.read_d(rs2));
+                let res = if a.is_nan() || b.is_nan() {
+                    f64::from_bits(CANONICAL_NAN_D)
+                } else {
+                    fmax_d(a, b)
+                };
+                self.write_d(rd, res);
+            }
+            Instruction::FroundS { rd, rs1, rm } | Instruction::FroundnxS { rd, rs1, rm } => {
+                self.check_rm(rm).map_err(|_| illegal)?;
+                let a = self.read_s(rs1);
+                let res = round_integral(a as f64, if rm == 0b111 { self.frm } else { rm }) as f32;
+                if matches!(instr, Instruction::FroundnxS { .. }) && !a.is_nan() && res != a {
+                    self.fflags |= FFLAG_NX;
+                }
+                self.write_s(rd, canonicalize_s(res));
+            }
+            Instruction::FroundD { rd, rs1, rm } | Instruction::FroundnxD { rd, rs1, rm } => {
+                self.check_rm(rm).map_err(|_| illegal)?;
+                let a = self.read_d(rs1);
+                let res = round_integral(a, if rm == 0b111 { self.frm } else { rm });
+                if matches!(instr, Instruction::FroundnxD { .. }) && !a.is_nan() && res != a {
+                    self.fflags |= FFLAG_NX;
+                }
+                self.write_d(rd, canonicalize_d(res));
+            }
+            Instruction::FcvtmodWD { rd, rs1 } => {
+                let a = self.read_d(rs1);
+                // the remainder is exact, so this is the low 32 bits
+                let res = if a.is_finite() {
+                    (a.trunc() % 4294967296.0) as i64 as u32
+                } else {
+                    0
+                };
+                self.write_x(rd, res);
+            }
+        }
+
+        self.pc = next_pc;
+        Ok(instr)
+    }
+
+    /// Compares the state produced by the last executed instruction against `core`
+    pub fn compare<Reader: MemReader<Idx = u32>>(
+        &self,
+        core: &Core32<Reader>,
+        pc: u32,
+        instr: Instruction,
+    ) -> Result<(), Divergence> {
+        if self.pc != core.pc() {
+            return Err(Divergence::Pc {
+                pc,
+                instr,
+                expected: self.pc,
+                actual: core.pc(),
+            });
+        }
+
+        let gp_regs = core.gp_regs();
+        for (reg, (&expected, &actual)) in self.x.iter().zip(gp_regs.iter()).enumerate().skip(1) {
+            let actual = actual as u32;
+            if expected != actual {
+                return Err(Divergence::GpReg {
+                    pc,
+                    instr,
+                    reg: reg as u8,
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        // singles are compared NaN-boxed, which the core must do too
+        if let Some((reg, _)) = instr.fp_dest() {
+            let expected = self.f[reg as usize];
+            let actual = core.fp_regs()[reg as usize];
+            if expected != actual {
+                return Err(Divergence::FpReg {
+                    pc,
+                    instr,
+                    reg,
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        if let Some(csr) = instr.csr().filter(|&csr| models_csr(csr)) {
+            let expected = self.read_csr(csr);
+            let actual = core.read_csr(csr).unwrap();
+            if expected != actual {
+                return Err(Divergence::Csr {
+                    pc,
+                    instr,
+                    csr,
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        if let Some((addr, len)) = self.last_store {
+            let range = addr as usize..(addr + len) as usize;
+            let expected = &self.memory[range.clone()];
+            let actual = &core.memory()[range];
+            if expected != actual {
+                return Err(Divergence::Memory {
+                    pc,
+                    instr,
+                    addr,
+                    expected: expected.to_vec(),
+                    actual: actual.to_vec(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The csrs the oracle keeps itself; the counters can't be predicted
+fn models_csr(csr: u16) -> bool {
+    matches!(csr, csr::FFLAGS | csr::FRM | csr::FCSR)
+}
+
+fn is_call(instr: Instruction) -> bool {
+    matches!(
+        instr,
+        Instruction::Jal { rd: 1, .. } | Instruction::Jalr { rd: 1, .. }
+    )
+}
+
+/// Runs `core` to completion with the oracle stepping alongside it, returning the
+/// first point at which the two disagree
+pub fn run_self_check<Reader: MemReader<Idx = u32>>(
+    core: &mut Core32<Reader>,
+) -> Result<RunInfo, Divergence> {
+    let mut oracle = Oracle::from_core(core);
+
+    loop {
+        let pc = core.pc();
+        let instr = match oracle.step() {
+            Ok(instr) => instr,
+            // the core stops on illegal instructions rather than executing them
+            Err(Divergence::Illegal { .. }) => match core.step() {
+                Some(info) => return Ok(info),
+                None => {
+                    oracle.sync_from(core);
+                    continue;
+                }
+            },
+            Err(Divergence::Unsupported { instr, .. })
+                if matches!(instr, Instruction::Unknown(_)) || instr.is_privileged() =>
+            {
+                match core.step() {
+                    Some(info) => return Ok(info),
+                    // emulated by the core's illegal instruction handler
+                    None => {
+                        oracle.sync_from(core);
+                        continue;
+                    }
+                }
+            }
+            Err(divergence) => return Err(divergence),
+        };
+
+        if let Some(info) = core.step() {
+            return Ok(info);
+        }
+
+        if matches!(instr, Instruction::Ecall | Instruction::Custom { .. })
+            || (is_call(instr) && core.is_intercepted(oracle.pc))
+            || instr.csr().is_some_and(|csr| !models_csr(csr))
+            || core.take_device_access()
+        {
+            oracle.sync_from(core);
+            continue;
+        }
+
+        oracle.compare(core, pc, instr)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::AdaptiveMemReader, load::LoadedElf};
+
+    // exits with the number of the first of its known answers that's wrong, so this
+    // catches a bug the core and the oracle share as well as one where they differ
+    #[test]
+    fn fp_known_answers_agree() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/guests/fpcheck.elf");
+        let elf = LoadedElf::load(path).unwrap();
+        let mut core = Core32::<AdaptiveMemReader<u32>>::new(elf, None, 16 << 20, false);
+        let info = run_self_check(&mut core).unwrap_or_else(|err| panic!("{err}"));
+        assert_eq!(info.return_code, 0, "case {} is wrong", info.return_code);
+    }
+}
@@ -0,0 +1,404 @@
+//! Just enough IEEE 754 arithmetic for the oracle, done on integers alone.
+//!
+//! Every operation works out its result exactly, as a significand and a power of two, and
+//! rounds it once at the end in whichever rounding mode the instruction asked for. None of it
+//! touches the host's FPU, which only ever rounds to nearest, or borrows the fast core's tricks
+//! for making it round otherwise, so the two can be checked against each other. Values are raw
+//! bit patterns, NaN results are always the canonical NaN, and no flags are raised.
+
+use std::cmp::Ordering;
+
+/// A binary interchange format, binary32 or binary64
+#[derive(Clone, Copy)]
+pub struct Format {
+    exp_bits: u32,
+    frac_bits: u32,
+}
+
+pub const SINGLE: Format = Format {
+    exp_bits: 8,
+    frac_bits: 23,
+};
+
+pub const DOUBLE: Format = Format {
+    exp_bits: 11,
+    frac_bits: 52,
+};
+
+impl Format {
+    fn bias(self) -> i32 {
+        (1 << (self.exp_bits - 1)) - 1
+    }
+
+    /// The bit that negates a value
+    pub fn sign(self) -> u64 {
+        1 << (self.exp_bits + self.frac_bits)
+    }
+
+    fn exp_mask(self) -> u64 {
+        ((1 << self.exp_bits) - 1) << self.frac_bits
+    }
+
+    fn frac_mask(self) -> u64 {
+        (1 << self.frac_bits) - 1
+    }
+
+    fn nan(self) -> u64 {
+        self.exp_mask() | 1 << (self.frac_bits - 1)
+    }
+
+    fn inf(self, sign: bool) -> u64 {
+        self.signed(sign, self.exp_mask())
+    }
+
+    fn zero(self, sign: bool) -> u64 {
+        self.signed(sign, 0)
+    }
+
+    fn signed(self, sign: bool, bits: u64) -> u64 {
+        if sign {
+            bits | self.sign()
+        } else {
+            bits
+        }
+    }
+
+    // the exponent of a subnormal's lowest bit, and so of the lowest bit of anything
+    fn min_exp(self) -> i32 {
+        1 - self.bias() - self.frac_bits as i32
+    }
+
+    // the exponent of the lowest bit of the largest finite numbers
+    fn max_exp(self) -> i32 {
+        self.bias() - self.frac_bits as i32
+    }
+
+    fn unpack(self, bits: u64) -> Value {
+        let sign = bits & self.sign() != 0;
+        let exp = (bits & self.exp_mask()) >> self.frac_bits;
+        let frac = (bits & self.frac_mask()) as u128;
+        if exp == self.exp_mask() >> self.frac_bits {
+            if frac == 0 {
+                Value::Inf(sign)
+            } else {
+                Value::Nan
+            }
+        } else if exp == 0 {
+            Value::Finite(Exact::new(sign, frac, self.min_exp()))
+        } else {
+            let exp = exp as i32 + self.min_exp() - 1;
+            Value::Finite(Exact::new(sign, frac | 1 << self.frac_bits, exp))
+        }
+    }
+
+    /// `exact` rounded into this format in rounding mode `rm`, which must be valid
+    fn round(self, exact: Exact, rm: u8) -> u64 {
+        let Exact {
+            sign,
+            sig,
+            exp,
+            sticky,
+        } = exact;
+        if sig == 0 && !sticky {
+            return self.zero(sign);
+        }
+        debug_assert!(sig >> 126 == 0);
+
+        // the exponent of the result's lowest bit: as many bits below the top as there are in
+        // a significand, but no lower than the subnormals go
+        let len = 128 - sig.leading_zeros() as i32;
+        let mut lsb = (exp + len - 1 - self.frac_bits as i32).max(self.min_exp());
+
+        // and what's cut off below that, against half of it
+        let (mut sig, half, inexact) = if lsb <= exp {
+            (sig << (exp - lsb), Ordering::Less, sticky)
+        } else if lsb - exp > len {
+            (0, Ordering::Less, true)
+        } else {
+            let shift = (lsb - exp) as u32;
+            let rest = sig & ((1 << shift) - 1);
+            let half = match rest.cmp(&(1 << (shift - 1))) {
+                Ordering::Equal if sticky => Ordering::Greater,
+                half => half,
+            };
+            (sig >> shift, half, rest != 0 || sticky)
+        };
+
+        let up = match rm {
+            // rne
+            0b000 => half == Ordering::Greater || (half == Ordering::Equal && sig & 1 != 0),
+            // rtz
+            0b001 => false,
+            // rdn
+            0b010 => inexact && sign,
+            // rup
+            0b011 => inexact && !sign,
+            // rmm
+            _ => half != Ordering::Less,
+        };
+        if up {
+            sig += 1;
+            if sig >> (self.frac_bits + 1) != 0 {
+                sig >>= 1;
+                lsb += 1;
+            }
+        }
+
+        if lsb > self.max_exp() {
+            let to_inf = match rm {
+                0b001 => false,
+                0b010 => sign,
+                0b011 => !sign,
+                _ => true,
+            };
+            let max = (self.exp_mask() - (1 << self.frac_bits)) | self.frac_mask();
+            return self.signed(sign, if to_inf { self.exp_mask() } else { max });
+        }
+        let bits = if sig >> self.frac_bits == 0 {
+            // subnormal, which only happens at the lowest exponent
+            sig as u64
+        } else {
+            let biased = (lsb - self.min_exp() + 1) as u64;
+            biased << self.frac_bits | (sig as u64 & self.frac_mask())
+        };
+        self.signed(sign, bits)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Value {
+    Nan,
+    Inf(bool),
+    Finite(Exact),
+}
+
+impl Value {
+    fn sign(self) -> bool {
+        match self {
+            Value::Nan => false,
+            Value::Inf(sign) => sign,
+            Value::Finite(exact) => exact.sign,
+        }
+    }
+}
+
+/// A finite value, `sig * 2^exp` with `sign`, and with `sticky` a little more, less than a unit
+/// of `exp`, when there was more to it than fits
+#[derive(Clone, Copy)]
+struct Exact {
+    sign: bool,
+    sig: u128,
+    exp: i32,
+    sticky: bool,
+}
+
+impl Exact {
+    fn new(sign: bool, sig: u128, exp: i32) -> Self {
+        Self {
+            sign,
+            sig,
+            exp,
+            sticky: false,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.sig == 0
+    }
+
+    // the exponent just above the top bit
+    fn top(&self) -> i32 {
+        self.exp + 128 - self.sig.leading_zeros() as i32
+    }
+
+    fn mul(self, other: Self) -> Self {
+        Self::new(
+            self.sign != other.sign,
+            self.sig * other.sig,
+            self.exp + other.exp,
+        )
+    }
+
+    /// `self + other`, both exact and with at most 106 bits, exact but for a sticky bit if they're
+    /// too far apart. An exact zero is -0 rounding down and +0 otherwise, unless both were zeros
+    /// of the same sign
+    fn add(self, other: Self, rm: u8) -> Self {
+        if self.is_zero() && other.is_zero() {
+            let sign = if self.sign == other.sign {
+                self.sign
+            } else {
+                rm == 0b010
+            };
+            return Self::new(sign, 0, 0);
+        }
+        if other.is_zero() {
+            return self;
+        }
+        if self.is_zero() {
+            return other;
+        }
+
+        // line both up with the larger's top bit at 120, where the smaller can't overflow it,
+        // jamming what falls off the bottom of the smaller into a sticky bit
+        let (big, small) = if self.top() >= other.top() {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let exp = big.top() - 120;
+        let big_sig = big.sig << (big.exp - exp);
+        let (small_sig, sticky) = if small.exp >= exp {
+            (small.sig << (small.exp - exp), false)
+        } else if exp - small.exp >= 128 {
+            (0, true)
+        } else {
+            let shift = exp - small.exp;
+            (small.sig >> shift, small.sig & ((1 << shift) - 1) != 0)
+        };
+
+        if big.sign == small.sign {
+            return Self {
+                sign: big.sign,
+                sig: big_sig + small_sig,
+                exp,
+                sticky,
+            };
+        }
+        // anything jammed is far too small to change which is bigger, and taking a little more
+        // than a whole unit off leaves a little less than one less
+        match (big_sig - sticky as u128).cmp(&small_sig) {
+            Ordering::Equal => Self::new(rm == 0b010, 0, 0),
+            Ordering::Greater => Self {
+                sign: big.sign,
+                sig: big_sig - small_sig - sticky as u128,
+                exp,
+                sticky,
+            },
+            Ordering::Less => Self::new(small.sign, small_sig - big_sig, exp),
+        }
+    }
+}
+
+/// `a + b` in `format`, rounded in rounding mode `rm`, which must be valid
+pub fn add(format: Format, a: u64, b: u64, rm: u8) -> u64 {
+    match (format.unpack(a), format.unpack(b)) {
+        (Value::Nan, _) | (_, Value::Nan) => format.nan(),
+        (Value::Inf(a), Value::Inf(b)) if a != b => format.nan(),
+        (Value::Inf(sign), _) | (_, Value::Inf(sign)) => format.inf(sign),
+        (Value::Finite(a), Value::Finite(b)) => format.round(a.add(b, rm), rm),
+    }
+}
+
+/// `a * b` in `format`, rounded in rounding mode `rm`, which must be valid
+pub fn mul(format: Format, a: u64, b: u64, rm: u8) -> u64 {
+    match (format.unpack(a), format.unpack(b)) {
+        (Value::Nan, _) | (_, Value::Nan) => format.nan(),
+        (Value::Inf(_), Value::Finite(x)) | (Value::Finite(x), Value::Inf(_)) if x.is_zero() => {
+            format.nan()
+        }
+        (Value::Inf(a), Value::Inf(b)) => format.inf(a != b),
+        (Value::Inf(a), Value::Finite(b)) | (Value::Finite(b), Value::Inf(a)) => {
+            format.inf(a != b.sign)
+        }
+        (Value::Finite(a), Value::Finite(b)) => format.round(a.mul(b), rm),
+    }
+}
+
+/// `a * b + c` in `format`, rounded once in rounding mode `rm`, which must be valid
+pub fn fma(format: Format, a: u64, b: u64, c: u64, rm: u8) -> u64 {
+    match (format.unpack(a), format.unpack(b), format.unpack(c)) {
+        (Value::Nan, _, _) | (_, Value::Nan, _) | (_, _, Value::Nan) => format.nan(),
+        (Value::Inf(_), Value::Finite(x), _) | (Value::Finite(x), Value::Inf(_), _)
+            if x.is_zero() =>
+        {
+            format.nan()
+        }
+        (Value::Finite(a), Value::Finite(b), Value::Finite(c)) => {
+            format.round(a.mul(b).add(c, rm), rm)
+        }
+        (Value::Finite(_), Value::Finite(_), Value::Inf(sign)) => format.inf(sign),
+        // an infinite product
+        (a, b, c) => {
+            let sign = a.sign() != b.sign();
+            match c {
+                Value::Inf(c) if c != sign => format.nan(),
+                _ => format.inf(sign),
+            }
+        }
+    }
+}
+
+/// `a / b` in `format`, rounded in rounding mode `rm`, which must be valid
+pub fn div(format: Format, a: u64, b: u64, rm: u8) -> u64 {
+    match (format.unpack(a), format.unpack(b)) {
+        (Value::Nan, _) | (_, Value::Nan) | (Value::Inf(_), Value::Inf(_)) => format.nan(),
+        (Value::Inf(a), Value::Finite(b)) => format.inf(a != b.sign),
+        (Value::Finite(a), Value::Inf(b)) => format.zero(a.sign != b),
+        (Value::Finite(a), Value::Finite(b)) if b.is_zero() => {
+            if a.is_zero() {
+                format.nan()
+            } else {
+                format.inf(a.sign != b.sign)
+            }
+        }
+        (Value::Finite(a), Value::Finite(b)) => {
+            // enough bits of quotient to round from, with the remainder as the sticky bit
+            let len = |sig: u128| 128 - sig.leading_zeros() as i32;
+            let shift = 64 + len(b.sig) - len(a.sig);
+            let num = a.sig << shift;
+            format.round(
+                Exact {
+                    sign: a.sign != b.sign,
+                    sig: num / b.sig,
+                    exp: a.exp - shift - b.exp,
+                    sticky: num % b.sig != 0,
+                },
+                rm,
+            )
+        }
+    }
+}
+
+/// The square root of `a` in `format`, rounded in rounding mode `rm`, which must be valid
+pub fn sqrt(format: Format, a: u64, rm: u8) -> u64 {
+    match format.unpack(a) {
+        Value::Nan | Value::Inf(true) => format.nan(),
+        Value::Inf(false) => format.inf(false),
+        Value::Finite(a) if a.is_zero() => format.zero(a.sign),
+        Value::Finite(a) if a.sign => format.nan(),
+        Value::Finite(a) => {
+            // widen to an even power of two with twice the bits needed, for the root to have
+            // enough, and the remainder as the sticky bit
+            let mut shift = 116 - (128 - a.sig.leading_zeros() as i32);
+            if (a.exp - shift) % 2 != 0 {
+                shift += 1;
+            }
+            let sig = a.sig << shift;
+            let root = sig.isqrt();
+            format.round(
+                Exact {
+                    sign: false,
+                    sig: root,
+                    exp: (a.exp - shift) / 2,
+                    sticky: root * root != sig,
+                },
+                rm,
+            )
+        }
+    }
+}
+
+/// `a`, in `from`, converted to `to` and rounded in rounding mode `rm`, which must be valid
+pub fn convert(from: Format, to: Format, a: u64, rm: u8) -> u64 {
+    match from.unpack(a) {
+        Value::Nan => to.nan(),
+        Value::Inf(sign) => to.inf(sign),
+        Value::Finite(a) => to.round(a, rm),
+    }
+}
+
+/// The integer `-val` if `negative`, else `val`, in `format`, rounded in rounding mode `rm`,
+/// which must be valid
+pub fn from_int(format: Format, negative: bool, val: u32, rm: u8) -> u64 {
+    format.round(Exact::new(negative, val as u128, 0), rm)
+}
@@ -0,0 +1,170 @@
+//! Patching instructions in the guest's code, for `riscy --patch`.
+//!
+//! A patch replaces the instruction word at a location, an address or
+//! `SYMBOL` or `SYMBOL+OFFSET`, with `nop`, `ret`, `j:LOCATION`, a jump there,
+//! or a word of its own in hex (`0x00100073`). That neutralizes code the guest
+//! can't be rebuilt without, like a loop polling hardware riscy doesn't have:
+//! `riscy --patch wait_ready+0x8=nop`.
+//!
+//! Patches are made with `Core32::patch` before the guest runs, to its copy of
+//! the program in memory, so the ELF is left as it was. The core decodes the
+//! word again, as it would a store to its code, so one can also be made
+//! between runs, or from a hook.
+
+use std::{fmt, str::FromStr};
+
+use crate::load::Symbol;
+
+const NOP: u32 = 0x0000_0013;
+// `jalr zero, 0(ra)`
+const RET: u32 = 0x0000_8067;
+
+/// Where a patch goes, or a `j:` patch jumps to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Addr(u32),
+    Symbol { name: String, offset: u32 },
+}
+
+impl Location {
+    /// The address, looking a symbol up in `symbols`
+    pub fn resolve(&self, symbols: &[Symbol]) -> Result<u32, String> {
+        match self {
+            Location::Addr(addr) => Ok(*addr),
+            Location::Symbol { name, offset } => symbols
+                .iter()
+                .find(|sym| sym.name == *name)
+                .map(|sym| (sym.addr as u32).wrapping_add(*offset))
+                .ok_or_else(|| format!("symbol '{name}' not found")),
+        }
+    }
+}
+
+impl FromStr for Location {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(addr) = parse_u32(s) {
+            return Ok(Location::Addr(addr));
+        }
+
+        let (name, offset) = match s.split_once('+') {
+            Some((name, offset)) => (
+                name,
+                parse_u32(offset).ok_or_else(|| format!("invalid offset '{offset}'"))?,
+            ),
+            None => (s, 0),
+        };
+        if name.is_empty() {
+            return Err(format!(
+                "invalid location '{s}', expected ADDR, SYMBOL or SYMBOL+OFFSET"
+            ));
+        }
+        Ok(Location::Symbol {
+            name: name.to_string(),
+            offset,
+        })
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Addr(addr) => write!(f, "{addr:#x}"),
+            Location::Symbol { name, offset: 0 } => write!(f, "{name}"),
+            Location::Symbol { name, offset } => write!(f, "{name}+{offset:#x}"),
+        }
+    }
+}
+
+// hex with `0x` or decimal
+fn parse_u32(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// What an instruction is replaced with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Patch {
+    Nop,
+    Ret,
+    Jump(Location),
+    Word(u32),
+}
+
+impl Patch {
+    /// The word the instruction at `addr` is replaced with
+    pub fn encode(&self, addr: u32, symbols: &[Symbol]) -> Result<u32, String> {
+        match self {
+            Patch::Nop => Ok(NOP),
+            Patch::Ret => Ok(RET),
+            Patch::Word(word) => Ok(*word),
+            Patch::Jump(target) => {
+                let target = target.resolve(symbols)?;
+                encode_jal(target.wrapping_sub(addr) as i32)
+                    .ok_or_else(|| format!("{target:#x} is out of reach of a jump from {addr:#x}"))
+            }
+        }
+    }
+}
+
+impl FromStr for Patch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nop" => Ok(Patch::Nop),
+            "ret" => Ok(Patch::Ret),
+            _ => {
+                if let Some(target) = s.strip_prefix("j:") {
+                    return Ok(Patch::Jump(target.parse()?));
+                }
+                s.strip_prefix("0x")
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .map(Patch::Word)
+                    .ok_or_else(|| {
+                        format!("invalid patch '{s}', expected nop, ret, j:LOCATION or 0xWORD")
+                    })
+            }
+        }
+    }
+}
+
+// `jal zero, offset`, if it's in range
+fn encode_jal(offset: i32) -> Option<u32> {
+    if offset % 2 != 0 || !(-(1 << 20)..1 << 20).contains(&offset) {
+        return None;
+    }
+    let imm = offset as u32;
+    Some(
+        ((imm >> 20) & 1) << 31
+            | ((imm >> 1) & 0x3ff) << 21
+            | ((imm >> 11) & 1) << 20
+            | ((imm >> 12) & 0xff) << 12
+            | 0x6f,
+    )
+}
+
+/// A `--patch` argument: `LOCATION=PATCH`
+#[derive(Debug, Clone)]
+pub struct PatchSpec {
+    pub at: Location,
+    pub patch: Patch,
+}
+
+impl FromStr for PatchSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (at, patch) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid patch '{s}', expected LOCATION=PATCH"))?;
+
+        Ok(PatchSpec {
+            at: at.parse()?,
+            patch: patch.parse()?,
+        })
+    }
+}
@@ -0,0 +1,250 @@
+//! A cycle-approximate model of a classic in-order 5-stage pipeline.
+//!
+//! `PipelineModel` is a hook that follows the instructions as the core runs
+//! them, working out when each would have reached decode on an IF, ID, EX,
+//! MEM, WB pipeline issuing one instruction a cycle. The run itself is
+//! unaffected; the model only counts the cycles it would have taken.
+//!
+//! Instructions stall in decode until their operands are ready. With
+//! forwarding, results go straight from the end of EX (or MEM, for loads) to
+//! the next EX, so only a load followed by a use stalls, for one cycle.
+//! Without it, results are only read once written back, which is two cycles
+//! after the producer's EX, the register file being written in the first half
+//! of a cycle and read in the second. Branches are predicted not taken, and
+//! taken branches and jumps flush the instructions fetched behind them, which
+//! costs `branch_penalty` cycles. Every instruction, multiplies and divides
+//! included, spends one cycle in each stage.
+
+use std::{cell::RefCell, fmt, rc::Rc, str::FromStr};
+
+use crate::{
+    cost::OpClass,
+    hooks::{Hook, HookAction},
+    instruction::Instruction,
+};
+
+// integer registers then fp registers
+const REGS: usize = 64;
+
+// cycles from decode until the result can be forwarded to the decode of a
+// dependent instruction, by what produced it, and without forwarding
+const ALU_READY: u64 = 1;
+const LOAD_READY: u64 = 2;
+const WRITEBACK_READY: u64 = 3;
+
+// the cycles after the last decode for it to get through EX, MEM and WB
+const DRAIN: u64 = 3;
+
+/// The pipeline's options, parsed from e.g. `forwarding=off,branch-penalty=1`;
+/// options not mentioned keep their default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PipelineConfig {
+    pub forwarding: bool,
+    /// Cycles lost to each taken branch or jump
+    pub branch_penalty: u64,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        // branches resolve in EX, so two instructions behind one are flushed
+        Self {
+            forwarding: true,
+            branch_penalty: 2,
+        }
+    }
+}
+
+impl FromStr for PipelineConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = PipelineConfig::default();
+
+        for part in s.split(',').filter(|part| !part.is_empty()) {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected OPTION=VALUE, got '{part}'"))?;
+            match name {
+                "forwarding" => {
+                    config.forwarding = match value {
+                        "on" => true,
+                        "off" => false,
+                        _ => return Err(format!("invalid forwarding '{value}', expected on/off")),
+                    }
+                }
+                "branch-penalty" => {
+                    config.branch_penalty = value
+                        .parse()
+                        .map_err(|_| format!("invalid branch penalty '{value}'"))?;
+                }
+                _ => return Err(format!("unknown pipeline option '{name}'")),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug)]
+struct PipelineState {
+    config: PipelineConfig,
+    instructions: u64,
+    // the cycle the last instruction was decoded in
+    decode: u64,
+    // the cycle each register's value can be decoded with, and whether a load
+    // produced it
+    ready: [(u64, bool); REGS],
+    // the last instruction, if it was a branch or jump, and where it was
+    branch: Option<u32>,
+    load_use_stalls: u64,
+    data_stalls: u64,
+    control_stalls: u64,
+}
+
+/// The pipeline model. Clones share their state, so one can be kept to read
+/// the report after the other is given to `Core32::add_hook`
+#[derive(Debug, Clone)]
+pub struct PipelineModel {
+    state: Rc<RefCell<PipelineState>>,
+}
+
+impl PipelineModel {
+    pub fn new(config: PipelineConfig) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(PipelineState {
+                config,
+                instructions: 0,
+                decode: 0,
+                ready: [(0, false); REGS],
+                branch: None,
+                load_use_stalls: 0,
+                data_stalls: 0,
+                control_stalls: 0,
+            })),
+        }
+    }
+
+    pub fn report(&self) -> PipelineReport {
+        let state = self.state.borrow();
+        PipelineReport {
+            config: state.config,
+            instructions: state.instructions,
+            // the first instruction is fetched in cycle 0 and decoded in 1
+            cycles: if state.instructions == 0 {
+                0
+            } else {
+                state.decode + DRAIN
+            },
+            load_use_stalls: state.load_use_stalls,
+            data_stalls: state.data_stalls,
+            control_stalls: state.control_stalls,
+        }
+    }
+}
+
+impl Hook for PipelineModel {
+    fn before_instruction(&mut self, pc: u32, instr: &Instruction) -> HookAction {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        state.instructions += 1;
+
+        let mut decode = state.decode + 1;
+
+        // anywhere but the next instruction means the branch was taken
+        if let Some(branch_pc) = state.branch.take() {
+            if pc != branch_pc.wrapping_add(4) {
+                decode += state.config.branch_penalty;
+                state.control_stalls += state.config.branch_penalty;
+            }
+        }
+
+        let gp = instr.gp_sources().into_iter().flatten();
+        let fp = instr.fp_sources().into_iter().flatten();
+        let sources = gp
+            .filter(|&reg| reg != 0)
+            .map(|reg| reg as usize)
+            .chain(fp.map(|reg| reg as usize + 32));
+        if let Some((ready, load)) = sources.map(|reg| state.ready[reg]).max() {
+            if ready > decode {
+                let stall = ready - decode;
+                if load && state.config.forwarding {
+                    state.load_use_stalls += stall;
+                } else {
+                    state.data_stalls += stall;
+                }
+                decode = ready;
+            }
+        }
+        state.decode = decode;
+
+        let class = OpClass::of(instr);
+        let load = class == OpClass::Load;
+        let ready = match (state.config.forwarding, load) {
+            (false, _) => WRITEBACK_READY,
+            (true, true) => LOAD_READY,
+            (true, false) => ALU_READY,
+        };
+        let dest = match (instr.gp_dest(), instr.fp_dest()) {
+            (Some(reg), _) if reg != 0 => Some(reg as usize),
+            (_, Some((reg, _))) => Some(reg as usize + 32),
+            _ => None,
+        };
+        if let Some(reg) = dest {
+            state.ready[reg] = (decode + ready, load);
+        }
+
+        if class == OpClass::Branch {
+            state.branch = Some(pc);
+        }
+
+        HookAction::Continue
+    }
+
+    fn wants_reg_writes(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PipelineReport {
+    pub config: PipelineConfig,
+    pub instructions: u64,
+    pub cycles: u64,
+    /// Cycles stalled waiting on a load, with forwarding
+    pub load_use_stalls: u64,
+    /// Cycles stalled waiting on any other result
+    pub data_stalls: u64,
+    /// Cycles lost to taken branches and jumps
+    pub control_stalls: u64,
+}
+
+impl PipelineReport {
+    pub fn cpi(&self) -> f64 {
+        self.cycles as f64 / self.instructions.max(1) as f64
+    }
+}
+
+impl fmt::Display for PipelineReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let forwarding = if self.config.forwarding { "on" } else { "off" };
+        writeln!(
+            f,
+            "pipeline (forwarding {forwarding}, branch penalty {}):",
+            self.config.branch_penalty
+        )?;
+        writeln!(f, "  {:<12} {:>14}", "instructions", self.instructions)?;
+        writeln!(f, "  {:<12} {:>14}", "cycles", self.cycles)?;
+        writeln!(f, "  {:<12} {:>14.3}", "CPI", self.cpi())?;
+        let stalls = [
+            ("load-use", self.load_use_stalls),
+            ("data", self.data_stalls),
+            ("control", self.control_stalls),
+        ];
+        write!(f, "  stalls:")?;
+        for (name, cycles) in stalls {
+            let share = cycles as f64 / self.cycles.max(1) as f64 * 100.0;
+            write!(f, "\n    {name:<10} {cycles:>14} ({share:.1}% of cycles)")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+//! Periodic progress reports for long runs.
+
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+/// How often to report, parsed from `5s`/`500ms` (time) or `100M`/`5000` (instructions)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressInterval {
+    Time(Duration),
+    Instructions(u64),
+}
+
+/// A time like `10s`, `1.5s` or `500ms`
+pub(crate) fn parse_duration(s: &str) -> Option<Duration> {
+    if let Some(ms) = s.strip_suffix("ms") {
+        return ms.parse().ok().map(Duration::from_millis);
+    }
+
+    let secs = s.strip_suffix('s')?.parse().ok()?;
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+impl FromStr for ProgressInterval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid progress interval '{s}'");
+
+        if s.ends_with('s') {
+            return parse_duration(s)
+                .map(ProgressInterval::Time)
+                .ok_or_else(err);
+        }
+
+        let (count, scale) = match s.as_bytes().last() {
+            Some(b'k' | b'K') => (&s[..s.len() - 1], 1_000),
+            Some(b'm' | b'M') => (&s[..s.len() - 1], 1_000_000),
+            Some(b'g' | b'G') => (&s[..s.len() - 1], 1_000_000_000),
+            _ => (s, 1),
+        };
+
+        match count.parse::<u64>() {
+            Ok(count) if count > 0 => Ok(ProgressInterval::Instructions(count * scale)),
+            _ => Err(err()),
+        }
+    }
+}
+
+/// A snapshot of how the run is going
+#[derive(Debug, Clone)]
+pub struct ProgressReport {
+    pub elapsed: Duration,
+    pub instret: u64,
+    /// Millions of instructions per second since the previous report
+    pub mips: f64,
+    pub pc: u32,
+    /// The function containing `pc`, if the ELF has symbols
+    pub function: Option<String>,
+    /// Number of calls to each syscall so far, by syscall number
+    pub syscalls: Vec<(i32, u64)>,
+    /// How much of guest memory the host has committed, see
+    /// `Core32::resident_memory`, or 0 if it can't say
+    pub resident_memory: usize,
+}
+
+impl fmt::Display for ProgressReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "progress: {:.1}s, {} instructions ({:.1} MIPS), pc {:#x}",
+            self.elapsed.as_secs_f64(),
+            self.instret,
+            self.mips,
+            self.pc
+        )?;
+
+        if let Some(function) = &self.function {
+            write!(f, " in {function}")?;
+        }
+
+        if !self.syscalls.is_empty() {
+            let syscalls = self
+                .syscalls
+                .iter()
+                .map(|(num, count)| format!("{num}x{count}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            write!(f, ", syscalls: {syscalls}")?;
+        }
+
+        Ok(())
+    }
+}
+
+pub type ProgressFn = Box<dyn FnMut(&ProgressReport)>;
+
+// how many instructions to run between looking at the clock
+const TIME_CHECK_INTERVAL: u64 = 1 << 20;
+
+pub(crate) struct Progress {
+    interval: ProgressInterval,
+    callback: ProgressFn,
+    start: Instant,
+    last_time: Instant,
+    last_instret: u64,
+}
+
+impl Progress {
+    pub(crate) fn new(interval: ProgressInterval, callback: ProgressFn) -> Self {
+        let now = Instant::now();
+        Self {
+            interval,
+            callback,
+            start: now,
+            last_time: now,
+            last_instret: 0,
+        }
+    }
+
+    /// The instret at which `tick` should next be called
+    pub(crate) fn next_check(&self, instret: u64) -> u64 {
+        match self.interval {
+            ProgressInterval::Time(_) => instret + TIME_CHECK_INTERVAL,
+            ProgressInterval::Instructions(count) => self.last_instret + count,
+        }
+    }
+
+    /// Whether a report is due; if so, `report` should be passed a snapshot
+    pub(crate) fn due(&self, instret: u64) -> bool {
+        match self.interval {
+            ProgressInterval::Time(every) => self.last_time.elapsed() >= every,
+            ProgressInterval::Instructions(count) => instret - self.last_instret >= count,
+        }
+    }
+
+    /// Fills in the timing fields of `report` and hands it to the callback
+    pub(crate) fn report(&mut self, mut report: ProgressReport) {
+        let now = Instant::now();
+        let secs = (now - self.last_time).as_secs_f64();
+
+        report.elapsed = now - self.start;
+        report.mips = (report.instret - self.last_instret) as f64 / secs.max(f64::EPSILON) / 1e6;
+
+        self.last_time = now;
+        self.last_instret = report.instret;
+
+        (self.callback)(&report);
+    }
+}
@@ -0,0 +1,257 @@
+//! Disassembly using the assembler's pseudo-instructions, for traces and the
+//! debugger.
+//!
+//! `disassemble` shows an instruction as it was most likely written: `ret`
+//! rather than `jalr zero, 0(ra)`, `beqz a0, 0x10124 <loop+0x8>` rather than
+//! `beq a0, zero, -12`. An `auipc` or `lui` and the instruction after it that
+//! completes the address or constant are shown together as one `la`, `call`,
+//! `tail` or `li`; `fuses` says whether an instruction is such a second half.
+//! Anything without a pseudo-instruction is shown as by `Instruction`'s
+//! `Display`. `switch_note` says how an instruction takes part in a `switch`,
+//! from the jump tables the core has found, see `jumptable`.
+
+use crate::{
+    csr,
+    instruction::{self, Instruction},
+    jumptable::JumpTables,
+    load::{self, Symbol},
+    register::Register,
+};
+
+const RA: u8 = 1;
+const T1: u8 = 6;
+
+/// The instruction at `pc`, followed by `next`, with pseudo-instructions and
+/// symbolic addresses
+pub fn disassemble(
+    pc: u32,
+    instr: Instruction,
+    next: Option<Instruction>,
+    symbols: &[Symbol],
+) -> String {
+    if let Some(fused) = next.and_then(|next| fused(pc, instr, next, symbols)) {
+        return fused;
+    }
+
+    single(pc, instr, symbols).unwrap_or_else(|| instr.to_string())
+}
+
+/// Whether `second` completes the pair started by `first`, so is already
+/// shown by `disassemble` as part of it
+pub fn fuses(first: Instruction, second: Instruction) -> bool {
+    fused(0, first, second, &[]).is_some()
+}
+
+/// A note for the instruction at `pc` if it's part of a `switch` through one
+/// of `tables`: `switch, 5 cases at .LJTI0_0` at the `jr`, or `case 1, 3 of
+/// switch at main+0x40` at a case
+pub fn switch_note(pc: u32, tables: &JumpTables, symbols: &[Symbol]) -> Option<String> {
+    if let Some(table) = tables.get(pc) {
+        return Some(format!(
+            "switch, {} cases at {}",
+            table.targets.len(),
+            symbolic(symbols, table.addr)
+        ));
+    }
+
+    let notes: Vec<_> = tables
+        .switching_to(pc)
+        .map(|table| {
+            let cases: Vec<_> = table.cases_of(pc).map(|case| case.to_string()).collect();
+            let noun = if cases.len() == 1 { "case" } else { "cases" };
+            format!(
+                "{noun} {} of switch at {}",
+                cases.join(", "),
+                symbolic(symbols, table.dispatch)
+            )
+        })
+        .collect();
+    (!notes.is_empty()).then(|| notes.join("; "))
+}
+
+fn fused(pc: u32, first: Instruction, second: Instruction, symbols: &[Symbol]) -> Option<String> {
+    let x = Register::gp;
+
+    match (first, second) {
+        (
+            Instruction::Auipc { rd, imm: hi },
+            Instruction::Addi {
+                rd: rd2,
+                rs1,
+                imm: lo,
+            },
+        ) if rd != 0 && rd2 == rd && rs1 == rd => {
+            let addr = pc.wrapping_add(hi as u32).wrapping_add(lo as u32);
+            Some(format!("la {}, {}", x(rd), symbolic(symbols, addr)))
+        }
+        (
+            Instruction::Auipc { rd, imm: hi },
+            Instruction::Jalr {
+                rd: link,
+                rs1,
+                imm: lo,
+            },
+        ) if rs1 == rd && ((link == RA && rd == RA) || (link == 0 && rd == T1)) => {
+            let target = pc.wrapping_add(hi as u32).wrapping_add(lo as u32);
+            let op = if link == RA { "call" } else { "tail" };
+            Some(format!("{op} {}", symbolic(symbols, target)))
+        }
+        (
+            Instruction::Lui { rd, imm: hi },
+            Instruction::Addi {
+                rd: rd2,
+                rs1,
+                imm: lo,
+            },
+        ) if rd != 0 && rd2 == rd && rs1 == rd => {
+            Some(format!("li {}, {}", x(rd), hi.wrapping_add(lo)))
+        }
+        _ => None,
+    }
+}
+
+fn single(pc: u32, instr: Instruction, symbols: &[Symbol]) -> Option<String> {
+    let x = Register::gp;
+    let fr = Register::fp;
+    let target = |imm: i32| load::describe(symbols, pc.wrapping_add(imm as u32));
+
+    let text = match instr {
+        Instruction::Addi {
+            rd: 0,
+            rs1: 0,
+            imm: 0,
+        } => "nop".to_owned(),
+        Instruction::Addi { rd, rs1: 0, imm } => format!("li {}, {imm}", x(rd)),
+        Instruction::Addi { rd, rs1, imm: 0 } => format!("mv {}, {}", x(rd), x(rs1)),
+        Instruction::Xori { rd, rs1, imm: -1 } => format!("not {}, {}", x(rd), x(rs1)),
+        Instruction::Sub { rd, rs1: 0, rs2 } => format!("neg {}, {}", x(rd), x(rs2)),
+        Instruction::Sltiu { rd, rs1, imm: 1 } => format!("seqz {}, {}", x(rd), x(rs1)),
+        Instruction::Sltu { rd, rs1: 0, rs2 } => format!("snez {}, {}", x(rd), x(rs2)),
+        Instruction::Slt { rd, rs1, rs2: 0 } => format!("sltz {}, {}", x(rd), x(rs1)),
+        Instruction::Slt { rd, rs1: 0, rs2 } => format!("sgtz {}, {}", x(rd), x(rs2)),
+
+        Instruction::Beq { rs1, rs2: 0, imm } => format!("beqz {}, {}", x(rs1), target(imm)),
+        Instruction::Bne { rs1, rs2: 0, imm } => format!("bnez {}, {}", x(rs1), target(imm)),
+        Instruction::Bge { rs1, rs2: 0, imm } => format!("bgez {}, {}", x(rs1), target(imm)),
+        Instruction::Blt { rs1, rs2: 0, imm } => format!("bltz {}, {}", x(rs1), target(imm)),
+        Instruction::Bge { rs1: 0, rs2, imm } => format!("blez {}, {}", x(rs2), target(imm)),
+        Instruction::Blt { rs1: 0, rs2, imm } => format!("bgtz {}, {}", x(rs2), target(imm)),
+        Instruction::Beq { rs1, rs2, imm } => {
+            format!("beq {}, {}, {}", x(rs1), x(rs2), target(imm))
+        }
+        Instruction::Bne { rs1, rs2, imm } => {
+            format!("bne {}, {}, {}", x(rs1), x(rs2), target(imm))
+        }
+        Instruction::Blt { rs1, rs2, imm } => {
+            format!("blt {}, {}, {}", x(rs1), x(rs2), target(imm))
+        }
+        Instruction::Bge { rs1, rs2, imm } => {
+            format!("bge {}, {}, {}", x(rs1), x(rs2), target(imm))
+        }
+        Instruction::Bltu { rs1, rs2, imm } => {
+            format!("bltu {}, {}, {}", x(rs1), x(rs2), target(imm))
+        }
+        Instruction::Bgeu { rs1, rs2, imm } => {
+            format!("bgeu {}, {}, {}", x(rs1), x(rs2), target(imm))
+        }
+
+        Instruction::Jal { rd: 0, imm } => format!("j {}", target(imm)),
+        Instruction::Jal { rd: RA, imm } => format!("jal {}", target(imm)),
+        Instruction::Jal { rd, imm } => format!("jal {}, {}", x(rd), target(imm)),
+        Instruction::Jalr {
+            rd: 0,
+            rs1: RA,
+            imm: 0,
+        } => "ret".to_owned(),
+        Instruction::Jalr { rd: 0, rs1, imm: 0 } => format!("jr {}", x(rs1)),
+        Instruction::Jalr {
+            rd: RA,
+            rs1,
+            imm: 0,
+        } => format!("jalr {}", x(rs1)),
+
+        Instruction::FsgnjS { rd, rs1, rs2 } if rs1 == rs2 => {
+            format!("fmv.s {}, {}", fr(rd), fr(rs1))
+        }
+        Instruction::FsgnjnS { rd, rs1, rs2 } if rs1 == rs2 => {
+            format!("fneg.s {}, {}", fr(rd), fr(rs1))
+        }
+        Instruction::FsgnjxS { rd, rs1, rs2 } if rs1 == rs2 => {
+            format!("fabs.s {}, {}", fr(rd), fr(rs1))
+        }
+        Instruction::FsgnjD { rd, rs1, rs2 } if rs1 == rs2 => {
+            format!("fmv.d {}, {}", fr(rd), fr(rs1))
+        }
+        Instruction::FsgnjnD { rd, rs1, rs2 } if rs1 == rs2 => {
+            format!("fneg.d {}, {}", fr(rd), fr(rs1))
+        }
+        Instruction::FsgnjxD { rd, rs1, rs2 } if rs1 == rs2 => {
+            format!("fabs.d {}, {}", fr(rd), fr(rs1))
+        }
+
+        Instruction::Csrrs { rd, rs1: 0, csr } => match csr_read_alias(csr) {
+            Some(op) => format!("{op} {}", x(rd)),
+            None => format!("csrr {}, {}", x(rd), instruction::csr_name(csr)),
+        },
+        Instruction::Csrrw { rd: 0, rs1, csr } => match csr_write_alias(csr) {
+            Some(op) => format!("{op} {}", x(rs1)),
+            None => format!("csrw {}, {}", instruction::csr_name(csr), x(rs1)),
+        },
+        Instruction::Csrrs { rd: 0, rs1, csr } => {
+            format!("csrs {}, {}", instruction::csr_name(csr), x(rs1))
+        }
+        Instruction::Csrrc { rd: 0, rs1, csr } => {
+            format!("csrc {}, {}", instruction::csr_name(csr), x(rs1))
+        }
+        Instruction::Csrrwi { rd: 0, zimm, csr } => {
+            format!("csrwi {}, {zimm}", instruction::csr_name(csr))
+        }
+        Instruction::Csrrsi { rd: 0, zimm, csr } => {
+            format!("csrsi {}, {zimm}", instruction::csr_name(csr))
+        }
+        Instruction::Csrrci { rd: 0, zimm, csr } => {
+            format!("csrci {}, {zimm}", instruction::csr_name(csr))
+        }
+
+        Instruction::Fence {
+            pred: 0b1111,
+            succ: 0b1111,
+        } => "fence".to_owned(),
+        _ => return None,
+    };
+
+    Some(text)
+}
+
+fn csr_read_alias(csr: u16) -> Option<&'static str> {
+    match csr {
+        csr::FFLAGS => Some("frflags"),
+        csr::FRM => Some("frrm"),
+        csr::FCSR => Some("frcsr"),
+        csr::CYCLE => Some("rdcycle"),
+        csr::TIME => Some("rdtime"),
+        csr::INSTRET => Some("rdinstret"),
+        csr::CYCLEH => Some("rdcycleh"),
+        csr::TIMEH => Some("rdtimeh"),
+        csr::INSTRETH => Some("rdinstreth"),
+        _ => None,
+    }
+}
+
+fn csr_write_alias(csr: u16) -> Option<&'static str> {
+    match csr {
+        csr::FFLAGS => Some("fsflags"),
+        csr::FRM => Some("fsrm"),
+        csr::FCSR => Some("fscsr"),
+        _ => None,
+    }
+}
+
+// `sym` or `sym+0x8`, or just the address if there's no symbol before it
+fn symbolic(symbols: &[Symbol], addr: u32) -> String {
+    match load::symbolize(symbols, addr as u64) {
+        Some((name, 0)) => name.to_owned(),
+        Some((name, offset)) => format!("{name}+{offset:#x}"),
+        None => format!("{addr:#x}"),
+    }
+}
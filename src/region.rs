@@ -0,0 +1,311 @@
+//! The guest's memory map: ROM, RAM and device windows.
+//!
+//! Guest memory is still one flat arena indexed by address, so ordinary loads
+//! and stores cost what they always have. The map says what the arena's
+//! addresses really are. The text segment is ROM: it's mapped from an image
+//! shared by every run of the same program (see `mmap::SharedImage`), and a
+//! guest store to it stops the run with `StopReason::RomWrite`, unless it's
+//! made writable with `Core32::set_writable_text`. Stores to decoded code, ROM
+//! or not, re-decode it, see `Core32::code_generation`. Device windows, added
+//! with `Core32::map_device`, send the loads and stores that land in them to a
+//! `Device`, for memory-mapped I/O, and a store to one can end the run.
+//! Everything else below `--size` is RAM. Executable segments other than the
+//! text segment, as `--load-extra` loads, are RAM the core runs as well.
+//!
+//! Only the guest's own loads, stores and atomics go through the map. Syscalls,
+//! natively serviced calls like `memcpy` and custom instruction handlers see
+//! the arena as plain memory.
+
+use std::fmt;
+
+use crate::load::Segment;
+
+/// A memory-mapped device, see `Core32::map_device`
+pub trait Device {
+    /// Reads `len` bytes (1, 2, 4 or 8) at `offset` into the window
+    fn read(&mut self, offset: u32, len: u32) -> u64;
+
+    /// Writes the low `len` bytes of `value` at `offset` into the window
+    fn write(&mut self, offset: u32, len: u32, value: u64);
+
+    /// The exit code to end the run with, if the last write asked to, as
+    /// `console::MagicConsole` does
+    fn exit_code(&mut self) -> Option<i32> {
+        None
+    }
+
+    /// Called once the guest exits, after the core's exit callbacks, see
+    /// `Core32::on_exit`, to flush anything buffered. A core restored from a
+    /// snapshot carries on using it
+    fn shutdown(&mut self) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Rom,
+    Ram,
+    /// The index of the device, in the order they were mapped
+    Device(usize),
+}
+
+/// What the guest may do at an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Perms {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl fmt::Display for Perms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |set, c| if set { c } else { '-' };
+        write!(
+            f,
+            "{}{}{}",
+            flag(self.read, 'r'),
+            flag(self.write, 'w'),
+            flag(self.execute, 'x')
+        )
+    }
+}
+
+const RW: Perms = Perms {
+    read: true,
+    write: true,
+    execute: false,
+};
+
+const RWX: Perms = Perms {
+    execute: true,
+    ..RW
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: u32,
+    // a region can run to the top of the address space, so this can be 1 << 32
+    pub len: u64,
+    pub kind: RegionKind,
+    pub perms: Perms,
+}
+
+impl Region {
+    /// One past the last address
+    pub fn end(&self) -> u64 {
+        self.start as u64 + self.len
+    }
+
+    pub fn contains(&self, addr: u32) -> bool {
+        addr >= self.start && (addr as u64) < self.end()
+    }
+}
+
+/// The regions of guest memory, in address order. Addresses in none of them
+/// are unmapped
+#[derive(Debug, Clone)]
+pub struct MemoryMap {
+    regions: Vec<Region>,
+}
+
+impl MemoryMap {
+    /// RAM from 0 to `size`, except for `rom`, where the executable ones of
+    /// `segments` are executable too
+    pub fn new(size: usize, rom: Option<(u32, u64)>, segments: &[Segment]) -> Self {
+        let mut map = Self {
+            regions: vec![Region {
+                start: 0,
+                len: size as u64,
+                kind: RegionKind::Ram,
+                perms: RW,
+            }],
+        };
+        if let Some((start, len)) = rom {
+            map.carve(Region {
+                start,
+                len,
+                kind: RegionKind::Rom,
+                perms: Perms {
+                    write: false,
+                    ..RWX
+                },
+            })
+            .expect("the text segment spans the end of the address space");
+        }
+        for seg in segments
+            .iter()
+            .filter(|seg| seg.executable && seg.size != 0)
+        {
+            let code = Region {
+                start: seg.vaddr as u32,
+                len: seg.size,
+                kind: RegionKind::Ram,
+                perms: RWX,
+            };
+            // the text segment is already ROM
+            if map.rom().is_some_and(|rom| overlaps(rom, &code)) {
+                continue;
+            }
+            map.carve(code)
+                .expect("an executable segment spans the end of the address space");
+        }
+        map
+    }
+
+    /// Makes ROM writable or not, as `Core32::set_writable_text` does
+    pub fn set_writable_text(&mut self, writable: bool) {
+        for region in &mut self.regions {
+            if region.kind == RegionKind::Rom {
+                region.perms.write = writable;
+            }
+        }
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    pub fn find(&self, addr: u32) -> Option<&Region> {
+        let idx = self
+            .regions
+            .partition_point(|region| region.end() <= addr as u64);
+        self.regions.get(idx).filter(|region| region.contains(addr))
+    }
+
+    /// The ROM, if there is any
+    pub fn rom(&self) -> Option<&Region> {
+        self.regions
+            .iter()
+            .find(|region| region.kind == RegionKind::Rom)
+    }
+
+    /// Where the lowest device window starts, if there are any
+    pub fn first_device(&self) -> Option<u32> {
+        self.regions
+            .iter()
+            .find(|region| matches!(region.kind, RegionKind::Device(_)))
+            .map(|region| region.start)
+    }
+
+    /// Adds a window for device `idx`, which may cover RAM but not ROM or
+    /// another device
+    pub fn add_device(&mut self, start: u32, len: u64, idx: usize) -> Result<(), String> {
+        self.carve(Region {
+            start,
+            len,
+            kind: RegionKind::Device(idx),
+            perms: RW,
+        })
+    }
+
+    // replaces whatever RAM is under `new` with it
+    fn carve(&mut self, new: Region) -> Result<(), String> {
+        if new.len == 0 || new.end() > 1 << 32 {
+            return Err(format!(
+                "{:#x}..{:#x} isn't a valid region",
+                new.start,
+                new.end()
+            ));
+        }
+        if let Some(clash) = self
+            .regions
+            .iter()
+            .find(|region| region.kind != RegionKind::Ram && overlaps(region, &new))
+        {
+            return Err(format!("{new} overlaps {clash}"));
+        }
+
+        let mut regions = Vec::with_capacity(self.regions.len() + 2);
+        for region in self.regions.drain(..) {
+            if !overlaps(&region, &new) {
+                regions.push(region);
+                continue;
+            }
+            if region.start < new.start {
+                regions.push(Region {
+                    len: (new.start - region.start) as u64,
+                    ..region
+                });
+            }
+            if region.end() > new.end() {
+                regions.push(Region {
+                    start: new.end() as u32,
+                    len: region.end() - new.end(),
+                    ..region
+                });
+            }
+        }
+        regions.push(new);
+        regions.sort_by_key(|region| region.start);
+        self.regions = regions;
+        Ok(())
+    }
+}
+
+fn overlaps(a: &Region, b: &Region) -> bool {
+    (a.start as u64) < b.end() && (b.start as u64) < a.end()
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            RegionKind::Rom => "rom".to_owned(),
+            RegionKind::Ram => "ram".to_owned(),
+            RegionKind::Device(idx) => format!("device {idx}"),
+        };
+        write!(f, "{kind} {:#010x}..{:#010x}", self.start, self.end())
+    }
+}
+
+impl fmt::Display for MemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "memory map:")?;
+        for region in &self.regions {
+            write!(f, "\n  {region}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(vaddr: u64, size: u64, executable: bool) -> Segment {
+        Segment {
+            offset: vaddr - 0x10000,
+            vaddr,
+            size,
+            data: vec![0; size as usize],
+            executable,
+            file: None,
+        }
+    }
+
+    fn perms(map: &MemoryMap, addr: u32) -> String {
+        map.find(addr).unwrap().perms.to_string()
+    }
+
+    #[test]
+    fn perms_follow_segments_and_writable_text() {
+        let segments = [
+            segment(0x10000, 0x1000, true),
+            segment(0x11000, 0x800, false),
+            // as `--load-extra` adds
+            segment(0x40000, 0x100, true),
+        ];
+        let mut map = MemoryMap::new(1 << 20, Some((0x10000, 0x1000)), &segments);
+
+        assert_eq!(perms(&map, 0x10000), "r-x");
+        assert_eq!(perms(&map, 0x11000), "rw-");
+        assert_eq!(perms(&map, 0x40000), "rwx");
+        assert_eq!(perms(&map, 0x400ff), "rwx");
+        assert_eq!(perms(&map, 0x40100), "rw-");
+
+        map.set_writable_text(true);
+        assert_eq!(perms(&map, 0x10000), "rwx");
+        assert_eq!(perms(&map, 0x40000), "rwx");
+
+        map.set_writable_text(false);
+        assert_eq!(perms(&map, 0x10000), "r-x");
+    }
+}
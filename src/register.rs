@@ -0,0 +1,173 @@
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Register {
+    Zero,     // x0
+    Ra,       // x1
+    Sp,       // x2
+    Gp,       // x3
+    Tp,       // x4
+    T(usize), // t registers: t0-t2 map to x5-x7, t3-t6 map to x28-x31
+    S(usize), // s registers: s0 maps to x8, s1 to x9, s2-s11 to x18-x27
+    A(usize), // a registers: a0-a7 map to x10-x17
+
+    Ft(usize), // ft registers: ft0-ft7 map to f0-f7, ft8-ft11 map to f28-f31
+    Fs(usize), // fs registers: fs0-fs1 map to f8-f9, fs2-fs11 map to f18-f27
+    Fa(usize), // fa registers: fa0-fa7 map to f10-f17
+}
+
+impl Register {
+    /// Index of the register within its own (integer or fp) register file
+    pub fn to_idx(self) -> u8 {
+        match self {
+            Register::Zero => 0,
+            Register::Ra => 1,
+            Register::Sp => 2,
+            Register::Gp => 3,
+            Register::Tp => 4,
+            Register::T(i) => match i {
+                0..=2 => i as u8 + 5,        // t0 => 5, t1 => 6, t2 => 7
+                3..=6 => (i as u8 - 3) + 28, // t3 => 28, ... t6 => 31
+                _ => unreachable!("invalid t register index"),
+            },
+            Register::S(i) => match i {
+                0 => 8,                 // s0/fp => 8
+                1 => 9,                 // s1 => 9
+                2..=11 => i as u8 + 16, // s2 => 18, ... s11 => 27
+                _ => unreachable!("invalid s register index"),
+            },
+            Register::A(i) => {
+                if i < 8 {
+                    i as u8 + 10 // a0 => 10, ... a7 => 17
+                } else {
+                    unreachable!("invalid a register index")
+                }
+            }
+
+            Register::Ft(i) => match i {
+                0..=7 => i as u8,             // ft0 => 0, ... ft7 => 7
+                8..=11 => (i as u8 - 8) + 28, // ft8 => 28, ... ft11 => 31
+                _ => unreachable!("invalid ft register index"),
+            },
+            Register::Fs(i) => match i {
+                0..=1 => i as u8 + 8,   // fs0 => 8, fs1 => 9
+                2..=11 => i as u8 + 16, // fs2 => 18, ... fs11 => 27
+                _ => unreachable!("invalid fs register index"),
+            },
+            Register::Fa(i) => {
+                if i < 8 {
+                    i as u8 + 10 // fa0 => 10, ... fa7 => 17
+                } else {
+                    unreachable!("invalid fa register index")
+                }
+            }
+        }
+    }
+
+    pub fn is_fp(self) -> bool {
+        matches!(self, Register::Ft(_) | Register::Fs(_) | Register::Fa(_))
+    }
+
+    /// The integer register `x{idx}`
+    pub fn gp(idx: u8) -> Self {
+        match idx {
+            0 => Register::Zero,
+            1 => Register::Ra,
+            2 => Register::Sp,
+            3 => Register::Gp,
+            4 => Register::Tp,
+            5..=7 => Register::T(idx as usize - 5),
+            8..=9 => Register::S(idx as usize - 8),
+            10..=17 => Register::A(idx as usize - 10),
+            18..=27 => Register::S(idx as usize - 16),
+            28..=31 => Register::T(idx as usize - 25),
+            _ => unreachable!("invalid x register index"),
+        }
+    }
+
+    /// The fp register `f{idx}`
+    pub fn fp(idx: u8) -> Self {
+        match idx {
+            0..=7 => Register::Ft(idx as usize),
+            8..=9 => Register::Fs(idx as usize - 8),
+            10..=17 => Register::Fa(idx as usize - 10),
+            18..=27 => Register::Fs(idx as usize - 16),
+            28..=31 => Register::Ft(idx as usize - 20),
+            _ => unreachable!("invalid f register index"),
+        }
+    }
+}
+
+/// Formats the ABI name (`a4`), or the numeric name (`x14`) with `{:#}`
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            let prefix = if self.is_fp() { "f" } else { "x" };
+            return write!(f, "{prefix}{}", self.to_idx());
+        }
+
+        match self {
+            Register::Zero => write!(f, "zero"),
+            Register::Ra => write!(f, "ra"),
+            Register::Sp => write!(f, "sp"),
+            Register::Gp => write!(f, "gp"),
+            Register::Tp => write!(f, "tp"),
+            Register::T(i) => write!(f, "t{i}"),
+            Register::S(i) => write!(f, "s{i}"),
+            Register::A(i) => write!(f, "a{i}"),
+            Register::Ft(i) => write!(f, "ft{i}"),
+            Register::Fs(i) => write!(f, "fs{i}"),
+            Register::Fa(i) => write!(f, "fa{i}"),
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    /// Accepts ABI names (`a0`, `fs3`, `fp`) as well as numeric ones (`x14`, `f3`)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("unknown register '{s}'");
+
+        let indexed = |prefix: &str, max: usize| -> Option<usize> {
+            let idx = s.strip_prefix(prefix)?;
+            // reject "a01" and friends
+            if idx.is_empty() || (idx.len() > 1 && idx.starts_with('0')) {
+                return None;
+            }
+            idx.parse().ok().filter(|&idx| idx <= max)
+        };
+
+        let reg = match s {
+            "zero" => Register::Zero,
+            "ra" => Register::Ra,
+            "sp" => Register::Sp,
+            "gp" => Register::Gp,
+            "tp" => Register::Tp,
+            "fp" => Register::S(0),
+            _ => {
+                if let Some(i) = indexed("x", 31) {
+                    Register::gp(i as u8)
+                } else if let Some(i) = indexed("ft", 11) {
+                    Register::Ft(i)
+                } else if let Some(i) = indexed("fs", 11) {
+                    Register::Fs(i)
+                } else if let Some(i) = indexed("fa", 7) {
+                    Register::Fa(i)
+                } else if let Some(i) = indexed("f", 31) {
+                    Register::fp(i as u8)
+                } else if let Some(i) = indexed("t", 6) {
+                    Register::T(i)
+                } else if let Some(i) = indexed("s", 11) {
+                    Register::S(i)
+                } else if let Some(i) = indexed("a", 7) {
+                    Register::A(i)
+                } else {
+                    return Err(err());
+                }
+            }
+        };
+
+        Ok(reg)
+    }
+}
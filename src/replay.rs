@@ -0,0 +1,143 @@
+//! Recording a guest's syscalls, and replaying them, for hermetic regression
+//! tests.
+//!
+//! `riscy --record-syscalls FILE` runs the guest as normal and writes a
+//! `SyscallTape` of every syscall it made: its number and arguments, the bytes
+//! it passed in (what it wrote, or the path it opened), what it returned and
+//! the bytes the host wrote back into guest memory (what it read, the time,
+//! random bytes). `riscy --replay-syscalls FILE` then serves each syscall from
+//! the tape instead of the host, so the run sees the same files, clocks and
+//! input as the recorded one wherever it happens, and nothing it does reaches
+//! the host. Its output is checked against the recording rather than written.
+//!
+//! A syscall that doesn't match the next one on the tape, in number, arguments
+//! or input, or one made after the tape runs out, stops the run with
+//! `StopReason::ReplayMismatch`. `exit` is recorded but always runs, and host
+//! calls aren't recorded at all, as they're the embedder's rather than the
+//! host's.
+
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use crate::checkpoint::{invalid, read_u32, write_u32};
+
+const MAGIC: &[u8; 8] = b"RSCYSYSC";
+const VERSION: u32 = 1;
+
+/// One completed syscall
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyscallRecord {
+    pub num: i32,
+    /// `a0` to `a5`
+    pub args: [u32; 6],
+    /// The guest memory the syscall read
+    pub input: Vec<u8>,
+    pub ret: i32,
+    /// What the syscall wrote to guest memory
+    pub output: Vec<u8>,
+}
+
+/// Every syscall of a run, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyscallTape {
+    pub records: Vec<SyscallRecord>,
+}
+
+impl SyscallTape {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = io::BufWriter::new(File::create(path)?);
+        self.write_to(&mut out)?;
+        out.flush()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::read_from(&mut io::BufReader::new(File::open(path)?))
+    }
+
+    fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        write_u32(out, VERSION)?;
+        write_u32(out, self.records.len() as u32)?;
+        for record in &self.records {
+            write_u32(out, record.num as u32)?;
+            for arg in record.args {
+                write_u32(out, arg)?;
+            }
+            write_bytes(out, &record.input)?;
+            write_u32(out, record.ret as u32)?;
+            write_bytes(out, &record.output)?;
+        }
+        Ok(())
+    }
+
+    fn read_from(input: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a riscy syscall recording"));
+        }
+
+        let version = read_u32(input)?;
+        if version != VERSION {
+            return Err(invalid(&format!(
+                "unsupported syscall recording version {version}"
+            )));
+        }
+
+        let count = read_u32(input)?;
+        let mut records = Vec::new();
+        for _ in 0..count {
+            let num = read_u32(input)? as i32;
+            let mut args = [0; 6];
+            for arg in &mut args {
+                *arg = read_u32(input)?;
+            }
+            let data = read_bytes(input)?;
+            let ret = read_u32(input)? as i32;
+            let output = read_bytes(input)?;
+            records.push(SyscallRecord {
+                num,
+                args,
+                input: data,
+                ret,
+                output,
+            });
+        }
+
+        Ok(SyscallTape { records })
+    }
+}
+
+/// What the core does with syscalls, when recording or replaying
+#[derive(Debug)]
+pub(crate) enum SyscallLog {
+    Record(SyscallTape),
+    Replay { tape: SyscallTape, next: usize },
+}
+
+impl SyscallLog {
+    pub(crate) fn tape(&self) -> &SyscallTape {
+        match self {
+            SyscallLog::Record(tape) | SyscallLog::Replay { tape, .. } => tape,
+        }
+    }
+}
+
+fn write_bytes(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    write_u32(out, bytes.len() as u32)?;
+    out.write_all(bytes)
+}
+
+fn read_bytes(input: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_u32(input)? as usize;
+    let mut bytes = Vec::new();
+    // read through `take`, so a corrupt length can't allocate gigabytes up front
+    input.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
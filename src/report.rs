@@ -0,0 +1,146 @@
+//! A machine-readable summary of a single run, for `riscy --report out.json`.
+//!
+//! The report is one JSON object, so CI can check how a run went without
+//! scraping stderr:
+//!
+//! ```json
+//! {
+//!   "schema": "riscy-run-report",
+//!   "version": 1,
+//!   "program": "hello.elf",
+//!   "reason": "exited",
+//!   "return_code": 0,
+//!   "instret": 18342,
+//!   "syscalls": [{"num": 64, "name": "write", "count": 1}],
+//!   "memory": {"size": 16777215, "resident": 73728},
+//!   "limit_hits": {"memory": 0, "fds": 0, "output": 0},
+//!   "duration_ms": 1.204
+//! }
+//! ```
+//!
+//! `reason` is as in `StopReason::name`, and is followed by `pc` when the
+//! reason has one, `fault` when an instruction stopped the run, with its
+//! `raw` encoding, `disassembly` and, if riscy panicked running it, `panic`,
+//! and `fatal` when the guest died. `resident` is how much of
+//! guest memory the host committed, which is also its high-water mark, as
+//! memory is never given back. `limit_hits` counts what the guest was refused
+//! by its `ResourceLimits`. Fields are only ever added within a version;
+//! `version` goes up when one changes meaning or goes away.
+
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+    time::Duration,
+};
+
+use crate::{
+    batch::json_str,
+    core::{Core32, Fault, MemReader, RunInfo, StopReason},
+    limits::LimitHits,
+    syscall,
+};
+
+pub const SCHEMA: &str = "riscy-run-report";
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone)]
+pub struct RunReport {
+    pub program: String,
+    pub reason: StopReason,
+    pub return_code: i32,
+    pub instret: u64,
+    pub fault: Option<Fault>,
+    pub fatal: Option<String>,
+    /// Calls to each syscall, by number
+    pub syscalls: BTreeMap<i32, u64>,
+    pub memory_size: usize,
+    /// `None` if the host couldn't say
+    pub resident: Option<usize>,
+    pub limit_hits: LimitHits,
+    pub duration: Duration,
+}
+
+impl RunReport {
+    /// The report for `core` having stopped with `info`, after running for
+    /// `duration`
+    pub fn new<Reader: MemReader<Idx = u32>>(
+        program: &str,
+        core: &Core32<Reader>,
+        info: &RunInfo,
+        duration: Duration,
+    ) -> Self {
+        Self {
+            program: program.to_string(),
+            reason: info.reason,
+            return_code: info.return_code,
+            instret: core.instret(),
+            fault: info.fault.clone(),
+            fatal: core.fatal().map(|fatal| fatal.to_string()),
+            syscalls: core.syscall_counts().clone(),
+            memory_size: core.memory().len(),
+            resident: core.resident_memory().ok(),
+            limit_hits: info.limit_hits,
+            duration,
+        }
+    }
+
+    pub fn write_json(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"schema\": {},", json_str(SCHEMA))?;
+        writeln!(out, "  \"version\": {VERSION},")?;
+        writeln!(out, "  \"program\": {},", json_str(&self.program))?;
+        writeln!(out, "  \"reason\": \"{}\",", self.reason.name())?;
+        if let Some(pc) = self.reason.pc() {
+            writeln!(out, "  \"pc\": {pc},")?;
+        }
+        if let Some(fault) = &self.fault {
+            let panic = fault.panic.as_deref().map_or(String::new(), |panic| {
+                format!(", \"panic\": {}", json_str(panic))
+            });
+            writeln!(
+                out,
+                "  \"fault\": {{\"pc\": {}, \"raw\": {}, \"disassembly\": {}{panic}}},",
+                fault.pc,
+                fault.raw,
+                json_str(&fault.disassembly)
+            )?;
+        }
+        if let Some(fatal) = &self.fatal {
+            writeln!(out, "  \"fatal\": {},", json_str(fatal))?;
+        }
+        writeln!(out, "  \"return_code\": {},", self.return_code)?;
+        writeln!(out, "  \"instret\": {},", self.instret)?;
+
+        let syscalls: Vec<_> = self
+            .syscalls
+            .iter()
+            .map(|(&num, &count)| {
+                let name =
+                    syscall::by_num(num).map_or("null".to_string(), |desc| json_str(desc.name));
+                format!("{{\"num\": {num}, \"name\": {name}, \"count\": {count}}}")
+            })
+            .collect();
+        writeln!(out, "  \"syscalls\": [{}],", syscalls.join(", "))?;
+
+        let resident = self
+            .resident
+            .map_or("null".to_string(), |resident| resident.to_string());
+        writeln!(
+            out,
+            "  \"memory\": {{\"size\": {}, \"resident\": {resident}}},",
+            self.memory_size
+        )?;
+        let hits = &self.limit_hits;
+        writeln!(
+            out,
+            "  \"limit_hits\": {{\"memory\": {}, \"fds\": {}, \"output\": {}}},",
+            hits.memory, hits.fds, hits.output
+        )?;
+        writeln!(
+            out,
+            "  \"duration_ms\": {:.3}",
+            self.duration.as_secs_f64() * 1e3
+        )?;
+        writeln!(out, "}}")
+    }
+}
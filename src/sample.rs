@@ -0,0 +1,242 @@
+//! A sampling profiler, cheap enough to leave on for hour-long runs.
+//!
+//! Rather than hooking every instruction, the core looks at the guest every so
+//! often, either every so many instructions or every so much host time, and
+//! records the call stack it's in. The stack is walked through frame pointers,
+//! so guests built with `-fno-omit-frame-pointer` get whole stacks and others
+//! get just the function they were in, plus whatever the walk could make out.
+//! Given a `CallStack`, which follows every call and return as a hook, the
+//! stacks are exact whatever the guest was built with, at the cost of the
+//! core's fast path.
+//!
+//! `Samples::write_folded` writes them out as folded stacks, one
+//! `outer;inner;leaf count` line each, which `flamegraph.pl` and `inferno`
+//! take as they are, and `flamegraph::write_svg` draws them.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    io::{self, Write},
+    ops::Range,
+    rc::Rc,
+    time::Instant,
+};
+
+use crate::{
+    hooks::{Hook, HookAction, Jump},
+    instruction::Instruction,
+    load::{self, Symbol},
+    progress::ProgressInterval,
+};
+
+// how many instructions to run between looking at the clock, for time
+// intervals
+const TIME_CHECK_INTERVAL: u64 = 1 << 16;
+
+// how deep a stack is walked or tracked, so a corrupt or runaway one can't
+// go on forever
+const MAX_DEPTH: usize = 256;
+
+/// The stacks seen, innermost frame first, and how often
+#[derive(Debug, Clone, Default)]
+pub struct Samples {
+    stacks: HashMap<Vec<u32>, u64>,
+    total: u64,
+}
+
+impl Samples {
+    /// How many samples were taken
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// The samples as folded stacks, outermost frame first and separated by
+    /// `;`, naming frames by the function they're in, or their address where
+    /// there isn't one
+    pub fn folded(&self, symbols: &[Symbol]) -> BTreeMap<String, u64> {
+        // stacks of different pcs in the same functions fold together
+        let mut folded = BTreeMap::<String, u64>::new();
+        for (stack, &count) in &self.stacks {
+            let frames: Vec<_> = stack
+                .iter()
+                .rev()
+                .map(|&pc| match load::symbolize(symbols, pc as u64) {
+                    Some((name, _)) => name.to_string(),
+                    None => format!("{pc:#x}"),
+                })
+                .collect();
+            *folded.entry(frames.join(";")).or_insert(0) += count;
+        }
+        folded
+    }
+
+    /// Writes the samples as folded stacks, one per line with its count
+    pub fn write_folded(&self, symbols: &[Symbol], out: &mut impl Write) -> io::Result<()> {
+        for (stack, count) in self.folded(symbols) {
+            writeln!(out, "{stack} {count}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    call_pc: u32,
+    ret: u32,
+}
+
+/// The guest's call stack, followed call by call. Clones share the stack, so
+/// one can be given to `Core32::set_sampling` and the other to
+/// `Core32::add_hook`
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    frames: Rc<RefCell<Vec<Frame>>>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The stack at `pc`, innermost first, with each caller at its call
+    fn at(&self, pc: u32) -> Vec<u32> {
+        let frames = self.frames.borrow();
+        let callers = frames.iter().rev().map(|frame| frame.call_pc);
+        [pc].into_iter().chain(callers).collect()
+    }
+}
+
+impl Hook for CallStack {
+    fn before_instruction(&mut self, pc: u32, _instr: &Instruction) -> HookAction {
+        // returned from a call the core serviced natively, without a `ret`
+        let mut frames = self.frames.borrow_mut();
+        if frames.last().is_some_and(|frame| frame.ret == pc) {
+            frames.pop();
+        }
+        HookAction::Continue
+    }
+
+    fn wants_reg_writes(&self) -> bool {
+        false
+    }
+
+    fn wants_jumps(&self) -> bool {
+        true
+    }
+
+    fn on_jump(&mut self, jump: &Jump) -> HookAction {
+        let mut frames = self.frames.borrow_mut();
+        match jump.instr {
+            // past the limit the outermost frames are the ones kept
+            Instruction::Jal { rd: 1, .. } | Instruction::Jalr { rd: 1, .. }
+                if frames.len() < MAX_DEPTH =>
+            {
+                frames.push(Frame {
+                    call_pc: jump.pc,
+                    ret: jump.pc.wrapping_add(4),
+                });
+            }
+            // `ret`, to wherever on the stack it goes, so a `longjmp` unwinds
+            // it too
+            Instruction::Jalr {
+                rd: 0,
+                rs1: 1,
+                imm: 0,
+            } => {
+                if let Some(idx) = frames.iter().rposition(|frame| frame.ret == jump.target) {
+                    frames.truncate(idx);
+                }
+            }
+            _ => {}
+        }
+        HookAction::Continue
+    }
+}
+
+pub(crate) struct Sampler {
+    interval: ProgressInterval,
+    last_time: Instant,
+    last_instret: u64,
+    call_stack: Option<CallStack>,
+    pub(crate) samples: Samples,
+}
+
+impl Sampler {
+    pub(crate) fn new(
+        interval: ProgressInterval,
+        instret: u64,
+        call_stack: Option<CallStack>,
+    ) -> Self {
+        Self {
+            interval,
+            last_time: Instant::now(),
+            last_instret: instret,
+            call_stack,
+            samples: Samples::default(),
+        }
+    }
+
+    /// The stack at `pc` as the `CallStack` has it, if there is one
+    pub(crate) fn call_stack(&self, pc: u32) -> Option<Vec<u32>> {
+        self.call_stack.as_ref().map(|stack| stack.at(pc))
+    }
+
+    /// The instret at which `due` should next be called
+    pub(crate) fn next_check(&self, instret: u64) -> u64 {
+        match self.interval {
+            ProgressInterval::Time(_) => instret + TIME_CHECK_INTERVAL,
+            ProgressInterval::Instructions(count) => self.last_instret + count,
+        }
+    }
+
+    /// Whether a sample is due, resetting the interval if so
+    pub(crate) fn due(&mut self, instret: u64) -> bool {
+        let due = match self.interval {
+            ProgressInterval::Time(every) => self.last_time.elapsed() >= every,
+            ProgressInterval::Instructions(count) => instret - self.last_instret >= count,
+        };
+        if due {
+            self.last_time = Instant::now();
+            self.last_instret = instret;
+        }
+        due
+    }
+
+    pub(crate) fn record(&mut self, stack: Vec<u32>) {
+        *self.samples.stacks.entry(stack).or_insert(0) += 1;
+        self.samples.total += 1;
+    }
+}
+
+/// The call stack at `pc`, innermost first, walked from frame pointer `fp`
+/// through guest `memory`. A frame keeps the return address at `fp - 4` and the
+/// caller's frame pointer at `fp - 8`, and the walk stops at the first frame
+/// that doesn't look like one: a return address outside `text`, or a frame
+/// pointer that isn't further up the stack
+pub(crate) fn walk_stack(pc: u32, sp: u32, fp: u32, memory: &[u8], text: Range<u32>) -> Vec<u32> {
+    let read = |addr: u32| -> Option<u32> {
+        let bytes = memory.get(addr as usize..addr as usize + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+    };
+
+    let mut stack = vec![pc];
+    let mut fp = fp;
+    let mut below = sp;
+    while stack.len() < MAX_DEPTH {
+        if fp <= below || !fp.is_multiple_of(4) {
+            break;
+        }
+        let (Some(ra), Some(next)) = (read(fp.wrapping_sub(4)), read(fp.wrapping_sub(8))) else {
+            break;
+        };
+        if !text.contains(&ra) {
+            break;
+        }
+        // the return address is the instruction after the call, which may be
+        // the first of the next function
+        stack.push(ra.wrapping_sub(4));
+        below = fp;
+        fp = next;
+    }
+    stack
+}
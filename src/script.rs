@@ -0,0 +1,323 @@
+//! Instrumentation scripts, written in rhai.
+//!
+//! `riscy --script hooks.rhai` runs the script's top level once before the
+//! guest starts, then calls whichever of these functions it defines:
+//!
+//! - `on_instruction(guest, text)` before each instruction
+//! - `on_mem_read(guest, addr, len)` and `on_mem_write(guest, addr, len)`
+//!   before each load or store, or syscall or native call touching memory
+//! - `on_syscall(guest, num, name)` before each `ecall`
+//! - `on_call(guest, target, name)` before each call, `name` being the
+//!   function's symbol if `target` is one
+//! - `on_exit(code)` once the run has stopped
+//!
+//! Any of the first five can stop the run by returning `true`, which reports
+//! a breakpoint. `guest` gives access to the registers and memory:
+//! `guest.pc`, `guest.reg("a0")`, `guest.read_u8/16/32(addr)`,
+//! `guest.read_str(addr)` and `guest.symbol(addr)`. Memory is read as plain
+//! memory, so device windows show whatever is underneath them.
+//!
+//! Top-level variables are kept between calls, so they can hold counts and
+//! the like. `print` and `debug` go to stderr, to stay out of the guest's
+//! output. Like every hook, a script takes the core off its fast path.
+
+use std::{cell::RefCell, path::Path, rc::Rc};
+
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Scope, AST, INT};
+
+use crate::{
+    core::{Core32, MemReader, RunInfo},
+    hooks::{Hook, HookAction, Jump, MemRead, MemWrite, RegWrite, Syscall},
+    instruction::Instruction,
+    load::{self, Symbol},
+    register::Register,
+    syscall,
+};
+
+// the longest string `read_str` will look for a NUL in
+const MAX_STR: usize = 4096;
+
+/// The guest as a script sees it, through the core's state before the
+/// instruction being reported
+#[derive(Clone)]
+struct Guest(Rc<RefCell<GuestState>>);
+
+struct GuestState {
+    pc: u32,
+    // integer registers then fp registers, kept up to date by `on_reg_write`
+    regs: [u64; 64],
+    memory: *const u8,
+    memory_len: usize,
+    symbols: Vec<Symbol>,
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+impl Guest {
+    fn pc(&mut self) -> INT {
+        self.0.borrow().pc as INT
+    }
+
+    /// Integer registers as unsigned, fp registers as their raw bits
+    fn reg(&mut self, name: &str) -> ScriptResult<INT> {
+        let reg: Register = name.parse()?;
+        let idx = reg.to_idx() as usize + if reg.is_fp() { 32 } else { 0 };
+        Ok(self.0.borrow().regs[idx] as INT)
+    }
+
+    fn read<const N: usize>(&mut self, addr: INT) -> ScriptResult<[u8; N]> {
+        let state = self.0.borrow();
+        let bytes = state.bytes(addr, N)?;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn read_u8(&mut self, addr: INT) -> ScriptResult<INT> {
+        Ok(u8::from_le_bytes(self.read(addr)?) as INT)
+    }
+
+    fn read_u16(&mut self, addr: INT) -> ScriptResult<INT> {
+        Ok(u16::from_le_bytes(self.read(addr)?) as INT)
+    }
+
+    fn read_u32(&mut self, addr: INT) -> ScriptResult<INT> {
+        Ok(u32::from_le_bytes(self.read(addr)?) as INT)
+    }
+
+    /// The NUL-terminated string at `addr`
+    fn read_str(&mut self, addr: INT) -> ScriptResult<String> {
+        let state = self.0.borrow();
+        let start = state.offset(addr)?;
+        let bytes = state.bytes(addr, (state.memory_len - start).min(MAX_STR))?;
+        let len = bytes
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| format!("no string at {addr:#x}"))?;
+        Ok(String::from_utf8_lossy(&bytes[..len]).into_owned())
+    }
+
+    fn symbol(&mut self, addr: INT) -> String {
+        load::describe(&self.0.borrow().symbols, addr as u32)
+    }
+
+    fn sync<Reader: MemReader<Idx = u32>>(&self, core: &Core32<Reader>) {
+        let mut state = self.0.borrow_mut();
+        state.pc = core.pc();
+        for (reg, value) in state.regs[..32].iter_mut().zip(core.gp_regs()) {
+            *reg = value as u32 as u64;
+        }
+        state.regs[32..].copy_from_slice(&core.fp_regs());
+    }
+}
+
+impl GuestState {
+    fn offset(&self, addr: INT) -> ScriptResult<usize> {
+        usize::try_from(addr)
+            .ok()
+            .filter(|&offset| offset < self.memory_len)
+            .ok_or_else(|| format!("address {addr:#x} is outside guest memory").into())
+    }
+
+    fn bytes(&self, addr: INT, len: usize) -> ScriptResult<&[u8]> {
+        let offset = self.offset(addr)?;
+        if offset + len > self.memory_len {
+            return Err(format!("{len} bytes at {addr:#x} run past guest memory").into());
+        }
+        // SAFETY: the arena is mapped for as long as the core, and the script
+        // only runs as one of the core's hooks or from `Script::finish`, which
+        // borrows the core
+        Ok(unsafe { std::slice::from_raw_parts(self.memory.add(offset), len) })
+    }
+}
+
+struct ScriptState {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    guest: Guest,
+}
+
+impl ScriptState {
+    fn defines(&self, name: &str) -> bool {
+        self.ast.iter_functions().any(|func| func.name == name)
+    }
+
+    fn call(&mut self, name: &str, args: impl rhai::FuncArgs) -> HookAction {
+        let options = CallFnOptions::new().eval_ast(false).rewind_scope(false);
+        match self.engine.call_fn_with_options::<Dynamic>(
+            options,
+            &mut self.scope,
+            &self.ast,
+            name,
+            args,
+        ) {
+            Ok(ret) if ret.as_bool() == Ok(true) => HookAction::Break,
+            Ok(_) => HookAction::Continue,
+            Err(err) => {
+                eprintln!("script: {name}: {err}");
+                HookAction::Break
+            }
+        }
+    }
+}
+
+/// A loaded script, run as a hook. Clones share the script, so one can be
+/// kept to call `finish` after the other is given to `Core32::add_hook`
+#[derive(Clone)]
+pub struct Script {
+    state: Rc<RefCell<ScriptState>>,
+    // which callbacks the script defines
+    on_instruction: bool,
+    on_mem_read: bool,
+    on_mem_write: bool,
+    on_syscall: bool,
+    on_call: bool,
+}
+
+impl Script {
+    /// Compiles the script at `path` and runs its top level. The registers are
+    /// taken from `core` as they are, so it should be loaded once the core is
+    /// ready to run
+    pub fn load<Reader: MemReader<Idx = u32>>(
+        path: &Path,
+        core: &Core32<Reader>,
+        symbols: Vec<Symbol>,
+    ) -> Result<Self, String> {
+        let mut engine = Engine::new();
+        engine.on_print(|text| eprintln!("{text}"));
+        engine.on_debug(|text, _, pos| eprintln!("{pos:?}: {text}"));
+        engine
+            .register_type_with_name::<Guest>("Guest")
+            .register_get("pc", Guest::pc)
+            .register_fn("reg", Guest::reg)
+            .register_fn("read_u8", Guest::read_u8)
+            .register_fn("read_u16", Guest::read_u16)
+            .register_fn("read_u32", Guest::read_u32)
+            .register_fn("read_str", Guest::read_str)
+            .register_fn("symbol", Guest::symbol);
+
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+        let mut scope = Scope::new();
+        engine
+            .run_ast_with_scope(&mut scope, &ast)
+            .map_err(|err| format!("{}: {err}", path.display()))?;
+
+        let memory = core.memory();
+        let guest = Guest(Rc::new(RefCell::new(GuestState {
+            pc: core.pc(),
+            regs: [0; 64],
+            memory: memory.as_ptr(),
+            memory_len: memory.len(),
+            symbols,
+        })));
+        let state = ScriptState {
+            engine,
+            ast,
+            scope,
+            guest,
+        };
+        state.guest.sync(core);
+
+        Ok(Self {
+            on_instruction: state.defines("on_instruction"),
+            on_mem_read: state.defines("on_mem_read"),
+            on_mem_write: state.defines("on_mem_write"),
+            on_syscall: state.defines("on_syscall"),
+            on_call: state.defines("on_call"),
+            state: Rc::new(RefCell::new(state)),
+        })
+    }
+
+    /// Calls the script's `on_exit`, if it has one, with the guest as the run
+    /// left it
+    pub fn finish<Reader: MemReader<Idx = u32>>(&self, core: &Core32<Reader>, info: &RunInfo) {
+        let mut state = self.state.borrow_mut();
+        if !state.defines("on_exit") {
+            return;
+        }
+        state.guest.sync(core);
+        state.call("on_exit", (info.return_code as INT,));
+    }
+
+    fn call(&self, name: &str, args: impl rhai::FuncArgs) -> HookAction {
+        self.state.borrow_mut().call(name, args)
+    }
+
+    fn guest(&self) -> Guest {
+        self.state.borrow().guest.clone()
+    }
+}
+
+impl Hook for Script {
+    fn before_instruction(&mut self, pc: u32, instr: &Instruction) -> HookAction {
+        let guest = self.guest();
+        guest.0.borrow_mut().pc = pc;
+        if !self.on_instruction {
+            return HookAction::Continue;
+        }
+        self.call("on_instruction", (guest, instr.to_string()))
+    }
+
+    // the registers are shadowed from the writes, rather than read from the core
+    fn on_reg_write(&mut self, write: &RegWrite) -> HookAction {
+        let idx = write.reg.to_idx() as usize + if write.reg.is_fp() { 32 } else { 0 };
+        self.guest().0.borrow_mut().regs[idx] = write.new;
+        HookAction::Continue
+    }
+
+    fn wants_syscalls(&self) -> bool {
+        self.on_syscall
+    }
+
+    fn on_syscall(&mut self, syscall: &Syscall) -> HookAction {
+        let name = syscall::by_num(syscall.num).map_or("", |desc| desc.name);
+        self.call(
+            "on_syscall",
+            (self.guest(), syscall.num as INT, name.to_owned()),
+        )
+    }
+
+    fn wants_mem_reads(&self) -> bool {
+        self.on_mem_read
+    }
+
+    fn on_mem_read(&mut self, read: &MemRead) -> HookAction {
+        self.call(
+            "on_mem_read",
+            (self.guest(), read.addr as INT, read.len as INT),
+        )
+    }
+
+    fn wants_mem_writes(&self) -> bool {
+        self.on_mem_write
+    }
+
+    fn on_mem_write(&mut self, write: &MemWrite) -> HookAction {
+        self.call(
+            "on_mem_write",
+            (self.guest(), write.addr as INT, write.len as INT),
+        )
+    }
+
+    fn wants_jumps(&self) -> bool {
+        self.on_call
+    }
+
+    fn on_jump(&mut self, jump: &Jump) -> HookAction {
+        // only `jal ra, ...` and `jalr ra, ...` are calls
+        if jump.instr.gp_dest() != Some(Register::Ra.to_idx()) {
+            return HookAction::Continue;
+        }
+
+        let guest = self.guest();
+        let name = guest
+            .0
+            .borrow()
+            .symbols
+            .iter()
+            .find(|sym| sym.addr as u32 == jump.target)
+            .map_or_else(String::new, |sym| sym.name.clone());
+        self.call("on_call", (guest, jump.target as INT, name))
+    }
+}
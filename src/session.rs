@@ -0,0 +1,315 @@
+//! A machine held between runs and driven over a unix socket, for
+//! `riscy serve`, so a test framework or notebook can make many short
+//! executions without paying for a process each.
+//!
+//! The daemon takes one client at a time. The machine, and any snapshots,
+//! outlive the connection, so a client can come back to them. Each request is
+//! a line, and each reply a line of `ok` and what was asked for, or `err` and
+//! why not. Numbers are in hex with `0x` or decimal, and bytes in hex:
+//!
+//! ```text
+//! load PATH [ARG...]   load a program, with PATH ARG... as its argv    ok ENTRY
+//! run [N]              run until it stops, or N more instructions      ok REASON pc PC instret N [exit CODE]
+//! read ADDR LEN        read guest memory                               ok BYTES
+//! write ADDR BYTES     write guest memory                              ok
+//! reg NAME [VALUE]     read a register, or write VALUE to it           ok VALUE
+//! stdin BYTES          give the guest input                            ok
+//! eof                  end the guest's input                           ok
+//! output               what the guest wrote since last asked           ok STDOUT STDERR
+//! snapshot NAME        keep the machine's state as NAME                ok
+//! restore NAME         go back to snapshot NAME                        ok
+//! quit                 close the connection
+//! shutdown             stop the daemon
+//! ```
+//!
+//! REASON is `StopReason::name`. The guest's stdin is a `GuestPipe`, so a read
+//! with no input given stops the run with `blocked`, and running again
+//! once there is retries it. Its output is kept for `output` rather than
+//! written anywhere, with empty bytes written as `-`.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+};
+
+use tracing::{info, warn};
+
+use crate::{
+    checkpoint::Snapshot,
+    core::{Core32, MemReader, StopReason},
+    driver::GuestPipe,
+    load::LoadedElf,
+    register::Register,
+};
+
+struct Machine<Reader: MemReader<Idx = u32>> {
+    core: Core32<Reader>,
+    stdin: GuestPipe,
+    exited: bool,
+    // how much of the guest's stdout and stderr `output` has given
+    taken: (usize, usize),
+}
+
+/// A machine and its snapshots, see the module docs
+pub struct Session<Reader: MemReader<Idx = u32>> {
+    size: usize,
+    machine: Option<Machine<Reader>>,
+    snapshots: BTreeMap<String, Snapshot>,
+}
+
+/// What a request leaves the connection to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Next {
+    Continue,
+    Quit,
+    Shutdown,
+}
+
+impl<Reader: MemReader<Idx = u32>> Session<Reader> {
+    /// A session loading programs into `size` bytes of memory
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            machine: None,
+            snapshots: BTreeMap::new(),
+        }
+    }
+
+    /// Serves clients on `path` until one asks for `shutdown`
+    pub fn serve(&mut self, path: &Path) -> io::Result<()> {
+        // a socket left behind by a previous daemon
+        let _ = std::fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+        info!(target: "session", "listening on {}", path.display());
+
+        for stream in listener.incoming() {
+            match self.connection(stream?) {
+                Ok(Next::Shutdown) => {
+                    let _ = std::fs::remove_file(path);
+                    return Ok(());
+                }
+                Ok(_) => {}
+                Err(err) => warn!(target: "session", "client went away: {err}"),
+            }
+        }
+        Ok(())
+    }
+
+    // serves a client until it goes or asks to
+    fn connection(&mut self, stream: UnixStream) -> io::Result<Next> {
+        let mut out = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let (next, reply) = match self.handle(&line?) {
+                Ok((next, reply)) => (next, reply),
+                Err(err) => (Next::Continue, format!("err {err}")),
+            };
+            match next {
+                Next::Continue => writeln!(out, "{reply}")?,
+                Next::Quit => return Ok(next),
+                Next::Shutdown => {
+                    // stopping whether or not the client hears it
+                    let _ = writeln!(out, "ok");
+                    return Ok(next);
+                }
+            }
+        }
+        Ok(Next::Quit)
+    }
+
+    /// Carries out one request, returning the reply
+    pub fn handle(&mut self, line: &str) -> Result<(Next, String), String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let reply = match words.as_slice() {
+            [] => return Err("empty request".to_string()),
+            ["quit"] => return Ok((Next::Quit, String::new())),
+            ["shutdown"] => return Ok((Next::Shutdown, String::new())),
+            ["load", path, args @ ..] => self.load(path, args)?,
+            ["run"] => self.run(None)?,
+            ["run", count] => self.run(Some(parse_u64(count)?))?,
+            ["read", addr, len] => {
+                let addr = parse_u64(addr)? as usize;
+                let len = parse_u64(len)? as usize;
+                let memory = self.machine()?.core.memory();
+                let bytes = memory
+                    .get(addr..addr.saturating_add(len))
+                    .ok_or_else(|| format!("{addr:#x}+{len:#x} is outside guest memory"))?;
+                format!("ok {}", hex(bytes))
+            }
+            ["write", addr, bytes] => {
+                let addr = u32::try_from(parse_u64(addr)?)
+                    .map_err(|_| format!("{addr} is outside guest memory"))?;
+                self.machine_mut()?
+                    .core
+                    .write_memory(addr, &unhex(bytes)?)?;
+                "ok".to_string()
+            }
+            ["reg", name] => {
+                let reg = parse_reg(name)?;
+                let core = &self.machine()?.core;
+                let value = if reg.is_fp() {
+                    core.fp_regs()[reg.to_idx() as usize]
+                } else {
+                    core.read(reg) as u32 as u64
+                };
+                format!("ok {value:#x}")
+            }
+            ["reg", name, value] => {
+                let reg = parse_reg(name)?;
+                if reg.is_fp() {
+                    return Err(format!("can't write {reg}, only integer registers"));
+                }
+                let value = u32::try_from(parse_u64(value)?)
+                    .map_err(|_| format!("{value} doesn't fit in {reg}"))?;
+                self.machine_mut()?.core.write(reg, value as i32);
+                format!("ok {value:#x}")
+            }
+            ["stdin", bytes] => {
+                self.machine()?.stdin.write(&unhex(bytes)?);
+                "ok".to_string()
+            }
+            ["eof"] => {
+                self.machine()?.stdin.close();
+                "ok".to_string()
+            }
+            ["output"] => self.output()?,
+            ["snapshot", name] => {
+                let mut snapshot = self.machine()?.core.snapshot();
+                // the guest's output is kept, so there's nothing of the host's
+                // to put back
+                snapshot.fd_offsets.clear();
+                self.snapshots.insert(name.to_string(), snapshot);
+                "ok".to_string()
+            }
+            ["restore", name] => {
+                let snapshot = self
+                    .snapshots
+                    .get(*name)
+                    .ok_or_else(|| format!("no snapshot '{name}'"))?;
+                let machine = self.machine.as_mut().ok_or("no program loaded")?;
+                machine.core.restore(snapshot)?;
+                machine.exited = false;
+                "ok".to_string()
+            }
+            [command, ..] => return Err(format!("unknown request '{command}'")),
+        };
+        Ok((Next::Continue, reply))
+    }
+
+    fn machine(&self) -> Result<&Machine<Reader>, String> {
+        self.machine
+            .as_ref()
+            .ok_or_else(|| "no program loaded".to_string())
+    }
+
+    fn machine_mut(&mut self) -> Result<&mut Machine<Reader>, String> {
+        self.machine
+            .as_mut()
+            .ok_or_else(|| "no program loaded".to_string())
+    }
+
+    fn load(&mut self, path: &str, args: &[&str]) -> Result<String, String> {
+        let elf = LoadedElf::load(path).map_err(|err| format!("{path}: {err}"))?;
+        elf.check_runnable()
+            .map_err(|err| format!("{path}: {err}"))?;
+
+        let mut core = Core32::<Reader>::new(elf, None, self.size, false);
+        let stdin = GuestPipe::new();
+        core.set_stdin_pipe(stdin.clone());
+        core.capture_output();
+        let argv: Vec<&str> = std::iter::once(path).chain(args.iter().copied()).collect();
+        core.set_args(&argv)?;
+
+        let entry = core.pc();
+        self.machine = Some(Machine {
+            core,
+            stdin,
+            exited: false,
+            taken: (0, 0),
+        });
+        // they're of the program replaced
+        self.snapshots.clear();
+        Ok(format!("ok {entry:#x}"))
+    }
+
+    fn run(&mut self, count: Option<u64>) -> Result<String, String> {
+        let machine = self.machine_mut()?;
+        if machine.exited {
+            return Err("the program has exited, restore a snapshot or load it again".to_string());
+        }
+
+        let core = &mut machine.core;
+        core.set_instruction_limit(count.map(|count| core.instret().saturating_add(count)));
+        let info = core.run();
+        core.set_instruction_limit(None);
+
+        let mut reply = format!(
+            "ok {} pc {:#x} instret {}",
+            info.reason.name(),
+            core.pc(),
+            core.instret()
+        );
+        if info.reason == StopReason::Exited {
+            machine.exited = true;
+            let _ = write!(reply, " exit {}", info.return_code);
+        }
+        Ok(reply)
+    }
+
+    fn output(&mut self) -> Result<String, String> {
+        let machine = self.machine_mut()?;
+        let (stdout, stderr) = (
+            machine.core.captured_stdout(),
+            machine.core.captured_stderr(),
+        );
+        let (stdout_taken, stderr_taken) = &mut machine.taken;
+        let reply = format!(
+            "ok {} {}",
+            hex(&stdout[*stdout_taken..]),
+            hex(&stderr[*stderr_taken..])
+        );
+        *stdout_taken = stdout.len();
+        *stderr_taken = stderr.len();
+        Ok(reply)
+    }
+}
+
+fn parse_u64(s: &str) -> Result<u64, String> {
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| format!("invalid number '{s}'"))
+}
+
+fn parse_reg(s: &str) -> Result<Register, String> {
+    s.parse().map_err(|_| format!("unknown register '{s}'"))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "-".to_string();
+    }
+    bytes.iter().fold(String::new(), |mut out, byte| {
+        let _ = write!(out, "{byte:02x}");
+        out
+    })
+}
+
+fn unhex(s: &str) -> Result<Vec<u8>, String> {
+    if s == "-" {
+        return Ok(Vec::new());
+    }
+    let invalid = || format!("invalid bytes '{s}', expected hex");
+    if !s.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|at| {
+            u8::from_str_radix(s.get(at..at + 2).ok_or_else(invalid)?, 16).map_err(|_| invalid())
+        })
+        .collect()
+}
@@ -0,0 +1,65 @@
+//! Test signatures, for running the RISC-V architecture tests.
+//!
+//! A riscv-arch-test program stores its results between its `begin_signature`
+//! and `end_signature` symbols, and RISCOF compares that region against a
+//! reference model's. `riscy --signature FILE` dumps it once the run stops, as
+//! every model does: one line per `granularity` bytes, in hex, the most
+//! significant digit first. The RISCOF plugin in `riscof/` runs riscy this way.
+
+use std::{
+    io::{self, Write},
+    ops::Range,
+};
+
+use crate::load::Symbol;
+
+pub const BEGIN_SIGNATURE: &str = "begin_signature";
+pub const END_SIGNATURE: &str = "end_signature";
+
+/// Where the signature is, from the program's symbols
+pub fn find(symbols: &[Symbol]) -> Result<Range<u32>, String> {
+    let addr = |name| {
+        symbols
+            .iter()
+            .find(|sym| sym.name == name)
+            .map(|sym| sym.addr as u32)
+            .ok_or_else(|| format!("no '{name}' symbol"))
+    };
+
+    let (begin, end) = (addr(BEGIN_SIGNATURE)?, addr(END_SIGNATURE)?);
+    if end < begin {
+        return Err(format!(
+            "signature ends at {end:#x}, before it begins at {begin:#x}"
+        ));
+    }
+    Ok(begin..end)
+}
+
+/// Writes the signature in `range` of guest `memory`, `granularity` bytes to a
+/// line. A last partial line is padded with zeroes
+pub fn write_signature(
+    memory: &[u8],
+    range: Range<u32>,
+    granularity: usize,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let signature = memory
+        .get(range.start as usize..range.end as usize)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "signature {:#x}..{:#x} is outside guest memory",
+                    range.start, range.end
+                ),
+            )
+        })?;
+
+    for line in signature.chunks(granularity) {
+        for byte in (0..granularity).rev() {
+            write!(out, "{:02x}", line.get(byte).copied().unwrap_or(0))?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
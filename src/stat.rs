@@ -0,0 +1,113 @@
+//! The guest's `struct stat`, and overriding what it says.
+//!
+//! `fstat` and `newfstatat` fill in the layout riscv-pk and libgloss use,
+//! asm-generic's `struct stat` with 64-bit fields, which is the same for rv32
+//! as rv64 with a 64-bit `time_t`. Whatever the host says about the file goes
+//! into it, unless a `StatSpoof` (`riscy --spoof-stat mtime=0,uid=0`) pins
+//! some of it down: fixed times make tools that embed them, like archivers,
+//! give the same output from one run to the next, and fixed owners and
+//! permissions hide who built it.
+
+use std::str::FromStr;
+
+/// The size of the guest's `struct stat`
+pub const STAT_SIZE: u32 = 128;
+
+/// Fields of every `struct stat` the guest sees to replace with a fixed value,
+/// parsed from `FIELD=VALUE,...`: `atime`, `mtime` and `ctime` in seconds since
+/// the epoch, `time` for all three, `uid`, `gid`, and `mode` for the permission
+/// bits in octal
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatSpoof {
+    pub atime: Option<i64>,
+    pub mtime: Option<i64>,
+    pub ctime: Option<i64>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub mode: Option<u32>,
+}
+
+impl FromStr for StatSpoof {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut spoof = StatSpoof::default();
+
+        for part in s.split(',').filter(|part| !part.is_empty()) {
+            let (name, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("expected FIELD=VALUE, got '{part}'"))?;
+            let invalid = || format!("invalid value '{value}' for {name}");
+
+            match name {
+                "atime" | "mtime" | "ctime" | "time" => {
+                    let secs = value.parse().map_err(|_| invalid())?;
+                    if matches!(name, "atime" | "time") {
+                        spoof.atime = Some(secs);
+                    }
+                    if matches!(name, "mtime" | "time") {
+                        spoof.mtime = Some(secs);
+                    }
+                    if matches!(name, "ctime" | "time") {
+                        spoof.ctime = Some(secs);
+                    }
+                }
+                "uid" => spoof.uid = Some(value.parse().map_err(|_| invalid())?),
+                "gid" => spoof.gid = Some(value.parse().map_err(|_| invalid())?),
+                "mode" => {
+                    let mode = u32::from_str_radix(value, 8)
+                        .ok()
+                        .filter(|&mode| mode <= 0o7777)
+                        .ok_or_else(invalid)?;
+                    spoof.mode = Some(mode);
+                }
+                _ => {
+                    return Err(format!(
+                        "unknown stat field '{name}', expected one of atime, mtime, ctime, \
+                         time, uid, gid, mode"
+                    ))
+                }
+            }
+        }
+
+        Ok(spoof)
+    }
+}
+
+/// `st` in the guest's layout, with `spoof` applied
+pub(crate) fn encode(st: &libc::stat, spoof: &StatSpoof) -> [u8; STAT_SIZE as usize] {
+    let mode = match spoof.mode {
+        Some(perm) => (st.st_mode & libc::S_IFMT) | perm,
+        None => st.st_mode,
+    };
+    // a spoofed time is a whole second
+    let time = |spoofed: Option<i64>, secs: i64, nsecs: i64| match spoofed {
+        Some(secs) => (secs, 0),
+        None => (secs, nsecs),
+    };
+    let (atime, atime_ns) = time(spoof.atime, st.st_atime, st.st_atime_nsec);
+    let (mtime, mtime_ns) = time(spoof.mtime, st.st_mtime, st.st_mtime_nsec);
+    let (ctime, ctime_ns) = time(spoof.ctime, st.st_ctime, st.st_ctime_nsec);
+
+    let mut buf = [0; STAT_SIZE as usize];
+    let mut put = |offset: usize, bytes: &[u8]| {
+        buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+    };
+    put(0, &st.st_dev.to_le_bytes());
+    put(8, &st.st_ino.to_le_bytes());
+    put(16, &mode.to_le_bytes());
+    put(20, &(st.st_nlink as u32).to_le_bytes());
+    put(24, &spoof.uid.unwrap_or(st.st_uid).to_le_bytes());
+    put(28, &spoof.gid.unwrap_or(st.st_gid).to_le_bytes());
+    put(32, &st.st_rdev.to_le_bytes());
+    put(48, &st.st_size.to_le_bytes());
+    put(56, &(st.st_blksize as i32).to_le_bytes());
+    put(64, &st.st_blocks.to_le_bytes());
+    put(72, &atime.to_le_bytes());
+    put(80, &(atime_ns as u64).to_le_bytes());
+    put(88, &mtime.to_le_bytes());
+    put(96, &(mtime_ns as u64).to_le_bytes());
+    put(104, &ctime.to_le_bytes());
+    put(112, &(ctime_ns as u64).to_le_bytes());
+    buf
+}
@@ -0,0 +1,64 @@
+//! Replacing guest functions with simple host behaviour, for `riscy --stub`.
+//!
+//! A stubbed function is never run: calls to it are serviced by the core, like
+//! those to `memcpy`, and go straight back to the caller. `return:VALUE` sets
+//! `a0` to `VALUE`, `ignore` leaves every register as it was, for functions
+//! returning nothing, and `fail` stops the run as a guest fatal error, for
+//! functions that shouldn't be reached at all. Only calls are caught, not tail
+//! calls jumping into the function.
+
+use std::str::FromStr;
+
+/// What a call to a stubbed function does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StubAction {
+    Return(u32),
+    Ignore,
+    Fail,
+}
+
+impl FromStr for StubAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid stub action '{s}', expected return:VALUE, ignore or fail");
+
+        match s {
+            "ignore" => Ok(StubAction::Ignore),
+            "fail" => Ok(StubAction::Fail),
+            _ => {
+                let value = s.strip_prefix("return:").ok_or_else(err)?;
+                let value = match value.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => value
+                        .parse::<u32>()
+                        .ok()
+                        .or_else(|| value.parse::<i32>().ok().map(|value| value as u32)),
+                };
+                value.map(StubAction::Return).ok_or_else(err)
+            }
+        }
+    }
+}
+
+/// A `--stub` argument: `FUNCTION=ACTION`
+#[derive(Debug, Clone)]
+pub struct StubSpec {
+    pub function: String,
+    pub action: StubAction,
+}
+
+impl FromStr for StubSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (function, action) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid stub '{s}', expected FUNCTION=ACTION"))?;
+
+        Ok(StubSpec {
+            function: function.to_string(),
+            action: action.parse()?,
+        })
+    }
+}
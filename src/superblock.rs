@@ -0,0 +1,488 @@
+//! Superblocks: hot paths through the code, run without going back to the
+//! core's dispatch loop after every instruction.
+//!
+//! The core counts how often each address is jumped to. Once one has been
+//! `HOT` times, a superblock is formed from there: the instructions in order,
+//! through unconditional jumps and through conditional branches in the
+//! direction they're expected to go, up to an indirect jump, a call, a
+//! syscall, a branch with no clear bias, a jump back to the start or `MAX_LEN`
+//! instructions. A branch is first expected to go backward-taken,
+//! forward-not-taken.
+//!
+//! Running one, the core executes each instruction exactly as it would have
+//! stepping, but only makes its between-instruction checks once, on entry, and
+//! after each instruction only checks the pc is the next one in the
+//! superblock. If it isn't, that's a side exit, and the branch's direction is
+//! noted. A superblock that side exits more than a quarter of the time is
+//! dropped and formed again with the directions seen, ending at any branch seen
+//! going both ways, so it follows the program as its branches' bias changes.
+//! Changing the code drops them all.
+//!
+//! A superblock ending in an indirect jump, a `jalr` making a virtual call or
+//! returning, keeps an inline cache of where it went last and the superblock
+//! there. Going there again, the core runs that superblock next without
+//! looking the target up; going anywhere else, it looks it up and caches what
+//! it finds. `Core32::inline_cache_stats` counts how often the cache was right.
+//!
+//! Superblocks can outlive the run that formed them: `Core32::save_superblocks`
+//! (`riscy --superblock-cache FILE`) writes each one's addresses, with a hash
+//! of the code there, and the branch directions seen, and
+//! `Core32::load_superblocks` forms them straight away on the next run, so
+//! hot paths don't wait to be counted again. A file saved from another
+//! program, by GNU build id or failing that a hash of its code, is refused.
+//! Any superblock whose code has changed since, as a program patched without
+//! a new build id would have, is dropped on its own.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::Path,
+    rc::Rc,
+};
+
+use crate::{
+    checkpoint::{invalid, read_u32, read_u64, write_u32, write_u64},
+    csr,
+    instruction::Instruction,
+};
+
+const MAGIC: &[u8; 8] = b"RSCYSBLK";
+const VERSION: u32 = 1;
+
+/// How many times an address is jumped to before a superblock is formed there
+pub const HOT: u32 = 64;
+/// The most instructions in a superblock
+pub const MAX_LEN: usize = 128;
+// shorter ones cost more to enter than they save
+const MIN_LEN: usize = 4;
+// entries before side exits are judged
+const MIN_ENTRIES: u32 = 16;
+
+// in `heads`, a formed superblock's index, or never to try again
+const FORMED: u32 = 1 << 31;
+const NEVER: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Bias {
+    Taken,
+    NotTaken,
+    Unbiased,
+}
+
+impl Bias {
+    fn encode(self) -> u32 {
+        match self {
+            Bias::Taken => 0,
+            Bias::NotTaken => 1,
+            Bias::Unbiased => 2,
+        }
+    }
+
+    fn decode(bits: u32) -> io::Result<Self> {
+        match bits {
+            0 => Ok(Bias::Taken),
+            1 => Ok(Bias::NotTaken),
+            2 => Ok(Bias::Unbiased),
+            _ => Err(invalid(&format!("invalid branch bias {bits}"))),
+        }
+    }
+}
+
+/// Superblocks as saved to a file, see the module docs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Saved {
+    pub(crate) build_id: Option<Vec<u8>>,
+    /// A hash of the program's code, as loaded
+    pub(crate) program: u64,
+    /// Each superblock's instructions' addresses, and a hash of their words
+    pub(crate) blocks: Vec<(Vec<u32>, u64)>,
+    pub(crate) bias: Vec<(u32, Bias)>,
+}
+
+impl Saved {
+    pub(crate) fn save(&self, path: &Path) -> io::Result<()> {
+        // written aside and renamed over, as a snapshot is
+        let tmp = path.with_extension("tmp");
+        let mut out = io::BufWriter::new(File::create(&tmp)?);
+        self.write_to(&mut out)?;
+        out.into_inner()?.sync_all()?;
+        fs::rename(&tmp, path)
+    }
+
+    pub(crate) fn load(path: &Path) -> io::Result<Self> {
+        Self::read_from(&mut io::BufReader::new(File::open(path)?))
+    }
+
+    fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        write_u32(out, VERSION)?;
+        let build_id = self.build_id.as_deref().unwrap_or_default();
+        write_u32(out, build_id.len() as u32)?;
+        out.write_all(build_id)?;
+        write_u64(out, self.program)?;
+
+        write_u32(out, self.blocks.len() as u32)?;
+        for (pcs, hash) in &self.blocks {
+            write_u32(out, pcs.len() as u32)?;
+            for &pc in pcs {
+                write_u32(out, pc)?;
+            }
+            write_u64(out, *hash)?;
+        }
+
+        write_u32(out, self.bias.len() as u32)?;
+        for &(pc, bias) in &self.bias {
+            write_u32(out, pc)?;
+            write_u32(out, bias.encode())?;
+        }
+        Ok(())
+    }
+
+    fn read_from(input: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a riscy superblock cache"));
+        }
+
+        let version = read_u32(input)?;
+        if version != VERSION {
+            return Err(invalid(&format!(
+                "unsupported superblock cache version {version}"
+            )));
+        }
+
+        let len = read_u32(input)? as usize;
+        let mut build_id = vec![0; len];
+        input.read_exact(&mut build_id)?;
+        let program = read_u64(input)?;
+
+        let mut blocks = Vec::new();
+        for _ in 0..read_u32(input)? {
+            let len = read_u32(input)?;
+            if len as usize > MAX_LEN {
+                return Err(invalid(&format!("superblock of {len} instructions")));
+            }
+            let pcs = (0..len)
+                .map(|_| read_u32(input))
+                .collect::<io::Result<_>>()?;
+            blocks.push((pcs, read_u64(input)?));
+        }
+
+        let mut bias = Vec::new();
+        for _ in 0..read_u32(input)? {
+            let pc = read_u32(input)?;
+            bias.push((pc, Bias::decode(read_u32(input)?)?));
+        }
+
+        Ok(Saved {
+            build_id: (!build_id.is_empty()).then_some(build_id),
+            program,
+            blocks,
+            bias,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Superblock {
+    /// Each instruction, and where it is
+    pub(crate) instrs: Rc<[(u32, Instruction)]>,
+    head: usize,
+    entries: u32,
+    side_exits: u32,
+    /// Whether it ends in an indirect jump
+    indirect: bool,
+    inline_cache: Option<InlineCache>,
+}
+
+// where a superblock's indirect jump went last, and the superblock there
+#[derive(Debug, Clone, Copy)]
+struct InlineCache {
+    target: u32,
+    block: usize,
+}
+
+/// How a superblock's indirect jump went, see `Superblocks::predict`
+pub(crate) enum Prediction {
+    /// To where it went last, with the superblock there
+    Hit(usize),
+    Miss,
+    /// It doesn't end in one
+    Direct,
+}
+
+/// How often superblocks' inline caches predicted their indirect jumps, see
+/// `Core32::inline_cache_stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InlineCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl InlineCacheStats {
+    /// The fraction of indirect jumps predicted, or 0 if there were none
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+/// What the core does on jumping to an address
+pub(crate) enum Entry {
+    Cold,
+    Form,
+    Run(usize),
+}
+
+#[derive(Debug)]
+pub(crate) struct Superblocks {
+    // by instruction index: how often it's been jumped to, `FORMED` with the
+    // superblock starting there, or `NEVER`
+    heads: Vec<u32>,
+    blocks: Vec<Superblock>,
+    // branch directions seen on side exits, by pc
+    bias: HashMap<u32, Bias>,
+    pub(crate) stats: InlineCacheStats,
+}
+
+impl Superblocks {
+    pub(crate) fn new(len: usize) -> Self {
+        Self {
+            heads: vec![0; len],
+            blocks: Vec::new(),
+            bias: HashMap::new(),
+            stats: InlineCacheStats::default(),
+        }
+    }
+
+    /// Counts a jump to the instruction at `idx`
+    #[inline(always)]
+    pub(crate) fn jumped_to(&mut self, idx: usize) -> Entry {
+        let Some(head) = self.heads.get_mut(idx) else {
+            return Entry::Cold;
+        };
+        match *head {
+            NEVER => Entry::Cold,
+            formed if formed & FORMED != 0 => Entry::Run((formed & !FORMED) as usize),
+            _ => {
+                *head += 1;
+                if *head >= HOT {
+                    Entry::Form
+                } else {
+                    Entry::Cold
+                }
+            }
+        }
+    }
+
+    /// Forms the superblock starting at `pc`, the instruction at `idx`, with
+    /// `instr_at` the instruction at an address, if it's code
+    pub(crate) fn form(
+        &mut self,
+        idx: usize,
+        pc: u32,
+        instr_at: impl Fn(u32) -> Option<Instruction>,
+    ) -> Option<usize> {
+        let mut instrs = Vec::new();
+        let mut at = pc;
+        while instrs.len() < MAX_LEN {
+            let Some(instr) = instr_at(at) else {
+                break;
+            };
+            instrs.push((at, instr));
+
+            let next = match successor(at, instr) {
+                Successor::Next => at.wrapping_add(4),
+                Successor::Jump(target) => target,
+                Successor::Branch(target) => {
+                    let guess = if target <= at {
+                        Bias::Taken
+                    } else {
+                        Bias::NotTaken
+                    };
+                    match self.bias.get(&at).copied().unwrap_or(guess) {
+                        Bias::Taken => target,
+                        Bias::NotTaken => at.wrapping_add(4),
+                        Bias::Unbiased => break,
+                    }
+                }
+                Successor::End => break,
+            };
+            if next == pc {
+                break;
+            }
+            at = next;
+        }
+
+        if instrs.len() < MIN_LEN {
+            self.heads[idx] = NEVER;
+            return None;
+        }
+
+        let indirect = matches!(instrs.last(), Some((_, Instruction::Jalr { .. })));
+        let block = self.blocks.len();
+        self.blocks.push(Superblock {
+            instrs: instrs.into(),
+            head: idx,
+            entries: 0,
+            side_exits: 0,
+            indirect,
+            inline_cache: None,
+        });
+        self.heads[idx] = FORMED | block as u32;
+        Some(block)
+    }
+
+    pub(crate) fn instrs(&mut self, block: usize) -> Rc<[(u32, Instruction)]> {
+        let block = &mut self.blocks[block];
+        block.entries += 1;
+        block.instrs.clone()
+    }
+
+    /// Predicts the superblock `block` continues into, having run to its end
+    /// and jumped to `target`
+    #[inline(always)]
+    pub(crate) fn predict(&mut self, block: usize, target: u32) -> Prediction {
+        let sb = &self.blocks[block];
+        if !sb.indirect {
+            return Prediction::Direct;
+        }
+        match sb.inline_cache {
+            // and it hasn't been dropped to be formed again since
+            Some(cache)
+                if cache.target == target
+                    && self.heads[self.blocks[cache.block].head] == FORMED | cache.block as u32 =>
+            {
+                self.stats.hits += 1;
+                Prediction::Hit(cache.block)
+            }
+            _ => {
+                self.stats.misses += 1;
+                Prediction::Miss
+            }
+        }
+    }
+
+    /// Caches `next` as the superblock at `target`, where `block`'s indirect
+    /// jump went
+    pub(crate) fn cache(&mut self, block: usize, target: u32, next: usize) {
+        self.blocks[block].inline_cache = Some(InlineCache {
+            target,
+            block: next,
+        });
+    }
+
+    /// Notes that `block` left early after the instruction at `at`, for `to`
+    pub(crate) fn side_exit(&mut self, block: usize, at: usize, to: u32) {
+        let sb = &mut self.blocks[block];
+        sb.side_exits += 1;
+
+        let (pc, instr) = sb.instrs[at];
+        if let Successor::Branch(_) = successor(pc, instr) {
+            let seen = if to == pc.wrapping_add(4) {
+                Bias::NotTaken
+            } else {
+                Bias::Taken
+            };
+            let bias = self.bias.entry(pc).or_insert(seen);
+            if *bias != seen {
+                *bias = Bias::Unbiased;
+            }
+        }
+
+        if sb.entries >= MIN_ENTRIES && sb.side_exits * 4 > sb.entries {
+            // counted afresh, to be formed again with what's been seen
+            self.heads[sb.head] = 0;
+            sb.entries = 0;
+            sb.side_exits = 0;
+        }
+    }
+
+    /// The addresses of each superblock still in use, and every branch
+    /// direction seen, to save
+    pub(crate) fn saved(&self) -> (Vec<Vec<u32>>, Vec<(u32, Bias)>) {
+        let blocks = self
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|&(block, sb)| self.heads[sb.head] == FORMED | block as u32)
+            .map(|(_, sb)| sb.instrs.iter().map(|&(pc, _)| pc).collect())
+            .collect();
+        let mut bias: Vec<_> = self.bias.iter().map(|(&pc, &bias)| (pc, bias)).collect();
+        bias.sort_unstable_by_key(|&(pc, _)| pc);
+        (blocks, bias)
+    }
+
+    /// Puts back a saved superblock, of `instrs` starting at the instruction at
+    /// `idx`, unless one's already been formed there
+    pub(crate) fn restore(&mut self, idx: usize, instrs: Vec<(u32, Instruction)>) -> bool {
+        let Some(&head) = self.heads.get(idx) else {
+            return false;
+        };
+        if head & FORMED != 0 || instrs.len() < MIN_LEN {
+            return false;
+        }
+
+        let indirect = matches!(instrs.last(), Some((_, Instruction::Jalr { .. })));
+        let block = self.blocks.len();
+        self.blocks.push(Superblock {
+            instrs: instrs.into(),
+            head: idx,
+            entries: 0,
+            side_exits: 0,
+            indirect,
+            inline_cache: None,
+        });
+        self.heads[idx] = FORMED | block as u32;
+        true
+    }
+
+    /// Puts back a saved branch direction
+    pub(crate) fn restore_bias(&mut self, pc: u32, bias: Bias) {
+        self.bias.insert(pc, bias);
+    }
+
+    /// Drops every superblock, as the code has changed
+    pub(crate) fn clear(&mut self) {
+        self.heads.fill(0);
+        self.blocks.clear();
+        self.bias.clear();
+    }
+}
+
+enum Successor {
+    Next,
+    Jump(u32),
+    Branch(u32),
+    End,
+}
+
+// where control goes after `instr`, as far as is known before it runs
+fn successor(pc: u32, instr: Instruction) -> Successor {
+    match instr {
+        Instruction::Beq { imm, .. }
+        | Instruction::Bne { imm, .. }
+        | Instruction::Blt { imm, .. }
+        | Instruction::Bge { imm, .. }
+        | Instruction::Bltu { imm, .. }
+        | Instruction::Bgeu { imm, .. } => Successor::Branch(pc.wrapping_add(imm as u32)),
+        // calls go through the core's interception of well known functions
+        Instruction::Jal { rd: 0, imm } => Successor::Jump(pc.wrapping_add(imm as u32)),
+        Instruction::Jal { .. }
+        | Instruction::Jalr { .. }
+        | Instruction::Ecall
+        | Instruction::Ebreak
+        | Instruction::FenceI
+        | Instruction::Custom { .. }
+        | Instruction::Mret
+        | Instruction::Unknown(_) => Successor::End,
+        // enabling an interrupt lets it in straight away, but the core only
+        // checks for one between superblocks
+        _ if matches!(instr.csr(), Some(csr::MSTATUS | csr::MIE | csr::MIP)) => Successor::End,
+        _ => Successor::Next,
+    }
+}
@@ -0,0 +1,171 @@
+//! Names and arguments of the syscalls the core emulates, for anything that
+//! reports or matches on syscalls.
+
+use crate::{
+    core::{
+        SYSCALL_BRK, SYSCALL_CHDIR, SYSCALL_CLOCK_GETTIME, SYSCALL_CLOCK_GETTIME64, SYSCALL_CLOSE,
+        SYSCALL_EXIT, SYSCALL_FSTAT, SYSCALL_GETCWD, SYSCALL_GETDENTS64, SYSCALL_GETRANDOM,
+        SYSCALL_GETRUSAGE, SYSCALL_GETTIMEOFDAY, SYSCALL_IOCTL, SYSCALL_MKDIRAT,
+        SYSCALL_NEWFSTATAT, SYSCALL_OPENAT, SYSCALL_PRLIMIT64, SYSCALL_READ, SYSCALL_RENAMEAT,
+        SYSCALL_RENAMEAT2, SYSCALL_SYSINFO, SYSCALL_UNAME, SYSCALL_WRITE,
+    },
+    hostcall::SYSCALL_HOSTCALL,
+};
+
+#[derive(Debug)]
+pub struct SyscallDesc {
+    pub num: i32,
+    pub name: &'static str,
+    /// The names of the arguments it takes from `a0` up
+    pub args: &'static [&'static str],
+}
+
+const SYSCALLS: &[SyscallDesc] = &[
+    SyscallDesc {
+        num: SYSCALL_READ,
+        name: "read",
+        args: &["fd", "buf", "count"],
+    },
+    SyscallDesc {
+        num: SYSCALL_WRITE,
+        name: "write",
+        args: &["fd", "buf", "count"],
+    },
+    SyscallDesc {
+        num: SYSCALL_OPENAT,
+        name: "openat",
+        args: &["dirfd", "path", "flags", "mode"],
+    },
+    SyscallDesc {
+        num: SYSCALL_CLOSE,
+        name: "close",
+        args: &["fd"],
+    },
+    SyscallDesc {
+        num: SYSCALL_GETCWD,
+        name: "getcwd",
+        args: &["buf", "size"],
+    },
+    SyscallDesc {
+        num: SYSCALL_CHDIR,
+        name: "chdir",
+        args: &["path"],
+    },
+    SyscallDesc {
+        num: SYSCALL_MKDIRAT,
+        name: "mkdirat",
+        args: &["dirfd", "path", "mode"],
+    },
+    SyscallDesc {
+        num: SYSCALL_RENAMEAT,
+        name: "renameat",
+        args: &["olddirfd", "oldpath", "newdirfd", "newpath"],
+    },
+    SyscallDesc {
+        num: SYSCALL_RENAMEAT2,
+        name: "renameat2",
+        args: &["olddirfd", "oldpath", "newdirfd", "newpath", "flags"],
+    },
+    SyscallDesc {
+        num: SYSCALL_GETDENTS64,
+        name: "getdents64",
+        args: &["fd", "dirp", "count"],
+    },
+    SyscallDesc {
+        num: SYSCALL_IOCTL,
+        name: "ioctl",
+        args: &["fd", "request", "arg"],
+    },
+    SyscallDesc {
+        num: SYSCALL_NEWFSTATAT,
+        name: "newfstatat",
+        args: &["dirfd", "path", "statbuf", "flags"],
+    },
+    SyscallDesc {
+        num: SYSCALL_FSTAT,
+        name: "fstat",
+        args: &["fd", "statbuf"],
+    },
+    SyscallDesc {
+        num: SYSCALL_EXIT,
+        name: "exit",
+        args: &["status"],
+    },
+    SyscallDesc {
+        num: SYSCALL_CLOCK_GETTIME,
+        name: "clock_gettime",
+        args: &["clockid", "tp"],
+    },
+    SyscallDesc {
+        num: SYSCALL_GETTIMEOFDAY,
+        name: "gettimeofday",
+        args: &["tv", "tz"],
+    },
+    SyscallDesc {
+        num: SYSCALL_UNAME,
+        name: "uname",
+        args: &["buf"],
+    },
+    SyscallDesc {
+        num: SYSCALL_GETRUSAGE,
+        name: "getrusage",
+        args: &["who", "usage"],
+    },
+    SyscallDesc {
+        num: SYSCALL_SYSINFO,
+        name: "sysinfo",
+        args: &["info"],
+    },
+    SyscallDesc {
+        num: SYSCALL_PRLIMIT64,
+        name: "prlimit64",
+        args: &["pid", "resource", "new_limit", "old_limit"],
+    },
+    SyscallDesc {
+        num: SYSCALL_BRK,
+        name: "brk",
+        args: &["addr"],
+    },
+    SyscallDesc {
+        num: SYSCALL_GETRANDOM,
+        name: "getrandom",
+        args: &["buf", "buflen", "flags"],
+    },
+    SyscallDesc {
+        num: SYSCALL_CLOCK_GETTIME64,
+        name: "clock_gettime64",
+        args: &["clockid", "tp"],
+    },
+    SyscallDesc {
+        num: SYSCALL_HOSTCALL,
+        name: "hostcall",
+        args: &["a0", "a1", "a2", "a3", "a4", "a5"],
+    },
+];
+
+pub fn by_num(num: i32) -> Option<&'static SyscallDesc> {
+    SYSCALLS.iter().find(|desc| desc.num == num)
+}
+
+pub fn by_name(name: &str) -> Option<&'static SyscallDesc> {
+    SYSCALLS.iter().find(|desc| desc.name == name)
+}
+
+/// A syscall and its arguments as it'd be written in C, e.g.
+/// `write(fd=0x1, buf=0x11200, count=0xd)`; unknown syscalls get all six
+pub fn describe(num: i32, args: &[u32; 6]) -> String {
+    let (name, names) = match by_num(num) {
+        Some(desc) => (desc.name.to_owned(), desc.args),
+        None => (
+            format!("syscall {num}"),
+            &["a0", "a1", "a2", "a3", "a4", "a5"][..],
+        ),
+    };
+
+    let args: Vec<_> = names
+        .iter()
+        .zip(args)
+        .map(|(name, value)| format!("{name}={value:#x}"))
+        .collect();
+    format!("{name}({})", args.join(", "))
+}
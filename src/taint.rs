@@ -0,0 +1,332 @@
+//! Byte-granular taint tracking.
+//!
+//! `TaintTracker` marks data from its sources as tainted and follows it as the
+//! guest computes with it, byte by byte in memory and whole registers at a
+//! time. It reports when tainted data is used as a jump target, passed to or
+//! written out by a syscall, or passed to one of the sink functions.
+//!
+//! Only data flow is followed: a result is tainted if any operand was (bar the
+//! `xor`/`sub` of a register with itself idiom), and branching on tainted data
+//! taints nothing. Sinks only see their argument registers, not what they
+//! point to.
+
+use std::{collections::HashSet, str::FromStr};
+
+use crate::{
+    core::SYSCALL_READ,
+    hooks::{Hook, HookAction, Jump, MemRead, MemWrite, Syscall},
+    instruction::Instruction,
+    load::{self, Symbol},
+    syscall,
+};
+
+/// Where tainted data comes from, parsed from `stdin`, `fd:N` or `mem:ADDR:LEN`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaintSource {
+    /// Everything the guest reads from the fd
+    Fd(i32),
+    /// The initial contents of a range of guest memory
+    Memory { addr: u32, len: u32 },
+}
+
+impl FromStr for TaintSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let num = |s: &str| match s.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => s.parse(),
+        };
+        let err = || format!("invalid taint source '{s}', expected stdin, fd:N or mem:ADDR:LEN");
+
+        if s == "stdin" {
+            return Ok(TaintSource::Fd(0));
+        }
+
+        if let Some(fd) = s.strip_prefix("fd:") {
+            return fd.parse().map(TaintSource::Fd).map_err(|_| err());
+        }
+
+        let (addr, len) = s
+            .strip_prefix("mem:")
+            .and_then(|range| range.split_once(':'))
+            .ok_or_else(err)?;
+
+        Ok(TaintSource::Memory {
+            addr: num(addr).map_err(|_| err())?,
+            len: num(len).map_err(|_| err())?,
+        })
+    }
+}
+
+pub struct TaintTracker {
+    symbols: Vec<Symbol>,
+    fds: Vec<i32>,
+    sinks: Vec<(u32, String)>,
+
+    memory: HashSet<u32>,
+    gp: [bool; 32],
+    fp: [bool; 32],
+
+    // the syscall being made, so `on_mem_write` knows what it's writing
+    syscall: Option<Syscall>,
+    // the taint of each byte read by the current instruction, for copies
+    read: Vec<bool>,
+    // the taint of `rs2` for an atomic, as the load into `rd` may replace it
+    // before `on_mem_write`
+    atomic_src: bool,
+    // only the first report at each pc is printed
+    reported: HashSet<u32>,
+}
+
+impl TaintTracker {
+    /// `sinks` are function names, which must all be in `symbols`
+    pub fn new(
+        sources: &[TaintSource],
+        sinks: &[String],
+        symbols: Vec<Symbol>,
+    ) -> Result<Self, String> {
+        let sinks = sinks
+            .iter()
+            .map(|name| {
+                symbols
+                    .iter()
+                    .find(|sym| &sym.name == name)
+                    .map(|sym| (sym.addr as u32, name.clone()))
+                    .ok_or_else(|| format!("taint sink '{name}' not found"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut tracker = Self {
+            symbols,
+            fds: Vec::new(),
+            sinks,
+            memory: HashSet::new(),
+            gp: [false; 32],
+            fp: [false; 32],
+            syscall: None,
+            read: Vec::new(),
+            atomic_src: false,
+            reported: HashSet::new(),
+        };
+
+        for &source in sources {
+            match source {
+                TaintSource::Fd(fd) => tracker.fds.push(fd),
+                TaintSource::Memory { addr, len } => tracker.set_memory(addr, len, true),
+            }
+        }
+
+        Ok(tracker)
+    }
+
+    fn set_memory(&mut self, addr: u32, len: u32, tainted: bool) {
+        for byte in (0..len).map(|offset| addr.wrapping_add(offset)) {
+            if tainted {
+                self.memory.insert(byte);
+            } else {
+                self.memory.remove(&byte);
+            }
+        }
+    }
+
+    fn set_gp(&mut self, reg: u8, tainted: bool) {
+        if reg != 0 {
+            self.gp[reg as usize] = tainted;
+        }
+    }
+
+    fn report(&mut self, pc: u32, what: &str) {
+        if self.reported.insert(pc) {
+            eprintln!("taint: {what} at {}", load::describe(&self.symbols, pc));
+        }
+    }
+}
+
+fn is_load(instr: &Instruction) -> bool {
+    matches!(
+        instr,
+        Instruction::Lb { .. }
+            | Instruction::Lh { .. }
+            | Instruction::Lw { .. }
+            | Instruction::Lbu { .. }
+            | Instruction::Lhu { .. }
+            | Instruction::Flw { .. }
+            | Instruction::Fld { .. }
+    )
+}
+
+// how many of `a0`-`a5` the syscalls riscy emulates take
+fn syscall_args(num: i32) -> usize {
+    syscall::by_num(num).map_or(6, |desc| desc.args.len())
+}
+
+impl Hook for TaintTracker {
+    fn before_instruction(&mut self, _pc: u32, instr: &Instruction) -> HookAction {
+        self.read.clear();
+
+        match *instr {
+            // loads, stores and syscalls are handled by the memory and syscall hooks
+            _ if is_load(instr) => {}
+            Instruction::Sb { .. }
+            | Instruction::Sh { .. }
+            | Instruction::Sw { .. }
+            | Instruction::Fsw { .. }
+            | Instruction::Fsd { .. }
+            | Instruction::Ecall => {}
+            _ if instr.is_atomic() => {
+                let [_, rs2, _] = instr.gp_sources();
+                self.atomic_src = rs2.is_some_and(|rs2| self.gp[rs2 as usize]);
+                // an `sc.w` writes its success, not anything loaded
+                if let Instruction::ScW { rd, .. } = *instr {
+                    self.set_gp(rd, false);
+                }
+            }
+
+            Instruction::Jal { rd, .. } | Instruction::Jalr { rd, .. } => self.set_gp(rd, false),
+            Instruction::Xor { rd, rs1, rs2 } | Instruction::Sub { rd, rs1, rs2 } if rs1 == rs2 => {
+                self.set_gp(rd, false)
+            }
+            _ if instr.csr().is_some() => self.set_gp(instr.gp_dest().unwrap(), false),
+
+            _ => {
+                let tainted = instr
+                    .gp_sources()
+                    .into_iter()
+                    .flatten()
+                    .any(|reg| self.gp[reg as usize])
+                    || instr
+                        .fp_sources()
+                        .into_iter()
+                        .flatten()
+                        .any(|reg| self.fp[reg as usize]);
+
+                if let Some(rd) = instr.gp_dest() {
+                    self.set_gp(rd, tainted);
+                }
+                if let Some((rd, _)) = instr.fp_dest() {
+                    self.fp[rd as usize] = tainted;
+                }
+            }
+        }
+
+        HookAction::Continue
+    }
+
+    fn wants_reg_writes(&self) -> bool {
+        false
+    }
+
+    fn wants_syscalls(&self) -> bool {
+        true
+    }
+
+    fn on_syscall(&mut self, syscall: &Syscall) -> HookAction {
+        let tainted = (0..syscall_args(syscall.num)).find(|&n| self.gp[10 + n]);
+        if let Some(n) = tainted {
+            self.report(
+                syscall.pc,
+                &format!("tainted argument a{n} to syscall {}", syscall.num),
+            );
+        }
+
+        self.syscall = Some(*syscall);
+        // replaced by the result
+        self.set_gp(10, false);
+
+        HookAction::Continue
+    }
+
+    fn wants_mem_reads(&self) -> bool {
+        true
+    }
+
+    fn on_mem_read(&mut self, read: &MemRead) -> HookAction {
+        self.read = (0..read.len)
+            .map(|offset| self.memory.contains(&read.addr.wrapping_add(offset)))
+            .collect();
+        let tainted = self.read.contains(&true);
+
+        if is_load(&read.instr) || read.instr.is_atomic() {
+            if let Some(rd) = read.instr.gp_dest() {
+                self.set_gp(rd, tainted);
+            }
+            if let Some((rd, _)) = read.instr.fp_dest() {
+                self.fp[rd as usize] = tainted;
+            }
+        } else if matches!(read.instr, Instruction::Ecall) && tainted {
+            let fd = self.syscall.map_or(-1, |syscall| syscall.args[0] as i32);
+            self.report(read.pc, &format!("tainted data written to fd {fd}"));
+        }
+
+        HookAction::Continue
+    }
+
+    fn wants_mem_writes(&self) -> bool {
+        true
+    }
+
+    fn on_mem_write(&mut self, write: &MemWrite) -> HookAction {
+        let tainted = match write.instr {
+            Instruction::Sb { rs2, .. }
+            | Instruction::Sh { rs2, .. }
+            | Instruction::Sw { rs2, .. } => self.gp[rs2 as usize],
+            Instruction::Fsw { rs2, .. } | Instruction::Fsd { rs2, .. } => self.fp[rs2 as usize],
+            Instruction::ScW { .. }
+            | Instruction::AmoswapW { .. }
+            | Instruction::AmocasW { .. } => self.atomic_src,
+            // the rest combine the old word with `rs2`
+            _ if write.instr.is_atomic() => self.atomic_src || self.read.contains(&true),
+            Instruction::Ecall => self.syscall.is_some_and(|syscall| {
+                syscall.num == SYSCALL_READ && self.fds.contains(&(syscall.args[0] as i32))
+            }),
+            // a natively serviced `memcpy`/`memmove` copies the taint along
+            // with the data, `memset` fills with the taint of its value
+            _ if self.read.len() == write.len as usize => {
+                for (offset, tainted) in std::mem::take(&mut self.read).into_iter().enumerate() {
+                    self.set_memory(write.addr.wrapping_add(offset as u32), 1, tainted);
+                }
+                return HookAction::Continue;
+            }
+            _ => self.gp[11],
+        };
+
+        self.set_memory(write.addr, write.len, tainted);
+        HookAction::Continue
+    }
+
+    fn wants_jumps(&self) -> bool {
+        true
+    }
+
+    fn on_jump(&mut self, jump: &Jump) -> HookAction {
+        if let Instruction::Jalr { rs1, .. } = jump.instr {
+            if self.gp[rs1 as usize] {
+                self.report(
+                    jump.pc,
+                    &format!("jump to tainted address {:#x}", jump.target),
+                );
+            }
+        }
+
+        let is_call = matches!(
+            jump.instr,
+            Instruction::Jal { rd: 1, .. } | Instruction::Jalr { rd: 1, .. }
+        );
+        let sink = self
+            .sinks
+            .iter()
+            .find(|(addr, _)| is_call && *addr == jump.target)
+            .map(|(_, name)| name.clone());
+
+        if let Some(name) = sink {
+            if let Some(n) = (0..8).find(|&n| self.gp[10 + n]) {
+                self.report(jump.pc, &format!("tainted argument a{n} to {name}"));
+            } else if let Some(n) = (0..8).find(|&n| self.fp[10 + n]) {
+                self.report(jump.pc, &format!("tainted argument fa{n} to {name}"));
+            }
+        }
+
+        HookAction::Continue
+    }
+}
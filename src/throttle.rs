@@ -0,0 +1,111 @@
+//! Slowing the guest down to an instruction rate, for `riscy --throttle`.
+//!
+//! Unthrottled, riscy runs guest code far faster than the hardware it was
+//! written for, so a delay loop blinking an LED flashes too fast to see, and a
+//! protocol timeout counted in spins expires before the other end could have
+//! answered. `Throttle` holds the guest to a rate in MIPS: every millisecond of
+//! guest time or so the core sleeps until the host has caught up with the
+//! instructions run. With `--deterministic` the virtual clock is scaled to
+//! match, each instruction taking `1000 / MIPS` ns rather than 1, so code
+//! timing itself with the `time` csr or `clock_gettime` sees the same rate.
+//!
+//! A throttle only slows the guest down, a host that can't keep up runs it as
+//! fast as it can. Once it's behind by more than `MAX_LAG`, as after a stop at
+//! a breakpoint, the throttle starts counting again from there rather than let
+//! the guest race to catch up.
+
+use std::{
+    fmt,
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+// how much guest time to run between looking at the clock
+const CHECK_INTERVAL: Duration = Duration::from_millis(1);
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+/// A rate in millions of instructions per second, parsed from a number above
+/// 0, e.g. `16` or `0.5`
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Mips(f64);
+
+impl Mips {
+    pub fn get(self) -> f64 {
+        self.0
+    }
+
+    /// How long an instruction takes at this rate
+    pub fn ns_per_instruction(self) -> f64 {
+        1e3 / self.0
+    }
+
+    fn instructions_in(self, time: Duration) -> u64 {
+        (time.as_secs_f64() * self.0 * 1e6) as u64
+    }
+}
+
+impl FromStr for Mips {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<f64>() {
+            Ok(mips) if mips.is_finite() && mips > 0.0 => Ok(Mips(mips)),
+            _ => Err(format!(
+                "invalid rate '{s}', expected a number of MIPS above 0"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Mips {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} MIPS", self.0)
+    }
+}
+
+pub(crate) struct Throttle {
+    mips: Mips,
+    // when the guest was at `start_instret`, which the rate is counted from
+    start: Instant,
+    start_instret: u64,
+}
+
+impl Throttle {
+    pub(crate) fn new(mips: Mips, instret: u64) -> Self {
+        Self {
+            mips,
+            start: Instant::now(),
+            start_instret: instret,
+        }
+    }
+
+    pub(crate) fn mips(&self) -> Mips {
+        self.mips
+    }
+
+    /// The instret at which `wait` should next be called
+    pub(crate) fn next_check(&self, instret: u64) -> u64 {
+        instret + self.mips.instructions_in(CHECK_INTERVAL).max(1)
+    }
+
+    /// Counts the rate from `instret`, now
+    pub(crate) fn reset(&mut self, instret: u64) {
+        self.start = Instant::now();
+        self.start_instret = instret;
+    }
+
+    /// Sleeps until running `instret` instructions has taken as long as it
+    /// should have
+    pub(crate) fn wait(&mut self, instret: u64) {
+        let run = instret.saturating_sub(self.start_instret);
+        let due = Duration::from_secs_f64(run as f64 / (self.mips.0 * 1e6));
+        let elapsed = self.start.elapsed();
+
+        if elapsed > due + MAX_LAG {
+            self.reset(instret);
+        } else if let Some(ahead) = due.checked_sub(elapsed) {
+            thread::sleep(ahead);
+        }
+    }
+}
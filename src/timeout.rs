@@ -0,0 +1,127 @@
+//! Timeouts for guest I/O on the host's file descriptors.
+//!
+//! A guest `read` of the host's stdin, or `write` to a full pipe, blocks the
+//! whole emulator until the host side moves, which in CI can be never.
+//! `IoTimeouts` bounds that: each blocking `read` or `write` may wait at most
+//! `per_call`, and none may wait past `deadline`, counted from when the
+//! timeouts were set. The core waits with poll(2) before making the call, so
+//! regular files, which are always ready, never time out.
+//!
+//! A call that times out either fails in the guest with `EINTR` or
+//! `ETIMEDOUT`, or stops the run with `StopReason::IoTimeout`, from which
+//! running again retries the call.
+
+use std::{
+    fmt, io,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use crate::progress;
+
+pub(crate) const EINTR: i32 = 4;
+pub(crate) const ETIMEDOUT: i32 = 110;
+
+/// What a timed out `read` or `write` does
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeoutAction {
+    /// Stops the run with `StopReason::IoTimeout`
+    #[default]
+    Stop,
+    /// Fails with `EINTR`, which most guests retry
+    Eintr,
+    /// Fails with `ETIMEDOUT`
+    Etimedout,
+}
+
+impl FromStr for TimeoutAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stop" => Ok(TimeoutAction::Stop),
+            "eintr" => Ok(TimeoutAction::Eintr),
+            "etimedout" => Ok(TimeoutAction::Etimedout),
+            _ => Err(format!(
+                "unknown timeout action '{s}', expected stop, eintr or etimedout"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TimeoutAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TimeoutAction::Stop => "stop",
+            TimeoutAction::Eintr => "eintr",
+            TimeoutAction::Etimedout => "etimedout",
+        })
+    }
+}
+
+/// A `--io-timeout` or `--io-deadline` argument, like `5s` or `500ms`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoDuration(pub Duration);
+
+impl FromStr for IoDuration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        progress::parse_duration(s)
+            .map(IoDuration)
+            .ok_or_else(|| format!("invalid duration '{s}', expected e.g. 5s or 500ms"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IoTimeouts {
+    /// The longest a single `read` or `write` may block
+    pub per_call: Option<Duration>,
+    /// How long after the timeouts are set any blocking must be over by
+    pub deadline: Option<Duration>,
+    pub action: TimeoutAction,
+}
+
+/// `IoTimeouts` as the core keeps them, with the deadline made absolute
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IoLimits {
+    per_call: Option<Duration>,
+    deadline: Option<Instant>,
+    pub(crate) action: TimeoutAction,
+}
+
+impl IoLimits {
+    pub(crate) fn new(timeouts: IoTimeouts) -> Self {
+        Self {
+            per_call: timeouts.per_call,
+            deadline: timeouts.deadline.map(|deadline| Instant::now() + deadline),
+            action: timeouts.action,
+        }
+    }
+
+    /// Waits for `fd` to be ready for `events` (`POLLIN` or `POLLOUT`), for as
+    /// long as the limits allow. False if it timed out
+    pub(crate) fn wait(&self, fd: i32, events: i16) -> bool {
+        let left = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let Some(timeout) = [self.per_call, left].into_iter().flatten().min() else {
+            return true;
+        };
+
+        let mut pollfd = libc::pollfd {
+            fd,
+            events,
+            revents: 0,
+        };
+        let ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        loop {
+            let ret = unsafe { libc::poll(&mut pollfd, 1, ms) };
+            if ret < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            // errors are left for the call itself to report
+            return ret != 0;
+        }
+    }
+}
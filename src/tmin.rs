@@ -0,0 +1,203 @@
+//! Shrinking an input that crashes the guest, for `riscy tmin`.
+//!
+//! `Minimizer` runs the program over and over with candidate inputs as its
+//! stdin, keeping any that crash it the same way as the original: the same
+//! `CrashSignature`, so the same kind of stop at the same pc. Blocks of the
+//! input are cut out, halving in size down to single bytes, and then the bytes
+//! left are replaced with `0` where that still crashes, so what's left stands
+//! out. Both passes repeat until neither makes any progress.
+//!
+//! Every run starts from a `Snapshot` of the core taken before the first
+//! instruction rather than from a freshly loaded program. Clocks and random
+//! bytes are made deterministic, so a crash doesn't come and go with them, and
+//! each run is capped at a multiple of the original's instructions, so an
+//! input that makes the guest loop forever is just one that doesn't crash.
+
+use std::fmt;
+
+use tracing::info;
+
+use crate::{
+    checkpoint::Snapshot,
+    core::{Core32, MemReader, RunInfo, StopReason},
+    load::LoadedElf,
+};
+
+// how many times longer than the original a run can take
+const LIMIT_FACTOR: u64 = 10;
+// and the least it's allowed, so tiny runs don't give up too early
+const MIN_LIMIT: u64 = 1_000_000;
+
+// what bytes are replaced with once they can't be cut
+const FILLER: u8 = b'0';
+
+/// How a run crashed: its stop reason, as in `StopReason::name`, and where.
+/// For a load or store fault that's the instruction making it, whatever the
+/// address, and for a guest that died in `abort` and the like, where it was
+/// called
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CrashSignature {
+    pub reason: &'static str,
+    pub pc: Option<u32>,
+}
+
+impl CrashSignature {
+    /// `None` for runs that stopped without crashing, including on the
+    /// instruction limit
+    pub(crate) fn of<Reader: MemReader<Idx = u32>>(
+        core: &Core32<Reader>,
+        info: &RunInfo,
+    ) -> Option<Self> {
+        let pc = match info.reason {
+            StopReason::Exited
+            | StopReason::Returned
+            | StopReason::Breakpoint { .. }
+            | StopReason::Cancelled { .. }
+            | StopReason::WouldBlock { .. }
+            | StopReason::IoTimeout { .. }
+            | StopReason::InstructionLimit { .. }
+            | StopReason::ReplayMismatch { .. } => return None,
+            StopReason::Fatal(_) => core.fatal().map(|fatal| fatal.caller.unwrap_or(fatal.pc)),
+            StopReason::LoadFault { pc, .. }
+            | StopReason::StoreFault { pc, .. }
+            | StopReason::FetchFault { pc }
+            | StopReason::Ebreak { pc }
+            | StopReason::IllegalInstruction { pc, .. }
+            | StopReason::RomWrite { pc, .. }
+            | StopReason::CfiViolation { pc, .. }
+            | StopReason::PossibleHang { pc }
+            | StopReason::InvariantViolated { pc, .. }
+            | StopReason::Panicked { pc } => Some(pc),
+        };
+
+        Some(Self {
+            reason: info.reason.name(),
+            pc,
+        })
+    }
+}
+
+impl fmt::Display for CrashSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)?;
+        if let Some(pc) = self.pc {
+            write!(f, " at pc {pc:#x}")?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Minimizer<Reader: MemReader<Idx = u32>> {
+    core: Core32<Reader>,
+    start: Snapshot,
+    limit: Option<u64>,
+    runs: u64,
+}
+
+impl<Reader: MemReader<Idx = u32>> Minimizer<Reader> {
+    pub fn new(elf: LoadedElf, size: usize) -> Self {
+        let mut core = Core32::<Reader>::new(elf, None, size, false);
+        core.set_deterministic(0);
+        core.capture_output();
+        core.set_stdin(Vec::new());
+
+        let mut start = core.snapshot();
+        // the guest's output is captured, so there's nothing of the host's to
+        // put back
+        start.fd_offsets.clear();
+
+        Self {
+            core,
+            start,
+            limit: None,
+            runs: 0,
+        }
+    }
+
+    /// How many times the program has been run
+    pub fn runs(&self) -> u64 {
+        self.runs
+    }
+
+    /// Runs the program over `input`, returning how it crashed if it did, and
+    /// how many instructions it ran
+    pub fn run(&mut self, input: &[u8]) -> (Option<CrashSignature>, u64) {
+        self.runs += 1;
+        self.core.set_stdin(input.to_vec());
+        self.core.capture_output();
+        self.core
+            .restore(&self.start)
+            .expect("restoring the core's own snapshot");
+        self.core.set_instruction_limit(self.limit);
+
+        let info = self.core.run();
+        let instret = self.core.instret() - self.start.instret;
+        (CrashSignature::of(&self.core, &info), instret)
+    }
+
+    /// Shrinks `input`, which must crash the program, as far as it will go
+    /// while still crashing it the same way. Fails if it doesn't crash it
+    pub fn minimize(&mut self, input: &[u8]) -> Result<(Vec<u8>, CrashSignature), String> {
+        self.limit = None;
+        let (signature, instret) = self.run(input);
+        let signature = signature.ok_or("the input doesn't crash the program")?;
+        self.limit = Some(self.start.instret + (instret * LIMIT_FACTOR).max(MIN_LIMIT));
+
+        let mut input = input.to_vec();
+        loop {
+            let len = input.len();
+            let cut = self.cut_blocks(&mut input, signature);
+            let filled = self.fill_bytes(&mut input, signature);
+            info!("{len} -> {} bytes after {} runs", input.len(), self.runs);
+            if !cut && !filled {
+                break;
+            }
+        }
+
+        Ok((input, signature))
+    }
+
+    fn crashes(&mut self, input: &[u8], signature: CrashSignature) -> bool {
+        self.run(input).0 == Some(signature)
+    }
+
+    fn cut_blocks(&mut self, input: &mut Vec<u8>, signature: CrashSignature) -> bool {
+        let mut cut = false;
+        let mut block = input.len().next_power_of_two() / 2;
+        while block > 0 {
+            let mut start = 0;
+            while start < input.len() {
+                let end = (start + block).min(input.len());
+                let mut candidate = input[..start].to_vec();
+                candidate.extend_from_slice(&input[end..]);
+
+                if self.crashes(&candidate, signature) {
+                    *input = candidate;
+                    cut = true;
+                } else {
+                    start += block;
+                }
+            }
+            block /= 2;
+        }
+        cut
+    }
+
+    fn fill_bytes(&mut self, input: &mut [u8], signature: CrashSignature) -> bool {
+        let mut filled = false;
+        for idx in 0..input.len() {
+            let byte = input[idx];
+            if byte == FILLER {
+                continue;
+            }
+
+            input[idx] = FILLER;
+            if self.crashes(input, signature) {
+                filled = true;
+            } else {
+                input[idx] = byte;
+            }
+        }
+        filled
+    }
+}
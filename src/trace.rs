@@ -0,0 +1,166 @@
+//! Streaming execution events to an external viewer over a socket.
+//!
+//! `TraceServer` listens on a unix socket or TCP port, waits for one client to
+//! connect, and then writes it an event for every instruction, control
+//! transfer and syscall. The stream is binary, all integers little-endian:
+//!
+//! ```text
+//! header:      b"RSCYTRC\0", version: u16 (currently 1)
+//! instruction: 0x01, pc: u32                    before it executes
+//! branch:      0x02, pc: u32, target: u32     after a branch or jump retires,
+//!                                               taken unless target is pc + 4
+//! syscall:     0x03, pc: u32, num: i32, args: [u32; 6]
+//!                                               before the syscall is made
+//! ```
+//!
+//! Instructions are identified by pc only, the client is expected to have the
+//! ELF. The stream ends when the connection is closed at the end of the run. If
+//! the client goes away first, the run carries on untraced.
+
+use std::{
+    fmt,
+    io::{self, BufWriter, Write},
+    net::TcpListener,
+    os::unix::net::UnixListener,
+    str::FromStr,
+};
+
+use tracing::warn;
+
+use crate::{
+    hooks::{Hook, HookAction, Syscall},
+    instruction::Instruction,
+};
+
+pub const MAGIC: &[u8; 8] = b"RSCYTRC\0";
+pub const VERSION: u16 = 1;
+
+pub const EVENT_INSTRUCTION: u8 = 0x01;
+pub const EVENT_BRANCH: u8 = 0x02;
+pub const EVENT_SYSCALL: u8 = 0x03;
+
+/// Where to listen, parsed from `unix:PATH` or `tcp:ADDR:PORT`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceAddr {
+    Unix(String),
+    Tcp(String),
+}
+
+impl FromStr for TraceAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(TraceAddr::Unix(path.to_owned()));
+        }
+
+        s.strip_prefix("tcp:")
+            .map(|addr| TraceAddr::Tcp(addr.to_owned()))
+            .ok_or_else(|| {
+                format!("invalid trace address '{s}', expected unix:PATH or tcp:ADDR:PORT")
+            })
+    }
+}
+
+impl fmt::Display for TraceAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceAddr::Unix(path) => write!(f, "unix:{path}"),
+            TraceAddr::Tcp(addr) => write!(f, "tcp:{addr}"),
+        }
+    }
+}
+
+pub struct TraceServer {
+    // `None` once the client has gone
+    out: Option<BufWriter<Box<dyn Write>>>,
+    // the last instruction, if it was a branch or jump
+    pending_branch: Option<u32>,
+}
+
+impl TraceServer {
+    /// Listens on `addr` and blocks until a client connects
+    pub fn accept(addr: &TraceAddr) -> io::Result<Self> {
+        let stream: Box<dyn Write> = match addr {
+            TraceAddr::Unix(path) => {
+                // a socket left behind by a previous run
+                let _ = std::fs::remove_file(path);
+                Box::new(UnixListener::bind(path)?.accept()?.0)
+            }
+            TraceAddr::Tcp(addr) => Box::new(TcpListener::bind(addr)?.accept()?.0),
+        };
+
+        let mut out = BufWriter::new(stream);
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+
+        Ok(Self {
+            out: Some(out),
+            pending_branch: None,
+        })
+    }
+
+    fn emit(&mut self, tag: u8, fields: &[u32]) {
+        let Some(out) = &mut self.out else {
+            return;
+        };
+
+        let result = out.write_all(&[tag]).and_then(|()| {
+            fields
+                .iter()
+                .try_for_each(|field| out.write_all(&field.to_le_bytes()))
+        });
+
+        if let Err(err) = result {
+            warn!(target: "trace", "trace client disconnected ({err}), no longer tracing");
+            self.out = None;
+        }
+    }
+}
+
+fn is_branch(instr: &Instruction) -> bool {
+    matches!(
+        instr,
+        Instruction::Jal { .. }
+            | Instruction::Jalr { .. }
+            | Instruction::Beq { .. }
+            | Instruction::Bne { .. }
+            | Instruction::Blt { .. }
+            | Instruction::Bge { .. }
+            | Instruction::Bltu { .. }
+            | Instruction::Bgeu { .. }
+    )
+}
+
+impl Hook for TraceServer {
+    fn before_instruction(&mut self, pc: u32, instr: &Instruction) -> HookAction {
+        if let Some(branch_pc) = self.pending_branch.take() {
+            self.emit(EVENT_BRANCH, &[branch_pc, pc]);
+        }
+
+        self.emit(EVENT_INSTRUCTION, &[pc]);
+        if is_branch(instr) {
+            self.pending_branch = Some(pc);
+        }
+
+        HookAction::Continue
+    }
+
+    fn wants_reg_writes(&self) -> bool {
+        false
+    }
+
+    fn wants_syscalls(&self) -> bool {
+        true
+    }
+
+    fn on_syscall(&mut self, syscall: &Syscall) -> HookAction {
+        let mut fields = [0; 8];
+        fields[0] = syscall.pc;
+        fields[1] = syscall.num as u32;
+        fields[2..].copy_from_slice(&syscall.args);
+        self.emit(EVENT_SYSCALL, &fields);
+
+        HookAction::Continue
+    }
+}
@@ -0,0 +1,223 @@
+//! Per-instruction tracing, for `riscy --debug` and `--trace-pcs`.
+//!
+//! `Core32::run_traced` runs the guest with a `Tracer` called before every
+//! instruction. The run loop is monomorphized over the tracer, so with
+//! `NoTrace`, which `Core32::run` uses unless the core was made with `debug`,
+//! there's nothing left in it to check whether tracing is on. Unlike a `Hook`,
+//! a tracer only watches: it can't stop the run or see memory accesses, so it
+//! doesn't need the core's instrumented path either.
+//!
+//! `Disassembly` prints each instruction as `--debug` always has, and `PcTrace`
+//! writes just the pc of each, for diffing two runs or feeding a coverage tool.
+//!
+//! With `--debug-fp`, `Disassembly` also prints the fp registers each fp
+//! instruction reads, before it, and the one it writes and `fflags`, after it.
+//! A register is shown as its bits in hex and as a float: one written as wide
+//! as the instruction writes, one read as a single if its top half is empty
+//! or NaN-boxing, a double otherwise.
+
+use std::io::{self, BufWriter, Write};
+
+use crate::{
+    csr::Fcsr,
+    instruction::{FpWidth, Instruction},
+    jumptable::JumpTables,
+    load::Symbol,
+    pseudo,
+    register::Register,
+};
+
+/// An instruction about to run
+#[derive(Debug, Clone, Copy)]
+pub struct TraceStep<'a> {
+    pub pc: u32,
+    pub instr: Instruction,
+    /// The instruction word it was decoded from
+    pub word: u32,
+    /// The instructions either side of it in memory, for pseudo-instructions
+    /// spanning two
+    pub prev: Option<Instruction>,
+    pub next: Option<Instruction>,
+    /// How many instructions ran before it
+    pub instret: u64,
+    pub symbols: &'a [Symbol],
+    /// The jump tables found so far, see `jumptable`
+    pub jump_tables: &'a JumpTables,
+    /// The fp state before it, if the tracer wants it
+    pub fp: Option<FpState>,
+}
+
+/// The fp registers' bits and `fcsr`
+#[derive(Debug, Clone, Copy)]
+pub struct FpState {
+    pub regs: [u64; 32],
+    pub fcsr: Fcsr,
+}
+
+pub trait Tracer {
+    /// Whether `trace` is called at all, `false` only for `NoTrace`
+    const ENABLED: bool = true;
+
+    /// Whether steps come with the fp state, which costs a copy of it
+    fn wants_fp(&self) -> bool {
+        false
+    }
+
+    fn trace(&mut self, step: &TraceStep<'_>);
+}
+
+/// No tracing, at no cost
+pub struct NoTrace;
+
+impl Tracer for NoTrace {
+    const ENABLED: bool = false;
+
+    fn trace(&mut self, _step: &TraceStep<'_>) {}
+}
+
+/// Each instruction disassembled, `pc: 0x110b4: addi a0, a0, 1`, with the two
+/// halves of a pseudo-instruction like `call` printed once, and a note after
+/// those in a `switch`, see `pseudo::switch_note`
+pub struct Disassembly<W: Write> {
+    out: W,
+    fp: bool,
+    // the fp register the last instruction writes, shown once it has, if it
+    // was an fp instruction
+    fp_written: Option<Option<(u8, FpWidth)>>,
+}
+
+impl<W: Write> Disassembly<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            fp: false,
+            fp_written: None,
+        }
+    }
+
+    /// Also shows the fp registers, see the module docs
+    pub fn with_fp(mut self, fp: bool) -> Self {
+        self.fp = fp;
+        self
+    }
+
+    // what the last instruction wrote, now that it has
+    fn trace_fp_written(&mut self, step: &TraceStep<'_>) {
+        let (Some(state), Some(dest)) = (&step.fp, self.fp_written.take()) else {
+            return;
+        };
+        let mut text = String::from("    ->");
+        if let Some((rd, width)) = dest {
+            text += &format!(" {}", show_fp(rd, state.regs[rd as usize], Some(width)));
+        }
+        let _ = writeln!(self.out, "{text} fflags {}", show_fflags(state.fcsr));
+    }
+
+    // what this instruction reads
+    fn trace_fp_read(&mut self, step: &TraceStep<'_>) {
+        let Some(state) = &step.fp else {
+            return;
+        };
+        let sources = step.instr.fp_sources();
+        let dest = step.instr.fp_dest();
+        if sources.iter().all(Option::is_none) && dest.is_none() {
+            return;
+        }
+        for rs in sources.into_iter().flatten() {
+            let _ = writeln!(
+                self.out,
+                "    {}",
+                show_fp(rs, state.regs[rs as usize], None)
+            );
+        }
+        self.fp_written = Some(dest);
+    }
+}
+
+impl Disassembly<io::Stderr> {
+    pub fn stderr() -> Self {
+        Self::new(io::stderr())
+    }
+}
+
+// `fa0 = 0x3ff8000000000000 (1.5)`, as a single if `width` says so or, not
+// knowing, if the top half is all zeroes or NaN-boxing ones
+fn show_fp(idx: u8, bits: u64, width: Option<FpWidth>) -> String {
+    let single = match width {
+        Some(width) => width == FpWidth::Single,
+        None => matches!(bits >> 32, 0 | 0xffff_ffff),
+    };
+    let value = if single {
+        format!("{:?}", f32::from_bits(bits as u32))
+    } else {
+        format!("{:?}", f64::from_bits(bits))
+    };
+    format!("{} = {bits:#018x} ({value})", Register::fp(idx))
+}
+
+fn show_fflags(fcsr: Fcsr) -> String {
+    let set: Vec<_> = [
+        ("NV", fcsr.nv()),
+        ("DZ", fcsr.dz()),
+        ("OF", fcsr.of()),
+        ("UF", fcsr.uf()),
+        ("NX", fcsr.nx()),
+    ]
+    .into_iter()
+    .filter_map(|(name, set)| set.then_some(name))
+    .collect();
+
+    if set.is_empty() {
+        "-".to_owned()
+    } else {
+        set.join("|")
+    }
+}
+
+impl<W: Write> Tracer for Disassembly<W> {
+    fn wants_fp(&self) -> bool {
+        self.fp
+    }
+
+    fn trace(&mut self, step: &TraceStep<'_>) {
+        self.trace_fp_written(step);
+        if step
+            .prev
+            .is_some_and(|prev| pseudo::fuses(prev, step.instr))
+        {
+            self.trace_fp_read(step);
+            return;
+        }
+
+        let mut text = pseudo::disassemble(step.pc, step.instr, step.next, step.symbols);
+        if let Some(note) = pseudo::switch_note(step.pc, step.jump_tables, step.symbols) {
+            text = format!("{text:<32} # {note}");
+        }
+        // a closed pipe shouldn't stop the guest
+        let _ = writeln!(self.out, "pc: {:#x}: {text}", step.pc);
+        self.trace_fp_read(step);
+    }
+}
+
+/// The pc of each instruction, in hex, one to a line
+pub struct PcTrace<W: Write> {
+    out: BufWriter<W>,
+}
+
+impl<W: Write> PcTrace<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out: BufWriter::new(out),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl<W: Write> Tracer for PcTrace<W> {
+    fn trace(&mut self, step: &TraceStep<'_>) {
+        let _ = writeln!(self.out, "{:08x}", step.pc);
+    }
+}
@@ -0,0 +1,312 @@
+//! An interactive terminal debugger, `riscy --tui`.
+//!
+//! Shows the registers, the disassembly around pc, the top of the stack and
+//! any watched memory, and steps or runs the core on key presses. The guest's
+//! output is captured and shown in panes of its own rather than written over
+//! the display.
+
+use std::{io, time::Duration};
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout, Rect},
+    style::{Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Paragraph},
+    DefaultTerminal, Frame,
+};
+
+use crate::{
+    core::{Core32, MemReader, RunInfo, StopReason},
+    instruction::Instruction,
+    load::{self, Symbol},
+    pseudo,
+    register::Register,
+};
+
+// instructions to run between looking for key presses while continuing
+const RUN_SLICE: u64 = 1 << 16;
+
+const STACK_WORDS: u32 = 16;
+const WATCH_BYTES: u32 = 32;
+
+const KEYS: &str = "s/space step  c continue/pause  q quit";
+
+pub struct Debugger<'a, Reader: MemReader<Idx = u32>> {
+    core: &'a mut Core32<Reader>,
+    symbols: &'a [Symbol],
+    watches: &'a [u32],
+    running: bool,
+    finished: Option<RunInfo>,
+    // where a hook last stopped the run, see `breakpoint`
+    breakpoint: Option<u32>,
+    // the registers before the last step or run, to highlight what changed
+    prev_regs: [i32; 32],
+}
+
+impl<'a, Reader: MemReader<Idx = u32>> Debugger<'a, Reader> {
+    /// `core` should have been set to capture its output, see
+    /// `Core32::capture_output`
+    pub fn new(core: &'a mut Core32<Reader>, symbols: &'a [Symbol], watches: &'a [u32]) -> Self {
+        let prev_regs = core.gp_regs();
+        Self {
+            core,
+            symbols,
+            watches,
+            running: false,
+            finished: None,
+            breakpoint: None,
+            prev_regs,
+        }
+    }
+
+    /// Takes over the terminal until the user quits, returning how the program
+    /// finished if it did
+    pub fn run(mut self) -> io::Result<Option<RunInfo>> {
+        let mut terminal = ratatui::init();
+        let result = self.event_loop(&mut terminal);
+        ratatui::restore();
+        result
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> io::Result<Option<RunInfo>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if self.running && !event::poll(Duration::ZERO)? {
+                self.steps(RUN_SLICE);
+                continue;
+            }
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(self.finished.take()),
+                KeyCode::Char('s' | ' ') => {
+                    self.running = false;
+                    self.steps(1);
+                }
+                KeyCode::Char('c') => self.running = !self.running && self.finished.is_none(),
+                _ => {}
+            }
+        }
+    }
+
+    fn steps(&mut self, count: u64) {
+        if self.finished.is_some() {
+            return;
+        }
+
+        self.prev_regs = self.core.gp_regs();
+        self.breakpoint = None;
+        for _ in 0..count {
+            match self.core.step() {
+                // stepping or continuing lets the instruction through
+                Some(RunInfo {
+                    reason: StopReason::Breakpoint { pc },
+                    ..
+                }) => {
+                    self.breakpoint = Some(pc);
+                    self.running = false;
+                    return;
+                }
+                Some(info) => {
+                    self.finished = Some(info);
+                    self.running = false;
+                    return;
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn read_word(&self, addr: u32) -> Option<u32> {
+        let bytes = self.core.memory().get(addr as usize..addr as usize + 4)?;
+        Some(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [top, middle, output, status] = Layout::vertical([
+            Constraint::Min(10),
+            Constraint::Length(STACK_WORDS as u16 / 2 + 2),
+            Constraint::Length(8),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [disasm, regs] =
+            Layout::horizontal([Constraint::Min(40), Constraint::Length(42)]).areas(top);
+        let [stack, memory] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(middle);
+        let [stdout, stderr] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(output);
+
+        self.draw_disassembly(frame, disasm);
+        self.draw_registers(frame, regs);
+        self.draw_stack(frame, stack);
+        self.draw_memory(frame, memory);
+        draw_output(frame, stdout, "stdout", self.core.captured_stdout());
+        draw_output(frame, stderr, "stderr", self.core.captured_stderr());
+
+        let state = match (&self.finished, self.running) {
+            (Some(info), _) => format!("finished: {:?}, a0 = {}", info.reason, info.return_code),
+            (None, true) => "running".to_owned(),
+            (None, false) => match self.breakpoint {
+                Some(pc) => format!("stopped at breakpoint {pc:#x}"),
+                None => "paused".to_owned(),
+            },
+        };
+        let status_line = Line::from(vec![
+            Span::raw(format!(
+                " {state} | {} instructions | ",
+                self.core.instret()
+            )),
+            Span::raw(KEYS).dim(),
+        ]);
+        frame.render_widget(status_line, status);
+    }
+
+    fn draw_disassembly(&self, frame: &mut Frame, area: Rect) {
+        let pc = self.core.pc();
+        let rows = area.height.saturating_sub(2) as u32;
+        // keep a third of the pane for what came before pc
+        let start = pc.saturating_sub(rows / 3 * 4);
+
+        let mut lines = Vec::new();
+        let mut addr = start;
+        while lines.len() < rows as usize {
+            let Some(word) = self.read_word(addr) else {
+                break;
+            };
+
+            if let Some(sym) = self.symbols.iter().find(|sym| sym.addr as u32 == addr) {
+                lines.push(Line::from(format!("{}:", sym.name)).bold());
+            }
+
+            let instr = Instruction::decode(word);
+            let next = self
+                .read_word(addr.wrapping_add(4))
+                .map(Instruction::decode);
+            let mut text = format!(
+                "{addr:#010x}  {}",
+                pseudo::disassemble(addr, instr, next, self.symbols)
+            );
+            if let Some(note) = pseudo::switch_note(addr, self.core.jump_tables(), self.symbols) {
+                text = format!("{text:<44} # {note}");
+            }
+            lines.push(if addr == pc {
+                Line::from(format!("> {text}")).reversed()
+            } else {
+                Line::from(format!("  {text}"))
+            });
+
+            addr = addr.wrapping_add(4);
+        }
+
+        let title = format!(" {} ", load::describe(self.symbols, pc));
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(title)),
+            area,
+        );
+    }
+
+    fn draw_registers(&self, frame: &mut Frame, area: Rect) {
+        let regs = self.core.gp_regs();
+        let reg = |idx: usize| {
+            let text = format!("{:>4} {:#010x}", Register::gp(idx as u8), regs[idx]);
+            if regs[idx] != self.prev_regs[idx] {
+                Span::styled(text, Style::new().yellow())
+            } else {
+                Span::raw(text)
+            }
+        };
+
+        let mut lines = vec![
+            Line::from(format!("  pc {:#010x}", self.core.pc())),
+            Line::raw(""),
+        ];
+        lines.extend(
+            (0..16).map(|row| Line::from(vec![reg(row), Span::raw("    "), reg(row + 16)])),
+        );
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" registers ")),
+            area,
+        );
+    }
+
+    fn draw_stack(&self, frame: &mut Frame, area: Rect) {
+        let sp = self.core.read(Register::Sp) as u32;
+        // two words a row
+        let lines: Vec<_> = (0..STACK_WORDS)
+            .step_by(2)
+            .map_while(|n| {
+                let first = self.read_word(sp.wrapping_add(n * 4))?;
+                let second = self.read_word(sp.wrapping_add(n * 4 + 4))?;
+                Some(Line::from(format!(
+                    "sp+{:<#5x} {first:#010x} {second:#010x}",
+                    n * 4
+                )))
+            })
+            .collect();
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(format!(" stack {sp:#x} "))),
+            area,
+        );
+    }
+
+    fn draw_memory(&self, frame: &mut Frame, area: Rect) {
+        let memory = self.core.memory();
+        let mut lines = Vec::new();
+
+        for &watch in self.watches {
+            for row in (0..WATCH_BYTES).step_by(8) {
+                let addr = watch.wrapping_add(row) as usize;
+                let Some(bytes) = memory.get(addr..addr + 8) else {
+                    break;
+                };
+
+                let hex: Vec<_> = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+                let ascii: String = bytes
+                    .iter()
+                    .map(|&byte| match byte {
+                        0x20..=0x7e => byte as char,
+                        _ => '.',
+                    })
+                    .collect();
+                lines.push(Line::from(format!(
+                    "{addr:#010x}  {}  {ascii}",
+                    hex.join(" ")
+                )));
+            }
+        }
+
+        if self.watches.is_empty() {
+            lines.push(Line::from("no watches, see --watch-mem").dim());
+        }
+
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title(" memory ")),
+            area,
+        );
+    }
+}
+
+fn draw_output(frame: &mut Frame, area: Rect, title: &str, output: &[u8]) {
+    let text = String::from_utf8_lossy(output);
+    let rows = area.height.saturating_sub(2) as usize;
+    let lines: Vec<_> = text.lines().map(Line::raw).collect();
+    let tail = lines[lines.len().saturating_sub(rows)..].to_vec();
+
+    frame.render_widget(
+        Paragraph::new(tail).block(Block::bordered().title(format!(" {title} "))),
+        area,
+    );
+}
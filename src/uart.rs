@@ -0,0 +1,148 @@
+//! A 16550-compatible UART, the serial port most RISC-V firmware and kernels
+//! expect, as at `0x10000000` on QEMU's `virt` machine.
+//!
+//! A `Uart` has the 16550's eight byte-wide registers, one to a byte. Its
+//! transmitter is never busy, so `LSR` always says it's empty and a byte
+//! written to `THR` goes straight out. Its receiver reads from what it was
+//! given with `Uart::with_input`, and `LSR.DR` says whether there's more. The
+//! divisor latch, line and modem control and scratch registers keep what's
+//! written to them and do nothing else, as there's no line to set up. It's
+//! polled only: `IER` is kept, but it never raises an interrupt.
+//!
+//! Map one with `MachineBuilder::device(Uart::at(addr))`, see `machine`, or
+//! `Core32::map_device`.
+
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+};
+
+use tracing::warn;
+
+use crate::{
+    machine::{At, Mmio},
+    region::Device,
+};
+
+const RBR_THR: u32 = 0;
+const IER: u32 = 1;
+const IIR_FCR: u32 = 2;
+const LCR: u32 = 3;
+const MCR: u32 = 4;
+const LSR: u32 = 5;
+const MSR: u32 = 6;
+const SCR: u32 = 7;
+
+// with `LCR.DLAB` set, registers 0 and 1 are the divisor latch
+const LCR_DLAB: u8 = 1 << 7;
+const LSR_DR: u8 = 1 << 0;
+const LSR_THRE: u8 = 1 << 5;
+const LSR_TEMT: u8 = 1 << 6;
+// no interrupt pending, with the FIFOs on
+const IIR_NONE: u8 = 0xc1;
+
+pub struct Uart<W: Write> {
+    out: W,
+    input: VecDeque<u8>,
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    scr: u8,
+    divisor: u16,
+}
+
+impl<W: Write> Uart<W> {
+    /// A UART transmitting to `out`, with nothing to receive
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            input: VecDeque::new(),
+            ier: 0,
+            lcr: 0,
+            mcr: 0,
+            scr: 0,
+            divisor: 0,
+        }
+    }
+
+    /// Has `input` received, for the guest to read
+    pub fn with_input(mut self, input: impl Into<Vec<u8>>) -> Self {
+        self.input.extend(input.into());
+        self
+    }
+
+    fn dlab(&self) -> bool {
+        self.lcr & LCR_DLAB != 0
+    }
+}
+
+impl Uart<io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+
+    /// A UART on stdout at `addr`, for `MachineBuilder::device`
+    pub fn at(addr: u32) -> At<Self> {
+        At::new(addr, Self::stdout())
+    }
+}
+
+impl<W: Write + 'static> Mmio for Uart<W> {
+    const WINDOW: u64 = 8;
+}
+
+impl<W: Write> Device for Uart<W> {
+    fn read(&mut self, offset: u32, _len: u32) -> u64 {
+        let value = match offset {
+            RBR_THR if self.dlab() => self.divisor as u8,
+            RBR_THR => self.input.pop_front().unwrap_or(0),
+            IER if self.dlab() => (self.divisor >> 8) as u8,
+            IER => self.ier,
+            IIR_FCR => IIR_NONE,
+            LCR => self.lcr,
+            MCR => self.mcr,
+            LSR => {
+                let ready = if self.input.is_empty() { 0 } else { LSR_DR };
+                LSR_THRE | LSR_TEMT | ready
+            }
+            MSR => 0,
+            SCR => self.scr,
+            _ => 0,
+        };
+        value as u64
+    }
+
+    fn write(&mut self, offset: u32, _len: u32, value: u64) {
+        let value = value as u8;
+        match offset {
+            RBR_THR if self.dlab() => self.divisor = self.divisor & 0xff00 | value as u16,
+            RBR_THR => {
+                let sent = self.out.write_all(&[value]).and_then(|()| {
+                    // a line at a time, whatever `out` is
+                    if value == b'\n' {
+                        self.out.flush()
+                    } else {
+                        Ok(())
+                    }
+                });
+                if let Err(err) = sent {
+                    warn!(target: "device", "uart write failed: {err}");
+                }
+            }
+            IER if self.dlab() => self.divisor = self.divisor & 0xff | (value as u16) << 8,
+            IER => self.ier = value & 0xf,
+            LCR => self.lcr = value,
+            MCR => self.mcr = value,
+            SCR => self.scr = value,
+            // the FIFOs are always on, and `LSR` and `MSR` are read-only
+            _ => {}
+        }
+    }
+
+    // for a last line with no newline
+    fn shutdown(&mut self) {
+        if let Err(err) = self.out.flush() {
+            warn!(target: "device", "uart write failed: {err}");
+        }
+    }
+}
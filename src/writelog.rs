@@ -0,0 +1,359 @@
+//! Recording the guest's writes to ranges of memory, for asking afterwards
+//! what wrote an address.
+//!
+//! `riscy --record-writes FILE` logs every write the guest makes to the
+//! ranges given with `--record-writes-range ADDR:LEN`, or to anywhere if none
+//! are: the pc that made it, where it went, and the bytes there before and
+//! after. Stores, atomics, syscalls filling a buffer and natively serviced
+//! calls like `memcpy` are all logged, but writes to devices aren't, as
+//! there's nothing to read back. The log is written as the guest runs, so it
+//! has everything up to a crash.
+//!
+//! `riscy writes FILE --addr ADDR` then answers what last wrote `ADDR`, and
+//! `--all` every write to it in order. Without `--addr` it counts the writes
+//! to each range, and by whom. With `--elf PROGRAM` pcs are named by symbol.
+//!
+//! The log is binary, all integers little-endian:
+//!
+//! ```text
+//! header: b"RSCYWLOG", version: u32 (currently 1), ranges: u32,
+//!         then per range start: u32, len: u32
+//! write:  pc: u32, addr: u32, len: u32, old: [u8; len], new: [u8; len]
+//! ```
+//!
+//! with writes until the end of the file. A write partly in a range is logged
+//! whole.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    ops::Range,
+    path::Path,
+    rc::Rc,
+    str::FromStr,
+};
+
+use tracing::warn;
+
+use crate::{
+    checkpoint::{invalid, read_u32, write_u32},
+    hooks::{Hook, HookAction, MemWritten},
+    load::{self, Symbol},
+};
+
+const MAGIC: &[u8; 8] = b"RSCYWLOG";
+const VERSION: u32 = 1;
+
+// how many writers the statistics list for each range
+const STATS_TOP: usize = 10;
+
+/// A range of guest memory, parsed from `ADDR:LEN`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteRange {
+    pub start: u32,
+    pub len: u32,
+}
+
+impl WriteRange {
+    pub fn contains(&self, addr: u32) -> bool {
+        addr.wrapping_sub(self.start) < self.len
+    }
+
+    /// Whether any of `addr..addr + len` is in the range
+    pub fn overlaps(&self, addr: u32, len: u32) -> bool {
+        let end = self.start as u64 + self.len as u64;
+        (addr as u64) < end && addr as u64 + len as u64 > self.start as u64
+    }
+}
+
+impl FromStr for WriteRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("invalid range '{s}', expected ADDR:LEN");
+        let (start, len) = s.split_once(':').ok_or_else(err)?;
+        Ok(WriteRange {
+            start: parse_u32(start).ok_or_else(err)?,
+            len: parse_u32(len).ok_or_else(err)?,
+        })
+    }
+}
+
+impl fmt::Display for WriteRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#x}..{:#x}",
+            self.start,
+            self.start as u64 + self.len as u64
+        )
+    }
+}
+
+/// An address, in hex with `0x` or decimal, with any `_`s between digits, as
+/// `0x8001_2340`
+pub fn parse_addr(s: &str) -> Result<u32, String> {
+    parse_u32(s).ok_or_else(|| format!("invalid address '{s}'"))
+}
+
+fn parse_u32(s: &str) -> Option<u32> {
+    let s = s.replace('_', "");
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// One logged write
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteRecord {
+    pub pc: u32,
+    pub addr: u32,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+impl WriteRecord {
+    pub fn range(&self) -> Range<u32> {
+        self.addr..self.addr.saturating_add(self.old.len() as u32)
+    }
+}
+
+struct Recorder {
+    out: Option<BufWriter<File>>,
+    ranges: Vec<WriteRange>,
+    written: u64,
+}
+
+/// Logs the guest's writes to a file, see the module docs. Clones share the
+/// file, so one can be kept to `finish` it after the other is given to
+/// `Core32::add_hook`
+#[derive(Clone)]
+pub struct WriteRecorder(Rc<RefCell<Recorder>>);
+
+impl WriteRecorder {
+    /// Logs the writes to `ranges`, or everywhere if there are none, to `path`
+    pub fn create(path: &Path, ranges: &[WriteRange]) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        write_u32(&mut out, VERSION)?;
+        write_u32(&mut out, ranges.len() as u32)?;
+        for range in ranges {
+            write_u32(&mut out, range.start)?;
+            write_u32(&mut out, range.len)?;
+        }
+
+        Ok(Self(Rc::new(RefCell::new(Recorder {
+            out: Some(out),
+            ranges: ranges.to_vec(),
+            written: 0,
+        }))))
+    }
+
+    /// Flushes the log, returning how many writes it has
+    pub fn finish(&self) -> io::Result<u64> {
+        let mut recorder = self.0.borrow_mut();
+        if let Some(out) = &mut recorder.out {
+            out.flush()?;
+        }
+        Ok(recorder.written)
+    }
+}
+
+impl Hook for WriteRecorder {
+    fn wants_reg_writes(&self) -> bool {
+        false
+    }
+
+    fn wants_mem_written(&self) -> bool {
+        true
+    }
+
+    fn on_mem_written(&mut self, written: &MemWritten) -> HookAction {
+        let mut recorder = self.0.borrow_mut();
+        let len = written.old.len() as u32;
+        if !recorder.ranges.is_empty()
+            && !recorder
+                .ranges
+                .iter()
+                .any(|range| range.overlaps(written.addr, len))
+        {
+            return HookAction::Continue;
+        }
+        let Some(out) = &mut recorder.out else {
+            return HookAction::Continue;
+        };
+
+        let logged = write_u32(out, written.pc)
+            .and_then(|()| write_u32(out, written.addr))
+            .and_then(|()| write_u32(out, len))
+            .and_then(|()| out.write_all(written.old))
+            .and_then(|()| out.write_all(written.new));
+        match logged {
+            Ok(()) => recorder.written += 1,
+            Err(err) => {
+                warn!(target: "writelog", "failed to log a write, no longer logging: {err}");
+                recorder.out = None;
+            }
+        }
+        HookAction::Continue
+    }
+}
+
+/// A log read back, see the module docs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteLog {
+    /// The ranges logged, everywhere if there are none
+    pub ranges: Vec<WriteRange>,
+    /// Every write, in the order they were made
+    pub records: Vec<WriteRecord>,
+}
+
+impl WriteLog {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    fn read_from(input: &mut impl Read) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a riscy write log"));
+        }
+        if read_u32(input)? != VERSION {
+            return Err(invalid("unsupported write log version"));
+        }
+
+        let ranges = (0..read_u32(input)?)
+            .map(|_| {
+                Ok(WriteRange {
+                    start: read_u32(input)?,
+                    len: read_u32(input)?,
+                })
+            })
+            .collect::<io::Result<_>>()?;
+
+        let mut records = Vec::new();
+        loop {
+            let pc = match read_u32(input) {
+                Ok(pc) => pc,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            };
+            let addr = read_u32(input)?;
+            let len = read_u32(input)? as usize;
+            let mut old = vec![0; len];
+            input.read_exact(&mut old)?;
+            let mut new = vec![0; len];
+            input.read_exact(&mut new)?;
+            records.push(WriteRecord { pc, addr, old, new });
+        }
+
+        Ok(WriteLog { ranges, records })
+    }
+
+    /// The writes to `addr`, each with its index in the log, in order
+    pub fn writes_to(&self, addr: u32) -> impl DoubleEndedIterator<Item = (usize, &WriteRecord)> {
+        self.records
+            .iter()
+            .enumerate()
+            .filter(move |(_, record)| record.range().contains(&addr))
+    }
+
+    /// The statistics for each range logged, or for all of memory if the log
+    /// has none
+    pub fn stats(&self) -> Vec<RangeStats> {
+        let ranges = if self.ranges.is_empty() {
+            vec![WriteRange {
+                start: 0,
+                len: u32::MAX,
+            }]
+        } else {
+            self.ranges.clone()
+        };
+
+        ranges
+            .into_iter()
+            .map(|range| {
+                let mut stats = RangeStats {
+                    range,
+                    writes: 0,
+                    bytes_changed: 0,
+                    by_pc: BTreeMap::new(),
+                };
+                for record in &self.records {
+                    let len = record.old.len() as u32;
+                    if !range.overlaps(record.addr, len) {
+                        continue;
+                    }
+                    stats.writes += 1;
+                    stats.bytes_changed += record
+                        .old
+                        .iter()
+                        .zip(&record.new)
+                        .enumerate()
+                        .filter(|&(at, (old, new))| {
+                            old != new && range.contains(record.addr.wrapping_add(at as u32))
+                        })
+                        .count() as u64;
+                    *stats.by_pc.entry(record.pc).or_default() += 1;
+                }
+                stats
+            })
+            .collect()
+    }
+}
+
+/// The writes to one range
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeStats {
+    pub range: WriteRange,
+    pub writes: u64,
+    /// How many bytes in the range the writes changed, counting each byte each
+    /// time
+    pub bytes_changed: u64,
+    /// Writes by the pc that made them
+    pub by_pc: BTreeMap<u32, u64>,
+}
+
+impl RangeStats {
+    /// Shows the statistics, naming the pcs by `symbols`
+    pub fn display<'a>(&'a self, symbols: &'a [Symbol]) -> impl fmt::Display + 'a {
+        DisplayStats {
+            stats: self,
+            symbols,
+        }
+    }
+}
+
+struct DisplayStats<'a> {
+    stats: &'a RangeStats,
+    symbols: &'a [Symbol],
+}
+
+impl fmt::Display for DisplayStats<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stats = self.stats;
+        writeln!(
+            f,
+            "{}: {} writes by {} pcs, {} bytes changed",
+            stats.range,
+            stats.writes,
+            stats.by_pc.len(),
+            stats.bytes_changed
+        )?;
+
+        let mut by_pc: Vec<_> = stats.by_pc.iter().map(|(&pc, &n)| (pc, n)).collect();
+        by_pc.sort_by_key(|&(pc, writes)| (std::cmp::Reverse(writes), pc));
+        for &(pc, writes) in by_pc.iter().take(STATS_TOP) {
+            writeln!(f, "  {writes:>10}  {}", load::describe(self.symbols, pc))?;
+        }
+        if by_pc.len() > STATS_TOP {
+            writeln!(f, "  {:>10}  ...", "")?;
+        }
+        Ok(())
+    }
+}
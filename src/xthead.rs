@@ -0,0 +1,286 @@
+//! T-Head's vendor extensions, for running binaries built for the C906 and
+//! C910 (`-mcpu=thead-c906`) unchanged.
+//!
+//! `execute` is a custom-0 handler, see `custom`, registered by `riscy
+//! --isa-vendor xthead`. It implements the RV32 instructions of the extensions
+//! GCC and LLVM generate for those cores:
+//!
+//! - XTheadBa, XTheadBb and XTheadBs: `th.addsl`, `th.srri`, `th.ext`,
+//!   `th.extu`, `th.ff0`, `th.ff1`, `th.rev`, `th.tstnbz` and `th.tst`
+//! - XTheadCondMov: `th.mveqz` and `th.mvnez`
+//! - XTheadMac: `th.mula`, `th.muls`, `th.mulah` and `th.mulsh`
+//! - XTheadMemIdx: the indexed loads and stores, `th.lrw` and friends, and
+//!   those updating their base register, `th.lwia` and friends
+//! - XTheadMemPair: `th.lwd` and `th.swd`
+//! - XTheadFMemIdx and XTheadFmv: the indexed fp loads and stores, and moving
+//!   the high half of a double to and from an integer register
+//! - XTheadCmo and XTheadSync: the cache and synchronisation operations, which
+//!   do nothing here, as there's one hart and no caches
+//!
+//! Encodings that are RV64-only or reserved, like a pair load into the same
+//! register twice, are illegal.
+
+use crate::{
+    custom::{CustomError, Machine},
+    opcodes::Field,
+};
+
+const OP_CMO: u32 = 0b000;
+const OP_ALU: u32 = 0b001;
+const OP_EXT: u32 = 0b010;
+const OP_EXTU: u32 = 0b011;
+const OP_LOAD: u32 = 0b100;
+const OP_STORE: u32 = 0b101;
+const OP_FLOAD: u32 = 0b110;
+const OP_FSTORE: u32 = 0b111;
+
+// what an integer memory access moves, from bits 31..29 of its funct5
+#[derive(Debug, Clone, Copy)]
+enum Width {
+    Byte,
+    Half,
+    Word,
+    ByteU,
+    HalfU,
+}
+
+impl Width {
+    fn of(funct5: u32) -> Option<Self> {
+        Some(match funct5 >> 2 {
+            0b000 => Width::Byte,
+            0b001 => Width::Half,
+            0b010 => Width::Word,
+            0b100 => Width::ByteU,
+            0b101 => Width::HalfU,
+            _ => return None,
+        })
+    }
+
+    fn len(self) -> u32 {
+        match self {
+            Width::Byte | Width::ByteU => 1,
+            Width::Half | Width::HalfU => 2,
+            Width::Word => 4,
+        }
+    }
+
+    fn load(self, m: &Machine, addr: u32) -> Result<u32, CustomError> {
+        let bytes = m.bytes(addr, self.len())?;
+        Ok(match self {
+            Width::Byte => bytes[0] as i8 as u32,
+            Width::ByteU => bytes[0] as u32,
+            Width::Half => i16::from_le_bytes([bytes[0], bytes[1]]) as u32,
+            Width::HalfU => u16::from_le_bytes([bytes[0], bytes[1]]) as u32,
+            Width::Word => u32::from_le_bytes(bytes.try_into().unwrap()),
+        })
+    }
+
+    fn store(self, m: &mut Machine, addr: u32, value: u32) -> Result<(), CustomError> {
+        let len = self.len();
+        m.bytes_mut(addr, len)?
+            .copy_from_slice(&value.to_le_bytes()[..len as usize]);
+        Ok(())
+    }
+}
+
+/// Executes XThead instruction `inst`, see the module docs
+pub fn execute(inst: u32, m: &mut Machine) -> Result<(), CustomError> {
+    let rd = Field::Rd.extract(inst) as u8;
+    let rs1 = Field::Rs1.extract(inst) as u8;
+    let rs2 = Field::Rs2.extract(inst) as u8;
+
+    match Field::Rm.extract(inst) {
+        OP_CMO => cmo(inst, rd),
+        OP_ALU => alu(inst, m, rd, rs1, rs2),
+        funct3 @ (OP_EXT | OP_EXTU) => {
+            let (msb, lsb) = (inst >> 26, (inst >> 20) & 0x3f);
+            if msb >= 32 || lsb > msb {
+                return Err(CustomError::Illegal);
+            }
+            // the field at the top, then shifted back down
+            let field = m.x(rs1) << (31 - msb);
+            let value = if funct3 == OP_EXT {
+                ((field as i32) >> (31 - msb + lsb)) as u32
+            } else {
+                field >> (31 - msb + lsb)
+            };
+            m.set_x(rd, value);
+            Ok(())
+        }
+        OP_LOAD => load(inst, m, rd, rs1, rs2),
+        OP_STORE => store(inst, m, rd, rs1, rs2),
+        funct3 @ (OP_FLOAD | OP_FSTORE) => fp_indexed(inst, m, funct3 == OP_FLOAD, rd, rs1, rs2),
+        _ => unreachable!("funct3 is 3 bits"),
+    }
+}
+
+// XTheadCmo and XTheadSync, all of which write nothing
+fn cmo(inst: u32, rd: u8) -> Result<(), CustomError> {
+    if rd != 0 || inst >> 27 != 0 {
+        return Err(CustomError::Illegal);
+    }
+    Ok(())
+}
+
+fn alu(inst: u32, m: &mut Machine, rd: u8, rs1: u8, rs2: u8) -> Result<(), CustomError> {
+    let (a, b) = (m.x(rs1), m.x(rs2));
+    let funct7 = inst >> 25;
+    let value = match (funct7, inst >> 20) {
+        // th.addsl, the shift in funct7's low bits
+        (0b000_0000..=0b000_0011, ..) => a.wrapping_add(b << (funct7 & 3)),
+        // th.srri, with RV32's 5-bit shift
+        (0b000_1000, ..) => a.rotate_right(rs2 as u32),
+        // th.tst
+        (0b100_0100, ..) => (a >> rs2) & 1,
+        (_, 0x800) => tstnbz(a),
+        (_, 0x820) => a.swap_bytes(),
+        (_, 0x840) => (!a).leading_zeros(),
+        (_, 0x860) => a.leading_zeros(),
+        // th.mveqz and th.mvnez, which leave rd alone if the test fails
+        (0b010_0000, ..) if b != 0 => return Ok(()),
+        (0b010_0001, ..) if b == 0 => return Ok(()),
+        (0b010_0000 | 0b010_0001, ..) => a,
+        // th.mula, th.muls, th.mulah and th.mulsh
+        (0b001_0000, ..) => m.x(rd).wrapping_add(a.wrapping_mul(b)),
+        (0b001_0001, ..) => m.x(rd).wrapping_sub(a.wrapping_mul(b)),
+        (0b001_0100, ..) => m.x(rd).wrapping_add(mul_half(a, b)),
+        (0b001_0101, ..) => m.x(rd).wrapping_sub(mul_half(a, b)),
+        // th.fmv.hw.x, into the high half of a double
+        (_, 0xa00) => {
+            let low = m.f(rd) & 0xffff_ffff;
+            m.set_f(rd, (a as u64) << 32 | low);
+            return Ok(());
+        }
+        // th.fmv.x.hw
+        (_, 0xc00) => (m.f(rs1) >> 32) as u32,
+        _ => return Err(CustomError::Illegal),
+    };
+    m.set_x(rd, value);
+    Ok(())
+}
+
+// 0xff for each zero byte, 0 for any other
+fn tstnbz(value: u32) -> u32 {
+    let bytes = value
+        .to_le_bytes()
+        .map(|byte| if byte == 0 { 0xff } else { 0 });
+    u32::from_le_bytes(bytes)
+}
+
+// the product of the low halves, signed
+fn mul_half(a: u32, b: u32) -> u32 {
+    (a as i16 as i32).wrapping_mul(b as i16 as i32) as u32
+}
+
+// th.lwd, or th.lr*, th.lur* and th.l*ia/ib, by funct5
+fn load(inst: u32, m: &mut Machine, rd: u8, rs1: u8, rs2: u8) -> Result<(), CustomError> {
+    let funct5 = inst >> 27;
+    let imm2 = (inst >> 25) & 3;
+
+    if funct5 == 0b11100 {
+        if rd == rs1 || rs2 == rs1 || rd == rs2 {
+            return Err(CustomError::Illegal);
+        }
+        let addr = m.x(rs1).wrapping_add(imm2 << 3);
+        let (first, second) = (m.load_u32(addr)?, m.load_u32(addr.wrapping_add(4))?);
+        m.set_x(rd, first);
+        m.set_x(rs2, second);
+        return Ok(());
+    }
+
+    let width = Width::of(funct5).ok_or(CustomError::Illegal)?;
+    match funct5 & 3 {
+        // indexed, with the index zero-extended or not, which on RV32 is
+        // the same
+        0b00 | 0b10 => {
+            let addr = m.x(rs1).wrapping_add(m.x(rs2) << imm2);
+            let value = width.load(m, addr)?;
+            m.set_x(rd, value);
+        }
+        // updating the base, before or after
+        update => {
+            if rd == rs1 {
+                return Err(CustomError::Illegal);
+            }
+            let offset = sext5(rs2 as u32) << imm2;
+            let updated = m.x(rs1).wrapping_add(offset);
+            let addr = if update == 0b01 { updated } else { m.x(rs1) };
+            let value = width.load(m, addr)?;
+            m.set_x(rd, value);
+            m.set_x(rs1, updated);
+        }
+    }
+    Ok(())
+}
+
+// th.swd, or th.sr*, th.sur* and th.s*ia/ib, with rd the register stored
+fn store(inst: u32, m: &mut Machine, rd: u8, rs1: u8, rs2: u8) -> Result<(), CustomError> {
+    let funct5 = inst >> 27;
+    let imm2 = (inst >> 25) & 3;
+
+    if funct5 == 0b11100 {
+        let addr = m.x(rs1).wrapping_add(imm2 << 3);
+        let (first, second) = (m.x(rd), m.x(rs2));
+        m.store_u32(addr, first)?;
+        return m.store_u32(addr.wrapping_add(4), second);
+    }
+
+    // no unsigned stores
+    let width = Width::of(funct5)
+        .filter(|width| matches!(width, Width::Byte | Width::Half | Width::Word))
+        .ok_or(CustomError::Illegal)?;
+    let value = m.x(rd);
+    match funct5 & 3 {
+        0b00 | 0b10 => {
+            let addr = m.x(rs1).wrapping_add(m.x(rs2) << imm2);
+            width.store(m, addr, value)
+        }
+        update => {
+            let offset = sext5(rs2 as u32) << imm2;
+            let updated = m.x(rs1).wrapping_add(offset);
+            let addr = if update == 0b01 { updated } else { m.x(rs1) };
+            width.store(m, addr, value)?;
+            m.set_x(rs1, updated);
+            Ok(())
+        }
+    }
+}
+
+// th.flr*, th.flur*, th.fsr* and th.fsur*, by funct5
+fn fp_indexed(
+    inst: u32,
+    m: &mut Machine,
+    load: bool,
+    rd: u8,
+    rs1: u8,
+    rs2: u8,
+) -> Result<(), CustomError> {
+    let double = match inst >> 27 {
+        0b01000 | 0b01010 => false,
+        0b01100 | 0b01110 => true,
+        _ => return Err(CustomError::Illegal),
+    };
+    let imm2 = (inst >> 25) & 3;
+    let addr = m.x(rs1).wrapping_add(m.x(rs2) << imm2);
+    let len = if double { 8 } else { 4 };
+
+    if load {
+        let bytes = m.bytes(addr, len)?;
+        let bits = if double {
+            u64::from_le_bytes(bytes.try_into().unwrap())
+        } else {
+            // NaN-boxed
+            u32::from_le_bytes(bytes.try_into().unwrap()) as u64 | 0xffff_ffff_0000_0000
+        };
+        m.set_f(rd, bits);
+    } else {
+        let bits = m.f(rd).to_le_bytes();
+        m.bytes_mut(addr, len)?
+            .copy_from_slice(&bits[..len as usize]);
+    }
+    Ok(())
+}
+
+fn sext5(imm: u32) -> u32 {
+    ((imm as i32) << 27 >> 27) as u32
+}